@@ -0,0 +1,53 @@
+/* The C ABI of wgpu_cube, built into the shared library with `cargo build --release --features ffi`.
+ * Functions which can fail return false (or a negative index), after which
+ * wgpu_cube_last_error says why. */
+#ifndef WGPU_CUBE_H
+#define WGPU_CUBE_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The renderer and the scene it draws, rendering offscreen rather than into a window */
+typedef struct Offscreen WgpuCubeRenderer;
+
+/* Why the last call on this thread failed, or NULL if nothing has.
+ * Only valid until the next call which fails */
+const char *wgpu_cube_last_error(void);
+
+/* Create a renderer drawing the default scene into frames `width` by `height` pixels, or NULL if it can't be */
+WgpuCubeRenderer *wgpu_cube_create(uint32_t width, uint32_t height);
+
+/* Free everything `renderer` holds on to, ignoring NULL */
+void wgpu_cube_destroy(WgpuCubeRenderer *renderer);
+
+/* Render frames `width` by `height` pixels from now on */
+bool wgpu_cube_resize(WgpuCubeRenderer *renderer, uint32_t width, uint32_t height);
+
+/* Move the camera to `eye`, looking at `target`, with a vertical field of view of `fovy` degrees */
+void wgpu_cube_set_camera(WgpuCubeRenderer *renderer, const float eye[3], const float target[3],
+                          float fovy);
+
+/* Add a mesh to the scene at `position`, returning its index or -1 if it can't be added.
+ * Each vertex is 8 floats: its position, texture co-ordinates and normal. The triangles are
+ * wound anticlockwise when seen from the front, and the mesh is painted with the scene's texture */
+int64_t wgpu_cube_load_mesh(WgpuCubeRenderer *renderer, const float *vertices, size_t vertex_count,
+                            const uint16_t *indices, size_t index_count, const float position[3]);
+
+/* Paint every mesh with a `width` by `height` sRGB image, 4 bytes per pixel in RGBA order */
+bool wgpu_cube_load_texture(WgpuCubeRenderer *renderer, const uint8_t *rgba, uint32_t width,
+                            uint32_t height);
+
+/* Move the scene along by a frame and render it into `pixels`, 4 bytes per pixel in RGBA order
+ * with the top row first, which has to be `len` bytes long */
+bool wgpu_cube_render(WgpuCubeRenderer *renderer, uint8_t *pixels, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
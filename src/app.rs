@@ -0,0 +1,53 @@
+//! Hooks for building apps of your own on top of the renderer, rather than editing the demo in `state.rs`.
+//! `run_app` drives an `App` with the same window, event loop and frame pacing that `run` drives the demo with
+
+use wgpu::{CommandEncoder, Device, Queue, TextureFormat, TextureView};
+use winit::{dpi::PhysicalSize, event::WindowEvent};
+
+use crate::{input::Input, math::Matrix4, state::State};
+
+/// Everything set up from `Config`, which an `App` adds to and moves around
+pub type Renderer = State;
+
+/// An app run by `run_app`. Every hook but `init` does nothing by default
+pub trait App: Sized + 'static {
+    /// Set the app up once the renderer has been created, e.g. adding models to it
+    fn init(renderer: &mut Renderer) -> Self;
+
+    /// Move things along by the `dt` seconds since the last frame, before the renderer uploads
+    /// the camera and lights for it
+    fn update(&mut self, _renderer: &mut Renderer, _dt: f32, _input: &Input) {}
+
+    /// Record any drawing of the app's own, over the top of the post-processed scene
+    /// and under the overlays (log console, profiler). Like the overlays, it isn't recorded
+    fn render(&mut self, _frame: &mut FrameContext) {}
+
+    /// Handle a window event before the renderer does, returning whether it was used up.
+    /// `Input` sees every event either way
+    fn on_event(&mut self, _renderer: &mut Renderer, _event: &WindowEvent) -> bool {
+        false
+    }
+}
+
+/// What an `App` needs to draw into the frame being rendered
+pub struct FrameContext<'a> {
+    pub device: &'a Device,
+    pub queue: &'a Queue,
+    /// Submitted along with the rest of the frame
+    pub encoder: &'a mut CommandEncoder,
+    /// The surface texture, which already holds the post-processed scene
+    pub view: &'a TextureView,
+    pub format: TextureFormat,
+    pub size: PhysicalSize<u32>,
+    /// The camera's view-projection matrix, with wgpu's 0 to 1 depth range
+    pub view_proj: Matrix4<f32>,
+}
+
+/// The demo `run` shows, which is whatever the `Config` asks for and nothing more
+pub struct Demo;
+
+impl App for Demo {
+    fn init(_renderer: &mut Renderer) -> Self {
+        Self
+    }
+}
@@ -0,0 +1,39 @@
+use std::borrow::Cow;
+
+use anyhow::*;
+
+/// The assets which are built into the binary, by their path relative to `src`
+#[cfg(not(target_os = "android"))]
+const EMBEDDED: &[(&str, &[u8])] = &[
+    (
+        "fonts/Hack-Regular.ttf",
+        include_bytes!("fonts/Hack-Regular.ttf"),
+    ),
+    ("plank_texture.png", include_bytes!("plank_texture.png")),
+];
+
+/// Load the asset at `path`, relative to `src`. Everywhere but Android they're built into the
+/// binary, while Android reads them out of the APK's assets, which `cargo apk` packages from `src`
+#[cfg(not(target_os = "android"))]
+pub fn load(path: &str) -> Result<Cow<'static, [u8]>> {
+    EMBEDDED
+        .iter()
+        .find(|(name, _)| *name == path)
+        .map(|(_, bytes)| Cow::Borrowed(*bytes))
+        .with_context(|| format!("no asset called {path}"))
+}
+
+/// Load the asset at `path`, relative to `src`. Everywhere but Android they're built into the
+/// binary, while Android reads them out of the APK's assets, which `cargo apk` packages from `src`
+#[cfg(target_os = "android")]
+pub fn load(path: &str) -> Result<Cow<'static, [u8]>> {
+    let name = std::ffi::CString::new(path)?;
+    let mut asset = ndk_glue::native_activity()
+        .asset_manager()
+        .open(&name)
+        .with_context(|| format!("no asset called {path} in the APK"))?;
+    let bytes = asset
+        .get_buffer()
+        .with_context(|| format!("failed to read {path} from the APK"))?;
+    Ok(Cow::Owned(bytes.to_vec()))
+}
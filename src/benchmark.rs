@@ -0,0 +1,144 @@
+use std::{
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+use crate::{gpu_info::GpuInfo, math::Point3};
+
+/// Renders a fixed number of frames while flying the camera along the same path every time,
+/// timing each one so that runs on different machines or commits can be compared
+pub struct Benchmark {
+    frames: u32,
+    /// The number of frames finished so far, including the warm-up frames
+    frame: u32,
+    last_frame: Option<Instant>,
+    frame_times: Vec<Duration>,
+}
+
+impl Benchmark {
+    /// Frames rendered before timing starts, which tend to be slowed down by pipelines compiling
+    /// and the driver settling in
+    pub const WARMUP_FRAMES: u32 = 10;
+    /// The height of the camera's orbit
+    const HEIGHT: f32 = 5.0;
+    /// The radius of the camera's orbit, the same distance from the origin the camera starts at
+    const RADIUS: f32 = 9.0;
+
+    /// Time `frames` frames, after `WARMUP_FRAMES` untimed ones
+    pub fn new(frames: u32) -> Self {
+        Self {
+            frames,
+            frame: 0,
+            last_frame: None,
+            frame_times: Vec::with_capacity(frames as usize),
+        }
+    }
+
+    /// Where the camera should be for the next frame, a single orbit around the origin over the timed frames
+    pub fn camera_eye(&self) -> Point3<f32> {
+        let timed = self.frame.saturating_sub(Self::WARMUP_FRAMES);
+        let angle = std::f32::consts::TAU * timed as f32 / self.frames.max(1) as f32;
+        Point3::new(
+            Self::RADIUS * angle.sin(),
+            Self::HEIGHT,
+            Self::RADIUS * angle.cos(),
+        )
+    }
+
+    /// Record that a frame has been presented, returning whether the benchmark is done.
+    /// Each frame is timed from the end of the one before, so that time spent outside of
+    /// `render`, e.g. waiting on the GPU, counts too
+    pub fn frame_finished(&mut self) -> bool {
+        let now = Instant::now();
+        if self.frame >= Self::WARMUP_FRAMES {
+            if let Some(last_frame) = self.last_frame {
+                self.frame_times.push(now - last_frame);
+            }
+        }
+        self.last_frame = Some(now);
+        self.frame += 1;
+        self.done()
+    }
+
+    pub fn done(&self) -> bool {
+        self.frame_times.len() >= self.frames as usize
+    }
+
+    /// Summarise the frame times recorded so far
+    pub fn report(&self) -> BenchmarkReport {
+        let mut frame_times = self
+            .frame_times
+            .iter()
+            .map(|time| time.as_secs_f64() * 1e3)
+            .collect::<Vec<_>>();
+        frame_times.sort_by(f64::total_cmp);
+        // nearest-rank percentiles
+        let percentile = |p: f64| {
+            let rank = (p / 100.0 * frame_times.len() as f64).ceil() as usize;
+            frame_times
+                .get(rank.clamp(1, frame_times.len().max(1)) - 1)
+                .copied()
+                .unwrap_or(0.0)
+        };
+        BenchmarkReport {
+            frames: frame_times.len(),
+            mean_ms: frame_times.iter().sum::<f64>() / frame_times.len().max(1) as f64,
+            min_ms: frame_times.first().copied().unwrap_or(0.0),
+            p50_ms: percentile(50.0),
+            p95_ms: percentile(95.0),
+            p99_ms: percentile(99.0),
+            max_ms: frame_times.last().copied().unwrap_or(0.0),
+        }
+    }
+}
+
+/// Frame time statistics from a `Benchmark`, in milliseconds
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BenchmarkReport {
+    pub frames: usize,
+    pub mean_ms: f64,
+    pub min_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl BenchmarkReport {
+    /// The report as a single line of JSON, along with the GPU it was run on
+    pub fn to_json(&self, gpu: &GpuInfo) -> String {
+        let mut json = String::from("{");
+        let mut field = |name: &str, value: f64| {
+            write!(json, "\"{name}\":{value:.4},").expect("writing to a string can't fail")
+        };
+        field("mean_ms", self.mean_ms);
+        field("min_ms", self.min_ms);
+        field("p50_ms", self.p50_ms);
+        field("p95_ms", self.p95_ms);
+        field("p99_ms", self.p99_ms);
+        field("max_ms", self.max_ms);
+        write!(
+            json,
+            "\"frames\":{},\"gpu\":\"{}\"}}",
+            self.frames,
+            json_escape(&gpu.summary())
+        )
+        .expect("writing to a string can't fail");
+        json
+    }
+}
+
+fn json_escape(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for c in string.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                write!(escaped, "\\u{:04x}", c as u32).expect("writing to a string can't fail")
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
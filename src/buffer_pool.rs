@@ -0,0 +1,244 @@
+use std::ops::Range;
+
+use wgpu::{
+    BindingResource, Buffer, BufferAddress, BufferBinding, BufferDescriptor, BufferSize,
+    BufferSlice, BufferUsages, Device, Queue, COPY_BUFFER_ALIGNMENT,
+};
+
+/// A region of one of a `BufferPool`'s buffers, handed out by `BufferPool::allocate`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Allocation {
+    /// Index of the block within the pool
+    block: usize,
+    /// Offset in bytes from the start of the block's buffer
+    pub offset: BufferAddress,
+    /// Size in bytes of the allocation, which may be larger than what was asked for due to alignment
+    pub size: BufferAddress,
+}
+
+impl Allocation {
+    pub fn range(&self) -> Range<BufferAddress> {
+        self.offset..self.offset + self.size
+    }
+}
+
+struct Block {
+    buffer: Buffer,
+    /// Free regions of `buffer`, sorted by offset and never adjacent to each other
+    free: Vec<Range<BufferAddress>>,
+}
+
+/// Sub-allocates lots of small pieces of data (e.g. the vertices of many meshes) out of a few large buffers,
+/// rather than creating a separate `wgpu::Buffer` for each of them
+pub struct BufferPool {
+    label: &'static str,
+    usage: BufferUsages,
+    /// The size of each new buffer, allocations larger than this get a buffer to themselves
+    block_size: BufferAddress,
+    /// Every allocation's offset is a multiple of this
+    alignment: BufferAddress,
+    blocks: Vec<Block>,
+}
+
+impl BufferPool {
+    /// `usage` always has `COPY_DST` added to it, so that data can be uploaded into the pool
+    pub fn new(label: &'static str, usage: BufferUsages, block_size: BufferAddress) -> Self {
+        Self {
+            label,
+            usage: usage | BufferUsages::COPY_DST,
+            block_size,
+            alignment: COPY_BUFFER_ALIGNMENT,
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Align allocations to `alignment` bytes,
+    /// e.g. uniform buffers need to be aligned to `Limits::min_uniform_buffer_offset_alignment`
+    pub fn with_alignment(mut self, alignment: BufferAddress) -> Self {
+        assert!(
+            alignment.is_power_of_two(),
+            "alignment must be a power of two"
+        );
+        self.alignment = alignment.max(COPY_BUFFER_ALIGNMENT);
+        self
+    }
+
+    /// Reserve `size` bytes, reusing freed space if possible and creating a new buffer if not
+    pub fn allocate(&mut self, device: &Device, size: BufferAddress) -> Allocation {
+        let size = align(size.max(1), self.alignment);
+
+        // First fit, which is simple and works well enough when most allocations are similarly sized
+        for (index, block) in self.blocks.iter_mut().enumerate() {
+            if let Some(offset) = take(&mut block.free, size) {
+                return Allocation {
+                    block: index,
+                    offset,
+                    size,
+                };
+            }
+        }
+
+        let block_size = size.max(self.block_size);
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some(self.label),
+            size: block_size,
+            usage: self.usage,
+            mapped_at_creation: false,
+        });
+        let mut free = Vec::new();
+        if size < block_size {
+            free.push(size..block_size);
+        }
+        self.blocks.push(Block { buffer, free });
+        tracing::debug!(
+            "{}: allocated block {} of {block_size} bytes",
+            self.label,
+            self.blocks.len() - 1
+        );
+
+        Allocation {
+            block: self.blocks.len() - 1,
+            offset: 0,
+            size,
+        }
+    }
+
+    /// Allocate space for `contents` and upload it
+    pub fn allocate_init(&mut self, device: &Device, queue: &Queue, contents: &[u8]) -> Allocation {
+        let allocation = self.allocate(device, contents.len() as BufferAddress);
+        self.write(queue, &allocation, contents);
+        allocation
+    }
+
+    /// Overwrite the start of `allocation` with `contents`
+    pub fn write(&self, queue: &Queue, allocation: &Allocation, contents: &[u8]) {
+        assert!(
+            contents.len() as BufferAddress <= allocation.size,
+            "contents don't fit in the allocation"
+        );
+        let buffer = self.buffer(allocation);
+        // Writes have to be a multiple of `COPY_BUFFER_ALIGNMENT` bytes, which the allocation is always padded to
+        if (contents.len() as BufferAddress).is_multiple_of(COPY_BUFFER_ALIGNMENT) {
+            queue.write_buffer(buffer, allocation.offset, contents);
+        } else {
+            let mut padded = contents.to_vec();
+            padded.resize(
+                align(contents.len() as BufferAddress, COPY_BUFFER_ALIGNMENT) as usize,
+                0,
+            );
+            queue.write_buffer(buffer, allocation.offset, &padded);
+        }
+    }
+
+    /// Return `allocation`'s space to the pool so that it can be reused
+    pub fn free(&mut self, allocation: Allocation) {
+        give_back(&mut self.blocks[allocation.block].free, allocation.range());
+    }
+
+    pub fn buffer(&self, allocation: &Allocation) -> &Buffer {
+        &self.blocks[allocation.block].buffer
+    }
+
+    /// The part of the pool's buffer covered by `allocation`, for use with `set_vertex_buffer` etc.
+    pub fn slice(&self, allocation: &Allocation) -> BufferSlice<'_> {
+        self.buffer(allocation).slice(allocation.range())
+    }
+
+    pub fn binding(&self, allocation: &Allocation) -> BindingResource<'_> {
+        BindingResource::Buffer(BufferBinding {
+            buffer: self.buffer(allocation),
+            offset: allocation.offset,
+            size: BufferSize::new(allocation.size),
+        })
+    }
+
+    /// The total size in bytes of every buffer in the pool
+    pub fn capacity(&self) -> BufferAddress {
+        self.blocks.iter().map(|block| block.buffer.size()).sum()
+    }
+}
+
+fn align(size: BufferAddress, alignment: BufferAddress) -> BufferAddress {
+    (size + alignment - 1) & !(alignment - 1)
+}
+
+/// Carve `size` bytes out of the first free region which is big enough, returning its offset
+fn take(free: &mut Vec<Range<BufferAddress>>, size: BufferAddress) -> Option<BufferAddress> {
+    let index = free
+        .iter()
+        .position(|range| range.end - range.start >= size)?;
+    let offset = free[index].start;
+    free[index].start += size;
+    if free[index].is_empty() {
+        free.remove(index);
+    }
+    Some(offset)
+}
+
+/// Return `range` to the free regions, which are kept sorted by offset and never adjacent to each other
+fn give_back(free: &mut Vec<Range<BufferAddress>>, range: Range<BufferAddress>) {
+    let index = free.partition_point(|free| free.start < range.start);
+    free.insert(index, range);
+
+    // Coalesce with the neighbouring free regions so the space can be used by larger allocations
+    if index + 1 < free.len() && free[index].end == free[index + 1].start {
+        free[index].end = free.remove(index + 1).end;
+    }
+    if index > 0 && free[index - 1].end == free[index].start {
+        free[index - 1].end = free.remove(index).end;
+    }
+}
+
+#[cfg(test)]
+// the free regions are compared against lists which are often one range long
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_first_fit() {
+        let mut free = vec![0..16, 32..96, 128..256];
+        // too big for the first region
+        assert_eq!(take(&mut free, 32), Some(32));
+        assert_eq!(free, [0..16, 64..96, 128..256]);
+        // uses up the first region entirely
+        assert_eq!(take(&mut free, 16), Some(0));
+        assert_eq!(free, [64..96, 128..256]);
+        assert_eq!(take(&mut free, 128), Some(128));
+        assert_eq!(free, [64..96]);
+        assert_eq!(take(&mut free, 64), None);
+        assert_eq!(free, [64..96]);
+    }
+
+    #[test]
+    fn give_back_coalesces() {
+        // a block of 4 allocations of 16 bytes, all taken
+        let mut free = Vec::new();
+        give_back(&mut free, 16..32);
+        assert_eq!(free, [16..32]);
+        give_back(&mut free, 48..64);
+        assert_eq!(free, [16..32, 48..64]);
+        // joins both neighbours
+        give_back(&mut free, 32..48);
+        assert_eq!(free, [16..64]);
+        give_back(&mut free, 0..16);
+        assert_eq!(free, [0..64]);
+    }
+
+    #[test]
+    fn give_back_middle_then_neighbours() {
+        let mut free = vec![0..64];
+        let [a, b, c] = [16, 16, 16].map(|size| take(&mut free, size).unwrap());
+        assert_eq!([a, b, c], [0, 16, 32]);
+        assert_eq!(free, [48..64]);
+        give_back(&mut free, b..b + 16);
+        assert_eq!(free, [16..32, 48..64]);
+        give_back(&mut free, a..a + 16);
+        assert_eq!(free, [0..32, 48..64]);
+        give_back(&mut free, c..c + 16);
+        assert_eq!(free, [0..64]);
+        // the whole block can be taken again
+        assert_eq!(take(&mut free, 64), Some(0));
+        assert!(free.is_empty());
+    }
+}
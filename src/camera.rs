@@ -1,7 +1,12 @@
 use bytemuck::{Pod, Zeroable};
-use cgmath::{perspective, Deg, Matrix4, Point3, Vector3};
 use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
 
+use crate::{
+    geometry::{Plane, Sphere},
+    math::{Deg, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3},
+    projection,
+};
+
 pub struct Camera {
     pub eye: Point3<f32>,
     pub target: Point3<f32>,
@@ -12,23 +17,13 @@ pub struct Camera {
     pub zfar: f32,
 }
 
-/// WGPU's coordinate system is based on DirectX and Metal's co-ordinate systems.
-/// `cgmath` is built for OpenGL's coordinate system,
-/// this matrix will scale and translate our scene from OpenGL's co-ordinate system to WGPU's
-#[rustfmt::skip]
-pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
-    1.0, 0.0, 0.0, 0.0,
-    0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.0,
-    0.0, 0.0, 0.5, 1.0,
-);
-
 impl Camera {
+    /// Already in wgpu's 0 to 1 depth range, so it can go straight into a uniform
     pub fn build_view_projection_matrix(&self) -> Matrix4<f32> {
         // Moves the world to be at the position and rotation of the camera
-        let view = Matrix4::look_at_rh(self.eye, self.target, self.up);
+        let view = projection::look_at(self.eye, self.target, self.up);
         // Warps the scene to give the effect of depth
-        let proj = perspective(Deg(self.fovy), self.aspect, self.znear, self.zfar);
+        let proj = projection::perspective(Deg(self.fovy), self.aspect, self.znear, self.zfar);
 
         proj * view
     }
@@ -42,9 +37,26 @@ pub struct CameraController {
     is_backward_pressed: bool,
     is_left_pressed: bool,
     is_right_pressed: bool,
+    /// Where the camera's moving to frame something, see `focus`
+    focus: Option<Focus>,
+}
+
+/// The camera gliding from one place to another
+struct Focus {
+    from_eye: Point3<f32>,
+    from_target: Point3<f32>,
+    to_eye: Point3<f32>,
+    to_target: Point3<f32>,
+    /// From 0.0 when it starts to 1.0 once it's there
+    progress: f32,
 }
 
 impl CameraController {
+    /// How long `focus` takes to move the camera, in seconds
+    const FOCUS_DURATION: f32 = 0.4;
+    /// How much room is left around what's focused on, as a multiple of its size
+    const FOCUS_MARGIN: f32 = 1.1;
+
     pub fn new(speed: f32) -> Self {
         Self {
             speed,
@@ -54,9 +66,15 @@ impl CameraController {
             is_backward_pressed: false,
             is_left_pressed: false,
             is_right_pressed: false,
+            focus: None,
         }
     }
 
+    /// How far the camera moves each frame while a key is held
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
     pub fn process_events(&mut self, event: &WindowEvent) -> bool {
         match event {
             WindowEvent::KeyboardInput {
@@ -101,8 +119,59 @@ impl CameraController {
         }
     }
 
-    pub fn update_camera(&self, camera: &mut Camera) {
-        use cgmath::InnerSpace;
+    /// Glide `camera` over to look at the middle of `sphere` from just far enough away to see all of it,
+    /// looking the same way as it does now. Moving the camera by hand stops it where it is
+    pub fn focus(&mut self, camera: &Camera, sphere: Sphere) {
+        let direction = (camera.target - camera.eye).normalize();
+        // whichever of the vertical and horizontal fields of view is narrower has to fit the sphere
+        let half_fovy = (camera.fovy / 2.0).to_radians();
+        let half_fovx = (half_fovy.tan() * camera.aspect).atan();
+        let distance = (sphere.radius * Self::FOCUS_MARGIN / half_fovy.min(half_fovx).sin())
+            .max(camera.znear + sphere.radius);
+        self.focus = Some(Focus {
+            from_eye: camera.eye,
+            from_target: camera.target,
+            to_eye: sphere.center - direction * distance,
+            to_target: sphere.center,
+            progress: 0.0,
+        });
+    }
+
+    pub fn is_focusing(&self) -> bool {
+        self.focus.is_some()
+    }
+
+    /// Forget which keys are held, e.g. once key presses stop coming here, as their releases won't either
+    pub fn release_all(&mut self) {
+        self.is_up_pressed = false;
+        self.is_down_pressed = false;
+        self.is_forward_pressed = false;
+        self.is_backward_pressed = false;
+        self.is_left_pressed = false;
+        self.is_right_pressed = false;
+    }
+
+    /// Move `camera`, `delta` seconds since the last frame
+    pub fn update_camera(&mut self, camera: &mut Camera, delta: f32) {
+        let moving = self.is_forward_pressed
+            || self.is_backward_pressed
+            || self.is_left_pressed
+            || self.is_right_pressed;
+        if moving {
+            self.focus = None;
+        }
+        if let Some(focus) = &mut self.focus {
+            focus.progress = (focus.progress + delta / Self::FOCUS_DURATION).min(1.0);
+            // smoothstep, so it eases in and out rather than jumping off and stopping dead
+            let t = focus.progress * focus.progress * (3.0 - 2.0 * focus.progress);
+            camera.eye = focus.from_eye + (focus.to_eye - focus.from_eye) * t;
+            camera.target = focus.from_target + (focus.to_target - focus.from_target) * t;
+            if focus.progress >= 1.0 {
+                self.focus = None;
+            }
+            return;
+        }
+
         let forward = camera.target - camera.eye;
         let forward_norm = forward.normalize();
         let forward_mag = forward.magnitude();
@@ -138,20 +207,63 @@ impl CameraController {
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Pod, Zeroable)]
 pub struct CameraUniform {
+    /// The camera's position, as a `vec4` for the sake of alignment
+    view_position: [f32; 4],
     view_proj: [[f32; 4]; 4],
+    /// Last frame's `view_proj`, used to work out the motion vectors for motion blur
+    prev_view_proj: [[f32; 4]; 4],
+    /// Anything where `dot(clip_plane, (position, 1))` is negative for any of them isn't drawn,
+    /// see `set_clip_planes`
+    clip_planes: [[f32; 4]; CameraUniform::CLIP_PLANES],
+    /// The inverse of `view_proj`, for turning screen positions back into directions in the world
+    inv_view_proj: [[f32; 4]; 4],
 }
 
 impl Default for CameraUniform {
     fn default() -> Self {
-        use cgmath::SquareMatrix;
         Self {
+            view_position: [0.0; 4],
             view_proj: Matrix4::identity().into(),
+            prev_view_proj: Matrix4::identity().into(),
+            clip_planes: [Self::UNCLIPPED; Self::CLIP_PLANES],
+            inv_view_proj: Matrix4::identity().into(),
         }
     }
 }
 
 impl CameraUniform {
+    /// How many clip planes there's room for, which has to match `camera.wgsl`
+    pub const CLIP_PLANES: usize = 4;
+    /// Every point is on the positive side, so nothing is clipped
+    const UNCLIPPED: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+    /// Call once per frame, as the previous matrix becomes last frame's
     pub fn update_view_proj(&mut self, camera: &Camera) {
-        self.view_proj = (OPENGL_TO_WGPU_MATRIX * camera.build_view_projection_matrix()).into();
+        self.set_view_proj(camera.eye, camera.build_view_projection_matrix());
+    }
+
+    /// Like `update_view_proj`, for views which don't come from a `Camera`, e.g. the faces of a cubemap
+    pub fn set_view_proj(&mut self, eye: Point3<f32>, view_proj: Matrix4<f32>) {
+        self.prev_view_proj = self.view_proj;
+        self.view_position = eye.to_homogeneous().into();
+        self.view_proj = view_proj.into();
+        // only singular if the camera is, e.g. if its target is its eye
+        self.inv_view_proj = view_proj.invert().unwrap_or_else(Matrix4::identity).into();
+    }
+
+    /// Only draw what's on the side each of `planes` faces, e.g. for leaving out what's under a reflective
+    /// surface when rendering its reflection, or for cutting the scene open.
+    /// Any past the first `CLIP_PLANES` are ignored
+    pub fn set_clip_planes<'a>(&mut self, planes: impl IntoIterator<Item = &'a Plane>) {
+        self.clip_planes = [Self::UNCLIPPED; Self::CLIP_PLANES];
+        for (clip_plane, plane) in self.clip_planes.iter_mut().zip(planes) {
+            let normal = plane.normal;
+            *clip_plane = [normal.x, normal.y, normal.z, -plane.distance];
+        }
+    }
+
+    /// Forget last frame's matrix, so that a sudden jump (e.g. on the first frame) isn't blurred
+    pub fn reset_history(&mut self) {
+        self.prev_view_proj = self.view_proj;
     }
 }
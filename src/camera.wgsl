@@ -0,0 +1,25 @@
+// Shared camera uniform, bound at group 1 by every pipeline that renders the scene
+
+struct CameraUniform {
+    // the camera's position in world space, w is unused
+    view_position: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    // last frame's `view_proj`, for working out how far things have moved on screen
+    prev_view_proj: mat4x4<f32>,
+    // anything on the negative side of any of these planes isn't drawn, e.g. what's under water in its reflection
+    // or what the render settings cut away. Unused planes are (0, 0, 0, 1), which nothing is behind
+    clip_planes: array<vec4<f32>, 4>,
+    inv_view_proj: mat4x4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Whether a fragment at `world_position` should be discarded for being behind any of the clip planes
+fn is_clipped(world_position: vec3<f32>) -> bool {
+    for (var i = 0; i < 4; i = i + 1) {
+        if dot(camera.clip_planes[i], vec4<f32>(world_position, 1.0)) < 0.0 {
+            return true;
+        }
+    }
+    return false;
+}
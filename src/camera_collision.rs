@@ -0,0 +1,107 @@
+//! Keeping the camera out of the scene's geometry. The camera is a small ball which is swept from where it
+//! was to where it's moving to against the boxes around the scene's instances, and slides along the face of
+//! whichever it runs into rather than stopping dead, so that orbiting past a cube glides round it.
+//! It's kept above the ground too, where there's terrain. Boxes it's already inside, e.g. because something
+//! moved onto it, are ignored so that it can get back out. `WalkController` moves through the scene the same way
+
+use crate::{
+    geometry::{Aabb, Ray},
+    math::{InnerSpace, Point3},
+};
+
+pub struct CameraCollision {
+    /// Whether the camera collides with anything at all
+    pub enabled: bool,
+    /// How close the camera can get to anything, which has to be more than the near plane's distance
+    /// for the camera not to see into what it's up against
+    pub radius: f32,
+}
+
+impl Default for CameraCollision {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            radius: 0.25,
+        }
+    }
+}
+
+impl CameraCollision {
+    /// Where the camera ends up moving from `from` towards `to` among `obstacles`, above `ground`'s height
+    /// at each point, if there's ground there
+    pub fn resolve(
+        &self,
+        from: Point3<f32>,
+        to: Point3<f32>,
+        obstacles: &[Aabb],
+        ground: impl Fn(f32, f32) -> Option<f32>,
+    ) -> Point3<f32> {
+        if !self.enabled {
+            return to;
+        }
+        // sweeping a ball against a box is close enough to sweeping a point against the box grown by the ball's radius
+        let obstacles = obstacles
+            .iter()
+            .map(|obstacle| obstacle.expanded(self.radius))
+            .collect::<Vec<_>>();
+        let mut position = slide(from, to, &obstacles).position;
+        if let Some(height) = ground(position.x, position.z) {
+            position.y = position.y.max(height + self.radius);
+        }
+        position
+    }
+}
+
+/// Where a point moving from `from` towards `to` ends up, see `slide`
+#[derive(Clone, Copy, Debug)]
+pub struct Slide {
+    pub position: Point3<f32>,
+    /// Whether it ran into the top of anything, i.e. it's standing on something
+    pub landed: bool,
+    /// Whether it ran into the bottom of anything
+    pub bumped: bool,
+}
+
+/// Move a point from `from` towards `to`, sliding along the face of any of `obstacles` it runs into
+/// rather than stopping dead. Boxes it starts inside are ignored, so that it can get back out
+pub fn slide(from: Point3<f32>, to: Point3<f32>, obstacles: &[Aabb]) -> Slide {
+    /// How many times it can slide off something in one move, e.g. into a corner
+    const MAX_SLIDES: usize = 3;
+    /// How far it's kept off what it slides along, so that it isn't caught on the face next time
+    const SKIN: f32 = 1e-3;
+
+    let mut slide = Slide {
+        position: from,
+        landed: false,
+        bumped: false,
+    };
+    let mut motion = to - from;
+    for _ in 0..MAX_SLIDES {
+        if motion.magnitude2() < f32::EPSILON {
+            break;
+        }
+        // distances along the ray are fractions of the motion
+        let ray = Ray {
+            origin: slide.position,
+            direction: motion,
+        };
+        let hit = obstacles
+            .iter()
+            .filter(|obstacle| !obstacle.contains(slide.position))
+            .filter_map(|obstacle| Some((ray.intersect_aabb(obstacle)?, obstacle)))
+            .filter(|&(distance, _)| distance < 1.0)
+            .min_by(|(a, _), (b, _)| a.total_cmp(b));
+        let Some((distance, obstacle)) = hit else {
+            slide.position += motion;
+            break;
+        };
+        let normal = obstacle.nearest_face_normal(ray.at(distance));
+        slide.position = ray.at(distance) + normal * SKIN;
+        slide.landed |= normal.y > 0.0;
+        slide.bumped |= normal.y < 0.0;
+        // what's left of the motion, without the part going into the face
+        let remaining = motion * (1.0 - distance);
+        motion = remaining - normal * remaining.dot(normal).min(0.0);
+    }
+    slide
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+
+use wgpu::{Adapter, Backend, Device, DownlevelFlags, Features, Limits};
+
+use crate::material_textures::MaterialTextures;
+
+/// The optional features the renderer makes use of, and whether the device has them.
+/// Anything which depends on one of them should check here and take its fallback path when it's
+/// missing, rather than assuming it and hitting a validation error on weaker adapters, e.g. WebGL
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// `PolygonMode::Line` for wireframes, otherwise they have to be drawn as line lists
+    pub polygon_mode_line: bool,
+    /// GPU timestamps for timing passes, otherwise only the CPU's side can be timed
+    pub timestamp_query: bool,
+    /// Pipeline statistics queries for counting the fragments each object shades, otherwise they go uncounted
+    pub pipeline_statistics: bool,
+    /// BC compressed textures, otherwise they have to be decompressed when they're loaded
+    pub texture_compression_bc: bool,
+    /// Push constants for small per-draw data, otherwise it has to go through uniform buffers
+    pub push_constants: bool,
+    /// Storage buffers in vertex shaders for morph targets, otherwise meshes are drawn unmorphed.
+    /// This is a downlevel flag rather than a feature, so it can't be requested
+    pub vertex_storage: bool,
+    /// Compute shaders for generating procedural textures, otherwise they're left out.
+    /// Also a downlevel flag, which WebGL doesn't have
+    pub compute_shaders: bool,
+    /// Storage textures for compute shaders to write images through, otherwise they write a storage buffer
+    /// which is copied into the texture. wgpu's GL backend can't create pipelines with them, so it goes without
+    pub storage_textures: bool,
+    /// Binding arrays of textures indexed per instance, for painting models with any of the scene's textures
+    /// without rebinding, otherwise each texture has a bind group of its own, see `MaterialTextures`
+    pub bindless_textures: bool,
+}
+
+impl Capabilities {
+    /// Every feature the renderer will use if it's available
+    pub const OPTIONAL: Features = Features::POLYGON_MODE_LINE
+        .union(Features::TIMESTAMP_QUERY)
+        .union(Features::PIPELINE_STATISTICS_QUERY)
+        .union(Features::TEXTURE_COMPRESSION_BC)
+        .union(Features::PUSH_CONSTANTS)
+        .union(Self::BINDLESS);
+
+    /// What `bindless_textures` needs, as instances index the array with what they pass to the fragment shader
+    const BINDLESS: Features = Features::TEXTURE_BINDING_ARRAY
+        .union(Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING);
+
+    /// The features to request from `adapter`, which are the optional ones it supports
+    pub fn features(adapter: &Adapter) -> Features {
+        adapter.features() & Self::OPTIONAL
+    }
+
+    /// What `device` was actually created with, from `adapter`
+    pub fn new(adapter: &Adapter, device: &Device) -> Self {
+        let features = device.features();
+        let compute_shaders = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(DownlevelFlags::COMPUTE_SHADERS);
+        Self {
+            polygon_mode_line: features.contains(Features::POLYGON_MODE_LINE),
+            timestamp_query: features.contains(Features::TIMESTAMP_QUERY),
+            pipeline_statistics: features.contains(Features::PIPELINE_STATISTICS_QUERY),
+            texture_compression_bc: features.contains(Features::TEXTURE_COMPRESSION_BC),
+            // the feature alone isn't much use without any room for them
+            push_constants: features.contains(Features::PUSH_CONSTANTS)
+                && device.limits().max_push_constant_size > 0,
+            vertex_storage: adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(DownlevelFlags::VERTEX_STORAGE),
+            compute_shaders,
+            storage_textures: compute_shaders && adapter.get_info().backend != Backend::Gl,
+            // with room for the binding array on top of the textures every stage can have
+            bindless_textures: features.contains(Self::BINDLESS)
+                && device.limits().max_sampled_textures_per_shader_stage as usize
+                    >= MaterialTextures::CAPACITY
+                        + Limits::default().max_sampled_textures_per_shader_stage as usize,
+        }
+    }
+
+    /// The optional features which are missing, and so have fallbacks in use.
+    /// This leaves out `vertex_storage`, `compute_shaders` and `storage_textures`, which aren't features
+    pub fn missing(&self) -> Features {
+        let mut missing = Features::empty();
+        missing.set(Features::POLYGON_MODE_LINE, !self.polygon_mode_line);
+        missing.set(Features::TIMESTAMP_QUERY, !self.timestamp_query);
+        missing.set(
+            Features::PIPELINE_STATISTICS_QUERY,
+            !self.pipeline_statistics,
+        );
+        missing.set(
+            Features::TEXTURE_COMPRESSION_BC,
+            !self.texture_compression_bc,
+        );
+        missing.set(Features::PUSH_CONSTANTS, !self.push_constants);
+        missing.set(Self::BINDLESS, !self.bindless_textures);
+        missing
+    }
+}
+
+/// Lists the fallbacks in use, if any
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fallbacks = [
+            (self.polygon_mode_line, "wireframes as line lists"),
+            (self.timestamp_query, "no GPU timings"),
+            (self.pipeline_statistics, "no fragment counts"),
+            (
+                self.texture_compression_bc,
+                "BC textures decompressed on load",
+            ),
+            (self.push_constants, "per-draw data in uniform buffers"),
+            (self.bindless_textures, "a bind group per texture"),
+            (self.vertex_storage, "no morph targets"),
+            (self.compute_shaders, "no procedural textures"),
+            (
+                // without compute shaders there aren't any image filters to fall back for
+                self.storage_textures || !self.compute_shaders,
+                "image filters written through buffers",
+            ),
+        ];
+        let mut fallbacks = fallbacks
+            .iter()
+            .filter(|(available, _)| !available)
+            .map(|(_, fallback)| *fallback)
+            .peekable();
+        if fallbacks.peek().is_none() {
+            return write!(f, "every optional feature is available");
+        }
+        write!(f, "falling back to ")?;
+        for (index, fallback) in fallbacks.enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{fallback}")?;
+        }
+        Ok(())
+    }
+}
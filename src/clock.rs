@@ -0,0 +1,120 @@
+use std::time::{Duration, Instant};
+
+/// The point in time a frame is rendered at
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameTime {
+    /// The number of frames before this one
+    pub frame: u64,
+    /// Seconds since the first frame
+    pub elapsed: f32,
+    /// Seconds since the last frame, 0 for the first one
+    pub delta: f32,
+}
+
+/// Where frame times come from, either the wall clock or a fixed step per frame.
+/// A fixed step makes every run see the same times, so that screenshots and benchmarks are
+/// reproducible no matter how long each frame really took
+#[derive(Clone, Copy, Debug)]
+pub enum Clock {
+    RealTime {
+        start: Option<Instant>,
+        last: Option<Instant>,
+        frame: u64,
+        /// When `pause` was called, if the clock hasn't been resumed since
+        paused: Option<Instant>,
+    },
+    Fixed {
+        delta: Duration,
+        frame: u64,
+    },
+}
+
+impl Clock {
+    /// The step used by `--deterministic`, as if running at 60 FPS
+    pub const DEFAULT_DELTA: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+    /// Follow the wall clock, starting from the first `tick`
+    pub fn real_time() -> Self {
+        Self::RealTime {
+            start: None,
+            last: None,
+            frame: 0,
+            paused: None,
+        }
+    }
+
+    /// Advance by exactly `delta` every frame, whatever the wall clock says
+    pub fn fixed(delta: Duration) -> Self {
+        Self::Fixed { delta, frame: 0 }
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        matches!(self, Self::Fixed { .. })
+    }
+
+    /// Stop the wall clock from counting, e.g. while the app is suspended,
+    /// so that animations carry on from where they were rather than jumping ahead
+    pub fn pause(&mut self) {
+        if let Self::RealTime { paused, .. } = self {
+            paused.get_or_insert_with(Instant::now);
+        }
+    }
+
+    /// Start counting again after `pause`, leaving out the time spent paused
+    pub fn resume(&mut self) {
+        if let Self::RealTime {
+            start,
+            last,
+            paused,
+            ..
+        } = self
+        {
+            if let Some(paused) = paused.take() {
+                let pause = paused.elapsed();
+                for instant in [start, last].into_iter().flatten() {
+                    *instant += pause;
+                }
+            }
+        }
+    }
+
+    /// Start the next frame, returning its time
+    pub fn tick(&mut self) -> FrameTime {
+        match self {
+            Self::RealTime {
+                start, last, frame, ..
+            } => {
+                let now = Instant::now();
+                let start = *start.get_or_insert(now);
+                let delta = last.replace(now).map_or(Duration::ZERO, |last| now - last);
+                let time = FrameTime {
+                    frame: *frame,
+                    elapsed: (now - start).as_secs_f32(),
+                    delta: delta.as_secs_f32(),
+                };
+                *frame += 1;
+                time
+            }
+            Self::Fixed { delta, frame } => {
+                let time = FrameTime {
+                    frame: *frame,
+                    // multiplied out rather than summed, so that rounding errors don't build up
+                    elapsed: (delta.as_secs_f64() * *frame as f64) as f32,
+                    delta: if *frame == 0 {
+                        0.0
+                    } else {
+                        delta.as_secs_f32()
+                    },
+                };
+                *frame += 1;
+                time
+            }
+        }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::real_time()
+    }
+}
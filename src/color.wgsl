@@ -0,0 +1,15 @@
+// Conversions between linear colours and the sRGB transfer function
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let cutoff = color < vec3<f32>(0.0031308);
+    let lower = color * 12.92;
+    let higher = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(higher, lower, cutoff);
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let cutoff = color < vec3<f32>(0.04045);
+    let lower = color / 12.92;
+    let higher = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(higher, lower, cutoff);
+}
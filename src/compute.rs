@@ -0,0 +1,153 @@
+//! Compute work which doesn't depend on the frame being rendered, like simulating particles or culling
+//! for the next frame, scheduled apart from the frame so that the GPU can overlap the two.
+//!
+//! wgpu has one queue per device, so this can't pick a separate hardware queue. Instead each frame's
+//! workloads are recorded into a command buffer of their own and submitted separately, ahead of the frame,
+//! which backends with more than one queue or which don't serialise submissions can run alongside
+//! the frame's rendering, and the rest run first as they would have anyway.
+//! wgpu orders accesses to the same buffer or texture across submissions by itself, so a workload
+//! is always finished with whatever the frame reads from it, but that also makes the frame wait for it.
+//! `PingPong` avoids the wait by having each side read what the other wrote the frame before.
+//! Auto exposure's luminance histogram is one, measuring the scene tonemapping was given last frame,
+//! see `Tonemapping::exposure_workload`
+
+use anyhow::*;
+use wgpu::{CommandEncoder, CommandEncoderDescriptor, Device, Maintain, Queue, SubmissionIndex};
+
+use crate::capabilities::Capabilities;
+
+/// Some compute work to dispatch every frame, see `AsyncCompute`
+pub trait ComputeWorkload {
+    /// Shown in GPU captures and profiles
+    fn label(&self) -> &str;
+
+    /// Record this frame's passes into `encoder`, which is submitted before the frame.
+    /// `queue` is for writing uniforms, which land before `encoder` runs
+    fn dispatch(&mut self, queue: &Queue, encoder: &mut CommandEncoder);
+}
+
+/// Which workload is which, for taking it back out of `AsyncCompute`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ComputeId(u64);
+
+/// Schedules `ComputeWorkload`s, submitting them every frame apart from the frame's rendering
+pub struct AsyncCompute {
+    /// Whether the device has compute shaders, without which nothing can be scheduled
+    supported: bool,
+    workloads: Vec<(ComputeId, Box<dyn ComputeWorkload>)>,
+    next_id: u64,
+    /// The workloads' last submission, for `wait`
+    submitted: Option<SubmissionIndex>,
+}
+
+impl AsyncCompute {
+    pub fn new(capabilities: &Capabilities) -> Self {
+        Self {
+            supported: capabilities.compute_shaders,
+            workloads: Vec::new(),
+            next_id: 0,
+            submitted: None,
+        }
+    }
+
+    /// Whether workloads can be added, which needs compute shaders
+    pub fn is_supported(&self) -> bool {
+        self.supported
+    }
+
+    /// Dispatch `workload` every frame from the next one, after those added before it
+    pub fn add(&mut self, workload: impl ComputeWorkload + 'static) -> Result<ComputeId> {
+        ensure!(
+            self.supported,
+            "the device doesn't have compute shaders to run {} with",
+            workload.label()
+        );
+        let id = ComputeId(self.next_id);
+        self.next_id += 1;
+        self.workloads.push((id, Box::new(workload)));
+        Ok(id)
+    }
+
+    /// Stop dispatching the workload, handing it back if it was still scheduled
+    pub fn remove(&mut self, id: ComputeId) -> Option<Box<dyn ComputeWorkload>> {
+        let index = self
+            .workloads
+            .iter()
+            .position(|(workload, _)| *workload == id)?;
+        Some(self.workloads.remove(index).1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.workloads.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.workloads.is_empty()
+    }
+
+    /// Record every workload into one command buffer and submit it, which has to happen
+    /// before the frame it's for is submitted
+    pub fn submit(&mut self, device: &Device, queue: &Queue) {
+        if self.workloads.is_empty() {
+            return;
+        }
+        puffin::profile_function!();
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Async Compute Encoder"),
+        });
+        for (_, workload) in &mut self.workloads {
+            puffin::profile_scope!("workload", workload.label());
+            encoder.push_debug_group(workload.label());
+            workload.dispatch(queue, &mut encoder);
+            encoder.pop_debug_group();
+        }
+        self.submitted = Some(queue.submit(std::iter::once(encoder.finish())));
+    }
+
+    /// Block until the workloads' last submission has finished on the GPU,
+    /// e.g. before reading back a buffer one of them wrote
+    pub fn wait(&self, device: &Device) {
+        if let Some(submitted) = &self.submitted {
+            device.poll(Maintain::WaitForSubmissionIndex(*submitted));
+        }
+    }
+}
+
+/// Two of something, like a buffer, a texture or a bind group of either, which take turns being written
+/// by one side, e.g. a workload, and read by the other, e.g. the frame, so that neither waits for the other
+/// to finish with it. The reader sees what was written a frame late, in exchange
+pub struct PingPong<T> {
+    items: [T; 2],
+    /// Which of `items` is being written this frame
+    written: usize,
+}
+
+impl<T> PingPong<T> {
+    /// Both copies, made by `create` from their index, 0 or 1, e.g. for telling their labels apart
+    pub fn new(mut create: impl FnMut(usize) -> T) -> Self {
+        Self {
+            items: [create(0), create(1)],
+            written: 0,
+        }
+    }
+
+    /// `items[0]` is written first
+    pub fn from_pair(items: [T; 2]) -> Self {
+        Self { items, written: 0 }
+    }
+
+    /// The copy being written this frame
+    pub fn write(&self) -> &T {
+        &self.items[self.written]
+    }
+
+    /// The copy written last frame, which is read this frame
+    pub fn read(&self) -> &T {
+        &self.items[1 - self.written]
+    }
+
+    /// Trade the copies over, once a frame before either is used
+    pub fn swap(&mut self) {
+        self.written = 1 - self.written;
+    }
+}
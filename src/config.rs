@@ -0,0 +1,757 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use anyhow::*;
+use wgpu::{Backends, PresentMode};
+
+use crate::{
+    debug_view::DebugView,
+    displacement::DisplacementConfig,
+    fog::FogMode,
+    fullscreen::VideoModeRequest,
+    glass::GlassSettings,
+    image_filter::ImageFilter,
+    jobs::JobSystem,
+    logging::LogConfig,
+    mirror::MirrorSettings,
+    parallax::Parallax,
+    postprocess::tonemapping::ExposureMode,
+    procedural::TextureSource,
+    recorder::RecordOutput,
+    sky::Sun,
+    stereo::{StereoOutput, StereoSettings},
+    subdivision::Subdivision,
+    terrain::TerrainConfig,
+    time_of_day::TimeOfDay,
+    vegetation::VegetationConfig,
+    viewport::Letterbox,
+    water::WaterSettings,
+    window::{self, WindowConfig},
+};
+
+/// Options for `run`, which can be parsed from the command line with `Config::from_args`
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// A directory to record every wgpu API call into, for bug reports against wgpu or drivers.
+    /// wgpu only records it when built with its `trace` feature, e.g. `cargo run --features wgpu/trace`
+    pub api_trace: Option<PathBuf>,
+    /// Whether to install a subscriber for logs and traces, or leave it to the host
+    pub logging: LogConfig,
+    /// Write every frame's CPU and GPU times to this file, as CSV or as JSON lines if it ends in `.json`
+    pub metrics: Option<PathBuf>,
+    /// Adjust the render scale to keep the GPU at this frame rate, see `DynamicResolution`
+    pub target_fps: Option<f64>,
+    /// Render this many frames of a fixed camera path, print their timings as JSON and exit
+    pub benchmark: Option<u32>,
+    /// Step time by a fixed amount every frame instead of following the wall clock,
+    /// so that every run renders exactly the same frames. Benchmarks always do
+    pub deterministic: bool,
+    /// The graphics APIs to pick an adapter from, e.g. to test the GL path on a machine with Vulkan.
+    /// If this isn't set the `WGPU_BACKEND` environment variable is used, then every backend
+    pub backends: Option<Backends>,
+    /// Where recordings started with F9 are written, as PNGs, a video or a GIF, which needs the `recording` feature
+    pub record: RecordOutput,
+    /// Start in exclusive fullscreen, in the video mode closest to this one. F11 toggles it
+    pub fullscreen: Option<VideoModeRequest>,
+    /// Print every monitor's video modes and exit, for picking one for `fullscreen`
+    pub list_video_modes: bool,
+    /// Take JSON commands over TCP on this address, which needs the `remote` feature
+    pub remote: Option<SocketAddr>,
+    /// How frames are synced with the display, which falls back to `PresentMode::Fifo` if unsupported.
+    /// `PresentMode::Immediate` gives the lowest latency, at the cost of tearing
+    pub present_mode: Option<PresentMode>,
+    /// How many threads the frame's passes are recorded on, one if `None`, see `State::set_encoding_threads`
+    pub encoding_threads: Option<usize>,
+    /// The TOML file the render settings are read from, otherwise `Settings::DEFAULT_PATH`
+    pub settings: Option<PathBuf>,
+    /// The window's title, icon, size limits and decorations
+    pub window: WindowConfig,
+    /// Keep the picture at a fixed aspect ratio, with black bars filling the rest of the window
+    pub letterbox: Option<Letterbox>,
+    /// The texture on the cubes and floor, otherwise the planks
+    pub texture: Option<TextureSource>,
+    /// Filter the texture on the cubes and floor with these, in order, once it's loaded
+    pub texture_filters: Vec<ImageFilter>,
+    /// More textures for the cubes, which take turns with `texture` from one cube to the next
+    pub cube_textures: Vec<TextureSource>,
+    /// Replace the floor with terrain built from a heightmap
+    pub terrain: Option<TerrainConfig>,
+    /// Add a reflective water surface
+    pub water: Option<WaterSettings>,
+    /// Stand these mirrors in the scene
+    pub mirrors: Vec<MirrorSettings>,
+    /// Make the front row of cubes glass, which bends light like this
+    pub glass: Option<GlassSettings>,
+    /// Outline these instances, by their index in the instance buffer, where the floor is first and the cubes follow
+    pub outline: Vec<u32>,
+    /// A `.ply` or `.xyz` file of points to show, see `load_points`
+    pub point_cloud: Option<PathBuf>,
+    /// How wide each of the point cloud's points is in world units, `PointCloudSettings`' default if `None`
+    pub point_size: Option<f32>,
+    /// Subdivide the cubes this way, this many times, before they're uploaded
+    pub subdivide: Option<(Subdivision, u32)>,
+    /// Draw lines along the edges of the cubes
+    pub edges: bool,
+    /// Show the normals of these models, by their index, where the floor is first and the cubes follow
+    pub normals: Vec<usize>,
+    /// Start with the scene's meshes showing this in place of their shaded colour, `V` cycles through the rest
+    pub debug_view: DebugView,
+    /// Draw the scene for each eye side by side, see `Stereo`
+    pub stereo: Option<StereoSettings>,
+    /// Tonemap the scene with this exposure, which is left out by default
+    pub exposure: Option<ExposureMode>,
+    /// How the fog thickens with distance, which is off by default
+    pub fog: FogMode,
+    /// Draw a sky lit by this sun instead of a flat background, ignored for transparent windows
+    pub sky: Option<Sun>,
+    /// Move the sky's sun through the day, which turns the sky on if it isn't already
+    pub time_of_day: Option<TimeOfDay>,
+    /// A greyscale texture, white for high, which gives the cubes and floor depth through `parallax`
+    pub height_map: Option<TextureSource>,
+    /// How deep `height_map` looks and how finely it's traced, which does nothing without one
+    pub parallax: Parallax,
+    /// Ripple the floor by a greyscale image, which divides it up finely enough to show it
+    pub displacement: Option<DisplacementConfig>,
+    /// Texture the scene by where it is, repeating this often in world units, see `triplanar_defs`
+    pub triplanar: Option<f32>,
+    /// Scatter grass over the floor or terrain
+    pub vegetation: Option<VegetationConfig>,
+    /// Simulate the cubes as rigid bodies, which needs the `physics` feature
+    pub physics: bool,
+    /// A rhai script to drive the scene with, which needs the `scripting` feature
+    pub script: Option<PathBuf>,
+}
+
+impl Config {
+    /// Parse the process' command line arguments
+    pub fn from_args() -> Result<Self> {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    /// Parse `args`, which shouldn't include the program name:
+    ///
+    /// `--trace <dir>` records a wgpu API trace into `dir`,
+    /// `--benchmark <frames>` times `frames` frames and exits,
+    /// `--metrics <path>` writes every frame's times to `path`, see `MetricsFormat::from_path`,
+    /// `--target-fps <fps>` lowers the resolution when the GPU can't keep up with `fps`, and raises it again when it can,
+    /// `--deterministic` renders every frame 1/60th of a second after the last,
+    /// `--record <path>` writes recordings to `path`, see `RecordOutput::from_path`,
+    /// `--backend <list>` picks from a comma separated list of backends, e.g. `vulkan,gl`,
+    /// `--fullscreen <mode>` starts exclusive fullscreen in a mode like `1920x1080@144`, or `auto`,
+    /// `--list-video-modes` prints the modes `--fullscreen` can pick from and exits,
+    /// `--remote <address>` takes commands over TCP on e.g. `127.0.0.1:7878`, see `remote`,
+    /// `--present-mode <mode>` is one of `fifo`, `mailbox`, `immediate`, `auto-vsync` or `auto-no-vsync`,
+    /// `--encoding-threads <count>` records the frame's passes on up to `count` threads, or `auto` for one per core,
+    /// `--settings <path>` reads the render settings from `path` rather than `settings.toml`, see `settings`,
+    /// `--title <title>` and `--icon <image>` set the window's title and icon,
+    /// `--min-size <size>` and `--max-size <size>` limit the window's size, e.g. `640x480`,
+    /// `--fixed-size` stops the window from being resized,
+    /// `--transparent` shows the desktop through the background,
+    /// `--no-decorations` leaves out the title bar and borders,
+    /// `--always-on-top` keeps the window above every other,
+    /// `--monitor <monitor>` opens on the monitor with this index or name,
+    /// `--position <position>` opens at e.g. `100,50` from the monitor's top left, or its `center`,
+    /// `--letterbox <aspect>` keeps the picture at an aspect ratio like `16:9` or `2.39`, with bars around it,
+    /// `--texture <texture>` puts `texture` on the cubes and floor instead of the planks,
+    /// which like the other textures is the path to an image or a procedural texture,
+    /// one of `checker[:squares]`, `noise[:cells[,octaves[,seed]]]` or `gradient`,
+    /// `--texture-filter <filter>` filters it on the GPU with `blur[:radius]`, `sharpen[:amount]` or `grayscale`,
+    /// and can be given more than once to filter it again,
+    /// `--cube-texture <texture>` adds a texture for the cubes to take turns with, and can be given more than once,
+    /// `--terrain <heightmap>` replaces the floor with terrain built from a greyscale image,
+    /// `--terrain-size <units>` and `--terrain-height <units>` set how wide and tall it is,
+    /// `--terrain-texture <image>` tiles `image` over the terrain instead of the planks,
+    /// `--terrain-splat <image>` blends between the layers listed by `--terrain-layers <list>`,
+    /// a comma separated list of up to 4 images, by the splat map's channels,
+    /// `--terrain-triplanar` projects its textures along each axis so that they don't stretch down slopes,
+    /// `--water <level>` adds a water surface at the height `level`,
+    /// `--mirror` stands a mirror behind the cubes,
+    /// `--glass` makes the front row of cubes glass,
+    /// `--ior <ior>` sets how much it bends the light through it, e.g. `1.33` for water,
+    /// `--outline <instances>` outlines the instances at these comma separated indices, e.g. `1,5`,
+    /// `--point-cloud <file>` shows the points in a `.ply` or `.xyz` file,
+    /// `--point-size <units>` sets how wide each point is,
+    /// `--subdivide <subdivision[:levels]>` splits the cubes' triangles, `midpoint` or smoothed by `loop`, once or `levels` times,
+    /// `--edges` draws lines along the edges of the cubes,
+    /// `--normals <models>` shows the normals of the models at these comma separated indices, e.g. `0,1`,
+    /// `--debug-view <view>` is one of `off`, `normals`, `uv`, `depth`, `overdraw` or `mip`,
+    /// `--stereo` draws the scene for each eye side by side,
+    /// `--anaglyph` draws it for each eye in red and cyan instead,
+    /// `--eye-separation <units>` and `--convergence <units>` set how far apart the eyes are and where their views meet,
+    /// `--exposure <exposure>` tonemaps the scene, exposed by a number of stops or `auto` to adapt to it,
+    /// `--fog <mode>` is one of `off`, `linear:START,END`, `exp:DENSITY` or `exp2:DENSITY`,
+    /// `--sky` draws a sky in place of the flat background,
+    /// `--sun <elevation,azimuth>` draws it with the sun at these angles in degrees,
+    /// `--time-of-day <hour>` moves the sun through the day starting at `hour`, e.g. `17.5`,
+    /// `--day-length <seconds>` sets how long a day takes,
+    /// `--height-map <texture>` gives the cubes and floor depth with parallax occlusion mapping,
+    /// `--parallax <scale>` sets how deep it looks, as a fraction of the texture's width,
+    /// `--parallax-steps <min,max>` sets how many steps it's traced in, looking straight on and at grazing angles,
+    /// `--displacement <texture>` raises the floor where `texture` is brighter,
+    /// `--displacement-amplitude <units>` sets how far it rises where it's white,
+    /// `--displacement-scroll <u,v>` slides the image across the floor by this many widths per second,
+    /// `--triplanar <units>` textures the scene by position rather than texture co-ordinates, repeating every `units`,
+    /// `--vegetation <count>` scatters `count` tufts of grass over the ground,
+    /// `--vegetation-extent <units>` scatters them up to `units` from the origin along x and z,
+    /// `--vegetation-seed <seed>` scatters them differently for each seed,
+    /// `--wind <x,z,strength>` blows them over towards `x,z`, bending their tips by `strength` times their height,
+    /// `--physics` makes the cubes rigid bodies, with C dropping another onto them,
+    /// `--script <path>` runs a rhai script, see `script`
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut config = Self::default();
+        let mut parallax_set = false;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--trace" => {
+                    let dir = args
+                        .next()
+                        .context("--trace needs a directory to record into")?;
+                    config.api_trace = Some(dir.into());
+                }
+                "--target-fps" => {
+                    let frame_rate = args
+                        .next()
+                        .context("--target-fps needs a frame rate, e.g. `60`")?;
+                    let frame_rate = frame_rate
+                        .parse()
+                        .with_context(|| format!("invalid frame rate `{frame_rate}`"))?;
+                    ensure!(frame_rate > 0.0, "--target-fps has to be positive");
+                    config.target_fps = Some(frame_rate);
+                }
+                "--benchmark" => {
+                    let frames = args
+                        .next()
+                        .context("--benchmark needs a number of frames to render")?;
+                    let frames = frames
+                        .parse()
+                        .with_context(|| format!("invalid number of frames `{frames}`"))?;
+                    ensure!(frames > 0, "--benchmark needs at least one frame");
+                    config.benchmark = Some(frames);
+                }
+                "--deterministic" => config.deterministic = true,
+                "--metrics" => {
+                    let path = args
+                        .next()
+                        .context("--metrics needs a CSV or JSON file to write to")?;
+                    config.metrics = Some(path.into());
+                }
+                "--record" => {
+                    let path = args
+                        .next()
+                        .context("--record needs a directory or video file to record to")?;
+                    config.record = RecordOutput::from_path(path);
+                }
+                "--backend" => {
+                    let list = args
+                        .next()
+                        .context("--backend needs a list of backends, e.g. `vulkan,gl`")?;
+                    let backends = wgpu::util::parse_backends_from_comma_list(&list);
+                    ensure!(!backends.is_empty(), "no known backends in `{list}`");
+                    config.backends = Some(backends);
+                }
+                "--fullscreen" => {
+                    let mode = args.next().context(
+                        "--fullscreen needs a video mode, e.g. `1920x1080@144` or `auto`",
+                    )?;
+                    config.fullscreen = Some(
+                        mode.parse()
+                            .with_context(|| format!("invalid video mode `{mode}`"))?,
+                    );
+                }
+                "--list-video-modes" => config.list_video_modes = true,
+                "--remote" => {
+                    let address = args
+                        .next()
+                        .context("--remote needs an address, e.g. `127.0.0.1:7878`")?;
+                    config.remote = Some(
+                        address
+                            .parse()
+                            .with_context(|| format!("invalid address `{address}`"))?,
+                    );
+                }
+                "--present-mode" => {
+                    let mode = args.next().context("--present-mode needs a mode")?;
+                    config.present_mode = Some(parse_present_mode(&mode)?);
+                }
+                "--encoding-threads" => {
+                    let count = args
+                        .next()
+                        .context("--encoding-threads needs a number of threads or `auto`")?;
+                    let count = match count.as_str() {
+                        "auto" => JobSystem::available().threads(),
+                        _ => count
+                            .parse()
+                            .with_context(|| format!("invalid number of threads `{count}`"))?,
+                    };
+                    ensure!(count > 0, "--encoding-threads needs at least one thread");
+                    config.encoding_threads = Some(count);
+                }
+                "--settings" => {
+                    let path = args.next().context("--settings needs a TOML file")?;
+                    config.settings = Some(path.into());
+                }
+                "--title" => {
+                    config.window.title = args.next().context("--title needs a title")?;
+                }
+                "--icon" => {
+                    let path = args.next().context("--icon needs an image")?;
+                    config.window.icon = Some(path.into());
+                }
+                "--min-size" => {
+                    let size = args
+                        .next()
+                        .context("--min-size needs a size, e.g. `640x480`")?;
+                    config.window.min_size = Some(window::parse_size(&size)?);
+                }
+                "--max-size" => {
+                    let size = args
+                        .next()
+                        .context("--max-size needs a size, e.g. `1920x1080`")?;
+                    config.window.max_size = Some(window::parse_size(&size)?);
+                }
+                "--fixed-size" => config.window.resizable = false,
+                "--transparent" => config.window.transparent = true,
+                "--no-decorations" => config.window.decorations = false,
+                "--always-on-top" => config.window.always_on_top = true,
+                "--monitor" => {
+                    let monitor = args
+                        .next()
+                        .context("--monitor needs a monitor's index or name")?;
+                    config.window.monitor = Some(monitor.parse()?);
+                }
+                "--position" => {
+                    let position = args
+                        .next()
+                        .context("--position needs a position, e.g. `100,50` or `center`")?;
+                    config.window.position = Some(position.parse()?);
+                }
+                "--letterbox" => {
+                    let aspect = args
+                        .next()
+                        .context("--letterbox needs an aspect ratio, e.g. `16:9` or `2.39`")?;
+                    config.letterbox = Some(aspect.parse()?);
+                }
+                "--texture" => {
+                    let source = args
+                        .next()
+                        .context("--texture needs an image or procedural texture")?;
+                    config.texture = Some(source.parse()?);
+                }
+                "--texture-filter" => {
+                    let filter = args
+                        .next()
+                        .context("--texture-filter needs a filter, e.g. `blur:4` or `grayscale`")?;
+                    config.texture_filters.push(filter.parse()?);
+                }
+                "--cube-texture" => {
+                    let source = args
+                        .next()
+                        .context("--cube-texture needs an image or procedural texture")?;
+                    config.cube_textures.push(source.parse()?);
+                }
+                "--terrain" => {
+                    let path = args.next().context("--terrain needs a heightmap")?;
+                    config
+                        .terrain
+                        .get_or_insert_with(Default::default)
+                        .heightmap = path.into();
+                }
+                "--terrain-size" | "--terrain-height" => {
+                    let units = args
+                        .next()
+                        .with_context(|| format!("{arg} needs a distance in world units"))?;
+                    let units = units
+                        .parse::<f32>()
+                        .with_context(|| format!("invalid distance `{units}`"))?;
+                    ensure!(units > 0.0, "{arg} needs a positive distance");
+                    let terrain = config.terrain.get_or_insert_with(Default::default);
+                    if arg == "--terrain-size" {
+                        terrain.size = units;
+                    } else {
+                        terrain.height = units;
+                    }
+                }
+                "--terrain-texture" => {
+                    let path = args.next().context("--terrain-texture needs an image")?;
+                    config.terrain.get_or_insert_with(Default::default).texture = Some(path.into());
+                }
+                "--terrain-splat" => {
+                    let path = args.next().context("--terrain-splat needs an image")?;
+                    config
+                        .terrain
+                        .get_or_insert_with(Default::default)
+                        .splat_map = Some(path.into());
+                }
+                "--terrain-layers" => {
+                    let list = args
+                        .next()
+                        .context("--terrain-layers needs a comma separated list of images")?;
+                    let layers = list.split(',').map(PathBuf::from).collect::<Vec<_>>();
+                    ensure!(
+                        layers.len() <= 4,
+                        "--terrain-layers takes at most 4 images, not {}",
+                        layers.len()
+                    );
+                    config.terrain.get_or_insert_with(Default::default).layers = layers;
+                }
+                "--terrain-triplanar" => {
+                    config
+                        .terrain
+                        .get_or_insert_with(Default::default)
+                        .triplanar = true;
+                }
+                "--water" => {
+                    let level = args.next().context("--water needs a height")?;
+                    config.water = Some(WaterSettings {
+                        level: level
+                            .parse()
+                            .with_context(|| format!("invalid height `{level}`"))?,
+                        ..Default::default()
+                    });
+                }
+                "--stereo" => {
+                    config.stereo.get_or_insert_with(Default::default);
+                }
+                "--anaglyph" => {
+                    config.stereo.get_or_insert_with(Default::default).output =
+                        StereoOutput::Anaglyph;
+                }
+                "--eye-separation" => {
+                    let separation = args
+                        .next()
+                        .context("--eye-separation needs a distance, e.g. `0.064`")?;
+                    config
+                        .stereo
+                        .get_or_insert_with(Default::default)
+                        .eye_separation = separation
+                        .parse()
+                        .with_context(|| format!("invalid eye separation `{separation}`"))?;
+                }
+                "--convergence" => {
+                    let distance = args
+                        .next()
+                        .context("--convergence needs a distance, e.g. `5`")?;
+                    let distance: f32 = distance
+                        .parse()
+                        .with_context(|| format!("invalid convergence distance `{distance}`"))?;
+                    ensure!(distance > 0.0, "--convergence has to be positive");
+                    config
+                        .stereo
+                        .get_or_insert_with(Default::default)
+                        .convergence = distance;
+                }
+                "--mirror" => {
+                    config.mirrors.push(MirrorSettings::default());
+                }
+                "--glass" => {
+                    config.glass.get_or_insert_with(Default::default);
+                }
+                "--outline" => {
+                    let instances = args
+                        .next()
+                        .context("--outline needs a list of instances, e.g. `1,5`")?;
+                    for instance in instances.split(',') {
+                        config.outline.push(
+                            instance
+                                .trim()
+                                .parse()
+                                .with_context(|| format!("invalid instance `{instance}`"))?,
+                        );
+                    }
+                }
+                "--point-cloud" => {
+                    let path = args
+                        .next()
+                        .context("--point-cloud needs a .ply or .xyz file")?;
+                    config.point_cloud = Some(path.into());
+                }
+                "--point-size" => {
+                    let units = args
+                        .next()
+                        .context("--point-size needs a width in world units")?;
+                    let units = units
+                        .parse::<f32>()
+                        .with_context(|| format!("invalid width `{units}`"))?;
+                    ensure!(units > 0.0, "--point-size needs a positive width");
+                    config.point_size = Some(units);
+                }
+                "--subdivide" => {
+                    let subdivide = args
+                        .next()
+                        .context("--subdivide needs `midpoint` or `loop`, e.g. `loop:2`")?;
+                    let (subdivision, levels) =
+                        subdivide.split_once(':').unwrap_or((&subdivide, "1"));
+                    let levels = levels
+                        .parse()
+                        .with_context(|| format!("invalid number of levels `{levels}`"))?;
+                    config.subdivide = Some((subdivision.parse()?, levels));
+                }
+                "--edges" => config.edges = true,
+                "--normals" => {
+                    let models = args
+                        .next()
+                        .context("--normals needs a list of models, e.g. `0,1`")?;
+                    for model in models.split(',') {
+                        config.normals.push(
+                            model
+                                .trim()
+                                .parse()
+                                .with_context(|| format!("invalid model `{model}`"))?,
+                        );
+                    }
+                }
+                "--debug-view" => {
+                    let view = args.next().context(
+                        "--debug-view needs one of `off`, `normals`, `uv`, `depth`, `overdraw` or `mip`",
+                    )?;
+                    config.debug_view = view.parse()?;
+                }
+                "--ior" => {
+                    let ior = args
+                        .next()
+                        .context("--ior needs an index of refraction, e.g. `1.5`")?;
+                    let ior: f32 = ior
+                        .parse()
+                        .with_context(|| format!("invalid index of refraction `{ior}`"))?;
+                    ensure!(ior >= 1.0, "--ior can't be less than 1");
+                    config.glass.get_or_insert_with(Default::default).ior = ior;
+                }
+                "--exposure" => {
+                    let exposure = args
+                        .next()
+                        .context("--exposure needs a number of stops or `auto`")?;
+                    config.exposure = Some(exposure.parse()?);
+                }
+                "--fog" => {
+                    let mode = args
+                        .next()
+                        .context("--fog needs a mode, e.g. `linear:10,40` or `exp:0.05`")?;
+                    config.fog = mode.parse()?;
+                }
+                "--sky" => {
+                    config.sky.get_or_insert_with(Default::default);
+                }
+                "--sun" => {
+                    let angles = args
+                        .next()
+                        .context("--sun needs an elevation and azimuth, e.g. `30,135`")?;
+                    let (elevation, azimuth) = angles
+                        .split_once(',')
+                        .context("--sun needs an elevation and azimuth, e.g. `30,135`")?;
+                    let angle = |s: &str| {
+                        s.trim()
+                            .parse::<f32>()
+                            .with_context(|| format!("invalid angle `{s}`"))
+                    };
+                    let sun = config.sky.get_or_insert_with(Default::default);
+                    sun.elevation = angle(elevation)?;
+                    sun.azimuth = angle(azimuth)?;
+                }
+                "--time-of-day" => {
+                    let hour = args.next().context("--time-of-day needs an hour")?;
+                    let hour = hour
+                        .parse::<f32>()
+                        .with_context(|| format!("invalid hour `{hour}`"))?;
+                    config
+                        .time_of_day
+                        .get_or_insert_with(Default::default)
+                        .set_hour(hour);
+                }
+                "--day-length" => {
+                    let seconds = args
+                        .next()
+                        .context("--day-length needs a number of seconds")?;
+                    let cycle_length = seconds
+                        .parse::<f32>()
+                        .with_context(|| format!("invalid number of seconds `{seconds}`"))?;
+                    ensure!(cycle_length > 0.0, "a day has to take some time");
+                    config
+                        .time_of_day
+                        .get_or_insert_with(Default::default)
+                        .cycle_length = cycle_length;
+                }
+                "--height-map" => {
+                    let source = args
+                        .next()
+                        .context("--height-map needs an image or procedural texture")?;
+                    config.height_map = Some(source.parse()?);
+                }
+                "--parallax" => {
+                    let scale = args.next().context("--parallax needs a scale")?;
+                    let scale = scale
+                        .parse::<f32>()
+                        .with_context(|| format!("invalid scale `{scale}`"))?;
+                    ensure!(scale >= 0.0, "--parallax can't be negative");
+                    config.parallax.scale = scale;
+                    parallax_set = true;
+                }
+                "--parallax-steps" => {
+                    let steps = args
+                        .next()
+                        .context("--parallax-steps needs a minimum and maximum, e.g. `8,32`")?;
+                    let (min, max) = steps
+                        .split_once(',')
+                        .context("--parallax-steps needs a minimum and maximum, e.g. `8,32`")?;
+                    let count = |s: &str| {
+                        s.trim()
+                            .parse::<u32>()
+                            .with_context(|| format!("invalid number of steps `{s}`"))
+                    };
+                    let (min, max) = (count(min)?, count(max)?);
+                    ensure!(
+                        0 < min && min <= max,
+                        "--parallax-steps needs a minimum of at least 1 and no more than the maximum"
+                    );
+                    config.parallax.min_steps = min;
+                    config.parallax.max_steps = max;
+                    parallax_set = true;
+                }
+                "--triplanar" => {
+                    let units = args
+                        .next()
+                        .context("--triplanar needs a distance in world units")?;
+                    let units = units
+                        .parse::<f32>()
+                        .with_context(|| format!("invalid distance `{units}`"))?;
+                    ensure!(units > 0.0, "--triplanar needs a positive distance");
+                    config.triplanar = Some(units);
+                }
+                "--displacement" => {
+                    let source = args
+                        .next()
+                        .context("--displacement needs an image or procedural texture")?;
+                    config.displacement.get_or_insert_with(Default::default).map =
+                        Some(source.parse()?);
+                }
+                "--displacement-amplitude" => {
+                    let units = args
+                        .next()
+                        .context("--displacement-amplitude needs a distance in world units")?;
+                    config
+                        .displacement
+                        .get_or_insert_with(Default::default)
+                        .floor_amplitude = units
+                        .parse()
+                        .with_context(|| format!("invalid distance `{units}`"))?;
+                }
+                "--displacement-scroll" => {
+                    let speeds = args.next().context(
+                        "--displacement-scroll needs a speed along u and v, e.g. `0.1,0`",
+                    )?;
+                    let (u, v) = speeds.split_once(',').context(
+                        "--displacement-scroll needs a speed along u and v, e.g. `0.1,0`",
+                    )?;
+                    let speed = |s: &str| {
+                        s.trim()
+                            .parse::<f32>()
+                            .with_context(|| format!("invalid speed `{s}`"))
+                    };
+                    config
+                        .displacement
+                        .get_or_insert_with(Default::default)
+                        .displacement
+                        .scroll = [speed(u)?, speed(v)?];
+                }
+                "--physics" => config.physics = true,
+                "--script" => {
+                    let path = args.next().context("--script needs a script")?;
+                    config.script = Some(path.into());
+                }
+                "--vegetation" => {
+                    let count = args
+                        .next()
+                        .context("--vegetation needs a number of tufts")?;
+                    config.vegetation.get_or_insert_with(Default::default).count = count
+                        .parse()
+                        .with_context(|| format!("invalid number of tufts `{count}`"))?;
+                }
+                "--vegetation-extent" => {
+                    let units = args
+                        .next()
+                        .context("--vegetation-extent needs a distance in world units")?;
+                    let units = units
+                        .parse::<f32>()
+                        .with_context(|| format!("invalid distance `{units}`"))?;
+                    ensure!(units > 0.0, "--vegetation-extent needs a positive distance");
+                    config
+                        .vegetation
+                        .get_or_insert_with(Default::default)
+                        .extent = units;
+                }
+                "--vegetation-seed" => {
+                    let seed = args.next().context("--vegetation-seed needs a number")?;
+                    config.vegetation.get_or_insert_with(Default::default).seed = seed
+                        .parse()
+                        .with_context(|| format!("invalid seed `{seed}`"))?;
+                }
+                "--wind" => {
+                    let wind = args
+                        .next()
+                        .context("--wind needs a direction and strength, e.g. `1,0.4,0.3`")?;
+                    let values = wind
+                        .split(',')
+                        .map(|s| {
+                            s.trim()
+                                .parse::<f32>()
+                                .with_context(|| format!("invalid number `{s}`"))
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    let [x, z, strength] = values[..] else {
+                        bail!("--wind needs a direction and strength, e.g. `1,0.4,0.3`");
+                    };
+                    let vegetation = config.vegetation.get_or_insert_with(Default::default);
+                    vegetation.wind.direction = [x, z];
+                    vegetation.wind.strength = strength;
+                }
+                _ => bail!("unknown argument `{arg}`"),
+            }
+        }
+        if config.time_of_day.is_some() {
+            config.sky.get_or_insert_with(Default::default);
+        }
+        if let Some(terrain) = &config.terrain {
+            ensure!(
+                !terrain.heightmap.as_os_str().is_empty(),
+                "the terrain options need a heightmap from --terrain"
+            );
+        }
+        if let Some(displacement) = &config.displacement {
+            ensure!(
+                displacement.map.is_some(),
+                "the displacement options need a texture from --displacement"
+            );
+        }
+        ensure!(
+            !parallax_set || config.height_map.is_some(),
+            "the parallax options need a height map from --height-map"
+        );
+        Ok(config)
+    }
+
+    /// The backends to use, from `backends` or the `WGPU_BACKEND` environment variable
+    pub fn backends(&self) -> Backends {
+        self.backends
+            .or_else(|| {
+                wgpu::util::backend_bits_from_env().filter(|backends| {
+                    let known = !backends.is_empty();
+                    if !known {
+                        tracing::warn!("WGPU_BACKEND has no known backends, using them all");
+                    }
+                    known
+                })
+            })
+            .unwrap_or(Backends::all())
+    }
+}
+
+/// One of `fifo`, `mailbox`, `immediate`, `auto-vsync` or `auto-no-vsync`
+pub fn parse_present_mode(mode: &str) -> Result<PresentMode> {
+    Ok(match mode {
+        "fifo" => PresentMode::Fifo,
+        "mailbox" => PresentMode::Mailbox,
+        "immediate" => PresentMode::Immediate,
+        "auto-vsync" => PresentMode::AutoVsync,
+        "auto-no-vsync" => PresentMode::AutoNoVsync,
+        _ => bail!("unknown present mode `{mode}`"),
+    })
+}
@@ -0,0 +1,285 @@
+use std::f32::consts::TAU;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupLayout, BlendState, Buffer, BufferAddress,
+    BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState,
+    DepthStencilState, Device, FragmentState, PipelineLayoutDescriptor, PrimitiveState,
+    PrimitiveTopology, Queue, RenderPass, RenderPipelineDescriptor, ShaderModuleDescriptor,
+    ShaderSource, StencilState, TextureFormat, VertexAttribute, VertexBufferLayout, VertexFormat,
+    VertexState, VertexStepMode,
+};
+
+use crate::{
+    math::{InnerSpace, Point3, Vector3},
+    pipeline::ScenePipeline,
+    reflection::ShaderReflection,
+    shader::{preprocess, ShaderDefs, ShaderLibrary},
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct DebugVertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+impl DebugVertex {
+    const ATTRIBUTES: [VertexAttribute; 2] = [
+        VertexAttribute {
+            offset: 0,
+            shader_location: 0,
+            format: VertexFormat::Float32x3,
+        },
+        VertexAttribute {
+            offset: std::mem::size_of::<[f32; 3]>() as BufferAddress,
+            shader_location: 1,
+            format: VertexFormat::Float32x3,
+        },
+    ];
+
+    fn desc<'a>() -> VertexBufferLayout<'a> {
+        VertexBufferLayout {
+            array_stride: std::mem::size_of::<DebugVertex>() as BufferAddress,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// Immediate-mode line drawing for visualising things which aren't otherwise visible, e.g. lights.
+/// Shapes are added every frame between `clear` and `prepare`, then drawn over the scene,
+/// hidden behind anything in front of them
+pub struct DebugDraw {
+    vertices: Vec<DebugVertex>,
+    /// Grows as needed to fit `vertices`
+    buffer: Buffer,
+    pipeline: ScenePipeline,
+    /// The shader only uses the camera at group 1, but group 0 still has to be bound to something
+    empty_bind_group: BindGroup,
+    /// The number of vertices uploaded by the last `prepare`
+    uploaded: u32,
+}
+
+impl DebugDraw {
+    /// The number of line segments used to approximate each circle
+    const CIRCLE_SEGMENTS: u32 = 32;
+
+    /// `camera_layout` is the layout of the camera bind group passed to `draw`,
+    /// and `formats` and `depth_format` must match the render pass the lines are drawn in
+    pub fn new(
+        device: &Device,
+        library: &ShaderLibrary,
+        camera_layout: &BindGroupLayout,
+        formats: &[TextureFormat],
+        depth_format: TextureFormat,
+    ) -> Result<Self> {
+        let name = "debug_draw.wgsl";
+        let source = preprocess(&library.resolve(name)?, &ShaderDefs::new())?;
+        let reflection = ShaderReflection::from_code(&source.clone().into(), &ShaderDefs::new())
+            .with_context(|| format!("failed to reflect {name}"))?;
+        let empty_layout = reflection.create_bind_group_layout(device, 0, Some(name));
+        let empty_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &empty_layout,
+            entries: &[],
+            label: Some("debug_draw_empty_bind_group"),
+        });
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(name),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some(name),
+            // use the scene's camera layout rather than reflecting it,
+            // as the scene's shader may use the camera from more stages than this one does
+            bind_group_layouts: &[&empty_layout, camera_layout],
+            push_constant_ranges: &[],
+        });
+        let targets = formats
+            .iter()
+            .map(|&format| {
+                Some(ColorTargetState {
+                    format,
+                    blend: Some(BlendState::REPLACE),
+                    write_mask: ColorWrites::ALL,
+                })
+            })
+            .collect::<Vec<_>>();
+        let pipeline = ScenePipeline::new(device, move |device, multisample| {
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(name),
+                layout: Some(&layout),
+                vertex: VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[DebugVertex::desc()],
+                },
+                fragment: Some(FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &targets,
+                }),
+                primitive: PrimitiveState {
+                    // every 2 vertices make up a separate line
+                    topology: PrimitiveTopology::LineList,
+                    ..Default::default()
+                },
+                // hidden behind the scene, but don't hide the scene or each other
+                depth_stencil: Some(DepthStencilState {
+                    format: depth_format,
+                    depth_write_enabled: false,
+                    depth_compare: CompareFunction::LessEqual,
+                    stencil: StencilState::default(),
+                    bias: DepthBiasState::default(),
+                }),
+                multisample,
+                multiview: None,
+            })
+        });
+
+        Ok(Self {
+            vertices: Vec::new(),
+            buffer: create_buffer(device, 1024),
+            pipeline,
+            empty_bind_group,
+            uploaded: 0,
+        })
+    }
+
+    /// Forget every shape added since the last `clear`
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn line(&mut self, start: Point3<f32>, end: Point3<f32>, color: [f32; 3]) {
+        self.vertices.extend([
+            DebugVertex {
+                position: start.into(),
+                color,
+            },
+            DebugVertex {
+                position: end.into(),
+                color,
+            },
+        ]);
+    }
+
+    /// A circle around `center`, in the plane facing `normal`
+    pub fn circle(
+        &mut self,
+        center: Point3<f32>,
+        normal: Vector3<f32>,
+        radius: f32,
+        color: [f32; 3],
+    ) {
+        let (tangent, bitangent) = basis(normal);
+        let point = |index: u32| {
+            let angle = index as f32 / Self::CIRCLE_SEGMENTS as f32 * TAU;
+            center + (tangent * angle.cos() + bitangent * angle.sin()) * radius
+        };
+        for index in 0..Self::CIRCLE_SEGMENTS {
+            self.line(point(index), point(index + 1), color);
+        }
+    }
+
+    /// A wireframe sphere, drawn as a circle around each axis
+    pub fn sphere(&mut self, center: Point3<f32>, radius: f32, color: [f32; 3]) {
+        self.circle(center, Vector3::unit_x(), radius, color);
+        self.circle(center, Vector3::unit_y(), radius, color);
+        self.circle(center, Vector3::unit_z(), radius, color);
+    }
+
+    /// A small star of lines along each axis, for marking a point
+    pub fn cross(&mut self, center: Point3<f32>, size: f32, color: [f32; 3]) {
+        for axis in [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()] {
+            self.line(center - axis * size, center + axis * size, color);
+        }
+    }
+
+    /// A wireframe cone from `apex`, pointing along `direction` for `length`,
+    /// with the given angle in radians between its axis and its sides
+    pub fn cone(
+        &mut self,
+        apex: Point3<f32>,
+        direction: Vector3<f32>,
+        angle: f32,
+        length: f32,
+        color: [f32; 3],
+    ) {
+        let direction = direction.normalize();
+        let radius = length * angle.tan();
+        let base = apex + direction * length;
+        self.circle(base, direction, radius, color);
+        let (tangent, bitangent) = basis(direction);
+        for side in [tangent, -tangent, bitangent, -bitangent] {
+            self.line(apex, base + side * radius, color);
+        }
+    }
+
+    /// A line from `start` to `end` with an arrowhead at `end`
+    pub fn arrow(&mut self, start: Point3<f32>, end: Point3<f32>, color: [f32; 3]) {
+        self.line(start, end, color);
+        let length = (end - start).magnitude();
+        if length > 0.0 {
+            // the head is a cone pointing back towards `start`
+            let head = length * 0.2;
+            let (tangent, bitangent) = basis(end - start);
+            let back = end - (end - start) / length * head;
+            for side in [tangent, -tangent, bitangent, -bitangent] {
+                self.line(end, back + side * head * 0.5, color);
+            }
+        }
+    }
+
+    /// Draw into a scene pass whose targets have `sample_count` samples per pixel from now on
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        self.pipeline.set_sample_count(device, sample_count);
+    }
+
+    /// Upload everything added since the last `clear`
+    pub fn prepare(&mut self, device: &Device, queue: &Queue) {
+        let size = std::mem::size_of_val(self.vertices.as_slice()) as BufferAddress;
+        if size > self.buffer.size() {
+            self.buffer = create_buffer(device, size.next_power_of_two());
+        }
+        if size > 0 {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.vertices));
+        }
+        self.uploaded = self.vertices.len() as u32;
+    }
+
+    /// Draw the lines uploaded by the last `prepare`, with the scene's camera bind group
+    pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>, camera_bind_group: &'a BindGroup) {
+        if self.uploaded == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.empty_bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+        render_pass.draw(0..self.uploaded, 0..1);
+    }
+}
+
+fn create_buffer(device: &Device, size: BufferAddress) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("Debug Draw Vertices"),
+        size,
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// Two directions perpendicular to `normal` and each other
+fn basis(normal: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let normal = normal.normalize();
+    let helper = if normal.y.abs() > 0.99 {
+        Vector3::unit_x()
+    } else {
+        Vector3::unit_y()
+    };
+    let tangent = normal.cross(helper).normalize();
+    (tangent, normal.cross(tangent))
+}
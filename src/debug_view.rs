@@ -0,0 +1,101 @@
+use std::str::FromStr;
+
+use anyhow::*;
+
+use crate::{pipeline::ADDITIVE, shader::ShaderDefs};
+
+/// What the scene's meshes show in place of their shaded colour, for seeing what goes into it.
+/// Each is a permutation of `shader.wgsl`, so it works whatever the material, see `State::set_debug_view`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DebugView {
+    #[default]
+    Off,
+    /// World space normals, with each axis from -1 to 1 shown from dark to bright in red, green and blue
+    Normals,
+    /// Texture co-ordinates, u in red and v in green, repeating each time they pass 1
+    TexCoords,
+    /// Distance from the camera, from black at the camera to white at its far plane
+    Depth,
+    /// How many times each pixel is drawn, from dark red through yellow to white,
+    /// with every mesh drawn whether or not something's in front of it
+    Overdraw,
+    /// Which mip level of the diffuse texture is sampled, from blue for the largest through green to red for the fourth and smaller
+    MipLevel,
+}
+
+impl DebugView {
+    pub const ALL: [Self; 6] = [
+        Self::Off,
+        Self::Normals,
+        Self::TexCoords,
+        Self::Depth,
+        Self::Overdraw,
+        Self::MipLevel,
+    ];
+
+    /// The view after this one, going back to `Off` after the last
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&view| view == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    /// The flag `shader.wgsl` tests for this view
+    fn flag(self) -> Option<&'static str> {
+        match self {
+            Self::Off => None,
+            Self::Normals => Some("DEBUG_NORMALS"),
+            Self::TexCoords => Some("DEBUG_TEX_COORDS"),
+            Self::Depth => Some("DEBUG_DEPTH"),
+            Self::Overdraw => Some("DEBUG_OVERDRAW"),
+            Self::MipLevel => Some("DEBUG_MIP_LEVEL"),
+        }
+    }
+
+    /// The view `defs` select, `Off` if they don't select any
+    pub fn from_defs(defs: &ShaderDefs) -> Self {
+        Self::ALL
+            .into_iter()
+            .find(|view| view.flag().is_some_and(|flag| defs.is_defined(flag)))
+            .unwrap_or_default()
+    }
+
+    /// `defs` selecting this view instead of whichever they did, where `far` is the distance
+    /// `Depth` shows as white, usually the camera's far plane
+    pub fn apply(self, defs: &ShaderDefs, far: f32) -> ShaderDefs {
+        let mut defs = defs.clone();
+        for flag in Self::ALL.into_iter().filter_map(Self::flag) {
+            defs.set(flag, false);
+        }
+        defs.set("DEBUG_DEPTH_FAR", false);
+        defs.set(ADDITIVE, false);
+        match self {
+            Self::Off => defs,
+            // `{:?}` always writes a decimal point, which WGSL needs to read it as a float
+            Self::Depth => defs
+                .flag("DEBUG_DEPTH")
+                .value("DEBUG_DEPTH_FAR", format!("{far:?}")),
+            // counted by adding up every fragment
+            Self::Overdraw => defs.flag("DEBUG_OVERDRAW").flag(ADDITIVE),
+            view => defs.flag(view.flag().expect("only `Off` has no flag")),
+        }
+    }
+}
+
+/// Parses one of `off`, `normals`, `uv`, `depth`, `overdraw` or `mip`
+impl FromStr for DebugView {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "off" => Self::Off,
+            "normals" => Self::Normals,
+            "uv" => Self::TexCoords,
+            "depth" => Self::Depth,
+            "overdraw" => Self::Overdraw,
+            "mip" => Self::MipLevel,
+            _ => bail!(
+                "unknown debug view `{s}`, expected one of `off`, `normals`, `uv`, `depth`, `overdraw` or `mip`"
+            ),
+        })
+    }
+}
@@ -0,0 +1,21 @@
+// Debug views, which replace the colour of the scene's meshes with something about them, see `DebugView`.
+// At most one of the `DEBUG_*` flags is defined at a time
+
+// From blue at 0 through green to red at 1
+fn debug_ramp(t: f32) -> vec3<f32> {
+    let t = clamp(t, 0.0, 1.0);
+    return vec3<f32>(clamp(2.0 * t - 1.0, 0.0, 1.0), 1.0 - abs(2.0 * t - 1.0), clamp(1.0 - 2.0 * t, 0.0, 1.0));
+}
+
+// The mip level of `texture` which is sampled at texture co-ordinates which change by `uv_dx` and `uv_dy`
+// from one pixel to the next, which are `dpdx` and `dpdy` of them, as only fragment shaders can work those out
+// and this is compiled into vertex shaders too.
+// This doesn't know how many levels the texture has, which GL can't ask for, so it can go past the last
+fn debug_mip_level(texture: texture_2d<f32>, uv_dx: vec2<f32>, uv_dy: vec2<f32>) -> f32 {
+    let size = vec2<f32>(textureDimensions(texture));
+    let footprint = max(length(uv_dx * size), length(uv_dy * size));
+    return log2(max(footprint, 1.0));
+}
+
+// What each fragment adds to the pixel for `DEBUG_OVERDRAW`, which is red after 10, yellow after 20 and white after 40
+let DEBUG_OVERDRAW_STEP: vec3<f32> = vec3<f32>(0.1, 0.05, 0.025);
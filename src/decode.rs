@@ -0,0 +1,69 @@
+//! Decoding the images a scene is loaded with on rayon's thread pool, all at once before any of them
+//! are needed, so that startup waits for the slowest image rather than the sum of them.
+//! The textures are still created and uploaded on the thread building the scene, from the decoded images
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::*;
+use image::DynamicImage;
+use rayon::prelude::*;
+
+use crate::{config::Config, procedural::TextureSource};
+
+/// Images decoded ahead of time, by the path they were read from
+#[derive(Default)]
+pub struct DecodedImages {
+    images: HashMap<PathBuf, Result<DynamicImage>>,
+}
+
+impl DecodedImages {
+    /// Decode every image at `paths` in parallel, once each however many times it's named
+    #[tracing::instrument(skip_all)]
+    pub fn decode<'a>(paths: impl IntoIterator<Item = &'a Path>) -> Self {
+        let mut paths = paths.into_iter().collect::<Vec<_>>();
+        paths.sort();
+        paths.dedup();
+        let images = paths
+            .into_par_iter()
+            .map(|path| (path.to_owned(), image::open(path).map_err(Error::from)))
+            .collect();
+        Self { images }
+    }
+
+    /// The image at `path`, taken out of the ones decoded ahead of time,
+    /// or decoded now if it wasn't one of them or has already been taken
+    pub fn take(&mut self, path: &Path) -> Result<DynamicImage> {
+        match self.images.remove(path) {
+            Some(image) => image,
+            None => Ok(image::open(path)?),
+        }
+    }
+}
+
+/// Every image file `config` loads the scene with, which are the ones worth decoding ahead of time
+pub fn config_images(config: &Config) -> Vec<&Path> {
+    let mut paths = Vec::new();
+    let sources = [
+        config.texture.as_ref(),
+        config.height_map.as_ref(),
+        config
+            .displacement
+            .as_ref()
+            .and_then(|displacement| displacement.map.as_ref()),
+    ];
+    for source in sources.into_iter().flatten().chain(&config.cube_textures) {
+        if let TextureSource::Image(path) = source {
+            paths.push(path.as_path());
+        }
+    }
+    if let Some(terrain) = &config.terrain {
+        paths.push(&terrain.heightmap);
+        paths.extend(terrain.texture.as_deref());
+        paths.extend(terrain.splat_map.as_deref());
+        paths.extend(terrain.layers.iter().map(PathBuf::as_path));
+    }
+    paths
+}
@@ -0,0 +1,58 @@
+use bytemuck::{Pod, Zeroable};
+
+use crate::procedural::TextureSource;
+
+/// Moves vertices out along their normals by a greyscale texture, black staying put and white moving
+/// by the instance's `displacement`, e.g. for rippling water or bumpy ground.
+/// Only vertices move, so the mesh needs enough of them to show the texture's detail,
+/// and it's lit and casts shadows as though it were still flat
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Displacement {
+    /// How fast the texture slides across the surface, in texture widths per second, for waves
+    pub scroll: [f32; 2],
+}
+
+/// Where the displacement texture comes from and how the floor uses it
+#[derive(Clone, Debug)]
+pub struct DisplacementConfig {
+    /// A greyscale texture stretched over the floor, which the other options need
+    pub map: Option<TextureSource>,
+    /// How far the floor rises where `map` is white, in world units
+    pub floor_amplitude: f32,
+    pub displacement: Displacement,
+}
+
+impl Default for DisplacementConfig {
+    fn default() -> Self {
+        Self {
+            map: None,
+            floor_amplitude: 0.5,
+            displacement: Displacement::default(),
+        }
+    }
+}
+
+/// The layout of `Displacement` expected by `displacement.wgsl`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+pub struct DisplacementUniform {
+    scroll: [f32; 2],
+    /// Seconds since the app started
+    time: f32,
+    _padding: f32,
+}
+
+impl DisplacementUniform {
+    pub fn update(&mut self, displacement: &Displacement, time: f32) {
+        self.scroll = displacement.scroll;
+        self.time = time;
+    }
+}
+
+impl From<&Displacement> for DisplacementUniform {
+    fn from(displacement: &Displacement) -> Self {
+        let mut uniform = Self::default();
+        uniform.update(displacement, 0.0);
+        uniform
+    }
+}
@@ -0,0 +1,21 @@
+// Vertex displacement, moving vertices out along their normals by a greyscale texture
+
+struct Displacement {
+    // how fast the texture slides across the surface, in texture widths per second
+    scroll: vec2<f32>,
+    time: f32,
+}
+@group(0) @binding(5)
+var t_displacement: texture_2d<f32>;
+@group(0) @binding(6)
+var<uniform> displacement: Displacement;
+// Wraps around, so that the texture can keep sliding
+@group(0) @binding(7)
+var s_displacement: sampler;
+
+// Where `position` ends up, moved along `normal` by up to `amplitude` where the texture is white
+fn displace(position: vec3<f32>, normal: vec3<f32>, uv: vec2<f32>, amplitude: f32) -> vec3<f32> {
+    // vertex shaders have no neighbouring pixels to pick a mip level from, so always use the largest
+    let height = textureSampleLevel(t_displacement, s_displacement, uv + displacement.scroll * displacement.time, 0.0).r;
+    return position + normal * height * amplitude;
+}
@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+/// Adjusts the render scale once a second to keep the GPU's frame time within a budget, see
+/// `State::set_dynamic_resolution`. This needs `Capabilities::timestamp_query` to time the GPU
+#[derive(Clone, Debug)]
+pub struct DynamicResolution {
+    /// How long the GPU should take over each frame, in milliseconds
+    pub budget_ms: f64,
+    /// The lowest the render scale goes
+    pub min_scale: f32,
+    /// The highest the render scale goes, which is above 1 to supersample when there's time to spare
+    pub max_scale: f32,
+    /// The GPU times of the frames since the last adjustment
+    samples: Vec<f64>,
+    last_adjusted: Instant,
+}
+
+impl DynamicResolution {
+    /// How often the render scale is adjusted
+    const INTERVAL: Duration = Duration::from_secs(1);
+    /// The scale only goes up again once frames are this far under budget, so that it doesn't keep
+    /// going up and down when they're only just in it
+    const HEADROOM: f64 = 0.8;
+    /// The furthest the scale moves at once, as the GPU time may have only spiked
+    const MAX_STEP: f32 = 0.25;
+    /// Scales are rounded to this, so that the targets aren't recreated for tiny changes
+    const GRANULARITY: f32 = 0.05;
+
+    /// Keep the GPU's time under `budget_ms`, between render scales of 0.5 and 1
+    pub fn new(budget_ms: f64) -> Self {
+        Self {
+            budget_ms,
+            min_scale: 0.5,
+            max_scale: 1.0,
+            samples: Vec::new(),
+            last_adjusted: Instant::now(),
+        }
+    }
+
+    /// A budget of a whole frame at `frame_rate` frames per second
+    pub fn with_frame_rate(frame_rate: f64) -> Self {
+        Self::new(1000.0 / frame_rate)
+    }
+
+    /// Add a frame's GPU time, in milliseconds
+    pub fn record(&mut self, gpu_ms: f64) {
+        self.samples.push(gpu_ms);
+    }
+
+    /// The scale to render at instead of `scale`, if it's been long enough since the last adjustment
+    /// and the frames since are over budget, or comfortably under it
+    pub fn adjust(&mut self, scale: f32) -> Option<f32> {
+        if self.last_adjusted.elapsed() < Self::INTERVAL || self.samples.is_empty() {
+            return None;
+        }
+        self.last_adjusted = Instant::now();
+        let average = self.samples.iter().sum::<f64>() / self.samples.len() as f64;
+        self.samples.clear();
+        if average <= self.budget_ms && average >= self.budget_ms * Self::HEADROOM {
+            return None;
+        }
+        // the GPU's time mostly goes on pixels, which go up with the square of the scale
+        let ideal = scale * (self.budget_ms / average).sqrt() as f32;
+        let new = (ideal.clamp(scale - Self::MAX_STEP, scale + Self::MAX_STEP) / Self::GRANULARITY)
+            .round()
+            * Self::GRANULARITY;
+        // rounding mustn't move it the wrong way
+        let new = if average > self.budget_ms {
+            new.min(scale)
+        } else {
+            new.max(scale)
+        };
+        let new = new.clamp(self.min_scale, self.max_scale.max(self.min_scale));
+        (new != scale).then_some(new)
+    }
+}
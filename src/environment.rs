@@ -0,0 +1,228 @@
+use std::num::NonZeroU32;
+
+use anyhow::*;
+use wgpu::{
+    AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindingResource,
+    Color, CommandEncoder, Device, Extent3d, FilterMode, LoadOp, Operations,
+    RenderPassColorAttachment, RenderPassDescriptor, Sampler, SamplerDescriptor, Texture,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView,
+    TextureViewDescriptor, TextureViewDimension,
+};
+
+use crate::{
+    camera::CameraUniform,
+    math::{Deg, Matrix4, Point3},
+    postprocess::{FullscreenPass, PostProcessStack},
+    projection,
+    shader::{ShaderDefs, ShaderLibrary},
+    shadow::CUBE_FACES,
+    sky::{Sky, Sun},
+    uniform::UniformBuffer,
+};
+
+/// What surrounds the scene in every direction, for shiny surfaces to reflect.
+/// Each smaller mip level is a blurrier copy of the one above it, for rougher surfaces
+pub struct EnvironmentMap {
+    _texture: Texture,
+    /// The whole cubemap, for sampling in the lighting shader
+    cube_view: TextureView,
+    /// One view per face of each mip level, for rendering into, in order of mip level then face
+    face_views: Vec<TextureView>,
+    /// Trilinear, so that roughness can pick a level in between two mip levels
+    sampler: Sampler,
+    /// Binds each face view as the input of the pass which shrinks it into the next mip level
+    face_bind_groups: Vec<BindGroup>,
+    /// Shrinks one mip level into the next, averaging each 2x2 block of texels
+    downsample_pass: FullscreenPass,
+    /// One camera looking out of each face from the origin
+    face_cameras: Vec<BindGroup>,
+    _face_camera_buffers: Vec<UniformBuffer<CameraUniform>>,
+    /// The sun the map was last rendered with, `None` if it hasn't been rendered yet
+    /// and `Some(None)` if it was rendered without a sky
+    rendered: Option<Option<Sun>>,
+}
+
+impl EnvironmentMap {
+    /// The scene's HDR format, as the sun is much brighter than 1.0
+    pub const FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+    /// The size of each face of the largest mip level in texels
+    pub const RESOLUTION: u32 = 128;
+    /// Down to 8x8, which is about as blurry as the roughest surfaces need.
+    /// Must match `ENVIRONMENT_MIP_LEVELS` in `light.wgsl`
+    pub const MIP_LEVELS: u32 = 5;
+
+    /// `camera_layout` is the layout of the scene's camera, which the sky is drawn with
+    pub fn new(
+        device: &Device,
+        library: &ShaderLibrary,
+        camera_layout: &BindGroupLayout,
+    ) -> Result<Self> {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Environment Map"),
+            size: Extent3d {
+                width: Self::RESOLUTION,
+                height: Self::RESOLUTION,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count: Self::MIP_LEVELS,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+        let cube_view = texture.create_view(&TextureViewDescriptor {
+            label: Some("Environment Map"),
+            dimension: Some(TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let face_views = (0..Self::MIP_LEVELS)
+            .flat_map(|level| (0..6).map(move |face| (level, face)))
+            .map(|(level, face)| {
+                texture.create_view(&TextureViewDescriptor {
+                    label: Some("Environment Map Face"),
+                    dimension: Some(TextureViewDimension::D2),
+                    base_mip_level: level,
+                    mip_level_count: NonZeroU32::new(1),
+                    base_array_layer: face,
+                    array_layer_count: NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Environment Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+
+        // Sampling halfway between each 2x2 block of texels averages them
+        let input_layout = PostProcessStack::create_input_layout(device);
+        let downsample_pass = FullscreenPass::new(
+            device,
+            library,
+            "blit.wgsl",
+            &ShaderDefs::new(),
+            &input_layout,
+            Self::FORMAT,
+        )?;
+        let face_bind_groups = face_views
+            .iter()
+            .map(|view| {
+                device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("environment_face_bind_group"),
+                    layout: &input_layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::TextureView(view),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::Sampler(&sampler),
+                        },
+                    ],
+                })
+            })
+            .collect();
+
+        // Flip vertically, see `CUBE_FACES`
+        let flip_y = Matrix4::from_nonuniform_scale(1.0, -1.0, 1.0);
+        let proj = flip_y * projection::perspective(Deg(90.0), 1.0, 0.1, 10.0);
+        let face_camera_buffers = CUBE_FACES
+            .iter()
+            .map(|&(direction, up)| {
+                let mut uniform = CameraUniform::default();
+                let view = Matrix4::look_to_rh(Point3::new(0.0, 0.0, 0.0), direction, up);
+                uniform.set_view_proj(Point3::new(0.0, 0.0, 0.0), proj * view);
+                UniformBuffer::new(device, uniform, Some("Environment Face Camera"))
+            })
+            .collect::<Vec<_>>();
+        let face_cameras = face_camera_buffers
+            .iter()
+            .map(|buffer| {
+                device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("environment_face_camera_bind_group"),
+                    layout: camera_layout,
+                    entries: &[buffer.bind_group_entry(0)],
+                })
+            })
+            .collect();
+
+        Ok(Self {
+            _texture: texture,
+            cube_view,
+            face_views,
+            sampler,
+            face_bind_groups,
+            downsample_pass,
+            face_cameras,
+            _face_camera_buffers: face_camera_buffers,
+            rendered: None,
+        })
+    }
+
+    /// The cubemap and its sampler, for binding at `binding` and the binding after it
+    pub fn bind_group_entries(&self, binding: u32) -> [BindGroupEntry<'_>; 2] {
+        [
+            BindGroupEntry {
+                binding,
+                resource: BindingResource::TextureView(&self.cube_view),
+            },
+            BindGroupEntry {
+                binding: binding + 1,
+                resource: BindingResource::Sampler(&self.sampler),
+            },
+        ]
+    }
+
+    /// Render the map again on the next `update`, e.g. because the background has changed
+    pub fn invalidate(&mut self) {
+        self.rendered = None;
+    }
+
+    /// Render `sky` into the map, or fill it with `background` if there isn't one.
+    /// This only does anything if the sun has moved since the map was last rendered
+    pub fn update(&mut self, encoder: &mut CommandEncoder, sky: Option<&Sky>, background: Color) {
+        let sun = sky.map(|sky| *sky.sun());
+        if self.rendered == Some(sun) {
+            return;
+        }
+        puffin::profile_function!();
+        self.rendered = Some(sun);
+
+        // the largest mip level comes first
+        for (view, camera) in self.face_views[..6].iter().zip(&self.face_cameras) {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Environment Map Pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(background),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            if let Some(sky) = sky {
+                render_pass.set_bind_group(1, camera, &[]);
+                sky.draw_environment(&mut render_pass);
+            }
+        }
+        for level in 1..Self::MIP_LEVELS as usize {
+            for face in 0..6 {
+                self.downsample_pass.draw(
+                    encoder,
+                    &self.face_bind_groups[(level - 1) * 6 + face],
+                    &[],
+                    &self.face_views[level * 6 + face],
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! A C ABI for driving the renderer from other languages, built into the `cdylib` with the `ffi` feature.
+//! `include/wgpu_cube.h` declares it. Every function taking a renderer needs one from
+//! `wgpu_cube_create` which hasn't been destroyed yet, and functions which can fail return
+//! `false` (or a negative index), after which `wgpu_cube_last_error` says why
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CString},
+    ptr,
+};
+
+use anyhow::*;
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    config::Config,
+    math::{Point3, Vector3},
+    offscreen::Offscreen,
+    transform::Transform,
+    vertex::Vertex,
+};
+
+thread_local! {
+    /// Why the last call on this thread failed
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Remember `error` for `wgpu_cube_last_error`, returning `failed` for the caller to return
+fn fail<T>(error: Error, failed: T) -> T {
+    tracing::error!("{error:#}");
+    let message = CString::new(format!("{error:#}")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    failed
+}
+
+/// Why the last call on this thread failed, or null if nothing has. Only valid until the next call which fails
+#[no_mangle]
+pub extern "C" fn wgpu_cube_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |error| error.as_ptr())
+    })
+}
+
+/// Create a renderer drawing the default scene into frames `width` by `height` pixels, or null if it can't be
+#[no_mangle]
+pub extern "C" fn wgpu_cube_create(width: u32, height: u32) -> *mut Offscreen {
+    match pollster::block_on(Offscreen::new(width, height, &Config::default())) {
+        Result::Ok(renderer) => Box::into_raw(Box::new(renderer)),
+        Err(error) => fail(error, ptr::null_mut()),
+    }
+}
+
+/// Free everything `renderer` holds on to, ignoring null
+///
+/// # Safety
+/// `renderer` has to be null or from `wgpu_cube_create`, and can't be used again afterwards
+#[no_mangle]
+pub unsafe extern "C" fn wgpu_cube_destroy(renderer: *mut Offscreen) {
+    if !renderer.is_null() {
+        drop(Box::from_raw(renderer));
+    }
+}
+
+/// Render frames `width` by `height` pixels from now on
+///
+/// # Safety
+/// `renderer` has to be from `wgpu_cube_create`
+#[no_mangle]
+pub unsafe extern "C" fn wgpu_cube_resize(
+    renderer: *mut Offscreen,
+    width: u32,
+    height: u32,
+) -> bool {
+    let renderer = &mut *renderer;
+    match renderer.resize(width, height) {
+        Result::Ok(()) => true,
+        Err(error) => fail(error, false),
+    }
+}
+
+/// Move the camera to `eye`, looking at `target`, with a vertical field of view of `fovy` degrees
+///
+/// # Safety
+/// `renderer` has to be from `wgpu_cube_create`, and `eye` and `target` have to point to 3 floats each
+#[no_mangle]
+pub unsafe extern "C" fn wgpu_cube_set_camera(
+    renderer: *mut Offscreen,
+    eye: *const f32,
+    target: *const f32,
+    fovy: f32,
+) {
+    let camera = (*renderer).state.camera();
+    let [x, y, z] = *eye.cast::<[f32; 3]>();
+    camera.eye = Point3::new(x, y, z);
+    let [x, y, z] = *target.cast::<[f32; 3]>();
+    camera.target = Point3::new(x, y, z);
+    camera.fovy = fovy;
+}
+
+/// Add a mesh to the scene at `position`, returning its index or -1 if it can't be added.
+/// Each vertex is 8 floats: its position, texture co-ordinates and normal. The triangles are
+/// wound anticlockwise when seen from the front, and the mesh is painted with the scene's texture
+///
+/// # Safety
+/// `renderer` has to be from `wgpu_cube_create`, `vertices` has to point to `8 * vertex_count` floats,
+/// `indices` to `index_count` indices and `position` to 3 floats. None of them can be null, even when
+/// a count is 0
+#[no_mangle]
+pub unsafe extern "C" fn wgpu_cube_load_mesh(
+    renderer: *mut Offscreen,
+    vertices: *const f32,
+    vertex_count: usize,
+    indices: *const u16,
+    index_count: usize,
+    position: *const f32,
+) -> i64 {
+    if vertices.is_null() || indices.is_null() || position.is_null() {
+        return fail(
+            anyhow!("a mesh's vertices, indices and position can't be null"),
+            -1,
+        );
+    }
+    if vertex_count == 0 || index_count == 0 {
+        return fail(
+            anyhow!("a mesh needs vertices and indices, not {vertex_count} and {index_count}"),
+            -1,
+        );
+    }
+    let renderer = &mut *renderer;
+    let vertices = std::slice::from_raw_parts(vertices.cast::<[f32; 8]>(), vertex_count)
+        .iter()
+        .map(|&[x, y, z, u, v, nx, ny, nz]| Vertex::new([x, y, z], [u, v], [nx, ny, nz]))
+        .collect::<Vec<_>>();
+    let indices = std::slice::from_raw_parts(indices, index_count);
+    let [x, y, z] = *position.cast::<[f32; 3]>();
+    let transform = Transform::from_translation(Vector3::new(x, y, z));
+    match renderer.add_mesh(&vertices, indices, transform, [1.0; 3]) {
+        Result::Ok(index) => index as i64,
+        Err(error) => fail(error, -1),
+    }
+}
+
+/// Paint every mesh with a `width` by `height` sRGB image, 4 bytes per pixel in RGBA order
+///
+/// # Safety
+/// `renderer` has to be from `wgpu_cube_create`, and `rgba` has to point to `4 * width * height` bytes,
+/// and can't be null
+#[no_mangle]
+pub unsafe extern "C" fn wgpu_cube_load_texture(
+    renderer: *mut Offscreen,
+    rgba: *const u8,
+    width: u32,
+    height: u32,
+) -> bool {
+    if rgba.is_null() {
+        return fail(anyhow!("a texture's pixels can't be null"), false);
+    }
+    if width == 0 || height == 0 {
+        return fail(
+            anyhow!("can't make a texture {width}x{height} pixels"),
+            false,
+        );
+    }
+    let renderer = &mut *renderer;
+    let pixels = std::slice::from_raw_parts(rgba, 4 * width as usize * height as usize);
+    let Some(image) = image::RgbaImage::from_raw(width, height, pixels.to_vec()) else {
+        return fail(
+            anyhow!("a {width}x{height} texture doesn't fit in its pixels"),
+            false,
+        );
+    };
+    match renderer.state.set_texture(&image.into()) {
+        Result::Ok(()) => true,
+        Err(error) => fail(error, false),
+    }
+}
+
+/// Move the scene along by a frame and render it into `pixels`, 4 bytes per pixel in RGBA order
+/// with the top row first, which has to be `len` bytes long
+///
+/// # Safety
+/// `renderer` has to be from `wgpu_cube_create`, and `pixels` has to point to `len` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn wgpu_cube_render(
+    renderer: *mut Offscreen,
+    pixels: *mut u8,
+    len: usize,
+) -> bool {
+    let renderer = &mut *renderer;
+    let PhysicalSize { width, height } = renderer.state.size;
+    let needed = 4 * width as usize * height as usize;
+    if len != needed {
+        return fail(
+            anyhow!("a {width}x{height} frame needs {needed} bytes, not {len}"),
+            false,
+        );
+    }
+    let frame = renderer.render();
+    std::slice::from_raw_parts_mut(pixels, len).copy_from_slice(&frame);
+    true
+}
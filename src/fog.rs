@@ -0,0 +1,93 @@
+use std::str::FromStr;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+
+/// Fog which thickens with distance from the camera, fading the scene into `color`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Fog {
+    /// Linear RGB, which usually wants to match the background
+    pub color: [f32; 3],
+    pub mode: FogMode,
+}
+
+/// How the fog thickens with distance
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FogMode {
+    #[default]
+    Off,
+    /// None before `start`, rising steadily to completely opaque at `end`
+    Linear { start: f32, end: f32 },
+    /// Thickens quickly near the camera then more slowly, like real fog
+    Exponential { density: f32 },
+    /// Stays thin for longer before closing in
+    ExponentialSquared { density: f32 },
+}
+
+/// Parses `off`, `linear:START,END`, `exp:DENSITY` or `exp2:DENSITY`
+impl FromStr for FogMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (mode, parameters) = s.split_once(':').unwrap_or((s, ""));
+        let number = |s: &str| -> Result<f32> {
+            let number = s
+                .trim()
+                .parse::<f32>()
+                .with_context(|| format!("invalid number `{s}`"))?;
+            ensure!(number >= 0.0, "fog distances can't be negative");
+            Ok(number)
+        };
+        Ok(match mode {
+            "off" => Self::Off,
+            "linear" => {
+                let (start, end) = parameters
+                    .split_once(',')
+                    .context("linear fog needs a start and end, e.g. `linear:10,40`")?;
+                let (start, end) = (number(start)?, number(end)?);
+                ensure!(start < end, "linear fog has to start before it ends");
+                Self::Linear { start, end }
+            }
+            "exp" => Self::Exponential {
+                density: number(parameters)?,
+            },
+            "exp2" => Self::ExponentialSquared {
+                density: number(parameters)?,
+            },
+            _ => bail!("unknown fog mode `{mode}`, expected `off`, `linear`, `exp` or `exp2`"),
+        })
+    }
+}
+
+/// The layout of `Fog` expected by `fog.wgsl`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+pub struct FogUniform {
+    color: [f32; 3],
+    /// One of the `FOG_*` constants in `fog.wgsl`
+    mode: u32,
+    density: f32,
+    start: f32,
+    end: f32,
+    _padding: f32,
+}
+
+impl FogUniform {
+    pub fn update(&mut self, fog: &Fog) {
+        self.color = fog.color;
+        (self.mode, self.density, self.start, self.end) = match fog.mode {
+            FogMode::Off => (0, 0.0, 0.0, 0.0),
+            FogMode::Linear { start, end } => (1, 0.0, start, end),
+            FogMode::Exponential { density } => (2, density, 0.0, 0.0),
+            FogMode::ExponentialSquared { density } => (3, density, 0.0, 0.0),
+        };
+    }
+}
+
+impl From<&Fog> for FogUniform {
+    fn from(fog: &Fog) -> Self {
+        let mut uniform = Self::default();
+        uniform.update(fog);
+        uniform
+    }
+}
@@ -0,0 +1,40 @@
+// Distance fog, bound at group 2 alongside the light as it's part of the scene's lighting
+
+struct Fog {
+    color: vec3<f32>,
+    // one of the `FOG_*` constants
+    mode: u32,
+    // for the exponential modes
+    density: f32,
+    // for linear fog, where it begins and where it's completely opaque
+    start: f32,
+    end: f32,
+};
+@group(2) @binding(3)
+var<uniform> fog: Fog;
+
+let FOG_OFF: u32 = 0u;
+let FOG_LINEAR: u32 = 1u;
+let FOG_EXPONENTIAL: u32 = 2u;
+let FOG_EXPONENTIAL_SQUARED: u32 = 3u;
+
+// How much of the fog's colour covers a fragment `view_depth` in front of the camera, from 0 to 1.
+// For a perspective projection, this depth is the `w` of the clip space position
+fn fog_amount(view_depth: f32) -> f32 {
+    if (fog.mode == FOG_LINEAR) {
+        return clamp((view_depth - fog.start) / max(fog.end - fog.start, 0.0001), 0.0, 1.0);
+    }
+    if (fog.mode == FOG_EXPONENTIAL) {
+        return 1.0 - exp(-fog.density * view_depth);
+    }
+    if (fog.mode == FOG_EXPONENTIAL_SQUARED) {
+        let amount = fog.density * view_depth;
+        return 1.0 - exp(-amount * amount);
+    }
+    return 0.0;
+}
+
+// `color` as seen through the fog from `view_depth` away
+fn apply_fog(color: vec3<f32>, view_depth: f32) -> vec3<f32> {
+    return mix(color, fog.color, fog_amount(view_depth));
+}
@@ -0,0 +1,238 @@
+//! Counting how many fragments each object in the scene shades, to show where overdraw comes from.
+//! wgpu doesn't have occlusion queries inside render passes yet, so this counts fragment shader
+//! invocations with pipeline statistics queries around each object's draws instead, which needs
+//! `Capabilities::pipeline_statistics`. That's roughly how many fragments each object shaded, including those
+//! covered up later by something nearer, so it shows where the overdraw is. It isn't an exact measure of it:
+//! drivers may or may not count fragments rejected by the early depth test, and can count helper invocations too.
+//! Like `GpuTimer`, the counts are read back a few frames later without waiting for them
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use wgpu::{
+    Buffer, BufferDescriptor, BufferUsages, CommandEncoder, Device, Maintain, MapMode,
+    PipelineStatisticsTypes, QuerySet, QuerySetDescriptor, QueryType, RenderPass,
+};
+
+/// Something in the scene which has its fragments counted
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DrawnObject {
+    Sky,
+    /// One of the models, by its index, drawn with all of its instances
+    Model(usize),
+    /// The instances of a model which are glass, drawn again in the glass pass
+    Glass(usize),
+    Terrain,
+    Vegetation,
+    PointCloud,
+    Mirror(usize),
+    Water,
+}
+
+impl fmt::Display for DrawnObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sky => write!(f, "sky"),
+            Self::Model(index) => write!(f, "model {index}"),
+            Self::Glass(index) => write!(f, "model {index} (glass)"),
+            Self::Terrain => write!(f, "terrain"),
+            Self::Vegetation => write!(f, "vegetation"),
+            Self::PointCloud => write!(f, "point cloud"),
+            Self::Mirror(index) => write!(f, "mirror {index}"),
+            Self::Water => write!(f, "water"),
+        }
+    }
+}
+
+/// Counts the fragments each object shades, a frame at a time
+pub struct FragmentStats {
+    slots: Vec<Slot>,
+    /// The slot being written to this frame, between `begin` and reading it back
+    current: Option<usize>,
+}
+
+struct Slot {
+    query_set: QuerySet,
+    /// Where the queries are resolved to be read back
+    read_buffer: Buffer,
+    /// The frame counted by the queries, if they're waiting to be read
+    frame: Option<u64>,
+    /// What each query counted, in order, once the frame's been recorded
+    objects: Mutex<Vec<DrawnObject>>,
+    /// Whether `map_async` has been called for the frame's counts
+    mapping: bool,
+    /// Set by `map_async`'s callback once the buffer can be read
+    mapped: Arc<AtomicBool>,
+    /// Set by `map_async`'s callback if the buffer couldn't be mapped, so that the slot can be used again
+    failed: Arc<AtomicBool>,
+}
+
+impl FragmentStats {
+    /// The number of frames which can be waiting to be read back at once
+    const SLOTS: usize = 4;
+    /// The number of draws which can be counted each frame, beyond which they're drawn uncounted
+    pub const MAX_OBJECTS: u32 = 256;
+    const BUFFER_SIZE: u64 = Self::MAX_OBJECTS as u64 * wgpu::QUERY_SIZE as u64;
+
+    pub fn new(device: &Device) -> Self {
+        let slots = (0..Self::SLOTS)
+            .map(|_| Slot {
+                query_set: device.create_query_set(&QuerySetDescriptor {
+                    label: Some("Fragment Stats Queries"),
+                    ty: QueryType::PipelineStatistics(
+                        PipelineStatisticsTypes::FRAGMENT_SHADER_INVOCATIONS,
+                    ),
+                    count: Self::MAX_OBJECTS,
+                }),
+                read_buffer: device.create_buffer(&BufferDescriptor {
+                    label: Some("Fragment Stats Read Buffer"),
+                    size: Self::BUFFER_SIZE,
+                    usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                }),
+                frame: None,
+                objects: Mutex::new(Vec::new()),
+                mapping: false,
+                mapped: Arc::new(AtomicBool::new(false)),
+                failed: Arc::new(AtomicBool::new(false)),
+            })
+            .collect();
+        Self {
+            slots,
+            current: None,
+        }
+    }
+
+    /// Start counting `frame`'s fragments, before `counter` is asked for.
+    /// A frame isn't counted if every slot is still waiting to be read
+    pub fn begin(&mut self, frame: u64) {
+        self.current = self.slots.iter().position(|slot| slot.frame.is_none());
+        if let Some(index) = self.current {
+            let slot = &mut self.slots[index];
+            slot.frame = Some(frame);
+            slot.objects.get_mut().unwrap().clear();
+        }
+    }
+
+    /// What the frame's objects are drawn through to count their fragments, which is disabled
+    /// when the frame isn't being counted
+    pub fn counter(&self) -> FragmentCounter<'_> {
+        FragmentCounter {
+            slot: self.current.map(|index| &self.slots[index]),
+            objects: Vec::new(),
+        }
+    }
+
+    /// Start reading back the frames counted since the last call, and return the counts of the latest one
+    /// which has been read, by its number. This has to be called after the frame has been submitted
+    pub fn read(&mut self, device: &Device) -> Option<(u64, Vec<(DrawnObject, u64)>)> {
+        self.current = None;
+        for slot in &mut self.slots {
+            if slot.frame.is_none() || slot.mapping {
+                continue;
+            }
+            slot.mapping = true;
+            let (mapped, failed) = (slot.mapped.clone(), slot.failed.clone());
+            slot.read_buffer
+                .slice(..)
+                .map_async(MapMode::Read, move |result| match result {
+                    Ok(()) => mapped.store(true, Ordering::Release),
+                    Err(error) => {
+                        tracing::error!("Failed to map the fragment stats' buffer: {error}");
+                        failed.store(true, Ordering::Release);
+                    }
+                });
+        }
+        device.poll(Maintain::Poll);
+
+        let mut latest = None;
+        for slot in &mut self.slots {
+            if slot.failed.swap(false, Ordering::Acquire) {
+                // the frame's counts are lost. The buffer's left unmapped by the failure, and unmapping it
+                // again would be a validation error, so the slot only has to be freed
+                slot.objects.get_mut().unwrap().clear();
+                slot.mapping = false;
+                slot.frame = None;
+                continue;
+            }
+            if !slot.mapped.swap(false, Ordering::Acquire) {
+                continue;
+            }
+            let objects = std::mem::take(slot.objects.get_mut().unwrap());
+            let counts = {
+                let range = slot.read_buffer.slice(..).get_mapped_range();
+                let counts = range
+                    .chunks_exact(wgpu::QUERY_SIZE as usize)
+                    .map(bytemuck::pod_read_unaligned::<u64>);
+                objects.into_iter().zip(counts).collect()
+            };
+            slot.read_buffer.unmap();
+            slot.mapping = false;
+            let frame = slot
+                .frame
+                .take()
+                .expect("mapped slots have counted a frame");
+            if latest.as_ref().is_none_or(|&(latest, _)| frame > latest) {
+                latest = Some((frame, counts));
+            }
+        }
+        latest
+    }
+}
+
+/// Counts the fragments of the objects drawn through it in one frame, see `FragmentStats::counter`
+pub struct FragmentCounter<'a> {
+    slot: Option<&'a Slot>,
+    /// What's been counted so far, by query
+    objects: Vec<DrawnObject>,
+}
+
+impl<'a> FragmentCounter<'a> {
+    /// One which counts nothing, e.g. for drawing reflections, whose fragments aren't the scene's
+    pub fn disabled() -> Self {
+        Self {
+            slot: None,
+            objects: Vec::new(),
+        }
+    }
+
+    /// Record `draw`, counting the fragments it shades as `object`'s. Pipeline statistics queries
+    /// can't be nested, so `draw` mustn't count anything itself
+    pub fn count<'p>(
+        &mut self,
+        render_pass: &mut RenderPass<'p>,
+        object: DrawnObject,
+        draw: impl FnOnce(&mut RenderPass<'p>),
+    ) {
+        let query = self.objects.len() as u32;
+        let Some(slot) = self.slot.filter(|_| query < FragmentStats::MAX_OBJECTS) else {
+            draw(render_pass);
+            return;
+        };
+        render_pass.begin_pipeline_statistics_query(&slot.query_set, query);
+        draw(render_pass);
+        render_pass.end_pipeline_statistics_query();
+        self.objects.push(object);
+    }
+
+    /// Resolve the counts to be read back, once every pass they were counted in has ended
+    pub fn finish(self, encoder: &mut CommandEncoder) {
+        let Some(slot) = self.slot else {
+            return;
+        };
+        if !self.objects.is_empty() {
+            encoder.resolve_query_set(
+                &slot.query_set,
+                0..self.objects.len() as u32,
+                &slot.read_buffer,
+                0,
+            );
+        }
+        *slot.objects.lock().unwrap() = self.objects;
+    }
+}
@@ -0,0 +1,252 @@
+//! Hands every frame's pixels to a callback, e.g. for streaming them over the network or feeding an encoder.
+//! Unlike the recorder, which waits for each frame to be read back, frames are copied into a ring of buffers
+//! and only handed over once the GPU has finished with them, a few frames later.
+//! If the ring is full the frame is skipped rather than stalling rendering
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{ensure, Result};
+use image::RgbaImage;
+use wgpu::{CommandEncoder, Device, Maintain, TextureFormat};
+
+use crate::{
+    postprocess::PostProcessStack,
+    readback::{rgba_image, Readback, ReadbackTarget},
+};
+
+/// A frame read back for the callback
+pub struct StreamedFrame {
+    /// The number of frames rendered before this one since the callback was set,
+    /// which skips ahead when frames are dropped
+    pub index: u64,
+    /// Without the overlays, like recordings
+    pub image: RgbaImage,
+}
+
+/// Reads back every frame while there's a callback for them, see the module docs
+pub struct FrameStream {
+    callback: Option<Box<dyn FnMut(StreamedFrame)>>,
+    ring: Option<Ring>,
+    /// The index of the next frame rendered
+    frame: u64,
+    /// The number of frames skipped because every buffer was still waiting to be read
+    dropped: u64,
+}
+
+impl FrameStream {
+    /// The number of frames which can be in flight at once, enough to cover the latency
+    /// of a readback without holding on to too much memory
+    pub const RING_LENGTH: usize = 3;
+
+    pub fn new() -> Self {
+        Self {
+            callback: None,
+            ring: None,
+            frame: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Hand every frame from the next one on to `callback`, in order, replacing any callback set before.
+    /// It's called on the render thread, so anything slow like encoding belongs on a thread of its own
+    pub fn set_callback(&mut self, callback: impl FnMut(StreamedFrame) + 'static) {
+        self.clear_callback();
+        self.callback = Some(Box::new(callback));
+    }
+
+    /// Stop reading frames back, dropping any still in flight
+    pub fn clear_callback(&mut self) {
+        self.callback = None;
+        self.ring = None;
+        self.frame = 0;
+        self.dropped = 0;
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.callback.is_some()
+    }
+
+    /// The number of frames skipped since the callback was set, because the GPU or the callback fell behind
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Copy the result of `post_process` into the next free buffer in the ring, if there's a callback for it
+    pub fn capture(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        post_process: &PostProcessStack,
+        format: TextureFormat,
+        width: u32,
+        height: u32,
+    ) {
+        if self.callback.is_none() {
+            return;
+        }
+        // the output pass draws a single sample per pixel, whatever the scene has
+        let ring = match &mut self.ring {
+            Some(ring) if ring.target.matches(format, width, height, 1) => ring,
+            ring => match Ring::new(device, format, width, height, 1) {
+                // frames in flight are the old size, so they're dropped along with the old ring
+                Ok(new) => ring.insert(new),
+                Err(error) => {
+                    tracing::error!("Can't stream frames: {error:#}");
+                    self.clear_callback();
+                    return;
+                }
+            },
+        };
+        let index = self.frame;
+        self.frame += 1;
+        let Some(slot) = ring.free_slot() else {
+            self.dropped += 1;
+            return;
+        };
+        post_process.draw_output(encoder, &ring.target.view);
+        ring.copy(encoder, slot, index);
+    }
+
+    /// Start reading back the frame copied by `capture`, and hand over every frame which has been read,
+    /// which has to be called after the frame's commands have been submitted
+    pub fn finish_frame(&mut self, device: &Device) {
+        let (Some(callback), Some(ring)) = (&mut self.callback, &mut self.ring) else {
+            return;
+        };
+        puffin::profile_function!();
+        ring.map_copied();
+        // finishes whichever readbacks the GPU is done with, without waiting for the rest
+        device.poll(Maintain::Poll);
+        while let Some(frame) = ring.take_mapped() {
+            callback(frame);
+        }
+    }
+}
+
+impl Default for FrameStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where frames are copied to be read back, and the buffers they're copied into
+struct Ring {
+    target: ReadbackTarget,
+    slots: Vec<Slot>,
+    /// The slots holding frames, oldest first, which are handed over in this order
+    in_flight: VecDeque<usize>,
+}
+
+struct Slot {
+    readback: Readback,
+    /// The index of the frame copied into the buffer, if any
+    frame: Option<u64>,
+    /// Whether the frame in the buffer is being read back
+    mapping: bool,
+    /// Filled in once the frame has been read back
+    read: Arc<Mutex<Option<Result<Vec<u8>>>>>,
+}
+
+impl Ring {
+    fn new(
+        device: &Device,
+        format: TextureFormat,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> Result<Self> {
+        ensure!(
+            matches!(
+                format,
+                TextureFormat::Rgba8Unorm
+                    | TextureFormat::Rgba8UnormSrgb
+                    | TextureFormat::Bgra8Unorm
+                    | TextureFormat::Bgra8UnormSrgb
+            ),
+            "frames in {format:?} can't be streamed"
+        );
+        let target = ReadbackTarget::new(
+            device,
+            "Frame Stream Target",
+            format,
+            width,
+            height,
+            sample_count,
+        );
+        let slots = (0..FrameStream::RING_LENGTH)
+            .map(|_| {
+                Ok(Slot {
+                    readback: target.readback(device)?,
+                    frame: None,
+                    mapping: false,
+                    read: Arc::new(Mutex::new(None)),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            target,
+            slots,
+            in_flight: VecDeque::with_capacity(FrameStream::RING_LENGTH),
+        })
+    }
+
+    fn free_slot(&self) -> Option<usize> {
+        self.slots.iter().position(|slot| slot.frame.is_none())
+    }
+
+    fn copy(&mut self, encoder: &mut CommandEncoder, slot: usize, frame: u64) {
+        self.target.copy(encoder, &self.slots[slot].readback);
+        self.slots[slot].frame = Some(frame);
+        self.in_flight.push_back(slot);
+    }
+
+    /// Start reading back every buffer copied into since the last call, which can only be done
+    /// once the copy has been submitted
+    fn map_copied(&mut self) {
+        for &index in &self.in_flight {
+            let slot = &mut self.slots[index];
+            if slot.mapping {
+                continue;
+            }
+            slot.mapping = true;
+            let read = slot.read.clone();
+            slot.readback
+                .then(move |bytes| *read.lock().unwrap() = Some(bytes));
+        }
+    }
+
+    /// The oldest frame in flight, if it's been read back, freeing its buffer for another.
+    /// Frames which failed to be read back are skipped
+    fn take_mapped(&mut self) -> Option<StreamedFrame> {
+        loop {
+            let &index = self.in_flight.front()?;
+            let slot = &mut self.slots[index];
+            let bytes = slot.read.lock().unwrap().take()?;
+            self.in_flight.pop_front();
+            slot.mapping = false;
+            let frame = slot
+                .frame
+                .take()
+                .expect("frames in flight have been copied");
+            match bytes.and_then(|bytes| {
+                rgba_image(
+                    bytes,
+                    self.target.format,
+                    self.target.width,
+                    self.target.height,
+                )
+            }) {
+                Ok(image) => {
+                    return Some(StreamedFrame {
+                        index: frame,
+                        image,
+                    })
+                }
+                Err(error) => tracing::error!("Failed to read back a streamed frame: {error:#}"),
+            }
+        }
+    }
+}
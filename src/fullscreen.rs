@@ -0,0 +1,157 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::*;
+use winit::{
+    dpi::PhysicalSize,
+    monitor::{MonitorHandle, VideoMode},
+    window::{Fullscreen, Window},
+};
+
+use crate::window;
+
+/// The video mode to go exclusive fullscreen in, where anything left out is picked for us:
+/// the largest size, and the highest refresh rate at that size
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VideoModeRequest {
+    pub size: Option<PhysicalSize<u32>>,
+    pub refresh_rate_millihertz: Option<u32>,
+}
+
+impl VideoModeRequest {
+    /// The mode of `monitor` closest to this one, preferring the exact size over the exact refresh rate
+    pub fn pick(&self, monitor: &MonitorHandle) -> Option<VideoMode> {
+        let modes = video_modes(monitor);
+        let size = self
+            .size
+            .filter(|&size| modes.iter().any(|mode| mode.size() == size))
+            .or_else(|| {
+                if let Some(size) = self.size {
+                    tracing::warn!(
+                        "{} has no {}x{} modes, using the largest instead",
+                        monitor_name(monitor),
+                        size.width,
+                        size.height
+                    );
+                }
+                modes.first().map(VideoMode::size)
+            })?;
+        modes
+            .into_iter()
+            .filter(|mode| mode.size() == size)
+            // the modes are sorted fastest first, so this picks the fastest when no rate was asked for
+            .min_by_key(|mode| {
+                self.refresh_rate_millihertz
+                    .map_or(0, |rate| mode.refresh_rate_millihertz().abs_diff(rate))
+            })
+    }
+}
+
+/// Parses `WIDTHxHEIGHT@HZ`, where either half can be left out, e.g. `1920x1080` or `@144`,
+/// or `auto` to leave both out
+impl FromStr for VideoModeRequest {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "auto" {
+            return Ok(Self::default());
+        }
+        let (size, rate) = s.split_once('@').unwrap_or((s, ""));
+        let size = if size.is_empty() {
+            None
+        } else {
+            Some(window::parse_size(size)?)
+        };
+        let refresh_rate_millihertz = if rate.is_empty() {
+            None
+        } else {
+            let hz = rate
+                .trim_end_matches("Hz")
+                .parse::<f64>()
+                .context("invalid refresh rate")?;
+            Some((hz * 1000.0).round() as u32)
+        };
+        Ok(Self {
+            size,
+            refresh_rate_millihertz,
+        })
+    }
+}
+
+/// Every video mode `monitor` supports, largest then fastest first
+pub fn video_modes(monitor: &MonitorHandle) -> Vec<VideoMode> {
+    let mut modes = monitor.video_modes().collect::<Vec<_>>();
+    modes.sort_by_key(|mode| {
+        let size = mode.size();
+        std::cmp::Reverse((
+            size.width * size.height,
+            mode.refresh_rate_millihertz(),
+            mode.bit_depth(),
+        ))
+    });
+    modes
+}
+
+/// Take `window` exclusive fullscreen on its current monitor, in the mode closest to `request`.
+/// The window is resized along with the display, which reconfigures the surface
+pub fn enter_exclusive(window: &Window, request: &VideoModeRequest) -> Result<VideoMode> {
+    let monitor = window
+        .current_monitor()
+        .context("the window isn't on a monitor")?;
+    let mode = request
+        .pick(&monitor)
+        .with_context(|| format!("{} has no video modes", monitor_name(&monitor)))?;
+    tracing::info!(
+        "Going fullscreen on {} at {}",
+        monitor_name(&monitor),
+        DisplayMode(&mode)
+    );
+    window.set_fullscreen(Some(Fullscreen::Exclusive(mode.clone())));
+    Ok(mode)
+}
+
+/// Leave fullscreen if `window` is in it, otherwise enter exclusive fullscreen in the mode closest
+/// to `request`, or borderless fullscreen without one
+pub fn toggle(window: &Window, request: Option<&VideoModeRequest>) {
+    if window.fullscreen().is_some() {
+        window.set_fullscreen(None);
+    } else if let Some(request) = request {
+        if let Err(error) = enter_exclusive(window, request) {
+            tracing::error!("Can't go fullscreen: {error:#}");
+        }
+    } else {
+        window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+    }
+}
+
+/// List every monitor's video modes
+pub fn describe_monitors(window: &Window) -> String {
+    let mut description = String::new();
+    for monitor in window.available_monitors() {
+        description += &format!("{}:\n", monitor_name(&monitor));
+        for mode in video_modes(&monitor) {
+            description += &format!("  {}\n", DisplayMode(&mode));
+        }
+    }
+    description
+}
+
+fn monitor_name(monitor: &MonitorHandle) -> String {
+    monitor.name().unwrap_or_else(|| "Unnamed monitor".into())
+}
+
+/// Formats a mode the way `VideoModeRequest` parses it, along with its bit depth
+struct DisplayMode<'a>(&'a VideoMode);
+
+impl fmt::Display for DisplayMode<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let size = self.0.size();
+        write!(
+            f,
+            "{}x{}@{} ({} bit)",
+            size.width,
+            size.height,
+            self.0.refresh_rate_millihertz() as f64 / 1000.0,
+            self.0.bit_depth()
+        )
+    }
+}
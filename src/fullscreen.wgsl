@@ -0,0 +1,23 @@
+// Shared vertex shader and input bindings for full-screen post-processing passes.
+// Draw 3 vertices without any vertex buffers, which produces a single triangle covering the whole screen
+
+struct FullscreenOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> FullscreenOutput {
+    var out: FullscreenOutput;
+    // (0, 0), (2, 0), (0, 2), the parts outside of the screen are clipped
+    out.uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    // Texture co-ordinates go downwards, whereas clip space goes upwards
+    out.clip_position = vec4<f32>(out.uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    return out;
+}
+
+// The output of the previous pass
+@group(0) @binding(0)
+var t_input: texture_2d<f32>;
+@group(0) @binding(1)
+var s_input: sampler;
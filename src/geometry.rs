@@ -0,0 +1,526 @@
+use crate::{
+    math::{EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3, Vector4},
+    transform::Transform,
+};
+
+/// An axis-aligned box around part of the scene, in world space
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    /// The box around all of `points`, or a point at the origin without any
+    pub fn around(points: &[Point3<f32>]) -> Self {
+        let Some(&first) = points.first() else {
+            return Self {
+                min: Point3::origin(),
+                max: Point3::origin(),
+            };
+        };
+        points.iter().fold(
+            Self {
+                min: first,
+                max: first,
+            },
+            |aabb, point| Self {
+                min: Point3::new(
+                    aabb.min.x.min(point.x),
+                    aabb.min.y.min(point.y),
+                    aabb.min.z.min(point.z),
+                ),
+                max: Point3::new(
+                    aabb.max.x.max(point.x),
+                    aabb.max.y.max(point.y),
+                    aabb.max.z.max(point.z),
+                ),
+            },
+        )
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    /// The box grown by `amount` on every side
+    pub fn expanded(&self, amount: f32) -> Self {
+        let amount = Vector3::new(amount, amount, amount);
+        Self {
+            min: self.min - amount,
+            max: self.max + amount,
+        }
+    }
+
+    /// The box around this one once it's been moved by `transform`, which is bigger than it
+    /// has to be if `transform` rotates it
+    pub fn transformed(&self, transform: &Transform) -> Self {
+        let ends = [self.min, self.max];
+        let corners = (0..8)
+            .map(|corner| {
+                transform.transform_point(Point3::new(
+                    ends[corner & 1].x,
+                    ends[corner >> 1 & 1].y,
+                    ends[corner >> 2 & 1].z,
+                ))
+            })
+            .collect::<Vec<_>>();
+        Self::around(&corners)
+    }
+
+    /// Which way the face of the box nearest to `point` faces, e.g. for sliding along the box
+    /// after running into it at `point`
+    pub fn nearest_face_normal(&self, point: Point3<f32>) -> Vector3<f32> {
+        let mut normal = Vector3::new(0.0, 0.0, 0.0);
+        let mut nearest = f32::INFINITY;
+        for axis in 0..3 {
+            for (distance, side) in [
+                ((point[axis] - self.min[axis]).abs(), -1.0),
+                ((self.max[axis] - point[axis]).abs(), 1.0),
+            ] {
+                if distance < nearest {
+                    nearest = distance;
+                    normal = Vector3::new(0.0, 0.0, 0.0);
+                    normal[axis] = side;
+                }
+            }
+        }
+        normal
+    }
+
+    /// How far `point` is from the nearest point in the box, 0 if it's inside
+    pub fn distance_to(&self, point: Point3<f32>) -> f32 {
+        let outside = |value: f32, min: f32, max: f32| (min - value).max(value - max).max(0.0);
+        Vector3::new(
+            outside(point.x, self.min.x, self.max.x),
+            outside(point.y, self.min.y, self.max.y),
+            outside(point.z, self.min.z, self.max.z),
+        )
+        .magnitude()
+    }
+
+    /// Whether `point` is inside the box or on its surface
+    pub fn contains(&self, point: Point3<f32>) -> bool {
+        self.distance_to(point) == 0.0
+    }
+}
+
+/// A ball, e.g. roughly bounding a mesh which is quicker to test than its box
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl Sphere {
+    /// A sphere around all of `points`, centred on the middle of their box, which is quick to find
+    /// though not always the smallest. Without any points it's a point at the origin
+    pub fn around(points: &[Point3<f32>]) -> Self {
+        let center = Aabb::around(points).center();
+        let radius = points
+            .iter()
+            .map(|&point| (point - center).magnitude2())
+            .fold(0.0, f32::max)
+            .sqrt();
+        Self { center, radius }
+    }
+
+    /// The smallest sphere around both this one and `other`
+    pub fn merge(&self, other: &Sphere) -> Self {
+        let between = other.center - self.center;
+        let distance = between.magnitude();
+        if distance + other.radius <= self.radius {
+            return *self;
+        }
+        if distance + self.radius <= other.radius {
+            return *other;
+        }
+        let radius = (distance + self.radius + other.radius) / 2.0;
+        Self {
+            center: self.center + between * ((radius - self.radius) / distance),
+            radius,
+        }
+    }
+
+    /// The sphere moved by `transform`, grown by its largest scale so that it still bounds whatever it did
+    pub fn transformed(&self, transform: &Transform) -> Self {
+        let scale = transform.scale;
+        Self {
+            center: transform.transform_point(self.center),
+            radius: self.radius * scale.x.abs().max(scale.y.abs()).max(scale.z.abs()),
+        }
+    }
+}
+
+/// An endless flat surface, made up of the points `distance` along `normal` from the origin,
+/// the same as `CameraUniform::set_clip_planes` takes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+    /// Which way the plane faces, which must be normalised
+    pub normal: Vector3<f32>,
+    pub distance: f32,
+}
+
+impl Plane {
+    /// The plane through `point` facing `normal`, which must be normalised
+    pub fn from_point(normal: Vector3<f32>, point: Point3<f32>) -> Self {
+        Self {
+            normal,
+            distance: normal.dot(point.to_vec()),
+        }
+    }
+
+    /// How far `point` is in front of the plane, negative if it's behind
+    pub fn signed_distance(&self, point: Point3<f32>) -> f32 {
+        self.normal.dot(point.to_vec()) - self.distance
+    }
+}
+
+/// A half-line from `origin` along `direction`, e.g. from the camera through the cursor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    /// Doesn't need to be normalised, though if it isn't the distances along the ray are in multiples of its length
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    /// The ray through a point on screen, given in normalised device co-ordinates from -1 to 1 with +y up,
+    /// for the camera whose view-projection matrix with wgpu's 0 to 1 depth range is `view_proj`.
+    /// It starts on the near plane, and its direction is normalised
+    pub fn from_screen(view_proj: &Matrix4<f32>, x: f32, y: f32) -> Option<Self> {
+        let inverse = view_proj.invert()?;
+        let unproject = |depth: f32| {
+            let point = inverse * Vector4::new(x, y, depth, 1.0);
+            Point3::from_homogeneous(point)
+        };
+        let near = unproject(0.0);
+        // not quite the far plane, which is at infinity for an infinite projection
+        let far = unproject(0.5);
+        Some(Self {
+            origin: near,
+            direction: (far - near).normalize(),
+        })
+    }
+
+    /// The point `distance` along the ray
+    pub fn at(&self, distance: f32) -> Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    /// How far along the ray it first enters `aabb`, 0 if it starts inside
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        // the slab method, where 1/0 is infinity so that rays parallel to a slab are only
+        // inside it if they start inside it
+        let (mut near, mut far) = (0.0f32, f32::INFINITY);
+        for axis in 0..3 {
+            let inverse = 1.0 / self.direction[axis];
+            let to_min = (aabb.min[axis] - self.origin[axis]) * inverse;
+            let to_max = (aabb.max[axis] - self.origin[axis]) * inverse;
+            // NaN when the ray starts exactly on a parallel slab's edge, which `min` and `max` skip over
+            near = near.max(to_min.min(to_max));
+            far = far.min(to_min.max(to_max));
+        }
+        (near <= far).then_some(near)
+    }
+
+    /// How far along the ray it hits the triangle `a`, `b`, `c` from either side,
+    /// along with the barycentric weights of `b` and `c` at the hit
+    pub fn intersect_triangle(
+        &self,
+        a: Point3<f32>,
+        b: Point3<f32>,
+        c: Point3<f32>,
+    ) -> Option<(f32, [f32; 2])> {
+        // Möller–Trumbore
+        let (ab, ac) = (b - a, c - a);
+        let p = self.direction.cross(ac);
+        let determinant = ab.dot(p);
+        if determinant.abs() < f32::EPSILON {
+            // parallel to the triangle
+            return None;
+        }
+        let inverse = 1.0 / determinant;
+        let to_origin = self.origin - a;
+        let u = to_origin.dot(p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = to_origin.cross(ab);
+        let v = self.direction.dot(q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = ac.dot(q) * inverse;
+        (distance >= 0.0).then_some((distance, [u, v]))
+    }
+
+    /// How far along the ray it crosses `plane` from either side
+    pub fn intersect_plane(&self, plane: &Plane) -> Option<f32> {
+        let towards = plane.normal.dot(self.direction);
+        if towards.abs() < f32::EPSILON {
+            return None;
+        }
+        let distance = -plane.signed_distance(self.origin) / towards;
+        (distance >= 0.0).then_some(distance)
+    }
+
+    /// How far along the ray it first enters `sphere`, 0 if it starts inside
+    pub fn intersect_sphere(&self, sphere: &Sphere) -> Option<f32> {
+        let to_center = sphere.center - self.origin;
+        let length = self.direction.magnitude2();
+        let along = to_center.dot(self.direction) / length;
+        let miss = to_center.magnitude2() - along * along * length;
+        let radius2 = sphere.radius * sphere.radius;
+        if miss > radius2 {
+            return None;
+        }
+        let half_chord = ((radius2 - miss) / length).sqrt();
+        let (enter, exit) = (along - half_chord, along + half_chord);
+        (exit >= 0.0).then_some(enter.max(0.0))
+    }
+}
+
+/// The 6 planes bounding what a camera can see, pointing inwards
+pub struct Frustum {
+    /// Normalised, so that they give true distances
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Extract the planes from a view-projection matrix with wgpu's 0 to 1 depth range
+    pub fn from_view_proj(view_proj: &Matrix4<f32>) -> Self {
+        let row = |i: usize| {
+            Vector4::new(
+                view_proj.x[i],
+                view_proj.y[i],
+                view_proj.z[i],
+                view_proj.w[i],
+            )
+        };
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        Self {
+            planes: [w + x, w - x, w + y, w - y, z, w - z]
+                .map(|plane| plane / plane.truncate().magnitude()),
+        }
+    }
+
+    /// Whether any of `aabb` might be visible. Boxes near the frustum's corners can be let through
+    /// when they're really outside, which only costs a wasted draw
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner furthest along the plane's normal
+            let corner = Vector4::new(
+                if plane.x >= 0.0 {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                if plane.y >= 0.0 {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                if plane.z >= 0.0 {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+                1.0,
+            );
+            plane.dot(corner) >= 0.0
+        })
+    }
+
+    /// Whether any of `sphere` might be visible, with the same leeway at the corners as `intersects`
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        let center = sphere.center.to_homogeneous();
+        self.planes
+            .iter()
+            .all(|plane| plane.dot(center) >= -sphere.radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{math::Deg, projection};
+
+    const EPSILON: f32 = 1e-5;
+
+    fn ray(origin: [f32; 3], direction: [f32; 3]) -> Ray {
+        Ray {
+            origin: origin.into(),
+            direction: direction.into(),
+        }
+    }
+
+    fn unit_box() -> Aabb {
+        Aabb {
+            min: Point3::new(-1.0, -1.0, -1.0),
+            max: Point3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    #[test]
+    fn ray_hits_aabb() {
+        let distance = ray([-5.0, 0.0, 0.0], [1.0, 0.0, 0.0]).intersect_aabb(&unit_box());
+        assert!((distance.unwrap() - 4.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn ray_misses_aabb() {
+        assert_eq!(
+            ray([-5.0, 2.0, 0.0], [1.0, 0.0, 0.0]).intersect_aabb(&unit_box()),
+            None
+        );
+        // pointing away from it
+        assert_eq!(
+            ray([-5.0, 0.0, 0.0], [-1.0, 0.0, 0.0]).intersect_aabb(&unit_box()),
+            None
+        );
+    }
+
+    #[test]
+    fn ray_parallel_to_slab() {
+        // parallel to the y and z slabs, inside both
+        let distance = ray([-5.0, 0.5, 0.5], [1.0, 0.0, 0.0]).intersect_aabb(&unit_box());
+        assert!((distance.unwrap() - 4.0).abs() < EPSILON);
+        // parallel to the y slab, outside it
+        assert_eq!(
+            ray([-5.0, 1.5, 0.0], [1.0, 0.0, 0.0]).intersect_aabb(&unit_box()),
+            None
+        );
+    }
+
+    #[test]
+    fn ray_starting_inside_aabb() {
+        assert_eq!(
+            ray([0.0, 0.0, 0.0], [0.0, 1.0, 0.0]).intersect_aabb(&unit_box()),
+            Some(0.0)
+        );
+    }
+
+    const TRIANGLE: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+
+    fn intersect_triangle(ray: Ray) -> Option<(f32, [f32; 2])> {
+        let [a, b, c] = TRIANGLE.map(Point3::from);
+        ray.intersect_triangle(a, b, c)
+    }
+
+    #[test]
+    fn ray_hits_triangle() {
+        let (distance, [u, v]) =
+            intersect_triangle(ray([0.25, 0.5, 2.0], [0.0, 0.0, -1.0])).unwrap();
+        assert!((distance - 2.0).abs() < EPSILON);
+        assert!((u - 0.25).abs() < EPSILON);
+        assert!((v - 0.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn ray_misses_triangle_edge() {
+        // just past the hypotenuse
+        assert_eq!(
+            intersect_triangle(ray([0.5, 0.51, 2.0], [0.0, 0.0, -1.0])),
+            None
+        );
+        // just past the edge along x
+        assert_eq!(
+            intersect_triangle(ray([0.5, -0.01, 2.0], [0.0, 0.0, -1.0])),
+            None
+        );
+    }
+
+    #[test]
+    fn ray_hits_triangle_back_face() {
+        let (distance, _) = intersect_triangle(ray([0.25, 0.25, -3.0], [0.0, 0.0, 1.0])).unwrap();
+        assert!((distance - 3.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn ray_hits_plane() {
+        let plane = Plane::from_point(Vector3::unit_y(), Point3::new(0.0, 2.0, 0.0));
+        let distance = ray([1.0, 0.0, 1.0], [0.0, 0.5, 0.0]).intersect_plane(&plane);
+        // in multiples of the direction's length
+        assert!((distance.unwrap() - 4.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn ray_parallel_to_plane() {
+        let plane = Plane::from_point(Vector3::unit_y(), Point3::new(0.0, 2.0, 0.0));
+        assert_eq!(
+            ray([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]).intersect_plane(&plane),
+            None
+        );
+    }
+
+    #[test]
+    fn plane_behind_ray() {
+        let plane = Plane::from_point(Vector3::unit_y(), Point3::new(0.0, 2.0, 0.0));
+        assert_eq!(
+            ray([0.0, 0.0, 0.0], [0.0, -1.0, 0.0]).intersect_plane(&plane),
+            None
+        );
+    }
+
+    #[test]
+    fn ray_hits_sphere() {
+        let sphere = Sphere {
+            center: Point3::new(0.0, 0.0, -10.0),
+            radius: 2.0,
+        };
+        let distance = ray([0.0, 0.0, 0.0], [0.0, 0.0, -1.0]).intersect_sphere(&sphere);
+        assert!((distance.unwrap() - 8.0).abs() < EPSILON);
+        assert_eq!(
+            ray([0.0, 3.0, 0.0], [0.0, 0.0, -1.0]).intersect_sphere(&sphere),
+            None
+        );
+    }
+
+    #[test]
+    fn ray_starting_inside_sphere() {
+        let sphere = Sphere {
+            center: Point3::new(0.0, 0.0, 0.0),
+            radius: 2.0,
+        };
+        assert_eq!(
+            ray([0.5, 0.0, 0.0], [1.0, 0.0, 0.0]).intersect_sphere(&sphere),
+            Some(0.0)
+        );
+    }
+
+    #[test]
+    fn frustum_culls() {
+        let view_proj = projection::perspective(Deg(90.0), 1.0, 0.1, 100.0)
+            * projection::look_at(
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(0.0, 0.0, -1.0),
+                Vector3::unit_y(),
+            );
+        let frustum = Frustum::from_view_proj(&view_proj);
+        let aabb_at = |x: f32, z: f32| Aabb {
+            min: Point3::new(x - 0.5, -0.5, z - 0.5),
+            max: Point3::new(x + 0.5, 0.5, z + 0.5),
+        };
+        let sphere_at = |x: f32, z: f32| Sphere {
+            center: Point3::new(x, 0.0, z),
+            radius: 0.5,
+        };
+
+        assert!(frustum.intersects(&aabb_at(0.0, -10.0)));
+        assert!(frustum.intersects_sphere(&sphere_at(0.0, -10.0)));
+        // straddling the left plane
+        assert!(frustum.intersects(&aabb_at(-10.0, -10.0)));
+        assert!(frustum.intersects_sphere(&sphere_at(-10.0, -10.0)));
+
+        // behind the camera
+        assert!(!frustum.intersects(&aabb_at(0.0, 10.0)));
+        assert!(!frustum.intersects_sphere(&sphere_at(0.0, 10.0)));
+        // off to the side
+        assert!(!frustum.intersects(&aabb_at(20.0, -10.0)));
+        assert!(!frustum.intersects_sphere(&sphere_at(20.0, -10.0)));
+        // past the far plane
+        assert!(!frustum.intersects(&aabb_at(0.0, -200.0)));
+        assert!(!frustum.intersects_sphere(&sphere_at(0.0, -200.0)));
+    }
+}
@@ -0,0 +1,298 @@
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    BindGroup, BindGroupEntry, BindGroupLayout, BindingResource, CommandEncoder, Device, Extent3d,
+    FilterMode, ImageCopyTexture, Origin3d, PipelineLayoutDescriptor, Queue, RenderPass, Sampler,
+    SamplerDescriptor, Texture, TextureAspect, TextureDescriptor, TextureDimension, TextureUsages,
+    TextureView, TextureViewDescriptor,
+};
+
+use crate::{
+    instance::InstanceRaw,
+    pipeline::PipelineCache,
+    postprocess::{PostProcessStack, SceneTargets},
+    reflection::ShaderReflection,
+    shader::{ShaderCode, ShaderDefs},
+    texture::OurTexture,
+    uniform::UniformBuffer,
+    vertex::Vertex,
+};
+
+/// The defines which select the glass permutation of the scene's shader, with the glass in bind group `group`
+pub fn glass_defs(defs: &ShaderDefs, group: u32) -> ShaderDefs {
+    defs.clone().flag("GLASS").value("GLASS_GROUP", group)
+}
+
+/// How glass bends the light through it, which can be changed at any time through `Glass::settings_mut`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GlassSettings {
+    /// The index of refraction, 1.5 for window glass and 1.33 for water
+    pub ior: f32,
+    /// How far light goes through the glass before it comes out the other side, in world units.
+    /// The thicker it is, the further what's behind it is shifted
+    pub thickness: f32,
+}
+
+impl Default for GlassSettings {
+    fn default() -> Self {
+        Self {
+            ior: 1.5,
+            thickness: 0.5,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct GlassUniform {
+    ior: f32,
+    thickness: f32,
+    view_scale: [f32; 2],
+}
+
+impl GlassUniform {
+    fn new(settings: &GlassSettings, view_scale: [f32; 2]) -> Self {
+        Self {
+            ior: settings.ior,
+            thickness: settings.thickness,
+            view_scale,
+        }
+    }
+}
+
+/// Draws the instances with some `Instance::transmission` as glass, which the scene's shader leaves out
+/// of the opaque pass. Once that's done the scene is copied, and the glass is drawn over it in a pass
+/// of its own, sampling the copy where the light through it would have come from and tinting it,
+/// with more reflected and less let through at grazing angles.
+///
+/// Only the opaque scene shows through, so glass behind glass and the water behind glass aren't seen,
+/// and glass isn't drawn in the water's or the mirrors' reflections
+pub struct Glass {
+    settings: GlassSettings,
+    uniform: UniformBuffer<GlassUniform>,
+    opaque_scene: OpaqueScene,
+    sampler: Sampler,
+    reflection: ShaderReflection,
+    group: u32,
+    layout: BindGroupLayout,
+    bind_group: BindGroup,
+    /// The scene's shader compiled with `glass_defs`, one pipeline per permutation of the scene's
+    pipeline_cache: PipelineCache,
+}
+
+/// The copy of the opaque scene the glass samples, which can't be the scene's own target while it's drawn into
+struct OpaqueScene {
+    texture: Texture,
+    view: TextureView,
+    width: u32,
+    height: u32,
+}
+
+impl OpaqueScene {
+    fn new(device: &Device, width: u32, height: u32) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Opaque Scene"),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: PostProcessStack::SCENE_FORMAT,
+            usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        Self {
+            texture,
+            view,
+            width,
+            height,
+        }
+    }
+}
+
+impl Glass {
+    /// `code` is the scene's shader, `scene_layouts` are the layouts of its first three bind groups
+    /// (the material, camera and lights), and the glass' own goes in `group` after them.
+    /// `width` and `height` are the size the scene is rendered at
+    #[tracing::instrument(skip_all)]
+    pub fn new(
+        device: &Device,
+        code: ShaderCode,
+        settings: GlassSettings,
+        scene_layouts: [&BindGroupLayout; 3],
+        group: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        let reflection = ShaderReflection::from_code(&code, &glass_defs(&ShaderDefs::new(), group))
+            .context("failed to reflect the glass permutation of the scene's shader")?;
+        let layout =
+            reflection.create_bind_group_layout(device, group, Some("glass_bind_group_layout"));
+        let [material_layout, camera_layout, light_layout] = scene_layouts;
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Glass Pipeline Layout"),
+            bind_group_layouts: &[material_layout, camera_layout, light_layout, &layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline_cache = PipelineCache::new(
+            "Glass Pipeline",
+            code,
+            pipeline_layout,
+            vec![Vertex::desc(), InstanceRaw::desc()],
+            vec![
+                PostProcessStack::SCENE_FORMAT,
+                SceneTargets::VELOCITY_FORMAT,
+            ],
+            Some(OurTexture::DEPTH_FORMAT),
+        );
+
+        let uniform = UniformBuffer::new(
+            device,
+            GlassUniform::new(&settings, [1.0, 1.0]),
+            Some("Glass Buffer"),
+        );
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Opaque Scene Sampler"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+        let opaque_scene = OpaqueScene::new(device, width, height);
+        let bind_group = create_bind_group(
+            device,
+            &reflection,
+            group,
+            &layout,
+            &uniform,
+            &opaque_scene,
+            &sampler,
+        )?;
+
+        Ok(Self {
+            settings,
+            uniform,
+            opaque_scene,
+            sampler,
+            reflection,
+            group,
+            layout,
+            bind_group,
+            pipeline_cache,
+        })
+    }
+
+    pub fn settings(&self) -> &GlassSettings {
+        &self.settings
+    }
+
+    /// Change how the glass bends light, which takes effect from the next `update`
+    pub fn settings_mut(&mut self) -> &mut GlassSettings {
+        &mut self.settings
+    }
+
+    /// Copy the scene at `width` by `height` from now on, the size it's rendered at
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        self.opaque_scene = OpaqueScene::new(device, width, height);
+        self.bind_group = create_bind_group(
+            device,
+            &self.reflection,
+            self.group,
+            &self.layout,
+            &self.uniform,
+            &self.opaque_scene,
+            &self.sampler,
+        )
+        .expect("the glass' bindings don't change size");
+    }
+
+    /// Write the settings, where each view of the scene covers `view_scale` of the target's width and height,
+    /// e.g. `[0.5, 1.0]` when the eyes are side by side
+    pub fn update(&mut self, queue: &Queue, view_scale: [f32; 2]) {
+        self.uniform
+            .set(&GlassUniform::new(&self.settings, view_scale));
+        self.uniform.write(queue);
+    }
+
+    /// Compile the glass permutation of the scene's permutation `defs`, if it hasn't been already
+    pub fn prepare(&mut self, device: &Device, defs: &ShaderDefs) -> Result<()> {
+        self.pipeline_cache
+            .prepare(device, &glass_defs(defs, self.group))
+    }
+
+    /// Swap the scene's shader out for `code`, returning the old one, see `PipelineCache::set_code`
+    pub fn set_code(&mut self, code: ShaderCode) -> ShaderCode {
+        self.pipeline_cache.set_code(code)
+    }
+
+    /// Draw into a scene pass whose targets have `sample_count` samples per pixel from now on,
+    /// which throws away every permutation like `set_code` if that's a change
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        self.pipeline_cache.set_sample_count(sample_count);
+    }
+
+    /// Copy the opaque scene out of `scene`, once it's been drawn and before the glass is
+    pub fn copy_opaque_scene(&self, encoder: &mut CommandEncoder, scene: &Texture) {
+        encoder.copy_texture_to_texture(
+            ImageCopyTexture {
+                texture: scene,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            ImageCopyTexture {
+                texture: &self.opaque_scene.texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            Extent3d {
+                width: self.opaque_scene.width,
+                height: self.opaque_scene.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// Switch `render_pass` to drawing glass with the glass permutation of `defs`, which must have been
+    /// compiled with `prepare`. The scene's own bind groups stay bound, and its plain meshes are drawn
+    /// as they usually are, leaving out everything that isn't glass
+    pub fn bind<'a>(&'a self, render_pass: &mut RenderPass<'a>, defs: &ShaderDefs) {
+        render_pass.set_pipeline(
+            self.pipeline_cache
+                .get(&glass_defs(defs, self.group))
+                .expect("the glass permutation is compiled along with the scene's"),
+        );
+        render_pass.set_bind_group(self.group, &self.bind_group, &[]);
+    }
+}
+
+fn create_bind_group(
+    device: &Device,
+    reflection: &ShaderReflection,
+    group: u32,
+    layout: &BindGroupLayout,
+    uniform: &UniformBuffer<GlassUniform>,
+    opaque_scene: &OpaqueScene,
+    sampler: &Sampler,
+) -> Result<BindGroup> {
+    reflection.create_bind_group(
+        device,
+        group,
+        layout,
+        &[
+            uniform.bind_group_entry(0),
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(&opaque_scene.view),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::Sampler(sampler),
+            },
+        ],
+        Some("glass_bind_group"),
+    )
+}
@@ -0,0 +1,51 @@
+// Glass, matching `GlassUniform`. Only compiled in with `GLASS`, which also defines `GLASS_GROUP`
+// as the bind group the glass' uniform and the copy of the opaque scene are in
+
+#ifdef GLASS
+struct Glass {
+    // index of refraction, how much light bends on its way into the glass
+    ior: f32,
+    // how far through the glass light travels before it comes out again, in world units
+    thickness: f32,
+    // how much of the target each view covers, e.g. half its width for each eye in stereo
+    view_scale: vec2<f32>,
+}
+@group(GLASS_GROUP) @binding(0)
+var<uniform> glass: Glass;
+// The opaque scene, copied out before the glass is drawn over it
+@group(GLASS_GROUP) @binding(1)
+var t_opaque_scene: texture_2d<f32>;
+@group(GLASS_GROUP) @binding(2)
+var s_opaque_scene: sampler;
+
+// How much light is reflected rather than let through, by Schlick's approximation
+fn glass_fresnel(normal: vec3<f32>, view_direction: vec3<f32>) -> f32 {
+    let f0 = pow((glass.ior - 1.0) / (glass.ior + 1.0), 2.0);
+    let cos_theta = max(dot(normal, view_direction), 0.0);
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// GLSL's `refract`, which naga doesn't know in WGSL. Zero when the light is reflected back inside, leaving it unbent
+fn refract_direction(incident: vec3<f32>, normal: vec3<f32>, eta: f32) -> vec3<f32> {
+    let cos_incident = dot(normal, incident);
+    let k = 1.0 - eta * eta * (1.0 - cos_incident * cos_incident);
+    if k < 0.0 {
+        return vec3<f32>(0.0);
+    }
+    return eta * incident - (eta * cos_incident + sqrt(k)) * normal;
+}
+
+// The opaque scene seen through glass at `world_position`, which is at `frag_position` in the target,
+// bent by refraction on its way in
+fn refracted_scene(frag_position: vec4<f32>, world_position: vec3<f32>, normal: vec3<f32>, view_direction: vec3<f32>) -> vec3<f32> {
+    let refracted = refract_direction(-view_direction, normal, 1.0 / glass.ior);
+    // The light comes out where it ends up `thickness` further on, which is only an estimate,
+    // as the far side of the glass isn't known
+    let entry = camera.view_proj * vec4<f32>(world_position, 1.0);
+    let exit = camera.view_proj * vec4<f32>(world_position + refracted * glass.thickness, 1.0);
+    // Texture co-ordinates are half the size of clip space, and go downwards rather than upwards
+    let offset = (exit.xy / exit.w - entry.xy / entry.w) * vec2<f32>(0.5, -0.5) * glass.view_scale;
+    let uv = frag_position.xy / vec2<f32>(textureDimensions(t_opaque_scene)) + offset;
+    return textureSampleLevel(t_opaque_scene, s_opaque_scene, uv, 0.0).rgb;
+}
+#endif
@@ -0,0 +1,65 @@
+use renderdoc::{InputButton, RenderDoc, V110};
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+/// Frame captures for debugging in RenderDoc, triggered with F12.
+/// RenderDoc's own capture keys are turned off, as they don't see key presses on every platform,
+/// and would capture twice on the ones where they do
+pub struct GpuCapture {
+    renderdoc: RenderDoc<V110>,
+    /// The number of captures made so far, for noticing when a new one has been saved
+    captures: u32,
+}
+
+impl GpuCapture {
+    /// Connect to RenderDoc, which only works if the app was launched from RenderDoc
+    pub fn new() -> Option<Self> {
+        match RenderDoc::<V110>::new() {
+            Ok(mut renderdoc) => {
+                renderdoc.set_capture_keys::<InputButton>(&[]);
+                tracing::info!("RenderDoc is attached, press F12 to capture a frame");
+                Some(Self {
+                    captures: renderdoc.get_num_captures(),
+                    renderdoc,
+                })
+            }
+            Err(error) => {
+                tracing::debug!("RenderDoc isn't attached: {error}");
+                None
+            }
+        }
+    }
+
+    /// Capture the next frame which is presented
+    pub fn trigger(&mut self) {
+        self.renderdoc.trigger_capture();
+    }
+
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F12),
+                        ..
+                    },
+                ..
+            } => {
+                self.trigger();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Log where any captures finished since the last call were saved
+    pub fn poll(&mut self) {
+        let captures = self.renderdoc.get_num_captures();
+        for index in self.captures..captures {
+            if let Some((path, _)) = self.renderdoc.get_capture(index) {
+                tracing::info!("Saved RenderDoc capture to {}", path.display());
+            }
+        }
+        self.captures = captures;
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt;
+
+use wgpu::{Adapter, AdapterInfo, Device, DownlevelCapabilities, Features, Limits};
+
+/// What the renderer is running on, for including in bug reports
+#[derive(Clone, Debug)]
+pub struct GpuInfo {
+    /// The adapter's name, backend, driver, etc.
+    pub adapter: AdapterInfo,
+    /// The features enabled on the device, which may be fewer than the adapter supports
+    pub features: Features,
+    /// The limits the device was created with, which may be lower than the adapter supports.
+    /// They're only as high as `limits::desired` asks for, so check these before relying on more
+    pub limits: Limits,
+    /// What the adapter can't do compared to a fully WebGPU compliant one, e.g. on GL
+    pub downlevel: DownlevelCapabilities,
+}
+
+impl GpuInfo {
+    pub fn new(adapter: &Adapter, device: &Device) -> Self {
+        Self {
+            adapter: adapter.get_info(),
+            features: device.features(),
+            limits: device.limits(),
+            downlevel: adapter.get_downlevel_capabilities(),
+        }
+    }
+
+    /// A single line naming the adapter, backend and driver, e.g. for an overlay
+    pub fn summary(&self) -> String {
+        let AdapterInfo {
+            name,
+            device_type,
+            driver,
+            driver_info,
+            backend,
+            ..
+        } = &self.adapter;
+        let mut summary = format!("{name} ({backend:?}, {device_type:?})");
+        for detail in [driver, driver_info] {
+            if !detail.is_empty() {
+                summary += " ";
+                summary += detail;
+            }
+        }
+        summary
+    }
+}
+
+/// The full report, over several lines
+impl fmt::Display for GpuInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let adapter = &self.adapter;
+        writeln!(f, "Adapter: {}", adapter.name)?;
+        writeln!(f, "Backend: {:?}", adapter.backend)?;
+        writeln!(f, "Device type: {:?}", adapter.device_type)?;
+        writeln!(
+            f,
+            "Vendor/device ID: {:#06x}/{:#06x}",
+            adapter.vendor, adapter.device
+        )?;
+        let driver = format!("{} {}", adapter.driver, adapter.driver_info);
+        // not every backend knows which driver it's using
+        let driver = match driver.trim() {
+            "" => "unknown",
+            driver => driver,
+        };
+        writeln!(f, "Driver: {driver}")?;
+        writeln!(f, "Features: {:?}", self.features)?;
+        writeln!(f, "Downlevel flags: {:?}", self.downlevel.flags)?;
+        writeln!(f, "Shader model: {:?}", self.downlevel.shader_model)?;
+        write!(f, "Limits: {:#?}", self.limits)
+    }
+}
@@ -0,0 +1,121 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use wgpu::{
+    Buffer, BufferDescriptor, BufferUsages, CommandEncoder, Device, Maintain, MapMode, QuerySet,
+    QuerySetDescriptor, QueryType, Queue,
+};
+
+/// Times how long the GPU takes over each frame with timestamp queries, which needs
+/// `Capabilities::timestamp_query`. The timestamps are read back a few frames later without waiting for them,
+/// and a frame isn't timed if every query is still waiting to be read
+pub struct GpuTimer {
+    slots: Vec<Slot>,
+    /// Nanoseconds per tick of the timestamps
+    period: f32,
+    /// The slot being written to this frame, between `begin` and `end`
+    current: Option<usize>,
+}
+
+struct Slot {
+    query_set: QuerySet,
+    /// Where the queries are resolved to be read back
+    read_buffer: Buffer,
+    /// The frame timed by the queries, if any are waiting to be read
+    frame: Option<u64>,
+    /// Whether `map_async` has been called for the frame's timestamps
+    mapping: bool,
+    /// Set by `map_async`'s callback once the buffer can be read
+    mapped: Arc<AtomicBool>,
+}
+
+impl GpuTimer {
+    /// The number of frames which can be waiting to be read back at once
+    const SLOTS: usize = 4;
+    /// The start and end of the frame
+    const QUERIES: u32 = 2;
+    const BUFFER_SIZE: u64 = Self::QUERIES as u64 * wgpu::QUERY_SIZE as u64;
+
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        let slots = (0..Self::SLOTS)
+            .map(|_| Slot {
+                query_set: device.create_query_set(&QuerySetDescriptor {
+                    label: Some("GPU Timer Queries"),
+                    ty: QueryType::Timestamp,
+                    count: Self::QUERIES,
+                }),
+                read_buffer: device.create_buffer(&BufferDescriptor {
+                    label: Some("GPU Timer Read Buffer"),
+                    size: Self::BUFFER_SIZE,
+                    usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                }),
+                frame: None,
+                mapping: false,
+                mapped: Arc::new(AtomicBool::new(false)),
+            })
+            .collect();
+        Self {
+            slots,
+            period: queue.get_timestamp_period(),
+            current: None,
+        }
+    }
+
+    /// Start timing `frame`, before anything else is recorded into `encoder`
+    pub fn begin(&mut self, encoder: &mut CommandEncoder, frame: u64) {
+        self.current = self.slots.iter().position(|slot| slot.frame.is_none());
+        if let Some(index) = self.current {
+            let slot = &mut self.slots[index];
+            slot.frame = Some(frame);
+            encoder.write_timestamp(&slot.query_set, 0);
+        }
+    }
+
+    /// Stop timing the frame, after everything else has been recorded into `encoder`
+    pub fn end(&mut self, encoder: &mut CommandEncoder) {
+        let Some(index) = self.current.take() else {
+            return;
+        };
+        let slot = &self.slots[index];
+        encoder.write_timestamp(&slot.query_set, 1);
+        encoder.resolve_query_set(&slot.query_set, 0..Self::QUERIES, &slot.read_buffer, 0);
+    }
+
+    /// Start reading back the frames timed since the last call, and return the GPU time of each one
+    /// which has been read, in milliseconds. This has to be called after the frame has been submitted
+    pub fn read(&mut self, device: &Device) -> Vec<(u64, f64)> {
+        for slot in &mut self.slots {
+            if slot.frame.is_none() || slot.mapping {
+                continue;
+            }
+            slot.mapping = true;
+            let mapped = slot.mapped.clone();
+            slot.read_buffer
+                .slice(..)
+                .map_async(MapMode::Read, move |result| match result {
+                    Ok(()) => mapped.store(true, Ordering::Release),
+                    Err(error) => tracing::error!("Failed to map the GPU timer's buffer: {error}"),
+                });
+        }
+        device.poll(Maintain::Poll);
+
+        let mut times = Vec::new();
+        for slot in &mut self.slots {
+            if !slot.mapped.swap(false, Ordering::Acquire) {
+                continue;
+            }
+            let [start, end]: [u64; 2] =
+                bytemuck::pod_read_unaligned(&slot.read_buffer.slice(..).get_mapped_range());
+            slot.read_buffer.unmap();
+            slot.mapping = false;
+            let frame = slot.frame.take().expect("mapped slots have timed a frame");
+            let ticks = end.saturating_sub(start);
+            times.push((frame, ticks as f64 * self.period as f64 / 1e6));
+        }
+        times.sort_by_key(|&(frame, _)| frame);
+        times
+    }
+}
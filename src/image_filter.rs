@@ -0,0 +1,495 @@
+//! Filtering textures on the GPU with compute shaders, e.g. to soften, sharpen or desaturate a texture
+//! after it's been loaded. Each pass of `image_filter.wgsl` samples one texture and writes the next
+//! through a storage texture binding, which can only be `Rgba8Unorm` or `Rgba16Float` here, as sRGB formats
+//! can't be bound as storage. Results for sRGB textures are encoded by the shader and copied over as bytes.
+//! Without `Capabilities::storage_textures` each pass writes a storage buffer instead, which is copied into the texture
+
+use std::{num::NonZeroU32, str::FromStr};
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    AddressMode, BindGroupEntry, BindGroupLayout, BindingResource, Buffer, BufferAddress,
+    BufferDescriptor, BufferUsages, CommandEncoder, CommandEncoderDescriptor,
+    ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Device, Extent3d,
+    FilterMode, ImageCopyBuffer, ImageDataLayout, PipelineLayoutDescriptor, Queue,
+    SamplerDescriptor, ShaderModuleDescriptor, ShaderSource, Texture, TextureDescriptor,
+    TextureDimension, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor,
+};
+
+use crate::{
+    capabilities::Capabilities,
+    reflection::ShaderReflection,
+    shader::{preprocess, ShaderDefs, ShaderLibrary},
+    texture::OurTexture,
+    uniform::UniformBuffer,
+};
+
+/// What `ImageFilters` can do to a texture
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFilter {
+    /// A Gaussian blur reaching `radius` texels either side, done in two passes, across then down
+    Blur { radius: u32 },
+    /// Exaggerates the difference between each texel and its neighbours by `amount`,
+    /// where 0 leaves the texture as it was
+    Sharpen { amount: f32 },
+    /// Every texel's luminance, keeping its alpha
+    Grayscale,
+}
+
+impl ImageFilter {
+    /// Larger blurs are better done on a smaller copy of the texture
+    pub const MAX_BLUR_RADIUS: u32 = 64;
+}
+
+/// Parses `blur[:radius]`, `sharpen[:amount]` or `grayscale`, e.g. `blur:4` or `sharpen:0.5`
+impl FromStr for ImageFilter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, parameter) = match s.split_once(':') {
+            Some((kind, parameter)) => (kind, Some(parameter.trim())),
+            None => (s, None),
+        };
+        Ok(match kind {
+            "blur" => {
+                let radius = parameter
+                    .map(|radius| {
+                        radius
+                            .parse::<u32>()
+                            .with_context(|| format!("invalid blur radius `{radius}`"))
+                    })
+                    .transpose()?
+                    .unwrap_or(2);
+                ensure!(
+                    (1..=Self::MAX_BLUR_RADIUS).contains(&radius),
+                    "a blur's radius has to be from 1 to {} texels",
+                    Self::MAX_BLUR_RADIUS
+                );
+                Self::Blur { radius }
+            }
+            "sharpen" => {
+                let amount = parameter
+                    .map(|amount| {
+                        amount
+                            .parse::<f32>()
+                            .with_context(|| format!("invalid sharpening amount `{amount}`"))
+                    })
+                    .transpose()?
+                    .unwrap_or(1.0);
+                ensure!(
+                    amount.is_finite() && amount >= 0.0,
+                    "sharpening needs an amount of at least 0"
+                );
+                Self::Sharpen { amount }
+            }
+            "grayscale" => {
+                ensure!(parameter.is_none(), "`grayscale` doesn't take a parameter");
+                Self::Grayscale
+            }
+            _ => bail!("unknown image filter `{kind}`, expected `blur`, `sharpen` or `grayscale`"),
+        })
+    }
+}
+
+/// The layout `image_filter.wgsl` expects
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+struct ImageFilterUniform {
+    kind: u32,
+    radius: i32,
+    sigma: f32,
+    amount: f32,
+    srgb: u32,
+    row_words: u32,
+    _padding: [u32; 2],
+}
+
+impl ImageFilterUniform {
+    const BLUR_X: u32 = 0;
+    const BLUR_Y: u32 = 1;
+    const SHARPEN: u32 = 2;
+    const GRAYSCALE: u32 = 3;
+}
+
+/// `image_filter.wgsl` specialised to write one storage format
+struct Variant {
+    /// What it writes, `Rgba8Unorm` or `Rgba16Float`
+    format: TextureFormat,
+    reflection: ShaderReflection,
+    layout: BindGroupLayout,
+    pipeline: ComputePipeline,
+}
+
+impl Variant {
+    fn new(
+        device: &Device,
+        library: &ShaderLibrary,
+        format: TextureFormat,
+        storage_textures: bool,
+    ) -> Result<Self> {
+        let name = "image_filter.wgsl";
+        let mut defs = ShaderDefs::new().value(
+            "OUTPUT_FORMAT",
+            match format {
+                TextureFormat::Rgba8Unorm => "rgba8unorm",
+                TextureFormat::Rgba16Float => "rgba16float",
+                _ => bail!("image filters can't write {format:?}"),
+            },
+        );
+        defs.set("STORAGE_TEXTURES", storage_textures);
+        defs.set("FLOAT_OUTPUT", format == TextureFormat::Rgba16Float);
+        let source = preprocess(&library.resolve(name)?, &defs)?;
+        let reflection = ShaderReflection::from_code(&source.clone().into(), &defs)
+            .with_context(|| format!("failed to reflect {name}"))?;
+        let layout = reflection.create_bind_group_layout(device, 0, Some(name));
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(name),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some(name),
+            layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some(name),
+                bind_group_layouts: &[&layout],
+                push_constant_ranges: &[],
+            })),
+            module: &shader,
+            entry_point: "cs_main",
+        });
+        Ok(Self {
+            format,
+            reflection,
+            layout,
+            pipeline,
+        })
+    }
+}
+
+/// Applies `ImageFilter`s to `OurTexture`s in `Rgba8Unorm`, `Rgba8UnormSrgb` or `Rgba16Float`,
+/// which are filtered as linear colours
+pub struct ImageFilters {
+    /// For 8 bit textures, whether they're sRGB or not
+    unorm: Variant,
+    /// For HDR textures, and the first pass of a blur, which keeps the precision for the second
+    float: Variant,
+    /// Whether passes write storage textures, otherwise buffers which are copied into textures,
+    /// see `Capabilities::storage_textures`
+    storage_textures: bool,
+}
+
+impl ImageFilters {
+    /// Must match `@workgroup_size` in `image_filter.wgsl`
+    const WORKGROUP_SIZE: u32 = 8;
+
+    /// Compiles `image_filter.wgsl`, which fails without compute shaders
+    pub fn new(
+        device: &Device,
+        library: &ShaderLibrary,
+        capabilities: &Capabilities,
+    ) -> Result<Self> {
+        ensure!(
+            capabilities.compute_shaders,
+            "image filters need compute shaders, which this adapter doesn't have"
+        );
+        Ok(Self {
+            unorm: Variant::new(
+                device,
+                library,
+                TextureFormat::Rgba8Unorm,
+                capabilities.storage_textures,
+            )?,
+            float: Variant::new(
+                device,
+                library,
+                TextureFormat::Rgba16Float,
+                capabilities.storage_textures,
+            )?,
+            storage_textures: capabilities.storage_textures,
+        })
+    }
+
+    /// Filter `texture`, replacing what it held, which needs it to have been created with
+    /// `TextureUsages::COPY_DST` like images and procedural textures are
+    #[tracing::instrument(skip(self, device, queue, texture))]
+    pub fn apply(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        texture: &OurTexture,
+        filter: ImageFilter,
+    ) -> Result<()> {
+        self.filter(device, queue, texture, &texture.texture, filter)
+    }
+
+    /// Filter `texture` into a new texture of the same size and format, leaving `texture` as it was
+    #[tracing::instrument(skip(self, device, queue, texture))]
+    pub fn apply_to_new(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        texture: &OurTexture,
+        filter: ImageFilter,
+        label: &str,
+    ) -> Result<OurTexture> {
+        // which can be filtered again in place, or copied out of, e.g. to read it back
+        let filtered = device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size: texture.size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: texture.format,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::COPY_SRC,
+        });
+        self.filter(device, queue, texture, &filtered, filter)?;
+
+        let view = filtered.create_view(&TextureViewDescriptor::default());
+        // The same as an image's
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+        Ok(OurTexture {
+            texture: filtered,
+            view,
+            sampler,
+            size: texture.size,
+            format: texture.format,
+        })
+    }
+
+    /// Filter `source` into `destination`, which is the same size and format, and may be `source`'s texture
+    fn filter(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        source: &OurTexture,
+        destination: &Texture,
+        filter: ImageFilter,
+    ) -> Result<()> {
+        let format = source.format;
+        let variant = match format {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => &self.unorm,
+            TextureFormat::Rgba16Float => &self.float,
+            _ => bail!("textures in {format:?} can't be filtered"),
+        };
+        ensure!(
+            source.size.depth_or_array_layers == 1,
+            "only 2D textures can be filtered"
+        );
+        let srgb = (format == TextureFormat::Rgba8UnormSrgb).into();
+        let output =
+            self.output_texture(device, source.size, variant.format, "Image Filter Output");
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Image Filter Encoder"),
+        });
+        match filter {
+            ImageFilter::Blur { radius } => {
+                let uniform = ImageFilterUniform {
+                    radius: radius as i32,
+                    // so that the blur reaches 3 standard deviations, past which the weights hardly count
+                    sigma: radius as f32 / 3.0,
+                    ..Default::default()
+                };
+                let across = self.output_texture(
+                    device,
+                    source.size,
+                    self.float.format,
+                    "Image Filter Blur",
+                );
+                self.dispatch(
+                    device,
+                    &mut encoder,
+                    &self.float,
+                    ImageFilterUniform {
+                        kind: ImageFilterUniform::BLUR_X,
+                        ..uniform
+                    },
+                    &source.view,
+                    &across,
+                    source.size,
+                )?;
+                self.dispatch(
+                    device,
+                    &mut encoder,
+                    variant,
+                    ImageFilterUniform {
+                        kind: ImageFilterUniform::BLUR_Y,
+                        srgb,
+                        ..uniform
+                    },
+                    &across.create_view(&TextureViewDescriptor::default()),
+                    &output,
+                    source.size,
+                )?;
+            }
+            ImageFilter::Sharpen { amount } => self.dispatch(
+                device,
+                &mut encoder,
+                variant,
+                ImageFilterUniform {
+                    kind: ImageFilterUniform::SHARPEN,
+                    amount,
+                    srgb,
+                    ..Default::default()
+                },
+                &source.view,
+                &output,
+                source.size,
+            )?,
+            ImageFilter::Grayscale => self.dispatch(
+                device,
+                &mut encoder,
+                variant,
+                ImageFilterUniform {
+                    kind: ImageFilterUniform::GRAYSCALE,
+                    srgb,
+                    ..Default::default()
+                },
+                &source.view,
+                &output,
+                source.size,
+            )?,
+        }
+
+        if variant.format == format {
+            encoder.copy_texture_to_texture(
+                output.as_image_copy(),
+                destination.as_image_copy(),
+                source.size,
+            );
+        } else {
+            // sRGB textures can't be copied into from a texture in another format,
+            // so the bytes the shader encoded go through a buffer instead
+            let (buffer, layout) = texel_buffer(device, source.size, variant.format);
+            let copy = ImageCopyBuffer {
+                buffer: &buffer,
+                layout,
+            };
+            encoder.copy_texture_to_buffer(output.as_image_copy(), copy.clone(), source.size);
+            encoder.copy_buffer_to_texture(copy, destination.as_image_copy(), source.size);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+        Ok(())
+    }
+
+    /// Record one pass of `image_filter.wgsl` over every texel of `input`, written to `output`,
+    /// which is in `variant`'s format and from `output_texture`
+    #[allow(clippy::too_many_arguments)]
+    fn dispatch(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        variant: &Variant,
+        mut uniform: ImageFilterUniform,
+        input: &TextureView,
+        output: &Texture,
+        size: Extent3d,
+    ) -> Result<()> {
+        let output_view = output.create_view(&TextureViewDescriptor::default());
+        let texels = (!self.storage_textures).then(|| texel_buffer(device, size, variant.format));
+        let output_resource = match &texels {
+            None => BindingResource::TextureView(&output_view),
+            Some((texels, layout)) => {
+                uniform.row_words = layout.bytes_per_row.map_or(0, NonZeroU32::get) / 4;
+                texels.as_entire_binding()
+            }
+        };
+        let uniform = UniformBuffer::new(device, uniform, Some("Image Filter Settings"));
+        let bind_group = variant.reflection.create_bind_group(
+            device,
+            0,
+            &variant.layout,
+            &[
+                uniform.bind_group_entry(0),
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(input),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: output_resource,
+                },
+            ],
+            Some("Image Filter"),
+        )?;
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("Image Filter Pass"),
+            });
+            compute_pass.set_pipeline(&variant.pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                size.width.div_ceil(Self::WORKGROUP_SIZE),
+                size.height.div_ceil(Self::WORKGROUP_SIZE),
+                1,
+            );
+        }
+        if let Some((texels, layout)) = &texels {
+            encoder.copy_buffer_to_texture(
+                ImageCopyBuffer {
+                    buffer: texels,
+                    layout: *layout,
+                },
+                output.as_image_copy(),
+                size,
+            );
+        }
+        Ok(())
+    }
+
+    /// A texture for a pass to write, which can be read by the next pass or copied out of
+    fn output_texture(
+        &self,
+        device: &Device,
+        size: Extent3d,
+        format: TextureFormat,
+        label: &str,
+    ) -> Texture {
+        let written = if self.storage_textures {
+            TextureUsages::STORAGE_BINDING
+        } else {
+            TextureUsages::COPY_DST
+        };
+        device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: written | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC,
+        })
+    }
+}
+
+/// A buffer to copy a texture of `size` in `format` through, row by row, and how its rows are laid out
+fn texel_buffer(
+    device: &Device,
+    size: Extent3d,
+    format: TextureFormat,
+) -> (Buffer, ImageDataLayout) {
+    let bytes_per_row = (format.describe().block_size as u32 * size.width)
+        .next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("Image Filter Texels"),
+        size: (bytes_per_row * size.height) as BufferAddress,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let layout = ImageDataLayout {
+        offset: 0,
+        bytes_per_row: NonZeroU32::new(bytes_per_row),
+        rows_per_image: NonZeroU32::new(size.height),
+    };
+    (buffer, layout)
+}
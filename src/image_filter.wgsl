@@ -0,0 +1,112 @@
+// One pass of an image filter, one invocation per texel, reading `source` and writing `destination`.
+// Filtering happens on linear colours, which sRGB textures are converted to when loaded.
+// STORAGE_TEXTURES writes a storage texture, otherwise a buffer, as they aren't supported on every backend
+
+#include "color.wgsl"
+
+struct ImageFilter {
+    // 0 for blurring along x, 1 for blurring along y, 2 for sharpening and 3 for grayscale
+    kind: u32,
+    // how many texels either side the blur reaches
+    radius: i32,
+    // the standard deviation of the blur's Gaussian, in texels
+    sigma: f32,
+    // how much sharpening exaggerates the difference between a texel and its neighbours
+    amount: f32,
+    // whether to encode the result as sRGB, for copying into an sRGB texture
+    srgb: u32,
+    // how far apart the rows of `destination` are, in words, when it's a buffer
+    row_words: u32,
+    _padding0: u32,
+    _padding1: u32,
+}
+@group(0) @binding(0)
+var<uniform> settings: ImageFilter;
+@group(0) @binding(1)
+var source: texture_2d<f32>;
+#ifdef STORAGE_TEXTURES
+// OUTPUT_FORMAT is substituted with the storage format, `rgba8unorm` or `rgba16float`
+@group(0) @binding(2)
+var destination: texture_storage_2d<OUTPUT_FORMAT, write>;
+#else
+// Row by row, packed as RGBA8 or as 2 words of half floats per texel for FLOAT_OUTPUT,
+// which is copied into the texture afterwards
+@group(0) @binding(2)
+var<storage, read_write> destination: array<u32>;
+#endif
+
+fn store(position: vec2<i32>, color: vec4<f32>) {
+#ifdef STORAGE_TEXTURES
+    textureStore(destination, position, color);
+#else
+    let row = u32(position.y) * settings.row_words;
+#ifdef FLOAT_OUTPUT
+    destination[row + 2u * u32(position.x)] = pack2x16float(color.rg);
+    destination[row + 2u * u32(position.x) + 1u] = pack2x16float(color.ba);
+#else
+    destination[row + u32(position.x)] = pack4x8unorm(color);
+#endif
+#endif
+}
+
+// The texel at `position`, clamped to the edges
+fn texel(position: vec2<i32>) -> vec4<f32> {
+    let size = vec2<i32>(textureDimensions(source));
+    return textureLoad(source, clamp(position, vec2<i32>(0, 0), size - vec2<i32>(1, 1)), 0);
+}
+
+fn blur(position: vec2<i32>, direction: vec2<i32>) -> vec4<f32> {
+    var sum = vec4<f32>(0.0);
+    var weights = 0.0;
+    for (var offset = -settings.radius; offset <= settings.radius; offset = offset + 1) {
+        let x = f32(offset);
+        let weight = exp(-x * x / (2.0 * settings.sigma * settings.sigma));
+        sum = sum + texel(position + direction * offset) * weight;
+        weights = weights + weight;
+    }
+    return sum / weights;
+}
+
+// An unsharp mask, taking away a blurred copy made from the 4 neighbours
+fn sharpen(position: vec2<i32>) -> vec4<f32> {
+    let center = texel(position);
+    let neighbours = texel(position + vec2<i32>(1, 0)) + texel(position - vec2<i32>(1, 0))
+        + texel(position + vec2<i32>(0, 1)) + texel(position - vec2<i32>(0, 1));
+    let sharpened = center + (center - neighbours * 0.25) * settings.amount;
+    return vec4<f32>(max(sharpened.rgb, vec3<f32>(0.0)), center.a);
+}
+
+fn grayscale(position: vec2<i32>) -> vec4<f32> {
+    let color = texel(position);
+    // Rec. 709 luminance, which is what the eye sees as brightness
+    let luminance = dot(color.rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+    return vec4<f32>(vec3<f32>(luminance), color.a);
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(source);
+    if (id.x >= u32(size.x) || id.y >= u32(size.y)) {
+        return;
+    }
+    let position = vec2<i32>(id.xy);
+    var color: vec4<f32>;
+    switch (settings.kind) {
+        case 0u: {
+            color = blur(position, vec2<i32>(1, 0));
+        }
+        case 1u: {
+            color = blur(position, vec2<i32>(0, 1));
+        }
+        case 2u: {
+            color = sharpen(position);
+        }
+        default: {
+            color = grayscale(position);
+        }
+    }
+    if (settings.srgb != 0u) {
+        color = vec4<f32>(linear_to_srgb(clamp(color.rgb, vec3<f32>(0.0), vec3<f32>(1.0))), color.a);
+    }
+    store(position, color);
+}
@@ -0,0 +1,104 @@
+use std::collections::HashSet;
+
+use winit::event::{
+    ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
+
+/// The state of the keyboard and mouse, gathered from every window event so that an `App` can
+/// ask what's held down when it updates rather than tracking it all itself
+#[derive(Clone, Debug, Default)]
+pub struct Input {
+    keys: HashSet<VirtualKeyCode>,
+    /// Keys which went down since the last frame
+    pressed_keys: HashSet<VirtualKeyCode>,
+    buttons: HashSet<MouseButton>,
+    /// In physical pixels from the top left of the window, `None` while it's outside
+    cursor: Option<[f32; 2]>,
+    /// Lines scrolled since the last frame, positive away from the user
+    scroll: f32,
+}
+
+impl Input {
+    /// Roughly how many pixels a trackpad scrolls for each line a mouse wheel would
+    const PIXELS_PER_LINE: f32 = 20.0;
+
+    /// Record what `event` changes, which is watched rather than consumed
+    pub fn process_events(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(keycode),
+                        ..
+                    },
+                ..
+            } => match state {
+                ElementState::Pressed => {
+                    // ignoring key repeats
+                    if self.keys.insert(*keycode) {
+                        self.pressed_keys.insert(*keycode);
+                    }
+                }
+                ElementState::Released => {
+                    self.keys.remove(keycode);
+                }
+            },
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => {
+                    self.buttons.insert(*button);
+                }
+                ElementState::Released => {
+                    self.buttons.remove(button);
+                }
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = Some([position.x as f32, position.y as f32]);
+            }
+            WindowEvent::CursorLeft { .. } => self.cursor = None,
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => {
+                        position.y as f32 / Self::PIXELS_PER_LINE
+                    }
+                }
+            }
+            // Nothing's held down in a window which isn't focused, and the releases won't arrive
+            WindowEvent::Focused(false) => {
+                self.keys.clear();
+                self.buttons.clear();
+            }
+            _ => (),
+        }
+    }
+
+    /// Forget what only lasts a frame, once the app has seen it
+    pub fn end_frame(&mut self) {
+        self.pressed_keys.clear();
+        self.scroll = 0.0;
+    }
+
+    pub fn is_key_down(&self, key: VirtualKeyCode) -> bool {
+        self.keys.contains(&key)
+    }
+
+    /// Whether `key` went down since the last frame
+    pub fn was_key_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.pressed_keys.contains(&key)
+    }
+
+    pub fn is_button_down(&self, button: MouseButton) -> bool {
+        self.buttons.contains(&button)
+    }
+
+    /// Where the cursor is in physical pixels from the top left of the window, if it's over it
+    pub fn cursor_position(&self) -> Option<[f32; 2]> {
+        self.cursor
+    }
+
+    /// How many lines the mouse wheel has scrolled since the last frame, positive away from the user
+    pub fn scroll(&self) -> f32 {
+        self.scroll
+    }
+}
@@ -0,0 +1,143 @@
+use crate::{
+    math::{Matrix, Matrix4},
+    transform::Transform,
+};
+use bytemuck::{Pod, Zeroable};
+use wgpu::{BufferAddress, VertexAttribute, VertexBufferLayout, VertexFormat, VertexStepMode};
+
+/// One copy of a mesh, drawn with the rest of its copies in a single instanced draw call
+#[derive(Clone, Debug)]
+pub struct Instance {
+    pub transform: Transform,
+    /// Multiplied with the mesh's texture, in linear RGB
+    pub tint: [f32; 3],
+    /// From 0.0 (mirror-like) to 1.0 (completely matte)
+    pub roughness: f32,
+    /// From 0.0 (e.g. plastic or wood) to 1.0 (metal), metals tint their reflections with their colour
+    pub metallic: f32,
+    /// Light given off by the surface itself whatever lights it, in linear RGB,
+    /// multiplied with the mesh's emissive texture. Anything brighter than 1.0 is picked up by bloom
+    pub emissive: [f32; 3],
+    /// How far the mesh's vertices move out along their normals where the displacement texture is white,
+    /// in the mesh's own units. 0.0 for meshes with hard edges, which would split apart
+    pub displacement: f32,
+    /// From 0.0 (opaque) to 1.0 (clear glass), how much of the scene behind shows through,
+    /// refracted and tinted, see `Glass`. Ignored on skinned and morphed meshes
+    pub transmission: f32,
+    /// Which of the scene's textures it's painted with, by its index in `MaterialTextures`,
+    /// where 0 is the scene's own. Indices past the last texture are painted with the scene's own
+    pub texture: u32,
+}
+
+impl Instance {
+    /// For an instance which hasn't moved since last frame
+    pub fn to_raw(&self) -> InstanceRaw {
+        self.to_raw_moved_from(&self.transform.to_matrix())
+    }
+
+    /// For an instance whose model matrix was `previous` last frame, which its motion vectors are worked out from
+    pub fn to_raw_moved_from(&self, previous: &Matrix4<f32>) -> InstanceRaw {
+        // the rows, leaving out the last
+        let previous = previous.transpose();
+        InstanceRaw {
+            model: self.transform.to_matrix().into(),
+            tint: self.tint,
+            material: [
+                self.roughness,
+                self.metallic,
+                self.displacement,
+                self.transmission,
+            ],
+            emissive: self.emissive,
+            texture: self.texture,
+            previous_model: [previous.x.into(), previous.y.into(), previous.z.into()],
+        }
+    }
+}
+
+/// The layout of `Instance` in the instance buffer,
+/// as shaders can't take quaternions as vertex attributes
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct InstanceRaw {
+    model: [[f32; 4]; 4],
+    tint: [f32; 3],
+    /// Roughness, metallic, displacement and transmission
+    material: [f32; 4],
+    emissive: [f32; 3],
+    texture: u32,
+    /// Last frame's `model`, as its first 3 rows, since the last is always (0, 0, 0, 1).
+    /// The normal matrix is worked out in the shader to leave room for it within 16 vertex attributes
+    previous_model: [[f32; 4]; 3],
+}
+
+impl InstanceRaw {
+    const ATTRIBUTES: [VertexAttribute; 11] = [
+        // A mat4 takes up 4 vertex slots, as each slot can hold at most a vec4.
+        // Start at 5 to leave room for more per-vertex attributes
+        VertexAttribute {
+            offset: 0,
+            shader_location: 5,
+            format: VertexFormat::Float32x4,
+        },
+        VertexAttribute {
+            offset: std::mem::size_of::<[f32; 4]>() as BufferAddress,
+            shader_location: 6,
+            format: VertexFormat::Float32x4,
+        },
+        VertexAttribute {
+            offset: std::mem::size_of::<[f32; 8]>() as BufferAddress,
+            shader_location: 7,
+            format: VertexFormat::Float32x4,
+        },
+        VertexAttribute {
+            offset: std::mem::size_of::<[f32; 12]>() as BufferAddress,
+            shader_location: 8,
+            format: VertexFormat::Float32x4,
+        },
+        VertexAttribute {
+            offset: std::mem::size_of::<[f32; 16]>() as BufferAddress,
+            shader_location: 9,
+            format: VertexFormat::Float32x3,
+        },
+        VertexAttribute {
+            offset: std::mem::size_of::<[f32; 19]>() as BufferAddress,
+            shader_location: 10,
+            format: VertexFormat::Float32x4,
+        },
+        VertexAttribute {
+            offset: std::mem::size_of::<[f32; 23]>() as BufferAddress,
+            shader_location: 11,
+            format: VertexFormat::Float32x3,
+        },
+        VertexAttribute {
+            offset: std::mem::size_of::<[f32; 26]>() as BufferAddress,
+            shader_location: 12,
+            format: VertexFormat::Uint32,
+        },
+        VertexAttribute {
+            offset: std::mem::size_of::<[f32; 27]>() as BufferAddress,
+            shader_location: 13,
+            format: VertexFormat::Float32x4,
+        },
+        VertexAttribute {
+            offset: std::mem::size_of::<[f32; 31]>() as BufferAddress,
+            shader_location: 14,
+            format: VertexFormat::Float32x4,
+        },
+        VertexAttribute {
+            offset: std::mem::size_of::<[f32; 35]>() as BufferAddress,
+            shader_location: 15,
+            format: VertexFormat::Float32x4,
+        },
+    ];
+
+    pub fn desc<'a>() -> VertexBufferLayout<'a> {
+        VertexBufferLayout {
+            array_stride: std::mem::size_of::<InstanceRaw>() as BufferAddress,
+            // the shader only moves on to the next instance once it's done with all of the vertices
+            step_mode: VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
@@ -0,0 +1,48 @@
+// Per-instance vertex attributes, matching `InstanceRaw`
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) tint: vec3<f32>,
+    // roughness, metallic, displacement and transmission
+    @location(10) material: vec4<f32>,
+    @location(11) emissive: vec3<f32>,
+    // which of the material's textures it's painted with
+    @location(12) texture: u32,
+    // last frame's model matrix, for motion vectors, as its rows rather than its columns
+    // as the last row is always (0, 0, 0, 1)
+    @location(13) previous_model_row_0: vec4<f32>,
+    @location(14) previous_model_row_1: vec4<f32>,
+    @location(15) previous_model_row_2: vec4<f32>,
+}
+
+fn instance_model_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+}
+
+fn instance_previous_model_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    return transpose(mat4x4<f32>(
+        instance.previous_model_row_0,
+        instance.previous_model_row_1,
+        instance.previous_model_row_2,
+        vec4<f32>(0.0, 0.0, 0.0, 1.0),
+    ));
+}
+
+// The inverse transpose of the model matrix, which transforms normals as they don't scale the same way
+// as positions. Worked out here rather than passed in, to leave room for the previous model matrix
+fn instance_normal_matrix(instance: InstanceInput) -> mat3x3<f32> {
+    let x = instance.model_matrix_0.xyz;
+    let y = instance.model_matrix_1.xyz;
+    let z = instance.model_matrix_2.xyz;
+    // the cofactor matrix, divided by the determinant
+    let cofactors = mat3x3<f32>(cross(y, z), cross(z, x), cross(x, y));
+    return cofactors * (1.0 / dot(x, cross(y, z)));
+}
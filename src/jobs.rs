@@ -0,0 +1,72 @@
+//! A small job system for spreading work which doesn't depend on itself over threads,
+//! used to record the frame's passes into their own command encoders in parallel, which wgpu allows
+//! as encoders are `Send` and everything they draw with is `Sync`
+
+/// One piece of work, borrowing whatever it needs for as long as `'a`
+pub type Job<'a, T> = Box<dyn FnOnce() -> T + Send + 'a>;
+
+/// Runs batches of jobs over a number of threads, counting the one calling `run`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JobSystem {
+    threads: usize,
+}
+
+impl JobSystem {
+    /// At least 1, which runs every job on the calling thread
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+        }
+    }
+
+    /// As many threads as the machine can run at once
+    pub fn available() -> Self {
+        Self::new(std::thread::available_parallelism().map_or(1, usize::from))
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Run every job and return what each one returned, in the same order as `jobs`.
+    /// They're split into runs of neighbouring jobs, one for each thread, with the first run
+    /// on the calling thread. The other threads are scoped to the batch, so only batches which
+    /// take longer than starting a thread are worth spreading out.
+    /// A panic in any job is carried on with on the calling thread once the rest are done
+    pub fn run<'a, T: Send>(&self, jobs: Vec<Job<'a, T>>) -> Vec<T> {
+        puffin::profile_function!();
+        if self.threads == 1 || jobs.len() <= 1 {
+            return jobs.into_iter().map(|job| job()).collect();
+        }
+        let per_thread = jobs.len().div_ceil(self.threads);
+        let mut runs = Vec::new();
+        let mut jobs = jobs.into_iter();
+        loop {
+            let run = jobs.by_ref().take(per_thread).collect::<Vec<_>>();
+            if run.is_empty() {
+                break;
+            }
+            runs.push(run);
+        }
+        let mut runs = runs.into_iter();
+        let first = runs.next().unwrap_or_default();
+        std::thread::scope(|scope| {
+            let spawned = runs
+                .map(|run| {
+                    scope.spawn(move || {
+                        puffin::profile_scope!("jobs");
+                        run.into_iter().map(|job| job()).collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            let mut results = first.into_iter().map(|job| job()).collect::<Vec<_>>();
+            for handle in spawned {
+                match handle.join() {
+                    Ok(run) => results.extend(run),
+                    Err(panic) => std::panic::resume_unwind(panic),
+                }
+            }
+            results
+        })
+    }
+}
@@ -1,63 +1,365 @@
+use std::{path::PathBuf, time::Instant};
+
+use anyhow::Context;
+
+use app::{App, Demo};
+use benchmark::Benchmark;
+use config::Config;
+use input::Input;
+use logging::LogConfig;
+use metrics::Metrics;
+use settings::{Settings, SettingsFile};
 use state::State;
-use wgpu::SurfaceError;
+use user_event::{EventProxy, UserEvent};
 use winit::{
     event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    event_loop::{ControlFlow, EventLoopBuilder},
 };
 
+pub mod app;
+pub mod assets;
+pub mod benchmark;
+pub mod buffer_pool;
 pub mod camera;
+pub mod camera_collision;
+pub mod capabilities;
+pub mod clock;
+pub mod compute;
+pub mod config;
+pub mod debug_draw;
+pub mod debug_view;
+pub mod decode;
+pub mod displacement;
+pub mod dynamic_resolution;
+pub mod environment;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fog;
+pub mod fragment_stats;
+pub mod frame_stream;
+pub mod fullscreen;
+pub mod geometry;
+pub mod glass;
+#[cfg(feature = "renderdoc")]
+pub mod gpu_capture;
+pub mod gpu_info;
+pub mod gpu_timer;
+pub mod image_filter;
+pub mod input;
+pub mod instance;
+pub mod jobs;
+pub mod light;
+pub mod limits;
+#[cfg(feature = "ui")]
+pub mod log_console;
+pub mod logging;
+pub mod marquee;
+pub mod material_textures;
+pub mod math;
+pub mod mesh;
+pub mod metrics;
+pub mod mirror;
+pub mod morph;
+pub mod normals;
+pub mod offscreen;
+pub mod outline;
+pub mod parallax;
+#[cfg(feature = "physics")]
+pub mod physics;
+pub mod pipeline;
+pub mod planar_reflection;
+pub mod point_cloud;
+pub mod postprocess;
+pub mod procedural;
+#[cfg(feature = "ui")]
+pub mod profiler;
+pub mod projection;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod readback;
+pub mod recorder;
+pub mod reflection;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod settings;
+pub mod shader;
+pub mod shadow;
+pub mod skin;
+pub mod sky;
 pub mod state;
+pub mod stereo;
+pub mod subdivision;
+pub mod terrain;
+#[cfg(feature = "ui")]
+pub mod text;
 pub mod texture;
+pub mod time_of_day;
+pub mod transform;
+pub mod triplanar;
+pub mod uniform;
+pub mod user_event;
+pub mod vegetation;
 pub mod vertex;
+pub mod viewport;
+pub mod walk;
+pub mod water;
+pub mod window;
 
-pub async fn run() {
-    env_logger::init();
-    let event_loop = EventLoop::new();
-    let window = WindowBuilder::new()
-        .with_title("WGPU Cube")
-        .build(&event_loop)
-        .unwrap();
-    let mut state = State::new(&window).await;
-
-    event_loop.run(move |event, _, control_flow| match event {
-        Event::WindowEvent {
-            window_id,
-            ref event,
-        } if window_id == window.id() && !state.input(event) => match event {
-            WindowEvent::CloseRequested
-            | WindowEvent::KeyboardInput {
-                input:
-                    KeyboardInput {
-                        state: ElementState::Pressed,
-                        virtual_keycode: Some(VirtualKeyCode::Escape),
-                        ..
-                    },
-                ..
-            } => *control_flow = ControlFlow::Exit,
-            WindowEvent::Resized(phys_size) => state.resize(*phys_size),
-            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                state.resize(**new_inner_size)
+/// The entry point on Android, where the app is a library loaded by a `NativeActivity`.
+/// There's no command line, so it always runs with the default `Config`
+#[cfg(target_os = "android")]
+#[ndk_glue::main(backtrace = "on")]
+pub fn android_main() {
+    pollster::block_on(run(Config::default()));
+}
+
+/// Show the demo scene described by `config`
+pub async fn run(config: Config) {
+    run_app::<Demo>(config).await
+}
+
+/// Run `A` in a window of its own, with the scene described by `config`
+pub async fn run_app<A: App>(config: Config) {
+    run_app_with_proxy::<A>(config, |_| ()).await
+}
+
+/// `run_app`, handing `on_start` a proxy for posting `UserEvent`s into the event loop
+/// before it starts, e.g. to send to a thread of its own
+pub async fn run_app_with_proxy<A: App>(config: Config, on_start: impl FnOnce(EventProxy)) {
+    let mut trace_guard = match config.logging {
+        LogConfig::Install => {
+            let chrome_trace = std::env::var_os(logging::CHROME_TRACE_VAR).map(PathBuf::from);
+            logging::init(chrome_trace.as_deref())
+        }
+        LogConfig::External => None,
+    };
+    let event_loop = EventLoopBuilder::<UserEvent>::with_user_event().build();
+    on_start(event_loop.create_proxy());
+    let mut metrics = Metrics::new();
+    if let Some(path) = &config.metrics {
+        if let Err(error) = metrics.write_to(path) {
+            tracing::error!("Can't write the frame metrics: {error:#}");
+        }
+    }
+    if let Some(address) = config.remote {
+        #[cfg(feature = "remote")]
+        if let Err(error) = remote::spawn(address, event_loop.create_proxy(), metrics.summary()) {
+            tracing::error!("Can't start the remote server: {error:#}");
+        }
+        #[cfg(not(feature = "remote"))]
+        tracing::warn!("--remote {address} needs the app to be built with the `remote` feature");
+    }
+    let window = config.window.build(&event_loop).unwrap();
+    if config.list_video_modes {
+        print!("{}", fullscreen::describe_monitors(&window));
+        return;
+    }
+    if let Some(request) = &config.fullscreen {
+        if let Err(error) = fullscreen::enter_exclusive(&window, request) {
+            tracing::error!("Can't go fullscreen: {error:#}");
+        }
+    }
+    let mut settings_file = SettingsFile::new(
+        config
+            .settings
+            .clone()
+            .unwrap_or_else(|| Settings::DEFAULT_PATH.into()),
+    );
+    // `--present-mode` takes precedence over the file, and benchmarks pick their own
+    let keep_present_mode = config.present_mode.is_some() || config.benchmark.is_some();
+    let apply_settings = move |state: &mut State, settings: Result<Settings, _>| match settings {
+        Ok(mut settings) => {
+            if keep_present_mode {
+                settings.present_mode = None;
             }
-            _ => (),
-        },
-        Event::RedrawRequested(window_id) if window_id == window.id() => {
-            state.update();
-            match state.render() {
-                // All is well
-                Ok(_) => (),
-                // Reconfigure the surface if lost
-                Err(SurfaceError::Lost) => state.resize(state.size),
-                // The system is OOM, should probably quit lol
-                Err(SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                // Any other errors should be resolved by the next frame
-                Err(e) => log::warn!("{e:#?}"),
+            state.apply_settings(&settings);
+        }
+        Err(error) => tracing::error!("{error:#}"),
+    };
+    let mut running: Option<(State, A)> = None;
+    let mut input = Input::default();
+    let mut benchmark = config.benchmark.map(Benchmark::new);
+    #[cfg(feature = "scripting")]
+    let mut script = config.script.as_ref().and_then(|path| {
+        script::Script::load(path)
+            .map_err(|error| tracing::error!("{error:#}"))
+            .ok()
+    });
+    #[cfg(not(feature = "scripting"))]
+    if let Some(path) = &config.script {
+        tracing::warn!(
+            "--script {} needs the app to be built with the `scripting` feature",
+            path.display()
+        );
+    }
+
+    event_loop.run(move |event, _, control_flow| {
+        let Some((state, app)) = &mut running else {
+            match event {
+                // Every platform resumes once at startup,
+                // and Android doesn't have a window to create a surface for until then
+                Event::Resumed => {
+                    let mut state = pollster::block_on(State::new(&window, &config));
+                    apply_settings(&mut state, settings_file.load());
+                    metrics.set_gpu_timed(state.is_gpu_timed());
+                    let app = A::init(&mut state);
+                    running = Some((state, app));
+                }
+                Event::LoopDestroyed => drop(trace_guard.take()),
+                Event::UserEvent(_) => {
+                    tracing::warn!("Dropped a user event, as the app hasn't started yet")
+                }
+                _ => (),
+            }
+            return;
+        };
+        if let Event::WindowEvent { window_id, event } = &event {
+            if *window_id == window.id() {
+                input.process_events(event);
             }
         }
-        Event::MainEventsCleared => {
-            // `RedrawRequested` will only trigger once, unless we manually request it.
-            window.request_redraw();
+        match event {
+            Event::WindowEvent {
+                window_id,
+                ref event,
+            } if window_id == window.id() && !app.on_event(state, event) && !state.input(event) => {
+                match event {
+                    WindowEvent::CloseRequested
+                    | WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::Escape),
+                                ..
+                            },
+                        ..
+                    } => *control_flow = ControlFlow::Exit,
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F11),
+                                ..
+                            },
+                        ..
+                    } => fullscreen::toggle(&window, config.fullscreen.as_ref()),
+                    // Also sent when going in and out of fullscreen, which reconfigures the surface
+                    WindowEvent::Resized(phys_size) => state.resize(*phys_size),
+                    WindowEvent::ScaleFactorChanged {
+                        scale_factor,
+                        new_inner_size,
+                    } => {
+                        state.set_scale_factor(*scale_factor);
+                        state.resize(**new_inner_size)
+                    }
+                    _ => (),
+                }
+            }
+            Event::UserEvent(event) => match event {
+                UserEvent::ReloadShader { name, source } => {
+                    match state.reload_shader(&name, source) {
+                        Ok(()) => tracing::info!("Reloaded {name}"),
+                        Err(error) => tracing::error!("{error:#}"),
+                    }
+                }
+                UserEvent::SetCamera { eye, target } => {
+                    let camera = state.camera();
+                    camera.eye = eye;
+                    camera.target = target;
+                }
+                UserEvent::Screenshot(path) => state.recorder().screenshot(path),
+                UserEvent::AddMesh {
+                    vertices,
+                    indices,
+                    transform,
+                    tint,
+                } => {
+                    if let Err(error) = state.add_mesh(&vertices, &indices, transform, tint) {
+                        tracing::error!("Can't add the mesh: {error:#}");
+                    }
+                }
+                UserEvent::LoadTexture(path) => {
+                    let loaded = image::open(&path)
+                        .with_context(|| format!("failed to load {}", path.display()))
+                        .and_then(|image| state.set_texture(&image));
+                    if let Err(error) = loaded {
+                        tracing::error!("{error:#}");
+                    }
+                }
+                UserEvent::SetFog(mode) => state.fog().mode = mode,
+                UserEvent::ShowGizmos(show) => state.show_gizmos = show,
+            },
+            // Android destroys the window while the app is in the background, taking the surface with it
+            Event::Suspended => {
+                state.suspend();
+                // nothing's going to be drawn, so don't spin while waiting to be resumed
+                *control_flow = ControlFlow::Wait;
+            }
+            Event::Resumed => {
+                state.resume(&window);
+                *control_flow = ControlFlow::Poll;
+            }
+            Event::RedrawRequested(window_id)
+                if window_id == window.id() && !state.is_suspended() =>
+            {
+                // Hands the previous frame's profile scopes over to the profiler overlay
+                puffin::GlobalProfiler::lock().new_frame();
+                let started = Instant::now();
+                let _frame = tracing::info_span!("frame").entered();
+                if let Some(benchmark) = &benchmark {
+                    state.camera().eye = benchmark.camera_eye();
+                }
+                if let Some(settings) = settings_file.poll() {
+                    tracing::info!("Reloading {}", settings_file.path().display());
+                    apply_settings(state, settings);
+                }
+                let time = state.tick();
+                app.update(state, time.delta, &input);
+                #[cfg(feature = "scripting")]
+                if let Some(script) = &mut script {
+                    script.update(state, time.delta, time.elapsed);
+                }
+                input.end_frame();
+                state.update();
+                match state.render_with(|frame| app.render(frame)) {
+                    // All is well
+                    Ok(_) => (),
+                    // Reconfigure or recreate the surface, depending on what went wrong
+                    Err(e) => {
+                        if let Err(e) = state.recover(&window, e) {
+                            // The system is OOM, should probably quit lol
+                            tracing::error!("{e:#?}");
+                            *control_flow = ControlFlow::Exit;
+                        }
+                    }
+                }
+                metrics.frame_finished(time.frame, started.elapsed());
+                for (frame, gpu_ms) in state.gpu_frame_times() {
+                    metrics.gpu_time(frame, gpu_ms);
+                }
+                if let Some(benchmark) = &mut benchmark {
+                    if benchmark.frame_finished() {
+                        let report = benchmark.report();
+                        tracing::info!("Benchmark finished: {report:?}");
+                        println!("{}", report.to_json(state.gpu_info()));
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
+            }
+            Event::LoopDestroyed => {
+                // Wait for the last frames of a recording to be written, as the process exits afterwards
+                state.recorder().stop();
+                metrics.close();
+                // The Chrome trace is only written out once its guard is dropped
+                drop(trace_guard.take());
+            }
+            Event::MainEventsCleared if !state.is_suspended() => {
+                // `RedrawRequested` will only trigger once, unless we manually request it.
+                window.request_redraw();
+            }
+            _ => (),
         }
-        _ => (),
     })
 }
@@ -0,0 +1,136 @@
+use bytemuck::{Pod, Zeroable};
+use serde::Deserialize;
+
+use crate::{
+    debug_draw::DebugDraw,
+    math::{InnerSpace, Point3, Vector3},
+};
+
+/// A light which shines equally in every direction from a single point, like a light bulb
+#[derive(Clone, Debug)]
+pub struct PointLight {
+    pub position: Point3<f32>,
+    /// Linear RGB
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// The distance at which the light fades out completely, which is also the far plane of its shadow map
+    pub range: f32,
+    /// Subtracted from the distance compared against the shadow map, to stop surfaces from shadowing themselves
+    /// ("shadow acne"), as a fraction of `range`. Too much makes shadows detach from their casters ("peter-panning")
+    pub shadow_bias: f32,
+    /// How far to push the point being shadowed out along its normal, in shadow map texels,
+    /// which fixes acne on surfaces facing away from the light without as much peter-panning as `shadow_bias`
+    pub shadow_normal_offset: f32,
+    pub shadow_filter: ShadowFilter,
+}
+
+impl PointLight {
+    /// Visualise the light as a marker at its position, along with a sphere showing how far it reaches
+    pub fn draw_gizmo(&self, debug_draw: &mut DebugDraw) {
+        debug_draw.cross(self.position, 0.25, self.color);
+        debug_draw.sphere(self.position, self.range, self.color);
+    }
+}
+
+/// How the edges of shadows are smoothed out, from cheapest to most expensive
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShadowFilter {
+    /// A single sample, which the sampler's bilinear filtering smooths over a texel at most
+    Hard,
+    /// Percentage-closer filtering, averaging a 3x3 grid of samples
+    #[default]
+    Pcf3x3,
+    /// Percentage-closer filtering, averaging a 5x5 grid of samples
+    Pcf5x5,
+    /// 16 samples scattered over a disc, which avoids the grid pattern of PCF
+    Poisson,
+}
+
+/// The layout of `PointLight` expected by `light.wgsl`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+pub struct LightUniform {
+    position: [f32; 3],
+    range: f32,
+    color: [f32; 3],
+    intensity: f32,
+    shadow_bias: f32,
+    shadow_normal_offset: f32,
+    /// One of the `SHADOW_FILTER_*` constants in `light.wgsl`
+    shadow_filter: u32,
+    _padding: f32,
+}
+
+impl LightUniform {
+    pub fn update(&mut self, light: &PointLight) {
+        self.position = light.position.into();
+        self.range = light.range;
+        self.color = light.color;
+        self.intensity = light.intensity;
+        self.shadow_bias = light.shadow_bias;
+        self.shadow_normal_offset = light.shadow_normal_offset;
+        self.shadow_filter = light.shadow_filter as u32;
+    }
+}
+
+impl From<&PointLight> for LightUniform {
+    fn from(light: &PointLight) -> Self {
+        let mut uniform = Self::default();
+        uniform.update(light);
+        uniform
+    }
+}
+
+/// A light so far away that it shines in the same direction everywhere, like the sun.
+/// It doesn't cast shadows
+#[derive(Clone, Debug)]
+pub struct DirectionalLight {
+    /// Pointing from the scene towards the light
+    pub direction: Vector3<f32>,
+    /// Linear RGB
+    pub color: [f32; 3],
+    /// 0.0 turns the light off
+    pub intensity: f32,
+    /// How much light reaches everywhere, so that shadows aren't completely black,
+    /// e.g. from the sky scattering the sun's light. Unlike the rest this is on by default
+    pub ambient: f32,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self {
+            direction: Vector3::unit_y(),
+            color: [1.0; 3],
+            intensity: 0.0,
+            ambient: 0.05,
+        }
+    }
+}
+
+/// The layout of `DirectionalLight` expected by `light.wgsl`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+pub struct DirectionalLightUniform {
+    direction: [f32; 3],
+    ambient: f32,
+    /// Multiplied by the intensity
+    color: [f32; 3],
+    _padding: f32,
+}
+
+impl DirectionalLightUniform {
+    pub fn update(&mut self, light: &DirectionalLight) {
+        self.direction = light.direction.normalize().into();
+        self.ambient = light.ambient;
+        self.color = light.color.map(|channel| channel * light.intensity);
+    }
+}
+
+impl From<&DirectionalLight> for DirectionalLightUniform {
+    fn from(light: &DirectionalLight) -> Self {
+        let mut uniform = Self::default();
+        uniform.update(light);
+        uniform
+    }
+}
@@ -0,0 +1,188 @@
+// Shared point light and its shadow map, along with the sun, bound at group 2 by every pipeline that lights the scene
+
+struct PointLight {
+    position: vec3<f32>,
+    // the distance at which the light fades out completely
+    range: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    // subtracted from the distance compared against the shadow map, as a fraction of `range`
+    shadow_bias: f32,
+    // how far to push the shadowed point out along its normal, in shadow map texels
+    shadow_normal_offset: f32,
+    // one of the `SHADOW_FILTER_*` constants
+    shadow_filter: u32,
+};
+@group(2) @binding(0)
+var<uniform> light: PointLight;
+// The distance from the light to the closest surface in each direction, divided by `range`
+@group(2) @binding(1)
+var t_shadow: texture_depth_cube;
+// Compares with bilinear filtering, smoothing between neighbouring texels.
+// There's no separate nearest sampler for hard shadows, as GL can't use one texture with two samplers
+@group(2) @binding(2)
+var s_shadow: sampler_comparison;
+
+// A light so far away that it shines in the same direction everywhere, like the sun
+struct DirectionalLight {
+    // pointing from the scene towards the light
+    direction: vec3<f32>,
+    // a little light which reaches everywhere, so that shadows aren't completely black
+    ambient: f32,
+    // linear RGB, already multiplied by the light's intensity, so all zeroes turns the light off
+    color: vec3<f32>,
+};
+@group(2) @binding(4)
+var<uniform> directional_light: DirectionalLight;
+// What surrounds the scene in every direction, blurrier in each smaller mip level
+@group(2) @binding(5)
+var t_environment: texture_cube<f32>;
+@group(2) @binding(6)
+var s_environment: sampler;
+// Must match `EnvironmentMap::MIP_LEVELS`
+let ENVIRONMENT_MIP_LEVELS: f32 = 5.0;
+
+let SHADOW_FILTER_HARD: u32 = 0u;
+let SHADOW_FILTER_PCF_3X3: u32 = 1u;
+let SHADOW_FILTER_PCF_5X5: u32 = 2u;
+let SHADOW_FILTER_POISSON: u32 = 3u;
+// The radius of the Poisson disc, in shadow map texels
+let SHADOW_POISSON_RADIUS: f32 = 2.5;
+
+// Average a (2 * radius + 1)^2 grid of comparisons around `direction`
+fn shadow_pcf(direction: vec3<f32>, depth: f32, tangent: vec3<f32>, bitangent: vec3<f32>, radius: i32) -> f32 {
+    var total = 0.0;
+    for (var y = -radius; y <= radius; y = y + 1) {
+        for (var x = -radius; x <= radius; x = x + 1) {
+            let offset = tangent * f32(x) + bitangent * f32(y);
+            total = total + textureSampleCompareLevel(t_shadow, s_shadow, direction + offset, depth);
+        }
+    }
+    let width = f32(2 * radius + 1);
+    return total / (width * width);
+}
+
+fn shadow_poisson(direction: vec3<f32>, depth: f32, tangent: vec3<f32>, bitangent: vec3<f32>) -> f32 {
+    var disc = array<vec2<f32>, 16>(
+        vec2<f32>(-0.94201624, -0.39906216),
+        vec2<f32>(0.94558609, -0.76890725),
+        vec2<f32>(-0.094184101, -0.92938870),
+        vec2<f32>(0.34495938, 0.29387760),
+        vec2<f32>(-0.91588581, 0.45771432),
+        vec2<f32>(-0.81544232, -0.87912464),
+        vec2<f32>(-0.38277543, 0.27676845),
+        vec2<f32>(0.97484398, 0.75648379),
+        vec2<f32>(0.44323325, -0.97511554),
+        vec2<f32>(0.53742981, -0.47373420),
+        vec2<f32>(-0.26496911, -0.41893023),
+        vec2<f32>(0.79197514, 0.19090188),
+        vec2<f32>(-0.24188840, 0.99706507),
+        vec2<f32>(-0.81409955, 0.91437590),
+        vec2<f32>(0.19984126, 0.78641367),
+        vec2<f32>(0.14383161, -0.14100790),
+    );
+    var total = 0.0;
+    for (var i = 0; i < 16; i = i + 1) {
+        let offset = (tangent * disc[i].x + bitangent * disc[i].y) * SHADOW_POISSON_RADIUS;
+        total = total + textureSampleCompareLevel(t_shadow, s_shadow, direction + offset, depth);
+    }
+    return total / 16.0;
+}
+
+// 1.0 if nothing is between `world_position` and the light, 0.0 if something is,
+// and in between at the softened edges of shadows
+fn point_shadow(world_position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    // Each texel covers this much of a cube face at a distance of 1.0 from the light,
+    // as the faces are 2.0 across at that distance
+    let texel_size = 2.0 / f32(textureDimensions(t_shadow).x);
+    let distance = length(world_position - light.position);
+    let offset_position = world_position + normal * light.shadow_normal_offset * texel_size * distance;
+
+    let to_surface = offset_position - light.position;
+    let direction = normalize(to_surface);
+    let depth = length(to_surface) / light.range - light.shadow_bias;
+    if (light.shadow_filter == SHADOW_FILTER_HARD) {
+        return textureSampleCompareLevel(t_shadow, s_shadow, direction, depth);
+    }
+
+    // Filter samples are spread over the plane facing the light, one texel apart
+    let helper = select(vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(1.0, 0.0, 0.0), abs(direction.y) > 0.99);
+    let tangent = normalize(cross(helper, direction)) * texel_size;
+    let bitangent = cross(direction, tangent);
+    if (light.shadow_filter == SHADOW_FILTER_PCF_3X3) {
+        return shadow_pcf(direction, depth, tangent, bitangent, 1);
+    }
+    if (light.shadow_filter == SHADOW_FILTER_PCF_5X5) {
+        return shadow_pcf(direction, depth, tangent, bitangent, 2);
+    }
+    return shadow_poisson(direction, depth, tangent, bitangent);
+}
+
+// What a surface is made of, which determines how it reflects light
+struct Material {
+    albedo: vec3<f32>,
+    // from 0.0 (mirror-like) to 1.0 (completely matte)
+    roughness: f32,
+    // from 0.0 (e.g. plastic or wood) to 1.0 (metal)
+    metallic: f32,
+    // light given off by the surface itself, which is added whatever lights it
+    emissive: vec3<f32>,
+}
+
+// Metals don't have a diffuse colour, they reflect their colour instead
+fn diffuse_color(material: Material) -> vec3<f32> {
+    return material.albedo * (1.0 - material.metallic);
+}
+
+fn specular_color(material: Material) -> vec3<f32> {
+    return mix(vec3<f32>(0.04), material.albedo, material.metallic);
+}
+
+// How much of `radiance` arriving from `light_direction` is reflected towards `view_direction`
+fn shade(light_direction: vec3<f32>, radiance: vec3<f32>, normal: vec3<f32>, view_direction: vec3<f32>, material: Material) -> vec3<f32> {
+    let diffuse = max(dot(normal, light_direction), 0.0);
+    // Blinn-Phong, with the exponent derived from roughness so that rougher surfaces have broader highlights
+    let half_direction = normalize(light_direction + view_direction);
+    let alpha = max(material.roughness * material.roughness, 0.05);
+    let shininess = 2.0 / (alpha * alpha) - 2.0;
+    let specular = pow(max(dot(normal, half_direction), 0.0), shininess) * (shininess + 8.0) / 8.0 * diffuse;
+    return (diffuse_color(material) * diffuse + specular_color(material) * specular) * radiance;
+}
+
+// The colour of a surface at `world_position` facing `normal`, lit by the point light,
+// as seen from `view_direction` (pointing from the surface towards the camera)
+fn point_light(world_position: vec3<f32>, normal: vec3<f32>, view_direction: vec3<f32>, material: Material) -> vec3<f32> {
+    let to_light = light.position - world_position;
+    let distance = length(to_light);
+    let light_direction = to_light / distance;
+    // Inverse square falloff, smoothly windowed so that it reaches zero at `range`
+    let window = clamp(1.0 - pow(distance / light.range, 4.0), 0.0, 1.0);
+    let attenuation = window * window / (distance * distance + 1.0);
+    let shadow = point_shadow(world_position, normal);
+    let radiance = light.color * light.intensity * attenuation * shadow;
+    return shade(light_direction, radiance, normal, view_direction, material);
+}
+
+// The light a surface facing `normal` gets from the directional light, which casts no shadows
+fn directional(normal: vec3<f32>, view_direction: vec3<f32>, material: Material) -> vec3<f32> {
+    return shade(directional_light.direction, directional_light.color, normal, view_direction, material);
+}
+
+// Everything lighting a surface, see `point_light`
+fn lighting(world_position: vec3<f32>, normal: vec3<f32>, view_direction: vec3<f32>, material: Material) -> vec3<f32> {
+    let ambient = directional_light.ambient * (diffuse_color(material) + specular_color(material));
+    return material.emissive + ambient + point_light(world_position, normal, view_direction, material) + directional(normal, view_direction, material);
+}
+
+// The surroundings mirrored by a surface, which blur as it gets rougher.
+// Kept apart from `lighting`, as surfaces with reflections of their own don't want these as well
+fn environment_reflection(normal: vec3<f32>, view_direction: vec3<f32>, material: Material) -> vec3<f32> {
+    let direction = reflect(-view_direction, normal);
+    let level = material.roughness * (ENVIRONMENT_MIP_LEVELS - 1.0);
+    let environment = textureSampleLevel(t_environment, s_environment, direction, level).rgb;
+    // Schlick's approximation, where rough surfaces don't brighten as much at grazing angles
+    let f0 = specular_color(material);
+    let cos_theta = max(dot(normal, view_direction), 0.0);
+    let fresnel = f0 + (max(vec3<f32>(1.0 - material.roughness), f0) - f0) * pow(1.0 - cos_theta, 5.0);
+    return environment * fresnel;
+}
@@ -0,0 +1,70 @@
+use wgpu::Limits;
+
+/// The limits the renderer asks for: wgpu's defaults, raised wherever more allows for more,
+/// e.g. larger uniform buffers or more bind groups. Use `intersect` to lower them to what the
+/// adapter supports, and check `Device::limits` (or `GpuInfo::limits`) for what was granted
+pub fn desired() -> Limits {
+    Limits {
+        max_texture_dimension_1d: 16384,
+        max_texture_dimension_2d: 16384,
+        max_texture_array_layers: 2048,
+        max_bind_groups: 8,
+        max_sampled_textures_per_shader_stage: 64,
+        max_samplers_per_shader_stage: 32,
+        max_storage_buffers_per_shader_stage: 16,
+        max_uniform_buffer_binding_size: 1 << 20,
+        max_storage_buffer_binding_size: 1 << 30,
+        max_vertex_buffers: 16,
+        max_vertex_attributes: 32,
+        // only usable along with `Features::PUSH_CONSTANTS`
+        max_push_constant_size: 128,
+        max_buffer_size: 1 << 32,
+        ..Limits::default()
+    }
+}
+
+/// The best of `desired` that `supported` allows: the lower of each maximum, and the higher of
+/// each minimum alignment. This can come out below `Limits::default()` on downlevel adapters
+pub fn intersect(desired: &Limits, supported: &Limits) -> Limits {
+    // every field has to be listed, so that new ones in wgpu can't be forgotten
+    macro_rules! intersect {
+        (max: $($max:ident),*; min: $($min:ident),*) => {
+            Limits {
+                $($max: desired.$max.min(supported.$max),)*
+                $($min: desired.$min.max(supported.$min),)*
+            }
+        };
+    }
+    intersect!(
+        max:
+            max_texture_dimension_1d,
+            max_texture_dimension_2d,
+            max_texture_dimension_3d,
+            max_texture_array_layers,
+            max_bind_groups,
+            max_dynamic_uniform_buffers_per_pipeline_layout,
+            max_dynamic_storage_buffers_per_pipeline_layout,
+            max_sampled_textures_per_shader_stage,
+            max_samplers_per_shader_stage,
+            max_storage_buffers_per_shader_stage,
+            max_storage_textures_per_shader_stage,
+            max_uniform_buffers_per_shader_stage,
+            max_uniform_buffer_binding_size,
+            max_storage_buffer_binding_size,
+            max_vertex_buffers,
+            max_vertex_attributes,
+            max_vertex_buffer_array_stride,
+            max_push_constant_size,
+            max_inter_stage_shader_components,
+            max_compute_workgroup_storage_size,
+            max_compute_invocations_per_workgroup,
+            max_compute_workgroup_size_x,
+            max_compute_workgroup_size_y,
+            max_compute_workgroup_size_z,
+            max_compute_workgroups_per_dimension,
+            max_buffer_size;
+        min:
+            min_uniform_buffer_offset_alignment,
+            min_storage_buffer_offset_alignment
+    )
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::VecDeque,
+    fmt::{Debug, Write},
+    sync::{Mutex, PoisonError},
+};
+
+use tracing::{field::Field, level_filters::LevelFilter, Event, Level, Subscriber};
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::{
+    field::Visit,
+    filter::{EnvFilter, FilterExt},
+    layer::{Context, Layer},
+    registry::LookupSpan,
+};
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+use crate::text::TextRenderer;
+
+/// The number of records kept for the console, older ones are dropped
+pub const CAPACITY: usize = 512;
+
+static RECORDS: Mutex<VecDeque<LogRecord>> = Mutex::new(VecDeque::new());
+
+/// An event kept around for the console
+#[derive(Clone, Debug)]
+pub struct LogRecord {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+/// Keeps the most recent events for the console
+struct ConsoleLayer;
+
+impl<S: Subscriber> Layer<S> for ConsoleLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        // records from the `log` crate, e.g. wgpu's, arrive as events with the `log` target
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        let record = LogRecord {
+            level: *metadata.level(),
+            target: metadata.target().to_owned(),
+            message: visitor.message + visitor.fields.as_str(),
+        };
+        let mut records = RECORDS.lock().unwrap_or_else(PoisonError::into_inner);
+        if records.len() == CAPACITY {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+}
+
+/// Formats an event's message followed by the rest of its fields, like `tracing_subscriber::fmt` does
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        match field.name() {
+            "message" => write!(self.message, "{value:?}"),
+            // `log` records' metadata, which has already been normalised
+            name if name.starts_with("log.") => Ok(()),
+            name => write!(self.fields, " {name}={value:?}"),
+        }
+        .expect("writing to a string can't fail");
+    }
+}
+
+/// The layer which feeds the console, for adding to your own subscriber if you aren't using `init`.
+/// `RUST_LOG` decides what it keeps like it does for everything else, but info and above are always kept
+pub fn layer<S>() -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    ConsoleLayer.with_filter(EnvFilter::from_default_env().or(LevelFilter::INFO))
+}
+
+/// Forget every captured record
+pub fn clear() {
+    RECORDS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clear();
+}
+
+/// An overlay listing the most recent log records, toggled with the grave key (`).
+/// While it's open, 1 to 5 choose the least important level shown, from errors only to everything
+pub struct LogConsole {
+    pub visible: bool,
+    level: LevelFilter,
+    target: Option<String>,
+}
+
+impl Default for LogConsole {
+    fn default() -> Self {
+        Self {
+            visible: false,
+            // wgpu logs every resource it creates as info, which would bury everything else
+            level: LevelFilter::WARN,
+            target: None,
+        }
+    }
+}
+
+impl LogConsole {
+    /// The fraction of the screen's height covered by the console
+    const HEIGHT: f32 = 0.4;
+    /// The space between the edges of the console and its text, in lines
+    const MARGIN: f32 = 0.5;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    /// Hide records less important than `level`, which only affects records that were kept
+    pub fn set_level(&mut self, level: LevelFilter) {
+        self.level = level;
+    }
+
+    pub fn target(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
+
+    /// Only show records whose target starts with `target`, e.g. `wgpu_cube` to hide wgpu's records
+    pub fn set_target(&mut self, target: Option<String>) {
+        self.target = target;
+    }
+
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        let key = match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => *key,
+            _ => return false,
+        };
+        let level = match key {
+            VirtualKeyCode::Grave => {
+                self.visible = !self.visible;
+                return true;
+            }
+            _ if !self.visible => return false,
+            VirtualKeyCode::Key1 => LevelFilter::ERROR,
+            VirtualKeyCode::Key2 => LevelFilter::WARN,
+            VirtualKeyCode::Key3 => LevelFilter::INFO,
+            VirtualKeyCode::Key4 => LevelFilter::DEBUG,
+            VirtualKeyCode::Key5 => LevelFilter::TRACE,
+            _ => return false,
+        };
+        self.level = level;
+        true
+    }
+
+    fn shows(&self, record: &LogRecord) -> bool {
+        record.level <= self.level
+            && self
+                .target
+                .as_ref()
+                .is_none_or(|target| record.target.starts_with(target.as_str()))
+    }
+
+    /// Add the console to `text` if it's visible, for a screen of the given size
+    pub fn draw(&self, text: &mut TextRenderer, width: u32, height: u32) {
+        if !self.visible {
+            return;
+        }
+        let line_height = text.line_height();
+        let margin = (line_height * Self::MARGIN).round();
+        let bottom = (height as f32 * Self::HEIGHT).round();
+        text.rect([0.0, 0.0], [width as f32, bottom], [0.0, 0.0, 0.0, 0.75]);
+
+        let columns = ((width as f32 - 2.0 * margin) / text.advance()).max(0.0) as usize;
+        let header = format!(
+            "Log ({} and above, {}) - 1-5 to change level, ` to close",
+            self.level,
+            self.target().unwrap_or("all targets")
+        );
+        text.text(
+            [margin, margin],
+            &truncate(&header, columns),
+            [0.6, 0.6, 0.6, 1.0],
+        );
+
+        // Fill the console from the bottom up with the newest records,
+        // splitting multi-line messages so that each line gets a row of its own
+        let rows = ((bottom - 2.0 * margin) / line_height) as usize;
+        let mut lines = Vec::new();
+        let records = RECORDS.lock().unwrap_or_else(PoisonError::into_inner);
+        for record in records.iter().rev().filter(|record| self.shows(record)) {
+            let message = format!("{:<5} {}: {}", record.level, record.target, record.message);
+            let color = level_color(record.level);
+            lines.extend(
+                message
+                    .lines()
+                    .rev()
+                    .map(|line| (truncate(line, columns), color)),
+            );
+            if lines.len() >= rows.saturating_sub(1) {
+                break;
+            }
+        }
+        drop(records);
+
+        let mut y = bottom - margin - line_height;
+        for (line, color) in lines.iter().take(rows.saturating_sub(1)) {
+            text.text([margin, y], line, *color);
+            y -= line_height;
+        }
+    }
+}
+
+fn level_color(level: Level) -> [f32; 4] {
+    match level {
+        Level::ERROR => [1.0, 0.2, 0.2, 1.0],
+        Level::WARN => [1.0, 0.8, 0.2, 1.0],
+        Level::INFO => [1.0, 1.0, 1.0, 1.0],
+        Level::DEBUG => [0.5, 0.7, 1.0, 1.0],
+        Level::TRACE => [0.5, 0.5, 0.5, 1.0],
+    }
+}
+
+fn truncate(line: &str, columns: usize) -> String {
+    line.chars().take(columns).collect()
+}
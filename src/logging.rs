@@ -0,0 +1,64 @@
+use std::path::Path;
+
+use tracing::level_filters::LevelFilter;
+use tracing_chrome::{ChromeLayerBuilder, FlushGuard};
+use tracing_subscriber::{
+    filter::{EnvFilter, Targets},
+    fmt,
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    Layer,
+};
+
+#[cfg(feature = "ui")]
+use crate::log_console;
+
+/// The environment variable `run` reads the path of the Chrome trace to write from, if it's set
+pub const CHROME_TRACE_VAR: &str = "WGPU_CUBE_CHROME_TRACE";
+
+/// Whether `run` installs a subscriber of its own
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogConfig {
+    /// Install the subscriber from `init`, recording a Chrome trace if `CHROME_TRACE_VAR` is set.
+    /// If the host has already installed one, theirs is kept
+    #[default]
+    Install,
+    /// Leave logging to the host's subscriber, which can add `log_console::layer()` for the log console
+    External,
+}
+
+/// Install the subscriber used by `run`, which prints events to stderr as `RUST_LOG` asks,
+/// keeps them for the log console with the `ui` feature, and records a Chrome trace to `chrome_trace` if there is one.
+/// The trace is written once the returned guard is dropped, and can be opened with `chrome://tracing` or Perfetto.
+///
+/// Embedders with a subscriber of their own can skip this with `LogConfig::External`, and add `log_console::layer()`
+/// to theirs. If there's already a global subscriber, or a logger for the `log` crate, it's left in place
+/// rather than panicking. Records from the `log` crate, e.g. wgpu's, are forwarded to the subscriber either way
+pub fn init(chrome_trace: Option<&Path>) -> Option<FlushGuard> {
+    let (chrome_layer, guard) = chrome_trace
+        .map(|path| {
+            ChromeLayerBuilder::new()
+                .file(path)
+                .include_args(true)
+                .build()
+        })
+        .unzip();
+    // only our own spans are interesting on the timeline, wgpu logs far too much to keep all of it
+    let chrome_layer = chrome_layer.map(|layer| {
+        layer.with_filter(Targets::new().with_target(env!("CARGO_CRATE_NAME"), LevelFilter::TRACE))
+    });
+
+    let subscriber = tracing_subscriber::registry()
+        .with(fmt::layer().with_filter(EnvFilter::from_default_env()));
+    #[cfg(feature = "ui")]
+    let subscriber = subscriber.with(log_console::layer());
+    let installed = subscriber.with(chrome_layer).try_init();
+    match installed {
+        Ok(()) => guard,
+        Err(error) => {
+            // goes to whichever subscriber is already installed
+            tracing::warn!("Keeping the subscriber or logger already installed: {error}");
+            None
+        }
+    }
+}
@@ -1,5 +1,9 @@
-use wgpu_cube::run;
+use wgpu_cube::{config::Config, run};
 
 fn main() {
-    pollster::block_on(run());
+    let config = Config::from_args().unwrap_or_else(|error| {
+        eprintln!("{error:#}");
+        std::process::exit(2);
+    });
+    pollster::block_on(run(config));
 }
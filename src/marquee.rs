@@ -0,0 +1,129 @@
+//! Picking out instances by dragging a rectangle over them with the left mouse button. Once it's let go,
+//! every instance whose box overlaps the rectangle on screen is outlined, which is the same selection
+//! F focuses the camera on. Clicking without dragging picks out whatever's under the cursor
+
+use winit::event::{ElementState, MouseButton, WindowEvent};
+
+use crate::{
+    geometry::{Aabb, Frustum},
+    math::{Matrix4, Vector4},
+};
+
+/// A rectangle on screen, in normalised device co-ordinates from -1 to 1 with +y up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScreenRect {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+impl ScreenRect {
+    /// The rectangle with `a` and `b` at opposite corners
+    pub fn from_corners(a: [f32; 2], b: [f32; 2]) -> Self {
+        Self {
+            min: [a[0].min(b[0]), a[1].min(b[1])],
+            max: [a[0].max(b[0]), a[1].max(b[1])],
+        }
+    }
+
+    /// Whether the rectangles overlap, including only touching
+    pub fn intersects(&self, other: &ScreenRect) -> bool {
+        (0..2).all(|axis| self.min[axis] <= other.max[axis] && other.min[axis] <= self.max[axis])
+    }
+
+    /// Where `aabb` covers the screen of the camera whose view-projection matrix is `view_proj`,
+    /// or `None` if it's out of view. A box reaching behind the camera could cover any of the screen,
+    /// so it's taken to cover all of it
+    pub fn around_aabb(aabb: &Aabb, view_proj: &Matrix4<f32>) -> Option<Self> {
+        if !Frustum::from_view_proj(view_proj).intersects(aabb) {
+            return None;
+        }
+        let whole_screen = Self {
+            min: [-1.0, -1.0],
+            max: [1.0, 1.0],
+        };
+        let ends = [aabb.min, aabb.max];
+        let mut corners = Vec::with_capacity(8);
+        for corner in 0..8 {
+            let clip = view_proj
+                * Vector4::new(
+                    ends[corner & 1].x,
+                    ends[corner >> 1 & 1].y,
+                    ends[corner >> 2 & 1].z,
+                    1.0,
+                );
+            if clip.w <= 0.0 {
+                return Some(whole_screen);
+            }
+            corners.push([clip.x / clip.w, clip.y / clip.w]);
+        }
+        Some(corners.iter().fold(
+            Self::from_corners(corners[0], corners[0]),
+            |rect, &corner| Self {
+                min: [rect.min[0].min(corner[0]), rect.min[1].min(corner[1])],
+                max: [rect.max[0].max(corner[0]), rect.max[1].max(corner[1])],
+            },
+        ))
+    }
+}
+
+/// Follows the left mouse button and the cursor to find the rectangles dragged out over the window
+#[derive(Default)]
+pub struct Marquee {
+    /// In physical pixels from the top left of the window, `None` while it's outside
+    cursor: Option<[f32; 2]>,
+    /// Where the button went down, while it's held
+    start: Option<[f32; 2]>,
+    /// The corners of the last rectangle let go of, until it's taken
+    finished: Option<([f32; 2], [f32; 2])>,
+}
+
+impl Marquee {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = Some([position.x as f32, position.y as f32]);
+                self.start.is_some()
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor = None;
+                false
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => match (state, self.cursor) {
+                (ElementState::Pressed, Some(cursor)) => {
+                    self.start = Some(cursor);
+                    true
+                }
+                (ElementState::Released, _) => {
+                    let start = self.start.take();
+                    self.finished = start.zip(self.cursor.or(start));
+                    start.is_some()
+                }
+                _ => false,
+            },
+            // the release won't arrive
+            WindowEvent::Focused(false) => {
+                self.start = None;
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// The opposite corners of the rectangle being dragged out, in physical pixels from the top left of the window
+    pub fn dragging(&self) -> Option<([f32; 2], [f32; 2])> {
+        self.start.zip(self.cursor)
+    }
+
+    /// The corners of the rectangle which was just let go of, if it hasn't been taken already
+    pub fn take_finished(&mut self) -> Option<([f32; 2], [f32; 2])> {
+        self.finished.take()
+    }
+}
@@ -0,0 +1,122 @@
+//! The textures the scene's models are painted with, which each instance picks one of through `Instance::texture`.
+//! With bindless textures, i.e. `Features::TEXTURE_BINDING_ARRAY` along with non-uniform indexing, they're all
+//! bound at once as a binding array which the fragment shader indexes with the instance's texture, so that
+//! nothing's rebound between models and one draw can paint each of its instances differently.
+//! Otherwise each texture has a material bind group of its own, and every run of neighbouring instances
+//! with the same texture is drawn on its own, with its texture's bind group
+
+use std::ops::Range;
+
+use anyhow::*;
+use wgpu::{BindGroup, BindingResource};
+
+use crate::{instance::Instance, shader::ShaderDefs, texture::OurTexture};
+
+/// The scene's textures, where the first is the one everything's painted with unless its instances say otherwise
+pub struct MaterialTextures {
+    textures: Vec<OurTexture>,
+    bindless: bool,
+}
+
+impl MaterialTextures {
+    /// How many textures there can be, which is the size of the binding array with bindless textures
+    pub const CAPACITY: usize = 16;
+
+    /// Starting off with only `first`, bound as a binding array if `bindless`,
+    /// which needs `Capabilities::bindless_textures`
+    pub fn new(first: OurTexture, bindless: bool) -> Self {
+        Self {
+            textures: vec![first],
+            bindless,
+        }
+    }
+
+    pub fn is_bindless(&self) -> bool {
+        self.bindless
+    }
+
+    pub fn textures(&self) -> &[OurTexture] {
+        &self.textures
+    }
+
+    /// The scene's own texture, whose sampler every texture is sampled with
+    pub fn first(&self) -> &OurTexture {
+        &self.textures[0]
+    }
+
+    pub fn set_first(&mut self, texture: OurTexture) {
+        self.textures[0] = texture;
+    }
+
+    /// Add `texture`, returning the index instances can be painted with it by.
+    /// The material's bind groups have to be made again to pick it up
+    pub fn add(&mut self, texture: OurTexture) -> Result<u32> {
+        ensure!(
+            self.textures.len() < Self::CAPACITY,
+            "there can only be {} textures",
+            Self::CAPACITY
+        );
+        self.textures.push(texture);
+        Ok(self.textures.len() as u32 - 1)
+    }
+
+    /// `defs` with whether the textures are bindless, which every permutation of the scene's shader has to agree on,
+    /// as it decides the material's bind group layout
+    pub fn shader_defs(&self, defs: &ShaderDefs) -> ShaderDefs {
+        let mut defs = defs.clone();
+        defs.set("BINDLESS", self.bindless);
+        if self.bindless {
+            defs.value("MATERIAL_TEXTURES", Self::CAPACITY)
+        } else {
+            defs.set("MATERIAL_TEXTURES", false);
+            defs
+        }
+    }
+
+    /// The material's bind groups, made by `create` from what goes in the textures' binding:
+    /// one with every texture when they're bindless, with the rest of the array filled by the first,
+    /// otherwise one for each texture, in order
+    pub fn bind_groups(
+        &self,
+        mut create: impl FnMut(BindingResource) -> BindGroup,
+    ) -> Vec<BindGroup> {
+        if self.bindless {
+            let views = (0..Self::CAPACITY)
+                .map(|index| &self.textures.get(index).unwrap_or(self.first()).view)
+                .collect::<Vec<_>>();
+            vec![create(BindingResource::TextureViewArray(&views))]
+        } else {
+            self.textures
+                .iter()
+                .map(|texture| create(BindingResource::TextureView(&texture.view)))
+                .collect()
+        }
+    }
+
+    /// The runs of neighbouring instances in `range` painted with the same texture, and the index of
+    /// their texture, for drawing them with its bind group when the textures aren't bindless
+    pub fn runs<'a>(
+        &self,
+        instances: &'a [Instance],
+        range: Range<u32>,
+    ) -> impl Iterator<Item = (usize, Range<u32>)> + 'a {
+        let count = self.textures.len();
+        let texture = move |index: u32| match instances[index as usize].texture as usize {
+            texture if texture < count => texture,
+            _ => 0,
+        };
+        let mut start = range.start;
+        std::iter::from_fn(move || {
+            if start >= range.end {
+                return None;
+            }
+            let run = texture(start);
+            let end = (start + 1..range.end)
+                .find(|&index| texture(index) != run)
+                .unwrap_or(range.end);
+            let instances = start..end;
+            start = end;
+            Some((run, instances))
+        })
+    }
+}
@@ -0,0 +1,104 @@
+//! The vectors, matrices and rotations the renderer is written with, which everything imports from here
+//! rather than from cgmath directly so that there's one place deciding where they come from.
+//! With the `glam` feature they convert to and from glam's, which most of the wgpu ecosystem uses,
+//! so an app written with glam can drive the camera and instances without doing the maths twice
+
+pub use cgmath::{
+    Deg, ElementWise, EuclideanSpace, InnerSpace, Matrix, Matrix3, Matrix4, Point3, Quaternion,
+    Rad, Rotation, Rotation3, SquareMatrix, Vector3, Vector4, VectorSpace, Zero,
+};
+
+/// Convert one of our maths types into glam's equivalent
+#[cfg(feature = "glam")]
+pub trait ToGlam {
+    type Glam;
+
+    fn to_glam(&self) -> Self::Glam;
+}
+
+/// Convert glam's equivalent of one of our maths types into it
+#[cfg(feature = "glam")]
+pub trait FromGlam<T> {
+    fn from_glam(value: T) -> Self;
+}
+
+/// Both ways between a type and a glam type, through an array they both convert to and from
+#[cfg(feature = "glam")]
+macro_rules! glam_conversions {
+    ($ours:ty, $glam:ty, $array:ty, $from_array:expr, $to_array:expr) => {
+        impl ToGlam for $ours {
+            type Glam = $glam;
+
+            fn to_glam(&self) -> $glam {
+                let array: $array = (*self).into();
+                $from_array(array)
+            }
+        }
+
+        impl FromGlam<$glam> for $ours {
+            fn from_glam(value: $glam) -> Self {
+                let array: $array = $to_array(&value);
+                array.into()
+            }
+        }
+    };
+}
+
+#[cfg(feature = "glam")]
+glam_conversions!(
+    Vector3<f32>,
+    glam::Vec3,
+    [f32; 3],
+    glam::Vec3::from_array,
+    glam::Vec3::to_array
+);
+#[cfg(feature = "glam")]
+glam_conversions!(
+    Point3<f32>,
+    glam::Vec3,
+    [f32; 3],
+    glam::Vec3::from_array,
+    glam::Vec3::to_array
+);
+#[cfg(feature = "glam")]
+glam_conversions!(
+    Vector4<f32>,
+    glam::Vec4,
+    [f32; 4],
+    glam::Vec4::from_array,
+    glam::Vec4::to_array
+);
+// both column-major
+#[cfg(feature = "glam")]
+glam_conversions!(
+    Matrix3<f32>,
+    glam::Mat3,
+    [[f32; 3]; 3],
+    |array| glam::Mat3::from_cols_array_2d(&array),
+    |matrix: &glam::Mat3| matrix.to_cols_array_2d()
+);
+#[cfg(feature = "glam")]
+glam_conversions!(
+    Matrix4<f32>,
+    glam::Mat4,
+    [[f32; 4]; 4],
+    |array| glam::Mat4::from_cols_array_2d(&array),
+    |matrix: &glam::Mat4| matrix.to_cols_array_2d()
+);
+
+/// cgmath keeps the scalar part first, glam last
+#[cfg(feature = "glam")]
+impl ToGlam for Quaternion<f32> {
+    type Glam = glam::Quat;
+
+    fn to_glam(&self) -> glam::Quat {
+        glam::Quat::from_xyzw(self.v.x, self.v.y, self.v.z, self.s)
+    }
+}
+
+#[cfg(feature = "glam")]
+impl FromGlam<glam::Quat> for Quaternion<f32> {
+    fn from_glam(value: glam::Quat) -> Self {
+        Quaternion::new(value.w, value.x, value.y, value.z)
+    }
+}
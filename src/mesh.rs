@@ -0,0 +1,154 @@
+use std::ops::Range;
+
+use wgpu::{Device, IndexFormat, PrimitiveTopology, Queue, RenderPass};
+
+use crate::{
+    buffer_pool::{Allocation, BufferPool},
+    geometry::{Aabb, Sphere},
+    math::Point3,
+    morph::MorphTargets,
+    normals::NormalLines,
+    skin::Skin,
+    vertex::{SkinnedVertex, Vertex},
+};
+
+/// A triangle mesh whose vertices and indices live in shared `BufferPool`s
+pub struct Mesh {
+    /// The mesh's vertices within the vertex pool
+    vertex_buffer: Allocation,
+    /// Indices into `vertex_buffer` which allow for deduplication of vertices
+    index_buffer: Allocation,
+    /// The number of indices in `index_buffer`
+    num_indices: u32,
+    /// Whether the vertices are `SkinnedVertex`s rather than `Vertex`s,
+    /// which need the skinned variant of each pipeline
+    skinned: bool,
+    /// How the indices are joined up, see `Mesh::with_topology`
+    topology: PrimitiveTopology,
+    /// Around the vertices, in the mesh's own space
+    bounds: Sphere,
+    aabb: Aabb,
+}
+
+impl Mesh {
+    /// Upload `vertices` and `indices` into the pools
+    #[tracing::instrument(skip_all, fields(vertices = vertices.len(), indices = indices.len()))]
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        vertex_pool: &mut BufferPool,
+        index_pool: &mut BufferPool,
+        vertices: &[Vertex],
+        indices: &[u16],
+    ) -> Self {
+        let positions = vertices
+            .iter()
+            .map(|vertex| Point3::from(vertex.position()))
+            .collect::<Vec<_>>();
+        Self {
+            vertex_buffer: vertex_pool.allocate_init(device, queue, bytemuck::cast_slice(vertices)),
+            index_buffer: index_pool.allocate_init(device, queue, bytemuck::cast_slice(indices)),
+            num_indices: indices.len() as u32,
+            skinned: false,
+            topology: PrimitiveTopology::TriangleList,
+            bounds: Sphere::around(&positions),
+            aabb: Aabb::around(&positions),
+        }
+    }
+
+    /// Upload a skinned mesh's `vertices` and `indices` into the pools
+    #[tracing::instrument(skip_all, fields(vertices = vertices.len(), indices = indices.len()))]
+    pub fn new_skinned(
+        device: &Device,
+        queue: &Queue,
+        vertex_pool: &mut BufferPool,
+        index_pool: &mut BufferPool,
+        vertices: &[SkinnedVertex],
+        indices: &[u16],
+    ) -> Self {
+        let positions = vertices
+            .iter()
+            .map(|vertex| Point3::from(vertex.position()))
+            .collect::<Vec<_>>();
+        Self {
+            vertex_buffer: vertex_pool.allocate_init(device, queue, bytemuck::cast_slice(vertices)),
+            index_buffer: index_pool.allocate_init(device, queue, bytemuck::cast_slice(indices)),
+            num_indices: indices.len() as u32,
+            skinned: true,
+            topology: PrimitiveTopology::TriangleList,
+            bounds: Sphere::around(&positions),
+            aabb: Aabb::around(&positions),
+        }
+    }
+
+    pub fn is_skinned(&self) -> bool {
+        self.skinned
+    }
+
+    /// A sphere around the mesh's vertices in its own space, as they were uploaded,
+    /// so skinned meshes are bounded in their bind pose and morphed ones without their targets
+    pub fn bounds(&self) -> Sphere {
+        self.bounds
+    }
+
+    /// The box around the mesh's vertices in its own space, like `bounds`
+    pub fn aabb(&self) -> Aabb {
+        self.aabb
+    }
+
+    /// Join the indices up as `topology` rather than as a list of triangles, e.g. `LineList` for a wireframe.
+    /// Only the scene's own pipelines draw anything else, so only triangles are glass, outlined or cast shadows,
+    /// and skinned and morphed meshes are always triangles
+    pub fn with_topology(mut self, topology: PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn topology(&self) -> PrimitiveTopology {
+        self.topology
+    }
+
+    /// Whether the mesh is a list of triangles, which is all most pipelines draw
+    pub fn is_triangle_list(&self) -> bool {
+        self.topology == PrimitiveTopology::TriangleList
+    }
+
+    /// Draw `instances` of the mesh with whatever pipeline, bind groups and instance buffer
+    /// `render_pass` currently has set, the pools must be the ones the mesh was created with
+    pub fn draw<'a>(
+        &self,
+        render_pass: &mut RenderPass<'a>,
+        vertex_pool: &'a BufferPool,
+        index_pool: &'a BufferPool,
+        instances: Range<u32>,
+    ) {
+        render_pass.set_vertex_buffer(0, vertex_pool.slice(&self.vertex_buffer));
+        render_pass.set_index_buffer(index_pool.slice(&self.index_buffer), IndexFormat::Uint16);
+        render_pass.draw_indexed(0..self.num_indices, 0, instances);
+    }
+}
+
+/// A mesh along with which of the instance buffer's instances it is drawn with
+pub struct Model {
+    pub mesh: Mesh,
+    pub instances: Range<u32>,
+    /// The pose of a skinned mesh, which every other mesh goes without
+    pub skin: Option<Skin>,
+    /// The shapes the mesh blends between, drawn with the morphed variant of each pipeline.
+    /// There's no variant which is both skinned and morphed, so this is only for unskinned meshes
+    pub morph: Option<MorphTargets>,
+    /// Lines along the mesh's vertex normals, for `NormalView` to show. Skinned meshes go without
+    pub normals: Option<NormalLines>,
+}
+
+impl Model {
+    pub fn draw<'a>(
+        &self,
+        render_pass: &mut RenderPass<'a>,
+        vertex_pool: &'a BufferPool,
+        index_pool: &'a BufferPool,
+    ) {
+        self.mesh
+            .draw(render_pass, vertex_pool, index_pool, self.instances.clone());
+    }
+}
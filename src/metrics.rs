@@ -0,0 +1,267 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+/// How long a frame took, in milliseconds
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameTimings {
+    /// The frame's number, counting from 0
+    pub frame: u64,
+    /// From the end of the last frame to the end of this one, which includes waiting for the display
+    pub frame_ms: f64,
+    /// Spent updating and recording the frame on the CPU
+    pub cpu_ms: f64,
+    /// Spent by the GPU on the frame's commands, if the device has timestamp queries and the frame was timed
+    pub gpu_ms: Option<f64>,
+}
+
+/// The timings so far, which the remote server exports for Prometheus to scrape
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MetricsSummary {
+    pub frames: u64,
+    pub last: Option<FrameTimings>,
+    pub total_frame_ms: f64,
+    pub total_cpu_ms: f64,
+    pub total_gpu_ms: f64,
+}
+
+impl MetricsSummary {
+    /// The summary in Prometheus' text format
+    pub fn to_prometheus(&self) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: f64| {
+            writeln!(
+                text,
+                "# HELP wgpu_cube_{name} {help}\n# TYPE wgpu_cube_{name} {kind}\nwgpu_cube_{name} {value}"
+            )
+            .expect("writing to a string can't fail")
+        };
+        metric(
+            "frames_total",
+            "counter",
+            "Frames rendered.",
+            self.frames as f64,
+        );
+        metric(
+            "frame_seconds_total",
+            "counter",
+            "Time spent on every frame, including waiting for the display.",
+            self.total_frame_ms / 1e3,
+        );
+        metric(
+            "cpu_seconds_total",
+            "counter",
+            "CPU time spent updating and recording frames.",
+            self.total_cpu_ms / 1e3,
+        );
+        metric(
+            "gpu_seconds_total",
+            "counter",
+            "GPU time spent on the frames which were timed.",
+            self.total_gpu_ms / 1e3,
+        );
+        if let Some(last) = &self.last {
+            metric(
+                "last_frame_seconds",
+                "gauge",
+                "Time spent on the last frame.",
+                last.frame_ms / 1e3,
+            );
+            metric(
+                "last_cpu_seconds",
+                "gauge",
+                "CPU time spent on the last frame.",
+                last.cpu_ms / 1e3,
+            );
+            if let Some(gpu_ms) = last.gpu_ms {
+                metric(
+                    "last_gpu_seconds",
+                    "gauge",
+                    "GPU time spent on the last frame which was timed.",
+                    gpu_ms / 1e3,
+                );
+            }
+        }
+        text
+    }
+}
+
+/// How frames are written to the metrics file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricsFormat {
+    /// A header, then a row for each frame
+    Csv,
+    /// An object on each line
+    JsonLines,
+}
+
+impl MetricsFormat {
+    /// `.json` and `.jsonl` files get a line of JSON for each frame, anything else is CSV
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json" | "jsonl") => Self::JsonLines,
+            _ => Self::Csv,
+        }
+    }
+}
+
+/// Collects every frame's timings into a `MetricsSummary`, and writes them to a file with `--metrics`
+/// so that they can be graphed. GPU times arrive a few frames late, so frames are held back until theirs do
+pub struct Metrics {
+    output: Option<(BufWriter<File>, MetricsFormat)>,
+    summary: Arc<Mutex<MetricsSummary>>,
+    /// Frames waiting for their GPU times
+    pending: VecDeque<FrameTimings>,
+    /// Whether GPU times are coming, otherwise frames are finished straight away
+    gpu_timed: bool,
+    last_frame: Option<Instant>,
+}
+
+impl Metrics {
+    /// The number of frames to wait for a GPU time before giving up on it, e.g. because every query was in use
+    const MAX_GPU_LAG: usize = 8;
+
+    pub fn new() -> Self {
+        Self {
+            output: None,
+            summary: Arc::default(),
+            pending: VecDeque::new(),
+            gpu_timed: false,
+            last_frame: None,
+        }
+    }
+
+    /// Write every frame's timings to `path` from now on, in the format its extension asks for
+    pub fn write_to(&mut self, path: &Path) -> Result<()> {
+        let format = MetricsFormat::from_path(path);
+        let file =
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        if format == MetricsFormat::Csv {
+            writeln!(writer, "frame,frame_ms,cpu_ms,gpu_ms")?;
+        }
+        self.output = Some((writer, format));
+        Ok(())
+    }
+
+    /// Whether to wait for GPU times from `gpu_time`, see `State::gpu_frame_times`
+    pub fn set_gpu_timed(&mut self, gpu_timed: bool) {
+        self.gpu_timed = gpu_timed;
+    }
+
+    /// The timings so far, shared with anything exporting them
+    pub fn summary(&self) -> Arc<Mutex<MetricsSummary>> {
+        self.summary.clone()
+    }
+
+    /// Record that `frame` has been presented, having spent `cpu` on the CPU
+    pub fn frame_finished(&mut self, frame: u64, cpu: Duration) {
+        let now = Instant::now();
+        let frame_ms = self
+            .last_frame
+            .map_or(0.0, |last_frame| (now - last_frame).as_secs_f64() * 1e3);
+        self.last_frame = Some(now);
+        self.pending.push_back(FrameTimings {
+            frame,
+            frame_ms,
+            cpu_ms: cpu.as_secs_f64() * 1e3,
+            gpu_ms: None,
+        });
+        self.flush();
+    }
+
+    /// Record the GPU time of a frame already passed to `frame_finished`
+    pub fn gpu_time(&mut self, frame: u64, gpu_ms: f64) {
+        if let Some(timings) = self
+            .pending
+            .iter_mut()
+            .find(|timings| timings.frame == frame)
+        {
+            timings.gpu_ms = Some(gpu_ms);
+        }
+        self.flush();
+    }
+
+    /// Finish every frame and write them out, whether or not their GPU times have arrived,
+    /// for when no more frames are coming
+    pub fn close(&mut self) {
+        self.gpu_timed = false;
+        self.flush();
+        if let Some((writer, _)) = &mut self.output {
+            if let Err(error) = writer.flush() {
+                tracing::error!("Failed to write the frame metrics: {error}");
+            }
+        }
+    }
+
+    /// Finish the frames which aren't waiting for a GPU time any more, oldest first
+    fn flush(&mut self) {
+        while let Some(timings) = self.pending.front() {
+            let waiting = self.gpu_timed
+                && timings.gpu_ms.is_none()
+                && self.pending.len() <= Self::MAX_GPU_LAG;
+            if waiting {
+                break;
+            }
+            let timings = self.pending.pop_front().expect("there's a pending frame");
+            self.finish(timings);
+        }
+    }
+
+    fn finish(&mut self, timings: FrameTimings) {
+        {
+            let mut summary = self.summary.lock().expect("the summary is never poisoned");
+            summary.frames += 1;
+            summary.total_frame_ms += timings.frame_ms;
+            summary.total_cpu_ms += timings.cpu_ms;
+            summary.total_gpu_ms += timings.gpu_ms.unwrap_or(0.0);
+            summary.last = Some(timings);
+        }
+        let Some((writer, format)) = &mut self.output else {
+            return;
+        };
+        let FrameTimings {
+            frame,
+            frame_ms,
+            cpu_ms,
+            gpu_ms,
+        } = timings;
+        let written = match format {
+            MetricsFormat::Csv => {
+                let gpu_ms = gpu_ms.map(|ms| format!("{ms:.4}")).unwrap_or_default();
+                writeln!(writer, "{frame},{frame_ms:.4},{cpu_ms:.4},{gpu_ms}")
+            }
+            MetricsFormat::JsonLines => {
+                let gpu_ms = gpu_ms.map_or("null".to_string(), |ms| format!("{ms:.4}"));
+                writeln!(
+                    writer,
+                    "{{\"frame\":{frame},\"frame_ms\":{frame_ms:.4},\"cpu_ms\":{cpu_ms:.4},\"gpu_ms\":{gpu_ms}}}"
+                )
+            }
+        };
+        if let Err(error) = written {
+            tracing::error!("Failed to write the frame metrics, so they've stopped: {error}");
+            self.output = None;
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Metrics {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
@@ -0,0 +1,346 @@
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    BindGroup, BindGroupEntry, BindGroupLayout, BindingResource, ColorTargetState, ColorWrites,
+    CompareFunction, DepthBiasState, DepthStencilState, Device, Face, FilterMode, FragmentState,
+    PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPass, RenderPipelineDescriptor, Sampler,
+    SamplerDescriptor, ShaderModuleDescriptor, ShaderSource, StencilState, TextureFormat,
+    VertexState,
+};
+
+use crate::{
+    buffer_pool::BufferPool,
+    camera::Camera,
+    geometry::Plane,
+    math::{InnerSpace, Matrix4, Point3, Vector3},
+    mesh::Mesh,
+    pipeline::ScenePipeline,
+    planar_reflection::PlanarReflection,
+    reflection::ShaderReflection,
+    shader::{preprocess, ShaderDefs, ShaderLibrary},
+    uniform::UniformBuffer,
+    vertex::{Vertex, FLOOR_INDICES},
+};
+
+/// Where a mirror is and how it looks, which can be changed at any time through `Mirror::settings_mut`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MirrorSettings {
+    pub center: Point3<f32>,
+    /// The way the reflective side faces, the other side isn't drawn
+    pub normal: Vector3<f32>,
+    /// Which way is up along the mirror, for turning it about `normal`
+    pub up: Vector3<f32>,
+    pub width: f32,
+    pub height: f32,
+    /// Linear RGB the reflection is multiplied by, a little under white like a real mirror
+    pub tint: [f32; 3],
+}
+
+impl Default for MirrorSettings {
+    /// Standing behind the cubes, facing them
+    fn default() -> Self {
+        Self {
+            center: Point3::new(0.0, 1.5, -6.0),
+            normal: Vector3::unit_z(),
+            up: Vector3::unit_y(),
+            width: 8.0,
+            height: 4.0,
+            tint: [0.9, 0.9, 0.9],
+        }
+    }
+}
+
+impl MirrorSettings {
+    /// The unit square on the xy plane moved to where the mirror is, facing along `normal`
+    fn model(&self) -> Matrix4<f32> {
+        let normal = self.normal.normalize();
+        let up = (self.up - normal * self.up.dot(normal)).normalize();
+        let right = up.cross(normal);
+        Matrix4::from_cols(
+            (right * self.width).extend(0.0),
+            (up * self.height).extend(0.0),
+            normal.extend(0.0),
+            self.center.to_homogeneous(),
+        )
+    }
+
+    /// How far the mirror's plane is along `normal` from the origin
+    fn distance(&self) -> f32 {
+        self.normal
+            .normalize()
+            .dot(self.center.to_homogeneous().truncate())
+    }
+
+    /// Whether `point` is on the reflective side of the mirror
+    pub fn faces(&self, point: Point3<f32>) -> bool {
+        self.normal
+            .normalize()
+            .dot(point.to_homogeneous().truncate())
+            > self.distance()
+    }
+
+    /// `camera` reflected in the mirror's plane. Its up direction is reflected along with it, which keeps it
+    /// from being mirrored itself, so that the winding order of every triangle is unchanged and back faces
+    /// are still culled. Instead the picture it sees is flipped horizontally, which `mirror.wgsl` flips back
+    pub fn mirrored(&self, camera: &Camera) -> Camera {
+        let normal = self.normal.normalize();
+        let distance = self.distance();
+        let reflect_point = |point: Point3<f32>| {
+            point - normal * (2.0 * (normal.dot(point.to_homogeneous().truncate()) - distance))
+        };
+        Camera {
+            eye: reflect_point(camera.eye),
+            target: reflect_point(camera.target),
+            up: camera.up - normal * (2.0 * normal.dot(camera.up)),
+            ..*camera
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct MirrorUniform {
+    model: [[f32; 4]; 4],
+    tint: [f32; 3],
+    _padding: f32,
+}
+
+impl MirrorUniform {
+    fn new(settings: &MirrorSettings) -> Self {
+        Self {
+            model: settings.model().into(),
+            tint: settings.tint,
+            _padding: 0.0,
+        }
+    }
+}
+
+/// A flat, rectangular mirror which reflects the scene in front of it, see `PlanarReflection`.
+/// It isn't drawn in other reflections, e.g. the water's, and doesn't see other mirrors
+pub struct Mirror {
+    settings: MirrorSettings,
+    uniform: UniformBuffer<MirrorUniform>,
+    /// A unit square, moved into place by the shader
+    mesh: Mesh,
+    planar_reflection: PlanarReflection,
+    sampler: Sampler,
+    reflection: ShaderReflection,
+    layout: BindGroupLayout,
+    bind_group: BindGroup,
+    pipeline: ScenePipeline,
+}
+
+impl Mirror {
+    /// `camera_layout` is the scene's, `formats` and `depth_format` must match the scene pass
+    /// and `width` and `height` are the size the scene is rendered at, which the reflection is rendered at too
+    #[allow(clippy::too_many_arguments)]
+    #[tracing::instrument(skip_all)]
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        library: &ShaderLibrary,
+        settings: MirrorSettings,
+        camera_layout: &BindGroupLayout,
+        formats: &[TextureFormat],
+        depth_format: TextureFormat,
+        vertex_pool: &mut BufferPool,
+        index_pool: &mut BufferPool,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        let name = "mirror.wgsl";
+        let source = preprocess(&library.resolve(name)?, &ShaderDefs::new())?;
+        let reflection = ShaderReflection::from_code(&source.clone().into(), &ShaderDefs::new())
+            .with_context(|| format!("failed to reflect {name}"))?;
+        let layout =
+            reflection.create_bind_group_layout(device, 0, Some("mirror_bind_group_layout"));
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(name),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Mirror Pipeline Layout"),
+            bind_group_layouts: &[&layout, camera_layout],
+            push_constant_ranges: &[],
+        });
+        let targets = formats
+            .iter()
+            .map(|&format| {
+                Some(ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })
+            })
+            .collect::<Vec<_>>();
+        let pipeline = ScenePipeline::new(device, move |device, multisample| {
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(name),
+                layout: Some(&pipeline_layout),
+                vertex: VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[Vertex::desc()],
+                },
+                fragment: Some(FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &targets,
+                }),
+                primitive: PrimitiveState {
+                    cull_mode: Some(Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: Some(DepthStencilState {
+                    format: depth_format,
+                    depth_write_enabled: true,
+                    depth_compare: CompareFunction::Less,
+                    stencil: StencilState::default(),
+                    bias: DepthBiasState::default(),
+                }),
+                multisample,
+                multiview: None,
+            })
+        });
+
+        // the water's square stood up, so that it faces along z
+        #[rustfmt::skip]
+        let vertices = [
+            Vertex::new([-0.5, 0.5, 0.0], [0.0, 0.0], [0.0, 0.0, 1.0]),
+            Vertex::new([0.5, 0.5, 0.0], [1.0, 0.0], [0.0, 0.0, 1.0]),
+            Vertex::new([-0.5, -0.5, 0.0], [0.0, 1.0], [0.0, 0.0, 1.0]),
+            Vertex::new([0.5, -0.5, 0.0], [1.0, 1.0], [0.0, 0.0, 1.0]),
+        ];
+        let mesh = Mesh::new(
+            device,
+            queue,
+            vertex_pool,
+            index_pool,
+            &vertices,
+            FLOOR_INDICES,
+        );
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Mirror Sampler"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+        let uniform =
+            UniformBuffer::new(device, MirrorUniform::new(&settings), Some("Mirror Buffer"));
+        let planar_reflection =
+            PlanarReflection::new(device, camera_layout, "Mirror Reflection", width, height);
+        let bind_group = create_bind_group(
+            device,
+            &reflection,
+            &layout,
+            &uniform,
+            &planar_reflection,
+            &sampler,
+        )?;
+
+        Ok(Self {
+            settings,
+            uniform,
+            mesh,
+            planar_reflection,
+            sampler,
+            reflection,
+            layout,
+            bind_group,
+            pipeline,
+        })
+    }
+
+    pub fn settings(&self) -> &MirrorSettings {
+        &self.settings
+    }
+
+    /// Move the mirror or change its tint, which takes effect from the next `update`
+    pub fn settings_mut(&mut self) -> &mut MirrorSettings {
+        &mut self.settings
+    }
+
+    /// Render the reflection at `width` by `height` from now on, the size of the scene
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        self.planar_reflection.resize(device, width, height);
+        self.bind_group = create_bind_group(
+            device,
+            &self.reflection,
+            &self.layout,
+            &self.uniform,
+            &self.planar_reflection,
+            &self.sampler,
+        )
+        .expect("the mirror's bindings don't change size");
+    }
+
+    /// Draw into a scene pass whose targets have `sample_count` samples per pixel from now on,
+    /// and render the reflection with as many, as it's drawn with the scene's pipelines
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        self.pipeline.set_sample_count(device, sample_count);
+        self.planar_reflection
+            .set_sample_count(device, sample_count);
+    }
+
+    /// Move the mirror to where its settings say and reflect `camera` in it,
+    /// cutting away what's behind any of the scene's `clip_planes` in the reflection too
+    pub fn update(&mut self, queue: &Queue, camera: &Camera, clip_planes: &[Plane]) {
+        self.uniform.set(&MirrorUniform::new(&self.settings));
+        self.uniform.write(queue);
+        let surface = Plane {
+            normal: self.settings.normal.normalize(),
+            distance: self.settings.distance(),
+        };
+        self.planar_reflection
+            .update(queue, &self.settings.mirrored(camera), surface, clip_planes);
+    }
+
+    /// The scene in front of the mirror, which has to be rendered before the mirror is drawn.
+    /// There's no need to when `camera` is behind it, as only the front is drawn
+    pub fn planar_reflection(&self, camera: &Camera) -> Option<&PlanarReflection> {
+        self.settings
+            .faces(camera.eye)
+            .then_some(&self.planar_reflection)
+    }
+
+    /// Draw the mirror into the scene pass, with the scene's camera bound at group 1
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        vertex_pool: &'a BufferPool,
+        index_pool: &'a BufferPool,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        self.mesh.draw(render_pass, vertex_pool, index_pool, 0..1);
+    }
+}
+
+fn create_bind_group(
+    device: &Device,
+    reflection: &ShaderReflection,
+    layout: &BindGroupLayout,
+    uniform: &UniformBuffer<MirrorUniform>,
+    planar_reflection: &PlanarReflection,
+    sampler: &Sampler,
+) -> Result<BindGroup> {
+    reflection.create_bind_group(
+        device,
+        0,
+        layout,
+        &[
+            uniform.bind_group_entry(0),
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(planar_reflection.view()),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::Sampler(sampler),
+            },
+        ],
+        Some("mirror_bind_group"),
+    )
+}
@@ -0,0 +1,58 @@
+// A flat mirror, showing the scene as seen by the camera reflected in it
+
+#include "camera.wgsl"
+
+struct MirrorUniform {
+    // places the unit square on the xy plane where the mirror is, facing along z
+    model: mat4x4<f32>,
+    // what the reflection is multiplied by
+    tint: vec3<f32>,
+}
+@group(0) @binding(0)
+var<uniform> mirror: MirrorUniform;
+// The scene in front of the mirror as seen by the reflected camera, which is flipped horizontally from our point of view
+@group(0) @binding(1)
+var t_reflection: texture_2d<f32>;
+@group(0) @binding(2)
+var s_reflection: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) current_position: vec4<f32>,
+    @location(1) previous_position: vec4<f32>,
+    @location(2) world_position: vec3<f32>,
+}
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>) -> VertexOutput {
+    let world_position = mirror.model * vec4<f32>(position, 1.0);
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.current_position = out.clip_position;
+    out.previous_position = camera.prev_view_proj * world_position;
+    out.world_position = world_position.xyz;
+    return out;
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec2<f32>,
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    if is_clipped(in.world_position) {
+        discard;
+    }
+    let current = in.current_position.xy / in.current_position.w;
+    // texture co-ordinates go downwards, and the reflection is flipped horizontally
+    let reflection_uv = current * vec2<f32>(-0.5, -0.5) + 0.5;
+    let reflection = textureSample(t_reflection, s_reflection, reflection_uv).rgb;
+
+    var out: FragmentOutput;
+    // the reflection was fogged over the whole distance to what's reflected, through the mirror
+    out.color = vec4<f32>(reflection * mirror.tint, 1.0);
+    let previous = in.previous_position.xy / in.previous_position.w;
+    out.velocity = (current - previous) * vec2<f32>(0.5, -0.5);
+    return out;
+}
@@ -0,0 +1,241 @@
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferUsages, Device, Queue,
+    ShaderStages,
+};
+
+use crate::{shader::ShaderDefs, uniform::UniformBuffer};
+
+/// The most targets a single mesh can have, which is the size of the weights array in `morph.wgsl`
+pub const MAX_MORPH_TARGETS: usize = 8;
+
+/// The defines which select the morphed permutation of a shader, with the targets in bind group `group`
+pub fn morphed_defs(defs: &ShaderDefs, group: u32) -> ShaderDefs {
+    defs.clone().flag("MORPHED").value("MORPH_GROUP", group)
+}
+
+/// One shape a mesh can be blended towards, as offsets from each of the mesh's vertices
+#[derive(Clone, Debug, Default)]
+pub struct MorphTarget {
+    pub position_deltas: Vec<[f32; 3]>,
+    /// Can be left empty if the target doesn't change the normals
+    pub normal_deltas: Vec<[f32; 3]>,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+struct MorphWeightsUniform {
+    weights: [[f32; 4]; MAX_MORPH_TARGETS / 4],
+    target_count: u32,
+    vertex_count: u32,
+    _padding: [u32; 2],
+}
+
+/// The layout of a delta in the storage buffer, where vec3s are padded out to 16 bytes
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+struct MorphDelta {
+    position: [f32; 4],
+    normal: [f32; 4],
+}
+
+/// A mesh's morph targets, along with how much of each is blended in.
+/// The deltas live in a storage buffer, so these need `Capabilities::vertex_storage`
+pub struct MorphTargets {
+    weights: UniformBuffer<MorphWeightsUniform>,
+    _deltas: Buffer,
+    bind_group: BindGroup,
+    animation: Option<MorphAnimation>,
+}
+
+impl MorphTargets {
+    /// The layout of every mesh's morph target bind group, which all morphed pipelines share
+    pub fn bind_group_layout(device: &Device) -> BindGroupLayout {
+        device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("morph_bind_group_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    /// Upload `targets` for a mesh with `vertex_count` vertices, with every weight starting at 0
+    pub fn new(
+        device: &Device,
+        layout: &BindGroupLayout,
+        vertex_count: usize,
+        targets: &[MorphTarget],
+    ) -> Result<Self> {
+        ensure!(!targets.is_empty(), "no morph targets");
+        ensure!(vertex_count > 0, "no vertices to morph");
+        ensure!(
+            targets.len() <= MAX_MORPH_TARGETS,
+            "a mesh can have at most {MAX_MORPH_TARGETS} morph targets, not {}",
+            targets.len()
+        );
+        let mut deltas = vec![MorphDelta::default(); targets.len() * vertex_count];
+        for (index, (target, deltas)) in targets
+            .iter()
+            .zip(deltas.chunks_mut(vertex_count))
+            .enumerate()
+        {
+            ensure!(
+                target.position_deltas.len() == vertex_count,
+                "morph target {index} has {} position deltas for {vertex_count} vertices",
+                target.position_deltas.len()
+            );
+            ensure!(
+                target.normal_deltas.is_empty() || target.normal_deltas.len() == vertex_count,
+                "morph target {index} has {} normal deltas for {vertex_count} vertices",
+                target.normal_deltas.len()
+            );
+            for (vertex, delta) in deltas.iter_mut().enumerate() {
+                let [x, y, z] = target.position_deltas[vertex];
+                delta.position = [x, y, z, 0.0];
+                if let Some(&[x, y, z]) = target.normal_deltas.get(vertex) {
+                    delta.normal = [x, y, z, 0.0];
+                }
+            }
+        }
+        let deltas = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Morph Target Deltas"),
+            contents: bytemuck::cast_slice(&deltas),
+            usage: BufferUsages::STORAGE,
+        });
+        let weights = UniformBuffer::new(
+            device,
+            MorphWeightsUniform {
+                target_count: targets.len() as u32,
+                vertex_count: vertex_count as u32,
+                ..Default::default()
+            },
+            Some("Morph Weights"),
+        );
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("morph_bind_group"),
+            layout,
+            entries: &[
+                weights.bind_group_entry(0),
+                BindGroupEntry {
+                    binding: 1,
+                    resource: deltas.as_entire_binding(),
+                },
+            ],
+        });
+        Ok(Self {
+            weights,
+            _deltas: deltas,
+            bind_group,
+            animation: None,
+        })
+    }
+
+    pub fn target_count(&self) -> usize {
+        self.weights.get().target_count as usize
+    }
+
+    /// How much of each target is blended in, where weights past the end of `weights` are left as they were
+    pub fn set_weights(&mut self, weights: &[f32]) {
+        let count = self.target_count();
+        let uniform = self.weights.get_mut();
+        for (index, &weight) in weights.iter().enumerate().take(count) {
+            uniform.weights[index / 4][index % 4] = weight;
+        }
+    }
+
+    /// Drive the weights from `animation` from now on, or stop animating them
+    pub fn set_animation(&mut self, animation: Option<MorphAnimation>) {
+        self.animation = animation;
+    }
+
+    /// Set the weights to where the animation is at `time` seconds, if there is one
+    pub fn animate(&mut self, time: f32) {
+        if let Some(weights) = self
+            .animation
+            .as_ref()
+            .map(|animation| animation.sample(time))
+        {
+            self.set_weights(&weights);
+        }
+    }
+
+    /// Upload the weights if they've changed since the last write
+    pub fn write(&mut self, queue: &Queue) {
+        self.weights.write(queue);
+    }
+
+    pub fn bind_group(&self) -> &BindGroup {
+        &self.bind_group
+    }
+}
+
+/// Keyframed weights for `MorphTargets`, linearly interpolated between keyframes and looped
+#[derive(Clone, Debug)]
+pub struct MorphAnimation {
+    /// Each keyframe's time in seconds and the weights at that time, in order of time
+    keyframes: Vec<(f32, Vec<f32>)>,
+}
+
+impl MorphAnimation {
+    /// `keyframes` are each a time in seconds and the weights at that time, and must be in order of time
+    pub fn new(keyframes: Vec<(f32, Vec<f32>)>) -> Result<Self> {
+        ensure!(!keyframes.is_empty(), "a morph animation needs a keyframe");
+        ensure!(
+            keyframes.windows(2).all(|pair| pair[0].0 < pair[1].0),
+            "morph animation keyframes must be in order of time"
+        );
+        Ok(Self { keyframes })
+    }
+
+    /// How long the animation takes before it loops
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |(time, _)| *time)
+    }
+
+    /// The weights `time` seconds into the animation
+    pub fn sample(&self, time: f32) -> Vec<f32> {
+        let duration = self.duration();
+        let time = if duration > 0.0 {
+            time.rem_euclid(duration)
+        } else {
+            0.0
+        };
+        let next = self.keyframes.partition_point(|(start, _)| *start <= time);
+        let Some((end, to)) = self.keyframes.get(next) else {
+            return self.keyframes[self.keyframes.len() - 1].1.clone();
+        };
+        let Some((start, from)) = next
+            .checked_sub(1)
+            .map(|previous| &self.keyframes[previous])
+        else {
+            return to.clone();
+        };
+        let t = (time - start) / (end - start);
+        from.iter()
+            .zip(to)
+            .map(|(from, to)| from + (to - from) * t)
+            .collect()
+    }
+}
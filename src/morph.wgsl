@@ -0,0 +1,40 @@
+// Morph targets, matching `MorphTargets`.
+// Only compiled in with `MORPHED`, which also defines `MORPH_GROUP` as the bind group they're in
+
+#ifdef MORPHED
+struct MorphWeights {
+    // 4 weights to a vec4, as arrays in uniforms have to be 16 byte aligned
+    weights: array<vec4<f32>, 2>,
+    target_count: u32,
+    vertex_count: u32,
+}
+@group(MORPH_GROUP) @binding(0)
+var<uniform> morph: MorphWeights;
+
+struct MorphDelta {
+    position: vec4<f32>,
+    normal: vec4<f32>,
+}
+// Every vertex's deltas for the first target, then the second, and so on
+@group(MORPH_GROUP) @binding(1)
+var<storage, read> morph_deltas: array<MorphDelta>;
+
+struct MorphedVertex {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+}
+
+// Add each target's deltas to the vertex's, scaled by the target's weight
+fn morph_vertex(vertex_index: u32, position: vec3<f32>, normal: vec3<f32>) -> MorphedVertex {
+    var out: MorphedVertex;
+    out.position = position;
+    out.normal = normal;
+    for (var morph_target = 0u; morph_target < morph.target_count; morph_target += 1u) {
+        let weight = morph.weights[morph_target / 4u][morph_target % 4u];
+        let delta = morph_deltas[morph_target * morph.vertex_count + vertex_index];
+        out.position += delta.position.xyz * weight;
+        out.normal += delta.normal.xyz * weight;
+    }
+    return out;
+}
+#endif
@@ -0,0 +1,229 @@
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    BindGroup, BindGroupLayout, BufferSlice, ColorTargetState, ColorWrites, CompareFunction,
+    DepthBiasState, DepthStencilState, Device, FragmentState, PipelineLayoutDescriptor,
+    PrimitiveState, PrimitiveTopology, Queue, RenderPass, RenderPipelineDescriptor,
+    ShaderModuleDescriptor, ShaderSource, StencilState, TextureFormat, VertexState,
+};
+
+use crate::{
+    buffer_pool::{Allocation, BufferPool},
+    instance::InstanceRaw,
+    mesh::Model,
+    pipeline::ScenePipeline,
+    reflection::ShaderReflection,
+    shader::{preprocess, ShaderDefs, ShaderLibrary},
+    uniform::UniformBuffer,
+    vertex::Vertex,
+};
+
+/// A line along each of a mesh's vertex normals, uploaded along with the mesh so that it can be shown
+/// whenever it's asked for, see `NormalView`
+pub struct NormalLines {
+    /// Both ends of every line, see `Vertex::normal_line`
+    vertex_buffer: Allocation,
+    num_vertices: u32,
+    /// Whether `NormalView` draws them
+    pub shown: bool,
+}
+
+impl NormalLines {
+    /// Upload the lines along `vertices`' normals into the vertex pool, hidden to begin with
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        vertex_pool: &mut BufferPool,
+        vertices: &[Vertex],
+    ) -> Self {
+        let lines = vertices
+            .iter()
+            .flat_map(Vertex::normal_line)
+            .collect::<Vec<_>>();
+        Self {
+            vertex_buffer: vertex_pool.allocate_init(device, queue, bytemuck::cast_slice(&lines)),
+            num_vertices: lines.len() as u32,
+            shown: false,
+        }
+    }
+}
+
+/// How the normals look, which can be changed at any time through `NormalView::settings_mut`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NormalViewSettings {
+    /// Linear RGB
+    pub color: [f32; 3],
+    /// How far each line reaches from its vertex, in world units
+    pub length: f32,
+}
+
+impl Default for NormalViewSettings {
+    fn default() -> Self {
+        Self {
+            color: [0.2, 0.6, 1.0],
+            length: 0.25,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct NormalViewUniform {
+    color: [f32; 3],
+    length: f32,
+}
+
+impl From<&NormalViewSettings> for NormalViewUniform {
+    fn from(settings: &NormalViewSettings) -> Self {
+        Self {
+            color: settings.color,
+            length: settings.length,
+        }
+    }
+}
+
+/// Draws each vertex normal of the models whose `NormalLines` are shown as a short line out from its vertex,
+/// for checking that a mesh's normals face the way they should. The lines are hidden behind anything
+/// in front of them, like the rest of the scene. Skinned meshes have none, as their vertices are only
+/// posed on the GPU, and morphed meshes' lines stay on their base shape
+pub struct NormalView {
+    settings: NormalViewSettings,
+    uniform: UniformBuffer<NormalViewUniform>,
+    bind_group: BindGroup,
+    pipeline: ScenePipeline,
+}
+
+impl NormalView {
+    /// `camera_layout` is the scene's, and `formats` and `depth_format` must match the scene pass
+    #[tracing::instrument(skip_all)]
+    pub fn new(
+        device: &Device,
+        library: &ShaderLibrary,
+        settings: NormalViewSettings,
+        camera_layout: &BindGroupLayout,
+        formats: &[TextureFormat],
+        depth_format: TextureFormat,
+    ) -> Result<Self> {
+        let name = "normals.wgsl";
+        let source = preprocess(&library.resolve(name)?, &ShaderDefs::new())?;
+        let reflection = ShaderReflection::from_code(&source.clone().into(), &ShaderDefs::new())
+            .with_context(|| format!("failed to reflect {name}"))?;
+        let layout =
+            reflection.create_bind_group_layout(device, 0, Some("normal_view_bind_group_layout"));
+        let uniform = UniformBuffer::new(
+            device,
+            NormalViewUniform::from(&settings),
+            Some("Normal View Buffer"),
+        );
+        let bind_group = reflection.create_bind_group(
+            device,
+            0,
+            &layout,
+            &[uniform.bind_group_entry(0)],
+            Some("normal_view_bind_group"),
+        )?;
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(name),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Normal View Pipeline Layout"),
+            bind_group_layouts: &[&layout, camera_layout],
+            push_constant_ranges: &[],
+        });
+        let targets = formats
+            .iter()
+            .map(|&format| {
+                Some(ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })
+            })
+            .collect::<Vec<_>>();
+        let pipeline = ScenePipeline::new(device, move |device, multisample| {
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(name),
+                layout: Some(&pipeline_layout),
+                vertex: VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[Vertex::desc(), InstanceRaw::desc()],
+                },
+                fragment: Some(FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &targets,
+                }),
+                primitive: PrimitiveState {
+                    topology: PrimitiveTopology::LineList,
+                    ..Default::default()
+                },
+                depth_stencil: Some(DepthStencilState {
+                    format: depth_format,
+                    // the lines are an overlay, which nothing else needs to be hidden behind
+                    depth_write_enabled: false,
+                    depth_compare: CompareFunction::Less,
+                    stencil: StencilState::default(),
+                    bias: DepthBiasState::default(),
+                }),
+                multisample,
+                multiview: None,
+            })
+        });
+
+        Ok(Self {
+            settings,
+            uniform,
+            bind_group,
+            pipeline,
+        })
+    }
+
+    pub fn settings(&self) -> &NormalViewSettings {
+        &self.settings
+    }
+
+    /// Change how the normals look, which takes effect from the next `update`
+    pub fn settings_mut(&mut self) -> &mut NormalViewSettings {
+        &mut self.settings
+    }
+
+    /// Draw into a scene pass whose targets have `sample_count` samples per pixel from now on
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        self.pipeline.set_sample_count(device, sample_count);
+    }
+
+    pub fn update(&mut self, queue: &Queue) {
+        self.uniform.set(&NormalViewUniform::from(&self.settings));
+        self.uniform.write(queue);
+    }
+
+    /// Draw the shown normals of `models` into the scene pass, with the scene's camera bound at group 1
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        models: &'a [Model],
+        instance_buffer: BufferSlice<'a>,
+        vertex_pool: &'a BufferPool,
+    ) {
+        let mut shown = models
+            .iter()
+            .filter_map(|model| {
+                let normals = model.normals.as_ref()?;
+                normals.shown.then_some((model, normals))
+            })
+            .peekable();
+        if shown.peek().is_none() {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(1, instance_buffer);
+        for (model, normals) in shown {
+            render_pass.set_vertex_buffer(0, vertex_pool.slice(&normals.vertex_buffer));
+            render_pass.draw(0..normals.num_vertices, model.instances.clone());
+        }
+    }
+}
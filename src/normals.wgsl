@@ -0,0 +1,48 @@
+// Short lines along meshes' vertex normals, matching `NormalViewUniform`. Both ends of each line are the same
+// vertex, with `along` 0.0 at the start, where the vertex is, and 1.0 at the end, `length` out along its normal
+
+#include "camera.wgsl"
+#include "instance.wgsl"
+
+struct NormalViewUniform {
+    // linear RGB
+    color: vec3<f32>,
+    // how far the lines reach, in world units
+    length: f32,
+}
+@group(0) @binding(0)
+var<uniform> normal_view: NormalViewUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    // only the first is used
+    @location(1) along: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+@vertex
+fn vs_main(vertex: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let world_position = (instance_model_matrix(instance) * vec4<f32>(vertex.position, 1.0)).xyz;
+    // the same length whatever the instance's scale
+    let world_normal = normalize(instance_normal_matrix(instance) * vertex.normal);
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position + world_normal * normal_view.length * vertex.along.x, 1.0);
+    return out;
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec2<f32>,
+}
+
+@fragment
+fn fs_main() -> FragmentOutput {
+    var out: FragmentOutput;
+    out.color = vec4<f32>(normal_view.color, 1.0);
+    out.velocity = vec2<f32>(0.0);
+    return out;
+}
@@ -0,0 +1,85 @@
+use anyhow::*;
+use image::RgbaImage;
+use wgpu::{CommandEncoderDescriptor, RequestAdapterOptions, TextureFormat};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    config::Config, recorder::CaptureTarget, state::State, transform::Transform, vertex::Vertex,
+};
+
+/// The renderer without a window, rendering each frame into a texture of its own and reading it back,
+/// e.g. for driving it from another language or generating images in bulk
+pub struct Offscreen {
+    pub state: State,
+    target: CaptureTarget,
+}
+
+impl Offscreen {
+    /// sRGB, so that the pixels read back are ready to be saved or shown
+    pub const FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+
+    /// Set up the scene described by `config`, rendering frames `width` by `height` pixels
+    pub async fn new(width: u32, height: u32, config: &Config) -> Result<Self> {
+        ensure!(
+            width > 0 && height > 0,
+            "can't render frames {width}x{height} pixels"
+        );
+        let backends = config.backends();
+        let instance = wgpu::Instance::new(backends);
+        let adapter = instance
+            .request_adapter(&RequestAdapterOptions::default())
+            .await
+            .with_context(|| format!("no adapter for {backends:?}"))?;
+        let (device, queue) = State::request_device(&adapter, config).await?;
+        let target = CaptureTarget::new(&device, Self::FORMAT, width, height, 1)?;
+        let state = State::with_device(
+            instance,
+            adapter,
+            device,
+            queue,
+            Self::FORMAT,
+            PhysicalSize::new(width, height),
+            1.0,
+            config,
+        );
+        Ok(Self { state, target })
+    }
+
+    /// Render frames `width` by `height` pixels from now on
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<()> {
+        ensure!(
+            width > 0 && height > 0,
+            "can't render frames {width}x{height} pixels"
+        );
+        self.target = CaptureTarget::new(&self.state.device, Self::FORMAT, width, height, 1)?;
+        self.state.resize(PhysicalSize::new(width, height));
+        Ok(())
+    }
+
+    /// Add a mesh to the scene, see `State::add_mesh`
+    pub fn add_mesh(
+        &mut self,
+        vertices: &[Vertex],
+        indices: &[u16],
+        transform: Transform,
+        tint: [f32; 3],
+    ) -> Result<usize> {
+        self.state.add_mesh(vertices, indices, transform, tint)
+    }
+
+    /// Move the scene along by a frame and render it, waiting for it to be read back
+    pub fn render(&mut self) -> RgbaImage {
+        self.state.tick();
+        self.state.update();
+        self.state.render_to(self.target.view());
+        let mut encoder = self
+            .state
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Offscreen Readback Encoder"),
+            });
+        self.target.copy(&mut encoder);
+        self.state.queue.submit(std::iter::once(encoder.finish()));
+        self.target.read(&self.state.device)
+    }
+}
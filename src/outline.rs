@@ -0,0 +1,304 @@
+use std::sync::Arc;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    BindGroup, BindGroupLayout, BufferSlice, ColorTargetState, ColorWrites, CompareFunction,
+    DepthBiasState, DepthStencilState, Device, Face, FragmentState, MultisampleState,
+    PipelineLayout, PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPass, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderSource, StencilFaceState,
+    StencilOperation, StencilState, TextureFormat, VertexState,
+};
+
+use crate::{
+    buffer_pool::BufferPool,
+    instance::InstanceRaw,
+    mesh::Model,
+    pipeline::ScenePipeline,
+    reflection::ShaderReflection,
+    shader::{preprocess, ShaderDefs, ShaderLibrary},
+    uniform::UniformBuffer,
+    vertex::Vertex,
+};
+
+/// How outlines look, which can be changed at any time through `Outline::settings_mut`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutlineSettings {
+    /// Linear RGB, where anything brighter than 1.0 is picked up by bloom
+    pub color: [f32; 3],
+    /// How far out the outline reaches from the instance, in world units
+    pub width: f32,
+}
+
+impl Default for OutlineSettings {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 0.6, 0.1],
+            width: 0.06,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct OutlineUniform {
+    color: [f32; 3],
+    width: f32,
+}
+
+impl From<&OutlineSettings> for OutlineUniform {
+    fn from(settings: &OutlineSettings) -> Self {
+        Self {
+            color: settings.color,
+            width: settings.width,
+        }
+    }
+}
+
+/// Outlines instances, e.g. to show which are picked out. Each is drawn into the stencil buffer first,
+/// marking its silhouette, then drawn again pushed out by
+/// `OutlineSettings::width`, only where it isn't marked, which leaves a ring around its silhouette.
+/// The outline is hidden behind anything in front of it, like the rest of the scene,
+/// but it's never drawn over the outlined instances themselves
+pub struct Outline {
+    settings: OutlineSettings,
+    /// Indices into the instance buffer, of instances of plain meshes
+    instances: Vec<u32>,
+    uniform: UniformBuffer<OutlineUniform>,
+    bind_group: BindGroup,
+    /// Marks where the instances are in the stencil buffer, without drawing any colour
+    mask_pipeline: ScenePipeline,
+    outline_pipeline: ScenePipeline,
+}
+
+impl Outline {
+    /// The stencil value the mask leaves where the instances are
+    const MASK: u32 = 1;
+
+    /// `camera_layout` is the scene's, and `formats` and `depth_format` must match the scene pass,
+    /// which has to have a stencil buffer
+    #[tracing::instrument(skip_all)]
+    pub fn new(
+        device: &Device,
+        library: &ShaderLibrary,
+        settings: OutlineSettings,
+        camera_layout: &BindGroupLayout,
+        formats: &[TextureFormat],
+        depth_format: TextureFormat,
+    ) -> Result<Self> {
+        let name = "outline.wgsl";
+        let source = preprocess(&library.resolve(name)?, &ShaderDefs::new())?;
+        let reflection = ShaderReflection::from_code(&source.clone().into(), &ShaderDefs::new())
+            .with_context(|| format!("failed to reflect {name}"))?;
+        let layout =
+            reflection.create_bind_group_layout(device, 0, Some("outline_bind_group_layout"));
+        let uniform = UniformBuffer::new(
+            device,
+            OutlineUniform::from(&settings),
+            Some("Outline Buffer"),
+        );
+        let bind_group = reflection.create_bind_group(
+            device,
+            0,
+            &layout,
+            &[uniform.bind_group_entry(0)],
+            Some("outline_bind_group"),
+        )?;
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(name),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Outline Pipeline Layout"),
+            bind_group_layouts: &[&layout, camera_layout],
+            push_constant_ranges: &[],
+        });
+        // each pipeline keeps the shader and layout for rebuilding itself
+        let shader = Arc::new(shader);
+        let pipeline_layout = Arc::new(pipeline_layout);
+        let formats = formats.to_vec();
+        let create_pipeline = |label, entry_point, color_writes, depth_compare, stencil| {
+            let (shader, pipeline_layout, formats) =
+                (shader.clone(), pipeline_layout.clone(), formats.clone());
+            ScenePipeline::new(device, move |device, multisample| {
+                create_pipeline(
+                    device,
+                    &shader,
+                    &pipeline_layout,
+                    label,
+                    entry_point,
+                    &formats,
+                    color_writes,
+                    depth_format,
+                    depth_compare,
+                    stencil,
+                    multisample,
+                )
+            })
+        };
+        let mask_pipeline = create_pipeline(
+            "Outline Mask Pipeline",
+            "vs_mask",
+            ColorWrites::empty(),
+            // the whole silhouette, even where something's in front of it,
+            // so that the outline only ever goes around the outside
+            CompareFunction::Always,
+            StencilFaceState {
+                compare: CompareFunction::Always,
+                fail_op: StencilOperation::Keep,
+                depth_fail_op: StencilOperation::Keep,
+                pass_op: StencilOperation::Replace,
+            },
+        );
+        let outline_pipeline = create_pipeline(
+            "Outline Pipeline",
+            "vs_outline",
+            ColorWrites::ALL,
+            CompareFunction::Less,
+            StencilFaceState {
+                compare: CompareFunction::NotEqual,
+                fail_op: StencilOperation::Keep,
+                depth_fail_op: StencilOperation::Keep,
+                pass_op: StencilOperation::Keep,
+            },
+        );
+
+        Ok(Self {
+            settings,
+            instances: Vec::new(),
+            uniform,
+            bind_group,
+            mask_pipeline,
+            outline_pipeline,
+        })
+    }
+
+    pub fn settings(&self) -> &OutlineSettings {
+        &self.settings
+    }
+
+    /// Change how the outlines look, which takes effect from the next `update`
+    pub fn settings_mut(&mut self) -> &mut OutlineSettings {
+        &mut self.settings
+    }
+
+    /// The instances which are outlined, by their index in the instance buffer
+    pub fn instances(&self) -> &[u32] {
+        &self.instances
+    }
+
+    /// Outline `instances` from now on, by their index in the instance buffer.
+    /// Only instances of plain meshes of triangles can be outlined, the rest are left as they are
+    pub fn set_instances(&mut self, instances: Vec<u32>) {
+        self.instances = instances;
+    }
+
+    /// Draw into a scene pass whose targets have `sample_count` samples per pixel from now on
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        self.mask_pipeline.set_sample_count(device, sample_count);
+        self.outline_pipeline.set_sample_count(device, sample_count);
+    }
+
+    pub fn update(&mut self, queue: &Queue) {
+        self.uniform.set(&OutlineUniform::from(&self.settings));
+        self.uniform.write(queue);
+    }
+
+    /// Draw the outlines into the scene pass, with the scene's camera bound at group 1.
+    /// This leaves the pass' stencil reference set to `MASK`
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        models: &'a [Model],
+        instance_buffer: BufferSlice<'a>,
+        vertex_pool: &'a BufferPool,
+        index_pool: &'a BufferPool,
+    ) {
+        if self.instances.is_empty() {
+            return;
+        }
+        let outlined = || {
+            self.instances.iter().filter_map(|&instance| {
+                let model = models.iter().find(|model| {
+                    model.instances.contains(&instance)
+                        && !model.mesh.is_skinned()
+                        && model.morph.is_none()
+                        && model.mesh.is_triangle_list()
+                })?;
+                Some((model, instance))
+            })
+        };
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(1, instance_buffer);
+        render_pass.set_stencil_reference(Self::MASK);
+        // Every mask goes first, so that no outline is drawn over another outlined instance
+        for pipeline in [&self.mask_pipeline, &self.outline_pipeline] {
+            render_pass.set_pipeline(pipeline);
+            for (model, instance) in outlined() {
+                model
+                    .mesh
+                    .draw(render_pass, vertex_pool, index_pool, instance..instance + 1);
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_pipeline(
+    device: &Device,
+    shader: &ShaderModule,
+    layout: &PipelineLayout,
+    label: &str,
+    vs_entry_point: &str,
+    formats: &[TextureFormat],
+    color_writes: ColorWrites,
+    depth_format: TextureFormat,
+    depth_compare: CompareFunction,
+    stencil: StencilFaceState,
+    multisample: MultisampleState,
+) -> RenderPipeline {
+    let targets = formats
+        .iter()
+        .map(|&format| {
+            Some(ColorTargetState {
+                format,
+                blend: None,
+                write_mask: color_writes,
+            })
+        })
+        .collect::<Vec<_>>();
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: vs_entry_point,
+            buffers: &[Vertex::desc(), InstanceRaw::desc()],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &targets,
+        }),
+        primitive: PrimitiveState {
+            cull_mode: Some(Face::Back),
+            ..Default::default()
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: depth_format,
+            // the outline is an overlay, which nothing else needs to be hidden behind
+            depth_write_enabled: false,
+            depth_compare,
+            stencil: StencilState {
+                front: stencil,
+                back: stencil,
+                read_mask: 0xff,
+                write_mask: 0xff,
+            },
+            bias: DepthBiasState::default(),
+        }),
+        multisample,
+        multiview: None,
+    })
+}
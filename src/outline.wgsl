@@ -0,0 +1,53 @@
+// Outlines around instances, matching `OutlineUniform`. The instances are first drawn into the stencil buffer
+// with `vs_mask`, then again pushed out by `vs_outline`, with only what's outside of the mask coloured in
+
+#include "camera.wgsl"
+#include "instance.wgsl"
+
+struct OutlineUniform {
+    // linear RGB, above 1.0 to glow with bloom
+    color: vec3<f32>,
+    // how far out the outline reaches, in world units
+    width: f32,
+}
+@group(0) @binding(0)
+var<uniform> outline: OutlineUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+fn outline_vertex(position: vec3<f32>, instance: InstanceInput, width: f32) -> VertexOutput {
+    let model_matrix = instance_model_matrix(instance);
+    let world_position = (model_matrix * vec4<f32>(position, 1.0)).xyz;
+    // Away from the instance's origin rather than along the normal, so that the corners of meshes with hard edges,
+    // whose faces have separate normals, stay joined. This suits convex meshes centred on their origin, like the cubes
+    let outwards = world_position - model_matrix[3].xyz;
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position + normalize(outwards) * width, 1.0);
+    return out;
+}
+
+@vertex
+fn vs_mask(@location(0) position: vec3<f32>, instance: InstanceInput) -> VertexOutput {
+    return outline_vertex(position, instance, 0.0);
+}
+
+@vertex
+fn vs_outline(@location(0) position: vec3<f32>, instance: InstanceInput) -> VertexOutput {
+    return outline_vertex(position, instance, outline.width);
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    // outlines shouldn't be motion blurred
+    @location(1) velocity: vec2<f32>,
+}
+
+@fragment
+fn fs_main() -> FragmentOutput {
+    var out: FragmentOutput;
+    out.color = vec4<f32>(outline.color, 1.0);
+    out.velocity = vec2<f32>(0.0);
+    return out;
+}
@@ -0,0 +1,59 @@
+use bytemuck::{Pod, Zeroable};
+
+/// Parallax occlusion mapping, which shifts where a surface's textures are sampled as if they were
+/// carved into it by its height map, giving flat faces depth without any extra geometry
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Parallax {
+    /// How deep the lowest parts of the height map are, as a fraction of the texture's width.
+    /// 0.0 turns it off
+    pub scale: f32,
+    /// How many steps the ray through the height map is split into when looking straight at the surface
+    pub min_steps: u32,
+    /// How many steps it's split into at grazing angles, where the ray travels furthest
+    pub max_steps: u32,
+}
+
+impl Default for Parallax {
+    fn default() -> Self {
+        Self {
+            scale: 0.05,
+            min_steps: 8,
+            max_steps: 32,
+        }
+    }
+}
+
+impl Parallax {
+    /// Without a height map there's nothing to shift the textures by
+    pub const OFF: Self = Self {
+        scale: 0.0,
+        min_steps: 0,
+        max_steps: 0,
+    };
+}
+
+/// The layout of `Parallax` expected by `parallax.wgsl`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+pub struct ParallaxUniform {
+    scale: f32,
+    min_steps: u32,
+    max_steps: u32,
+    _padding: u32,
+}
+
+impl ParallaxUniform {
+    pub fn update(&mut self, parallax: &Parallax) {
+        self.scale = parallax.scale;
+        self.min_steps = parallax.min_steps.max(1);
+        self.max_steps = parallax.max_steps.max(self.min_steps);
+    }
+}
+
+impl From<&Parallax> for ParallaxUniform {
+    fn from(parallax: &Parallax) -> Self {
+        let mut uniform = Self::default();
+        uniform.update(parallax);
+        uniform
+    }
+}
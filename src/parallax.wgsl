@@ -0,0 +1,62 @@
+// Parallax occlusion mapping, marching the view ray down through a height map to find which part of
+// the texture it would really have hit. Shares the material's sampler, so it has to be bound at group 0
+
+struct Parallax {
+    // how deep the lowest parts of the height map are, as a fraction of the texture's width, 0.0 is off
+    scale: f32,
+    // how many steps the ray is split into, looking straight at the surface and at grazing angles
+    min_steps: u32,
+    max_steps: u32,
+}
+@group(0) @binding(3)
+var t_height: texture_2d<f32>;
+@group(0) @binding(4)
+var<uniform> parallax: Parallax;
+
+// How far below the top of the height map `uv` is, from 0.0 to 1.0.
+// Takes the derivatives explicitly, as the loop it's called from doesn't run the same number of times
+// for every fragment, where textures can't work them out themselves
+fn parallax_depth(uv: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> f32 {
+    return 1.0 - textureSampleGrad(t_height, s_diffuse, uv, ddx, ddy).r;
+}
+
+// Where to sample the surface's textures instead of `uv`, for a fragment at `world_position` facing `normal`,
+// seen from `view_direction` (pointing from the surface towards the camera)
+fn parallax_uv(uv: vec2<f32>, world_position: vec3<f32>, normal: vec3<f32>, view_direction: vec3<f32>) -> vec2<f32> {
+    // There are no tangents in the vertices, so the directions u and v run in are worked out
+    // from how they change across the screen compared to the position
+    let dp_dx = dpdx(world_position);
+    let dp_dy = dpdy(world_position);
+    let duv_dx = dpdx(uv);
+    let duv_dy = dpdy(uv);
+    if parallax.scale <= 0.0 {
+        return uv;
+    }
+    let dp_dy_perp = cross(dp_dy, normal);
+    let dp_dx_perp = cross(normal, dp_dx);
+    let tangent = normalize(dp_dy_perp * duv_dx.x + dp_dx_perp * duv_dy.x);
+    let bitangent = normalize(dp_dy_perp * duv_dx.y + dp_dx_perp * duv_dy.y);
+    let view = vec3<f32>(dot(view_direction, tangent), dot(view_direction, bitangent), dot(view_direction, normal));
+
+    // More steps at grazing angles, where the ray crosses more of the texture
+    let steps = mix(f32(parallax.max_steps), f32(parallax.min_steps), clamp(view.z, 0.0, 1.0));
+    let step_depth = 1.0 / steps;
+    // Limited so that the offset doesn't run off to infinity edge on
+    let uv_step = view.xy / max(view.z, 0.1) * parallax.scale / steps;
+
+    var current_uv = uv;
+    var ray_depth = 0.0;
+    var surface_depth = parallax_depth(current_uv, duv_dx, duv_dy);
+    for (var i = 0u; i < parallax.max_steps && ray_depth < surface_depth; i = i + 1u) {
+        current_uv = current_uv - uv_step;
+        ray_depth = ray_depth + step_depth;
+        surface_depth = parallax_depth(current_uv, duv_dx, duv_dy);
+    }
+
+    // Interpolate between the last step above the surface and the first one below it
+    let previous_uv = current_uv + uv_step;
+    let after = surface_depth - ray_depth;
+    let before = parallax_depth(previous_uv, duv_dx, duv_dy) - (ray_depth - step_depth);
+    let weight = after / min(after - before, -0.0001);
+    return mix(current_uv, previous_uv, weight);
+}
@@ -0,0 +1,163 @@
+use rapier3d::{
+    na::{Quaternion as NaQuaternion, UnitQuaternion},
+    prelude::*,
+};
+
+use crate::{
+    instance::Instance,
+    math::{Quaternion, Vector3},
+    transform::Transform,
+};
+
+/// Rigid bodies simulated by rapier, each of which moves one instance in the instance buffer.
+/// The simulation runs in fixed steps whatever the frame rate, so that stacks behave the same way on every machine
+pub struct Physics {
+    gravity: Vector<Real>,
+    integration_parameters: IntegrationParameters,
+    pipeline: PhysicsPipeline,
+    islands: IslandManager,
+    broad_phase: BroadPhase,
+    narrow_phase: NarrowPhase,
+    bodies: RigidBodySet,
+    colliders: ColliderSet,
+    impulse_joints: ImpulseJointSet,
+    multibody_joints: MultibodyJointSet,
+    ccd_solver: CCDSolver,
+    /// Time which has passed but hasn't been simulated yet, as it's less than a step
+    accumulator: f32,
+    /// The body moving each instance, by the instance's index in the instance buffer
+    instances: Vec<(usize, RigidBodyHandle)>,
+}
+
+impl Physics {
+    /// How much time each step simulates, in seconds
+    pub const TIMESTEP: f32 = 1.0 / 60.0;
+    /// The most steps taken in one `step`, so that a long frame doesn't make the next one even longer.
+    /// Any more time than that is dropped, which slows the simulation down rather than stalling the app
+    const MAX_STEPS: u32 = 4;
+
+    /// An empty world with an endless flat ground at `ground_height`, which everything falls towards
+    pub fn new(ground_height: f32) -> Self {
+        let mut colliders = ColliderSet::new();
+        colliders.insert(
+            ColliderBuilder::halfspace(Vector::y_axis())
+                .translation(vector![0.0, ground_height, 0.0])
+                .build(),
+        );
+        Self {
+            gravity: vector![0.0, -9.81, 0.0],
+            integration_parameters: IntegrationParameters {
+                dt: Self::TIMESTEP,
+                ..Default::default()
+            },
+            pipeline: PhysicsPipeline::new(),
+            islands: IslandManager::new(),
+            broad_phase: BroadPhase::new(),
+            narrow_phase: NarrowPhase::new(),
+            bodies: RigidBodySet::new(),
+            colliders,
+            impulse_joints: ImpulseJointSet::new(),
+            multibody_joints: MultibodyJointSet::new(),
+            ccd_solver: CCDSolver::new(),
+            accumulator: 0.0,
+            instances: Vec::new(),
+        }
+    }
+
+    /// Add a box which starts where `instance` is and moves it from then on.
+    /// `half_extents` is half of the box's size before the instance scales it,
+    /// and `index` is where the instance is in the instance buffer
+    pub fn add_cuboid(
+        &mut self,
+        index: usize,
+        instance: &Instance,
+        half_extents: Vector3<f32>,
+    ) -> RigidBodyHandle {
+        let Transform {
+            translation,
+            rotation,
+            scale,
+        } = instance.transform;
+        let body = RigidBodyBuilder::dynamic()
+            .translation(vector![translation.x, translation.y, translation.z])
+            .rotation(to_rotation(rotation).scaled_axis())
+            .build();
+        let handle = self.bodies.insert(body);
+        let collider = ColliderBuilder::cuboid(
+            half_extents.x * scale.x,
+            half_extents.y * scale.y,
+            half_extents.z * scale.z,
+        )
+        .restitution(0.2)
+        .build();
+        self.colliders
+            .insert_with_parent(collider, handle, &mut self.bodies);
+        self.instances.push((index, handle));
+        handle
+    }
+
+    /// How many bodies are being simulated
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    /// Simulate as many fixed steps as fit into the `delta` seconds since the last call,
+    /// returning how many were taken. The rest is carried over to the next call
+    pub fn step(&mut self, delta: f32) -> u32 {
+        puffin::profile_function!();
+        self.accumulator += delta;
+        let mut steps = 0;
+        while self.accumulator >= Self::TIMESTEP {
+            if steps == Self::MAX_STEPS {
+                self.accumulator = 0.0;
+                break;
+            }
+            self.pipeline.step(
+                &self.gravity,
+                &self.integration_parameters,
+                &mut self.islands,
+                &mut self.broad_phase,
+                &mut self.narrow_phase,
+                &mut self.bodies,
+                &mut self.colliders,
+                &mut self.impulse_joints,
+                &mut self.multibody_joints,
+                &mut self.ccd_solver,
+                None,
+                &(),
+                &(),
+            );
+            self.accumulator -= Self::TIMESTEP;
+            steps += 1;
+        }
+        steps
+    }
+
+    /// Move each body's instance in `instances` to where the body is, leaving its scale alone
+    pub fn sync(&self, instances: &mut [Instance]) {
+        for &(index, handle) in &self.instances {
+            let (Some(body), Some(instance)) = (self.bodies.get(handle), instances.get_mut(index))
+            else {
+                continue;
+            };
+            let position = body.translation();
+            let rotation = body.rotation();
+            instance.transform.translation = Vector3::new(position.x, position.y, position.z);
+            instance.transform.rotation =
+                Quaternion::new(rotation.w, rotation.i, rotation.j, rotation.k);
+        }
+    }
+}
+
+fn to_rotation(rotation: Quaternion<f32>) -> UnitQuaternion<f32> {
+    UnitQuaternion::from_quaternion(NaQuaternion::new(
+        rotation.s,
+        rotation.v.x,
+        rotation.v.y,
+        rotation.v.z,
+    ))
+}
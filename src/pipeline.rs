@@ -0,0 +1,306 @@
+use std::{borrow::Cow, collections::HashMap, ops::Deref};
+
+use anyhow::*;
+use wgpu::{
+    BlendComponent, BlendFactor, BlendOperation, BlendState, ColorTargetState, ColorWrites,
+    CompareFunction, DepthBiasState, DepthStencilState, Device, Face, FragmentState, FrontFace,
+    IndexFormat, MultisampleState, PipelineLayout, PolygonMode, PrimitiveState, PrimitiveTopology,
+    RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, StencilState,
+    TextureFormat, VertexBufferLayout, VertexState,
+};
+
+use naga::ShaderStage;
+
+use crate::shader::{parse_glsl, preprocess, ShaderCode, ShaderDefs};
+
+/// Defining this flag in a permutation's `ShaderDefs` adds every fragment to what's already there
+/// rather than replacing it, with none hidden behind another, e.g. to count how many times each pixel is drawn
+pub const ADDITIVE: &str = "ADDITIVE";
+
+/// The value of this in a permutation's `ShaderDefs` is the primitive topology its pipeline draws,
+/// see `topology_defs`. Without it meshes are drawn as lists of triangles
+pub const TOPOLOGY: &str = "TOPOLOGY";
+
+/// `defs` drawing meshes as `topology` rather than as lists of triangles. Lines and points have no surface
+/// to light, so they define `UNLIT` as well, which the scene's shader draws in its albedo
+pub fn topology_defs(defs: &ShaderDefs, topology: PrimitiveTopology) -> ShaderDefs {
+    let name = match topology {
+        PrimitiveTopology::TriangleList => return defs.clone(),
+        PrimitiveTopology::TriangleStrip => return defs.clone().value(TOPOLOGY, "TRIANGLE_STRIP"),
+        PrimitiveTopology::LineList => "LINE_LIST",
+        PrimitiveTopology::LineStrip => "LINE_STRIP",
+        PrimitiveTopology::PointList => "POINT_LIST",
+    };
+    defs.clone().value(TOPOLOGY, name).flag("UNLIT")
+}
+
+/// The topology `topology_defs` put in `defs`
+fn topology(defs: &ShaderDefs) -> Result<PrimitiveTopology> {
+    Ok(match defs.get(TOPOLOGY) {
+        None => PrimitiveTopology::TriangleList,
+        Some("TRIANGLE_STRIP") => PrimitiveTopology::TriangleStrip,
+        Some("LINE_LIST") => PrimitiveTopology::LineList,
+        Some("LINE_STRIP") => PrimitiveTopology::LineStrip,
+        Some("POINT_LIST") => PrimitiveTopology::PointList,
+        Some(name) => bail!("unknown topology `{name}`"),
+    })
+}
+
+/// Lazily builds and caches one `RenderPipeline` per permutation of `ShaderDefs`,
+/// all sharing the same shader source, layout and vertex buffers
+pub struct PipelineCache {
+    label: &'static str,
+    code: ShaderCode,
+    layout: PipelineLayout,
+    vertex_buffers: Vec<VertexBufferLayout<'static>>,
+    /// The format of each colour target, in the order of the fragment shader's outputs
+    formats: Vec<TextureFormat>,
+    depth_format: Option<TextureFormat>,
+    /// How every pipeline tests and writes the stencil buffer, which it ignores by default
+    stencil: StencilState,
+    /// How many samples each pixel of the targets has, see `set_sample_count`
+    sample_count: u32,
+    pipelines: HashMap<ShaderDefs, RenderPipeline>,
+}
+
+impl PipelineCache {
+    pub fn new(
+        label: &'static str,
+        code: impl Into<ShaderCode>,
+        layout: PipelineLayout,
+        vertex_buffers: Vec<VertexBufferLayout<'static>>,
+        formats: Vec<TextureFormat>,
+        depth_format: Option<TextureFormat>,
+    ) -> Self {
+        Self {
+            label,
+            code: code.into(),
+            layout,
+            vertex_buffers,
+            formats,
+            depth_format,
+            stencil: StencilState::default(),
+            sample_count: 1,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Test and write the stencil buffer with `stencil`, whose reference value is set on the render pass
+    /// with `RenderPass::set_stencil_reference`. Only for a `depth_format` with a stencil aspect
+    pub fn with_stencil(mut self, stencil: StencilState) -> Self {
+        self.stencil = stencil;
+        self.clear();
+        self
+    }
+
+    /// Compile the pipeline for `defs` if this permutation hasn't been seen before
+    pub fn prepare(&mut self, device: &Device, defs: &ShaderDefs) -> Result<()> {
+        if !self.pipelines.contains_key(defs) {
+            let pipeline = self.create_pipeline(device, defs)?;
+            self.pipelines.insert(defs.clone(), pipeline);
+        }
+        Ok(())
+    }
+
+    /// Get the pipeline for `defs`, which must have already been compiled with `PipelineCache::prepare`
+    pub fn get(&self, defs: &ShaderDefs) -> Option<&RenderPipeline> {
+        self.pipelines.get(defs)
+    }
+
+    /// The number of permutations which have been compiled so far
+    pub fn len(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.is_empty()
+    }
+
+    /// Throw away every compiled permutation, e.g. when the surface format changes
+    pub fn clear(&mut self) {
+        self.pipelines.clear();
+    }
+
+    /// Draw into targets with `sample_count` samples per pixel from now on. If that's a change,
+    /// every permutation is thrown away, so they have to be prepared again
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        if sample_count != self.sample_count {
+            self.sample_count = sample_count;
+            self.clear();
+        }
+    }
+
+    /// Swap the shader out for `code`, returning the old one. Every permutation is thrown away,
+    /// so they have to be prepared again with the new code
+    pub fn set_code(&mut self, code: impl Into<ShaderCode>) -> ShaderCode {
+        self.clear();
+        std::mem::replace(&mut self.code, code.into())
+    }
+
+    #[tracing::instrument(skip(self, device), fields(label = self.label))]
+    fn create_pipeline(&self, device: &Device, defs: &ShaderDefs) -> Result<RenderPipeline> {
+        let (vertex_shader, fragment_shader) = match &self.code {
+            ShaderCode::Wgsl(source) => {
+                let source = preprocess(source, defs).with_context(|| {
+                    format!("failed to preprocess {} with {defs:?}", self.label)
+                })?;
+                let shader = device.create_shader_module(ShaderModuleDescriptor {
+                    label: Some(self.label),
+                    source: ShaderSource::Wgsl(source.into()),
+                });
+                (shader, None)
+            }
+            ShaderCode::Glsl { vertex, fragment } => {
+                // Translate through naga ourselves so that syntax errors are reported rather than panicking
+                let create = |source: &str, stage| -> Result<_> {
+                    let module = parse_glsl(source, stage, defs)
+                        .with_context(|| format!("failed to compile {}", self.label))?;
+                    Ok(device.create_shader_module(ShaderModuleDescriptor {
+                        label: Some(self.label),
+                        source: ShaderSource::Naga(Cow::Owned(module)),
+                    }))
+                };
+                (
+                    create(vertex, ShaderStage::Vertex)?,
+                    Some(create(fragment, ShaderStage::Fragment)?),
+                )
+            }
+        };
+        let fragment_shader = fragment_shader.as_ref().unwrap_or(&vertex_shader);
+        let topology = topology(defs)?;
+        let (vs_entry_point, fs_entry_point) = self.code.entry_points();
+        let additive = defs.is_defined(ADDITIVE);
+        let blend = if additive {
+            BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::One,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+                alpha: BlendComponent::OVER,
+            }
+        } else {
+            BlendState::REPLACE
+        };
+        let targets = self
+            .formats
+            .iter()
+            .map(|&format| {
+                Some(ColorTargetState {
+                    format,
+                    blend: Some(blend),
+                    write_mask: ColorWrites::ALL,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        Ok(device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(self.label),
+            layout: Some(&self.layout),
+            vertex: VertexState {
+                module: &vertex_shader,
+                // the "main function" for the vertex shader
+                entry_point: vs_entry_point,
+                // what type of vertices we want to pass to the vertex shader
+                buffers: &self.vertex_buffers,
+            },
+            // technically optional
+            fragment: Some(FragmentState {
+                module: fragment_shader,
+                entry_point: fs_entry_point,
+                // what colour outputs wgpu should set up,
+                // one for each `@location` the fragment shader writes to
+                targets: &targets,
+            }),
+            primitive: PrimitiveState {
+                // every 3 vertices will correspond to 1 triangle, unless the permutation asks for something else
+                topology,
+                // meshes' indices are all `u16`s
+                strip_index_format: topology.is_strip().then_some(IndexFormat::Uint16),
+                // how to determine if a triangle is facing forwards or not
+                // in this case the triangle is facing forwards if the vertices are arranged counter-clockwise
+                front_face: FrontFace::Ccw,
+                // cull a triangle (don't render it) if it is facing backwards, lines and points have no back
+                cull_mode: matches!(
+                    topology,
+                    PrimitiveTopology::TriangleList | PrimitiveTopology::TriangleStrip
+                )
+                .then_some(Face::Back),
+                polygon_mode: PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // only draw fragments which are closer than what has already been drawn
+            depth_stencil: self.depth_format.map(|format| DepthStencilState {
+                format,
+                depth_write_enabled: !additive,
+                depth_compare: if additive {
+                    CompareFunction::Always
+                } else {
+                    CompareFunction::Less
+                },
+                stencil: self.stencil.clone(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState {
+                // how many samples the pipeline will use, which has to match the targets'
+                count: self.sample_count,
+                // which samples should be active, in this case, we want to use all of them
+                mask: !0,
+                // to do with anti-aliasing
+                alpha_to_coverage_enabled: false,
+            },
+            // how many array layers the render attachments can have
+            // we won't be rendering to array textures, hence the `None`
+            multiview: None,
+        }))
+    }
+}
+
+/// Builds a `ScenePipeline`'s pipeline with the given multisampling
+type CreatePipeline = dyn Fn(&Device, MultisampleState) -> RenderPipeline + Send + Sync;
+
+/// A render pipeline which draws into the scene pass, and so has to be rebuilt whenever the number of samples
+/// the scene's targets have changes, see `State::set_sample_count`. It's built by `create`, from whatever it
+/// captures (its shader and layout, say) and the `MultisampleState` it's given. Starts out single-sampled
+pub struct ScenePipeline {
+    create: Box<CreatePipeline>,
+    pipeline: RenderPipeline,
+    sample_count: u32,
+}
+
+impl ScenePipeline {
+    pub fn new(
+        device: &Device,
+        create: impl Fn(&Device, MultisampleState) -> RenderPipeline + Send + Sync + 'static,
+    ) -> Self {
+        let pipeline = create(device, MultisampleState::default());
+        Self {
+            create: Box::new(create),
+            pipeline,
+            sample_count: 1,
+        }
+    }
+
+    /// Rebuild the pipeline for targets with `sample_count` samples per pixel, if it isn't already
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        if sample_count != self.sample_count {
+            self.pipeline = (self.create)(
+                device,
+                MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+            );
+            self.sample_count = sample_count;
+        }
+    }
+}
+
+impl Deref for ScenePipeline {
+    type Target = RenderPipeline;
+
+    fn deref(&self) -> &RenderPipeline {
+        &self.pipeline
+    }
+}
@@ -0,0 +1,207 @@
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupLayout, Color, CommandEncoder, Device, LoadOp,
+    Operations, Queue, RenderPass, RenderPassColorAttachment, RenderPassDepthStencilAttachment,
+    RenderPassDescriptor, TextureView,
+};
+
+use crate::{
+    camera::{Camera, CameraUniform},
+    geometry::Plane,
+    math::{Matrix4, SquareMatrix},
+    postprocess::{MultisampledTargets, PostProcessStack, SceneTargets},
+    texture::OurTexture,
+    uniform::UniformBuffer,
+};
+
+/// The scene as seen in a flat reflective surface, which `Water` and `Mirror` draw their reflections from.
+/// It's rendered in its own pass before the scene, from a camera mirrored in the surface,
+/// by drawing the scene again with `camera_bind_group`. Anything behind the surface is clipped away,
+/// so that it isn't reflected from the wrong side
+pub struct PlanarReflection {
+    label: &'static str,
+    targets: ReflectionTargets,
+    camera: UniformBuffer<CameraUniform>,
+    camera_bind_group: BindGroup,
+    view_proj: Matrix4<f32>,
+}
+
+/// The offscreen targets the reflection is rendered into, which match the scene pass' formats
+/// and sample count so that the scene's pipelines can draw into them
+struct ReflectionTargets {
+    color: OurTexture,
+    /// Nothing reads this, it's only here because the scene's pipelines write motion vectors
+    velocity: OurTexture,
+    depth: OurTexture,
+    /// Drawn into in place of the rest when the scene has more than one sample per pixel,
+    /// with only the colour resolved into `color`
+    multisampled: Option<MultisampledTargets>,
+}
+
+impl ReflectionTargets {
+    fn new(device: &Device, label: &str, width: u32, height: u32, sample_count: u32) -> Self {
+        Self {
+            color: OurTexture::create_render_target(
+                device,
+                width,
+                height,
+                PostProcessStack::SCENE_FORMAT,
+                label,
+            ),
+            velocity: OurTexture::create_render_target(
+                device,
+                width,
+                height,
+                SceneTargets::VELOCITY_FORMAT,
+                &format!("{label} Velocity"),
+            ),
+            depth: OurTexture::create_depth_texture(
+                device,
+                width,
+                height,
+                &format!("{label} Depth"),
+            ),
+            multisampled: MultisampledTargets::new(device, label, width, height, sample_count),
+        }
+    }
+
+    fn sample_count(&self) -> u32 {
+        self.multisampled
+            .as_ref()
+            .map_or(1, |multisampled| multisampled.sample_count)
+    }
+}
+
+impl PlanarReflection {
+    /// `camera_layout` is the scene's, and `label` names the targets and the pass, e.g. `Water Reflection`
+    pub fn new(
+        device: &Device,
+        camera_layout: &BindGroupLayout,
+        label: &'static str,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let camera = UniformBuffer::new(
+            device,
+            CameraUniform::default(),
+            Some(&format!("{label} Camera Buffer")),
+        );
+        let camera_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("reflection_camera_bind_group"),
+            layout: camera_layout,
+            entries: &[camera.bind_group_entry(0)],
+        });
+        Self {
+            label,
+            targets: ReflectionTargets::new(device, label, width, height, 1),
+            camera,
+            camera_bind_group,
+            view_proj: Matrix4::identity(),
+        }
+    }
+
+    /// Render the reflection at `width` by `height` from now on
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        self.targets = ReflectionTargets::new(
+            device,
+            self.label,
+            width,
+            height,
+            self.targets.sample_count(),
+        );
+    }
+
+    /// Render the reflection with `sample_count` samples per pixel from now on, the same as the scene.
+    /// It's still resolved into the same `view`
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        let size = self.targets.color.size;
+        self.targets.multisampled =
+            MultisampledTargets::new(device, self.label, size.width, size.height, sample_count);
+    }
+
+    /// Look through `mirrored`, the camera reflected in the surface, leaving out anything behind `surface`
+    /// as well as what the scene's `clip_planes` cut away
+    pub fn update(
+        &mut self,
+        queue: &Queue,
+        mirrored: &Camera,
+        surface: Plane,
+        clip_planes: &[Plane],
+    ) {
+        self.view_proj = mirrored.build_view_projection_matrix();
+        let uniform = self.camera.get_mut();
+        uniform.update_view_proj(mirrored);
+        // the reflection doesn't move on screen the way the scene does, so it has no motion to blur
+        uniform.reset_history();
+        uniform.set_clip_planes(std::iter::once(&surface).chain(clip_planes));
+        self.camera.write(queue);
+    }
+
+    /// The mirrored camera, to bind at group 1 in place of the scene's camera while drawing the reflection
+    pub fn camera_bind_group(&self) -> &BindGroup {
+        &self.camera_bind_group
+    }
+
+    /// The view-projection matrix of the mirrored camera, for culling what's drawn in the reflection pass
+    pub fn view_proj(&self) -> &Matrix4<f32> {
+        &self.view_proj
+    }
+
+    /// What the reflection was rendered into, for the surface to sample
+    pub fn view(&self) -> &TextureView {
+        &self.targets.color.view
+    }
+
+    /// Begin the pass which the reflection is drawn in, clearing it to `background`
+    pub fn begin_pass<'a>(
+        &'a self,
+        encoder: &'a mut CommandEncoder,
+        background: Color,
+    ) -> RenderPass<'a> {
+        // drawn into the multisampled targets if there are any, resolving only the colour, which is all that's read
+        let targets = &self.targets;
+        let (color, resolve_target, velocity, depth) = match &targets.multisampled {
+            Some(multisampled) => (
+                &multisampled.color.view,
+                Some(&targets.color.view),
+                &multisampled.velocity.view,
+                &multisampled.depth.view,
+            ),
+            None => (
+                &targets.color.view,
+                None,
+                &targets.velocity.view,
+                &targets.depth.view,
+            ),
+        };
+        encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some(self.label),
+            color_attachments: &[
+                Some(RenderPassColorAttachment {
+                    view: color,
+                    resolve_target,
+                    ops: Operations {
+                        load: LoadOp::Clear(background),
+                        // once it's resolved the samples aren't needed
+                        store: resolve_target.is_none(),
+                    },
+                }),
+                Some(RenderPassColorAttachment {
+                    view: velocity,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color::TRANSPARENT),
+                        store: false,
+                    },
+                }),
+            ],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: depth,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(1.0),
+                    store: false,
+                }),
+                stencil_ops: None,
+            }),
+        })
+    }
+}
@@ -0,0 +1,440 @@
+use std::path::Path;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, Buffer, BufferAddress, BufferUsages, ColorTargetState, ColorWrites,
+    CompareFunction, DepthBiasState, DepthStencilState, Device, FragmentState,
+    PipelineLayoutDescriptor, PrimitiveState, PrimitiveTopology, Queue, RenderPass,
+    RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, StencilState, TextureFormat,
+    VertexAttribute, VertexBufferLayout, VertexFormat, VertexState, VertexStepMode,
+};
+
+use crate::{
+    pipeline::ScenePipeline,
+    reflection::ShaderReflection,
+    shader::{preprocess, ShaderDefs, ShaderLibrary},
+    uniform::UniformBuffer,
+};
+
+/// A point of a `PointCloud`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+pub struct CloudPoint {
+    pub position: [f32; 3],
+    /// sRGB, with alpha unused, as colours usually are in point cloud files
+    pub color: [u8; 4],
+}
+
+impl CloudPoint {
+    const ATTRIBUTES: [VertexAttribute; 2] = [
+        VertexAttribute {
+            offset: 0,
+            shader_location: 0,
+            format: VertexFormat::Float32x3,
+        },
+        VertexAttribute {
+            offset: std::mem::size_of::<[f32; 3]>() as BufferAddress,
+            shader_location: 1,
+            format: VertexFormat::Unorm8x4,
+        },
+    ];
+
+    /// One point per instance, each drawn as a quad
+    fn desc<'a>() -> VertexBufferLayout<'a> {
+        VertexBufferLayout {
+            array_stride: std::mem::size_of::<CloudPoint>() as BufferAddress,
+            step_mode: VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// Load the points in a `.ply` or `.xyz` file, going by its extension.
+/// PLY files can be ASCII or binary, with their points as the first element, and an XYZ file has a point
+/// on each line, its position followed by an optional colour as 3 numbers from 0 to 255.
+/// Points without a colour are white
+pub fn load_points(path: &Path) -> Result<Vec<CloudPoint>> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("ply") => parse_ply(&bytes),
+        Some("xyz") => parse_xyz(std::str::from_utf8(&bytes)?),
+        _ => bail!("{} isn't a .ply or .xyz file", path.display()),
+    }
+    .with_context(|| format!("failed to load {}", path.display()))
+}
+
+/// Parse an XYZ file, see `load_points`
+pub fn parse_xyz(text: &str) -> Result<Vec<CloudPoint>> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            let values = line
+                .split_whitespace()
+                .map(str::parse::<f32>)
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("invalid number on line {}", number + 1))?;
+            let color = match values[..] {
+                [_, _, _] => [255; 3],
+                [_, _, _, r, g, b] => [r, g, b].map(|channel| channel as u8),
+                _ => bail!(
+                    "line {} needs a position and optionally a colour, not {} numbers",
+                    number + 1,
+                    values.len()
+                ),
+            };
+            Ok(CloudPoint {
+                position: [values[0], values[1], values[2]],
+                color: [color[0], color[1], color[2], 255],
+            })
+        })
+        .collect()
+}
+
+/// How a PLY file stores its numbers
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PlyFormat {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+/// One of the types a PLY property can have, by its size in bytes
+#[derive(Clone, Copy)]
+enum PlyType {
+    Int(usize),
+    UInt(usize),
+    Float(usize),
+}
+
+impl PlyType {
+    fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "char" | "int8" => Self::Int(1),
+            "uchar" | "uint8" => Self::UInt(1),
+            "short" | "int16" => Self::Int(2),
+            "ushort" | "uint16" => Self::UInt(2),
+            "int" | "int32" => Self::Int(4),
+            "uint" | "uint32" => Self::UInt(4),
+            "float" | "float32" => Self::Float(4),
+            "double" | "float64" => Self::Float(8),
+            _ => bail!("unknown property type `{name}`"),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::Int(size) | Self::UInt(size) | Self::Float(size) => size,
+        }
+    }
+
+    /// Read a value of this type from the front of `bytes`
+    fn read(self, bytes: &[u8], format: PlyFormat) -> f64 {
+        let mut buffer = [0; 8];
+        let size = self.size();
+        buffer[..size].copy_from_slice(&bytes[..size]);
+        if format == PlyFormat::BigEndian {
+            buffer[..size].reverse();
+        }
+        let bits = u64::from_le_bytes(buffer);
+        match self {
+            Self::UInt(_) => bits as f64,
+            // sign extended from however many bits there are
+            Self::Int(size) => ((bits << (64 - size * 8)) as i64 >> (64 - size * 8)) as f64,
+            Self::Float(4) => f32::from_bits(bits as u32) as f64,
+            Self::Float(_) => f64::from_bits(bits),
+        }
+    }
+}
+
+/// Parse a PLY file, see `load_points`
+pub fn parse_ply(bytes: &[u8]) -> Result<Vec<CloudPoint>> {
+    let header_end = bytes
+        .windows(b"end_header".len())
+        .position(|window| window == b"end_header")
+        .context("the header never ends")?;
+    let header = std::str::from_utf8(&bytes[..header_end])?;
+    // the body starts on the line after `end_header`
+    let body_start = bytes[header_end..]
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .map_or(bytes.len(), |newline| header_end + newline + 1);
+    let body = &bytes[body_start..];
+
+    let mut lines = header.lines().map(str::trim);
+    ensure!(lines.next() == Some("ply"), "not a PLY file");
+    let mut format = None;
+    let mut count = None;
+    let mut properties = Vec::new();
+    for line in lines {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words[..] {
+            ["format", name, _] => {
+                format = Some(match name {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::LittleEndian,
+                    "binary_big_endian" => PlyFormat::BigEndian,
+                    _ => bail!("unknown format `{name}`"),
+                })
+            }
+            ["element", "vertex", vertices] => {
+                ensure!(count.is_none(), "the points have to be the first element");
+                count = Some(
+                    vertices
+                        .parse::<usize>()
+                        .with_context(|| format!("invalid number of points `{vertices}`"))?,
+                );
+            }
+            ["element", ..] => {
+                // anything after the points can be ignored, but not anything before
+                if count.is_none() {
+                    bail!("the points have to be the first element");
+                }
+                break;
+            }
+            ["property", "list", ..] => bail!("the points can't have list properties"),
+            ["property", kind, name] => properties.push((name, PlyType::parse(kind)?)),
+            _ => {}
+        }
+    }
+    let format = format.context("the header has no format")?;
+    let count = count.context("there are no points")?;
+    let index = |name: &str| {
+        properties
+            .iter()
+            .position(|&(property, _)| property == name)
+    };
+    let [x, y, z] = ["x", "y", "z"].map(index);
+    let (Some(x), Some(y), Some(z)) = (x, y, z) else {
+        bail!("the points need an x, y and z");
+    };
+    let color = match ["red", "green", "blue"].map(index) {
+        [Some(red), Some(green), Some(blue)] => Some([red, green, blue]),
+        _ => None,
+    };
+    let to_point = |values: &[f64]| {
+        let color = color.map_or([255; 3], |channels| {
+            channels.map(|channel| match properties[channel].1 {
+                // floating point colours go from 0 to 1
+                PlyType::Float(_) => (values[channel] * 255.0).round() as u8,
+                _ => values[channel] as u8,
+            })
+        });
+        CloudPoint {
+            position: [values[x], values[y], values[z]].map(|value| value as f32),
+            color: [color[0], color[1], color[2], 255],
+        }
+    };
+
+    let mut points = Vec::with_capacity(count);
+    let mut values = vec![0.0; properties.len()];
+    if format == PlyFormat::Ascii {
+        let body = std::str::from_utf8(body)?;
+        let mut lines = body.lines().filter(|line| !line.trim().is_empty());
+        for point in 0..count {
+            let line = lines
+                .next()
+                .with_context(|| format!("there are only {point} of {count} points"))?;
+            for (value, word) in values.iter_mut().zip(line.split_whitespace()) {
+                *value = word
+                    .parse()
+                    .with_context(|| format!("invalid number `{word}`"))?;
+            }
+            points.push(to_point(&values));
+        }
+    } else {
+        let stride = properties
+            .iter()
+            .map(|(_, kind)| kind.size())
+            .sum::<usize>();
+        ensure!(
+            body.len() >= stride * count,
+            "there are only {} of {count} points",
+            body.len() / stride
+        );
+        for point in body.chunks_exact(stride).take(count) {
+            let mut offset = 0;
+            for (value, (_, kind)) in values.iter_mut().zip(&properties) {
+                *value = kind.read(&point[offset..], format);
+                offset += kind.size();
+            }
+            points.push(to_point(&values));
+        }
+    }
+    Ok(points)
+}
+
+/// How the points are drawn, which can be changed at any time through `PointCloud::settings_mut`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PointCloudSettings {
+    /// How wide each point is, in world units, so that nearer points are bigger
+    pub size: f32,
+}
+
+impl Default for PointCloudSettings {
+    fn default() -> Self {
+        Self { size: 0.05 }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct PointCloudUniform {
+    size: f32,
+    _padding: [f32; 3],
+}
+
+impl From<&PointCloudSettings> for PointCloudUniform {
+    fn from(settings: &PointCloudSettings) -> Self {
+        Self {
+            size: settings.size,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// A cloud of coloured points, e.g. from a 3D scan, placed as they are in world space and drawn unlit.
+/// GPUs only draw points a pixel wide, so each point is drawn as a disc facing the camera instead,
+/// which is an instance of a quad whose corners the shader works out, rather than a `PointList`
+pub struct PointCloud {
+    settings: PointCloudSettings,
+    points: Buffer,
+    num_points: u32,
+    uniform: UniformBuffer<PointCloudUniform>,
+    bind_group: BindGroup,
+    pipeline: ScenePipeline,
+}
+
+impl PointCloud {
+    /// `camera_layout` is the scene's, and `formats` and `depth_format` must match the scene pass
+    #[allow(clippy::too_many_arguments)]
+    #[tracing::instrument(skip_all, fields(points = points.len()))]
+    pub fn new(
+        device: &Device,
+        library: &ShaderLibrary,
+        settings: PointCloudSettings,
+        camera_layout: &BindGroupLayout,
+        formats: &[TextureFormat],
+        depth_format: TextureFormat,
+        points: &[CloudPoint],
+    ) -> Result<Self> {
+        ensure!(!points.is_empty(), "there are no points");
+        let name = "point_cloud.wgsl";
+        let source = preprocess(&library.resolve(name)?, &ShaderDefs::new())?;
+        let reflection = ShaderReflection::from_code(&source.clone().into(), &ShaderDefs::new())
+            .with_context(|| format!("failed to reflect {name}"))?;
+        let layout =
+            reflection.create_bind_group_layout(device, 0, Some("point_cloud_bind_group_layout"));
+        let uniform = UniformBuffer::new(
+            device,
+            PointCloudUniform::from(&settings),
+            Some("Point Cloud Buffer"),
+        );
+        let bind_group = reflection.create_bind_group(
+            device,
+            0,
+            &layout,
+            &[uniform.bind_group_entry(0)],
+            Some("point_cloud_bind_group"),
+        )?;
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(name),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Point Cloud Pipeline Layout"),
+            bind_group_layouts: &[&layout, camera_layout],
+            push_constant_ranges: &[],
+        });
+        let targets = formats
+            .iter()
+            .map(|&format| {
+                Some(ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })
+            })
+            .collect::<Vec<_>>();
+        let pipeline = ScenePipeline::new(device, move |device, multisample| {
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(name),
+                layout: Some(&pipeline_layout),
+                vertex: VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[CloudPoint::desc()],
+                },
+                fragment: Some(FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &targets,
+                }),
+                primitive: PrimitiveState {
+                    topology: PrimitiveTopology::TriangleStrip,
+                    ..Default::default()
+                },
+                depth_stencil: Some(DepthStencilState {
+                    format: depth_format,
+                    depth_write_enabled: true,
+                    depth_compare: CompareFunction::Less,
+                    stencil: StencilState::default(),
+                    bias: DepthBiasState::default(),
+                }),
+                multisample,
+                multiview: None,
+            })
+        });
+
+        let points_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Point Cloud Points"),
+            contents: bytemuck::cast_slice(points),
+            usage: BufferUsages::VERTEX,
+        });
+
+        Ok(Self {
+            settings,
+            points: points_buffer,
+            num_points: points.len() as u32,
+            uniform,
+            bind_group,
+            pipeline,
+        })
+    }
+
+    pub fn settings(&self) -> &PointCloudSettings {
+        &self.settings
+    }
+
+    /// Change how the points are drawn, which takes effect from the next `update`
+    pub fn settings_mut(&mut self) -> &mut PointCloudSettings {
+        &mut self.settings
+    }
+
+    /// Draw into a scene pass whose targets have `sample_count` samples per pixel from now on
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        self.pipeline.set_sample_count(device, sample_count);
+    }
+
+    pub fn update(&mut self, queue: &Queue) {
+        self.uniform.set(&PointCloudUniform::from(&self.settings));
+        self.uniform.write(queue);
+    }
+
+    /// Draw the points with the camera in `camera_bind_group`, e.g. the scene's or a reflection's
+    pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>, camera_bind_group: &'a BindGroup) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.points.slice(..));
+        render_pass.draw(0..4, 0..self.num_points);
+    }
+}
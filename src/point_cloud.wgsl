@@ -0,0 +1,69 @@
+// Point clouds, drawn as a round disc facing the camera for each point, matching `PointCloudUniform`.
+// Each point is an instance of a quad of 4 vertices, which are worked out from `vertex_index`
+
+#include "camera.wgsl"
+#include "color.wgsl"
+
+struct PointCloudUniform {
+    // how wide each point is, in world units
+    size: f32,
+}
+@group(0) @binding(0)
+var<uniform> point_cloud: PointCloudUniform;
+
+struct PointInput {
+    @location(0) position: vec3<f32>,
+    // sRGB, as it was in the file
+    @location(1) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+    // from -1 to 1 across the quad, for rounding it off
+    @location(1) corner: vec2<f32>,
+    @location(2) world_position: vec3<f32>,
+    @location(3) current_position: vec4<f32>,
+    @location(4) previous_position: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32, in: PointInput) -> VertexOutput {
+    let corner = vec2<f32>(f32(vertex_index & 1u), f32(vertex_index >> 1u)) * 2.0 - 1.0;
+    // Facing the camera in world space, so that further points are smaller
+    let forward = normalize(in.position - camera.view_position.xyz);
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if abs(forward.y) > 0.999 {
+        up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let right = normalize(cross(forward, up));
+    let world_position = in.position
+        + (right * corner.x + cross(right, forward) * corner.y) * (point_cloud.size * 0.5);
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.color = srgb_to_linear(in.color.rgb);
+    out.corner = corner;
+    out.world_position = world_position;
+    out.current_position = out.clip_position;
+    out.previous_position = camera.prev_view_proj * vec4<f32>(world_position, 1.0);
+    return out;
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec2<f32>,
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    if dot(in.corner, in.corner) > 1.0 || is_clipped(in.world_position) {
+        discard;
+    }
+    var out: FragmentOutput;
+    out.color = vec4<f32>(in.color, 1.0);
+    let current = in.current_position.xy / in.current_position.w;
+    let previous = in.previous_position.xy / in.previous_position.w;
+    // Texture co-ordinates are half the size of clip space, and go downwards rather than upwards
+    out.velocity = (current - previous) * vec2<f32>(0.5, -0.5);
+    return out;
+}
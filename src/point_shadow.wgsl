@@ -0,0 +1,53 @@
+// Renders one face of a point light's shadow cubemap, storing the distance to the light rather than the usual depth
+
+#include "instance.wgsl"
+#include "morph.wgsl"
+#include "skin.wgsl"
+
+struct ShadowFace {
+    view_proj: mat4x4<f32>,
+    light_position: vec3<f32>,
+    range: f32,
+};
+@group(0) @binding(0)
+var<uniform> face: ShadowFace;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+}
+
+@vertex
+fn vs_main(
+    @location(0) position: vec3<f32>,
+    instance: InstanceInput,
+#ifdef SKINNED
+    skin: SkinInput,
+#endif
+#ifdef MORPHED
+    @builtin(vertex_index) vertex_index: u32,
+#endif
+) -> VertexOutput {
+#ifdef MORPHED
+    // only the position matters for shadows
+    let bind_pose = morph_vertex(vertex_index, position, vec3<f32>(0.0)).position;
+#else
+    let bind_pose = position;
+#endif
+#ifdef SKINNED
+    let local_position = skin_matrix(skin) * vec4<f32>(bind_pose, 1.0);
+#else
+    let local_position = vec4<f32>(bind_pose, 1.0);
+#endif
+    let world_position = instance_model_matrix(instance) * local_position;
+    var out: VertexOutput;
+    out.world_position = world_position.xyz;
+    out.clip_position = face.view_proj * world_position;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @builtin(frag_depth) f32 {
+    // Every face uses the same distance, so the lighting shader can compare against it without knowing which face it hit
+    return length(in.world_position - face.light_position) / face.range;
+}
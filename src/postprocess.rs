@@ -0,0 +1,686 @@
+use std::any::Any;
+
+use anyhow::*;
+use wgpu::{
+    AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType,
+    ColorTargetState, ColorWrites, CommandEncoder, CompareFunction, DepthBiasState,
+    DepthStencilState, Device, Extent3d, FilterMode, FragmentState, LoadOp, MultisampleState,
+    Operations, PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPassColorAttachment,
+    RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline,
+    RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, StencilState, Texture, TextureDescriptor,
+    TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureView,
+    TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+use crate::{
+    reflection::ShaderReflection,
+    shader::{preprocess, ShaderDefs, ShaderLibrary},
+    texture::OurTexture,
+    viewport::OutputRegion,
+};
+
+pub mod anaglyph;
+pub mod bloom;
+pub mod chromatic_aberration;
+pub mod color_grading;
+pub mod depth_of_field;
+pub mod film_grain;
+pub mod motion_blur;
+pub mod tonemapping;
+pub mod vignette;
+
+/// A single full-screen effect in the `PostProcessStack`
+pub trait PostEffect: Any {
+    fn label(&self) -> &'static str;
+
+    /// Disabled effects are skipped entirely, rather than costing a pass
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    /// Upload any changed settings to the GPU, called once per frame before `render`
+    fn prepare(&mut self, _queue: &Queue) {}
+
+    /// Called when the scene's render targets are recreated, e.g. because the window was resized
+    fn resize(&mut self, _device: &Device, _scene: &SceneTargets) {}
+
+    /// Read from `input` (bound at group 0) and write the result to `output`
+    fn render(&self, encoder: &mut CommandEncoder, input: &BindGroup, output: &TextureView);
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/// A render pipeline which draws a single triangle covering the whole target,
+/// used to run a fragment shader over every pixel of the previous pass' output
+pub struct FullscreenPass {
+    label: &'static str,
+    pipeline: RenderPipeline,
+    reflection: ShaderReflection,
+    /// Layouts for group 1 onwards, group 0 is always the input texture
+    layouts: Vec<BindGroupLayout>,
+}
+
+impl FullscreenPass {
+    /// Build the pass from the shader called `name` in `library`,
+    /// which should `#include "fullscreen.wgsl"` and provide an `fs_main`
+    pub fn new(
+        device: &Device,
+        library: &ShaderLibrary,
+        name: &'static str,
+        defs: &ShaderDefs,
+        input_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) -> Result<Self> {
+        let source = preprocess(&library.resolve(name)?, defs)?;
+        let reflection = ShaderReflection::from_code(&source.clone().into(), &ShaderDefs::new())
+            .with_context(|| format!("failed to reflect {name}"))?;
+        let layouts = (1..reflection.group_count())
+            .map(|group| reflection.create_bind_group_layout(device, group, Some(name)))
+            .collect::<Vec<_>>();
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(name),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+        let bind_group_layouts = std::iter::once(input_layout)
+            .chain(&layouts)
+            .collect::<Vec<_>>();
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some(name),
+            bind_group_layouts: &bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(name),
+            layout: Some(&layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                // the vertices are generated from `vertex_index`
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            // the triangle is always facing the camera, no need for culling
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            multiview: None,
+        });
+
+        Ok(Self {
+            label: name,
+            pipeline,
+            reflection,
+            layouts,
+        })
+    }
+
+    /// The layout of bind group `group`, which must be at least 1
+    pub fn layout(&self, group: u32) -> &BindGroupLayout {
+        &self.layouts[group as usize - 1]
+    }
+
+    /// Create (and validate against the shader) a bind group for `group`, which must be at least 1
+    pub fn create_bind_group(
+        &self,
+        device: &Device,
+        group: u32,
+        entries: &[BindGroupEntry],
+    ) -> Result<BindGroup> {
+        self.reflection.create_bind_group(
+            device,
+            group,
+            self.layout(group),
+            entries,
+            Some(self.label),
+        )
+    }
+
+    /// Run the pass over `output`, with `input` at group 0 and `bind_groups` from group 1 onwards
+    pub fn draw(
+        &self,
+        encoder: &mut CommandEncoder,
+        input: &BindGroup,
+        bind_groups: &[&BindGroup],
+        output: &TextureView,
+    ) {
+        self.draw_region(
+            encoder,
+            input,
+            bind_groups,
+            output,
+            &OutputRegion::default(),
+        );
+    }
+
+    /// `draw` into only part of `output`, clearing the rest to `region.clear_color`
+    pub fn draw_region(
+        &self,
+        encoder: &mut CommandEncoder,
+        input: &BindGroup,
+        bind_groups: &[&BindGroup],
+        output: &TextureView,
+        region: &OutputRegion,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some(self.label),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: Operations {
+                    // only what's outside of the region isn't overwritten
+                    load: LoadOp::Clear(region.clear_color),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        region.apply(&mut render_pass);
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, input, &[]);
+        for (index, bind_group) in bind_groups.iter().enumerate() {
+            render_pass.set_bind_group(index as u32 + 1, bind_group, &[]);
+        }
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+/// An intermediate texture which passes render into and read from
+struct RenderTarget {
+    texture: Texture,
+    view: TextureView,
+    /// Binds `view` as the input of the next pass
+    bind_group: BindGroup,
+}
+
+impl RenderTarget {
+    fn new(
+        device: &Device,
+        layout: &BindGroupLayout,
+        sampler: &Sampler,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Post-process Target"),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: PostProcessStack::SCENE_FORMAT,
+            // copied from for `Glass` to see the opaque scene through
+            usage: TextureUsages::RENDER_ATTACHMENT
+                | TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_SRC,
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some("post_process_input_bind_group"),
+        });
+
+        Self {
+            texture,
+            view,
+            bind_group,
+        }
+    }
+}
+
+/// The render targets the scene is drawn into besides colour, which effects can read from
+pub struct SceneTargets {
+    pub width: u32,
+    pub height: u32,
+    pub depth: OurTexture,
+    /// How far each pixel moved on screen since the last frame, in texture co-ordinates,
+    /// for motion blur and anything else which needs to reproject last frame's image (e.g. TAA)
+    pub velocity: OurTexture,
+    /// What the scene's drawn into in place of the rest if it has more than one sample per pixel,
+    /// see `PostProcessStack::set_sample_count`
+    pub multisampled: Option<MultisampledTargets>,
+    /// Binds `multisampled`'s depth for `DepthResolve`
+    depth_resolve: Option<BindGroup>,
+}
+
+impl SceneTargets {
+    /// Per-pixel motion vectors only need two channels, and half precision is plenty
+    pub const VELOCITY_FORMAT: TextureFormat = TextureFormat::Rg16Float;
+
+    fn new(
+        device: &Device,
+        depth_resolve: &DepthResolve,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> Self {
+        let mut targets = Self {
+            width,
+            height,
+            depth: OurTexture::create_depth_texture(device, width, height, "Scene Depth"),
+            velocity: OurTexture::create_render_target(
+                device,
+                width,
+                height,
+                Self::VELOCITY_FORMAT,
+                "Scene Velocity",
+            ),
+            multisampled: None,
+            depth_resolve: None,
+        };
+        targets.set_sample_count(device, depth_resolve, sample_count);
+        targets
+    }
+
+    /// How many samples each pixel of the scene has
+    pub fn sample_count(&self) -> u32 {
+        self.multisampled
+            .as_ref()
+            .map_or(1, |multisampled| multisampled.sample_count)
+    }
+
+    fn set_sample_count(
+        &mut self,
+        device: &Device,
+        depth_resolve: &DepthResolve,
+        sample_count: u32,
+    ) {
+        self.multisampled =
+            MultisampledTargets::new(device, "Scene", self.width, self.height, sample_count);
+        self.depth_resolve = self
+            .multisampled
+            .as_ref()
+            .map(|multisampled| depth_resolve.create_bind_group(device, &multisampled.depth));
+    }
+}
+
+/// Targets with `sample_count` samples per pixel, which a pass draws into in place of single-sampled ones
+/// to smooth the edges of what it draws. Colour and velocity are resolved into the single-sampled targets
+/// at the end of the pass, as their `resolve_target`s, but depth has to be resolved by a pass of its own
+pub struct MultisampledTargets {
+    pub sample_count: u32,
+    pub color: OurTexture,
+    pub velocity: OurTexture,
+    pub depth: OurTexture,
+}
+
+impl MultisampledTargets {
+    /// Targets in the scene pass' formats, or `None` for a single sample, which is drawn into the single-sampled targets directly
+    pub fn new(
+        device: &Device,
+        label: &str,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> Option<Self> {
+        let create = |format, usage, name| {
+            OurTexture::create_multisampled_target(
+                device,
+                width,
+                height,
+                format,
+                sample_count,
+                usage,
+                &format!("{label} Multisampled {name}"),
+            )
+        };
+        // only the depth is read from, by `DepthResolve`
+        (sample_count > 1).then(|| Self {
+            sample_count,
+            color: create(
+                PostProcessStack::SCENE_FORMAT,
+                TextureUsages::empty(),
+                "Color",
+            ),
+            velocity: create(
+                SceneTargets::VELOCITY_FORMAT,
+                TextureUsages::empty(),
+                "Velocity",
+            ),
+            depth: create(
+                OurTexture::DEPTH_FORMAT,
+                TextureUsages::TEXTURE_BINDING,
+                "Depth",
+            ),
+        })
+    }
+}
+
+/// Copies the first sample of each pixel of the scene's multisampled depth into `SceneTargets::depth`,
+/// for the effects which read depth. Passes can only resolve colour
+struct DepthResolve {
+    layout: BindGroupLayout,
+    pipeline: RenderPipeline,
+}
+
+impl DepthResolve {
+    fn new(device: &Device, library: &ShaderLibrary) -> Result<Self> {
+        let name = "depth_resolve.wgsl";
+        let source = preprocess(&library.resolve(name)?, &ShaderDefs::new())?;
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    multisampled: true,
+                    view_dimension: TextureViewDimension::D2,
+                    // which depth can be bound as, see `depth_resolve.wgsl`
+                    sample_type: TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            }],
+            label: Some("depth_resolve_bind_group_layout"),
+        });
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(name),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some(name),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(name),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            // only depth is written
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: Some(DepthStencilState {
+                format: OurTexture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Always,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState::default(),
+            multiview: None,
+        });
+        Ok(Self { layout, pipeline })
+    }
+
+    fn create_bind_group(&self, device: &Device, depth: &OurTexture) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            layout: &self.layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&depth.create_depth_view()),
+            }],
+            label: Some("depth_resolve_bind_group"),
+        })
+    }
+
+    /// Resolve the depth bound by `bind_group` into `output`
+    fn draw(&self, encoder: &mut CommandEncoder, bind_group: &BindGroup, output: &OurTexture) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Depth Resolve"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &output.view,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+/// The scene is rendered into an offscreen HDR target rather than the surface,
+/// then each enabled effect is applied in order, ping-ponging between two targets,
+/// before the result is finally copied to the surface
+pub struct PostProcessStack {
+    input_layout: BindGroupLayout,
+    sampler: Sampler,
+    targets: [RenderTarget; 2],
+    scene: SceneTargets,
+    depth_resolve: DepthResolve,
+    /// Writes the final result to the output, converting it to the output's format
+    output_pass: FullscreenPass,
+    effects: Vec<Box<dyn PostEffect>>,
+    /// Which of `targets` the last frame's result ended up in
+    result: usize,
+    /// Where in the output the result is drawn
+    output_region: OutputRegion,
+    /// Whether every effect is skipped, whether or not it's enabled
+    bypassed: bool,
+}
+
+impl PostProcessStack {
+    /// The format the scene and every intermediate pass renders to,
+    /// floating point so that values above 1.0 survive until the output pass
+    pub const SCENE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+    pub fn new(
+        device: &Device,
+        library: &ShaderLibrary,
+        output_format: TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        let input_layout = Self::create_input_layout(device);
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Post-process Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+        let targets = [
+            RenderTarget::new(device, &input_layout, &sampler, width, height),
+            RenderTarget::new(device, &input_layout, &sampler, width, height),
+        ];
+        let depth_resolve = DepthResolve::new(device, library)?;
+        let scene = SceneTargets::new(device, &depth_resolve, width, height, 1);
+        let output_pass = FullscreenPass::new(
+            device,
+            library,
+            "blit.wgsl",
+            &ShaderDefs::new(),
+            &input_layout,
+            output_format,
+        )?;
+
+        Ok(Self {
+            input_layout,
+            sampler,
+            targets,
+            scene,
+            depth_resolve,
+            output_pass,
+            effects: Vec::new(),
+            result: 0,
+            output_region: OutputRegion::default(),
+            bypassed: false,
+        })
+    }
+
+    /// Write the result to outputs of another format from now on, e.g. when the surface's format changes
+    pub fn set_output_format(
+        &mut self,
+        device: &Device,
+        library: &ShaderLibrary,
+        format: TextureFormat,
+    ) -> Result<()> {
+        self.output_pass = FullscreenPass::new(
+            device,
+            library,
+            "blit.wgsl",
+            &ShaderDefs::new(),
+            &self.input_layout,
+            format,
+        )?;
+        Ok(())
+    }
+
+    /// A new layout for the input of a `FullscreenPass`, which `input_layout` is one of
+    pub fn create_input_layout(device: &Device) -> BindGroupLayout {
+        device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    // compute passes can read the input too, e.g. to measure it
+                    visibility: ShaderStages::FRAGMENT | ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT | ShaderStages::COMPUTE,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("post_process_input_layout"),
+        })
+    }
+
+    /// The layout every effect's pass must use for group 0
+    pub fn input_layout(&self) -> &BindGroupLayout {
+        &self.input_layout
+    }
+
+    /// The view the scene should be rendered into, in `SCENE_FORMAT`
+    pub fn scene_view(&self) -> &TextureView {
+        &self.targets[0].view
+    }
+
+    /// The texture behind `scene_view`, e.g. to copy the scene out of partway through the frame
+    pub fn scene_texture(&self) -> &Texture {
+        &self.targets[0].texture
+    }
+
+    /// The scene's depth buffer, motion vectors and other non-colour targets
+    pub fn scene(&self) -> &SceneTargets {
+        &self.scene
+    }
+
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        self.targets = [
+            RenderTarget::new(device, &self.input_layout, &self.sampler, width, height),
+            RenderTarget::new(device, &self.input_layout, &self.sampler, width, height),
+        ];
+        self.scene = SceneTargets::new(
+            device,
+            &self.depth_resolve,
+            width,
+            height,
+            self.scene.sample_count(),
+        );
+        for effect in &mut self.effects {
+            effect.resize(device, &self.scene);
+        }
+    }
+
+    /// Draw the scene with `sample_count` samples per pixel from now on, into `SceneTargets::multisampled`,
+    /// which is resolved into `scene_view` and the rest of `scene` before the effects are applied.
+    /// The effects go on reading the same single-sampled targets, so they don't have to be resized
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        self.scene
+            .set_sample_count(device, &self.depth_resolve, sample_count);
+    }
+
+    /// Add `effect` to the end of the stack
+    pub fn push(&mut self, effect: impl PostEffect) {
+        self.effects.push(Box::new(effect));
+    }
+
+    /// Find the effect of type `T`, e.g. to change its settings
+    pub fn effect_mut<T: PostEffect>(&mut self) -> Option<&mut T> {
+        self.effects
+            .iter_mut()
+            .find_map(|effect| effect.as_any_mut().downcast_mut())
+    }
+
+    /// Apply every enabled effect to the scene, writing the result to `output`
+    pub fn render(&mut self, queue: &Queue, encoder: &mut CommandEncoder, output: &TextureView) {
+        puffin::profile_function!();
+        // its colour is resolved by the scene's passes, but its depth has to be done here
+        if let Some(bind_group) = &self.scene.depth_resolve {
+            self.depth_resolve
+                .draw(encoder, bind_group, &self.scene.depth);
+        }
+        // the scene starts off in the first target
+        let mut current = 0;
+        let bypassed = self.bypassed;
+        for effect in self
+            .effects
+            .iter_mut()
+            .filter(|effect| !bypassed && effect.enabled())
+        {
+            puffin::profile_scope!("post effect", effect.label());
+            effect.prepare(queue);
+            let (input, next) = (&self.targets[current], &self.targets[1 - current]);
+            effect.render(encoder, &input.bind_group, &next.view);
+            current = 1 - current;
+        }
+
+        self.result = current;
+        self.draw_output(encoder, output);
+    }
+
+    /// Skip every effect from now on if `bypassed` is set, so that the scene reaches the output as it was drawn,
+    /// e.g. for a debug view. The effects keep their settings for when it's unset
+    pub fn set_bypassed(&mut self, bypassed: bool) {
+        self.bypassed = bypassed;
+    }
+
+    /// Draw the result into only part of the output from now on, e.g. to letterbox it.
+    /// The region is in the output's pixels, so it has to be within every output it's drawn to
+    pub fn set_output_region(&mut self, region: OutputRegion) {
+        self.output_region = region;
+    }
+
+    /// Write the result of the last `render` to another output, e.g. one which can be read back
+    pub fn draw_output(&self, encoder: &mut CommandEncoder, output: &TextureView) {
+        self.output_pass.draw_region(
+            encoder,
+            &self.targets[self.result].bind_group,
+            &[],
+            output,
+            &self.output_region,
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use std::any::Any;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{BindGroup, CommandEncoder, Device, Queue, TextureView};
+
+use super::{FullscreenPass, PostEffect, PostProcessStack};
+use crate::{
+    shader::{ShaderDefs, ShaderLibrary},
+    uniform::UniformBuffer,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct AnaglyphUniform {
+    saturation: f32,
+    _padding: [f32; 3],
+}
+
+/// Combines the eyes drawn side by side by `Stereo` into a red/cyan anaglyph, the left eye in red.
+/// Each eye only gets half of the scene's width, so the picture is half as sharp across as it is down
+pub struct Anaglyph {
+    pass: FullscreenPass,
+    uniform: UniformBuffer<AnaglyphUniform>,
+    bind_group: BindGroup,
+    /// Follows `StereoSettings::output`, which `State::update` keeps it in line with
+    pub(crate) enabled: bool,
+}
+
+impl Anaglyph {
+    pub fn new(device: &Device, library: &ShaderLibrary, stack: &PostProcessStack) -> Result<Self> {
+        let pass = FullscreenPass::new(
+            device,
+            library,
+            "anaglyph.wgsl",
+            &ShaderDefs::new(),
+            stack.input_layout(),
+            PostProcessStack::SCENE_FORMAT,
+        )?;
+        let uniform = UniformBuffer::new(
+            device,
+            AnaglyphUniform {
+                saturation: 0.5,
+                _padding: [0.0; 3],
+            },
+            Some("Anaglyph Settings"),
+        );
+        let bind_group = pass.create_bind_group(device, 1, &[uniform.bind_group_entry(0)])?;
+
+        Ok(Self {
+            pass,
+            uniform,
+            bind_group,
+            enabled: false,
+        })
+    }
+
+    pub fn saturation(&self) -> f32 {
+        self.uniform.get().saturation
+    }
+
+    /// How much colour each eye keeps, from 0.0 (grey) to 1.0 (all of it). Colours which are mostly red
+    /// or cyan only reach one eye, so more saturation looks more colourful but ghosts and flickers more
+    pub fn set_saturation(&mut self, saturation: f32) {
+        self.uniform.get_mut().saturation = saturation.clamp(0.0, 1.0);
+    }
+}
+
+impl PostEffect for Anaglyph {
+    fn label(&self) -> &'static str {
+        "Anaglyph"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn prepare(&mut self, queue: &Queue) {
+        self.uniform.write(queue);
+    }
+
+    fn render(&self, encoder: &mut CommandEncoder, input: &BindGroup, output: &TextureView) {
+        self.pass.draw(encoder, input, &[&self.bind_group], output);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -0,0 +1,27 @@
+// Red/cyan anaglyph: the scene is drawn for each eye side by side, and this puts the left eye's view
+// in the red channel and the right eye's in green and blue, for glasses with a red lens over the left eye
+
+#include "fullscreen.wgsl"
+
+struct AnaglyphSettings {
+    // how much of each view's colour is kept, 0 being grey, which ghosts the least
+    saturation: f32,
+}
+@group(1) @binding(0)
+var<uniform> settings: AnaglyphSettings;
+
+fn desaturate(color: vec3<f32>) -> vec3<f32> {
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    return mix(vec3<f32>(luminance), color, settings.saturation);
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    // kept half a texel from the middle, so that neither view is filtered with the other
+    let half_texel = 0.5 / f32(textureDimensions(t_input).x);
+    let x = clamp(in.uv.x * 0.5, half_texel, 0.5 - half_texel);
+    let left = textureSample(t_input, s_input, vec2<f32>(x, in.uv.y));
+    let right = textureSample(t_input, s_input, vec2<f32>(x + 0.5, in.uv.y));
+    let color = vec3<f32>(desaturate(left.rgb).r, desaturate(right.rgb).gb);
+    return vec4<f32>(color, max(left.a, right.a));
+}
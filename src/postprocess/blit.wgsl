@@ -0,0 +1,8 @@
+// Copies the input to the output, converting between their formats
+
+#include "fullscreen.wgsl"
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_input, s_input, in.uv);
+}
@@ -0,0 +1,108 @@
+use std::any::Any;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{BindGroup, CommandEncoder, Device, Queue, TextureView};
+
+use super::{FullscreenPass, PostEffect, PostProcessStack};
+use crate::{
+    shader::{ShaderDefs, ShaderLibrary},
+    uniform::UniformBuffer,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    intensity: f32,
+    radius: f32,
+    _padding: f32,
+}
+
+/// Makes anything brighter than the screen can show glow, e.g. emissive surfaces and the sun
+pub struct Bloom {
+    pass: FullscreenPass,
+    uniform: UniformBuffer<BloomUniform>,
+    bind_group: BindGroup,
+    pub enabled: bool,
+}
+
+impl Bloom {
+    pub fn new(device: &Device, library: &ShaderLibrary, stack: &PostProcessStack) -> Result<Self> {
+        let pass = FullscreenPass::new(
+            device,
+            library,
+            "bloom.wgsl",
+            &ShaderDefs::new(),
+            stack.input_layout(),
+            PostProcessStack::SCENE_FORMAT,
+        )?;
+        let uniform = UniformBuffer::new(
+            device,
+            BloomUniform {
+                threshold: 1.0,
+                intensity: 1.0,
+                radius: 24.0,
+                _padding: 0.0,
+            },
+            Some("Bloom Settings"),
+        );
+        let bind_group = pass.create_bind_group(device, 1, &[uniform.bind_group_entry(0)])?;
+
+        Ok(Self {
+            pass,
+            uniform,
+            bind_group,
+            enabled: false,
+        })
+    }
+
+    pub fn threshold(&self) -> f32 {
+        self.uniform.get().threshold
+    }
+
+    /// How bright something has to be before it glows, where 1.0 is the brightest the screen can show
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.uniform.get_mut().threshold = threshold.max(0.0);
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.uniform.get().intensity
+    }
+
+    /// How strong the glow is
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.uniform.get_mut().intensity = intensity.max(0.0);
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.uniform.get().radius
+    }
+
+    /// How far the glow spreads, in pixels
+    pub fn set_radius(&mut self, radius: f32) {
+        self.uniform.get_mut().radius = radius.max(0.0);
+    }
+}
+
+impl PostEffect for Bloom {
+    fn label(&self) -> &'static str {
+        "Bloom"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled && self.intensity() > 0.0 && self.radius() > 0.0
+    }
+
+    fn prepare(&mut self, queue: &Queue) {
+        self.uniform.write(queue);
+    }
+
+    fn render(&self, encoder: &mut CommandEncoder, input: &BindGroup, output: &TextureView) {
+        self.pass.draw(encoder, input, &[&self.bind_group], output);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
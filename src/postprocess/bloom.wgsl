@@ -0,0 +1,43 @@
+// Bloom, spreading light from anything brighter than the threshold into the pixels around it,
+// in a single pass which gathers a disc of samples like depth of field does
+
+#include "fullscreen.wgsl"
+
+struct BloomSettings {
+    // how bright a pixel has to be before it blooms, where 1.0 is the brightest the screen can show
+    threshold: f32,
+    // how much of the glow is added back onto the image
+    intensity: f32,
+    // how far the glow spreads, in pixels
+    radius: f32,
+}
+@group(1) @binding(0)
+var<uniform> settings: BloomSettings;
+
+let SAMPLE_COUNT: i32 = 48;
+// Spacing the samples by the golden angle spreads them evenly over the disc
+let GOLDEN_ANGLE: f32 = 2.39996323;
+
+// The part of `color` which is over the threshold, keeping its hue
+fn bright_part(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(max(color.r, color.g), color.b);
+    return color * max(brightness - settings.threshold, 0.0) / max(brightness, 0.0001);
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_input));
+    let center = textureSampleLevel(t_input, s_input, in.uv, 0.0);
+    var glow = bright_part(center.rgb);
+    var total_weight = 1.0;
+    for (var i = 1; i < SAMPLE_COUNT; i = i + 1) {
+        let distance = sqrt(f32(i) / f32(SAMPLE_COUNT));
+        let angle = f32(i) * GOLDEN_ANGLE;
+        let offset = vec2<f32>(cos(angle), sin(angle)) * distance * settings.radius / size;
+        // falling off like a gaussian, so the glow has no visible edge
+        let weight = exp(-4.0 * distance * distance);
+        glow = glow + bright_part(textureSampleLevel(t_input, s_input, in.uv + offset, 0.0).rgb) * weight;
+        total_weight = total_weight + weight;
+    }
+    return vec4<f32>(center.rgb + glow / total_weight * settings.intensity, center.a);
+}
@@ -0,0 +1,86 @@
+use std::any::Any;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{BindGroup, CommandEncoder, Device, Queue, TextureView};
+
+use super::{FullscreenPass, PostEffect, PostProcessStack};
+use crate::{
+    shader::{ShaderDefs, ShaderLibrary},
+    uniform::UniformBuffer,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct ChromaticAberrationUniform {
+    intensity: f32,
+    _padding: [f32; 3],
+}
+
+/// Splits the red and blue channels apart towards the edges of the image, like a cheap lens
+pub struct ChromaticAberration {
+    pass: FullscreenPass,
+    uniform: UniformBuffer<ChromaticAberrationUniform>,
+    bind_group: BindGroup,
+    pub enabled: bool,
+}
+
+impl ChromaticAberration {
+    pub fn new(device: &Device, library: &ShaderLibrary, stack: &PostProcessStack) -> Result<Self> {
+        let pass = FullscreenPass::new(
+            device,
+            library,
+            "chromatic_aberration.wgsl",
+            &ShaderDefs::new(),
+            stack.input_layout(),
+            PostProcessStack::SCENE_FORMAT,
+        )?;
+        let uniform = UniformBuffer::new(
+            device,
+            ChromaticAberrationUniform {
+                intensity: 4.0,
+                _padding: [0.0; 3],
+            },
+            Some("Chromatic Aberration Settings"),
+        );
+        let bind_group = pass.create_bind_group(device, 1, &[uniform.bind_group_entry(0)])?;
+
+        Ok(Self {
+            pass,
+            uniform,
+            bind_group,
+            enabled: false,
+        })
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.uniform.get().intensity
+    }
+
+    /// How far apart, in pixels, the red and blue channels are in the corners of the image
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.uniform.get_mut().intensity = intensity.max(0.0);
+    }
+}
+
+impl PostEffect for ChromaticAberration {
+    fn label(&self) -> &'static str {
+        "Chromatic Aberration"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled && self.intensity() > 0.0
+    }
+
+    fn prepare(&mut self, queue: &Queue) {
+        self.uniform.write(queue);
+    }
+
+    fn render(&self, encoder: &mut CommandEncoder, input: &BindGroup, output: &TextureView) {
+        self.pass.draw(encoder, input, &[&self.bind_group], output);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -0,0 +1,24 @@
+// Chromatic aberration, where a lens focuses each wavelength slightly differently,
+// giving coloured fringes which get wider towards the edges of the image
+
+#include "fullscreen.wgsl"
+
+struct ChromaticAberrationSettings {
+    // how far apart the red and blue channels are at the corners, in pixels
+    intensity: f32,
+}
+@group(1) @binding(0)
+var<uniform> settings: ChromaticAberrationSettings;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_input));
+    let from_center = in.uv - vec2<f32>(0.5);
+    // scaled so that the offset at the corners is `intensity` pixels
+    let offset = from_center * sqrt(2.0) * settings.intensity / size;
+
+    let center = textureSample(t_input, s_input, in.uv);
+    let r = textureSample(t_input, s_input, in.uv + offset).r;
+    let b = textureSample(t_input, s_input, in.uv - offset).b;
+    return vec4<f32>(r, center.g, b, center.a);
+}
@@ -0,0 +1,226 @@
+use std::{any::Any, num::NonZeroU32};
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use image::{DynamicImage, GenericImageView};
+use wgpu::{
+    AddressMode, BindGroup, BindGroupEntry, BindingResource, CommandEncoder, Device, Extent3d,
+    FilterMode, ImageCopyTexture, ImageDataLayout, Origin3d, Queue, Sampler, SamplerDescriptor,
+    Texture, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+    TextureView, TextureViewDescriptor,
+};
+
+use super::{FullscreenPass, PostEffect, PostProcessStack};
+use crate::{
+    shader::{ShaderDefs, ShaderLibrary},
+    uniform::UniformBuffer,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct ColorGradingUniform {
+    blend: f32,
+    lut_size: f32,
+    _padding: [f32; 2],
+}
+
+/// Film-style colour grading using a 3D lookup table (LUT),
+/// loaded from the usual PNG strip format where an `N`x`N`x`N` LUT is laid out as
+/// `N` slices of `N`x`N` side by side, red increasing left to right within a slice,
+/// green increasing downwards, and blue increasing from slice to slice
+pub struct ColorGrading {
+    pass: FullscreenPass,
+    uniform: UniformBuffer<ColorGradingUniform>,
+    _lut: Texture,
+    sampler: Sampler,
+    bind_group: BindGroup,
+    /// Starts off disabled, as there's no point in applying the identity LUT
+    pub enabled: bool,
+}
+
+impl ColorGrading {
+    /// The size of the LUT used until one is loaded, which leaves colours unchanged
+    pub const DEFAULT_LUT_SIZE: u32 = 32;
+
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        library: &ShaderLibrary,
+        stack: &PostProcessStack,
+    ) -> Result<Self> {
+        let pass = FullscreenPass::new(
+            device,
+            library,
+            "color_grading.wgsl",
+            &ShaderDefs::new(),
+            stack.input_layout(),
+            PostProcessStack::SCENE_FORMAT,
+        )?;
+        let size = Self::DEFAULT_LUT_SIZE;
+        let uniform = UniformBuffer::new(
+            device,
+            ColorGradingUniform {
+                blend: 1.0,
+                lut_size: size as f32,
+                _padding: [0.0; 2],
+            },
+            Some("Colour Grading Settings"),
+        );
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Colour Grading LUT Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            // interpolate between the entries of the LUT
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let identity = (0..size * size * size)
+            .flat_map(|index| {
+                let (r, g, b) = (index % size, index / size % size, index / (size * size));
+                let [r, g, b] = [r, g, b].map(|channel| (channel * 255 / (size - 1)) as u8);
+                [r, g, b, 255]
+            })
+            .collect::<Vec<_>>();
+        let lut = create_lut(device, queue, &identity, size);
+        let lut_view = lut.create_view(&TextureViewDescriptor::default());
+        let bind_group = create_bind_group(device, &pass, &uniform, &lut_view, &sampler)?;
+
+        Ok(Self {
+            pass,
+            uniform,
+            _lut: lut,
+            sampler,
+            bind_group,
+            enabled: false,
+        })
+    }
+
+    /// Swap to a new LUT, taking effect from the next frame
+    #[tracing::instrument(skip_all)]
+    pub fn set_lut(&mut self, device: &Device, queue: &Queue, image: &DynamicImage) -> Result<()> {
+        let (width, height) = image.dimensions();
+        let size = height;
+        ensure!(
+            size >= 2 && width == size * size,
+            "a LUT strip should be N*N pixels wide and N pixels high, but it's {width}x{height}"
+        );
+
+        // Rearrange the slices of the strip into the layers of a 3D texture
+        let rgba = image.to_rgba8();
+        let mut data = Vec::with_capacity((size * size * size * 4) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.extend_from_slice(&rgba.get_pixel(b * size + r, g).0);
+                }
+            }
+        }
+
+        let lut = create_lut(device, queue, &data, size);
+        let lut_view = lut.create_view(&TextureViewDescriptor::default());
+        self.bind_group =
+            create_bind_group(device, &self.pass, &self.uniform, &lut_view, &self.sampler)?;
+        self._lut = lut;
+        self.uniform.get_mut().lut_size = size as f32;
+
+        Ok(())
+    }
+
+    /// Decode a LUT strip from an image file's bytes and swap to it
+    pub fn load_lut(&mut self, device: &Device, queue: &Queue, bytes: &[u8]) -> Result<()> {
+        let image = image::load_from_memory(bytes)?;
+        self.set_lut(device, queue, &image)
+    }
+
+    pub fn blend(&self) -> f32 {
+        self.uniform.get().blend
+    }
+
+    /// How strongly to apply the LUT, from 0.0 (not at all) to 1.0 (fully)
+    pub fn set_blend(&mut self, blend: f32) {
+        self.uniform.get_mut().blend = blend.clamp(0.0, 1.0);
+    }
+}
+
+impl PostEffect for ColorGrading {
+    fn label(&self) -> &'static str {
+        "Colour Grading"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled && self.blend() > 0.0
+    }
+
+    fn prepare(&mut self, queue: &Queue) {
+        self.uniform.write(queue);
+    }
+
+    fn render(&self, encoder: &mut CommandEncoder, input: &BindGroup, output: &TextureView) {
+        self.pass.draw(encoder, input, &[&self.bind_group], output);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+fn create_lut(device: &Device, queue: &Queue, data: &[u8], size: u32) -> Texture {
+    let extent = Extent3d {
+        width: size,
+        height: size,
+        depth_or_array_layers: size,
+    };
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some("Colour Grading LUT"),
+        size: extent,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D3,
+        // the shader does the sRGB conversions itself, as the LUT's input is sRGB too
+        format: TextureFormat::Rgba8Unorm,
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+    });
+    queue.write_texture(
+        ImageCopyTexture {
+            aspect: TextureAspect::All,
+            texture: &texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+        },
+        data,
+        ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(4 * size),
+            rows_per_image: NonZeroU32::new(size),
+        },
+        extent,
+    );
+    texture
+}
+
+fn create_bind_group(
+    device: &Device,
+    pass: &FullscreenPass,
+    uniform: &UniformBuffer<ColorGradingUniform>,
+    lut_view: &TextureView,
+    sampler: &Sampler,
+) -> Result<BindGroup> {
+    pass.create_bind_group(
+        device,
+        1,
+        &[
+            uniform.bind_group_entry(0),
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(lut_view),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::Sampler(sampler),
+            },
+        ],
+    )
+}
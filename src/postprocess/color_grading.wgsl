@@ -0,0 +1,29 @@
+// Colour grading through a 3D lookup table
+
+#include "fullscreen.wgsl"
+#include "color.wgsl"
+
+struct ColorGradingSettings {
+    // 0.0 leaves the image untouched, 1.0 applies the LUT fully
+    blend: f32,
+    // the number of entries along each axis of the LUT
+    lut_size: f32,
+}
+@group(1) @binding(0)
+var<uniform> settings: ColorGradingSettings;
+@group(1) @binding(1)
+var t_lut: texture_3d<f32>;
+@group(1) @binding(2)
+var s_lut: sampler;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_input, s_input, in.uv);
+    // LUTs are authored in sRGB, and map sRGB colours to sRGB colours
+    let encoded = linear_to_srgb(clamp(color.rgb, vec3<f32>(0.0), vec3<f32>(1.0)));
+    // Sample between the centres of the first and last texels, so 0.0 and 1.0 map exactly to the ends of the LUT
+    let scale = (settings.lut_size - 1.0) / settings.lut_size;
+    let offset = 0.5 / settings.lut_size;
+    let graded = srgb_to_linear(textureSample(t_lut, s_lut, encoded * scale + offset).rgb);
+    return vec4<f32>(mix(color.rgb, graded, settings.blend), color.a);
+}
@@ -0,0 +1,162 @@
+use std::any::Any;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    BindGroup, BindGroupEntry, BindingResource, CommandEncoder, Device, Queue, TextureView,
+};
+
+use super::{FullscreenPass, PostEffect, PostProcessStack, SceneTargets};
+use crate::{
+    camera::Camera,
+    shader::{ShaderDefs, ShaderLibrary},
+    uniform::UniformBuffer,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct DepthOfFieldUniform {
+    focus_distance: f32,
+    aperture: f32,
+    max_radius: f32,
+    autofocus: u32,
+    znear: f32,
+    zfar: f32,
+    _padding: [f32; 2],
+}
+
+/// Blurs everything in front of and behind the focus distance, based on the scene's depth buffer
+pub struct DepthOfField {
+    pass: FullscreenPass,
+    uniform: UniformBuffer<DepthOfFieldUniform>,
+    bind_group: BindGroup,
+    pub enabled: bool,
+}
+
+impl DepthOfField {
+    pub fn new(
+        device: &Device,
+        library: &ShaderLibrary,
+        stack: &PostProcessStack,
+        camera: &Camera,
+    ) -> Result<Self> {
+        let pass = FullscreenPass::new(
+            device,
+            library,
+            "depth_of_field.wgsl",
+            &ShaderDefs::new(),
+            stack.input_layout(),
+            PostProcessStack::SCENE_FORMAT,
+        )?;
+        let uniform = UniformBuffer::new(
+            device,
+            DepthOfFieldUniform {
+                focus_distance: 7.0,
+                aperture: 0.5,
+                max_radius: 8.0,
+                autofocus: 0,
+                znear: camera.znear,
+                zfar: camera.zfar,
+                _padding: [0.0; 2],
+            },
+            Some("Depth of Field Settings"),
+        );
+        let bind_group = create_bind_group(device, &pass, &uniform, stack.scene())?;
+
+        Ok(Self {
+            pass,
+            uniform,
+            bind_group,
+            enabled: false,
+        })
+    }
+
+    pub fn focus_distance(&self) -> f32 {
+        self.uniform.get().focus_distance
+    }
+
+    /// The distance from the camera which is perfectly in focus, ignored when autofocus is on
+    pub fn set_focus_distance(&mut self, distance: f32) {
+        self.uniform.get_mut().focus_distance = distance.max(0.0);
+    }
+
+    pub fn aperture(&self) -> f32 {
+        self.uniform.get().aperture
+    }
+
+    /// Larger apertures make things go out of focus more quickly, for a shallower depth of field
+    pub fn set_aperture(&mut self, aperture: f32) {
+        self.uniform.get_mut().aperture = aperture.max(0.0);
+    }
+
+    pub fn max_radius(&self) -> f32 {
+        self.uniform.get().max_radius
+    }
+
+    /// The radius, in pixels, of the blur applied to things which are completely out of focus
+    pub fn set_max_radius(&mut self, radius: f32) {
+        self.uniform.get_mut().max_radius = radius.max(0.0);
+    }
+
+    pub fn autofocus(&self) -> bool {
+        self.uniform.get().autofocus != 0
+    }
+
+    /// Focus on whatever is under the centre of the screen, rather than at the focus distance
+    pub fn set_autofocus(&mut self, autofocus: bool) {
+        self.uniform.get_mut().autofocus = autofocus as u32;
+    }
+
+    /// Keep the depth linearisation in sync with the camera's clipping planes
+    pub fn set_clip_planes(&mut self, camera: &Camera) {
+        let uniform = self.uniform.get_mut();
+        uniform.znear = camera.znear;
+        uniform.zfar = camera.zfar;
+    }
+}
+
+impl PostEffect for DepthOfField {
+    fn label(&self) -> &'static str {
+        "Depth of Field"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled && self.max_radius() > 0.0
+    }
+
+    fn prepare(&mut self, queue: &Queue) {
+        self.uniform.write(queue);
+    }
+
+    fn resize(&mut self, device: &Device, scene: &SceneTargets) {
+        self.bind_group = create_bind_group(device, &self.pass, &self.uniform, scene)
+            .expect("the depth of field bind group should always match its shader");
+    }
+
+    fn render(&self, encoder: &mut CommandEncoder, input: &BindGroup, output: &TextureView) {
+        self.pass.draw(encoder, input, &[&self.bind_group], output);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+fn create_bind_group(
+    device: &Device,
+    pass: &FullscreenPass,
+    uniform: &UniformBuffer<DepthOfFieldUniform>,
+    scene: &SceneTargets,
+) -> Result<BindGroup> {
+    pass.create_bind_group(
+        device,
+        1,
+        &[
+            uniform.bind_group_entry(0),
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(&scene.depth.create_depth_view()),
+            },
+        ],
+    )
+}
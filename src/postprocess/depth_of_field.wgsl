@@ -0,0 +1,72 @@
+// Depth of field, as a single-pass "scatter-as-gather" blur:
+// each pixel gathers the samples around it whose circle of confusion (CoC) is large enough to reach it
+
+#include "fullscreen.wgsl"
+
+struct DepthOfFieldSettings {
+    // the distance from the camera which is perfectly in focus
+    focus_distance: f32,
+    // how quickly things go out of focus away from the focus distance
+    aperture: f32,
+    // the largest CoC radius, in pixels
+    max_radius: f32,
+    // when non-zero, focus on whatever is in the centre of the screen rather than `focus_distance`
+    autofocus: u32,
+    znear: f32,
+    zfar: f32,
+}
+@group(1) @binding(0)
+var<uniform> settings: DepthOfFieldSettings;
+// Bound as a float texture rather than `texture_depth_2d`, as naga can't translate
+// `textureLoad` from depth textures to GLSL. wgpu allows depth views to be bound either way
+@group(1) @binding(1)
+var t_depth: texture_2d<f32>;
+
+let SAMPLE_COUNT: i32 = 48;
+// Spacing the samples by the golden angle spreads them evenly over the disc
+let GOLDEN_ANGLE: f32 = 2.39996323;
+
+// Undo the perspective projection to get the distance from the camera
+fn linear_depth(pixel: vec2<i32>) -> f32 {
+    let depth = textureLoad(t_depth, pixel, 0).r;
+    return settings.znear * settings.zfar / (settings.zfar - depth * (settings.zfar - settings.znear));
+}
+
+// The radius in pixels that something at `depth` gets blurred over
+fn circle_of_confusion(depth: f32, focus: f32) -> f32 {
+    return clamp(settings.aperture * abs(depth - focus) / depth, 0.0, 1.0) * settings.max_radius;
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let max_pixel = vec2<i32>(size) - vec2<i32>(1);
+    let pixel = min(vec2<i32>(in.uv * size), max_pixel);
+
+    let focus = select(settings.focus_distance, linear_depth(vec2<i32>(size * 0.5)), settings.autofocus != 0u);
+    let center_depth = linear_depth(pixel);
+    let center_coc = circle_of_confusion(center_depth, focus);
+
+    let center = textureSampleLevel(t_input, s_input, in.uv, 0.0);
+    var color = center.rgb;
+    var total_weight = 1.0;
+    for (var i = 1; i < SAMPLE_COUNT; i = i + 1) {
+        let radius = settings.max_radius * sqrt(f32(i) / f32(SAMPLE_COUNT));
+        let angle = f32(i) * GOLDEN_ANGLE;
+        let sample_uv = in.uv + vec2<f32>(cos(angle), sin(angle)) * radius / size;
+        let sample_pixel = clamp(vec2<i32>(sample_uv * size), vec2<i32>(0), max_pixel);
+
+        let sample_depth = linear_depth(sample_pixel);
+        var sample_coc = circle_of_confusion(sample_depth, focus);
+        // Stop blurry backgrounds from bleeding over sharp things in front of them
+        if (sample_depth > center_depth) {
+            sample_coc = min(sample_coc, center_coc);
+        }
+        // Only count samples whose blur actually reaches this pixel
+        let weight = smoothstep(radius - 0.5, radius + 0.5, sample_coc);
+        color = color + textureSampleLevel(t_input, s_input, sample_uv, 0.0).rgb * weight;
+        total_weight = total_weight + weight;
+    }
+
+    return vec4<f32>(color / total_weight, center.a);
+}
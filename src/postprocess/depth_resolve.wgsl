@@ -0,0 +1,18 @@
+// Copies the first sample of each pixel of a multisampled depth buffer into a single-sampled one.
+// Averaging the samples would make up depths along edges which nothing was drawn at
+
+// Bound as floats rather than depth, which naga's GLSL backend can't load from
+@group(0) @binding(0)
+var t_depth: texture_multisampled_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    // the same triangle as `fullscreen.wgsl`'s
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @builtin(frag_depth) f32 {
+    return textureLoad(t_depth, vec2<i32>(position.xy), 0).x;
+}
@@ -0,0 +1,93 @@
+use std::any::Any;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{BindGroup, CommandEncoder, Device, Queue, TextureView};
+
+use super::{FullscreenPass, PostEffect, PostProcessStack};
+use crate::{
+    shader::{ShaderDefs, ShaderLibrary},
+    uniform::UniformBuffer,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct FilmGrainUniform {
+    intensity: f32,
+    time: f32,
+    _padding: [f32; 2],
+}
+
+/// Adds noise which changes every frame, like the grain of photographic film
+pub struct FilmGrain {
+    pass: FullscreenPass,
+    uniform: UniformBuffer<FilmGrainUniform>,
+    bind_group: BindGroup,
+    pub enabled: bool,
+}
+
+impl FilmGrain {
+    pub fn new(device: &Device, library: &ShaderLibrary, stack: &PostProcessStack) -> Result<Self> {
+        let pass = FullscreenPass::new(
+            device,
+            library,
+            "film_grain.wgsl",
+            &ShaderDefs::new(),
+            stack.input_layout(),
+            PostProcessStack::SCENE_FORMAT,
+        )?;
+        let uniform = UniformBuffer::new(
+            device,
+            FilmGrainUniform {
+                intensity: 0.05,
+                time: 0.0,
+                _padding: [0.0; 2],
+            },
+            Some("Film Grain Settings"),
+        );
+        let bind_group = pass.create_bind_group(device, 1, &[uniform.bind_group_entry(0)])?;
+
+        Ok(Self {
+            pass,
+            uniform,
+            bind_group,
+            enabled: false,
+        })
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.uniform.get().intensity
+    }
+
+    /// The strength of the grain, where 1.0 is noise covering the whole range of brightness
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.uniform.get_mut().intensity = intensity.max(0.0);
+    }
+
+    /// The noise is seeded with `time` in seconds, so it's the same whenever the time is
+    pub fn set_time(&mut self, time: f32) {
+        self.uniform.get_mut().time = time;
+    }
+}
+
+impl PostEffect for FilmGrain {
+    fn label(&self) -> &'static str {
+        "Film Grain"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled && self.intensity() > 0.0
+    }
+
+    fn prepare(&mut self, queue: &Queue) {
+        self.uniform.write(queue);
+    }
+
+    fn render(&self, encoder: &mut CommandEncoder, input: &BindGroup, output: &TextureView) {
+        self.pass.draw(encoder, input, &[&self.bind_group], output);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -0,0 +1,30 @@
+// Animated film grain, adding a different pattern of noise every frame
+
+#include "fullscreen.wgsl"
+#include "color.wgsl"
+
+struct FilmGrainSettings {
+    // the strength of the noise, in sRGB units
+    intensity: f32,
+    // seconds since the effect was created, to animate the noise
+    time: f32,
+}
+@group(1) @binding(0)
+var<uniform> settings: FilmGrainSettings;
+
+// A cheap hash giving a pseudo-random number between 0.0 and 1.0 for each input
+fn hash(p: vec3<f32>) -> f32 {
+    var q = fract(p * 0.1031);
+    q = q + dot(q, q.zyx + 31.32);
+    return fract((q.x + q.y) * q.z);
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_input, s_input, in.uv);
+    let noise = hash(vec3<f32>(in.clip_position.xy, fract(settings.time) * 1000.0)) - 0.5;
+    // Add the grain in sRGB space, so that it's equally visible in the shadows and highlights
+    let encoded = linear_to_srgb(max(color.rgb, vec3<f32>(0.0)));
+    let grainy = srgb_to_linear(max(encoded + noise * settings.intensity, vec3<f32>(0.0)));
+    return vec4<f32>(grainy, color.a);
+}
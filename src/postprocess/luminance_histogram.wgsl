@@ -0,0 +1,110 @@
+// Auto exposure: `build_histogram` sorts the scene's pixels into bins by their log luminance,
+// then `adapt` finds the average and moves the exposure towards it a little each frame, like an eye adapting.
+// They run ahead of the frame on a half size copy of the scene tonemapping was given last frame
+// Based on https://bruop.github.io/exposure/
+
+let BIN_COUNT: u32 = 256u;
+
+struct HistogramSettings {
+    // the log2 luminance at the bottom of bin 1. Anything darker goes in bin 0, which is left out of the average
+    min_log_luminance: f32,
+    // how many stops bins 1 to 255 cover
+    log_luminance_range: f32,
+    // how far to move towards the average this frame, from 0 to 1
+    adaptation: f32,
+    // stops added to the exposure picked from the average
+    compensation: f32,
+    min_exposure: f32,
+    max_exposure: f32,
+}
+@group(1) @binding(0)
+var<uniform> settings: HistogramSettings;
+// Filled in by `build_histogram`, then emptied again by `adapt`
+@group(1) @binding(1)
+var<storage, read_write> histogram: array<atomic<u32>, 256>;
+
+struct Adaptation {
+    // copied into the tonemapping pass' settings every frame
+    exposure: f32,
+    // the average luminance the exposure has adapted to so far
+    luminance: f32,
+}
+// Written this frame, while the frame copies the exposure out of `previous`
+@group(1) @binding(2)
+var<storage, read_write> adaptation: Adaptation;
+// Written last frame
+@group(1) @binding(3)
+var<storage, read> previous: Adaptation;
+
+// The copy of the scene
+@group(0) @binding(0)
+var t_input: texture_2d<f32>;
+
+var<workgroup> bins: array<atomic<u32>, 256>;
+var<workgroup> weighted: array<u32, 256>;
+
+fn bin(color: vec3<f32>) -> u32 {
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    if (luminance < exp2(settings.min_log_luminance)) {
+        return 0u;
+    }
+    let position = clamp(
+        (log2(luminance) - settings.min_log_luminance) / settings.log_luminance_range,
+        0.0,
+        1.0
+    );
+    return u32(position * f32(BIN_COUNT - 2u)) + 1u;
+}
+
+// One invocation per pixel, each workgroup counting into its own bins before adding them to the histogram
+@compute @workgroup_size(16, 16)
+fn build_histogram(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) index: u32,
+) {
+    atomicStore(&bins[index], 0u);
+    workgroupBarrier();
+
+    let pixel = vec2<i32>(id.xy);
+    if (all(pixel < textureDimensions(t_input))) {
+        let color = textureLoad(t_input, pixel, 0).rgb;
+        atomicAdd(&bins[bin(color)], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram[index], atomicLoad(&bins[index]));
+}
+
+// A single workgroup, one invocation per bin
+@compute @workgroup_size(256)
+fn adapt(@builtin(local_invocation_index) index: u32) {
+    let count = atomicLoad(&histogram[index]);
+    atomicStore(&histogram[index], 0u);
+    weighted[index] = count * index;
+    workgroupBarrier();
+
+    // add up every bin's count times its index, halving the number of invocations each step
+    for (var stride = BIN_COUNT / 2u; stride > 0u; stride = stride / 2u) {
+        if (index < stride) {
+            weighted[index] = weighted[index] + weighted[index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if (index == 0u) {
+        let size = textureDimensions(t_input);
+        // `count` is bin 0's, the pixels too dark to count
+        let lit = max(f32(size.x * size.y) - f32(count), 1.0);
+        let mean_bin = f32(weighted[0]) / lit - 1.0;
+        let log_luminance = mean_bin / f32(BIN_COUNT - 2u) * settings.log_luminance_range
+            + settings.min_log_luminance;
+        let luminance = mix(previous.luminance, exp2(log_luminance), settings.adaptation);
+        adaptation.luminance = luminance;
+        // exposed so that the average comes out mid grey
+        adaptation.exposure = clamp(
+            0.18 / luminance * exp2(settings.compensation),
+            settings.min_exposure,
+            settings.max_exposure
+        );
+    }
+}
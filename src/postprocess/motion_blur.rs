@@ -0,0 +1,125 @@
+use std::any::Any;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    BindGroup, BindGroupEntry, BindingResource, CommandEncoder, Device, Queue, TextureView,
+};
+
+use super::{FullscreenPass, PostEffect, PostProcessStack, SceneTargets};
+use crate::{
+    shader::{ShaderDefs, ShaderLibrary},
+    uniform::UniformBuffer,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct MotionBlurUniform {
+    shutter: f32,
+    max_length: f32,
+    _padding: [f32; 2],
+}
+
+/// Smears each pixel along the direction it moved in since the last frame,
+/// using the motion vectors the scene writes to `SceneTargets::velocity`
+pub struct MotionBlur {
+    pass: FullscreenPass,
+    uniform: UniformBuffer<MotionBlurUniform>,
+    bind_group: BindGroup,
+    pub enabled: bool,
+}
+
+impl MotionBlur {
+    pub fn new(device: &Device, library: &ShaderLibrary, stack: &PostProcessStack) -> Result<Self> {
+        let pass = FullscreenPass::new(
+            device,
+            library,
+            "motion_blur.wgsl",
+            &ShaderDefs::new(),
+            stack.input_layout(),
+            PostProcessStack::SCENE_FORMAT,
+        )?;
+        let uniform = UniformBuffer::new(
+            device,
+            MotionBlurUniform {
+                shutter: 0.5,
+                max_length: 32.0,
+                _padding: [0.0; 2],
+            },
+            Some("Motion Blur Settings"),
+        );
+        let bind_group = create_bind_group(device, &pass, &uniform, stack.scene())?;
+
+        Ok(Self {
+            pass,
+            uniform,
+            bind_group,
+            enabled: false,
+        })
+    }
+
+    pub fn shutter(&self) -> f32 {
+        self.uniform.get().shutter
+    }
+
+    /// The fraction of a frame the shutter stays open for, 0.5 being the film-like "180 degree shutter",
+    /// longer exposures give longer blurs
+    pub fn set_shutter(&mut self, shutter: f32) {
+        self.uniform.get_mut().shutter = shutter.max(0.0);
+    }
+
+    pub fn max_length(&self) -> f32 {
+        self.uniform.get().max_length
+    }
+
+    /// The longest blur in pixels, so that very fast movements don't smear across the whole screen
+    pub fn set_max_length(&mut self, length: f32) {
+        self.uniform.get_mut().max_length = length.max(0.0);
+    }
+}
+
+impl PostEffect for MotionBlur {
+    fn label(&self) -> &'static str {
+        "Motion Blur"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled && self.shutter() > 0.0 && self.max_length() > 0.0
+    }
+
+    fn prepare(&mut self, queue: &Queue) {
+        self.uniform.write(queue);
+    }
+
+    fn resize(&mut self, device: &Device, scene: &SceneTargets) {
+        self.bind_group = create_bind_group(device, &self.pass, &self.uniform, scene)
+            .expect("the motion blur bind group should always match its shader");
+    }
+
+    fn render(&self, encoder: &mut CommandEncoder, input: &BindGroup, output: &TextureView) {
+        self.pass.draw(encoder, input, &[&self.bind_group], output);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+fn create_bind_group(
+    device: &Device,
+    pass: &FullscreenPass,
+    uniform: &UniformBuffer<MotionBlurUniform>,
+    scene: &SceneTargets,
+) -> Result<BindGroup> {
+    pass.create_bind_group(
+        device,
+        1,
+        &[
+            uniform.bind_group_entry(0),
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(&scene.velocity.view),
+            },
+        ],
+    )
+}
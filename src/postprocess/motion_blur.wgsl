@@ -0,0 +1,43 @@
+// Motion blur, averaging samples along the direction each pixel moved in since the last frame
+
+#include "fullscreen.wgsl"
+
+struct MotionBlurSettings {
+    // the fraction of the frame the virtual camera's shutter is open for
+    shutter: f32,
+    // the longest blur, in pixels
+    max_length: f32,
+}
+@group(1) @binding(0)
+var<uniform> settings: MotionBlurSettings;
+@group(1) @binding(1)
+var t_velocity: texture_2d<f32>;
+
+let SAMPLE_COUNT: i32 = 16;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_velocity));
+    let pixel = min(vec2<i32>(in.uv * size), vec2<i32>(size) - vec2<i32>(1));
+
+    var velocity = textureLoad(t_velocity, pixel, 0).xy * settings.shutter;
+    let length_in_pixels = length(velocity * size);
+    if (length_in_pixels > settings.max_length) {
+        velocity = velocity * (settings.max_length / length_in_pixels);
+    }
+
+    let center = textureSampleLevel(t_input, s_input, in.uv, 0.0);
+    // Not worth blurring movements of less than half a pixel
+    if (length(velocity * size) < 0.5) {
+        return center;
+    }
+
+    // Spread the samples over the path the pixel took while the shutter was open, centred on now
+    var color = vec3<f32>(0.0);
+    for (var i = 0; i < SAMPLE_COUNT; i = i + 1) {
+        let t = (f32(i) + 0.5) / f32(SAMPLE_COUNT) - 0.5;
+        color = color + textureSampleLevel(t_input, s_input, in.uv + velocity * t, 0.0).rgb;
+    }
+
+    return vec4<f32>(color / f32(SAMPLE_COUNT), center.a);
+}
@@ -0,0 +1,460 @@
+use std::{any::Any, cell::RefCell, rc::Rc, str::FromStr};
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    AddressMode, BindGroup, BindGroupEntry, BindGroupLayout, Buffer, BufferUsages, CommandEncoder,
+    ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Device, FilterMode,
+    PipelineLayoutDescriptor, Queue, Sampler, SamplerDescriptor, ShaderModuleDescriptor,
+    ShaderSource, TextureView,
+};
+
+use super::{FullscreenPass, PostEffect, PostProcessStack, RenderTarget, SceneTargets};
+use crate::{
+    capabilities::Capabilities,
+    compute::{ComputeWorkload, PingPong},
+    reflection::ShaderReflection,
+    shader::{preprocess, ShaderDefs, ShaderLibrary},
+    uniform::UniformBuffer,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct TonemappingUniform {
+    exposure: f32,
+    _padding: [f32; 3],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+struct HistogramUniform {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+    compensation: f32,
+    min_exposure: f32,
+    max_exposure: f32,
+    _padding: [f32; 2],
+}
+
+/// The layout of `Adaptation` in `luminance_histogram.wgsl`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct Adaptation {
+    exposure: f32,
+    luminance: f32,
+}
+
+/// How the exposure is picked
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExposureMode {
+    /// A fixed exposure, in stops (EV) brighter or darker than the scene as it's rendered
+    Manual(f32),
+    /// Adapt to the scene's average brightness over time, like an eye, see `AutoExposure`.
+    /// This needs compute shaders
+    Auto,
+}
+
+impl Default for ExposureMode {
+    fn default() -> Self {
+        Self::Manual(0.0)
+    }
+}
+
+/// Parses `auto`, or a manual exposure in stops, e.g. `-1.5`
+impl FromStr for ExposureMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            ev => Ok(Self::Manual(ev.parse().with_context(|| {
+                format!("invalid exposure `{ev}`, expected `auto` or a number of stops")
+            })?)),
+        }
+    }
+}
+
+/// How `ExposureMode::Auto` adapts to the scene
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutoExposure {
+    /// Stops added to the exposure picked from the scene's brightness, positive to brighten it
+    pub compensation: f32,
+    /// The darkest it can expose the scene, in stops
+    pub min_ev: f32,
+    /// The brightest it can expose the scene, in stops
+    pub max_ev: f32,
+    /// How quickly it adapts, where about two thirds of a change in brightness is adapted to in `1 / speed` seconds
+    pub speed: f32,
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        Self {
+            compensation: 0.0,
+            min_ev: -4.0,
+            max_ev: 4.0,
+            speed: 1.5,
+        }
+    }
+}
+
+/// Exposes the scene, then tonemaps it with a filmic curve so that anything brighter than the screen
+/// can show rolls off rather than clipping. Belongs after bloom, which needs the HDR values,
+/// and before colour grading, whose LUT expects colours between 0 and 1
+pub struct Tonemapping {
+    pass: FullscreenPass,
+    uniform: UniformBuffer<TonemappingUniform>,
+    bind_group: BindGroup,
+    mode: ExposureMode,
+    pub auto_exposure: AutoExposure,
+    /// Measures the scene for `ExposureMode::Auto`, if the adapter has compute shaders,
+    /// shared with the `ExposureWorkload` which runs its compute passes
+    histogram: Option<Rc<RefCell<LuminanceHistogram>>>,
+    /// Seconds since the last frame, which the exposure adapts over
+    delta_time: f32,
+    /// Starts off disabled, so that the scene is shown as it's rendered unless asked otherwise
+    pub enabled: bool,
+}
+
+impl Tonemapping {
+    /// The log2 luminances the histogram covers, which are well beyond how dark or bright the scene gets
+    const MIN_LOG_LUMINANCE: f32 = -8.0;
+    const LOG_LUMINANCE_RANGE: f32 = 12.0;
+
+    pub fn new(
+        device: &Device,
+        library: &ShaderLibrary,
+        stack: &PostProcessStack,
+        capabilities: &Capabilities,
+    ) -> Result<Self> {
+        let pass = FullscreenPass::new(
+            device,
+            library,
+            "tonemapping.wgsl",
+            &ShaderDefs::new(),
+            stack.input_layout(),
+            PostProcessStack::SCENE_FORMAT,
+        )?;
+        let uniform = UniformBuffer::new(
+            device,
+            TonemappingUniform {
+                exposure: 1.0,
+                _padding: [0.0; 3],
+            },
+            Some("Tonemapping Settings"),
+        );
+        let bind_group = pass.create_bind_group(device, 1, &[uniform.bind_group_entry(0)])?;
+        let histogram = capabilities
+            .compute_shaders
+            .then(|| LuminanceHistogram::new(device, library, stack))
+            .transpose()?
+            .map(|histogram| Rc::new(RefCell::new(histogram)));
+
+        Ok(Self {
+            pass,
+            uniform,
+            bind_group,
+            mode: ExposureMode::default(),
+            auto_exposure: AutoExposure::default(),
+            histogram,
+            delta_time: 0.0,
+            enabled: false,
+        })
+    }
+
+    pub fn mode(&self) -> ExposureMode {
+        self.mode
+    }
+
+    /// Pick the exposure by hand or adapt it to the scene, which fails for `ExposureMode::Auto`
+    /// without compute shaders
+    pub fn set_mode(&mut self, mode: ExposureMode) -> Result<()> {
+        ensure!(
+            mode != ExposureMode::Auto || self.histogram.is_some(),
+            "auto exposure needs compute shaders, which this adapter doesn't have"
+        );
+        if let ExposureMode::Manual(ev) = mode {
+            // whatever auto exposure left in the uniform has to be replaced
+            self.uniform.get_mut().exposure = ev.exp2();
+        }
+        self.mode = mode;
+        Ok(())
+    }
+
+    /// How long it's been since the last frame in seconds, which auto exposure adapts over
+    pub fn set_delta_time(&mut self, delta_time: f32) {
+        self.delta_time = delta_time;
+    }
+
+    /// What measures the scene for `ExposureMode::Auto` ahead of each frame, which has to be added to
+    /// `AsyncCompute` for the exposure to adapt. `None` without compute shaders
+    pub fn exposure_workload(&self) -> Option<ExposureWorkload> {
+        self.histogram.clone().map(ExposureWorkload)
+    }
+}
+
+impl PostEffect for Tonemapping {
+    fn label(&self) -> &'static str {
+        "Tonemapping"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn prepare(&mut self, queue: &Queue) {
+        match (self.mode, &mut self.histogram) {
+            (ExposureMode::Manual(_), _) => self.uniform.write(queue),
+            // the exposure is copied into the uniform on the GPU instead
+            (ExposureMode::Auto, Some(histogram)) => {
+                let histogram = &mut *histogram.borrow_mut();
+                let AutoExposure {
+                    compensation,
+                    min_ev,
+                    max_ev,
+                    speed,
+                } = self.auto_exposure;
+                histogram.settings.set(&HistogramUniform {
+                    min_log_luminance: Self::MIN_LOG_LUMINANCE,
+                    log_luminance_range: Self::LOG_LUMINANCE_RANGE,
+                    adaptation: 1.0 - (-self.delta_time * speed.max(0.0)).exp(),
+                    compensation,
+                    min_exposure: min_ev.exp2(),
+                    max_exposure: max_ev.max(min_ev).exp2(),
+                    ..Default::default()
+                });
+                histogram.settings.write(queue);
+            }
+            (ExposureMode::Auto, None) => unreachable!("`set_mode` checks there's a histogram"),
+        }
+    }
+
+    fn resize(&mut self, device: &Device, scene: &SceneTargets) {
+        if let Some(histogram) = &self.histogram {
+            histogram
+                .borrow_mut()
+                .resize(device, scene.width, scene.height);
+        }
+    }
+
+    fn render(&self, encoder: &mut CommandEncoder, input: &BindGroup, output: &TextureView) {
+        if let (ExposureMode::Auto, Some(histogram)) = (self.mode, &self.histogram) {
+            let mut histogram = histogram.borrow_mut();
+            histogram.copy_scene(encoder, input);
+            histogram.copy_exposure(encoder, self.uniform.buffer());
+        }
+        self.pass.draw(encoder, input, &[&self.bind_group], output);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// The compute passes in `luminance_histogram.wgsl`, which adapt the exposure to the scene.
+/// They run ahead of the frame as an `ExposureWorkload`, on a copy of the scene tonemapping was given
+/// the frame before, so that the frame never waits for them
+struct LuminanceHistogram {
+    build: ComputePipeline,
+    adapt: ComputePipeline,
+    settings: UniformBuffer<HistogramUniform>,
+    /// Copies the scene into `scene`
+    downsample: FullscreenPass,
+    input_layout: BindGroupLayout,
+    sampler: Sampler,
+    /// The frame copies the scene into one while the workload measures the other
+    scene: PingPong<RenderTarget>,
+    /// Whether the frame has copied the scene since it was last measured, which it doesn't
+    /// while tonemapping's disabled or the exposure's set by hand
+    copied: bool,
+    /// The workload adapts the exposure into one while the frame copies it out of the other, kept from
+    /// one frame to the next so that the exposure changes gradually
+    adaptation: PingPong<Buffer>,
+    /// Adapting into each of `adaptation` from the other
+    bind_groups: PingPong<BindGroup>,
+    /// The size of `scene`, which there's an invocation of `build_histogram` for each pixel of
+    size: (u32, u32),
+}
+
+impl LuminanceHistogram {
+    /// Must match `@workgroup_size` of `build_histogram` in `luminance_histogram.wgsl`
+    const WORKGROUP_SIZE: u32 = 16;
+    const BIN_COUNT: u64 = 256;
+
+    fn new(device: &Device, library: &ShaderLibrary, stack: &PostProcessStack) -> Result<Self> {
+        let name = "luminance_histogram.wgsl";
+        let source = preprocess(&library.resolve(name)?, &ShaderDefs::new())?;
+        let reflection = ShaderReflection::from_code(&source.clone().into(), &ShaderDefs::new())
+            .with_context(|| format!("failed to reflect {name}"))?;
+        let layout = reflection.create_bind_group_layout(device, 1, Some(name));
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(name),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+        // group 0 is the copy of the scene, bound like a pass' input
+        let input_layout = PostProcessStack::create_input_layout(device);
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some(name),
+            bind_group_layouts: &[&input_layout, &layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some(name),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+        let (build, adapt) = (pipeline("build_histogram"), pipeline("adapt"));
+        let downsample = FullscreenPass::new(
+            device,
+            library,
+            "blit.wgsl",
+            &ShaderDefs::new(),
+            stack.input_layout(),
+            PostProcessStack::SCENE_FORMAT,
+        )?;
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Luminance Histogram Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let settings = UniformBuffer::new(
+            device,
+            HistogramUniform::default(),
+            Some("Luminance Histogram Settings"),
+        );
+        let histogram = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Luminance Histogram"),
+            contents: &[0; Self::BIN_COUNT as usize * 4],
+            usage: BufferUsages::STORAGE,
+        });
+        // starts off at an exposure of 1
+        let adaptation = PingPong::new(|_| {
+            device.create_buffer_init(&BufferInitDescriptor {
+                label: Some("Exposure Adaptation"),
+                contents: bytemuck::bytes_of(&Adaptation {
+                    exposure: 1.0,
+                    luminance: 0.18,
+                }),
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            })
+        });
+        let bind_group = |written: &Buffer, previous: &Buffer| {
+            reflection.create_bind_group(
+                device,
+                1,
+                &layout,
+                &[
+                    settings.bind_group_entry(0),
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: histogram.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: written.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: previous.as_entire_binding(),
+                    },
+                ],
+                Some(name),
+            )
+        };
+        // swapped along with `adaptation`, so that the one written is always `adaptation.write()`
+        let bind_groups = PingPong::from_pair([
+            bind_group(adaptation.write(), adaptation.read())?,
+            bind_group(adaptation.read(), adaptation.write())?,
+        ]);
+
+        let (width, height) = Self::scene_size(stack.scene().width, stack.scene().height);
+        let scene =
+            PingPong::new(|_| RenderTarget::new(device, &input_layout, &sampler, width, height));
+        Ok(Self {
+            build,
+            adapt,
+            settings,
+            downsample,
+            input_layout,
+            sampler,
+            scene,
+            copied: false,
+            adaptation,
+            bind_groups,
+            size: (width, height),
+        })
+    }
+
+    /// Half the scene's size, which is plenty for an average and a quarter of the pixels to measure
+    fn scene_size(width: u32, height: u32) -> (u32, u32) {
+        ((width / 2).max(1), (height / 2).max(1))
+    }
+
+    fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        let (width, height) = Self::scene_size(width, height);
+        self.scene = PingPong::new(|_| {
+            RenderTarget::new(device, &self.input_layout, &self.sampler, width, height)
+        });
+        self.copied = false;
+        self.size = (width, height);
+    }
+
+    /// Copy `input`, the scene tonemapping's been given, to be measured ahead of the next frame
+    fn copy_scene(&mut self, encoder: &mut CommandEncoder, input: &BindGroup) {
+        self.downsample
+            .draw(encoder, input, &[], &self.scene.write().view);
+        self.copied = true;
+    }
+
+    /// Copy the exposure adapted ahead of the last frame into `exposure`, the tonemapping pass' uniform buffer
+    fn copy_exposure(&self, encoder: &mut CommandEncoder, exposure: &Buffer) {
+        // `exposure` is the first field of both
+        encoder.copy_buffer_to_buffer(self.adaptation.read(), 0, exposure, 0, 4);
+    }
+
+    /// Build the histogram of the scene copied last frame and adapt the exposure to it
+    fn measure(&mut self, encoder: &mut CommandEncoder) {
+        if !std::mem::take(&mut self.copied) {
+            return;
+        }
+        self.scene.swap();
+        self.adaptation.swap();
+        self.bind_groups.swap();
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Luminance Histogram Pass"),
+        });
+        compute_pass.set_bind_group(0, &self.scene.read().bind_group, &[]);
+        compute_pass.set_bind_group(1, self.bind_groups.write(), &[]);
+        compute_pass.set_pipeline(&self.build);
+        compute_pass.dispatch_workgroups(
+            self.size.0.div_ceil(Self::WORKGROUP_SIZE),
+            self.size.1.div_ceil(Self::WORKGROUP_SIZE),
+            1,
+        );
+        compute_pass.set_pipeline(&self.adapt);
+        compute_pass.dispatch_workgroups(1, 1, 1);
+    }
+}
+
+/// Runs auto exposure's compute passes ahead of each frame, see `Tonemapping::exposure_workload`
+pub struct ExposureWorkload(Rc<RefCell<LuminanceHistogram>>);
+
+impl ComputeWorkload for ExposureWorkload {
+    fn label(&self) -> &str {
+        "Luminance Histogram"
+    }
+
+    fn dispatch(&mut self, _queue: &Queue, encoder: &mut CommandEncoder) {
+        self.0.borrow_mut().measure(encoder);
+    }
+}
@@ -0,0 +1,28 @@
+// Scales the scene by the exposure, then maps it into the 0 to 1 the display can show with a filmic curve,
+// so that highlights roll off rather than clipping
+
+#include "fullscreen.wgsl"
+
+struct TonemappingSettings {
+    // what the scene's colours are multiplied by, picked by hand or adapted to the scene by `luminance_histogram.wgsl`
+    exposure: f32,
+}
+@group(1) @binding(0)
+var<uniform> settings: TonemappingSettings;
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve,
+// from https://knarkowicz.wordpress.com/2016/01/06/aces-filmic-tone-mapping-curve/
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_input, s_input, in.uv);
+    return vec4<f32>(aces(color.rgb * settings.exposure), color.a);
+}
@@ -0,0 +1,109 @@
+use std::any::Any;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{BindGroup, CommandEncoder, Device, Queue, TextureView};
+
+use super::{FullscreenPass, PostEffect, PostProcessStack};
+use crate::{
+    shader::{ShaderDefs, ShaderLibrary},
+    uniform::UniformBuffer,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct VignetteUniform {
+    intensity: f32,
+    radius: f32,
+    smoothness: f32,
+    _padding: f32,
+}
+
+/// Darkens the image towards its corners
+pub struct Vignette {
+    pass: FullscreenPass,
+    uniform: UniformBuffer<VignetteUniform>,
+    bind_group: BindGroup,
+    pub enabled: bool,
+}
+
+impl Vignette {
+    pub fn new(device: &Device, library: &ShaderLibrary, stack: &PostProcessStack) -> Result<Self> {
+        let pass = FullscreenPass::new(
+            device,
+            library,
+            "vignette.wgsl",
+            &ShaderDefs::new(),
+            stack.input_layout(),
+            PostProcessStack::SCENE_FORMAT,
+        )?;
+        let uniform = UniformBuffer::new(
+            device,
+            VignetteUniform {
+                intensity: 0.5,
+                radius: 0.5,
+                smoothness: 0.5,
+                _padding: 0.0,
+            },
+            Some("Vignette Settings"),
+        );
+        let bind_group = pass.create_bind_group(device, 1, &[uniform.bind_group_entry(0)])?;
+
+        Ok(Self {
+            pass,
+            uniform,
+            bind_group,
+            enabled: false,
+        })
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.uniform.get().intensity
+    }
+
+    /// How dark the corners get, from 0.0 (not at all) to 1.0 (completely black)
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.uniform.get_mut().intensity = intensity.clamp(0.0, 1.0);
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.uniform.get().radius
+    }
+
+    /// How far from the centre the darkening starts, where 1.0 is the corners
+    pub fn set_radius(&mut self, radius: f32) {
+        self.uniform.get_mut().radius = radius.max(0.0);
+    }
+
+    pub fn smoothness(&self) -> f32 {
+        self.uniform.get().smoothness
+    }
+
+    /// How gradually the darkening fades in, larger values give a softer edge
+    pub fn set_smoothness(&mut self, smoothness: f32) {
+        // a little smoothness stops `smoothstep` from dividing by zero
+        self.uniform.get_mut().smoothness = smoothness.max(0.001);
+    }
+}
+
+impl PostEffect for Vignette {
+    fn label(&self) -> &'static str {
+        "Vignette"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled && self.intensity() > 0.0
+    }
+
+    fn prepare(&mut self, queue: &Queue) {
+        self.uniform.write(queue);
+    }
+
+    fn render(&self, encoder: &mut CommandEncoder, input: &BindGroup, output: &TextureView) {
+        self.pass.draw(encoder, input, &[&self.bind_group], output);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
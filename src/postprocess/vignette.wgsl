@@ -0,0 +1,23 @@
+// Vignette, darkening the image towards its corners like an old camera lens
+
+#include "fullscreen.wgsl"
+
+struct VignetteSettings {
+    // how dark the corners get, from 0.0 (not at all) to 1.0 (black)
+    intensity: f32,
+    // the distance from the centre where the darkening starts, 1.0 being the corners
+    radius: f32,
+    // how gradually the darkening fades in past `radius`
+    smoothness: f32,
+}
+@group(1) @binding(0)
+var<uniform> settings: VignetteSettings;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_input, s_input, in.uv);
+    // 0.0 at the centre of the screen and 1.0 in the corners
+    let distance = length(in.uv - vec2<f32>(0.5)) * sqrt(2.0);
+    let falloff = smoothstep(settings.radius, settings.radius + settings.smoothness, distance);
+    return vec4<f32>(color.rgb * (1.0 - falloff * settings.intensity), color.a);
+}
@@ -0,0 +1,307 @@
+use std::{num::NonZeroU32, path::PathBuf, str::FromStr};
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    AddressMode, BindGroupEntry, BufferAddress, BufferDescriptor, BufferUsages,
+    CommandEncoderDescriptor, ComputePassDescriptor, ComputePipelineDescriptor, Device, Extent3d,
+    FilterMode, ImageCopyBuffer, ImageDataLayout, PipelineLayoutDescriptor, Queue,
+    SamplerDescriptor, ShaderModuleDescriptor, ShaderSource, TextureDescriptor, TextureDimension,
+    TextureUsages, TextureViewDescriptor,
+};
+
+use crate::{
+    capabilities::Capabilities,
+    decode::DecodedImages,
+    reflection::ShaderReflection,
+    shader::{preprocess, ShaderDefs, ShaderLibrary},
+    texture::{OurTexture, TextureKind},
+    uniform::UniformBuffer,
+};
+
+/// A texture drawn on the GPU by `procedural.wgsl` rather than loaded from an image.
+/// They all tile, and their colours are linear
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Procedural {
+    /// Alternating squares of `colors`, `squares` along each side
+    Checkerboard { squares: u32, colors: [[f32; 3]; 2] },
+    /// Perlin noise from black to white, `cells` across at its coarsest,
+    /// with `octaves` layers of finer and fainter noise on top
+    Noise { cells: u32, octaves: u32, seed: u32 },
+    /// From `top` down to `bottom`
+    Gradient { top: [f32; 3], bottom: [f32; 3] },
+}
+
+impl Procedural {
+    /// How many texels there are along each side
+    pub const SIZE: u32 = 512;
+    /// Must match `@workgroup_size` in `procedural.wgsl`
+    const WORKGROUP_SIZE: u32 = 8;
+
+    /// Draw the texture into a new `SIZE` x `SIZE` texture, which fails without compute shaders.
+    /// `kind` is what it holds, like an image's, see `OurTexture`, though it can't be `TextureKind::Hdr`
+    #[tracing::instrument(skip(device, queue, library, capabilities))]
+    pub fn generate(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        library: &ShaderLibrary,
+        capabilities: &Capabilities,
+        label: &str,
+        kind: TextureKind,
+    ) -> Result<OurTexture> {
+        ensure!(
+            capabilities.compute_shaders,
+            "procedural textures need compute shaders, which this adapter doesn't have"
+        );
+        ensure!(
+            kind != TextureKind::Hdr,
+            "procedural textures only have 8 bits per channel, so they can't be HDR"
+        );
+        let name = "procedural.wgsl";
+        let source = preprocess(&library.resolve(name)?, &ShaderDefs::new())?;
+        let reflection = ShaderReflection::from_code(&source.clone().into(), &ShaderDefs::new())
+            .with_context(|| format!("failed to reflect {name}"))?;
+        let layout = reflection.create_bind_group_layout(device, 0, Some(name));
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(name),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some(name),
+            layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some(name),
+                bind_group_layouts: &[&layout],
+                push_constant_ranges: &[],
+            })),
+            module: &shader,
+            entry_point: "cs_main",
+        });
+
+        let size = Extent3d {
+            width: Self::SIZE,
+            height: Self::SIZE,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: kind.format(),
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        });
+        // 4 bytes per texel, which for `SIZE` makes each row a multiple of `COPY_BYTES_PER_ROW_ALIGNMENT`
+        let bytes_per_row = 4 * Self::SIZE;
+        let texels = device.create_buffer(&BufferDescriptor {
+            label: Some("Procedural Texels"),
+            size: (bytes_per_row * Self::SIZE) as BufferAddress,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let mut uniform = ProceduralUniform::from(self);
+        uniform.size = Self::SIZE;
+        uniform.srgb = kind.is_srgb().into();
+        let uniform = UniformBuffer::new(device, uniform, Some(label));
+        let bind_group = reflection.create_bind_group(
+            device,
+            0,
+            &layout,
+            &[
+                uniform.bind_group_entry(0),
+                BindGroupEntry {
+                    binding: 1,
+                    resource: texels.as_entire_binding(),
+                },
+            ],
+            Some(label),
+        )?;
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Procedural Texture Encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("Procedural Texture Pass"),
+            });
+            compute_pass.set_pipeline(&pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            let workgroups = Self::SIZE.div_ceil(Self::WORKGROUP_SIZE);
+            compute_pass.dispatch_workgroups(workgroups, workgroups, 1);
+        }
+        encoder.copy_buffer_to_texture(
+            ImageCopyBuffer {
+                buffer: &texels,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(bytes_per_row),
+                    rows_per_image: NonZeroU32::new(Self::SIZE),
+                },
+            },
+            texture.as_image_copy(),
+            size,
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        // The same as an image's
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+        Ok(OurTexture {
+            texture,
+            view,
+            sampler,
+            size,
+            format: kind.format(),
+        })
+    }
+}
+
+/// Parses `checker[:squares]`, `noise[:cells[,octaves[,seed]]]` or `gradient`,
+/// e.g. `checker:16` or `noise:4,6`
+impl FromStr for Procedural {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, parameters) = s.split_once(':').unwrap_or((s, ""));
+        let mut parameters = parameters
+            .split(',')
+            .filter(|parameter| !parameter.trim().is_empty())
+            .map(|parameter| {
+                parameter
+                    .trim()
+                    .parse::<u32>()
+                    .with_context(|| format!("invalid number `{parameter}`"))
+            });
+        let mut next = |default: u32| parameters.next().unwrap_or(Ok(default));
+        let procedural = match kind {
+            "checker" => Self::Checkerboard {
+                squares: next(8)?,
+                colors: [[0.8; 3], [0.1; 3]],
+            },
+            "noise" => Self::Noise {
+                cells: next(4)?,
+                octaves: next(5)?,
+                seed: next(0)?,
+            },
+            "gradient" => Self::Gradient {
+                top: [1.0; 3],
+                bottom: [0.0; 3],
+            },
+            _ => bail!(
+                "unknown procedural texture `{kind}`, expected `checker`, `noise` or `gradient`"
+            ),
+        };
+        ensure!(
+            parameters.next().is_none(),
+            "too many numbers for a `{kind}` texture"
+        );
+        if let Self::Checkerboard { squares: 0, .. } | Self::Noise { cells: 0, .. } = procedural {
+            bail!("a `{kind}` texture needs at least 1 square or cell");
+        }
+        Ok(procedural)
+    }
+}
+
+/// The layout `procedural.wgsl` expects
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+struct ProceduralUniform {
+    kind: u32,
+    cells: u32,
+    octaves: u32,
+    seed: u32,
+    color_a: [f32; 3],
+    size: u32,
+    color_b: [f32; 3],
+    srgb: u32,
+}
+
+impl From<&Procedural> for ProceduralUniform {
+    fn from(procedural: &Procedural) -> Self {
+        match *procedural {
+            Procedural::Checkerboard { squares, colors } => Self {
+                kind: 0,
+                cells: squares,
+                color_a: colors[0],
+                color_b: colors[1],
+                ..Default::default()
+            },
+            Procedural::Noise {
+                cells,
+                octaves,
+                seed,
+            } => Self {
+                kind: 1,
+                cells,
+                octaves,
+                seed,
+                color_a: [0.0; 3],
+                color_b: [1.0; 3],
+                ..Default::default()
+            },
+            Procedural::Gradient { top, bottom } => Self {
+                kind: 2,
+                color_a: top,
+                color_b: bottom,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Where one of the scene's textures comes from
+#[derive(Clone, Debug, PartialEq)]
+pub enum TextureSource {
+    Image(PathBuf),
+    Procedural(Procedural),
+}
+
+impl TextureSource {
+    /// Load or generate the texture, which holds `kind`, see `OurTexture`.
+    /// Images are taken from `images` if they were decoded ahead of time
+    #[allow(clippy::too_many_arguments)]
+    pub fn load(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        library: &ShaderLibrary,
+        capabilities: &Capabilities,
+        images: &mut DecodedImages,
+        label: &str,
+        kind: TextureKind,
+    ) -> Result<OurTexture> {
+        match self {
+            Self::Image(path) => {
+                let image = images
+                    .take(path)
+                    .with_context(|| format!("failed to load {}", path.display()))?;
+                OurTexture::from_image(device, queue, &image, Some(label), kind)
+            }
+            Self::Procedural(procedural) => {
+                procedural.generate(device, queue, library, capabilities, label, kind)
+            }
+        }
+    }
+}
+
+/// A procedural texture if `s` names one, otherwise the path to an image
+impl FromStr for TextureSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let kind = s.split_once(':').map_or(s, |(kind, _)| kind);
+        Ok(match kind {
+            "checker" | "noise" | "gradient" => Self::Procedural(s.parse()?),
+            _ => Self::Image(s.into()),
+        })
+    }
+}
@@ -0,0 +1,107 @@
+// Generates a texture on the GPU, one invocation per texel.
+// Everything it draws tiles, so it can be repeated across a surface without seams
+
+struct Procedural {
+    // 0 for a checkerboard, 1 for noise and 2 for a gradient
+    kind: u32,
+    // how many squares or noise cells there are along each side
+    cells: u32,
+    // how many layers of noise to add together, each with twice as many cells as the last
+    octaves: u32,
+    seed: u32,
+    // the colours at 0 and 1, i.e. the checkerboard's two colours, the noise's low and high and the gradient's top and bottom
+    color_a: vec3<f32>,
+    // texels along each side
+    size: u32,
+    color_b: vec3<f32>,
+    // whether to encode the colours as sRGB, for colour textures
+    srgb: u32,
+}
+@group(0) @binding(0)
+var<uniform> procedural: Procedural;
+// Row by row, packed as RGBA8, which is copied into the texture afterwards.
+// Written to rather than a storage texture as those aren't supported on every backend
+@group(0) @binding(1)
+var<storage, read_write> texels: array<u32>;
+
+// The inverse of what the GPU does when sampling an sRGB texture
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+// "lowbias32" from https://nullprogram.com/blog/2018/07/31/
+fn hash(x: u32) -> u32 {
+    var h = x;
+    h = h ^ (h >> 16u);
+    h = h * 0x7feb352du;
+    h = h ^ (h >> 15u);
+    h = h * 0x846ca68bu;
+    h = h ^ (h >> 16u);
+    return h;
+}
+
+// A random unit vector for the corner `corner` of the noise's grid,
+// which wraps around every `period` cells so that the noise tiles
+fn corner_gradient(corner: vec2<u32>, period: u32) -> vec2<f32> {
+    let wrapped = corner % period;
+    let angle = f32(hash(wrapped.x ^ hash(wrapped.y ^ hash(procedural.seed)))) / 4294967295.0 * 6.2831853;
+    return vec2<f32>(cos(angle), sin(angle));
+}
+
+// Perlin's gradient noise, from about -0.7 to 0.7
+fn perlin(position: vec2<f32>, period: u32) -> f32 {
+    let cell = vec2<u32>(floor(position));
+    let offset = fract(position);
+    // quintic, so that there are no creases where cells meet
+    let fade = offset * offset * offset * (offset * (offset * 6.0 - 15.0) + 10.0);
+    let a = dot(corner_gradient(cell, period), offset);
+    let b = dot(corner_gradient(cell + vec2<u32>(1u, 0u), period), offset - vec2<f32>(1.0, 0.0));
+    let c = dot(corner_gradient(cell + vec2<u32>(0u, 1u), period), offset - vec2<f32>(0.0, 1.0));
+    let d = dot(corner_gradient(cell + vec2<u32>(1u, 1u), period), offset - vec2<f32>(1.0, 1.0));
+    return mix(mix(a, b, fade.x), mix(c, d, fade.x), fade.y);
+}
+
+// Octaves of noise, each half as strong as the last, from 0 to 1
+fn fractal_noise(uv: vec2<f32>) -> f32 {
+    var period = procedural.cells;
+    var amplitude = 1.0;
+    var sum = 0.0;
+    var total = 0.0;
+    for (var octave = 0u; octave < procedural.octaves; octave = octave + 1u) {
+        sum = sum + perlin(uv * f32(period), period) * amplitude;
+        total = total + amplitude;
+        period = period * 2u;
+        amplitude = amplitude * 0.5;
+    }
+    return clamp(0.5 + 0.7 * sum / total, 0.0, 1.0);
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = procedural.size;
+    // the last workgroups hang off the edge unless the size is a multiple of 8
+    if id.x >= size || id.y >= size {
+        return;
+    }
+    let uv = (vec2<f32>(id.xy) + 0.5) / f32(size);
+    var value = 0.0;
+    switch procedural.kind {
+        case 0u: {
+            let square = vec2<u32>(uv * f32(procedural.cells));
+            value = f32((square.x + square.y) % 2u);
+        }
+        case 1u: {
+            value = fractal_noise(uv);
+        }
+        default: {
+            value = uv.y;
+        }
+    }
+    var color = mix(procedural.color_a, procedural.color_b, value);
+    if procedural.srgb != 0u {
+        color = linear_to_srgb(color);
+    }
+    texels[id.y * size + id.x] = pack4x8unorm(vec4<f32>(color, 1.0));
+}
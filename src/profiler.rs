@@ -0,0 +1,231 @@
+use std::sync::Arc;
+
+use puffin::{GlobalFrameView, MergeScope, ScopeCollection, UnpackedFrameData};
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+use crate::{fragment_stats::DrawnObject, gpu_info::GpuInfo, text::TextRenderer};
+
+/// An overlay breaking down where the CPU's time goes each frame, built from puffin's profile scopes
+/// and toggled with P. Scopes are only recorded while it's open, unless something else turned them on.
+/// It also shows how many fragments each object shaded, when the device can count them
+#[derive(Default)]
+pub struct ProfilerOverlay {
+    visible: bool,
+    /// A summary of the GPU, as timings don't mean much without it
+    gpu: Option<String>,
+    /// The fragments each object shaded in a recent frame, and how many pixels the scene has, see `set_fragments`
+    fragments: Vec<(DrawnObject, u64)>,
+    pixels: u64,
+    /// Collects each frame's scopes as `puffin::GlobalProfiler::new_frame` finishes it
+    view: GlobalFrameView,
+}
+
+impl ProfilerOverlay {
+    /// The number of frames the timings are averaged over
+    const FRAMES: usize = 60;
+    /// The fraction of the screen's height covered by the overlay, along the bottom
+    const HEIGHT: f32 = 0.4;
+    /// The space between the edges of the overlay and its text, in lines
+    const MARGIN: f32 = 0.5;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name the GPU at the top of the overlay
+    pub fn set_gpu(&mut self, gpu: &GpuInfo) {
+        self.gpu = Some(gpu.summary());
+    }
+
+    /// Break down the fragments shaded by each object below the GPU, along with how many times over
+    /// each covers the scene's `pixels`, which is where overdraw comes from
+    pub fn set_fragments(&mut self, fragments: &[(DrawnObject, u64)], pixels: u64) {
+        self.fragments.clear();
+        self.fragments.extend_from_slice(fragments);
+        self.fragments
+            .sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        self.pixels = pixels;
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Showing the overlay turns puffin's scopes on, and hiding it turns them back off
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+        puffin::set_scopes_on(visible);
+    }
+
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::P),
+                        ..
+                    },
+                ..
+            } => {
+                self.set_visible(!self.visible);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Add the overlay to `text` if it's visible, for a screen of the given size
+    pub fn draw(&self, text: &mut TextRenderer, width: u32, height: u32) {
+        if !self.visible {
+            return;
+        }
+        let line_height = text.line_height();
+        let margin = (line_height * Self::MARGIN).round();
+        let top = (height as f32 * (1.0 - Self::HEIGHT)).round();
+        text.rect(
+            [0.0, top],
+            [width as f32, height as f32],
+            [0.0, 0.0, 0.0, 0.75],
+        );
+
+        let view = self.view.lock();
+        let frames = view
+            .latest_frames(Self::FRAMES)
+            .filter_map(|frame| frame.unpacked().ok())
+            .collect::<Vec<_>>();
+        let frame_ns = frames
+            .iter()
+            .map(|frame| frame.meta.range_ns.1 - frame.meta.range_ns.0)
+            .sum::<i64>()
+            / frames.len().max(1) as i64;
+        let mut rows = vec![Row {
+            label: format!(
+                "Profiler ({:.2} ms of scopes per frame, over {} frames) - P to close",
+                frame_ns as f64 * 1e-6,
+                frames.len()
+            ),
+            fraction: 0.0,
+        }];
+        if let Some(gpu) = &self.gpu {
+            rows.push(Row {
+                label: format!("GPU: {gpu}"),
+                fraction: 0.0,
+            });
+        }
+        rows.extend(fragment_rows(&self.fragments, self.pixels));
+        rows.extend(scope_rows(view.scope_collection(), &frames, frame_ns));
+        drop(view);
+
+        let columns = ((width as f32 - 2.0 * margin) / text.advance()).max(0.0) as usize;
+        let bar_width = width as f32 - 2.0 * margin;
+        let max_rows = ((height as f32 - top - 2.0 * margin) / line_height) as usize;
+        let mut y = top + margin;
+        for row in rows.iter().take(max_rows) {
+            // each scope's share of the frame, as a bar behind its row
+            if row.fraction > 0.0 {
+                text.rect(
+                    [margin, y],
+                    [margin + bar_width * row.fraction.min(1.0), y + line_height],
+                    [0.2, 0.4, 1.0, 0.4],
+                );
+            }
+            let label = row.label.chars().take(columns).collect::<String>();
+            text.text([margin, y], &label, [1.0; 4]);
+            y += line_height;
+        }
+    }
+}
+
+struct Row {
+    label: String,
+    /// The fraction of the average frame spent in the scope
+    fraction: f32,
+}
+
+/// A row for the fragments each object shaded, if there are any,
+/// with a bar for how much of the scene's pixels they'd cover
+fn fragment_rows(fragments: &[(DrawnObject, u64)], pixels: u64) -> Vec<Row> {
+    if fragments.is_empty() {
+        return Vec::new();
+    }
+    let overdraw = |count: u64| count as f64 / pixels.max(1) as f64;
+    let total = fragments.iter().map(|&(_, count)| count).sum::<u64>();
+    let mut rows = vec![Row {
+        label: format!(
+            "Fragments shaded: {total} ({:.2}x the scene's pixels)",
+            overdraw(total)
+        ),
+        fraction: 0.0,
+    }];
+    rows.extend(fragments.iter().map(|&(object, count)| Row {
+        label: format!(
+            "  {:<38} {count:>10}  {:>5.2}x",
+            object.to_string(),
+            overdraw(count)
+        ),
+        fraction: overdraw(count) as f32,
+    }));
+    rows
+}
+
+/// A row for every scope of every thread, averaged over `frames` and nested under their parents
+fn scope_rows(
+    scopes: &ScopeCollection,
+    frames: &[Arc<UnpackedFrameData>],
+    frame_ns: i64,
+) -> Vec<Row> {
+    let mut rows = Vec::new();
+    let threads = frames
+        .last()
+        .map(|frame| frame.thread_streams.keys().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    for thread in threads {
+        match puffin::merge_scopes_for_thread(scopes, frames, &thread) {
+            Ok(merged) => {
+                rows.push(Row {
+                    label: format!("Thread {}", thread.name),
+                    fraction: 0.0,
+                });
+                for scope in &merged {
+                    add_scope_rows(&mut rows, scopes, scope, frame_ns, 1);
+                }
+            }
+            Err(error) => {
+                tracing::warn!("Failed to read the profile of {}: {error:?}", thread.name)
+            }
+        }
+    }
+    rows
+}
+
+fn add_scope_rows(
+    rows: &mut Vec<Row>,
+    scopes: &ScopeCollection,
+    scope: &MergeScope<'_>,
+    frame_ns: i64,
+    depth: usize,
+) {
+    let name = scopes
+        .fetch_by_id(&scope.id)
+        .map_or("?", |details| details.name().as_ref());
+    let name = if scope.data.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{name} ({})", scope.data)
+    };
+    rows.push(Row {
+        label: format!(
+            "{:indent$}{name:<width$} {:>7.3} ms  {:>7.3} ms max",
+            "",
+            scope.duration_per_frame_ns as f64 * 1e-6,
+            scope.max_duration_ns as f64 * 1e-6,
+            indent = 2 * depth,
+            width = 40usize.saturating_sub(2 * depth),
+        ),
+        fraction: scope.duration_per_frame_ns as f32 / frame_ns.max(1) as f32,
+    });
+    for child in &scope.children {
+        add_scope_rows(rows, scopes, child, frame_ns, depth + 1);
+    }
+}
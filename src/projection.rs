@@ -0,0 +1,172 @@
+//! View and projection matrices for wgpu, whose clip space has depth from 0 at the near plane to 1 at the far plane
+//! rather than OpenGL's -1 to 1, which is what cgmath's `perspective` builds for.
+//! Every matrix is right-handed, looking down -z with +y up
+
+use crate::math::{Deg, Matrix4, Point3, Rad, Vector3};
+
+/// Converts a projection built for OpenGL's -1 to 1 depth into one for wgpu's 0 to 1,
+/// e.g. one from cgmath's `perspective`. The projections here don't need it
+#[rustfmt::skip]
+pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+/// Moves the world so that `eye` is at the origin looking at `target`, with `up` pointing up the screen
+pub fn look_at(eye: Point3<f32>, target: Point3<f32>, up: Vector3<f32>) -> Matrix4<f32> {
+    Matrix4::look_at_rh(eye, target, up)
+}
+
+/// How much to scale x and y by so that `fovy` and `aspect` (width over height) fit the screen
+fn focal_lengths(fovy: Deg<f32>, aspect: f32) -> (f32, f32) {
+    let focal_length = 1.0 / (Rad::from(fovy).0 / 2.0).tan();
+    (focal_length / aspect, focal_length)
+}
+
+/// A perspective projection with a vertical field of view of `fovy` and an aspect ratio of width over height,
+/// which maps `near` to a depth of 0 and `far` to 1. Like `Matrix4::new` the numbers are column by column
+#[rustfmt::skip]
+pub fn perspective(fovy: Deg<f32>, aspect: f32, near: f32, far: f32) -> Matrix4<f32> {
+    let (x, y) = focal_lengths(fovy, aspect);
+    let range = near - far;
+    Matrix4::new(
+        x, 0.0, 0.0, 0.0,
+        0.0, y, 0.0, 0.0,
+        0.0, 0.0, far / range, -1.0,
+        0.0, 0.0, near * far / range, 0.0,
+    )
+}
+
+/// `perspective` with the far plane infinitely far away, so that nothing is ever too far to be drawn
+#[rustfmt::skip]
+pub fn perspective_infinite(fovy: Deg<f32>, aspect: f32, near: f32) -> Matrix4<f32> {
+    let (x, y) = focal_lengths(fovy, aspect);
+    Matrix4::new(
+        x, 0.0, 0.0, 0.0,
+        0.0, y, 0.0, 0.0,
+        0.0, 0.0, -1.0, -1.0,
+        0.0, 0.0, -near, 0.0,
+    )
+}
+
+/// `perspective` with depth going the other way, from 1 at `near` to 0 at `far`.
+/// Floating point depth is far more precise near 0, which this spends on the distance where it's needed most.
+/// It needs a depth buffer cleared to 0 and compared with `CompareFunction::Greater`
+#[rustfmt::skip]
+pub fn perspective_reversed(fovy: Deg<f32>, aspect: f32, near: f32, far: f32) -> Matrix4<f32> {
+    let (x, y) = focal_lengths(fovy, aspect);
+    let range = far - near;
+    Matrix4::new(
+        x, 0.0, 0.0, 0.0,
+        0.0, y, 0.0, 0.0,
+        0.0, 0.0, near / range, -1.0,
+        0.0, 0.0, near * far / range, 0.0,
+    )
+}
+
+/// `perspective_reversed` with the far plane infinitely far away, the most precise of them all
+#[rustfmt::skip]
+pub fn perspective_infinite_reversed(fovy: Deg<f32>, aspect: f32, near: f32) -> Matrix4<f32> {
+    let (x, y) = focal_lengths(fovy, aspect);
+    Matrix4::new(
+        x, 0.0, 0.0, 0.0,
+        0.0, y, 0.0, 0.0,
+        0.0, 0.0, 0.0, -1.0,
+        0.0, 0.0, near, 0.0,
+    )
+}
+
+/// An orthographic projection of the box from `left` to `right`, `bottom` to `top` and `near` to `far`,
+/// which maps `near` to a depth of 0 and `far` to 1
+#[rustfmt::skip]
+pub fn orthographic(
+    left: f32,
+    right: f32,
+    bottom: f32,
+    top: f32,
+    near: f32,
+    far: f32,
+) -> Matrix4<f32> {
+    let (width, height, depth) = (right - left, top - bottom, far - near);
+    Matrix4::new(
+        2.0 / width, 0.0, 0.0, 0.0,
+        0.0, 2.0 / height, 0.0, 0.0,
+        0.0, 0.0, -1.0 / depth, 0.0,
+        -(right + left) / width, -(top + bottom) / height, -near / depth, 1.0,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vector4;
+
+    const EPSILON: f32 = 1e-5;
+    const NEAR: f32 = 0.1;
+    const FAR: f32 = 100.0;
+
+    /// The depth `projection` gives a point `distance` in front of the camera, after dividing by w
+    fn depth(projection: Matrix4<f32>, distance: f32) -> f32 {
+        let clip = projection * Vector4::new(0.0, 0.0, -distance, 1.0);
+        clip.z / clip.w
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < EPSILON,
+            "{actual} isn't {expected}"
+        );
+    }
+
+    #[test]
+    fn perspective_depth() {
+        let projection = perspective(Deg(60.0), 1.5, NEAR, FAR);
+        assert_close(depth(projection, NEAR), 0.0);
+        assert_close(depth(projection, FAR), 1.0);
+    }
+
+    #[test]
+    fn perspective_reversed_depth() {
+        let projection = perspective_reversed(Deg(60.0), 1.5, NEAR, FAR);
+        assert_close(depth(projection, NEAR), 1.0);
+        assert_close(depth(projection, FAR), 0.0);
+    }
+
+    #[test]
+    fn perspective_infinite_depth() {
+        let projection = perspective_infinite(Deg(60.0), 1.5, NEAR);
+        assert_close(depth(projection, NEAR), 0.0);
+        let far = depth(projection, 1e4);
+        assert!(far < 1.0 && far > 1.0 - 1e-4, "{far} isn't nearly 1");
+        assert!(depth(projection, 1e2) < far);
+    }
+
+    #[test]
+    fn perspective_infinite_reversed_depth() {
+        let projection = perspective_infinite_reversed(Deg(60.0), 1.5, NEAR);
+        assert_close(depth(projection, NEAR), 1.0);
+        let far = depth(projection, 1e4);
+        assert!(far > 0.0 && far < 1e-4, "{far} isn't nearly 0");
+        assert!(depth(projection, 1e2) > far);
+    }
+
+    #[test]
+    fn orthographic_box() {
+        let projection = orthographic(-2.0, 4.0, -1.0, 3.0, 0.5, 10.0);
+        let project = |x: f32, y: f32, z: f32| {
+            let clip = projection * Vector4::new(x, y, z, 1.0);
+            [clip.x / clip.w, clip.y / clip.w, clip.z / clip.w]
+        };
+        for (point, expected) in [
+            (project(-2.0, -1.0, -0.5), [-1.0, -1.0, 0.0]),
+            (project(4.0, 3.0, -10.0), [1.0, 1.0, 1.0]),
+            (project(1.0, 1.0, -5.25), [0.0, 0.0, 0.5]),
+        ] {
+            for (actual, expected) in point.into_iter().zip(expected) {
+                assert_close(actual, expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! A Python module for scripting the renderer, built with the `python` feature, e.g. for generating
+//! datasets of rendered images. The shared library has to be renamed to `wgpu_cube.so` (or `.pyd` on Windows)
+//! for Python to import it:
+//!
+//! ```python
+//! import wgpu_cube
+//!
+//! renderer = wgpu_cube.Renderer(320, 240, ["--fog", "exp:0.05"])
+//! renderer.add_cube(position=(0, 3, 0), tint=(1, 0.2, 0.2))
+//! renderer.set_camera(eye=(0, 5, 10), target=(0, 0, 0))
+//! image = renderer.render()  # a (240, 320, 4) array of RGBA bytes
+//! ```
+
+use numpy::{
+    PyArray1, PyArray3, PyArrayMethods, PyReadonlyArray1, PyReadonlyArray2, PyReadonlyArray3,
+    PyUntypedArrayMethods,
+};
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+};
+
+use crate::{
+    config::Config,
+    math::{Point3, Vector3},
+    offscreen::Offscreen,
+    transform::Transform,
+    vertex::{Vertex, INDICES, VERTICES},
+};
+
+fn runtime_error(error: anyhow::Error) -> PyErr {
+    PyRuntimeError::new_err(format!("{error:#}"))
+}
+
+/// The renderer and the scene it draws, rendering offscreen into arrays of pixels.
+/// `args` are the same command line flags the app takes, e.g. `["--terrain", "heightmap.png"]`
+#[pyclass(unsendable)]
+struct Renderer {
+    offscreen: Offscreen,
+}
+
+#[pymethods]
+impl Renderer {
+    #[new]
+    #[pyo3(signature = (width = 640, height = 480, args = Vec::new()))]
+    fn new(width: u32, height: u32, args: Vec<String>) -> PyResult<Self> {
+        let config = Config::parse(args).map_err(runtime_error)?;
+        let offscreen =
+            pollster::block_on(Offscreen::new(width, height, &config)).map_err(runtime_error)?;
+        Ok(Self { offscreen })
+    }
+
+    /// The width and height of the frames rendered, in pixels
+    #[getter]
+    fn size(&self) -> (u32, u32) {
+        let size = self.offscreen.state.size;
+        (size.width, size.height)
+    }
+
+    fn resize(&mut self, width: u32, height: u32) -> PyResult<()> {
+        self.offscreen.resize(width, height).map_err(runtime_error)
+    }
+
+    /// Move the camera to `eye`, looking at `target`, with a vertical field of view of `fovy` degrees
+    #[pyo3(signature = (eye, target, fovy = None))]
+    fn set_camera(&mut self, eye: [f32; 3], target: [f32; 3], fovy: Option<f32>) {
+        let camera = self.offscreen.state.camera();
+        camera.eye = Point3::from(eye);
+        camera.target = Point3::from(target);
+        if let Some(fovy) = fovy {
+            camera.fovy = fovy;
+        }
+    }
+
+    /// Move the light to `position`, with its colour in linear RGB
+    #[pyo3(signature = (position, color = None, intensity = None))]
+    fn set_light(&mut self, position: [f32; 3], color: Option<[f32; 3]>, intensity: Option<f32>) {
+        let light = self.offscreen.state.light();
+        light.position = Point3::from(position);
+        if let Some(color) = color {
+            light.color = color;
+        }
+        if let Some(intensity) = intensity {
+            light.intensity = intensity;
+        }
+    }
+
+    /// Add a mesh to the scene, returning its index. `vertices` is an `(n, 8)` array of each vertex's
+    /// position, texture co-ordinates and normal, and `indices` lists the triangles' corners,
+    /// wound anticlockwise when seen from the front
+    #[pyo3(signature = (vertices, indices, position = [0.0; 3], scale = 1.0, tint = [1.0; 3]))]
+    fn add_mesh(
+        &mut self,
+        vertices: PyReadonlyArray2<f32>,
+        indices: PyReadonlyArray1<u16>,
+        position: [f32; 3],
+        scale: f32,
+        tint: [f32; 3],
+    ) -> PyResult<usize> {
+        if vertices.shape()[1] != 8 {
+            return Err(PyValueError::new_err(format!(
+                "vertices need 8 floats each, not {}",
+                vertices.shape()[1]
+            )));
+        }
+        let vertices = vertices
+            .as_array()
+            .rows()
+            .into_iter()
+            .map(|row| {
+                Vertex::new(
+                    [row[0], row[1], row[2]],
+                    [row[3], row[4]],
+                    [row[5], row[6], row[7]],
+                )
+            })
+            .collect::<Vec<_>>();
+        let indices = indices.as_array().to_vec();
+        self.offscreen
+            .add_mesh(&vertices, &indices, transform(position, scale), tint)
+            .map_err(runtime_error)
+    }
+
+    /// Add one of the scene's cubes, 2 units across before it's scaled, returning its index
+    #[pyo3(signature = (position = [0.0; 3], scale = 1.0, tint = [1.0; 3]))]
+    fn add_cube(&mut self, position: [f32; 3], scale: f32, tint: [f32; 3]) -> PyResult<usize> {
+        self.offscreen
+            .add_mesh(VERTICES, INDICES, transform(position, scale), tint)
+            .map_err(runtime_error)
+    }
+
+    /// Paint every mesh with `rgba`, a `(height, width, 4)` array of sRGB bytes
+    fn set_texture(&mut self, rgba: PyReadonlyArray3<u8>) -> PyResult<()> {
+        let &[height, width, 4] = rgba.shape() else {
+            return Err(PyValueError::new_err(format!(
+                "a texture needs to be (height, width, 4), not {:?}",
+                rgba.shape()
+            )));
+        };
+        if width == 0 || height == 0 {
+            return Err(PyValueError::new_err(format!(
+                "can't make a texture {width}x{height} pixels"
+            )));
+        }
+        let pixels = rgba.as_array().iter().copied().collect();
+        let image = image::RgbaImage::from_raw(width as u32, height as u32, pixels)
+            .expect("the array's shape matches the image's size");
+        self.offscreen
+            .state
+            .set_texture(&image.into())
+            .map_err(runtime_error)
+    }
+
+    /// Move the scene along by a frame and render it, returning a `(height, width, 4)` array of RGBA bytes
+    fn render<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyArray3<u8>>> {
+        let frame = self.offscreen.render();
+        let (width, height) = frame.dimensions();
+        PyArray1::from_vec(py, frame.into_raw()).reshape([height as usize, width as usize, 4])
+    }
+}
+
+fn transform(position: [f32; 3], scale: f32) -> Transform {
+    Transform::from_translation(Vector3::from(position))
+        .with_scale(Vector3::new(scale, scale, scale))
+}
+
+#[pymodule]
+fn wgpu_cube(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Renderer>()
+}
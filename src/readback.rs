@@ -0,0 +1,350 @@
+//! Reading buffers and textures back from the GPU, which takes a buffer the CPU can map, a copy into it,
+//! waiting for the copy to be submitted and mapped, and for textures, stripping the padding wgpu puts
+//! on the end of every row. `Readback` does all of that for screenshots, recordings, tests and
+//! debugging, handing the bytes over through a callback, a future or by blocking.
+//! Multisampled textures can't be copied out of at all, so they're `resolve`d into a single-sampled one first
+
+use std::{
+    future::Future,
+    num::NonZeroU32,
+    pin::Pin,
+    sync::{mpsc, Arc, Mutex},
+    task::{self, Poll, Waker},
+};
+
+use anyhow::*;
+use image::RgbaImage;
+use wgpu::{
+    Buffer, BufferAddress, BufferDescriptor, BufferUsages, CommandEncoder, Device, Extent3d,
+    ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, LoadOp, Maintain, MapMode, Operations,
+    RenderPassColorAttachment, RenderPassDescriptor, Texture, TextureAspect, TextureDescriptor,
+    TextureDimension, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor,
+};
+
+/// A buffer to copy a buffer or texture of a fixed size into and read it back out of,
+/// which can be used again for the next copy once the last one has been read
+pub struct Readback {
+    buffer: Arc<Buffer>,
+    layout: Layout,
+}
+
+/// How what's copied into the buffer is laid out
+#[derive(Clone, Copy, Debug)]
+enum Layout {
+    /// Exactly as it was
+    Buffer,
+    /// Rows of `row_bytes`, each padded out to `padded_row_bytes`
+    Texture {
+        size: Extent3d,
+        row_bytes: u32,
+        padded_row_bytes: u32,
+    },
+}
+
+impl Readback {
+    /// For reading back `size` bytes of a buffer, which has to be a multiple of 4
+    pub fn for_buffer(device: &Device, size: BufferAddress) -> Self {
+        Self {
+            buffer: Arc::new(device.create_buffer(&BufferDescriptor {
+                label: Some("Readback Buffer"),
+                size,
+                usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            })),
+            layout: Layout::Buffer,
+        }
+    }
+
+    /// For reading back `size` texels of a texture in `format`, or of one `aspect` of it for depth and stencil.
+    /// Compressed formats can't be read back
+    pub fn for_texture(
+        device: &Device,
+        format: TextureFormat,
+        aspect: TextureAspect,
+        size: Extent3d,
+    ) -> Result<Self> {
+        let info = format.describe();
+        ensure!(
+            info.block_dimensions == (1, 1),
+            "compressed textures in {format:?} can't be read back"
+        );
+        let texel_bytes = match aspect {
+            TextureAspect::StencilOnly => 1,
+            _ => info.block_size as u32,
+        };
+        let row_bytes = size.width * texel_bytes;
+        let padded_row_bytes = row_bytes.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        Ok(Self {
+            buffer: Arc::new(device.create_buffer(&BufferDescriptor {
+                label: Some("Texture Readback Buffer"),
+                size: padded_row_bytes as BufferAddress
+                    * size.height as BufferAddress
+                    * size.depth_or_array_layers as BufferAddress,
+                usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            })),
+            layout: Layout::Texture {
+                size,
+                row_bytes,
+                padded_row_bytes,
+            },
+        })
+    }
+
+    /// Record copying `source`, from `offset`, into the readback buffer
+    pub fn copy_buffer(
+        &self,
+        encoder: &mut CommandEncoder,
+        source: &Buffer,
+        offset: BufferAddress,
+    ) {
+        encoder.copy_buffer_to_buffer(source, offset, &self.buffer, 0, self.buffer.size());
+    }
+
+    /// Record copying the texels of `source` into the readback buffer,
+    /// as many as it was made for starting at `source`'s origin
+    pub fn copy_texture(&self, encoder: &mut CommandEncoder, source: ImageCopyTexture) {
+        let Layout::Texture {
+            size,
+            padded_row_bytes,
+            ..
+        } = self.layout
+        else {
+            panic!("`Readback::copy_texture` needs a readback made by `for_texture`");
+        };
+        encoder.copy_texture_to_buffer(
+            source,
+            ImageCopyBuffer {
+                buffer: &self.buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_row_bytes),
+                    rows_per_image: NonZeroU32::new(size.height),
+                },
+            },
+            size,
+        );
+    }
+
+    /// Once the copy has been submitted, map the buffer and hand its bytes to `callback`, without any padding.
+    /// Like all of wgpu's mapping this only happens while the device is polled, which rendering a frame does
+    pub fn then(&self, callback: impl FnOnce(Result<Vec<u8>>) + Send + 'static) {
+        let buffer = self.buffer.clone();
+        let layout = self.layout;
+        self.buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                callback(
+                    result
+                        .context("failed to map the readback buffer")
+                        .map(|()| {
+                            let bytes = unpad(&buffer.slice(..).get_mapped_range(), layout);
+                            buffer.unmap();
+                            bytes
+                        }),
+                );
+            });
+    }
+
+    /// `then` as a future, which is ready once the device has been polled after the copy finished
+    pub fn read(&self) -> ReadbackFuture {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let sender = shared.clone();
+        self.then(move |bytes| {
+            let mut shared = sender.lock().unwrap();
+            shared.bytes = Some(bytes);
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        });
+        ReadbackFuture { shared }
+    }
+
+    /// Block until the copy has been read back, once it's been submitted
+    pub fn wait(&self, device: &Device) -> Result<Vec<u8>> {
+        let (sender, receiver) = mpsc::channel();
+        self.then(move |bytes| {
+            let _ = sender.send(bytes);
+        });
+        device.poll(Maintain::Wait);
+        receiver
+            .recv()
+            .context("the readback buffer was never mapped")?
+    }
+}
+
+/// A texture to draw a frame into and read it back from, as surface textures can only be drawn into.
+/// With more than one sample per pixel it's drawn into a multisampled texture, which `copy` resolves first
+pub struct ReadbackTarget {
+    /// The single-sampled texture which is copied from
+    texture: Texture,
+    /// What's drawn into
+    pub view: TextureView,
+    /// The multisampled texture behind `view` and the view of `texture` it's resolved into, if there's more than one sample
+    multisampled: Option<(Texture, TextureView)>,
+    pub format: TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    pub sample_count: u32,
+}
+
+impl ReadbackTarget {
+    pub fn new(
+        device: &Device,
+        label: &str,
+        format: TextureFormat,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> Self {
+        let create = |sample_count, usage| {
+            device.create_texture(&TextureDescriptor {
+                label: Some(label),
+                size: Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count,
+                dimension: TextureDimension::D2,
+                format,
+                usage: TextureUsages::RENDER_ATTACHMENT | usage,
+            })
+        };
+        let texture = create(1, TextureUsages::COPY_SRC);
+        let resolved = texture.create_view(&TextureViewDescriptor::default());
+        let (view, multisampled) = if sample_count > 1 {
+            let multisampled = create(sample_count, TextureUsages::empty());
+            let view = multisampled.create_view(&TextureViewDescriptor::default());
+            (view, Some((multisampled, resolved)))
+        } else {
+            (resolved, None)
+        };
+        Self {
+            texture,
+            view,
+            multisampled,
+            format,
+            width,
+            height,
+            sample_count,
+        }
+    }
+
+    pub fn matches(
+        &self,
+        format: TextureFormat,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> bool {
+        self.format == format
+            && self.width == width
+            && self.height == height
+            && self.sample_count == sample_count
+    }
+
+    pub fn size(&self) -> Extent3d {
+        Extent3d {
+            width: self.width,
+            height: self.height,
+            depth_or_array_layers: 1,
+        }
+    }
+
+    /// A readback for what's drawn into the target
+    pub fn readback(&self, device: &Device) -> Result<Readback> {
+        Readback::for_texture(device, self.format, TextureAspect::All, self.size())
+    }
+
+    /// Record copying what's been drawn into `readback`, resolving it first if it's multisampled
+    pub fn copy(&self, encoder: &mut CommandEncoder, readback: &Readback) {
+        if let Some((_, resolved)) = &self.multisampled {
+            resolve(encoder, &self.view, resolved);
+        }
+        readback.copy_texture(encoder, self.texture.as_image_copy());
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    bytes: Option<Result<Vec<u8>>>,
+    waker: Option<Waker>,
+}
+
+/// The bytes `Readback::read` is reading back
+pub struct ReadbackFuture {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Future for ReadbackFuture {
+    type Output = Result<Vec<u8>>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut task::Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.bytes.take() {
+            Some(bytes) => Poll::Ready(bytes),
+            None => {
+                shared.waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Record resolving `source`, a multisampled colour target, into `target`, a single-sampled one of the same
+/// size and format, which can then be copied into a `Readback`. It's a pass which only loads `source`,
+/// so whatever was drawn into it is kept for anything else which draws into it afterwards
+pub fn resolve(encoder: &mut CommandEncoder, source: &TextureView, target: &TextureView) {
+    encoder.begin_render_pass(&RenderPassDescriptor {
+        label: Some("Resolve"),
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view: source,
+            resolve_target: Some(target),
+            ops: Operations {
+                load: LoadOp::Load,
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: None,
+    });
+}
+
+fn unpad(data: &[u8], layout: Layout) -> Vec<u8> {
+    let Layout::Texture {
+        size,
+        row_bytes,
+        padded_row_bytes,
+    } = layout
+    else {
+        return data.to_vec();
+    };
+    let mut bytes = Vec::with_capacity(
+        row_bytes as usize * size.height as usize * size.depth_or_array_layers as usize,
+    );
+    for row in data.chunks_exact(padded_row_bytes as usize) {
+        bytes.extend_from_slice(&row[..row_bytes as usize]);
+    }
+    bytes
+}
+
+/// An image out of the bytes read back from a texture in one of the 8 bit RGBA or BGRA formats,
+/// with BGRA swapped round to RGBA
+pub fn rgba_image(
+    mut bytes: Vec<u8>,
+    format: TextureFormat,
+    width: u32,
+    height: u32,
+) -> Result<RgbaImage> {
+    match format {
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => {}
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => {
+            for pixel in bytes.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        _ => bail!("textures in {format:?} aren't 8 bit RGBA"),
+    }
+    RgbaImage::from_raw(width, height, bytes).context("the bytes aren't a whole image")
+}
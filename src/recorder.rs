@@ -0,0 +1,502 @@
+#[cfg(feature = "recording")]
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver},
+};
+use std::{path::PathBuf, sync::mpsc::SyncSender, thread::JoinHandle, time::Duration};
+
+#[cfg(feature = "recording")]
+use anyhow::Context;
+use anyhow::{ensure, Result};
+use image::RgbaImage;
+#[cfg(feature = "recording")]
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    imageops::{self, FilterType},
+    Delay, Frame,
+};
+use wgpu::{CommandEncoder, Device, TextureFormat, TextureView};
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+#[cfg(feature = "ui")]
+use crate::text::TextRenderer;
+use crate::{
+    postprocess::PostProcessStack,
+    readback::{rgba_image, Readback, ReadbackTarget},
+};
+
+/// Where recordings are written
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RecordOutput {
+    /// A numbered PNG for every frame, in this directory
+    Png(PathBuf),
+    /// A video encoded by `ffmpeg`, which has to be on the `PATH`
+    Ffmpeg(PathBuf),
+    /// A short, small animated GIF, see `GifSettings`
+    Gif(PathBuf),
+}
+
+impl RecordOutput {
+    /// A path ending in `.gif` is a GIF, one with any other extension, like `demo.mp4`,
+    /// is a video for ffmpeg to encode, and anything else is a directory for a PNG sequence
+    pub fn from_path(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        match path.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("gif") => Self::Gif(path),
+            Some(_) => Self::Ffmpeg(path),
+            None => Self::Png(path),
+        }
+    }
+
+    /// Where the `index`th recording goes, so that later recordings don't overwrite earlier ones
+    #[cfg(feature = "recording")]
+    fn path(&self, index: u32) -> PathBuf {
+        match self {
+            Self::Png(dir) => dir.join(format!("{index:03}")),
+            Self::Ffmpeg(path) | Self::Gif(path) if index == 0 => path.clone(),
+            Self::Ffmpeg(path) | Self::Gif(path) => {
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                let extension = path.extension().unwrap_or_default().to_string_lossy();
+                path.with_file_name(format!("{stem}_{index}.{extension}"))
+            }
+        }
+    }
+}
+
+impl Default for RecordOutput {
+    fn default() -> Self {
+        Self::Png("recordings".into())
+    }
+}
+
+/// How GIFs are recorded, which trades quality for small files that are quick to share
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GifSettings {
+    /// Recording stops by itself after this long
+    pub duration: Duration,
+    /// Frames are scaled down to be at most this wide
+    pub max_width: u32,
+    /// Frames are dropped to get down to this rate, as GIF delays are in hundredths of a second
+    /// and many viewers slow down anything faster than 50 FPS
+    pub frame_rate: u32,
+    /// From 1 to 30, lower values pick better palettes at the cost of encoding more slowly
+    pub quantization_speed: i32,
+}
+
+impl Default for GifSettings {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(5),
+            max_width: 480,
+            frame_rate: 25,
+            quantization_speed: 10,
+        }
+    }
+}
+
+/// Records every frame while it's on, toggled with F9. Frames are copied back from the GPU
+/// and written out on a thread of their own, so recording slows rendering down a fair bit;
+/// the `--deterministic` clock keeps the recording smooth regardless.
+/// The overlays (log console, profiler) aren't recorded, and neither are they in screenshots.
+/// Recording needs the `recording` feature, but screenshots can always be taken
+pub struct Recorder {
+    output: RecordOutput,
+    /// The rate frames are rendered at, and so the rate videos are encoded at
+    pub frame_rate: u32,
+    pub gif: GifSettings,
+    target: Option<CaptureTarget>,
+    recording: Option<Recording>,
+    /// The number of recordings started so far
+    #[cfg(feature = "recording")]
+    recordings: u32,
+    /// Where to save the next frame, for each screenshot asked for since the last one
+    screenshots: Vec<PathBuf>,
+}
+
+impl Recorder {
+    /// The number of frames which can be waiting to be written before rendering waits for the writer
+    #[cfg(feature = "recording")]
+    const QUEUE_LENGTH: usize = 8;
+
+    pub fn new(output: RecordOutput) -> Self {
+        Self {
+            output,
+            frame_rate: 60,
+            gif: GifSettings::default(),
+            target: None,
+            recording: None,
+            #[cfg(feature = "recording")]
+            recordings: 0,
+            screenshots: Vec::new(),
+        }
+    }
+
+    pub fn output(&self) -> &RecordOutput {
+        &self.output
+    }
+
+    /// Where the next recording is written, which doesn't affect the current one
+    pub fn set_output(&mut self, output: RecordOutput) {
+        self.output = output;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Start recording from the next frame, if we aren't already
+    #[cfg(feature = "recording")]
+    pub fn start(&mut self) {
+        if self.recording.is_some() {
+            return;
+        }
+        let path = self.output.path(self.recordings);
+        self.recordings += 1;
+        tracing::info!("Recording to {}", path.display());
+
+        let (sender, frames) = mpsc::sync_channel(Self::QUEUE_LENGTH);
+        let output = self.output.clone();
+        let (frame_rate, gif) = (self.frame_rate, self.gif);
+        let writer = std::thread::Builder::new()
+            .name("recorder".into())
+            .spawn(move || match output {
+                RecordOutput::Png(_) => write_png_sequence(&path, frames),
+                RecordOutput::Ffmpeg(_) => write_video(&path, frame_rate, frames),
+                RecordOutput::Gif(_) => write_gif(&path, frame_rate, &gif, frames),
+            })
+            .expect("failed to spawn the recorder's thread");
+        // GIFs are for short clips, so they stop by themselves
+        let max_frames = matches!(self.output, RecordOutput::Gif(_))
+            .then(|| (gif.duration.as_secs_f32() * frame_rate as f32).round() as u32);
+        self.recording = Some(Recording {
+            sender,
+            writer,
+            frames: 0,
+            max_frames,
+        });
+    }
+
+    #[cfg(not(feature = "recording"))]
+    pub fn start(&mut self) {
+        tracing::warn!("Recording needs the app to be built with the `recording` feature");
+    }
+
+    /// Stop recording, waiting for every frame to be written out
+    pub fn stop(&mut self) {
+        let Some(recording) = self.recording.take() else {
+            return;
+        };
+        drop(recording.sender);
+        match recording.writer.join() {
+            Ok(Ok(())) => tracing::info!("Recorded {} frames", recording.frames),
+            Ok(Err(error)) => tracing::error!("Recording failed: {error:#}"),
+            Err(_) => tracing::error!("The recorder's thread panicked"),
+        }
+    }
+
+    /// Save the next frame as a PNG at `path`, whether or not we're recording
+    pub fn screenshot(&mut self, path: impl Into<PathBuf>) {
+        self.screenshots.push(path.into());
+    }
+
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F9),
+                        ..
+                    },
+                ..
+            } => {
+                if self.is_recording() {
+                    self.stop();
+                } else {
+                    self.start();
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Add a recording indicator to `text`, in the top right corner
+    #[cfg(feature = "ui")]
+    pub fn draw(&self, text: &mut TextRenderer, width: u32) {
+        let Some(recording) = &self.recording else {
+            return;
+        };
+        let label = format!("REC {}", recording.frames);
+        let line_height = text.line_height();
+        let right = width as f32 - line_height;
+        let left = right - line_height - text.advance() * (label.len() + 1) as f32;
+        text.rect(
+            [left, line_height * 0.5],
+            [left + line_height * 0.6, line_height * 1.1],
+            [1.0, 0.1, 0.1, 1.0],
+        );
+        text.text(
+            [left + line_height, line_height * 0.3],
+            &label,
+            [1.0, 0.1, 0.1, 1.0],
+        );
+    }
+
+    /// Copy the result of `post_process` into a buffer which `finish_frame` reads back,
+    /// if we're recording or taking a screenshot
+    pub fn capture(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        post_process: &PostProcessStack,
+        format: TextureFormat,
+        width: u32,
+        height: u32,
+    ) {
+        if self.recording.is_none() && self.screenshots.is_empty() {
+            return;
+        }
+        // the output pass draws a single sample per pixel, whatever the scene has
+        let target = match &mut self.target {
+            Some(target) if target.matches(format, width, height, 1) => target,
+            target => match CaptureTarget::new(device, format, width, height, 1) {
+                Ok(new) => target.insert(new),
+                Err(error) => {
+                    tracing::error!("Can't capture the frame: {error:#}");
+                    self.screenshots.clear();
+                    self.stop();
+                    return;
+                }
+            },
+        };
+        post_process.draw_output(encoder, target.view());
+        target.copy(encoder);
+    }
+
+    /// Read back the frame copied by `capture` and hand it to the writer,
+    /// which has to be called after the frame's commands have been submitted
+    /// Screenshots are saved before this returns, as there's only ever one of them at a time
+    pub fn finish_frame(&mut self, device: &Device) {
+        if self.recording.is_none() && self.screenshots.is_empty() {
+            return;
+        }
+        let Some(target) = &self.target else {
+            return;
+        };
+        puffin::profile_function!();
+        let frame = target.read(device);
+        for path in self.screenshots.drain(..) {
+            match frame.save(&path) {
+                Ok(()) => tracing::info!("Saved a screenshot to {}", path.display()),
+                Err(error) => {
+                    tracing::error!("Can't save a screenshot to {}: {error}", path.display())
+                }
+            }
+        }
+        let Some(recording) = &mut self.recording else {
+            return;
+        };
+        if recording.sender.send(frame).is_err() {
+            // the writer gave up, `stop` reports why
+            self.stop();
+            return;
+        }
+        recording.frames += 1;
+        if recording
+            .max_frames
+            .is_some_and(|max_frames| recording.frames >= max_frames)
+        {
+            self.stop();
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+struct Recording {
+    sender: SyncSender<RgbaImage>,
+    writer: JoinHandle<Result<()>>,
+    frames: u32,
+    /// The number of frames to stop after, if any
+    max_frames: Option<u32>,
+}
+
+/// A copy of the final image which can be read back, as surface textures can only be rendered to.
+/// Whatever's drawn into `view` is resolved first if it has more than one sample per pixel, see `ReadbackTarget`
+pub(crate) struct CaptureTarget {
+    target: ReadbackTarget,
+    readback: Readback,
+}
+
+impl CaptureTarget {
+    pub(crate) fn new(
+        device: &Device,
+        format: TextureFormat,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> Result<Self> {
+        ensure!(
+            matches!(
+                format,
+                TextureFormat::Rgba8Unorm
+                    | TextureFormat::Rgba8UnormSrgb
+                    | TextureFormat::Bgra8Unorm
+                    | TextureFormat::Bgra8UnormSrgb
+            ),
+            "frames in {format:?} can't be recorded"
+        );
+        let target = ReadbackTarget::new(
+            device,
+            "Capture Target",
+            format,
+            width,
+            height,
+            sample_count,
+        );
+        let readback = target.readback(device)?;
+        Ok(Self { target, readback })
+    }
+
+    /// What to draw the frame into
+    pub(crate) fn view(&self) -> &TextureView {
+        &self.target.view
+    }
+
+    pub(crate) fn matches(
+        &self,
+        format: TextureFormat,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> bool {
+        self.target.matches(format, width, height, sample_count)
+    }
+
+    pub(crate) fn copy(&self, encoder: &mut CommandEncoder) {
+        self.target.copy(encoder, &self.readback);
+    }
+
+    /// Wait for the copy to finish, then read it back into an image
+    pub(crate) fn read(&self, device: &Device) -> RgbaImage {
+        let bytes = self
+            .readback
+            .wait(device)
+            .expect("failed to read back the capture target");
+        rgba_image(
+            bytes,
+            self.target.format,
+            self.target.width,
+            self.target.height,
+        )
+        .expect("capture targets are 8 bit RGBA or BGRA")
+    }
+}
+
+#[cfg(feature = "recording")]
+fn write_png_sequence(dir: &Path, frames: Receiver<RgbaImage>) -> Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    for (index, frame) in frames.into_iter().enumerate() {
+        let path = dir.join(format!("{index:05}.png"));
+        frame
+            .save(&path)
+            .with_context(|| format!("failed to save {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "recording")]
+fn write_video(path: &Path, frame_rate: u32, frames: Receiver<RgbaImage>) -> Result<()> {
+    // ffmpeg needs to know the size up front, so it's started along with the first frame
+    let mut ffmpeg: Option<(Child, ChildStdin, (u32, u32))> = None;
+    for frame in frames {
+        let (_, stdin, size) = match &mut ffmpeg {
+            Some(ffmpeg) => ffmpeg,
+            None => {
+                let mut child = spawn_ffmpeg(path, frame_rate, frame.width(), frame.height())?;
+                let stdin = child.stdin.take().expect("ffmpeg's stdin is piped");
+                ffmpeg.insert((child, stdin, frame.dimensions()))
+            }
+        };
+        ensure!(
+            *size == frame.dimensions(),
+            "the window was resized while recording, which ffmpeg can't follow"
+        );
+        stdin
+            .write_all(frame.as_raw())
+            .context("ffmpeg stopped reading frames")?;
+    }
+
+    if let Some((mut child, stdin, _)) = ffmpeg {
+        // closing stdin tells ffmpeg that there are no more frames
+        drop(stdin);
+        let status = child.wait().context("failed to wait for ffmpeg")?;
+        ensure!(status.success(), "ffmpeg failed with {status}");
+    }
+    Ok(())
+}
+
+#[cfg(feature = "recording")]
+fn write_gif(
+    path: &Path,
+    frame_rate: u32,
+    settings: &GifSettings,
+    frames: Receiver<RgbaImage>,
+) -> Result<()> {
+    let file =
+        File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    let mut encoder = GifEncoder::new_with_speed(
+        BufWriter::new(file),
+        settings.quantization_speed.clamp(1, 30),
+    );
+    encoder.set_repeat(Repeat::Infinite)?;
+    let gif_rate = settings.frame_rate.clamp(1, frame_rate.max(1));
+    let delay = Delay::from_numer_denom_ms(1000, gif_rate);
+
+    let mut written = 0;
+    for (index, frame) in frames.into_iter().enumerate() {
+        // keep the frames which land closest to the GIF's own frame times
+        let due = (index as u64 * gif_rate as u64 / frame_rate.max(1) as u64) as u32;
+        if due < written {
+            continue;
+        }
+        let frame = if frame.width() > settings.max_width {
+            let height = (frame.height() as u64 * settings.max_width as u64 / frame.width() as u64)
+                .max(1) as u32;
+            imageops::resize(&frame, settings.max_width, height, FilterType::Triangle)
+        } else {
+            frame
+        };
+        // each frame gets a palette of its own, quantized by NeuQuant
+        encoder
+            .encode_frame(Frame::from_parts(frame, 0, 0, delay))
+            .context("failed to encode a frame of the GIF")?;
+        written += 1;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "recording")]
+fn spawn_ffmpeg(path: &Path, frame_rate: u32, width: u32, height: u32) -> Result<Child> {
+    Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error"])
+        .args(["-f", "rawvideo", "-pix_fmt", "rgba"])
+        .args(["-video_size", &format!("{width}x{height}")])
+        .args(["-framerate", &frame_rate.to_string()])
+        .args(["-i", "-"])
+        // the most widely playable pixel format, which needs an even size
+        .args(["-vf", "crop=trunc(iw/2)*2:trunc(ih/2)*2"])
+        .args(["-pix_fmt", "yuv420p"])
+        .arg(path)
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("failed to start ffmpeg, is it installed?")
+}
@@ -0,0 +1,382 @@
+use std::{
+    collections::BTreeMap,
+    num::{NonZeroU32, NonZeroU64},
+};
+
+use anyhow::*;
+use naga::{
+    valid::{Capabilities, ValidationFlags, Validator},
+    AddressSpace, ArraySize, ConstantInner, ImageClass, ImageDimension, Module, ScalarKind,
+    ScalarValue, StorageAccess, StorageFormat, TypeInner,
+};
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, BufferBindingType, Device,
+    SamplerBindingType, ShaderStages, StorageTextureAccess, TextureFormat, TextureSampleType,
+    TextureViewDimension,
+};
+
+use crate::shader::{parse_glsl, preprocess, source_location, ShaderCode, ShaderDefs};
+
+/// A resource binding declared by a shader, found through naga's reflection
+#[derive(Clone, Debug)]
+pub struct ReflectedBinding {
+    /// The name of the global variable in the shader, if it has one
+    pub name: Option<String>,
+    pub entry: BindGroupLayoutEntry,
+}
+
+/// Every resource binding declared by a pipeline's shaders,
+/// used to derive bind group layouts rather than writing them out by hand,
+/// and to check that the resources we bind actually match what the shader expects
+#[derive(Clone, Debug, Default)]
+pub struct ShaderReflection {
+    /// Keyed by `(group, binding)`
+    bindings: BTreeMap<(u32, u32), ReflectedBinding>,
+}
+
+impl ShaderReflection {
+    /// Reflect the bindings of `code` as specialised by `defs`
+    pub fn from_code(code: &ShaderCode, defs: &ShaderDefs) -> Result<Self> {
+        match code {
+            ShaderCode::Wgsl(source) => {
+                let source = preprocess(source, defs)?;
+                let module = naga::front::wgsl::parse_str(&source).map_err(|error| {
+                    let report = error.emit_to_string(&source);
+                    match error.location(&source) {
+                        Some(location) => {
+                            let (file, line) =
+                                source_location(&source, location.line_number as usize);
+                            anyhow!("{report}at {}:{line}", file.unwrap_or("wgsl"))
+                        }
+                        None => anyhow!(report),
+                    }
+                })?;
+                Self::from_module(&module)
+            }
+            ShaderCode::Glsl { vertex, fragment } => {
+                let vertex = parse_glsl(vertex, naga::ShaderStage::Vertex, defs)?;
+                let fragment = parse_glsl(fragment, naga::ShaderStage::Fragment, defs)?;
+                Self::from_module(&vertex)?.merge(Self::from_module(&fragment)?)
+            }
+        }
+    }
+
+    pub fn from_module(module: &Module) -> Result<Self> {
+        // Validation tells us which globals each entry point (and the functions it calls) uses
+        let info = Validator::new(ValidationFlags::all(), Capabilities::all())
+            .validate(module)
+            .context("shader failed validation")?;
+
+        let mut bindings = BTreeMap::new();
+        for (handle, global) in module.global_variables.iter() {
+            let binding = match &global.binding {
+                Some(binding) => binding,
+                None => continue,
+            };
+
+            let mut visibility = ShaderStages::NONE;
+            for (index, entry_point) in module.entry_points.iter().enumerate() {
+                if !info.get_entry_point(index)[handle].is_empty() {
+                    visibility |= match entry_point.stage {
+                        naga::ShaderStage::Vertex => ShaderStages::VERTEX,
+                        naga::ShaderStage::Fragment => ShaderStages::FRAGMENT,
+                        naga::ShaderStage::Compute => ShaderStages::COMPUTE,
+                    };
+                }
+            }
+            // Resources which aren't used by any entry point are compiled out, so we can skip them too
+            if visibility.is_empty() {
+                continue;
+            }
+
+            // Binding arrays take the binding type of what they hold, and a count
+            let (ty, count) = match module.types[global.ty].inner {
+                TypeInner::BindingArray { base, size } => (
+                    &module.types[base],
+                    match size {
+                        ArraySize::Constant(size) => match module.constants[size].inner {
+                            ConstantInner::Scalar {
+                                value: ScalarValue::Uint(size),
+                                ..
+                            } => NonZeroU32::new(size as u32),
+                            ConstantInner::Scalar {
+                                value: ScalarValue::Sint(size),
+                                ..
+                            } => NonZeroU32::new(size as u32),
+                            _ => bail!("binding arrays need a whole number of elements"),
+                        },
+                        ArraySize::Dynamic => bail!("binding arrays need a fixed size"),
+                    },
+                ),
+                _ => (&module.types[global.ty], None),
+            };
+            let min_binding_size = || {
+                ty.inner
+                    .try_size(&module.constants)
+                    .ok()
+                    .and_then(|size| NonZeroU64::new(size as u64))
+            };
+            let ty = match (global.space, &ty.inner) {
+                (AddressSpace::Uniform, _) => BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: min_binding_size(),
+                },
+                (AddressSpace::Storage { access }, _) => BindingType::Buffer {
+                    ty: BufferBindingType::Storage {
+                        read_only: !access.contains(StorageAccess::STORE),
+                    },
+                    has_dynamic_offset: false,
+                    min_binding_size: min_binding_size(),
+                },
+                (
+                    AddressSpace::Handle,
+                    &TypeInner::Image {
+                        dim,
+                        arrayed,
+                        class,
+                    },
+                ) => {
+                    let view_dimension = view_dimension(dim, arrayed);
+                    match class {
+                        ImageClass::Sampled { kind, multi } => BindingType::Texture {
+                            sample_type: match kind {
+                                ScalarKind::Float => TextureSampleType::Float { filterable: true },
+                                ScalarKind::Sint => TextureSampleType::Sint,
+                                ScalarKind::Uint => TextureSampleType::Uint,
+                                ScalarKind::Bool => bail!("boolean textures aren't supported"),
+                            },
+                            view_dimension,
+                            multisampled: multi,
+                        },
+                        ImageClass::Depth { multi } => BindingType::Texture {
+                            sample_type: TextureSampleType::Depth,
+                            view_dimension,
+                            multisampled: multi,
+                        },
+                        ImageClass::Storage { format, access } => BindingType::StorageTexture {
+                            access: match (
+                                access.contains(StorageAccess::LOAD),
+                                access.contains(StorageAccess::STORE),
+                            ) {
+                                (true, true) => StorageTextureAccess::ReadWrite,
+                                (true, false) => StorageTextureAccess::ReadOnly,
+                                _ => StorageTextureAccess::WriteOnly,
+                            },
+                            format: storage_format(format),
+                            view_dimension,
+                        },
+                    }
+                }
+                (AddressSpace::Handle, &TypeInner::Sampler { comparison }) => {
+                    BindingType::Sampler(if comparison {
+                        SamplerBindingType::Comparison
+                    } else {
+                        SamplerBindingType::Filtering
+                    })
+                }
+                (space, inner) => bail!(
+                    "unsupported binding `{}` in {space:?}: {inner:?}",
+                    global.name.as_deref().unwrap_or("<unnamed>")
+                ),
+            };
+
+            bindings.insert(
+                (binding.group, binding.binding),
+                ReflectedBinding {
+                    name: global.name.clone(),
+                    entry: BindGroupLayoutEntry {
+                        binding: binding.binding,
+                        visibility,
+                        ty,
+                        count,
+                    },
+                },
+            );
+        }
+
+        Ok(Self { bindings })
+    }
+
+    /// Combine the bindings of two shader modules, e.g. a GLSL vertex and fragment shader
+    pub fn merge(mut self, other: Self) -> Result<Self> {
+        for (key, binding) in other.bindings {
+            match self.bindings.get_mut(&key) {
+                Some(existing) => {
+                    ensure!(
+                        existing.entry.ty == binding.entry.ty,
+                        "group {} binding {} is declared as both {:?} and {:?}",
+                        key.0,
+                        key.1,
+                        existing.entry.ty,
+                        binding.entry.ty
+                    );
+                    existing.entry.visibility |= binding.entry.visibility;
+                }
+                None => {
+                    self.bindings.insert(key, binding);
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// The number of bind groups the pipeline layout needs, i.e. one more than the highest group used
+    pub fn group_count(&self) -> u32 {
+        self.bindings
+            .keys()
+            .last()
+            .map_or(0, |&(group, _)| group + 1)
+    }
+
+    pub fn bindings(&self, group: u32) -> impl Iterator<Item = &ReflectedBinding> {
+        self.bindings
+            .range((group, 0)..=(group, u32::MAX))
+            .map(|(_, binding)| binding)
+    }
+
+    pub fn layout_entries(&self, group: u32) -> Vec<BindGroupLayoutEntry> {
+        self.bindings(group).map(|binding| binding.entry).collect()
+    }
+
+    pub fn create_bind_group_layout(
+        &self,
+        device: &Device,
+        group: u32,
+        label: Option<&str>,
+    ) -> BindGroupLayout {
+        device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &self.layout_entries(group),
+            label,
+        })
+    }
+
+    /// Check that `entries` provides exactly the resources the shader expects for `group`
+    pub fn validate(&self, group: u32, entries: &[BindGroupEntry]) -> Result<()> {
+        for binding in self.bindings(group) {
+            let name = binding.name.as_deref().unwrap_or("<unnamed>");
+            let index = binding.entry.binding;
+            let entry = entries
+                .iter()
+                .find(|entry| entry.binding == index)
+                .with_context(|| {
+                    format!("missing resource for `{name}` (group {group} binding {index})")
+                })?;
+
+            match (&binding.entry.ty, &entry.resource) {
+                (
+                    BindingType::Buffer {
+                        min_binding_size, ..
+                    },
+                    BindingResource::Buffer(buffer),
+                ) => {
+                    let size = buffer
+                        .size
+                        .map_or(buffer.buffer.size() - buffer.offset, NonZeroU64::get);
+                    let min_size = min_binding_size.map_or(0, NonZeroU64::get);
+                    ensure!(
+                        size >= min_size,
+                        "buffer bound to `{name}` is {size} bytes but the shader expects at least {min_size}"
+                    );
+                }
+                (BindingType::Texture { .. }, BindingResource::TextureViewArray(views)) => {
+                    let count = binding.entry.count.map_or(0, NonZeroU32::get);
+                    ensure!(
+                        views.len() == count as usize,
+                        "{} textures bound to `{name}` but the shader expects {count}",
+                        views.len()
+                    );
+                }
+                (BindingType::Texture { .. }, BindingResource::TextureView(_))
+                | (BindingType::StorageTexture { .. }, BindingResource::TextureView(_))
+                | (BindingType::Sampler(_), BindingResource::Sampler(_)) => (),
+                (ty, _) => bail!("resource bound to `{name}` doesn't match the shader's {ty:?}"),
+            }
+        }
+
+        for entry in entries {
+            ensure!(
+                self.bindings.contains_key(&(group, entry.binding)),
+                "group {group} binding {} isn't used by the shader",
+                entry.binding
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Validate `entries` against the shader, then create a bind group from them
+    pub fn create_bind_group(
+        &self,
+        device: &Device,
+        group: u32,
+        layout: &BindGroupLayout,
+        entries: &[BindGroupEntry],
+        label: Option<&str>,
+    ) -> Result<BindGroup> {
+        self.validate(group, entries)
+            .with_context(|| format!("invalid bind group {}", label.unwrap_or("<unnamed>")))?;
+        Ok(device.create_bind_group(&BindGroupDescriptor {
+            layout,
+            entries,
+            label,
+        }))
+    }
+}
+
+fn view_dimension(dim: ImageDimension, arrayed: bool) -> TextureViewDimension {
+    match (dim, arrayed) {
+        (ImageDimension::D1, _) => TextureViewDimension::D1,
+        (ImageDimension::D2, false) => TextureViewDimension::D2,
+        (ImageDimension::D2, true) => TextureViewDimension::D2Array,
+        (ImageDimension::D3, _) => TextureViewDimension::D3,
+        (ImageDimension::Cube, false) => TextureViewDimension::Cube,
+        (ImageDimension::Cube, true) => TextureViewDimension::CubeArray,
+    }
+}
+
+fn storage_format(format: StorageFormat) -> TextureFormat {
+    use StorageFormat as Sf;
+    use TextureFormat as Tf;
+
+    match format {
+        Sf::R8Unorm => Tf::R8Unorm,
+        Sf::R8Snorm => Tf::R8Snorm,
+        Sf::R8Uint => Tf::R8Uint,
+        Sf::R8Sint => Tf::R8Sint,
+
+        Sf::R16Uint => Tf::R16Uint,
+        Sf::R16Sint => Tf::R16Sint,
+        Sf::R16Float => Tf::R16Float,
+        Sf::Rg8Unorm => Tf::Rg8Unorm,
+        Sf::Rg8Snorm => Tf::Rg8Snorm,
+        Sf::Rg8Uint => Tf::Rg8Uint,
+        Sf::Rg8Sint => Tf::Rg8Sint,
+
+        Sf::R32Uint => Tf::R32Uint,
+        Sf::R32Sint => Tf::R32Sint,
+        Sf::R32Float => Tf::R32Float,
+        Sf::Rg16Uint => Tf::Rg16Uint,
+        Sf::Rg16Sint => Tf::Rg16Sint,
+        Sf::Rg16Float => Tf::Rg16Float,
+        Sf::Rgba8Unorm => Tf::Rgba8Unorm,
+        Sf::Rgba8Snorm => Tf::Rgba8Snorm,
+        Sf::Rgba8Uint => Tf::Rgba8Uint,
+        Sf::Rgba8Sint => Tf::Rgba8Sint,
+
+        Sf::Rgb10a2Unorm => Tf::Rgb10a2Unorm,
+        Sf::Rg11b10Float => Tf::Rg11b10Float,
+
+        Sf::Rg32Uint => Tf::Rg32Uint,
+        Sf::Rg32Sint => Tf::Rg32Sint,
+        Sf::Rg32Float => Tf::Rg32Float,
+        Sf::Rgba16Uint => Tf::Rgba16Uint,
+        Sf::Rgba16Sint => Tf::Rgba16Sint,
+        Sf::Rgba16Float => Tf::Rgba16Float,
+
+        Sf::Rgba32Uint => Tf::Rgba32Uint,
+        Sf::Rgba32Sint => Tf::Rgba32Sint,
+        Sf::Rgba32Float => Tf::Rgba32Float,
+    }
+}
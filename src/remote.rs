@@ -0,0 +1,209 @@
+//! A server taking commands as JSON over TCP, built with the `remote` feature and started with `--remote <address>`,
+//! for driving the app from test scripts or a dashboard. Each line sent is a command, answered by a line
+//! of its own once the command has been handed to the event loop:
+//!
+//! ```sh
+//! $ echo '{"command": "set_camera", "eye": [0, 5, 10], "target": [0, 0, 0]}' | nc localhost 7878
+//! {"ok":true}
+//! ```
+//!
+//! The commands are posted as `UserEvent`s, so anything which fails while they're carried out,
+//! e.g. a screenshot which can't be saved, is logged rather than answered.
+//!
+//! The same address answers `GET /metrics` over HTTP with the frame times in Prometheus' text format,
+//! for graphing them alongside everything else Prometheus scrapes
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+};
+
+use anyhow::{ensure, Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    math::{Point3, Vector3},
+    metrics::MetricsSummary,
+    transform::Transform,
+    user_event::{EventProxy, UserEvent},
+    vertex::Vertex,
+};
+
+/// A command sent to the server, tagged by its `command` field
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Command {
+    /// Move the camera to `eye`, looking at `target`
+    SetCamera { eye: [f32; 3], target: [f32; 3] },
+    /// Add a mesh at `position`, scaled by `scale` and tinted `tint`. Each vertex is its position,
+    /// texture co-ordinates and normal, and the triangles are wound anticlockwise when seen from the front
+    AddMesh {
+        vertices: Vec<[f32; 8]>,
+        indices: Vec<u16>,
+        #[serde(default)]
+        position: [f32; 3],
+        #[serde(default = "one")]
+        scale: f32,
+        #[serde(default = "white")]
+        tint: [f32; 3],
+    },
+    /// Paint the scene with the image at `path`
+    LoadTexture { path: PathBuf },
+    /// Save the next frame as a PNG at `path`
+    Screenshot { path: PathBuf },
+    /// Replace the shader `name` with `source`
+    ReloadShader { name: String, source: String },
+    /// Change the fog, with a mode like `--fog` takes, e.g. `exp:0.05`
+    SetFog { mode: String },
+    /// Show or hide the gizmos, which G toggles
+    ShowGizmos { show: bool },
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+fn white() -> [f32; 3] {
+    [1.0; 3]
+}
+
+impl Command {
+    /// The event which carries the command out
+    pub fn into_event(self) -> Result<UserEvent> {
+        Ok(match self {
+            Self::SetCamera { eye, target } => UserEvent::SetCamera {
+                eye: Point3::from(eye),
+                target: Point3::from(target),
+            },
+            Self::AddMesh {
+                vertices,
+                indices,
+                position,
+                scale,
+                tint,
+            } => {
+                ensure!(scale > 0.0, "a mesh needs a positive scale");
+                let vertices = vertices
+                    .iter()
+                    .map(|v| Vertex::new([v[0], v[1], v[2]], [v[3], v[4]], [v[5], v[6], v[7]]))
+                    .collect();
+                UserEvent::AddMesh {
+                    vertices,
+                    indices,
+                    transform: Transform::from_translation(Vector3::from(position))
+                        .with_scale(Vector3::new(scale, scale, scale)),
+                    tint,
+                }
+            }
+            Self::LoadTexture { path } => UserEvent::LoadTexture(path),
+            Self::Screenshot { path } => UserEvent::Screenshot(path),
+            Self::ReloadShader { name, source } => UserEvent::ReloadShader { name, source },
+            Self::SetFog { mode } => UserEvent::SetFog(mode.parse()?),
+            Self::ShowGizmos { show } => UserEvent::ShowGizmos(show),
+        })
+    }
+}
+
+/// Listen on `address`, posting the commands from every connection through `proxy` until the event loop exits,
+/// and exporting `metrics` to anything scraping them
+pub fn spawn(
+    address: SocketAddr,
+    proxy: EventProxy,
+    metrics: Arc<Mutex<MetricsSummary>>,
+) -> Result<JoinHandle<()>> {
+    let listener =
+        TcpListener::bind(address).with_context(|| format!("failed to listen on {address}"))?;
+    tracing::info!("Taking remote commands on {address}");
+    let handle = std::thread::Builder::new()
+        .name("remote".into())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(error) => {
+                        tracing::warn!("Failed to accept a remote connection: {error}");
+                        continue;
+                    }
+                };
+                let (proxy, metrics) = (proxy.clone(), metrics.clone());
+                let spawned = std::thread::Builder::new()
+                    .name("remote connection".into())
+                    .spawn(move || {
+                        let peer = stream.peer_addr().ok();
+                        if let Err(error) = serve(stream, &proxy, &metrics) {
+                            tracing::warn!("Remote connection from {peer:?} failed: {error:#}");
+                        }
+                    });
+                if let Err(error) = spawned {
+                    tracing::error!("Failed to spawn a thread for a remote connection: {error}");
+                }
+            }
+        })
+        .context("failed to spawn the remote server's thread")?;
+    Ok(handle)
+}
+
+/// Answer the commands sent over `stream` until it's closed, or the event loop exits
+fn serve(stream: TcpStream, proxy: &EventProxy, metrics: &Mutex<MetricsSummary>) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut lines = BufReader::new(stream).lines();
+    while let Some(line) = lines.next() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(request) = line.strip_prefix("GET ") {
+            // the rest of the request is headers, up to a blank line
+            for header in lines.by_ref() {
+                if header?.is_empty() {
+                    break;
+                }
+            }
+            let path = request.split_whitespace().next().unwrap_or_default();
+            return serve_http(&mut writer, path, metrics);
+        }
+        let event = serde_json::from_str::<Command>(&line)
+            .context("invalid command")
+            .and_then(Command::into_event);
+        let reply = match event {
+            Ok(event) => {
+                if proxy.send_event(event).is_err() {
+                    writeln!(
+                        writer,
+                        "{}",
+                        json!({ "ok": false, "error": "the app has exited" })
+                    )?;
+                    return Ok(());
+                }
+                json!({ "ok": true })
+            }
+            Err(error) => json!({ "ok": false, "error": format!("{error:#}") }),
+        };
+        writeln!(writer, "{reply}")?;
+    }
+    Ok(())
+}
+
+/// Answer an HTTP `GET` for `path`, which only has the metrics
+fn serve_http(writer: &mut TcpStream, path: &str, metrics: &Mutex<MetricsSummary>) -> Result<()> {
+    let (status, body) = match path {
+        "/metrics" => (
+            "200 OK",
+            metrics
+                .lock()
+                .expect("the summary is never poisoned")
+                .to_prometheus(),
+        ),
+        _ => ("404 Not Found", String::from("Only /metrics is served\n")),
+    };
+    write!(
+        writer,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    Ok(())
+}
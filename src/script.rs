@@ -0,0 +1,329 @@
+//! Scripting the scene with rhai, built with the `scripting` feature and started with `--script <path>`,
+//! for prototyping without recompiling. The script runs once when it's loaded, then its `update` function,
+//! if it has one, is called every frame with the seconds since the last frame and since the first:
+//!
+//! ```rhai
+//! let cube = spawn_cube(0, 3, 0);
+//! set_tint(cube, 1.0, 0.2, 0.2);
+//!
+//! fn update(dt, time) {
+//!     set_rotation(0, 0, time * 90.0, 0);
+//! }
+//! ```
+//!
+//! `spawn_cube(x, y, z)` returns the cube's number, counting up from 0, which `set_position(cube, x, y, z)`,
+//! `set_rotation(cube, x, y, z)` in degrees, `set_scale(cube, scale)` and `set_tint(cube, r, g, b)` take.
+//! `set_camera(eye_x, eye_y, eye_z, target_x, target_y, target_z)` moves the camera, and `print` logs.
+//!
+//! The script is reloaded whenever the file changes. The cubes the last version spawned are reused by the new one
+//! in the order it spawns them, and any it doesn't spawn are hidden, so that reloading doesn't pile them up
+
+use std::{
+    cell::{Cell, RefCell},
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{anyhow, Context, Result};
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, Scope, AST, INT};
+
+use crate::{
+    instance::Instance,
+    math::{Deg, EuclideanSpace, Point3, Quaternion, Rotation3, Vector3},
+    state::State,
+    transform::Transform,
+    vertex::{INDICES, VERTICES},
+};
+
+/// A change to the scene asked for by the script, carried out once it returns to us
+enum Op {
+    Spawn {
+        cube: usize,
+        position: Vector3<f32>,
+    },
+    SetPosition(usize, Vector3<f32>),
+    SetRotation(usize, Quaternion<f32>),
+    SetScale(usize, f32),
+    SetTint(usize, [f32; 3]),
+    SetCamera {
+        eye: Point3<f32>,
+        target: Point3<f32>,
+    },
+}
+
+/// A script driving the scene, see the module docs
+pub struct Script {
+    path: PathBuf,
+    engine: Engine,
+    /// The script's global variables, which live from one frame to the next
+    scope: Scope<'static>,
+    ast: AST,
+    /// Whether the script has an `update` function, which stops being called if it fails until the script is reloaded
+    has_update: bool,
+    /// Filled in by the functions the script calls
+    ops: Rc<RefCell<Vec<Op>>>,
+    /// The number of cubes the script has spawned since it was loaded
+    spawned: Rc<Cell<usize>>,
+    /// The instance of each cube spawned, by any version of the script
+    cubes: Vec<usize>,
+    /// When the file was last changed, as of the last time it was loaded
+    modified: Option<SystemTime>,
+    last_checked: Instant,
+}
+
+impl Script {
+    /// How often the file is checked for changes
+    const RELOAD_INTERVAL: Duration = Duration::from_millis(250);
+
+    /// Load and run the script at `path`. Anything it does to the scene happens on the first `update`
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let ops = Rc::new(RefCell::new(Vec::new()));
+        let spawned = Rc::new(Cell::new(0));
+        let mut script = Self {
+            path: path.into(),
+            engine: engine(&ops, &spawned),
+            scope: Scope::new(),
+            ast: AST::empty(),
+            has_update: false,
+            ops,
+            spawned,
+            cubes: Vec::new(),
+            modified: None,
+            last_checked: Instant::now(),
+        };
+        script.reload()?;
+        Ok(script)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Compile and run the script again from the start, keeping the old version if the new one fails
+    pub fn reload(&mut self) -> Result<()> {
+        self.modified = modified(&self.path);
+        let source = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("failed to read {}", self.path.display()))?;
+        let ast = self
+            .engine
+            .compile(source)
+            .map_err(|error| anyhow!("failed to compile {}: {error}", self.path.display()))?;
+        let spawned = self.spawned.replace(0);
+        let mut scope = Scope::new();
+        if let Err(error) = self.engine.run_ast_with_scope(&mut scope, &ast) {
+            // what the new version asked for before failing is dropped along with it
+            self.ops.borrow_mut().clear();
+            self.spawned.set(spawned);
+            return Err(anyhow!("failed to run {}: {error}", self.path.display()));
+        }
+        self.has_update = ast
+            .iter_functions()
+            .any(|function| function.name == "update" && function.params.len() == 2);
+        self.ast = ast;
+        self.scope = scope;
+        // the cubes spawned again are shown again as the ops are applied
+        for cube in 0..self.cubes.len() {
+            self.ops.borrow_mut().insert(cube, Op::SetScale(cube, 0.0));
+        }
+        tracing::info!("Loaded {}", self.path.display());
+        Ok(())
+    }
+
+    /// Reload the script if it's changed, call its `update` and carry out what it asked for
+    pub fn update(&mut self, state: &mut State, dt: f32, time: f32) {
+        puffin::profile_function!();
+        if self.last_checked.elapsed() >= Self::RELOAD_INTERVAL {
+            self.last_checked = Instant::now();
+            if modified(&self.path) != self.modified {
+                if let Err(error) = self.reload() {
+                    tracing::error!("{error:#}");
+                }
+            }
+        }
+        if self.has_update {
+            let called = self.engine.call_fn_with_options::<Dynamic>(
+                CallFnOptions::new().eval_ast(false),
+                &mut self.scope,
+                &self.ast,
+                "update",
+                (dt as rhai::FLOAT, time as rhai::FLOAT),
+            );
+            if let Err(error) = called {
+                tracing::error!(
+                    "{}'s update failed, it won't be called again until it's changed: {error}",
+                    self.path.display()
+                );
+                self.has_update = false;
+            }
+        }
+        self.apply(state);
+    }
+
+    fn apply(&mut self, state: &mut State) {
+        let ops = std::mem::take(&mut *self.ops.borrow_mut());
+        for op in ops {
+            match op {
+                Op::Spawn { cube, position } if cube == self.cubes.len() => {
+                    let instances = state.add_instances(&[cube_instance(position)]);
+                    state.add_model(VERTICES, INDICES, instances.clone());
+                    self.cubes.push(instances.start as usize);
+                }
+                Op::Spawn {
+                    cube: index,
+                    position,
+                } => *spawned_cube(state, &self.cubes, index) = cube_instance(position),
+                Op::SetPosition(index, position) => {
+                    spawned_cube(state, &self.cubes, index)
+                        .transform
+                        .translation = position
+                }
+                Op::SetRotation(index, rotation) => {
+                    spawned_cube(state, &self.cubes, index).transform.rotation = rotation
+                }
+                Op::SetScale(index, scale) => {
+                    spawned_cube(state, &self.cubes, index).transform.scale =
+                        Vector3::new(scale, scale, scale)
+                }
+                Op::SetTint(index, tint) => spawned_cube(state, &self.cubes, index).tint = tint,
+                Op::SetCamera { eye, target } => {
+                    let camera = state.camera();
+                    camera.eye = eye;
+                    camera.target = target;
+                }
+            }
+        }
+    }
+}
+
+/// The instance of `cube`, which the script can only name once it's spawned it, so it's in `cubes` by now
+fn spawned_cube<'a>(state: &'a mut State, cubes: &[usize], cube: usize) -> &'a mut Instance {
+    &mut state.instances_mut()[cubes[cube]]
+}
+
+fn cube_instance(position: Vector3<f32>) -> Instance {
+    Instance {
+        transform: Transform::from_translation(position),
+        tint: [1.0; 3],
+        roughness: 0.5,
+        metallic: 0.0,
+        emissive: [0.0; 3],
+        displacement: 0.0,
+        transmission: 0.0,
+        texture: 0,
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Either of rhai's number types, so that scripts can write `3` as well as `3.0`
+fn number(value: &Dynamic) -> Result<f32, Box<EvalAltResult>> {
+    value
+        .as_float()
+        .map(|value| value as f32)
+        .or_else(|_| value.as_int().map(|value| value as f32))
+        .map_err(|type_name| format!("expected a number, not {type_name}").into())
+}
+
+/// An engine with the functions for changing the scene, which record what they're asked to do in `ops`
+fn engine(ops: &Rc<RefCell<Vec<Op>>>, spawned: &Rc<Cell<usize>>) -> Engine {
+    let mut engine = Engine::new();
+    engine.on_print(|text| tracing::info!(target: "script", "{text}"));
+    engine.on_debug(|text, _, position| tracing::debug!(target: "script", "{position}: {text}"));
+
+    // Checks the cube has been spawned, returning it as an index
+    let cube = {
+        let spawned = spawned.clone();
+        move |cube: INT| -> Result<usize, Box<EvalAltResult>> {
+            usize::try_from(cube)
+                .ok()
+                .filter(|&cube| cube < spawned.get())
+                .ok_or_else(|| format!("there's no cube {cube}").into())
+        }
+    };
+    let vector = |x: &Dynamic, y: &Dynamic, z: &Dynamic| -> Result<_, Box<EvalAltResult>> {
+        Ok(Vector3::new(number(x)?, number(y)?, number(z)?))
+    };
+
+    {
+        let (ops, spawned) = (ops.clone(), spawned.clone());
+        engine.register_fn(
+            "spawn_cube",
+            move |x: Dynamic, y: Dynamic, z: Dynamic| -> Result<INT, Box<EvalAltResult>> {
+                let position = vector(&x, &y, &z)?;
+                let cube = spawned.get();
+                spawned.set(cube + 1);
+                ops.borrow_mut().push(Op::Spawn { cube, position });
+                Ok(cube as INT)
+            },
+        );
+    }
+    {
+        let (ops, cube) = (ops.clone(), cube.clone());
+        engine.register_fn(
+            "set_position",
+            move |index: INT, x: Dynamic, y: Dynamic, z: Dynamic| {
+                let op = Op::SetPosition(cube(index)?, vector(&x, &y, &z)?);
+                ops.borrow_mut().push(op);
+                Ok::<_, Box<EvalAltResult>>(())
+            },
+        );
+    }
+    {
+        let (ops, cube) = (ops.clone(), cube.clone());
+        engine.register_fn(
+            "set_rotation",
+            move |index: INT, x: Dynamic, y: Dynamic, z: Dynamic| {
+                let angles = vector(&x, &y, &z)?;
+                let rotation = Quaternion::from_angle_y(Deg(angles.y))
+                    * Quaternion::from_angle_x(Deg(angles.x))
+                    * Quaternion::from_angle_z(Deg(angles.z));
+                ops.borrow_mut()
+                    .push(Op::SetRotation(cube(index)?, rotation));
+                Ok::<_, Box<EvalAltResult>>(())
+            },
+        );
+    }
+    {
+        let (ops, cube) = (ops.clone(), cube.clone());
+        engine.register_fn("set_scale", move |index: INT, scale: Dynamic| {
+            let op = Op::SetScale(cube(index)?, number(&scale)?);
+            ops.borrow_mut().push(op);
+            Ok::<_, Box<EvalAltResult>>(())
+        });
+    }
+    {
+        let ops = ops.clone();
+        engine.register_fn(
+            "set_tint",
+            move |index: INT, r: Dynamic, g: Dynamic, b: Dynamic| {
+                let tint = vector(&r, &g, &b)?;
+                ops.borrow_mut()
+                    .push(Op::SetTint(cube(index)?, tint.into()));
+                Ok::<_, Box<EvalAltResult>>(())
+            },
+        );
+    }
+    {
+        let ops = ops.clone();
+        engine.register_fn(
+            "set_camera",
+            move |eye_x: Dynamic,
+                  eye_y: Dynamic,
+                  eye_z: Dynamic,
+                  target_x: Dynamic,
+                  target_y: Dynamic,
+                  target_z: Dynamic| {
+                let eye = Point3::from_vec(vector(&eye_x, &eye_y, &eye_z)?);
+                let target = Point3::from_vec(vector(&target_x, &target_y, &target_z)?);
+                ops.borrow_mut().push(Op::SetCamera { eye, target });
+                Ok::<_, Box<EvalAltResult>>(())
+            },
+        );
+    }
+    engine
+}
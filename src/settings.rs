@@ -0,0 +1,229 @@
+//! Render settings read from a TOML file, `settings.toml` in the working directory unless `--settings` says otherwise,
+//! which `run` applies at startup and again whenever the file changes. Every setting is optional:
+//!
+//! ```toml
+//! clear_color = [0.1, 0.2, 0.3]
+//! present_mode = "mailbox"
+//! camera_speed = 0.2
+//! camera_collision = true
+//! render_scale = 1.5
+//! msaa = 4
+//!
+//! [shadows]
+//! filter = "poisson"
+//! bias = 0.002
+//! normal_offset = 1.0
+//!
+//! # cuts away everything past x = 1, and can be given up to 3 times
+//! [[clip_planes]]
+//! normal = [-1.0, 0.0, 0.0]
+//! distance = -1.0
+//! ```
+
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{ensure, Context, Error, Result};
+use serde::{Deserialize, Deserializer};
+use wgpu::PresentMode;
+
+use crate::{
+    config,
+    geometry::Plane,
+    light::ShadowFilter,
+    math::{InnerSpace, Vector3},
+    state::State,
+};
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// What the scene is cleared to without a sky, in linear RGB. Transparent windows stay see-through
+    pub clear_color: [f64; 3],
+    /// One of the modes `--present-mode` takes, which is used instead if it's given
+    #[serde(deserialize_with = "present_mode")]
+    pub present_mode: Option<PresentMode>,
+    /// How far the camera moves each frame while a movement key is held
+    pub camera_speed: f32,
+    /// Whether the camera is stopped from moving into things, see `CameraCollision`
+    pub camera_collision: bool,
+    /// The scene's resolution as a multiple of the window's, from 0.5 to 2.0, see `State::set_render_scale`.
+    /// It's ignored with `--target-fps`, which adjusts it by itself
+    pub render_scale: f32,
+    /// How many samples each pixel of the scene has for anti-aliasing, 1 or 4, see `State::set_sample_count`.
+    /// It stays as it was, with an error logged, on adapters which can't multisample the scene
+    pub msaa: u32,
+    pub shadows: ShadowSettings,
+    /// What the scene's cut away behind, see `State::set_clip_planes`
+    pub clip_planes: Vec<ClipPlaneSettings>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            clear_color: [0.1, 0.2, 0.3],
+            present_mode: None,
+            camera_speed: 0.2,
+            camera_collision: true,
+            render_scale: 1.0,
+            msaa: 1,
+            shadows: ShadowSettings::default(),
+            clip_planes: Vec::new(),
+        }
+    }
+}
+
+/// How the point light's shadow is sampled, see `PointLight`
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShadowSettings {
+    /// One of `hard`, `pcf3x3`, `pcf5x5` or `poisson`
+    pub filter: ShadowFilter,
+    pub bias: f32,
+    pub normal_offset: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            filter: ShadowFilter::default(),
+            bias: 0.002,
+            normal_offset: 1.0,
+        }
+    }
+}
+
+/// A plane which the scene is cut away behind, keeping what's on the side `normal` points towards
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ClipPlaneSettings {
+    /// Which doesn't have to be normalised, but can't be zero
+    pub normal: [f32; 3],
+    /// How far the plane is from the origin along `normal`
+    #[serde(default)]
+    pub distance: f32,
+}
+
+impl ClipPlaneSettings {
+    pub fn plane(&self) -> Plane {
+        Plane {
+            normal: Vector3::from(self.normal).normalize(),
+            distance: self.distance,
+        }
+    }
+}
+
+impl Settings {
+    /// The file read when `--settings` isn't given, which doesn't have to exist
+    pub const DEFAULT_PATH: &'static str = "settings.toml";
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let toml = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        toml.parse()
+            .with_context(|| format!("invalid settings in {}", path.display()))
+    }
+}
+
+impl FromStr for Settings {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let settings = toml::from_str::<Self>(s)?;
+        ensure!(
+            settings.clear_color.iter().all(|&channel| channel >= 0.0),
+            "clear_color can't be negative"
+        );
+        ensure!(
+            settings.camera_speed > 0.0,
+            "camera_speed has to be positive"
+        );
+        ensure!(
+            State::RENDER_SCALES.contains(&settings.render_scale),
+            "render_scale has to be between 0.5 and 2.0"
+        );
+        ensure!(
+            State::SAMPLE_COUNTS.contains(&settings.msaa),
+            "msaa has to be one of {:?}",
+            State::SAMPLE_COUNTS
+        );
+        ensure!(
+            settings.shadows.bias >= 0.0 && settings.shadows.normal_offset >= 0.0,
+            "shadows.bias and shadows.normal_offset can't be negative"
+        );
+        ensure!(
+            settings.clip_planes.len() <= State::MAX_CLIP_PLANES,
+            "there can only be {} clip_planes",
+            State::MAX_CLIP_PLANES
+        );
+        ensure!(
+            settings
+                .clip_planes
+                .iter()
+                .all(|plane| plane.normal.iter().any(|&axis| axis != 0.0)),
+            "clip_planes need a normal which isn't zero"
+        );
+        Ok(settings)
+    }
+}
+
+fn present_mode<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<PresentMode>, D::Error> {
+    let mode = String::deserialize(deserializer)?;
+    config::parse_present_mode(&mode)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// The settings file, checked every so often for changes
+pub struct SettingsFile {
+    path: PathBuf,
+    /// When the file was last changed, as of the last time it was loaded
+    modified: Option<SystemTime>,
+    last_checked: Instant,
+}
+
+impl SettingsFile {
+    /// How often the file is checked for changes
+    const RELOAD_INTERVAL: Duration = Duration::from_millis(500);
+
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            modified: None,
+            last_checked: Instant::now(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Load the settings, which are the defaults if the file doesn't exist
+    pub fn load(&mut self) -> Result<Settings> {
+        self.modified = modified(&self.path);
+        if self.modified.is_none() && !self.path.exists() {
+            return Ok(Settings::default());
+        }
+        Settings::load(&self.path)
+    }
+
+    /// Load the settings again if the file has changed since they were last loaded
+    pub fn poll(&mut self) -> Option<Result<Settings>> {
+        if self.last_checked.elapsed() < Self::RELOAD_INTERVAL {
+            return None;
+        }
+        self.last_checked = Instant::now();
+        (modified(&self.path) != self.modified).then(|| self.load())
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
@@ -0,0 +1,463 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::*;
+use naga::{
+    front::glsl::{Options, Parser},
+    Module, ShaderStage,
+};
+
+/// A set of compile-time defines used to specialise a single WGSL source into a pipeline permutation,
+/// e.g. `HAS_NORMAL_MAP`, `USE_VERTEX_COLOR` or `NUM_LIGHTS = 4`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ShaderDefs(BTreeMap<String, Option<String>>);
+
+impl ShaderDefs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Define `name` as a flag, which can be tested with `#ifdef`/`#ifndef`
+    pub fn flag(mut self, name: &str) -> Self {
+        self.0.insert(name.to_string(), None);
+        self
+    }
+
+    /// Define `name` with a value, every occurrence of `name` in the source will be replaced by `value`
+    pub fn value(mut self, name: &str, value: impl ToString) -> Self {
+        self.0.insert(name.to_string(), Some(value.to_string()));
+        self
+    }
+
+    pub fn set(&mut self, name: &str, enabled: bool) {
+        if enabled {
+            self.0.insert(name.to_string(), None);
+        } else {
+            self.0.remove(name);
+        }
+    }
+
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).and_then(Option::as_deref)
+    }
+
+    /// Convert to the `#define`s that naga's GLSL preprocessor expects, flags are defined as `1`
+    fn to_glsl_defines(&self) -> naga::FastHashMap<String, String> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone().unwrap_or_else(|| "1".into())))
+            .collect()
+    }
+}
+
+/// The source code for a render pipeline's vertex and fragment stages
+#[derive(Clone, Debug)]
+pub enum ShaderCode {
+    /// A single WGSL module containing both `vs_main` and `fs_main`,
+    /// specialised using our own `#ifdef` preprocessor
+    Wgsl(Cow<'static, str>),
+    /// A pair of GLSL shaders each with their own `main` function,
+    /// translated by naga (whose preprocessor handles `#ifdef`s natively)
+    Glsl {
+        vertex: Cow<'static, str>,
+        fragment: Cow<'static, str>,
+    },
+}
+
+impl ShaderCode {
+    /// Load a GLSL vertex/fragment shader pair (e.g. `foo.vert` and `foo.frag`) from disk
+    pub fn from_glsl_files(vertex: impl AsRef<Path>, fragment: impl AsRef<Path>) -> Result<Self> {
+        let read = |path: &Path| {
+            fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))
+        };
+        Ok(Self::Glsl {
+            vertex: read(vertex.as_ref())?.into(),
+            fragment: read(fragment.as_ref())?.into(),
+        })
+    }
+
+    /// The names of the vertex and fragment entry points
+    pub fn entry_points(&self) -> (&'static str, &'static str) {
+        match self {
+            Self::Wgsl(_) => ("vs_main", "fs_main"),
+            Self::Glsl { .. } => ("main", "main"),
+        }
+    }
+}
+
+impl From<String> for ShaderCode {
+    fn from(source: String) -> Self {
+        Self::Wgsl(source.into())
+    }
+}
+
+impl From<&'static str> for ShaderCode {
+    fn from(source: &'static str) -> Self {
+        Self::Wgsl(source.into())
+    }
+}
+
+/// Translate a GLSL shader for `stage` into a naga module, reporting all errors with their line numbers
+pub fn parse_glsl(source: &str, stage: ShaderStage, defs: &ShaderDefs) -> Result<Module> {
+    let options = Options {
+        stage,
+        defines: defs.to_glsl_defines(),
+    };
+    Parser::default().parse(&options, source).map_err(|errors| {
+        let messages = errors
+            .iter()
+            .map(|error| {
+                let location = error.meta.location(source);
+                format!(
+                    "{}:{}: {}",
+                    location.line_number, location.line_position, error.kind
+                )
+            })
+            .collect::<Vec<_>>();
+        anyhow!(
+            "failed to parse GLSL {stage:?} shader:\n{}",
+            messages.join("\n")
+        )
+    })
+}
+
+/// Run the preprocessor over `source`, resolving `#ifdef`, `#ifndef`, `#else` and `#endif` directives,
+/// and substituting the values of any valued defines
+pub fn preprocess(source: &str, defs: &ShaderDefs) -> Result<String> {
+    let mut output = String::with_capacity(source.len());
+    // Each entry is whether the branch we are currently inside of is active,
+    // and whether we have already seen an `#else` for it
+    let mut stack: Vec<(bool, bool)> = Vec::new();
+
+    for (line_no, line) in source.lines().enumerate() {
+        let line_no = line_no + 1;
+        let trimmed = line.trim_start();
+        let active = stack.iter().all(|&(active, _)| active);
+
+        if let Some(directive) = trimmed.strip_prefix('#') {
+            let mut parts = directive.split_whitespace();
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some("ifdef"), Some(name), None) => stack.push((defs.is_defined(name), false)),
+                (Some("ifndef"), Some(name), None) => stack.push((!defs.is_defined(name), false)),
+                (Some("else"), None, None) => match stack.last_mut() {
+                    Some((active, seen_else @ false)) => {
+                        *active = !*active;
+                        *seen_else = true;
+                    }
+                    Some(_) => bail!("line {line_no}: duplicate `#else`"),
+                    None => bail!("line {line_no}: `#else` without a matching `#ifdef`"),
+                },
+                (Some("endif"), None, None) => {
+                    if stack.pop().is_none() {
+                        bail!("line {line_no}: `#endif` without a matching `#ifdef`");
+                    }
+                }
+                _ => bail!("line {line_no}: unknown preprocessor directive `{trimmed}`"),
+            }
+            // Keep the line numbers the same so that naga's errors still point to the right place
+            output.push('\n');
+            continue;
+        }
+
+        if active {
+            substitute(line, defs, &mut output);
+        }
+        output.push('\n');
+    }
+
+    ensure!(stack.is_empty(), "unterminated `#ifdef` at end of shader");
+    Ok(output)
+}
+
+/// Copy `line` into `output`, replacing whole identifiers which have a defined value
+fn substitute(line: &str, defs: &ShaderDefs, output: &mut String) {
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut rest = line;
+    while let Some(start) = rest.find(is_ident) {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c| !is_ident(c)).unwrap_or(rest.len());
+        let ident = &rest[..end];
+        output.push_str(defs.get(ident).unwrap_or(ident));
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+}
+
+/// A collection of named WGSL sources which can pull each other in with `#include "name.wgsl"`,
+/// so that common code (e.g. the camera uniform) is only written once
+#[derive(Clone, Debug)]
+pub struct ShaderLibrary {
+    sources: HashMap<String, Cow<'static, str>>,
+}
+
+impl Default for ShaderLibrary {
+    fn default() -> Self {
+        let mut library = Self {
+            sources: HashMap::new(),
+        };
+        library.add("camera.wgsl", include_str!("camera.wgsl"));
+        library.add("color.wgsl", include_str!("color.wgsl"));
+        library.add("debug_draw.wgsl", include_str!("debug_draw.wgsl"));
+        library.add("debug_view.wgsl", include_str!("debug_view.wgsl"));
+        library.add("displacement.wgsl", include_str!("displacement.wgsl"));
+        library.add("fog.wgsl", include_str!("fog.wgsl"));
+        library.add("fullscreen.wgsl", include_str!("fullscreen.wgsl"));
+        library.add("glass.wgsl", include_str!("glass.wgsl"));
+        library.add("image_filter.wgsl", include_str!("image_filter.wgsl"));
+        library.add("instance.wgsl", include_str!("instance.wgsl"));
+        library.add("light.wgsl", include_str!("light.wgsl"));
+        library.add("mirror.wgsl", include_str!("mirror.wgsl"));
+        library.add("morph.wgsl", include_str!("morph.wgsl"));
+        library.add("normals.wgsl", include_str!("normals.wgsl"));
+        library.add("point_cloud.wgsl", include_str!("point_cloud.wgsl"));
+        library.add("outline.wgsl", include_str!("outline.wgsl"));
+        library.add("parallax.wgsl", include_str!("parallax.wgsl"));
+        library.add("point_shadow.wgsl", include_str!("point_shadow.wgsl"));
+        library.add("procedural.wgsl", include_str!("procedural.wgsl"));
+        library.add("shader.wgsl", include_str!("shader.wgsl"));
+        library.add("skin.wgsl", include_str!("skin.wgsl"));
+        library.add("sky.wgsl", include_str!("sky.wgsl"));
+        library.add("terrain.wgsl", include_str!("terrain.wgsl"));
+        #[cfg(feature = "ui")]
+        library.add("text.wgsl", include_str!("text.wgsl"));
+        library.add("triplanar.wgsl", include_str!("triplanar.wgsl"));
+        library.add("vegetation.wgsl", include_str!("vegetation.wgsl"));
+        library.add("water.wgsl", include_str!("water.wgsl"));
+        library.add("anaglyph.wgsl", include_str!("postprocess/anaglyph.wgsl"));
+        library.add("blit.wgsl", include_str!("postprocess/blit.wgsl"));
+        library.add("bloom.wgsl", include_str!("postprocess/bloom.wgsl"));
+        library.add(
+            "chromatic_aberration.wgsl",
+            include_str!("postprocess/chromatic_aberration.wgsl"),
+        );
+        library.add(
+            "color_grading.wgsl",
+            include_str!("postprocess/color_grading.wgsl"),
+        );
+        library.add(
+            "depth_of_field.wgsl",
+            include_str!("postprocess/depth_of_field.wgsl"),
+        );
+        library.add(
+            "depth_resolve.wgsl",
+            include_str!("postprocess/depth_resolve.wgsl"),
+        );
+        library.add(
+            "film_grain.wgsl",
+            include_str!("postprocess/film_grain.wgsl"),
+        );
+        library.add(
+            "motion_blur.wgsl",
+            include_str!("postprocess/motion_blur.wgsl"),
+        );
+        library.add(
+            "luminance_histogram.wgsl",
+            include_str!("postprocess/luminance_histogram.wgsl"),
+        );
+        library.add(
+            "tonemapping.wgsl",
+            include_str!("postprocess/tonemapping.wgsl"),
+        );
+        library.add("vignette.wgsl", include_str!("postprocess/vignette.wgsl"));
+        library
+    }
+}
+
+impl ShaderLibrary {
+    /// Create a library containing all of the shaders built in to this crate
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `source` under `name`, replacing any previous source with the same name
+    pub fn add(&mut self, name: &str, source: impl Into<Cow<'static, str>>) {
+        self.sources.insert(name.to_string(), source.into());
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.sources.contains_key(name)
+    }
+
+    /// Produce the source for `name` with all of its `#include`s expanded,
+    /// each file is only included once no matter how many times it is referenced.
+    /// Wherever the source switches from one file to another there's a `// #line` comment saying where it
+    /// carries on from, so that `source_location` can find where a line of the result came from.
+    /// Note: includes are expanded before `#ifdef`s are evaluated, so they are unconditional
+    pub fn resolve(&self, name: &str) -> Result<String> {
+        let mut output = String::new();
+        self.resolve_into(name, &mut Vec::new(), &mut HashSet::new(), &mut output)?;
+        Ok(output)
+    }
+
+    fn resolve_into<'a>(
+        &'a self,
+        name: &'a str,
+        stack: &mut Vec<&'a str>,
+        included: &mut HashSet<&'a str>,
+        output: &mut String,
+    ) -> Result<()> {
+        if stack.contains(&name) {
+            bail!("cyclic include: {} -> {name}", stack.join(" -> "));
+        }
+        if !included.insert(name) {
+            return Ok(());
+        }
+        let source = self
+            .sources
+            .get(name)
+            .with_context(|| format!("unknown shader `{name}`"))?;
+
+        stack.push(name);
+        line_marker(name, 1, output);
+        for (line_no, line) in source.lines().enumerate() {
+            match line.trim().strip_prefix("#include") {
+                Some(path) => {
+                    let path = path
+                        .trim()
+                        .strip_prefix('"')
+                        .and_then(|path| path.strip_suffix('"'))
+                        .with_context(|| {
+                            format!("{name}:{}: expected `#include \"file.wgsl\"`", line_no + 1)
+                        })?;
+                    self.resolve_into(path, stack, included, output)?;
+                    line_marker(name, line_no + 2, output);
+                }
+                None => {
+                    output.push_str(line);
+                    output.push('\n');
+                }
+            }
+        }
+        stack.pop();
+
+        Ok(())
+    }
+}
+
+/// Begins the comments `ShaderLibrary::resolve` marks where each file carries on with
+const LINE_MARKER: &str = "// #line ";
+
+/// Say that the next line of `output` is line `line` of `name`
+fn line_marker(name: &str, line: usize, output: &mut String) {
+    output.push_str(&format!("{LINE_MARKER}{line} \"{name}\"\n"));
+}
+
+/// Which file and line, counting from 1, line `line` of `source` came from, going by the `// #line` comments
+/// `ShaderLibrary::resolve` leaves in it. Without any, it's `source`'s own line, in no file
+pub fn source_location(source: &str, line: usize) -> (Option<&str>, usize) {
+    source
+        .lines()
+        .take(line.saturating_sub(1))
+        .enumerate()
+        .filter_map(|(index, text)| {
+            let (marked, name) = text.strip_prefix(LINE_MARKER)?.split_once(' ')?;
+            let name = name.strip_prefix('"')?.strip_suffix('"')?;
+            // the marker is on line `index + 1`, and says the line after it is `marked`
+            Some((Some(name), marked.parse::<usize>().ok()? + line - index - 2))
+        })
+        .last()
+        .unwrap_or((None, line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(source: &str, defs: &ShaderDefs) -> Vec<String> {
+        preprocess(source, defs)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn else_branch() {
+        let source = "#ifdef A\na\n#else\nnot a\n#endif";
+        assert_eq!(
+            lines(source, &ShaderDefs::new().flag("A")),
+            ["", "a", "", "", ""]
+        );
+        assert_eq!(lines(source, &ShaderDefs::new()), ["", "", "", "not a", ""]);
+        assert_eq!(
+            lines("#ifndef A\nnot a\n#else\na\n#endif", &ShaderDefs::new()),
+            ["", "not a", "", "", ""]
+        );
+    }
+
+    #[test]
+    fn nested_in_inactive_branch() {
+        let source = "#ifdef A\n#ifdef B\nb\n#else\nnot b\n#endif\n#endif\nafter";
+        // B's branches are both inside A's, which is inactive
+        assert_eq!(
+            lines(source, &ShaderDefs::new().flag("B")),
+            ["", "", "", "", "", "", "", "after"]
+        );
+        assert_eq!(
+            lines(source, &ShaderDefs::new()),
+            ["", "", "", "", "", "", "", "after"]
+        );
+        assert_eq!(
+            lines(source, &ShaderDefs::new().flag("A")),
+            ["", "", "", "", "not b", "", "", "after"]
+        );
+    }
+
+    #[test]
+    fn keeps_line_count() {
+        let source = "one\n#ifdef A\ntwo\n#else\nthree\n#endif\nfour\n";
+        for defs in [ShaderDefs::new(), ShaderDefs::new().flag("A")] {
+            let output = preprocess(source, &defs).unwrap();
+            assert_eq!(output.lines().count(), source.lines().count());
+            assert_eq!(output.lines().nth(6), Some("four"));
+        }
+    }
+
+    #[test]
+    fn malformed_directives() {
+        let defs = ShaderDefs::new();
+        let error = |source: &str| preprocess(source, &defs).unwrap_err().to_string();
+        assert_eq!(
+            error("#ifdef A\n#else\n#else\n#endif"),
+            "line 3: duplicate `#else`"
+        );
+        assert_eq!(
+            error("#endif"),
+            "line 1: `#endif` without a matching `#ifdef`"
+        );
+        assert_eq!(
+            error("#else"),
+            "line 1: `#else` without a matching `#ifdef`"
+        );
+        assert_eq!(
+            error("#ifdef A\n#ifdef B\n#endif"),
+            "unterminated `#ifdef` at end of shader"
+        );
+        assert!(error("#ifdef A B\n#endif").contains("unknown preprocessor directive"));
+        assert!(error("#ifndef\n#endif").contains("unknown preprocessor directive"));
+    }
+
+    #[test]
+    fn substitutes_whole_identifiers() {
+        let defs = ShaderDefs::new().value("NUM_LIGHTS", 4);
+        assert_eq!(
+            lines(
+                "var<private> a: array<f32, NUM_LIGHTS>;\nlet b = NUM_LIGHTS_MAX + MY_NUM_LIGHTS;",
+                &defs
+            ),
+            [
+                "var<private> a: array<f32, 4>;",
+                "let b = NUM_LIGHTS_MAX + MY_NUM_LIGHTS;"
+            ]
+        );
+    }
+}
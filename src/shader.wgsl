@@ -1,39 +1,225 @@
 // Vertex shader
 
-struct CameraUniform {
-    view_proj: mat4x4<f32>,
-};
-@group(1) @binding(0)
-var<uniform> camera: CameraUniform;
+#include "camera.wgsl"
+#include "debug_view.wgsl"
+#include "displacement.wgsl"
+#include "fog.wgsl"
+#include "glass.wgsl"
+#include "instance.wgsl"
+#include "light.wgsl"
+#include "morph.wgsl"
+#include "skin.wgsl"
+#include "triplanar.wgsl"
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
 }
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    // `clip_position` gets converted to pixel co-ordinates before the fragment shader sees it,
+    // so pass along copies of where the vertex is this frame and where it was last frame
+    @location(1) current_position: vec4<f32>,
+    @location(2) previous_position: vec4<f32>,
+    @location(3) world_position: vec3<f32>,
+    @location(4) normal: vec3<f32>,
+    @location(5) tint: vec3<f32>,
+    // roughness and metallic
+    @location(6) material: vec2<f32>,
+    @location(7) emissive: vec3<f32>,
+    @location(8) transmission: f32,
+#ifdef BINDLESS
+    @location(9) @interpolate(flat) texture: u32,
+#endif
 }
 
 @vertex
 fn vs_main(
     model: VertexInput,
+    instance: InstanceInput,
+#ifdef SKINNED
+    skin: SkinInput,
+#endif
+#ifdef MORPHED
+    @builtin(vertex_index) vertex_index: u32,
+#endif
 ) -> VertexOutput {
+#ifdef MORPHED
+    // Blended before skinning, as the targets are in the bind pose
+    let morphed = morph_vertex(vertex_index, model.position, model.normal);
+    let undisplaced_position = morphed.position;
+    let local_normal = morphed.normal;
+#else
+    let undisplaced_position = model.position;
+    let local_normal = model.normal;
+#endif
+    // In the mesh's own space, so that it moves with the mesh when it's skinned
+    let local_position = displace(undisplaced_position, local_normal, model.tex_coords, instance.material.z);
+#ifdef SKINNED
+    // Posed before the instance moves it into the world, like any other mesh.
+    // Joints are assumed not to scale unevenly, so the normal can go through the same matrix
+    let skin_matrix = skin_matrix(skin);
+    let position = skin_matrix * vec4<f32>(local_position, 1.0);
+    let normal = (skin_matrix * vec4<f32>(local_normal, 0.0)).xyz;
+#else
+    let position = vec4<f32>(local_position, 1.0);
+    let normal = local_normal;
+#endif
+    let model_matrix = instance_model_matrix(instance);
+    let world_position = model_matrix * position;
+
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
-    out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    out.normal = instance_normal_matrix(instance) * normal;
+    out.tint = instance.tint;
+    out.material = instance.material.xy;
+    out.emissive = instance.emissive;
+    out.transmission = instance.material.w;
+#ifdef BINDLESS
+    out.texture = instance.texture;
+#endif
+    out.clip_position = camera.view_proj * world_position;
+    out.current_position = out.clip_position;
+    // Both the camera and the instance can have moved. Skinned and morphed meshes' last poses aren't kept,
+    // so only how they've moved as a whole counts
+    out.previous_position = camera.prev_view_proj * instance_previous_model_matrix(instance) * position;
+
+    // Glass is left out of the opaque pass and drawn over it afterwards, see `Glass`,
+    // which only plain meshes can be
+    var is_glass = instance.material.w > 0.0;
+#ifdef SKINNED
+    is_glass = false;
+#endif
+#ifdef MORPHED
+    is_glass = false;
+#endif
+#ifdef GLASS
+    let skipped = !is_glass;
+#else
+    let skipped = is_glass;
+#endif
+    if skipped {
+        // outside of the clip volume, so that the triangle is dropped before it's rasterised
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+    }
     return out;
 }
 
 // Fragment shader
 
+#ifdef BINDLESS
+// Every texture the scene can be painted with, MATERIAL_TEXTURES of them, which each instance picks from
+@group(0) @binding(0)
+var t_diffuse: binding_array<texture_2d<f32>, MATERIAL_TEXTURES>;
+#else
 @group(0) @binding(0)
 var t_diffuse: texture_2d<f32>;
+#endif
 @group(0)@binding(1)
 var s_diffuse: sampler;
+// Multiplied with the instance's emissive colour, white to glow all over
+@group(0) @binding(2)
+var t_emissive: texture_2d<f32>;
+// After the material's textures, as it samples with `s_diffuse`
+#include "parallax.wgsl"
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    // how far this fragment has moved on screen since last frame, in texture co-ordinates
+    @location(1) velocity: vec2<f32>,
+}
 
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
-}
\ No newline at end of file
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    var out: FragmentOutput;
+    // Normals get shortened when they're interpolated across a triangle
+    let normal = normalize(in.normal);
+    let view_direction = normalize(camera.view_position.xyz - in.world_position);
+#ifdef BINDLESS
+    // past the end is the scene's own texture, like the textures which aren't there
+    let texture = select(in.texture, 0u, in.texture >= u32(MATERIAL_TEXTURES));
+#endif
+#ifdef TRIPLANAR
+    // There are no texture co-ordinates to shift, so no parallax
+    let position_dx = dpdx(in.world_position);
+    let position_dy = dpdy(in.world_position);
+#ifdef BINDLESS
+    let albedo = triplanar_sample(t_diffuse[texture], s_diffuse, in.world_position, position_dx, position_dy, normal, TRIPLANAR_TILE_SIZE);
+#else
+    let albedo = triplanar_sample(t_diffuse, s_diffuse, in.world_position, position_dx, position_dy, normal, TRIPLANAR_TILE_SIZE);
+#endif
+    let emissive = triplanar_sample(t_emissive, s_diffuse, in.world_position, position_dx, position_dy, normal, TRIPLANAR_TILE_SIZE).rgb;
+#else
+    let uv = parallax_uv(in.tex_coords, in.world_position, normal, view_direction);
+#ifdef BINDLESS
+    let albedo = textureSample(t_diffuse[texture], s_diffuse, uv);
+#else
+    let albedo = textureSample(t_diffuse, s_diffuse, uv);
+#endif
+    let emissive = textureSample(t_emissive, s_diffuse, uv).rgb;
+#endif
+#ifdef DEBUG_MIP_LEVEL
+#ifdef BINDLESS
+    let mip_level = debug_mip_level(t_diffuse[texture], dpdx(in.tex_coords), dpdy(in.tex_coords));
+#else
+    let mip_level = debug_mip_level(t_diffuse, dpdx(in.tex_coords), dpdy(in.tex_coords));
+#endif
+#endif
+    // only after sampling, as texture lookups need every fragment around them to still be running
+    if is_clipped(in.world_position) {
+        discard;
+    }
+    var material: Material;
+    material.albedo = albedo.rgb * in.tint;
+    material.roughness = in.material.x;
+    material.metallic = in.material.y;
+    material.emissive = emissive * in.emissive;
+    let color = lighting(in.world_position, normal, view_direction, material)
+        + environment_reflection(normal, view_direction, material);
+#ifdef GLASS
+    // Clear glass has no colour of its own, it only reflects what's around it
+    // and tints the scene behind, which shows through wherever it doesn't
+    var surface = material;
+    surface.albedo = vec3<f32>(0.0);
+    surface.metallic = 0.0;
+    let reflected = lighting(in.world_position, normal, view_direction, surface)
+        + environment_reflection(normal, view_direction, surface);
+    let behind = refracted_scene(in.clip_position, in.world_position, normal, view_direction)
+        * material.albedo * (1.0 - glass_fresnel(normal, view_direction));
+    // The scene behind was fogged when it was drawn, which covers the glass as well, so only the reflections are
+    let clear = reflected * (1.0 - fog_amount(in.current_position.w)) + behind;
+    out.color = vec4<f32>(mix(apply_fog(color, in.current_position.w), clear, in.transmission), albedo.a);
+#else
+    out.color = vec4<f32>(apply_fog(color, in.current_position.w), albedo.a);
+#endif
+#ifdef UNLIT
+    // lines and points have no surface for the light to fall on
+    out.color = vec4<f32>(apply_fog(material.albedo + material.emissive, in.current_position.w), albedo.a);
+#endif
+#ifdef DEBUG_NORMALS
+    out.color = vec4<f32>(normal * 0.5 + 0.5, 1.0);
+#endif
+#ifdef DEBUG_TEX_COORDS
+    out.color = vec4<f32>(fract(in.tex_coords), 0.0, 1.0);
+#endif
+#ifdef DEBUG_DEPTH
+    out.color = vec4<f32>(vec3<f32>(in.current_position.w / DEBUG_DEPTH_FAR), 1.0);
+#endif
+#ifdef DEBUG_OVERDRAW
+    out.color = vec4<f32>(DEBUG_OVERDRAW_STEP, 1.0);
+#endif
+#ifdef DEBUG_MIP_LEVEL
+    // a whole level at a time, so that where it changes stands out, and red from the fourth on
+    out.color = vec4<f32>(debug_ramp(floor(mip_level) / 4.0), 1.0);
+#endif
+
+    let current = in.current_position.xy / in.current_position.w;
+    let previous = in.previous_position.xy / in.previous_position.w;
+    // Texture co-ordinates are half the size of clip space, and go downwards rather than upwards
+    out.velocity = (current - previous) * vec2<f32>(0.5, -0.5);
+    return out;
+}
@@ -0,0 +1,334 @@
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    AddressMode, BindGroup, BindGroupEntry, BindGroupLayout, BindingResource, BufferSlice,
+    CommandEncoder, CompareFunction, DepthBiasState, DepthStencilState, Device, Extent3d, Face,
+    FilterMode, FragmentState, FrontFace, LoadOp, MultisampleState, Operations,
+    PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPassDepthStencilAttachment,
+    RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerDescriptor,
+    ShaderModuleDescriptor, ShaderSource, StencilState, Texture, TextureDescriptor,
+    TextureDimension, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor,
+    TextureViewDimension, VertexBufferLayout, VertexState,
+};
+
+use crate::{
+    buffer_pool::BufferPool,
+    instance::InstanceRaw,
+    light::PointLight,
+    math::{Deg, Matrix4, Point3, Vector3},
+    mesh::Model,
+    morph::morphed_defs,
+    projection,
+    reflection::ShaderReflection,
+    shader::{preprocess, ShaderDefs, ShaderLibrary},
+    skin::skinned_defs,
+    terrain::Terrain,
+    uniform::UniformBuffer,
+    vertex::{SkinnedVertex, Vertex},
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+struct ShadowFaceUniform {
+    view_proj: [[f32; 4]; 4],
+    light_position: [f32; 3],
+    range: f32,
+}
+
+/// The direction each face of a cubemap looks in, and which way is up, in the order of the cubemap's layers.
+/// These are the usual OpenGL vectors, which come out upside down in wgpu's conventions,
+/// so the projection is flipped vertically to match how the cubemap is sampled
+pub const CUBE_FACES: [(Vector3<f32>, Vector3<f32>); 6] = [
+    (Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, -1.0, 0.0)),
+    (Vector3::new(-1.0, 0.0, 0.0), Vector3::new(0.0, -1.0, 0.0)),
+    (Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, 0.0, 1.0)),
+    (Vector3::new(0.0, -1.0, 0.0), Vector3::new(0.0, 0.0, -1.0)),
+    (Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, -1.0, 0.0)),
+    (Vector3::new(0.0, 0.0, -1.0), Vector3::new(0.0, -1.0, 0.0)),
+];
+
+/// A shadow map for a `PointLight`, which needs to cover every direction so is stored as a depth cubemap.
+/// Each face is rendered in its own pass, as rendering all 6 at once needs multiview,
+/// which hardly any adapters support
+pub struct PointShadowMap {
+    _texture: Texture,
+    /// The whole cubemap, for sampling in the lighting shader
+    cube_view: TextureView,
+    /// One view per face, for rendering into
+    face_views: Vec<TextureView>,
+    /// Compares distances rather than returning them, with linear filtering for slightly softer edges
+    sampler: Sampler,
+    faces: Vec<UniformBuffer<ShadowFaceUniform>>,
+    face_bind_groups: Vec<BindGroup>,
+    pipeline: RenderPipeline,
+    /// For skinned meshes, with their skin in bind group 1
+    skinned_pipeline: RenderPipeline,
+    /// For morphed meshes, with their targets in bind group 1, if the adapter can morph them
+    morphed_pipeline: Option<RenderPipeline>,
+    /// Where the light was as of the last `update` and how far it reaches,
+    /// for leaving out the parts of the terrain it can't light
+    light_bounds: (Point3<f32>, f32),
+}
+
+impl PointShadowMap {
+    pub const FORMAT: TextureFormat = TextureFormat::Depth32Float;
+    /// The size of each face of the cubemap in texels
+    pub const RESOLUTION: u32 = 1024;
+    /// Anything closer to the light than this doesn't cast a shadow
+    const NEAR: f32 = 0.05;
+
+    /// `skin_layout` and `morph_layout` are the layouts of every `Skin`'s and `MorphTargets`' bind group,
+    /// where there's no `morph_layout` if the adapter can't morph meshes
+    #[tracing::instrument(skip_all)]
+    pub fn new(
+        device: &Device,
+        library: &ShaderLibrary,
+        skin_layout: &BindGroupLayout,
+        morph_layout: Option<&BindGroupLayout>,
+    ) -> Result<Self> {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Point Shadow Map"),
+            size: Extent3d {
+                width: Self::RESOLUTION,
+                height: Self::RESOLUTION,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+        let cube_view = texture.create_view(&TextureViewDescriptor {
+            label: Some("Point Shadow Map"),
+            dimension: Some(TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let face_views = (0..6)
+            .map(|face| {
+                texture.create_view(&TextureViewDescriptor {
+                    label: Some("Point Shadow Map Face"),
+                    dimension: Some(TextureViewDimension::D2),
+                    base_array_layer: face,
+                    array_layer_count: std::num::NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Point Shadow Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            // lit if the surface is no further away than the closest thing the light can see
+            compare: Some(CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let name = "point_shadow.wgsl";
+        let code = library.resolve(name)?;
+        let source = preprocess(&code, &ShaderDefs::new())?;
+        let skinned_source = preprocess(&code, &skinned_defs(&ShaderDefs::new(), 1))?;
+        let morphed_source = preprocess(&code, &morphed_defs(&ShaderDefs::new(), 1))?;
+        let reflection = ShaderReflection::from_code(&source.clone().into(), &ShaderDefs::new())
+            .with_context(|| format!("failed to reflect {name}"))?;
+        let layout = reflection.create_bind_group_layout(device, 0, Some(name));
+        let faces = (0..6)
+            .map(|_| {
+                UniformBuffer::new(
+                    device,
+                    ShadowFaceUniform::default(),
+                    Some("Point Shadow Face"),
+                )
+            })
+            .collect::<Vec<_>>();
+        let face_bind_groups = faces
+            .iter()
+            .map(|face| {
+                reflection.create_bind_group(
+                    device,
+                    0,
+                    &layout,
+                    &[face.bind_group_entry(0)],
+                    Some("point_shadow_face_bind_group"),
+                )
+            })
+            .collect::<Result<_>>()?;
+        let pipeline = create_pipeline(device, name, source, &[&layout], Vertex::desc());
+        let skinned_pipeline = create_pipeline(
+            device,
+            name,
+            skinned_source,
+            &[&layout, skin_layout],
+            SkinnedVertex::desc(),
+        );
+        let morphed_pipeline = morph_layout.map(|morph_layout| {
+            create_pipeline(
+                device,
+                name,
+                morphed_source,
+                &[&layout, morph_layout],
+                Vertex::desc(),
+            )
+        });
+
+        Ok(Self {
+            _texture: texture,
+            cube_view,
+            face_views,
+            sampler,
+            faces,
+            face_bind_groups,
+            pipeline,
+            skinned_pipeline,
+            morphed_pipeline,
+            light_bounds: (Point3::new(0.0, 0.0, 0.0), 0.0),
+        })
+    }
+
+    /// The entries for binding the shadow map at `binding` and its sampler at `binding + 1`
+    pub fn bind_group_entries(&self, binding: u32) -> [BindGroupEntry<'_>; 2] {
+        [
+            BindGroupEntry {
+                binding,
+                resource: BindingResource::TextureView(&self.cube_view),
+            },
+            BindGroupEntry {
+                binding: binding + 1,
+                resource: BindingResource::Sampler(&self.sampler),
+            },
+        ]
+    }
+
+    /// Point each face's camera out from `light`
+    pub fn update(&mut self, queue: &Queue, light: &PointLight) {
+        // Flip vertically, see `CUBE_FACES`
+        let flip_y = Matrix4::from_nonuniform_scale(1.0, -1.0, 1.0);
+        let proj = flip_y * projection::perspective(Deg(90.0), 1.0, Self::NEAR, light.range);
+        self.light_bounds = (light.position, light.range);
+        for (face, (direction, up)) in self.faces.iter_mut().zip(CUBE_FACES) {
+            let view = Matrix4::look_to_rh(light.position, direction, up);
+            face.set(&ShadowFaceUniform {
+                view_proj: (proj * view).into(),
+                light_position: light.position.into(),
+                range: light.range,
+            });
+            face.write(queue);
+        }
+    }
+
+    /// Render `models` and the part of `terrain` within the light's reach into every face of the cubemap,
+    /// with their instances from `instance_buffer`
+    pub fn render(
+        &self,
+        encoder: &mut CommandEncoder,
+        models: &[Model],
+        terrain: Option<&Terrain>,
+        instance_buffer: BufferSlice<'_>,
+        vertex_pool: &BufferPool,
+        index_pool: &BufferPool,
+    ) {
+        puffin::profile_function!();
+        for (view, bind_group) in self.face_views.iter().zip(&self.face_bind_groups) {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Point Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view,
+                    // 1.0 is as far away as the light reaches, so anything beyond it is lit
+                    depth_ops: Some(Operations {
+                        load: LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.set_vertex_buffer(1, instance_buffer);
+            for model in models.iter().filter(|model| {
+                !model.mesh.is_skinned() && model.morph.is_none() && model.mesh.is_triangle_list()
+            }) {
+                model.draw(&mut render_pass, vertex_pool, index_pool);
+            }
+            if let Some(terrain) = terrain {
+                let (center, radius) = self.light_bounds;
+                terrain.draw_shadow_casters(
+                    &mut render_pass,
+                    center,
+                    radius,
+                    vertex_pool,
+                    index_pool,
+                );
+            }
+            render_pass.set_pipeline(&self.skinned_pipeline);
+            for model in models {
+                if let Some(skin) = &model.skin {
+                    render_pass.set_bind_group(1, skin.bind_group(), &[]);
+                    model.draw(&mut render_pass, vertex_pool, index_pool);
+                }
+            }
+            if let Some(morphed_pipeline) = &self.morphed_pipeline {
+                render_pass.set_pipeline(morphed_pipeline);
+                for model in models.iter().filter(|model| model.skin.is_none()) {
+                    if let Some(morph) = &model.morph {
+                        render_pass.set_bind_group(1, morph.bind_group(), &[]);
+                        model.draw(&mut render_pass, vertex_pool, index_pool);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn create_pipeline(
+    device: &Device,
+    name: &str,
+    source: String,
+    layouts: &[&BindGroupLayout],
+    vertex: VertexBufferLayout<'_>,
+) -> RenderPipeline {
+    let shader = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(name),
+        source: ShaderSource::Wgsl(source.into()),
+    });
+    let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(name),
+        bind_group_layouts: layouts,
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(name),
+        layout: Some(&layout),
+        vertex: VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[vertex, InstanceRaw::desc()],
+        },
+        fragment: Some(FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            // only the depth is written
+            targets: &[],
+        }),
+        primitive: PrimitiveState {
+            // The flipped projection turns counter-clockwise triangles clockwise
+            front_face: FrontFace::Cw,
+            // Only the back faces cast shadows, which moves the shadow's surface away from the lit side,
+            // and so avoids most "shadow acne"
+            cull_mode: Some(Face::Front),
+            ..Default::default()
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: PointShadowMap::FORMAT,
+            depth_write_enabled: true,
+            depth_compare: CompareFunction::Less,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState::default(),
+        multiview: None,
+    })
+}
@@ -0,0 +1,88 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
+    BufferBindingType, Device, Queue, ShaderStages,
+};
+
+use crate::{
+    math::{Matrix4, SquareMatrix},
+    {shader::ShaderDefs, uniform::UniformBuffer},
+};
+
+/// The most joints a single skin can have, which is the length of the array in `skin.wgsl`
+pub const MAX_JOINTS: usize = 64;
+
+/// The defines which select the skinned permutation of a shader, with the joints in bind group `group`
+pub fn skinned_defs(defs: &ShaderDefs, group: u32) -> ShaderDefs {
+    defs.clone().flag("SKINNED").value("SKIN_GROUP", group)
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct JointsUniform {
+    matrices: [[[f32; 4]; 4]; MAX_JOINTS],
+}
+
+/// The pose of a skinned mesh, as one matrix per joint which every vertex weighted to that joint
+/// is moved by. Uploaded as a uniform rather than a storage buffer, so that it works on downlevel
+/// adapters without storage buffers in vertex shaders
+pub struct Skin {
+    joints: UniformBuffer<JointsUniform>,
+    bind_group: BindGroup,
+}
+
+impl Skin {
+    /// The layout of every skin's bind group, which all skinned pipelines share
+    pub fn bind_group_layout(device: &Device) -> BindGroupLayout {
+        device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("skin_bind_group_layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        })
+    }
+
+    /// A skin in its bind pose, i.e. with every joint matrix the identity
+    pub fn new(device: &Device, layout: &BindGroupLayout) -> Self {
+        let identity: [[f32; 4]; 4] = Matrix4::identity().into();
+        let joints = UniformBuffer::new(
+            device,
+            JointsUniform {
+                matrices: [identity; MAX_JOINTS],
+            },
+            Some("Joints Buffer"),
+        );
+        let bind_group = joints.create_bind_group(device, layout, Some("skin_bind_group"));
+        Self { joints, bind_group }
+    }
+
+    /// Pose the skin, where each matrix is a joint's transform times its inverse bind matrix.
+    /// Joints past the end of `matrices` are left as they were
+    pub fn set_joint_matrices(&mut self, matrices: &[Matrix4<f32>]) {
+        assert!(
+            matrices.len() <= MAX_JOINTS,
+            "a skin can have at most {MAX_JOINTS} joints, not {}",
+            matrices.len()
+        );
+        let joints = self.joints.get_mut();
+        for (joint, matrix) in joints.matrices.iter_mut().zip(matrices) {
+            *joint = (*matrix).into();
+        }
+    }
+
+    /// Upload the pose if it has changed since the last write
+    pub fn write(&mut self, queue: &Queue) {
+        self.joints.write(queue);
+    }
+
+    pub fn bind_group(&self) -> &BindGroup {
+        &self.bind_group
+    }
+}
@@ -0,0 +1,23 @@
+// Linear blend skinning, matching `SkinnedVertex` and `JointsUniform`.
+// Only compiled in with `SKINNED`, which also defines `SKIN_GROUP` as the bind group the joints are in
+
+#ifdef SKINNED
+struct SkinInput {
+    @location(3) joints: vec4<u32>,
+    @location(4) weights: vec4<f32>,
+}
+
+struct Joints {
+    matrices: array<mat4x4<f32>, 64>,
+}
+@group(SKIN_GROUP) @binding(0)
+var<uniform> joints: Joints;
+
+// Moves a vertex from the mesh's bind pose to where its joints have posed it, still in the mesh's space
+fn skin_matrix(skin: SkinInput) -> mat4x4<f32> {
+    return joints.matrices[skin.joints.x] * skin.weights.x
+        + joints.matrices[skin.joints.y] * skin.weights.y
+        + joints.matrices[skin.joints.z] * skin.weights.z
+        + joints.matrices[skin.joints.w] * skin.weights.w;
+}
+#endif
@@ -0,0 +1,315 @@
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    BindGroup, BindGroupLayout, BlendState, ColorTargetState, ColorWrites, CompareFunction,
+    DepthBiasState, DepthStencilState, Device, FragmentState, MultisampleState,
+    PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPass, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, StencilState, TextureFormat,
+    VertexState,
+};
+
+use crate::{
+    environment::EnvironmentMap,
+    light::DirectionalLight,
+    math::{InnerSpace, Vector3},
+    pipeline::ScenePipeline,
+    reflection::ShaderReflection,
+    shader::{preprocess, ShaderDefs, ShaderLibrary},
+    uniform::UniformBuffer,
+};
+
+/// Where the sun is and how bright it is, which both colours the sky and lights the scene
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sun {
+    /// Degrees above the horizon, where below 0 it has set
+    pub elevation: f32,
+    /// Degrees clockwise from -z when looking down, so 90 is +x
+    pub azimuth: f32,
+    /// Linear RGB, at midday
+    pub color: [f32; 3],
+    /// How brightly it lights the scene at midday
+    pub intensity: f32,
+    /// How big the disc looks in the sky, in degrees from its centre to its edge.
+    /// The real sun is about a quarter of a degree, which is only a few pixels across
+    pub angular_radius: f32,
+}
+
+impl Default for Sun {
+    fn default() -> Self {
+        Self {
+            elevation: 30.0,
+            azimuth: 135.0,
+            color: [1.0, 0.95, 0.85],
+            intensity: 2.0,
+            angular_radius: 1.5,
+        }
+    }
+}
+
+impl Sun {
+    /// Pointing from the scene towards the sun
+    pub fn direction(&self) -> Vector3<f32> {
+        let (elevation, azimuth) = (self.elevation.to_radians(), self.azimuth.to_radians());
+        Vector3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        )
+    }
+
+    /// How much of the sun's light gets through the air, which reddens and dims it towards sunset
+    /// and fades it out completely once it's gone below the horizon
+    fn transmittance(&self) -> [f32; 3] {
+        let height = self.direction().y;
+        let sunset = 1.0 - smoothstep(0.0, 0.35, height);
+        let visible = smoothstep(-0.05, 0.05, height);
+        lerp(&[1.0; 3], &[1.0, 0.45, 0.2], sunset).map(|channel| channel * visible)
+    }
+
+    /// The light the sun casts on the scene, which is off once it has set
+    /// apart from the dim ambient light of the night sky
+    pub fn light(&self) -> DirectionalLight {
+        let transmittance = self.transmittance();
+        DirectionalLight {
+            direction: self.direction(),
+            color: [0, 1, 2].map(|channel| self.color[channel] * transmittance[channel]),
+            intensity: self.intensity,
+            ambient: NIGHT_AMBIENT + (DAY_AMBIENT - NIGHT_AMBIENT) * self.daylight(),
+        }
+    }
+
+    /// From 0 at night to 1 during the day, which is still a little above 0 for a while after sunset
+    fn daylight(&self) -> f32 {
+        smoothstep(-0.2, 0.05, self.direction().y)
+    }
+
+    /// The sky straight up and at the horizon
+    fn sky_colors(&self) -> ([f32; 3], [f32; 3]) {
+        let height = self.direction().y;
+        // how low the sun is, from 0 when it's high to 1 when it's on the horizon
+        let sunset = 1.0 - smoothstep(0.0, 0.35, height);
+        let day = self.daylight();
+        let zenith = lerp(
+            &NIGHT_ZENITH,
+            &lerp(&DAY_ZENITH, &SUNSET_ZENITH, sunset),
+            day,
+        );
+        let horizon = lerp(
+            &NIGHT_HORIZON,
+            &lerp(&DAY_HORIZON, &SUNSET_HORIZON, sunset),
+            day,
+        );
+        (zenith, horizon)
+    }
+}
+
+/// Straight up and at the horizon, during the day, at sunset and at night
+const DAY_ZENITH: [f32; 3] = [0.12, 0.3, 0.75];
+const DAY_HORIZON: [f32; 3] = [0.55, 0.7, 0.9];
+const SUNSET_ZENITH: [f32; 3] = [0.1, 0.15, 0.35];
+const SUNSET_HORIZON: [f32; 3] = [0.9, 0.45, 0.2];
+const NIGHT_ZENITH: [f32; 3] = [0.005, 0.008, 0.02];
+const NIGHT_HORIZON: [f32; 3] = [0.015, 0.02, 0.04];
+/// How much light the sky scatters into the shade, during the day and at night
+const DAY_AMBIENT: f32 = 0.05;
+const NIGHT_AMBIENT: f32 = 0.01;
+
+fn lerp(from: &[f32; 3], to: &[f32; 3], t: f32) -> [f32; 3] {
+    [0, 1, 2].map(|channel| from[channel] + (to[channel] - from[channel]) * t)
+}
+
+fn smoothstep(from: f32, to: f32, x: f32) -> f32 {
+    let t = ((x - from) / (to - from)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// The layout `sky.wgsl` expects
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+struct SkyUniform {
+    sun_direction: [f32; 3],
+    sun_cos_radius: f32,
+    zenith: [f32; 3],
+    _padding0: f32,
+    horizon: [f32; 3],
+    _padding1: f32,
+    sun_color: [f32; 3],
+    _padding2: f32,
+}
+
+impl SkyUniform {
+    fn new(sun: &Sun) -> Self {
+        let (zenith, horizon) = sun.sky_colors();
+        let light = sun.light();
+        Self {
+            sun_direction: light.direction.normalize().into(),
+            sun_cos_radius: sun.angular_radius.to_radians().cos(),
+            zenith,
+            horizon,
+            sun_color: light.color.map(|channel| channel * light.intensity),
+            ..Default::default()
+        }
+    }
+}
+
+/// A gradient sky which follows the sun through the day, from blue overhead to a paler horizon,
+/// reddening at sunset and darkening at night.
+/// It's drawn behind everything else in place of clearing to a flat colour
+pub struct Sky {
+    sun: Sun,
+    uniform: UniformBuffer<SkyUniform>,
+    bind_group: BindGroup,
+    pipeline: ScenePipeline,
+    /// Draws into an `EnvironmentMap` rather than the scene, with no motion vectors or depth
+    environment_pipeline: RenderPipeline,
+}
+
+impl Sky {
+    /// `camera_layout` is the scene's, and `formats` and `depth_format` must match the scene pass
+    #[tracing::instrument(skip_all)]
+    pub fn new(
+        device: &Device,
+        library: &ShaderLibrary,
+        sun: Sun,
+        camera_layout: &BindGroupLayout,
+        formats: &[TextureFormat],
+        depth_format: TextureFormat,
+    ) -> Result<Self> {
+        let name = "sky.wgsl";
+        let source = preprocess(&library.resolve(name)?, &ShaderDefs::new())?;
+        let reflection = ShaderReflection::from_code(&source.clone().into(), &ShaderDefs::new())
+            .with_context(|| format!("failed to reflect {name}"))?;
+        let layout = reflection.create_bind_group_layout(device, 0, Some("sky_bind_group_layout"));
+        let uniform = UniformBuffer::new(device, SkyUniform::new(&sun), Some("Sky Buffer"));
+        let bind_group = reflection.create_bind_group(
+            device,
+            0,
+            &layout,
+            &[uniform.bind_group_entry(0)],
+            Some("sky_bind_group"),
+        )?;
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(name),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Sky Pipeline Layout"),
+            bind_group_layouts: &[&layout, camera_layout],
+            push_constant_ranges: &[],
+        });
+        let targets = formats
+            .iter()
+            .map(|&format| {
+                Some(ColorTargetState {
+                    format,
+                    blend: Some(BlendState::REPLACE),
+                    write_mask: ColorWrites::ALL,
+                })
+            })
+            .collect::<Vec<_>>();
+        // made first, as the scene's pipeline keeps the shader and layout for rebuilding itself
+        let environment_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("sky.wgsl environment"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs_environment",
+                targets: &[Some(ColorTargetState {
+                    format: EnvironmentMap::FORMAT,
+                    blend: Some(BlendState::REPLACE),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            multiview: None,
+        });
+        let pipeline = ScenePipeline::new(device, move |device, multisample| {
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(name),
+                layout: Some(&pipeline_layout),
+                vertex: VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &targets,
+                }),
+                primitive: PrimitiveState::default(),
+                // drawn first and left out of the depth buffer, so everything drawn after it is in front
+                depth_stencil: Some(DepthStencilState {
+                    format: depth_format,
+                    depth_write_enabled: false,
+                    depth_compare: CompareFunction::Always,
+                    stencil: StencilState::default(),
+                    bias: DepthBiasState::default(),
+                }),
+                multisample,
+                multiview: None,
+            })
+        });
+
+        Ok(Self {
+            sun,
+            uniform,
+            bind_group,
+            pipeline,
+            environment_pipeline,
+        })
+    }
+
+    pub fn sun(&self) -> &Sun {
+        &self.sun
+    }
+
+    /// Move the sun, which takes effect from the next `update`
+    pub fn sun_mut(&mut self) -> &mut Sun {
+        &mut self.sun
+    }
+
+    /// The light the sun casts on the scene
+    pub fn light(&self) -> DirectionalLight {
+        self.sun.light()
+    }
+
+    /// The colour of the sky at the horizon, which distant fog should fade into
+    pub fn horizon_color(&self) -> [f32; 3] {
+        self.sun.sky_colors().1
+    }
+
+    /// Upload any changes to the sun
+    pub fn update(&mut self, queue: &Queue) {
+        self.uniform.set(&SkyUniform::new(&self.sun));
+        self.uniform.write(queue);
+    }
+
+    /// Draw into a scene pass whose targets have `sample_count` samples per pixel from now on
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        self.pipeline.set_sample_count(device, sample_count);
+    }
+
+    /// Fill the background, before anything else is drawn.
+    /// The camera bind group must already be bound at group 1
+    pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    /// Fill a face of an `EnvironmentMap`, whose camera must already be bound at group 1
+    pub fn draw_environment<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        render_pass.set_pipeline(&self.environment_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
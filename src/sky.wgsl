@@ -0,0 +1,82 @@
+// An analytic sky lit by the sun, drawn behind everything in place of a flat clear colour
+
+#include "camera.wgsl"
+
+struct SkyUniform {
+    // pointing from the scene towards the sun
+    sun_direction: vec3<f32>,
+    // the cosine of the sun's angular radius, so anything closer to `sun_direction` than this is the sun's disc
+    sun_cos_radius: f32,
+    // straight up
+    zenith: vec3<f32>,
+    _padding0: f32,
+    // where the sky meets the ground, which the ground below it fades into
+    horizon: vec3<f32>,
+    _padding1: f32,
+    // linear RGB, already multiplied by the sun's intensity
+    sun_color: vec3<f32>,
+    _padding2: f32,
+}
+@group(0) @binding(0)
+var<uniform> sky: SkyUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// A single triangle covering the whole screen at the far plane
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let ndc = vec2<f32>(f32(index / 2u) * 4.0 - 1.0, f32(index % 2u) * 4.0 - 1.0);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 1.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec2<f32>,
+}
+
+// The sky in `direction`, which points away from the camera
+fn sky_color(direction: vec3<f32>) -> vec3<f32> {
+    // the gradient bunches up towards the horizon, and the ground below it is a darker horizon
+    let height = max(direction.y, 0.0);
+    var color = mix(sky.horizon, sky.zenith, sqrt(height));
+    color = mix(sky.horizon * 0.3, color, smoothstep(-0.1, 0.0, direction.y));
+
+    // light scattered around the sun, which is strongest when the sun is low and its light passes through more air
+    let cos_sun = dot(direction, sky.sun_direction);
+    let low_sun = 1.0 - clamp(sky.sun_direction.y, 0.0, 1.0);
+    color = color + sky.sun_color * pow(max(cos_sun, 0.0), 8.0) * (0.05 + 0.15 * low_sun);
+    // the disc itself, with its edge softened over a little less than its radius
+    let edge = 1.0 - sky.sun_cos_radius;
+    let disc = smoothstep(sky.sun_cos_radius - edge * 0.5, sky.sun_cos_radius + edge * 0.5, cos_sun);
+    color = color + sky.sun_color * disc * 10.0 * f32(direction.y >= 0.0);
+    return color;
+}
+
+// The direction through this pixel, from unprojecting it at the near and far planes
+fn view_direction(ndc: vec2<f32>) -> vec3<f32> {
+    let near = camera.inv_view_proj * vec4<f32>(ndc, 0.0, 1.0);
+    let far = camera.inv_view_proj * vec4<f32>(ndc, 1.0, 1.0);
+    return normalize(far.xyz / far.w - near.xyz / near.w);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    let color = sky_color(view_direction(in.ndc));
+    var out: FragmentOutput;
+    out.color = vec4<f32>(color, 1.0);
+    // the sky is infinitely far away, so only turning the camera would move it and that isn't worth blurring
+    out.velocity = vec2<f32>(0.0);
+    return out;
+}
+
+// For rendering into the faces of an environment map, which only have colour
+@fragment
+fn fs_environment(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(sky_color(view_direction(in.ndc)), 1.0);
+}
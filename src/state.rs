@@ -1,28 +1,119 @@
-use cgmath::Vector3;
+use std::ops::{Range, RangeInclusive};
+
+use anyhow::Context;
+use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
 use wgpu::{
-    include_wgsl,
-    util::{BufferInitDescriptor, DeviceExt},
-    Backends, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
-    BindGroupLayoutEntry, BindingResource, BindingType, BlendState, Buffer, BufferBindingType,
-    BufferUsages, Color, ColorTargetState, ColorWrites, CommandEncoderDescriptor,
-    CompositeAlphaMode, Device, DeviceDescriptor, Face, Features, FragmentState, FrontFace,
-    IndexFormat, Instance, Limits, LoadOp, MultisampleState, Operations, PipelineLayoutDescriptor,
-    PolygonMode, PowerPreference, PresentMode, PrimitiveState, PrimitiveTopology, Queue,
-    RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor,
-    RequestAdapterOptions, SamplerBindingType, ShaderStages, Surface, SurfaceConfiguration,
-    SurfaceError, TextureSampleType, TextureUsages, TextureViewDescriptor, TextureViewDimension,
+    Adapter, AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindingResource, BufferUsages, Color, CommandBuffer, CommandEncoder, CommandEncoderDescriptor,
+    CompositeAlphaMode, Device, DeviceDescriptor, FilterMode, LoadOp, Operations,
+    PipelineLayoutDescriptor, PowerPreference, PresentMode, PrimitiveTopology, Queue, RenderPass,
+    RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor,
+    RequestAdapterOptions, RequestDeviceError, Sampler, SamplerDescriptor, Surface,
+    SurfaceConfiguration, SurfaceError, Texture, TextureFormat, TextureFormatFeatureFlags,
+    TextureUsages, TextureView, TextureViewDescriptor,
+};
+use winit::{
+    dpi::PhysicalSize,
+    event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent},
+    window::Window,
 };
-use winit::{dpi::PhysicalSize, event::WindowEvent, window::Window};
 
+#[cfg(feature = "renderdoc")]
+use crate::gpu_capture::GpuCapture;
+#[cfg(feature = "physics")]
+use crate::physics::Physics;
 use crate::{
+    app::FrameContext,
+    assets,
+    buffer_pool::{Allocation, BufferPool},
     camera::{Camera, CameraController, CameraUniform},
-    texture::OurTexture,
-    vertex::{Vertex, INDICES, VERTICES},
+    camera_collision::CameraCollision,
+    capabilities::Capabilities,
+    clock::{Clock, FrameTime},
+    compute::AsyncCompute,
+    config::Config,
+    debug_draw::DebugDraw,
+    debug_view::DebugView,
+    decode::{config_images, DecodedImages},
+    displacement::{Displacement, DisplacementUniform},
+    dynamic_resolution::DynamicResolution,
+    environment::EnvironmentMap,
+    fog::{Fog, FogUniform},
+    fragment_stats::{DrawnObject, FragmentCounter, FragmentStats},
+    frame_stream::FrameStream,
+    geometry::{Aabb, Plane, Sphere},
+    glass::Glass,
+    gpu_info::GpuInfo,
+    gpu_timer::GpuTimer,
+    image_filter::{ImageFilter, ImageFilters},
+    instance::{Instance, InstanceRaw},
+    jobs::{Job, JobSystem},
+    light::{DirectionalLightUniform, LightUniform, PointLight, ShadowFilter},
+    limits,
+    marquee::{Marquee, ScreenRect},
+    material_textures::MaterialTextures,
+    math::{Deg, Matrix4, Point3, Quaternion, Rotation3, SquareMatrix, Vector3, Vector4},
+    mesh::{Mesh, Model},
+    mirror::Mirror,
+    morph::{morphed_defs, MorphTarget, MorphTargets},
+    normals::{NormalLines, NormalView, NormalViewSettings},
+    outline::{Outline, OutlineSettings},
+    parallax::{Parallax, ParallaxUniform},
+    pipeline::{topology_defs, PipelineCache},
+    point_cloud::{load_points, PointCloud, PointCloudSettings},
+    postprocess::{
+        anaglyph::Anaglyph, bloom::Bloom, chromatic_aberration::ChromaticAberration,
+        color_grading::ColorGrading, depth_of_field::DepthOfField, film_grain::FilmGrain,
+        motion_blur::MotionBlur, tonemapping::Tonemapping, vignette::Vignette, PostProcessStack,
+        SceneTargets,
+    },
+    procedural::TextureSource,
+    recorder::Recorder,
+    reflection::ShaderReflection,
+    settings::{ClipPlaneSettings, Settings},
+    shader::{ShaderCode, ShaderDefs, ShaderLibrary},
+    shadow::PointShadowMap,
+    skin::{skinned_defs, Skin},
+    sky::Sky,
+    stereo::{Eye, Stereo, StereoOutput},
+    subdivision::subdivide,
+    terrain::Terrain,
+    texture::{OurTexture, TextureKind},
+    time_of_day::TimeOfDay,
+    transform::Transform,
+    triplanar::triplanar_defs,
+    uniform::UniformBuffer,
+    vegetation::{Vegetation, Wind},
+    vertex::{
+        crease_edges, floor_grid, SkinnedVertex, Vertex, FLOOR_EXTENT, FLOOR_HEIGHT, FLOOR_INDICES,
+        FLOOR_VERTICES, INDICES, VERTICES,
+    },
+    viewport::{Letterbox, OutputRegion, ScissorRect, Viewport},
+    walk::WalkController,
+    water::Water,
 };
+#[cfg(feature = "ui")]
+use crate::{log_console::LogConsole, profiler::ProfilerOverlay, text::TextRenderer};
+
+/// The bind group skinned meshes have their skin in, after the material, camera and light
+const SKIN_GROUP: u32 = 3;
+/// The bind group morphed meshes have their targets in, the same as `SKIN_GROUP` as a mesh can't be both
+const MORPH_GROUP: u32 = 3;
+/// The bind group glass has the opaque scene in, the same as `SKIN_GROUP` as only plain meshes can be glass
+const GLASS_GROUP: u32 = 3;
+/// The height of the terrain's highest point, level with the floor it replaces so that it's all under the cubes
+const TERRAIN_TOP: f32 = -1.0;
 
 pub struct State {
-    /// A handle to a surface, onto which rendered images can be presented
-    pub surface: Surface,
+    /// Kept for recreating the surface if the platform invalidates it
+    instance: wgpu::Instance,
+    /// A handle to a surface, onto which rendered images can be presented.
+    /// There's none while the app is suspended
+    pub surface: Option<Surface>,
+    /// Whether the surface was lost on the last frame, which reconfiguring it didn't fix
+    surface_lost: bool,
+    /// The GPU the device belongs to, for asking what the surface supports
+    adapter: Adapter,
     /// A handle to a graphics chip
     pub device: Device,
     /// Executes commands, and provides methods for writing to buffers and textures
@@ -31,35 +122,218 @@ pub struct State {
     pub config: SurfaceConfiguration,
     /// The size of the window in physical pixels
     pub size: PhysicalSize<u32>,
-    /// A handle to a graphics rendering pipeline
-    render_pipeline: RenderPipeline,
-
-    /// A handle to a buffer of vertices
-    vertex_buffer: Buffer,
-    /// Indices into `vertex_buffer` which allow for deduplication of vertices
-    index_buffer: Buffer,
-    /// The number of indices in `index_buffer`
-    num_indices: u32,
-    /// All of the associated information for a `wgpu::Texture`
-    _diffuse_texture: OurTexture,
-    /// A group of bound resources
-    diffuse_bind_group: BindGroup,
+    /// The scene's resolution as a multiple of the window's, see `set_render_scale`
+    render_scale: f32,
+    /// Adjusts `render_scale` to the GPU's frame time, if it's on
+    dynamic_resolution: Option<DynamicResolution>,
+    /// Where the picture is drawn in the window, see `set_viewport`
+    viewport: Option<Viewport>,
+    /// What's drawn of the picture, see `set_scissor_rect`
+    scissor_rect: Option<ScissorRect>,
+    /// Keeps the picture at a fixed aspect ratio, if there's no `viewport`
+    letterbox: Option<Letterbox>,
+    /// The adapter we're running on and the device's features and limits
+    gpu_info: GpuInfo,
+    /// The optional features the device has, which decide the fallbacks used in their place
+    capabilities: Capabilities,
+    /// Decides the time of each frame, which animations and effects like film grain are driven by
+    clock: Clock,
+    time: FrameTime,
+    /// Every shader, kept for rebuilding pipelines
+    shader_library: ShaderLibrary,
+    /// The render pipelines for each permutation of the shader's defines
+    pipeline_cache: PipelineCache,
+    /// The defines used to select the current render pipeline from `pipeline_cache`
+    shader_defs: ShaderDefs,
+    /// The same permutations for skinned meshes, with their skin in bind group `SKIN_GROUP`.
+    /// Only compiled once there's a skinned mesh in the scene
+    skinned_pipeline_cache: PipelineCache,
+    /// The layout of every skin's bind group
+    skin_layout: BindGroupLayout,
+    /// The same permutations for morphed meshes, with their targets in bind group `MORPH_GROUP`.
+    /// Only compiled once there's a morphed mesh in the scene, and missing without `Capabilities::vertex_storage`
+    morphed_pipeline_cache: Option<PipelineCache>,
+    /// The layout of every mesh's morph targets, if they're supported
+    morph_layout: Option<BindGroupLayout>,
+
+    /// What the scene is cleared to, which is see-through if the window is transparent
+    background: Color,
+    /// Drawn over the background, if it was asked for and the window isn't transparent
+    sky: Option<Sky>,
+    /// Moves the sky's sun, if there's both a sky and a day to move it through
+    time_of_day: Option<TimeOfDay>,
+
+    /// Full-screen effects applied to the rendered scene before it is presented
+    post_process: PostProcessStack,
+
+    /// Shared buffers which every mesh's vertices are sub-allocated from
+    vertex_pool: BufferPool,
+    /// Shared buffers which every mesh's indices are sub-allocated from
+    index_pool: BufferPool,
+    /// Every instance in the scene, sub-allocated from `vertex_pool`
+    instance_buffer: Allocation,
+    /// Everything in the scene, i.e. the field of cubes and the floor they sit on
+    models: Vec<Model>,
+    /// What's in `instance_buffer`, which physics moves the cubes of
+    instances: Vec<Instance>,
+    /// Whether `instances` has been changed through `instances_mut` since it was last uploaded
+    instances_changed: bool,
+    /// Each instance's model matrix as of the last frame, for motion blur to see how far it's moved
+    previous_models: Vec<Matrix4<f32>>,
+    /// Whether any instance moved in the last frame, so that they have to be uploaded again
+    /// for their motion vectors to come back to rest even if nothing moves them this frame
+    instances_moving: bool,
+    /// The index of the cubes in `models`, whose instances are the last in the instance buffer
+    /// so that spawning another cube only has to extend their range
+    #[cfg(feature = "physics")]
+    cube_model: usize,
+    /// Drops the cubes onto the floor and stacks them, if it was asked for
+    #[cfg(feature = "physics")]
+    physics: Option<Physics>,
+    /// The ground in place of the floor, if there's a heightmap for it
+    terrain: Option<Terrain>,
+    /// A reflective water surface, if one was asked for
+    water: Option<Water>,
+    mirrors: Vec<Mirror>,
+    /// Points loaded from a file, if one was given
+    point_cloud: Option<PointCloud>,
+    /// Draws the instances which are glass over the rest of the scene, missing only if it failed to be created
+    glass: Option<Glass>,
+    /// Grass scattered over the ground, if it was asked for
+    vegetation: Option<Vegetation>,
+    /// Draws the scene once for each eye, if it was asked for
+    stereo: Option<Stereo>,
+    /// The textures models are painted with, the first unless their instances pick another
+    material_textures: MaterialTextures,
+    emissive_texture: OurTexture,
+    /// White all over without a height map, which leaves the surfaces flat
+    height_texture: OurTexture,
+    /// Only if there's a height map for it to use
+    parallax: Option<Parallax>,
+    parallax_uniform: UniformBuffer<ParallaxUniform>,
+    /// Black all over without a displacement map, which leaves every vertex where it is
+    displacement_texture: OurTexture,
+    displacement_sampler: Sampler,
+    /// Only if there's a displacement map for it to use
+    displacement: Option<Displacement>,
+    displacement_uniform: UniformBuffer<DisplacementUniform>,
+    /// The material's bind groups, one for all of `material_textures` if they're bindless, otherwise one for each
+    diffuse_bind_groups: Vec<BindGroup>,
+    /// For rebuilding `diffuse_bind_groups` when the textures change
+    texture_bind_group_layout: BindGroupLayout,
 
     camera: Camera,
     camera_controller: CameraController,
-    camera_uniform: CameraUniform,
-    camera_buffer: Buffer,
+    /// What stops the camera moving into things
+    camera_collision: CameraCollision,
+    /// What moves the camera instead of `camera_controller` while walking round the scene, see `set_walking`
+    walk: Option<WalkController>,
+    camera_uniform: UniformBuffer<CameraUniform>,
     camera_bind_group: BindGroup,
+    /// Anything behind one of these is cut away, see `set_clip_planes`
+    clip_planes: Vec<Plane>,
+
+    light: PointLight,
+    light_uniform: UniformBuffer<LightUniform>,
+    fog: Fog,
+    fog_uniform: UniformBuffer<FogUniform>,
+    /// The sun's light, which is off without a sky
+    directional_light_uniform: UniformBuffer<DirectionalLightUniform>,
+    /// Reflected by shiny surfaces, rendered from the sky whenever the sun moves
+    environment_map: EnvironmentMap,
+    /// The lights' uniforms and shadow map, and the environment map
+    light_bind_group: BindGroup,
+    shadow_map: PointShadowMap,
+
+    /// Lines drawn over the scene for visualising things like lights
+    debug_draw: DebugDraw,
+    /// Rings the instances it's given, nothing to begin with
+    outline: Outline,
+    /// The rectangle being dragged out to pick instances to outline
+    marquee: Marquee,
+    /// Draws the normals of models whose `NormalLines` are shown
+    normal_view: NormalView,
+    /// Whether to draw the lights with `debug_draw`, toggled with G
+    pub show_gizmos: bool,
+    /// Screen-space text drawn over the final image
+    #[cfg(feature = "ui")]
+    text: TextRenderer,
+    /// Recent log records, shown over the scene when toggled
+    #[cfg(feature = "ui")]
+    log_console: LogConsole,
+    /// A breakdown of the CPU time spent on each frame, shown over the scene when toggled
+    #[cfg(feature = "ui")]
+    profiler: ProfilerOverlay,
+    /// Records frames to disk, started and stopped with F9
+    recorder: Recorder,
+    /// Hands frames to a callback of the app's, if it has set one
+    frame_stream: FrameStream,
+    /// Spreads recording the frame's passes over threads, see `set_encoding_threads`
+    jobs: JobSystem,
+    /// Compute work submitted ahead of each frame, see `async_compute`
+    async_compute: AsyncCompute,
+    /// Times each frame on the GPU, if the device has timestamp queries
+    gpu_timer: Option<GpuTimer>,
+    /// The GPU times read back by `gpu_timer` which haven't been taken by `gpu_frame_times` yet
+    gpu_times: Vec<(u64, f64)>,
+    /// Counts the fragments each object shades, if the device has pipeline statistics queries
+    fragment_stats: Option<FragmentStats>,
+    /// The latest counts read back by `fragment_stats`, by object
+    fragment_counts: Vec<(DrawnObject, u64)>,
+    /// Captures frames with F12, if the app was launched from RenderDoc
+    #[cfg(feature = "renderdoc")]
+    gpu_capture: Option<GpuCapture>,
 }
 
 impl State {
+    /// The most GPU times kept for `gpu_frame_times`, with the oldest dropped first
+    const MAX_GPU_TIMES: usize = 64;
+    /// What the rectangle being dragged out to pick instances is drawn in
+    const MARQUEE_COLOR: [f32; 3] = [1.0, 0.8, 0.2];
+    /// The samples per pixel `set_sample_count` takes, if the adapter has them, see `sample_counts`
+    pub const SAMPLE_COUNTS: [u32; 2] = [1, 4];
+    /// The render scales `set_render_scale` takes
+    pub const RENDER_SCALES: RangeInclusive<f32> = 0.5..=2.0;
+    /// How many clip planes `set_clip_planes` takes, which leaves room in the camera for a planar reflection's
+    pub const MAX_CLIP_PLANES: usize = CameraUniform::CLIP_PLANES - 1;
+
     // Create a connection to the GPU, and setup a surface
-    pub async fn new(window: &Window) -> Self {
-        let size = window.inner_size();
+    pub async fn new(window: &Window, app_config: &Config) -> Self {
+        // Safety: `run` keeps the window for as long as the event loop, which outlives the state
+        unsafe {
+            Self::from_raw_window(
+                window,
+                window.inner_size(),
+                window.scale_factor(),
+                app_config,
+            )
+            .await
+        }
+    }
 
+    /// `new`, for a window owned by something else rather than one created by `run`,
+    /// e.g. a widget in a Qt, GTK or Tauri app the renderer is embedded in.
+    /// `size` is in physical pixels, and the host has to pass on resizes through `resize`
+    /// and changes to the scale factor through `set_scale_factor`.
+    /// If the host replaces the window, or the surface is lost, it's recreated with `recreate_raw_surface`
+    ///
+    /// # Safety
+    /// `target`'s window and display have to stay valid for as long as the state,
+    /// or until the surface is recreated for another
+    #[tracing::instrument(skip_all)]
+    pub async unsafe fn from_raw_window<W>(
+        target: &W,
+        size: PhysicalSize<u32>,
+        scale_factor: f64,
+        app_config: &Config,
+    ) -> Self
+    where
+        W: HasRawWindowHandle + HasRawDisplayHandle,
+    {
         // `instance` is a handle to the GPU
-        let instance = Instance::new(Backends::all());
-        let surface = unsafe { instance.create_surface(window) };
+        let backends = app_config.backends();
+        let instance = wgpu::Instance::new(backends);
+        let surface = instance.create_surface(target);
         let adapter = instance
             .request_adapter(&RequestAdapterOptions {
                 power_preference: PowerPreference::default(),
@@ -68,81 +342,293 @@ impl State {
                 force_fallback_adapter: false,
             })
             .await
-            .unwrap();
-        log::info!("Adapter: {:#?}", &adapter);
-        let (device, queue) = adapter
+            .unwrap_or_else(|| panic!("No adapter for {backends:?} can present to the window"));
+        let adapter_info = adapter.get_info();
+        tracing::info!(
+            "Using {} through {:?}, out of {backends:?}",
+            adapter_info.name,
+            adapter_info.backend
+        );
+        let (device, queue) = Self::request_device(&adapter, app_config).await.unwrap();
+        let format = surface_format(&surface, &adapter);
+        Self::build(
+            instance,
+            adapter,
+            device,
+            queue,
+            Some(surface),
+            format,
+            size,
+            scale_factor,
+            app_config,
+        )
+    }
+
+    /// A device with the features and limits the renderer makes the most of,
+    /// for an app creating the device itself to hand to `with_device`
+    pub async fn request_device(
+        adapter: &Adapter,
+        app_config: &Config,
+    ) -> Result<(Device, Queue), RequestDeviceError> {
+        // wgpu records into a file inside the directory, but won't create the directory itself
+        let api_trace = app_config.api_trace.as_deref().filter(|dir| {
+            std::fs::create_dir_all(dir)
+                .map_err(|error| {
+                    tracing::error!(
+                        "Can't record a wgpu API trace into {}: {error}",
+                        dir.display()
+                    )
+                })
+                .is_ok()
+        });
+        adapter
             .request_device(
                 &DeviceDescriptor {
-                    // any extra features
-                    features: Features::empty(),
-                    // the minimum limits for certain types of resources that our adapter should meet
-                    limits: Limits::default(),
+                    // whichever optional features the adapter has, see `Capabilities` for the fallbacks
+                    features: Capabilities::features(adapter),
+                    // as much as the adapter allows of what we'd like, `gpu_info.limits` says what was granted
+                    limits: limits::intersect(&limits::desired(), &adapter.limits()),
                     label: None,
                 },
-                None,
+                api_trace,
             )
             .await
-            .unwrap();
+    }
+
+    /// `new`, for rendering into textures the caller supplies to `render_to` rather than into a window,
+    /// so that another wgpu app can composite the scene into its own frames.
+    /// The state takes over the caller's device, which the caller goes on using through `device` and `queue`
+    /// to create the textures and do its own rendering. They have to be `format` and `size`,
+    /// and usable as a `RENDER_ATTACHMENT`. Without a surface `render` does nothing,
+    /// and `is_suspended` is always true
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_device(
+        instance: wgpu::Instance,
+        adapter: Adapter,
+        device: Device,
+        queue: Queue,
+        format: TextureFormat,
+        size: PhysicalSize<u32>,
+        scale_factor: f64,
+        app_config: &Config,
+    ) -> Self {
+        Self::build(
+            instance,
+            adapter,
+            device,
+            queue,
+            None,
+            format,
+            size,
+            scale_factor,
+            app_config,
+        )
+    }
+
+    /// Everything but the connection to the GPU, rendering to `surface` if there is one
+    #[allow(clippy::too_many_arguments)]
+    fn build(
+        instance: wgpu::Instance,
+        adapter: Adapter,
+        device: Device,
+        queue: Queue,
+        surface: Option<Surface>,
+        format: TextureFormat,
+        size: PhysicalSize<u32>,
+        // only the text depends on it
+        #[cfg_attr(not(feature = "ui"), allow(unused_variables))] scale_factor: f64,
+        app_config: &Config,
+    ) -> Self {
+        let gpu_info = GpuInfo::new(&adapter, &device);
+        tracing::info!("{gpu_info}");
+        let capabilities = Capabilities::new(&adapter, &device);
+        tracing::info!("Capabilities: {capabilities}");
         let config = SurfaceConfiguration {
             usage: TextureUsages::RENDER_ATTACHMENT,
-            format: surface.get_supported_formats(&adapter)[0],
+            format,
             width: size.width,
             height: size.height,
             // The method used to sync the surface with the display,
-            // `PresentMode::Fifo` will cap the display rate at the display's framerate
-            present_mode: PresentMode::Fifo,
-            alpha_mode: CompositeAlphaMode::Auto,
+            // `PresentMode::Fifo` will cap the display rate at the display's framerate,
+            // which would make every benchmark come out at the refresh rate
+            present_mode: app_config
+                .present_mode
+                .unwrap_or(if app_config.benchmark.is_some() {
+                    PresentMode::AutoNoVsync
+                } else {
+                    PresentMode::Fifo
+                }),
+            alpha_mode: match &surface {
+                Some(surface) if app_config.window.transparent => {
+                    transparent_alpha_mode(surface, &adapter)
+                }
+                _ => CompositeAlphaMode::Auto,
+            },
+        };
+        if let Some(surface) = &surface {
+            surface.configure(&device, &config);
+        }
+
+        // Every image the scene is loaded with is decoded at once on rayon's threads, as is the point cloud,
+        // so that they're ready to upload as they're needed below
+        let (mut images, points) = rayon::join(
+            || DecodedImages::decode(config_images(app_config)),
+            || app_config.point_cloud.as_deref().map(load_points),
+        );
+
+        let shader_library = ShaderLibrary::new();
+        // Any of the textures which fail to load or generate fall back to their defaults rather than stopping the app
+        let mut load_texture = |source: &TextureSource, label: &str, kind: TextureKind| {
+            source
+                .load(
+                    &device,
+                    &queue,
+                    &shader_library,
+                    &capabilities,
+                    &mut images,
+                    label,
+                    kind,
+                )
+                .map_err(|error| tracing::error!("Failed to create the {label}: {error:#}"))
+                .ok()
         };
-        surface.configure(&device, &config);
+        let diffuse_texture = app_config
+            .texture
+            .as_ref()
+            .and_then(|source| load_texture(source, "Diffuse Texture", TextureKind::Albedo))
+            .unwrap_or_else(|| {
+                let diffuse_bytes = assets::load("plank_texture.png").unwrap();
+                OurTexture::from_bytes(
+                    &device,
+                    &queue,
+                    &diffuse_bytes,
+                    "plank_texture.png",
+                    TextureKind::Albedo,
+                )
+                .unwrap()
+            });
+        if !app_config.texture_filters.is_empty() {
+            let filtered =
+                ImageFilters::new(&device, &shader_library, &capabilities).and_then(|filters| {
+                    app_config.texture_filters.iter().try_for_each(|&filter| {
+                        filters.apply(&device, &queue, &diffuse_texture, filter)
+                    })
+                });
+            if let Err(error) = filtered {
+                tracing::error!("Failed to filter the Diffuse Texture: {error:#}");
+            }
+        }
+        // Nothing in the scene has an emissive texture of its own, so they all glow evenly
+        let emissive_texture = OurTexture::from_image(
+            &device,
+            &queue,
+            &image::RgbaImage::from_pixel(1, 1, image::Rgba([255; 4])).into(),
+            Some("Emissive Texture"),
+            TextureKind::Albedo,
+        )
+        .unwrap();
+        // Without a height map the surfaces are left flat
+        let height_texture = app_config
+            .height_map
+            .as_ref()
+            .and_then(|source| load_texture(source, "Height Texture", TextureKind::Data));
+        let parallax = height_texture.as_ref().map(|_| app_config.parallax);
+        let height_texture = height_texture.unwrap_or_else(|| {
+            OurTexture::from_image(
+                &device,
+                &queue,
+                &image::RgbaImage::from_pixel(1, 1, image::Rgba([255; 4])).into(),
+                Some("Height Texture"),
+                TextureKind::Data,
+            )
+            .unwrap()
+        });
+        let parallax_uniform = UniformBuffer::new(
+            &device,
+            ParallaxUniform::from(&parallax.unwrap_or(Parallax::OFF)),
+            Some("Parallax Buffer"),
+        );
+
+        // Likewise without a displacement map the floor is left flat
+        let displacement_texture = app_config.displacement.as_ref().and_then(|config| {
+            load_texture(
+                config.map.as_ref()?,
+                "Displacement Texture",
+                TextureKind::Data,
+            )
+        });
+        let displacement_config = app_config
+            .displacement
+            .as_ref()
+            .filter(|_| displacement_texture.is_some());
+        let displacement = displacement_config.map(|config| config.displacement);
+        let displacement_texture = displacement_texture.unwrap_or_else(|| {
+            OurTexture::from_image(
+                &device,
+                &queue,
+                &image::RgbaImage::from_pixel(1, 1, image::Rgba([0, 0, 0, 255])).into(),
+                Some("Displacement Texture"),
+                TextureKind::Data,
+            )
+            .unwrap()
+        });
+        let displacement_sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Displacement Sampler"),
+            address_mode_u: AddressMode::Repeat,
+            address_mode_v: AddressMode::Repeat,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+        let displacement_uniform = UniformBuffer::new(
+            &device,
+            DisplacementUniform::from(&displacement.unwrap_or_default()),
+            Some("Displacement Buffer"),
+        );
 
-        let diffuse_bytes = include_bytes!("plank_texture.png");
-        let diffuse_texture =
-            OurTexture::from_bytes(&device, &queue, diffuse_bytes, "happy-tree.png").unwrap();
+        // Every permutation of the shader's defines gets its own pipeline, compiled on demand,
+        // they all have to share the bind group layouts reflected from the default permutation
+        let shader_code = ShaderCode::from(shader_library.resolve("shader.wgsl").unwrap());
+        let mut material_textures =
+            MaterialTextures::new(diffuse_texture, capabilities.bindless_textures);
+        for source in &app_config.cube_textures {
+            if let Some(texture) = load_texture(source, "Cube Texture", TextureKind::Albedo) {
+                if let Err(error) = material_textures.add(texture) {
+                    tracing::error!("Failed to add the Cube Texture: {error:#}");
+                }
+            }
+        }
+        let shader_defs = material_textures.shader_defs(&ShaderDefs::new());
+        let reflection = ShaderReflection::from_code(&shader_code, &shader_defs).unwrap();
 
         // We have a bind group layout as it allows us to swap out bind groups on the fly, as long as the layout is the same
         let texture_bind_group_layout =
-            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                entries: &[
-                    BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: ShaderStages::FRAGMENT,
-                        ty: BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: TextureViewDimension::D2,
-                            sample_type: TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: ShaderStages::FRAGMENT,
-                        // This should match the filterable field of the
-                        // corresponding Texture entry above.
-                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
-        let diffuse_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::TextureView(&diffuse_texture.view),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
+            reflection.create_bind_group_layout(&device, 0, Some("texture_bind_group_layout"));
+        let diffuse_bind_groups = material_textures.bind_groups(|diffuse| {
+            reflection
+                .create_bind_group(
+                    &device,
+                    0,
+                    &texture_bind_group_layout,
+                    &material_entries(
+                        diffuse,
+                        &material_textures.first().sampler,
+                        &emissive_texture,
+                        &height_texture,
+                        &parallax_uniform,
+                        &displacement_texture,
+                        &displacement_uniform,
+                        &displacement_sampler,
+                    ),
+                    Some("diffuse_bind_group"),
+                )
+                .unwrap()
         });
 
         let camera = Camera {
-            // position the camera one unit up and 2 units back
+            // position the camera 5 units up and 9 units back, to fit the whole field of cubes in
             // +z is out of the screen
-            eye: (0.0, 4.0, 6.0).into(),
+            eye: (0.0, 5.0, 9.0).into(),
             // have it look at the origin
             target: (0.0, 0.0, 0.0).into(),
             // which way is "up"
@@ -154,121 +640,741 @@ impl State {
         };
         let camera_controller = CameraController::new(0.2);
 
+        // A uniform buffer for the camera
         let mut camera_uniform = CameraUniform::default();
         camera_uniform.update_view_proj(&camera);
-
-        // A uniform buffer for the camera
-        let camera_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Camera Buffer"),
-            contents: bytemuck::cast_slice(&[camera_uniform]),
-            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-        });
+        // there is no previous frame, so nothing has moved yet
+        camera_uniform.reset_history();
+        let camera_uniform = UniformBuffer::new(&device, camera_uniform, Some("Camera Buffer"));
         let camera_bind_group_layout =
-            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                entries: &[BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::VERTEX,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("camera_bind_group_layout"),
+            reflection.create_bind_group_layout(&device, 1, Some("camera_bind_group_layout"));
+        let camera_bind_group = reflection
+            .create_bind_group(
+                &device,
+                1,
+                &camera_bind_group_layout,
+                &[camera_uniform.bind_group_entry(0)],
+                Some("camera_bind_group"),
+            )
+            .unwrap();
+
+        // A single point light above the cube, casting its shadow onto the floor
+        let light = PointLight {
+            position: (3.0, 5.0, 2.0).into(),
+            color: [1.0, 0.95, 0.9],
+            intensity: 40.0,
+            range: 25.0,
+            shadow_bias: 0.002,
+            shadow_normal_offset: 1.0,
+            shadow_filter: ShadowFilter::default(),
+        };
+        let light_uniform =
+            UniformBuffer::new(&device, LightUniform::from(&light), Some("Light Buffer"));
+        let skin_layout = Skin::bind_group_layout(&device);
+        // Morph targets need storage buffers in vertex shaders, without them the layout is invalid
+        let morph_layout = capabilities
+            .vertex_storage
+            .then(|| MorphTargets::bind_group_layout(&device));
+        let shadow_map = PointShadowMap::new(
+            &device,
+            &shader_library,
+            &skin_layout,
+            morph_layout.as_ref(),
+        )
+        .unwrap();
+        let background = if app_config.window.transparent {
+            // premultiplied, so all zeroes
+            Color::TRANSPARENT
+        } else {
+            Color {
+                r: 0.1,
+                g: 0.2,
+                b: 0.3,
+                a: 1.0,
+            }
+        };
+        // A sky would cover a transparent window, so there's never one there
+        let sky = app_config
+            .sky
+            .filter(|_| !app_config.window.transparent)
+            .map(|mut sun| {
+                if let Some(time_of_day) = &app_config.time_of_day {
+                    time_of_day.place_sun(&mut sun);
+                }
+                sun
+            })
+            .and_then(|sun| {
+                Sky::new(
+                    &device,
+                    &shader_library,
+                    sun,
+                    &camera_bind_group_layout,
+                    &[
+                        PostProcessStack::SCENE_FORMAT,
+                        SceneTargets::VELOCITY_FORMAT,
+                    ],
+                    OurTexture::DEPTH_FORMAT,
+                )
+                .map_err(|error| tracing::error!("Failed to create the sky: {error:#}"))
+                .ok()
             });
-        let camera_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-            label: Some("camera_bind_group"),
-        });
+        // Faded into the background, so that distant things disappear rather than standing out against it
+        let fog = Fog {
+            color: sky.as_ref().map_or(
+                [
+                    background.r as f32,
+                    background.g as f32,
+                    background.b as f32,
+                ],
+                Sky::horizon_color,
+            ),
+            mode: app_config.fog,
+        };
+        let fog_uniform = UniformBuffer::new(&device, FogUniform::from(&fog), Some("Fog Buffer"));
+        let directional_light_uniform = UniformBuffer::new(
+            &device,
+            DirectionalLightUniform::from(&sky.as_ref().map(Sky::light).unwrap_or_default()),
+            Some("Directional Light Buffer"),
+        );
+        let environment_map =
+            EnvironmentMap::new(&device, &shader_library, &camera_bind_group_layout).unwrap();
+        let light_bind_group_layout =
+            reflection.create_bind_group_layout(&device, 2, Some("light_bind_group_layout"));
+        let [environment_entry, environment_sampler_entry] = environment_map.bind_group_entries(5);
+        let [shadow_entry, shadow_sampler_entry] = shadow_map.bind_group_entries(1);
+        let light_bind_group = reflection
+            .create_bind_group(
+                &device,
+                2,
+                &light_bind_group_layout,
+                &[
+                    light_uniform.bind_group_entry(0),
+                    shadow_entry,
+                    shadow_sampler_entry,
+                    fog_uniform.bind_group_entry(3),
+                    directional_light_uniform.bind_group_entry(4),
+                    environment_entry,
+                    environment_sampler_entry,
+                ],
+                Some("light_bind_group"),
+            )
+            .unwrap();
 
-        let shader = device.create_shader_module(include_wgsl!("shader.wgsl"));
         let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
+            bind_group_layouts: &[
+                &texture_bind_group_layout,
+                &camera_bind_group_layout,
+                &light_bind_group_layout,
+            ],
             push_constant_ranges: &[],
         });
-        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                // the "main function" for the vertex shader
-                entry_point: "vs_main",
-                // what type of vertices we want to pass to the vertex shader
-                buffers: &[Vertex::desc()],
-            },
-            // technically optional
-            fragment: Some(FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                // what colour outputs wgpu should set up,
-                // currently only need one for the `surface`
-                targets: &[Some(ColorTargetState {
-                    format: config.format,
-                    blend: Some(BlendState::REPLACE),
-                    write_mask: ColorWrites::ALL,
-                })],
-            }),
-            primitive: PrimitiveState {
-                // every 3 vertices will correspond to 1 triangle
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                // how to determine if a triangle is facing forwards or not
-                // in this case the triangle is facing forwards if the vertices are arranged counter-clockwise
-                front_face: FrontFace::Ccw,
-                // cull a triangle (don't render it) if it is facing backwards
-                cull_mode: Some(Face::Back),
-                polygon_mode: PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: MultisampleState {
-                // how many samples the pipeline will use
-                count: 1,
-                // which samples should be active, in this case, we want to use all of them
-                mask: !0,
-                // to do with anti-aliasing
-                alpha_to_coverage_enabled: false,
-            },
-            // how many array layers the render attachments can have
-            // we won't be rendering to array textures, hence the `None`
-            multiview: None,
+        let skinned_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Skinned Render Pipeline Layout"),
+            bind_group_layouts: &[
+                &texture_bind_group_layout,
+                &camera_bind_group_layout,
+                &light_bind_group_layout,
+                &skin_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let morphed_pipeline_cache = morph_layout.as_ref().map(|morph_layout| {
+            let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("Morphed Render Pipeline Layout"),
+                bind_group_layouts: &[
+                    &texture_bind_group_layout,
+                    &camera_bind_group_layout,
+                    &light_bind_group_layout,
+                    morph_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+            PipelineCache::new(
+                "Morphed Render Pipeline",
+                shader_code.clone(),
+                layout,
+                vec![Vertex::desc(), InstanceRaw::desc()],
+                vec![
+                    PostProcessStack::SCENE_FORMAT,
+                    SceneTargets::VELOCITY_FORMAT,
+                ],
+                Some(OurTexture::DEPTH_FORMAT),
+            )
+        });
+        let skinned_pipeline_cache = PipelineCache::new(
+            "Skinned Render Pipeline",
+            shader_code.clone(),
+            skinned_pipeline_layout,
+            vec![SkinnedVertex::desc(), InstanceRaw::desc()],
+            vec![
+                PostProcessStack::SCENE_FORMAT,
+                SceneTargets::VELOCITY_FORMAT,
+            ],
+            Some(OurTexture::DEPTH_FORMAT),
+        );
+        let glass = Glass::new(
+            &device,
+            shader_code.clone(),
+            app_config.glass.unwrap_or_default(),
+            [
+                &texture_bind_group_layout,
+                &camera_bind_group_layout,
+                &light_bind_group_layout,
+            ],
+            GLASS_GROUP,
+            size.width,
+            size.height,
+        )
+        .map_err(|error| tracing::error!("Failed to create the glass: {error:#}"))
+        .ok();
+        let mut pipeline_cache = PipelineCache::new(
+            "Render Pipeline",
+            shader_code,
+            render_pipeline_layout,
+            vec![Vertex::desc(), InstanceRaw::desc()],
+            vec![
+                PostProcessStack::SCENE_FORMAT,
+                SceneTargets::VELOCITY_FORMAT,
+            ],
+            Some(OurTexture::DEPTH_FORMAT),
+        );
+        // Triplanar mapping only changes how textures are sampled, so it still fits the default layouts
+        let shader_defs = match app_config.triplanar {
+            Some(tile_size) => triplanar_defs(&shader_defs, tile_size),
+            None => shader_defs,
+        };
+        let shader_defs = app_config.debug_view.apply(&shader_defs, camera.zfar);
+        pipeline_cache.prepare(&device, &shader_defs).unwrap();
+        let glass = glass.and_then(|mut glass| {
+            glass
+                .prepare(&device, &shader_defs)
+                .map_err(|error| tracing::error!("Failed to compile the glass: {error:#}"))
+                .ok()?;
+            Some(glass)
+        });
+
+        let debug_draw = DebugDraw::new(
+            &device,
+            &shader_library,
+            &camera_bind_group_layout,
+            &[
+                PostProcessStack::SCENE_FORMAT,
+                SceneTargets::VELOCITY_FORMAT,
+            ],
+            OurTexture::DEPTH_FORMAT,
+        )
+        .unwrap();
+        let mut outline = Outline::new(
+            &device,
+            &shader_library,
+            OutlineSettings::default(),
+            &camera_bind_group_layout,
+            &[
+                PostProcessStack::SCENE_FORMAT,
+                SceneTargets::VELOCITY_FORMAT,
+            ],
+            OurTexture::DEPTH_FORMAT,
+        )
+        .unwrap();
+        outline.set_instances(app_config.outline.clone());
+        let normal_view = NormalView::new(
+            &device,
+            &shader_library,
+            NormalViewSettings::default(),
+            &camera_bind_group_layout,
+            &[
+                PostProcessStack::SCENE_FORMAT,
+                SceneTargets::VELOCITY_FORMAT,
+            ],
+            OurTexture::DEPTH_FORMAT,
+        )
+        .unwrap();
+
+        let mut post_process = PostProcessStack::new(
+            &device,
+            &shader_library,
+            config.format,
+            size.width,
+            size.height,
+        )
+        .unwrap();
+        let depth_of_field =
+            DepthOfField::new(&device, &shader_library, &post_process, &camera).unwrap();
+        post_process.push(depth_of_field);
+        // Blur after depth of field, as the camera's shutter sees an already out of focus image
+        let motion_blur = MotionBlur::new(&device, &shader_library, &post_process).unwrap();
+        post_process.push(motion_blur);
+        // The lens effects come before grading, the film effects after
+        let bloom = Bloom::new(&device, &shader_library, &post_process).unwrap();
+        post_process.push(bloom);
+        let chromatic_aberration =
+            ChromaticAberration::new(&device, &shader_library, &post_process).unwrap();
+        post_process.push(chromatic_aberration);
+        // Tonemapping brings the scene down to what the screen can show, which is what the LUT expects
+        let mut tonemapping =
+            Tonemapping::new(&device, &shader_library, &post_process, &capabilities).unwrap();
+        if let Some(mode) = app_config.exposure {
+            tonemapping.enabled = true;
+            if let Err(error) = tonemapping.set_mode(mode) {
+                tracing::error!("Falling back to a fixed exposure: {error:#}");
+            }
+        }
+        let mut async_compute = AsyncCompute::new(&capabilities);
+        if let Some(workload) = tonemapping.exposure_workload() {
+            async_compute
+                .add(workload)
+                .expect("the histogram is only made with compute shaders");
+        }
+        post_process.push(tonemapping);
+        let color_grading =
+            ColorGrading::new(&device, &queue, &shader_library, &post_process).unwrap();
+        post_process.push(color_grading);
+        // The film effects are for the picture as it's seen, not each eye's half of it
+        let anaglyph = Anaglyph::new(&device, &shader_library, &post_process).unwrap();
+        post_process.push(anaglyph);
+        let vignette = Vignette::new(&device, &shader_library, &post_process).unwrap();
+        post_process.push(vignette);
+        let film_grain = FilmGrain::new(&device, &shader_library, &post_process).unwrap();
+        post_process.push(film_grain);
+        post_process.set_bypassed(app_config.debug_view != DebugView::Off);
+        // Benchmarks should render the same frames every time, or their timings can't be compared
+        let clock = if app_config.deterministic || app_config.benchmark.is_some() {
+            Clock::fixed(Clock::DEFAULT_DELTA)
+        } else {
+            Clock::real_time()
+        };
+
+        // Sized for the window's scale factor, so that the text is equally readable on high DPI displays
+        #[cfg(feature = "ui")]
+        let text = TextRenderer::new(
+            &device,
+            &queue,
+            &shader_library,
+            config.format,
+            14.0,
+            scale_factor as f32,
+        )
+        .unwrap();
+
+        #[cfg(feature = "ui")]
+        let mut profiler = ProfilerOverlay::new();
+        #[cfg(feature = "ui")]
+        profiler.set_gpu(&gpu_info);
+
+        let mut vertex_pool = BufferPool::new("Vertex Pool", BufferUsages::VERTEX, 1 << 20);
+        let mut index_pool = BufferPool::new("Index Pool", BufferUsages::INDEX, 1 << 18);
+
+        // A field of cubes, each with its own tint and material, which can all be drawn with a single draw call.
+        // Roughness increases from left to right, and metallic from back to front
+        const CUBES_PER_ROW: u32 = 3;
+        const CUBE_SPACING: f32 = 3.0;
+        const TINTS: [[f32; 3]; 3] = [[1.0, 0.6, 0.6], [0.6, 1.0, 0.6], [0.6, 0.6, 1.0]];
+        let floor = Instance {
+            transform: Transform::IDENTITY,
+            tint: [1.0; 3],
+            roughness: 1.0,
+            metallic: 0.0,
+            emissive: [0.0; 3],
+            displacement: displacement_config.map_or(0.0, |config| config.floor_amplitude),
+            transmission: 0.0,
+            texture: 0,
+        };
+        let glass_cubes = app_config.glass.is_some();
+        let cube_textures = material_textures.textures().len() as u32;
+        let cubes = (0..CUBES_PER_ROW).flat_map(|z| {
+            (0..CUBES_PER_ROW).map(move |x| {
+                let offset = (CUBES_PER_ROW - 1) as f32 / 2.0;
+                let translation =
+                    Vector3::new(x as f32 - offset, 0.0, z as f32 - offset) * CUBE_SPACING;
+                let rotation = Quaternion::from_angle_y(Deg(15.0 * (x + z * CUBES_PER_ROW) as f32));
+                Instance {
+                    transform: Transform::from_translation(translation).with_rotation(rotation),
+                    tint: TINTS[((x + z) % 3) as usize],
+                    roughness: 0.2 + 0.8 * x as f32 / (CUBES_PER_ROW - 1) as f32,
+                    metallic: z as f32 / (CUBES_PER_ROW - 1) as f32,
+                    emissive: [0.0; 3],
+                    // their corners are split between faces, which would pull apart
+                    displacement: 0.0,
+                    // the front row is see-through when there's glass
+                    transmission: if glass_cubes && z == CUBES_PER_ROW - 1 {
+                        1.0
+                    } else {
+                        0.0
+                    },
+                    // taking turns with any `--cube-texture`s
+                    texture: (x + z * CUBES_PER_ROW) % cube_textures,
+                }
+            })
+        });
+        let instances = std::iter::once(floor).chain(cubes).collect::<Vec<_>>();
+        let instance_buffer = vertex_pool.allocate_init(
+            &device,
+            &queue,
+            bytemuck::cast_slice(
+                &instances
+                    .iter()
+                    .map(|instance| instance.to_raw())
+                    .collect::<Vec<_>>(),
+            ),
+        );
+        // Every cube is a body, resting on the ground where the floor or top of the terrain is
+        #[cfg(feature = "physics")]
+        let physics = app_config.physics.then(|| {
+            let mut physics = Physics::new(TERRAIN_TOP);
+            for (index, cube) in instances.iter().enumerate().skip(1) {
+                physics.add_cuboid(index, cube, Vector3::new(1.0, 1.0, 1.0));
+            }
+            physics
+        });
+        #[cfg(not(feature = "physics"))]
+        if app_config.physics {
+            tracing::warn!("--physics needs the app to be built with the `physics` feature");
+        }
+        // A terrain which fails to load is left out rather than stopping the app, the floor takes its place
+        let terrain = app_config.terrain.as_ref().and_then(|terrain_config| {
+            Terrain::new(
+                &device,
+                &queue,
+                &shader_library,
+                terrain_config,
+                TERRAIN_TOP,
+                &camera_bind_group_layout,
+                &light_bind_group_layout,
+                vec![
+                    PostProcessStack::SCENE_FORMAT,
+                    SceneTargets::VELOCITY_FORMAT,
+                ],
+                OurTexture::DEPTH_FORMAT,
+                &mut vertex_pool,
+                &mut index_pool,
+                // the floor's instance is the identity, which the terrain's vertices are already placed for
+                0..1,
+                &mut images,
+            )
+            .map_err(|error| tracing::error!("Failed to build the terrain: {error:#}"))
+            .ok()
+        });
+        // Scattered over whichever ground there is, and left out if it fails like the terrain
+        let vegetation = app_config
+            .vegetation
+            .as_ref()
+            .and_then(|vegetation_config| {
+                let ground_height = |x: f32, z: f32| match &terrain {
+                    Some(terrain) => terrain.height_at(x, z),
+                    None => {
+                        (x.abs() <= FLOOR_EXTENT && z.abs() <= FLOOR_EXTENT).then_some(FLOOR_HEIGHT)
+                    }
+                };
+                Vegetation::new(
+                    &device,
+                    &queue,
+                    &shader_library,
+                    vegetation_config,
+                    ground_height,
+                    &camera_bind_group_layout,
+                    &light_bind_group_layout,
+                    vec![
+                        PostProcessStack::SCENE_FORMAT,
+                        SceneTargets::VELOCITY_FORMAT,
+                    ],
+                    OurTexture::DEPTH_FORMAT,
+                    &mut vertex_pool,
+                    &mut index_pool,
+                )
+                .map_err(|error| tracing::error!("Failed to scatter the vegetation: {error:#}"))
+                .ok()
+            });
+        let water = app_config.water.and_then(|settings| {
+            Water::new(
+                &device,
+                &queue,
+                &shader_library,
+                settings,
+                &camera_bind_group_layout,
+                &light_bind_group_layout,
+                &[
+                    PostProcessStack::SCENE_FORMAT,
+                    SceneTargets::VELOCITY_FORMAT,
+                ],
+                OurTexture::DEPTH_FORMAT,
+                &mut vertex_pool,
+                &mut index_pool,
+                size.width,
+                size.height,
+            )
+            .map_err(|error| tracing::error!("Failed to create the water: {error:#}"))
+            .ok()
+        });
+        let mirrors = app_config
+            .mirrors
+            .iter()
+            .filter_map(|&settings| {
+                Mirror::new(
+                    &device,
+                    &queue,
+                    &shader_library,
+                    settings,
+                    &camera_bind_group_layout,
+                    &[
+                        PostProcessStack::SCENE_FORMAT,
+                        SceneTargets::VELOCITY_FORMAT,
+                    ],
+                    OurTexture::DEPTH_FORMAT,
+                    &mut vertex_pool,
+                    &mut index_pool,
+                    size.width,
+                    size.height,
+                )
+                .map_err(|error| tracing::error!("Failed to create a mirror: {error:#}"))
+                .ok()
+            })
+            .collect();
+        // Left out if it fails to load, like the terrain
+        let point_cloud = points.and_then(|points| {
+            let settings = PointCloudSettings {
+                size: app_config
+                    .point_size
+                    .unwrap_or(PointCloudSettings::default().size),
+            };
+            points
+                .and_then(|points| {
+                    PointCloud::new(
+                        &device,
+                        &shader_library,
+                        settings,
+                        &camera_bind_group_layout,
+                        &[
+                            PostProcessStack::SCENE_FORMAT,
+                            SceneTargets::VELOCITY_FORMAT,
+                        ],
+                        OurTexture::DEPTH_FORMAT,
+                        &points,
+                    )
+                })
+                .map_err(|error| tracing::error!("Failed to load the point cloud: {error:#}"))
+                .ok()
         });
-        let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(VERTICES),
-            usage: BufferUsages::VERTEX,
+        let stereo = app_config
+            .stereo
+            .map(|settings| Stereo::new(&device, &camera_bind_group_layout, settings));
+        let mut models = Vec::new();
+        if terrain.is_none() {
+            // A vertex every 0.2 units, to show the displacement map's detail
+            let (vertices, indices) = match displacement {
+                Some(_) => floor_grid(100),
+                None => (FLOOR_VERTICES.to_vec(), FLOOR_INDICES.to_vec()),
+            };
+            models.push(Model {
+                mesh: Mesh::new(
+                    &device,
+                    &queue,
+                    &mut vertex_pool,
+                    &mut index_pool,
+                    &vertices,
+                    &indices,
+                ),
+                instances: 0..1,
+                skin: None,
+                morph: None,
+                normals: Some(NormalLines::new(
+                    &device,
+                    &queue,
+                    &mut vertex_pool,
+                    &vertices,
+                )),
+            });
+        }
+        let (cube_vertices, cube_indices) = app_config
+            .subdivide
+            .and_then(|(subdivision, levels)| {
+                subdivide(VERTICES, INDICES, subdivision, levels)
+                    .map_err(|error| tracing::error!("Failed to subdivide the cubes: {error:#}"))
+                    .ok()
+            })
+            .unwrap_or_else(|| (VERTICES.to_vec(), INDICES.to_vec()));
+        models.push(Model {
+            mesh: Mesh::new(
+                &device,
+                &queue,
+                &mut vertex_pool,
+                &mut index_pool,
+                &cube_vertices,
+                &cube_indices,
+            ),
+            instances: 1..instances.len() as u32,
+            skin: None,
+            morph: None,
+            normals: Some(NormalLines::new(
+                &device,
+                &queue,
+                &mut vertex_pool,
+                &cube_vertices,
+            )),
         });
-        let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(INDICES),
-            usage: BufferUsages::INDEX,
+        for &index in &app_config.normals {
+            if let Some(normals) = models
+                .get_mut(index)
+                .and_then(|model| model.normals.as_mut())
+            {
+                normals.shown = true;
+            }
+        }
+        #[cfg(feature = "physics")]
+        let cube_model = models.len() - 1;
+        if app_config.edges {
+            // A hair outside the faces, so that they don't hide the lines
+            let vertices = cube_vertices
+                .iter()
+                .map(|vertex| vertex.pushed_out(0.005))
+                .collect::<Vec<_>>();
+            pipeline_cache
+                .prepare(
+                    &device,
+                    &topology_defs(&shader_defs, PrimitiveTopology::LineList),
+                )
+                .unwrap();
+            models.push(Model {
+                mesh: Mesh::new(
+                    &device,
+                    &queue,
+                    &mut vertex_pool,
+                    &mut index_pool,
+                    &vertices,
+                    &crease_edges(&cube_vertices, &cube_indices),
+                )
+                .with_topology(PrimitiveTopology::LineList),
+                instances: 1..instances.len() as u32,
+                skin: None,
+                morph: None,
+                normals: Some(NormalLines::new(
+                    &device,
+                    &queue,
+                    &mut vertex_pool,
+                    &vertices,
+                )),
+            });
+        }
+        let gpu_timer = capabilities
+            .timestamp_query
+            .then(|| GpuTimer::new(&device, &queue));
+        let fragment_stats = capabilities
+            .pipeline_statistics
+            .then(|| FragmentStats::new(&device));
+        let dynamic_resolution = app_config.target_fps.and_then(|frame_rate| {
+            if gpu_timer.is_none() {
+                tracing::warn!(
+                    "--target-fps needs timestamp queries, which this adapter doesn't have"
+                );
+            }
+            gpu_timer
+                .as_ref()
+                .map(|_| DynamicResolution::with_frame_rate(frame_rate))
         });
 
-        Self {
+        let mut state = Self {
+            instance,
             surface,
+            surface_lost: false,
+            adapter,
             device,
             queue,
             config,
             size,
-            render_pipeline,
-            vertex_buffer,
-            index_buffer,
-            num_indices: INDICES.len() as u32,
-            diffuse_bind_group,
-            _diffuse_texture: diffuse_texture,
+            render_scale: 1.0,
+            dynamic_resolution,
+            gpu_info,
+            capabilities,
+            clock,
+            time: FrameTime::default(),
+            shader_library,
+            pipeline_cache,
+            shader_defs,
+            skinned_pipeline_cache,
+            skin_layout,
+            morphed_pipeline_cache,
+            morph_layout,
+            background,
+            time_of_day: app_config.time_of_day.filter(|_| sky.is_some()),
+            sky,
+            post_process,
+            vertex_pool,
+            index_pool,
+            instance_buffer,
+            models,
+            previous_models: instances
+                .iter()
+                .map(|instance| instance.transform.to_matrix())
+                .collect(),
+            instances,
+            instances_changed: false,
+            instances_moving: false,
+            #[cfg(feature = "physics")]
+            cube_model,
+            #[cfg(feature = "physics")]
+            physics,
+            terrain,
+            water,
+            mirrors,
+            point_cloud,
+            glass,
+            stereo,
+            vegetation,
+            diffuse_bind_groups,
+            texture_bind_group_layout,
+            material_textures,
+            emissive_texture,
+            height_texture,
+            parallax,
+            parallax_uniform,
+            displacement_texture,
+            displacement_sampler,
+            displacement,
+            displacement_uniform,
             camera,
             camera_controller,
+            camera_collision: CameraCollision::default(),
+            walk: None,
             camera_uniform,
-            camera_buffer,
             camera_bind_group,
+            light,
+            light_uniform,
+            fog,
+            fog_uniform,
+            directional_light_uniform,
+            environment_map,
+            light_bind_group,
+            shadow_map,
+            debug_draw,
+            outline,
+            marquee: Marquee::new(),
+            normal_view,
+            show_gizmos: false,
+            clip_planes: Vec::new(),
+            #[cfg(feature = "ui")]
+            text,
+            #[cfg(feature = "ui")]
+            log_console: LogConsole::new(),
+            #[cfg(feature = "ui")]
+            profiler,
+            recorder: Recorder::new(app_config.record.clone()),
+            frame_stream: FrameStream::new(),
+            jobs: JobSystem::new(app_config.encoding_threads.unwrap_or(1)),
+            async_compute,
+            gpu_timer,
+            gpu_times: Vec::new(),
+            fragment_stats,
+            fragment_counts: Vec::new(),
+            #[cfg(feature = "renderdoc")]
+            gpu_capture: GpuCapture::new(),
+            viewport: None,
+            scissor_rect: None,
+            letterbox: app_config.letterbox,
+        };
+        if state.letterbox.is_some() {
+            state.update_output_region();
         }
+        state
     }
 
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
@@ -276,69 +1382,2060 @@ impl State {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            self.configure_surface();
+            self.update_output_region();
+        }
+    }
 
-            self.camera.aspect = self.config.width as f32 / self.config.height as f32;
+    /// Apply the settings read from the settings file, see `settings`
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        // a transparent window is cleared to nothing, whatever the settings say
+        if self.background != Color::TRANSPARENT {
+            let [r, g, b] = settings.clear_color;
+            self.background = Color { r, g, b, a: 1.0 };
+            if self.sky.is_none() {
+                self.fog.color = [r as f32, g as f32, b as f32];
+            }
+            self.environment_map.invalidate();
+        }
+        if let Some(present_mode) = settings.present_mode {
+            if present_mode != self.config.present_mode {
+                self.config.present_mode = present_mode;
+                self.configure_surface();
+            }
+        }
+        self.camera_controller.set_speed(settings.camera_speed);
+        self.camera_collision.enabled = settings.camera_collision;
+        // dynamic resolution picks its own
+        if self.dynamic_resolution.is_none() {
+            self.set_render_scale(settings.render_scale);
+        }
+        if let Err(error) = self.set_sample_count(settings.msaa) {
+            tracing::error!("Failed to set the sample count: {error:#}");
+        }
+        self.light.shadow_filter = settings.shadows.filter;
+        self.light.shadow_bias = settings.shadows.bias;
+        self.light.shadow_normal_offset = settings.shadows.normal_offset;
+        let clip_planes = settings
+            .clip_planes
+            .iter()
+            .map(ClipPlaneSettings::plane)
+            .collect::<Vec<_>>();
+        if let Err(error) = self.set_clip_planes(&clip_planes) {
+            tracing::error!("Failed to set the clip planes: {error:#}");
         }
     }
 
-    pub fn input(&mut self, event: &WindowEvent) -> bool {
-        self.camera_controller.process_events(event)
+    /// The planes the scene's cut away behind
+    pub fn clip_planes(&self) -> &[Plane] {
+        &self.clip_planes
     }
 
-    pub fn update(&mut self) {
-        self.camera_controller.update_camera(&mut self.camera);
-        self.camera_uniform.update_view_proj(&self.camera);
-        self.queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_uniform]),
+    /// Cut away everything behind any of `planes`, of which there can be up to `MAX_CLIP_PLANES`,
+    /// e.g. for seeing inside the scene. Their normals have to be normalised.
+    /// Reflections are cut away the same, but the shadows are still cast from the whole scene
+    pub fn set_clip_planes(&mut self, planes: &[Plane]) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            planes.len() <= Self::MAX_CLIP_PLANES,
+            "there can only be {} clip planes",
+            Self::MAX_CLIP_PLANES
         );
+        self.clip_planes = planes.to_vec();
+        self.camera_uniform
+            .get_mut()
+            .set_clip_planes(&self.clip_planes);
+        Ok(())
     }
 
-    pub fn render(&mut self) -> Result<(), SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&TextureViewDescriptor::default());
-        let mut encoder = self
-            .device
-            .create_command_encoder(&CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
-        // `encoder.begin_render_pass()` takes a mutable reference to `encoder`
-        // which we want to drop once we're done with, hence the block expression
+    /// How many samples each pixel of the scene has, see `set_sample_count`
+    pub fn sample_count(&self) -> u32 {
+        self.post_process.scene().sample_count()
+    }
+
+    /// Which of `SAMPLE_COUNTS` the scene can be drawn with on this adapter, which is all of them
+    /// if it can multisample the scene pass' formats and resolve their colours. wgpu can only say whether
+    /// a format can be multisampled at all rather than how many times, but any adapter which can has 4 samples
+    pub fn sample_counts(&self) -> Vec<u32> {
+        let supports = |format, flags| {
+            self.adapter
+                .get_texture_format_features(format)
+                .flags
+                .contains(flags)
+        };
+        let resolvable =
+            TextureFormatFeatureFlags::MULTISAMPLE | TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE;
+        let multisampled = supports(PostProcessStack::SCENE_FORMAT, resolvable)
+            && supports(SceneTargets::VELOCITY_FORMAT, resolvable)
+            && supports(
+                OurTexture::DEPTH_FORMAT,
+                TextureFormatFeatureFlags::MULTISAMPLE,
+            );
+        Self::SAMPLE_COUNTS
+            .into_iter()
+            .filter(|&count| count == 1 || multisampled)
+            .collect()
+    }
+
+    /// Draw the scene with `sample_count` samples per pixel from now on, one of `sample_counts`,
+    /// which smooths the edges of everything in it. It's resolved before it's post-processed, so the effects
+    /// read the same single-sampled targets either way. Every pipeline which draws into the scene's targets,
+    /// and the reflections', is rebuilt for the new count
+    pub fn set_sample_count(&mut self, sample_count: u32) -> anyhow::Result<()> {
+        let supported = self.sample_counts();
+        anyhow::ensure!(
+            supported.contains(&sample_count),
+            "the adapter can't draw the scene with {sample_count} samples per pixel, only {supported:?}"
+        );
+        if sample_count == self.sample_count() {
+            return Ok(());
+        }
+        let device = &self.device;
+        self.post_process.set_sample_count(device, sample_count);
+        self.pipeline_cache.set_sample_count(sample_count);
+        self.skinned_pipeline_cache.set_sample_count(sample_count);
+        if let Some(cache) = &mut self.morphed_pipeline_cache {
+            cache.set_sample_count(sample_count);
+        }
+        if let Some(glass) = &mut self.glass {
+            glass.set_sample_count(sample_count);
+        }
+        if let Some(sky) = &mut self.sky {
+            sky.set_sample_count(device, sample_count);
+        }
+        if let Some(terrain) = &mut self.terrain {
+            terrain.set_sample_count(device, sample_count)?;
+        }
+        if let Some(vegetation) = &mut self.vegetation {
+            vegetation.set_sample_count(device, sample_count)?;
+        }
+        if let Some(water) = &mut self.water {
+            water.set_sample_count(device, sample_count);
+        }
+        for mirror in &mut self.mirrors {
+            mirror.set_sample_count(device, sample_count);
+        }
+        if let Some(point_cloud) = &mut self.point_cloud {
+            point_cloud.set_sample_count(device, sample_count);
+        }
+        self.outline.set_sample_count(device, sample_count);
+        self.normal_view.set_sample_count(device, sample_count);
+        self.debug_draw.set_sample_count(device, sample_count);
+        // the scene's caches were emptied, so the permutations in use are compiled again
+        self.set_shader_defs(self.shader_defs.clone())
+    }
+
+    /// The scene's resolution as a multiple of the window's
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    /// Render the scene at `scale` times the window's resolution, from 0.5 to make it cheaper on weak GPUs
+    /// up to 2.0 to supersample it. The output pass filters the scene to the window's size either way,
+    /// and the overlays are always drawn at the window's resolution
+    pub fn set_render_scale(&mut self, scale: f32) {
+        let scale = scale.clamp(*Self::RENDER_SCALES.start(), *Self::RENDER_SCALES.end());
+        if scale != self.render_scale {
+            tracing::info!("Rendering at {scale}x");
+            self.render_scale = scale;
+            self.resize_scene();
+        }
+    }
+
+    /// The size the scene is rendered at, which is the picture's scaled by `render_scale`
+    /// as far as the device allows, see `picture_viewport`
+    pub fn render_size(&self) -> PhysicalSize<u32> {
+        let max = self.device.limits().max_texture_dimension_2d;
+        let picture = self.picture_viewport();
+        let scale = |length: f32| ((length * self.render_scale).round() as u32).clamp(1, max);
+        PhysicalSize::new(scale(picture.width), scale(picture.height))
+    }
+
+    /// Where in the window the picture is drawn: the viewport if there is one, between the letterbox's bars
+    /// if there are any, otherwise all of it
+    pub fn picture_viewport(&self) -> Viewport {
+        let (width, height) = (self.size.width, self.size.height);
+        self.viewport
+            .and_then(|viewport| viewport.clamp(width, height))
+            .or_else(|| Some(self.letterbox?.fit(width, height)))
+            .unwrap_or_else(|| Viewport::full(width, height))
+    }
+
+    pub fn viewport(&self) -> Option<Viewport> {
+        self.viewport
+    }
+
+    /// Draw the picture into only this part of the window, or all of it with `None`,
+    /// rendering the scene at its aspect ratio. This takes the place of the letterbox while it's set
+    pub fn set_viewport(&mut self, viewport: Option<Viewport>) {
+        self.viewport = viewport;
+        self.update_output_region();
+    }
+
+    pub fn scissor_rect(&self) -> Option<ScissorRect> {
+        self.scissor_rect
+    }
+
+    /// Only draw the picture inside of this part of the window, or everywhere with `None`.
+    /// Unlike the viewport this cuts the picture off rather than squeezing it in, and leaves the scene as it is
+    pub fn set_scissor_rect(&mut self, scissor_rect: Option<ScissorRect>) {
+        self.scissor_rect = scissor_rect;
+        self.update_output_region();
+    }
+
+    pub fn letterbox(&self) -> Option<Letterbox> {
+        self.letterbox
+    }
+
+    /// Keep the picture at the letterbox's aspect ratio whatever shape the window is, or fill it with `None`
+    pub fn set_letterbox(&mut self, letterbox: Option<Letterbox>) {
+        self.letterbox = letterbox;
+        self.update_output_region();
+    }
+
+    /// Fit the picture to the window again after it, the viewport, the scissor rectangle or the letterbox changed
+    fn update_output_region(&mut self) {
+        let picture = self.picture_viewport();
+        let (width, height) = (self.size.width, self.size.height);
+        self.post_process.set_output_region(OutputRegion {
+            viewport: (picture != Viewport::full(width, height)).then_some(picture),
+            scissor: self
+                .scissor_rect
+                .map(|scissor_rect| scissor_rect.clamp(width, height)),
+            clear_color: self
+                .letterbox
+                .map_or(Color::BLACK, |letterbox| letterbox.color),
+        });
+        self.camera.aspect = picture.aspect();
+        self.resize_scene();
+    }
+
+    /// Adjust the render scale to keep the GPU's frame time within `dynamic_resolution`'s budget,
+    /// or stop adjusting it with `None`, leaving it where it is. This needs the GPU to be timed, see `is_gpu_timed`
+    pub fn set_dynamic_resolution(
+        &mut self,
+        dynamic_resolution: Option<DynamicResolution>,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            dynamic_resolution.is_none() || self.is_gpu_timed(),
+            "dynamic resolution needs timestamp queries, which this adapter doesn't have"
+        );
+        self.dynamic_resolution = dynamic_resolution;
+        Ok(())
+    }
+
+    /// Recreate everything the scene is rendered into at `render_size`
+    fn resize_scene(&mut self) {
+        let size = self.render_size();
+        self.post_process
+            .resize(&self.device, size.width, size.height);
+        if let Some(water) = &mut self.water {
+            water.resize(&self.device, size.width, size.height);
+        }
+        for mirror in &mut self.mirrors {
+            mirror.resize(&self.device, size.width, size.height);
+        }
+        if let Some(glass) = &mut self.glass {
+            glass.resize(&self.device, size.width, size.height);
+        }
+    }
+
+    /// Resize the text and overlays for the window's new scale factor,
+    /// e.g. when it's dragged onto a monitor with a different DPI
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        tracing::info!("The scale factor changed to {scale_factor}");
+        #[cfg(feature = "ui")]
+        if let Err(error) = self
+            .text
+            .set_scale(&self.device, &self.queue, scale_factor as f32)
         {
-            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: Operations {
-                        load: LoadOp::Clear(Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: None,
-            });
+            tracing::error!("Failed to rasterise the text at {scale_factor}x: {error:#}");
+        }
+    }
+
+    /// Configure the surface for the window's current size, checking that the format and
+    /// alpha mode it was configured with are still supported, as they can change when e.g.
+    /// the window moves to another monitor
+    pub fn configure_surface(&mut self) {
+        let Some(surface) = &self.surface else {
+            return;
+        };
+        let format = surface_format(surface, &self.adapter);
+        if format != self.config.format {
+            tracing::info!(
+                "The surface's format changed from {:?} to {format:?}",
+                self.config.format
+            );
+            self.config.format = format;
+            // everything drawing straight to the surface has to be rebuilt for the new format
+            if let Err(error) =
+                self.post_process
+                    .set_output_format(&self.device, &self.shader_library, format)
+            {
+                tracing::error!("Failed to rebuild the output pass for {format:?}: {error:#}");
+            }
+            #[cfg(feature = "ui")]
+            self.text.set_format(&self.device, format);
+        }
+        let alpha_modes = surface.get_supported_alpha_modes(&self.adapter);
+        if !alpha_modes.contains(&self.config.alpha_mode) {
+            // `Auto` is always supported, so this can only happen if another mode was picked
+            self.config.alpha_mode = CompositeAlphaMode::Auto;
+        }
+        let present_modes = surface.get_supported_present_modes(&self.adapter);
+        if !present_modes.contains(&self.config.present_mode) {
+            // every surface supports `Fifo`
+            self.config.present_mode = PresentMode::Fifo;
+        }
+        surface.configure(&self.device, &self.config);
+    }
+
+    /// Create a new surface for `window`, for when the platform has invalidated the old one,
+    /// e.g. after a driver reset
+    pub fn recreate_surface(&mut self, window: &Window) {
+        // Safety: as in `new`
+        unsafe { self.recreate_raw_surface(window) }
+    }
+
+    /// `recreate_surface`, for a window owned by something else
+    ///
+    /// # Safety
+    /// The same as `from_raw_window`
+    pub unsafe fn recreate_raw_surface<W>(&mut self, target: &W)
+    where
+        W: HasRawWindowHandle + HasRawDisplayHandle,
+    {
+        tracing::info!("Recreating the surface");
+        let surface = self.instance.create_surface(target);
+        if !self.adapter.is_surface_supported(&surface) {
+            tracing::error!("The new surface can't be presented to from the adapter");
+        }
+        self.surface = Some(surface);
+        self.configure_surface();
+    }
+
+    /// Whether the app has been suspended, in which case there's nothing to render to
+    pub fn is_suspended(&self) -> bool {
+        self.surface.is_none()
+    }
+
+    /// Drop the surface and pause the clock, for when the app is suspended.
+    /// On Android the window is destroyed, so the surface can't outlive this
+    pub fn suspend(&mut self) {
+        if self.surface.take().is_some() {
+            tracing::info!("Suspended");
+            self.clock.pause();
+        }
+    }
+
+    /// Recreate the surface for `window` and carry on from where `suspend` left off.
+    /// Redundant resumes are ignored, as every platform resumes once at startup
+    pub fn resume(&mut self, window: &Window) {
+        if self.surface.is_none() {
+            tracing::info!("Resumed");
+            // the window may have been resized in the meantime
+            let size = window.inner_size();
+            if size != self.size {
+                self.resize(size);
+            }
+            self.recreate_surface(window);
+            self.clock.resume();
+        }
+    }
+
+    /// Get ready to render again after `render` returned `error`.
+    /// Running out of memory is the only error which can't be recovered from, which is returned
+    pub fn recover(&mut self, window: &Window, error: SurfaceError) -> Result<(), SurfaceError> {
+        match error {
+            // The surface no longer matches the window, e.g. because it was resized before we noticed
+            SurfaceError::Outdated => self.configure_surface(),
+            // Reconfiguring is usually enough, but a surface which is lost again straight away
+            // has been invalidated by the platform, e.g. because the monitor was unplugged
+            SurfaceError::Lost if self.surface_lost => self.recreate_surface(window),
+            SurfaceError::Lost => {
+                self.surface_lost = true;
+                self.configure_surface();
+            }
+            // The display didn't hand over a frame in time, e.g. because the window is hidden,
+            // so skip this one
+            SurfaceError::Timeout => tracing::debug!("Timed out acquiring a frame"),
+            SurfaceError::OutOfMemory => return Err(error),
+        }
+        Ok(())
+    }
+
+    /// What the scene's meshes show in place of their shaded colour
+    pub fn debug_view(&self) -> DebugView {
+        DebugView::from_defs(&self.shader_defs)
+    }
+
+    /// Show `view` in place of the shaded scene, which is a permutation of the scene's shader,
+    /// and skips post-processing so that it reaches the output as it is
+    pub fn set_debug_view(&mut self, view: DebugView) -> anyhow::Result<()> {
+        self.set_shader_defs(view.apply(&self.shader_defs, self.camera.zfar))
+    }
+
+    /// Switch to the shader permutation described by `defs`, compiling it if necessary
+    pub fn set_shader_defs(&mut self, defs: ShaderDefs) -> anyhow::Result<()> {
+        // the material's bind group layout depends on it, so it can't be switched
+        let defs = self.material_textures.shader_defs(&defs);
+        self.pipeline_cache.prepare(&self.device, &defs)?;
+        for model in self
+            .models
+            .iter()
+            .filter(|model| !model.mesh.is_triangle_list())
+        {
+            self.pipeline_cache
+                .prepare(&self.device, &topology_defs(&defs, model.mesh.topology()))?;
+        }
+        if self.models.iter().any(|model| model.mesh.is_skinned()) {
+            self.skinned_pipeline_cache
+                .prepare(&self.device, &skinned_defs(&defs, SKIN_GROUP))?;
+        }
+        if let Some(cache) = &mut self.morphed_pipeline_cache {
+            if self.models.iter().any(|model| model.morph.is_some()) {
+                cache.prepare(&self.device, &morphed_defs(&defs, MORPH_GROUP))?;
+            }
+        }
+        if let Some(glass) = &mut self.glass {
+            glass.prepare(&self.device, &defs)?;
+        }
+        // post-processing would change what the debug view shows
+        self.post_process
+            .set_bypassed(DebugView::from_defs(&defs) != DebugView::Off);
+        self.shader_defs = defs;
+        Ok(())
+    }
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), IndexFormat::Uint16);
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+    /// Replace the shader `name` in the shader library with `source`, e.g. one edited on disk.
+    /// Only the scene's own pipelines are rebuilt straight away, which they are if `name` is
+    /// `shader.wgsl` or one of the files it includes; everything else picks it up when it's next rebuilt.
+    /// If the new scene shader doesn't compile the old one is kept, and the library is left alone
+    pub fn reload_shader(&mut self, name: &str, source: String) -> anyhow::Result<()> {
+        let mut library = self.shader_library.clone();
+        library.add(name, source);
+        let code = ShaderCode::from(library.resolve("shader.wgsl")?);
+        // wgpu panics on shaders which don't parse, so let naga find any mistakes first
+        ShaderReflection::from_code(&code, &self.shader_defs)
+            .with_context(|| format!("failed to reload {name}"))?;
+        let previous = self.pipeline_cache.set_code(code.clone());
+        self.skinned_pipeline_cache.set_code(code.clone());
+        if let Some(cache) = &mut self.morphed_pipeline_cache {
+            cache.set_code(code.clone());
+        }
+        if let Some(glass) = &mut self.glass {
+            glass.set_code(code);
         }
+        if let Err(error) = self.set_shader_defs(self.shader_defs.clone()) {
+            self.pipeline_cache.set_code(previous.clone());
+            self.skinned_pipeline_cache.set_code(previous.clone());
+            if let Some(cache) = &mut self.morphed_pipeline_cache {
+                cache.set_code(previous.clone());
+            }
+            if let Some(glass) = &mut self.glass {
+                glass.set_code(previous);
+            }
+            self.set_shader_defs(self.shader_defs.clone())?;
+            return Err(error.context(format!("failed to reload {name}")));
+        }
+        self.shader_library = library;
+        Ok(())
+    }
+
+    /// Add a mesh to the scene, drawn with `instances` from the instance buffer and the scene's texture,
+    /// returning its index
+    pub fn add_model(
+        &mut self,
+        vertices: &[Vertex],
+        indices: &[u16],
+        instances: Range<u32>,
+    ) -> usize {
+        self.add_model_with_topology(
+            vertices,
+            indices,
+            instances,
+            PrimitiveTopology::TriangleList,
+        )
+        .expect("the scene's own permutation is always compiled")
+    }
+
+    /// `add_model`, with the indices joined up as `topology` rather than as a list of triangles,
+    /// see `Mesh::with_topology`
+    pub fn add_model_with_topology(
+        &mut self,
+        vertices: &[Vertex],
+        indices: &[u16],
+        instances: Range<u32>,
+        topology: PrimitiveTopology,
+    ) -> anyhow::Result<usize> {
+        self.pipeline_cache
+            .prepare(&self.device, &topology_defs(&self.shader_defs, topology))?;
+        let mesh = Mesh::new(
+            &self.device,
+            &self.queue,
+            &mut self.vertex_pool,
+            &mut self.index_pool,
+            vertices,
+            indices,
+        )
+        .with_topology(topology);
+        let normals = NormalLines::new(&self.device, &self.queue, &mut self.vertex_pool, vertices);
+        self.models.push(Model {
+            mesh,
+            instances,
+            skin: None,
+            morph: None,
+            normals: Some(normals),
+        });
+        Ok(self.models.len() - 1)
+    }
 
-        // Submit the finished command buffer for execution
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+    /// Add a mesh to the scene where `transform` puts it, tinted `tint` and painted with the scene's texture,
+    /// returning its index. Its triangles are wound anticlockwise when seen from the front
+    pub fn add_mesh(
+        &mut self,
+        vertices: &[Vertex],
+        indices: &[u16],
+        transform: Transform,
+        tint: [f32; 3],
+    ) -> anyhow::Result<usize> {
+        if let Some(index) = indices
+            .iter()
+            .find(|&&index| index as usize >= vertices.len())
+        {
+            anyhow::bail!(
+                "index {index} is past the mesh's {} vertices",
+                vertices.len()
+            );
+        }
+        anyhow::ensure!(
+            indices.len().is_multiple_of(3),
+            "{} indices isn't a whole number of triangles",
+            indices.len()
+        );
+        let instances = self.add_instances(&[Instance {
+            transform,
+            tint,
+            roughness: 0.5,
+            metallic: 0.0,
+            emissive: [0.0; 3],
+            displacement: 0.0,
+            transmission: 0.0,
+            texture: 0,
+        }]);
+        Ok(self.add_model(vertices, indices, instances))
+    }
 
+    /// Replace the texture every model is painted with
+    pub fn set_texture(&mut self, image: &image::DynamicImage) -> anyhow::Result<()> {
+        self.material_textures.set_first(OurTexture::from_image(
+            &self.device,
+            &self.queue,
+            image,
+            Some("Diffuse Texture"),
+            TextureKind::Albedo,
+        )?);
+        self.rebuild_material();
         Ok(())
     }
+
+    /// Add a texture for instances to be painted with instead, returning the index for `Instance::texture`.
+    /// There can be up to `MaterialTextures::CAPACITY` of them, counting the one every model starts out with
+    pub fn add_texture(&mut self, image: &image::DynamicImage) -> anyhow::Result<u32> {
+        let texture = OurTexture::from_image(
+            &self.device,
+            &self.queue,
+            image,
+            Some("Material Texture"),
+            TextureKind::Albedo,
+        )?;
+        let index = self.material_textures.add(texture)?;
+        self.rebuild_material();
+        Ok(index)
+    }
+
+    fn rebuild_material(&mut self) {
+        self.diffuse_bind_groups = self.material_textures.bind_groups(|diffuse| {
+            self.device.create_bind_group(&BindGroupDescriptor {
+                label: Some("diffuse_bind_group"),
+                layout: &self.texture_bind_group_layout,
+                entries: &material_entries(
+                    diffuse,
+                    &self.material_textures.first().sampler,
+                    &self.emissive_texture,
+                    &self.height_texture,
+                    &self.parallax_uniform,
+                    &self.displacement_texture,
+                    &self.displacement_uniform,
+                    &self.displacement_sampler,
+                ),
+            })
+        });
+    }
+
+    /// Filter the texture every model is painted with, in place
+    pub fn filter_texture(&mut self, filter: ImageFilter) -> anyhow::Result<()> {
+        ImageFilters::new(&self.device, &self.shader_library, &self.capabilities)?.apply(
+            &self.device,
+            &self.queue,
+            self.material_textures.first(),
+            filter,
+        )
+    }
+
+    /// Add a skinned mesh to the scene, drawn with `instances` from the instance buffer,
+    /// returning its index for posing it through `skin`. It starts out in its bind pose
+    pub fn add_skinned_model(
+        &mut self,
+        vertices: &[SkinnedVertex],
+        indices: &[u16],
+        instances: Range<u32>,
+    ) -> anyhow::Result<usize> {
+        self.skinned_pipeline_cache
+            .prepare(&self.device, &skinned_defs(&self.shader_defs, SKIN_GROUP))?;
+        let mesh = Mesh::new_skinned(
+            &self.device,
+            &self.queue,
+            &mut self.vertex_pool,
+            &mut self.index_pool,
+            vertices,
+            indices,
+        );
+        self.models.push(Model {
+            mesh,
+            instances,
+            skin: Some(Skin::new(&self.device, &self.skin_layout)),
+            morph: None,
+            normals: None,
+        });
+        Ok(self.models.len() - 1)
+    }
+
+    /// The pose of the skinned model at `index`, which is uploaded by the next `update`
+    pub fn skin(&mut self, index: usize) -> Option<&mut Skin> {
+        self.models.get_mut(index)?.skin.as_mut()
+    }
+
+    /// Add a mesh with morph `targets` to the scene, drawn with `instances` from the instance buffer,
+    /// returning its index for setting its weights through `morph_targets`. Every weight starts at 0.
+    /// Without `Capabilities::vertex_storage` the mesh is added without its targets
+    pub fn add_morphed_model(
+        &mut self,
+        vertices: &[Vertex],
+        indices: &[u16],
+        targets: &[MorphTarget],
+        instances: Range<u32>,
+    ) -> anyhow::Result<usize> {
+        let morph = match (&mut self.morphed_pipeline_cache, &self.morph_layout) {
+            (Some(cache), Some(layout)) => {
+                cache.prepare(&self.device, &morphed_defs(&self.shader_defs, MORPH_GROUP))?;
+                Some(MorphTargets::new(
+                    &self.device,
+                    layout,
+                    vertices.len(),
+                    targets,
+                )?)
+            }
+            _ => {
+                tracing::warn!(
+                    "Morph targets aren't supported, the mesh will be drawn without them"
+                );
+                None
+            }
+        };
+        let mesh = Mesh::new(
+            &self.device,
+            &self.queue,
+            &mut self.vertex_pool,
+            &mut self.index_pool,
+            vertices,
+            indices,
+        );
+        let normals = NormalLines::new(&self.device, &self.queue, &mut self.vertex_pool, vertices);
+        self.models.push(Model {
+            mesh,
+            instances,
+            skin: None,
+            morph,
+            normals: Some(normals),
+        });
+        Ok(self.models.len() - 1)
+    }
+
+    /// The morph targets of the model at `index`, for setting or animating their weights,
+    /// which are uploaded by the next `update`
+    pub fn morph_targets(&mut self, index: usize) -> Option<&mut MorphTargets> {
+        self.models.get_mut(index)?.morph.as_mut()
+    }
+
+    /// Drop another cube into the scene where `transform` puts it, painted like one of the first cubes,
+    /// returning its body. Only while there's physics for it to fall with
+    #[cfg(feature = "physics")]
+    pub fn spawn_cube(
+        &mut self,
+        transform: Transform,
+    ) -> Option<rapier3d::prelude::RigidBodyHandle> {
+        let physics = self.physics.as_mut()?;
+        let cubes = &mut self.models[self.cube_model].instances;
+        // cycling through the cubes already there
+        let painted_like = cubes.start as usize + physics.len() % cubes.len();
+        let cube = Instance {
+            transform,
+            ..self.instances[painted_like].clone()
+        };
+        if cubes.end as usize != self.instances.len() {
+            tracing::warn!("Can't spawn a cube, as other instances were added after the cubes");
+            return None;
+        }
+        let handle = physics.add_cuboid(self.instances.len(), &cube, Vector3::new(1.0, 1.0, 1.0));
+        cubes.end += 1;
+        self.add_instances(&[cube]);
+        Some(handle)
+    }
+
+    /// Add `instances` to the end of the instance buffer, returning where they are in it
+    /// for drawing a model with, e.g. with `add_model`
+    pub fn add_instances(&mut self, instances: &[Instance]) -> Range<u32> {
+        let start = self.instances.len() as u32;
+        self.instances.extend_from_slice(instances);
+        // appearing out of nowhere rather than moving
+        self.previous_models.extend(
+            instances
+                .iter()
+                .map(|instance| instance.transform.to_matrix()),
+        );
+        // the old buffer is too small, and the GPU is done with it by the time the new one's written
+        let old = std::mem::replace(
+            &mut self.instance_buffer,
+            self.vertex_pool.allocate(
+                &self.device,
+                (self.instances.len() * std::mem::size_of::<InstanceRaw>()) as u64,
+            ),
+        );
+        self.vertex_pool.free(old);
+        self.write_instances();
+        start..self.instances.len() as u32
+    }
+
+    /// Every instance in the scene, in the order they were added, for moving or repainting them.
+    /// Changes are uploaded by the next `update`
+    pub fn instances_mut(&mut self) -> &mut [Instance] {
+        self.instances_changed = true;
+        &mut self.instances
+    }
+
+    /// Move the instance at `index` to `transform` without it being blurred on its way there, as it would be
+    /// if it was moved through `instances_mut`. Uploaded by the next `update`
+    pub fn teleport_instance(&mut self, index: usize, transform: Transform) {
+        self.previous_models[index] = transform.to_matrix();
+        self.instances[index].transform = transform;
+        self.instances_changed = true;
+    }
+
+    /// Upload `instances` into the instance buffer, along with where each was last frame
+    fn write_instances(&self) {
+        let instances = self
+            .instances
+            .iter()
+            .zip(&self.previous_models)
+            .map(|(instance, previous)| instance.to_raw_moved_from(previous))
+            .collect::<Vec<_>>();
+        self.vertex_pool.write(
+            &self.queue,
+            &self.instance_buffer,
+            bytemuck::cast_slice(&instances),
+        );
+    }
+
+    /// The depth of field effect, e.g. for changing the focus distance
+    pub fn depth_of_field(&mut self) -> &mut DepthOfField {
+        self.post_process
+            .effect_mut()
+            .expect("depth of field is added to the stack in `new`")
+    }
+
+    /// The bloom effect, e.g. for turning it on to make emissive surfaces glow
+    pub fn bloom(&mut self) -> &mut Bloom {
+        self.post_process
+            .effect_mut()
+            .expect("bloom is added to the stack in `new`")
+    }
+
+    /// The motion blur effect, e.g. for changing the shutter amount
+    pub fn motion_blur(&mut self) -> &mut MotionBlur {
+        self.post_process
+            .effect_mut()
+            .expect("motion blur is added to the stack in `new`")
+    }
+
+    /// The adapter we're running on and what the device was created with, e.g. for bug reports
+    pub fn gpu_info(&self) -> &GpuInfo {
+        &self.gpu_info
+    }
+
+    /// The time of the frame being rendered, as of the last `update`
+    pub fn time(&self) -> FrameTime {
+        self.time
+    }
+
+    /// The optional features the renderer can use on this device
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// The ground, if it's terrain rather than the flat floor, e.g. for finding how high it is somewhere
+    pub fn terrain(&self) -> Option<&Terrain> {
+        self.terrain.as_ref()
+    }
+
+    /// The water surface, if there is one, e.g. for changing its level or colour
+    pub fn water(&mut self) -> Option<&mut Water> {
+        self.water.as_mut()
+    }
+
+    /// The mirrors in the scene, e.g. for moving them around
+    pub fn mirrors(&mut self) -> &mut [Mirror] {
+        &mut self.mirrors
+    }
+
+    /// The point cloud, if one was loaded, e.g. for changing how big its points are
+    pub fn point_cloud(&mut self) -> Option<&mut PointCloud> {
+        self.point_cloud.as_mut()
+    }
+
+    /// What outlines instances, e.g. for picking out which ones are outlined
+    pub fn outline(&mut self) -> &mut Outline {
+        &mut self.outline
+    }
+
+    /// A sphere around every outlined instance, which are what's selected, or `None` if none are
+    pub fn selection_bounds(&self) -> Option<Sphere> {
+        self.outline
+            .instances()
+            .iter()
+            .filter_map(|&index| {
+                let model = self
+                    .models
+                    .iter()
+                    .find(|model| model.instances.contains(&index))?;
+                let instance = self.instances.get(index as usize)?;
+                Some(model.mesh.bounds().transformed(&instance.transform))
+            })
+            .reduce(|bounds, sphere| bounds.merge(&sphere))
+    }
+
+    /// The boxes around every instance of every model, in world space, which the camera can't move into
+    pub fn obstacles(&self) -> Vec<Aabb> {
+        self.models
+            .iter()
+            .flat_map(|model| {
+                let aabb = model.mesh.aabb();
+                self.instances[model.instances.start as usize..model.instances.end as usize]
+                    .iter()
+                    .map(move |instance| aabb.transformed(&instance.transform))
+            })
+            .collect()
+    }
+
+    /// The instances whose boxes overlap the rectangle with corners `a` and `b` on screen, by their index
+    /// in the instance buffer. The corners are in physical pixels from the top left of the window
+    pub fn instances_in_rect(&self, a: [f32; 2], b: [f32; 2]) -> Vec<u32> {
+        let rect = ScreenRect::from_corners(self.window_to_ndc(a), self.window_to_ndc(b));
+        let view_proj = self.camera.build_view_projection_matrix();
+        self.models
+            .iter()
+            .flat_map(|model| {
+                let aabb = model.mesh.aabb();
+                model.instances.clone().filter(move |&index| {
+                    let instance = &self.instances[index as usize];
+                    ScreenRect::around_aabb(&aabb.transformed(&instance.transform), &view_proj)
+                        .is_some_and(|bounds| bounds.intersects(&rect))
+                })
+            })
+            .collect()
+    }
+
+    /// Where `position`, in physical pixels from the top left of the window, is in the picture
+    /// in normalised device co-ordinates from -1 to 1 with +y up
+    pub fn window_to_ndc(&self, [x, y]: [f32; 2]) -> [f32; 2] {
+        let picture = self.picture_viewport();
+        [
+            (x - picture.x) / picture.width * 2.0 - 1.0,
+            1.0 - (y - picture.y) / picture.height * 2.0,
+        ]
+    }
+
+    /// Glide the camera over to frame the selection, returning whether anything's selected to focus on
+    pub fn focus_selection(&mut self) -> bool {
+        let Some(bounds) = self.selection_bounds() else {
+            return false;
+        };
+        self.set_walking(false);
+        self.camera_controller.focus(&self.camera, bounds);
+        true
+    }
+
+    /// Whether the camera's walking round the scene at eye level, rather than orbiting
+    pub fn is_walking(&self) -> bool {
+        self.walk.is_some()
+    }
+
+    /// Start walking round the scene from where the camera is, looking the same way, or go back to orbiting.
+    /// The camera's target ends up just in front of it, so orbiting afterwards turns it on the spot
+    pub fn set_walking(&mut self, walking: bool) {
+        if walking != self.is_walking() {
+            // whichever controller isn't in use doesn't see keys being let go,
+            // and the walker starts afresh each time
+            self.camera_controller.release_all();
+            self.walk = walking.then(|| WalkController::new(&self.camera));
+        }
+    }
+
+    /// What draws models' normals, e.g. for changing how long the lines are
+    pub fn normal_view(&mut self) -> &mut NormalView {
+        &mut self.normal_view
+    }
+
+    /// Show or hide the normals of the model at `index`, returning whether it has any to show,
+    /// which skinned models don't
+    pub fn set_normals_shown(&mut self, index: usize, shown: bool) -> bool {
+        let Some(normals) = self
+            .models
+            .get_mut(index)
+            .and_then(|model| model.normals.as_mut())
+        else {
+            return false;
+        };
+        normals.shown = shown;
+        true
+    }
+
+    /// What draws the glass, e.g. for changing how it bends light. Any instance can be made glass
+    /// through `Instance::transmission`
+    pub fn glass(&mut self) -> Option<&mut Glass> {
+        self.glass.as_mut()
+    }
+
+    /// The eyes the scene is drawn for side by side, if it's drawn in stereo, e.g. for changing how far apart they are
+    pub fn stereo(&mut self) -> Option<&mut Stereo> {
+        self.stereo.as_mut()
+    }
+
+    /// The wind the vegetation sways in, if there's any vegetation, changes take effect from the next `update`
+    pub fn wind(&mut self) -> Option<&mut Wind> {
+        Some(self.vegetation.as_mut()?.wind())
+    }
+
+    /// The camera, which the camera controller moves during `update`
+    pub fn camera(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    /// What stops the camera moving into things, e.g. for turning it off or changing how close it gets
+    pub fn camera_collision(&mut self) -> &mut CameraCollision {
+        &mut self.camera_collision
+    }
+
+    /// The point light lighting the scene, changes take effect from the next `update`
+    pub fn light(&mut self) -> &mut PointLight {
+        &mut self.light
+    }
+
+    /// The sky, if there is one, e.g. for moving the sun
+    pub fn sky(&mut self) -> Option<&mut Sky> {
+        self.sky.as_mut()
+    }
+
+    /// The time of day, if the sun is moving through one, which overrides the sun's position
+    pub fn time_of_day(&mut self) -> Option<&mut TimeOfDay> {
+        self.time_of_day.as_mut()
+    }
+
+    /// The distance fog, changes take effect from the next `update`.
+    /// While there's a sky its colour follows the sky's horizon
+    pub fn fog(&mut self) -> &mut Fog {
+        &mut self.fog
+    }
+
+    /// How the displacement map moves, if there is one, changes take effect from the next `update`
+    pub fn displacement(&mut self) -> Option<&mut Displacement> {
+        self.displacement.as_mut()
+    }
+
+    /// How deep the height map looks, if there is one, changes take effect from the next `update`
+    pub fn parallax(&mut self) -> Option<&mut Parallax> {
+        self.parallax.as_mut()
+    }
+
+    /// The colour grading effect, e.g. for swapping LUTs or changing the blend factor
+    pub fn color_grading(&mut self) -> &mut ColorGrading {
+        self.post_process
+            .effect_mut()
+            .expect("colour grading is added to the stack in `new`")
+    }
+
+    /// The vignette effect, e.g. for changing how dark the corners get
+    pub fn vignette(&mut self) -> &mut Vignette {
+        self.post_process
+            .effect_mut()
+            .expect("the vignette is added to the stack in `new`")
+    }
+
+    /// The film grain effect, e.g. for changing the strength of the noise
+    pub fn film_grain(&mut self) -> &mut FilmGrain {
+        self.post_process
+            .effect_mut()
+            .expect("film grain is added to the stack in `new`")
+    }
+
+    /// The anaglyph effect, e.g. for changing how much colour is kept. It's only used for `StereoOutput::Anaglyph`
+    pub fn anaglyph(&mut self) -> &mut Anaglyph {
+        self.post_process
+            .effect_mut()
+            .expect("the anaglyph is added to the stack in `new`")
+    }
+
+    /// The tonemapping effect, e.g. for switching between manual and auto exposure
+    pub fn tonemapping(&mut self) -> &mut Tonemapping {
+        self.post_process
+            .effect_mut()
+            .expect("tonemapping is added to the stack in `new`")
+    }
+
+    /// The chromatic aberration effect, e.g. for changing how far apart the colours are split
+    pub fn chromatic_aberration(&mut self) -> &mut ChromaticAberration {
+        self.post_process
+            .effect_mut()
+            .expect("chromatic aberration is added to the stack in `new`")
+    }
+
+    /// The log console overlay, e.g. for filtering which records it shows
+    #[cfg(feature = "ui")]
+    pub fn log_console(&mut self) -> &mut LogConsole {
+        &mut self.log_console
+    }
+
+    /// The profiler overlay, e.g. for showing it without a keyboard
+    #[cfg(feature = "ui")]
+    pub fn profiler(&mut self) -> &mut ProfilerOverlay {
+        &mut self.profiler
+    }
+
+    pub fn recorder(&mut self) -> &mut Recorder {
+        &mut self.recorder
+    }
+
+    /// Whether `gpu_frame_times` has anything to return, which needs `Capabilities::timestamp_query`
+    pub fn is_gpu_timed(&self) -> bool {
+        self.gpu_timer.is_some()
+    }
+
+    /// The GPU time in milliseconds of every frame read back since the last call, by the frame's number.
+    /// They're read back a few frames late, and not every frame is timed if the GPU falls behind
+    pub fn gpu_frame_times(&mut self) -> Vec<(u64, f64)> {
+        std::mem::take(&mut self.gpu_times)
+    }
+
+    /// Whether `fragment_counts` has anything to return, which needs `Capabilities::pipeline_statistics`
+    pub fn has_fragment_stats(&self) -> bool {
+        self.fragment_stats.is_some()
+    }
+
+    /// How many fragments each object shaded in the latest frame read back, which is a few frames late.
+    /// Comparing them with the number of pixels shows how much overdraw each contributes.
+    /// Nothing's counted while the scene is drawn in stereo
+    pub fn fragment_counts(&self) -> &[(DrawnObject, u64)] {
+        &self.fragment_counts
+    }
+
+    /// Where to set a callback for every frame's pixels, e.g. for streaming them
+    pub fn frame_stream(&mut self) -> &mut FrameStream {
+        &mut self.frame_stream
+    }
+
+    /// Where to add compute work of the app's own, which is submitted apart from each frame, ahead of it
+    pub fn async_compute(&mut self) -> &mut AsyncCompute {
+        &mut self.async_compute
+    }
+
+    /// Block until the last frame's async compute has finished, see `AsyncCompute::wait`
+    pub fn wait_for_async_compute(&self) {
+        self.async_compute.wait(&self.device);
+    }
+
+    /// Outline the rectangle being dragged out with `debug_draw`, just in front of the near plane
+    /// so that nothing hides it
+    fn draw_marquee(&mut self, start: [f32; 2], end: [f32; 2]) {
+        let Some(inverse) = self.camera.build_view_projection_matrix().invert() else {
+            return;
+        };
+        let corner = |x: f32, y: f32| {
+            let [x, y] = self.window_to_ndc([x, y]);
+            // not quite on the near plane, where it could be clipped
+            Point3::from_homogeneous(inverse * Vector4::new(x, y, 1e-4, 1.0))
+        };
+        let corners = [
+            corner(start[0], start[1]),
+            corner(end[0], start[1]),
+            corner(end[0], end[1]),
+            corner(start[0], end[1]),
+        ];
+        for (index, &from) in corners.iter().enumerate() {
+            self.debug_draw
+                .line(from, corners[(index + 1) % 4], Self::MARQUEE_COLOR);
+        }
+    }
+
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        #[cfg(feature = "ui")]
+        if self.log_console.process_events(event) || self.profiler.process_events(event) {
+            return true;
+        }
+        if self.recorder.process_events(event) {
+            return true;
+        }
+        #[cfg(feature = "renderdoc")]
+        if let Some(gpu_capture) = &mut self.gpu_capture {
+            if gpu_capture.process_events(event) {
+                return true;
+            }
+        }
+        if let Some(time_of_day) = &mut self.time_of_day {
+            if time_of_day.process_events(event) {
+                return true;
+            }
+        }
+        if self.marquee.process_events(event) {
+            if let Some((start, end)) = self.marquee.take_finished() {
+                let selected = self.instances_in_rect(start, end);
+                tracing::info!("Outlined {} instances", selected.len());
+                self.outline.set_instances(selected);
+            }
+            return true;
+        }
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::G),
+                        ..
+                    },
+                ..
+            } => {
+                self.show_gizmos = !self.show_gizmos;
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::V),
+                        ..
+                    },
+                ..
+            } => {
+                let view = self.debug_view().next();
+                match self.set_debug_view(view) {
+                    Ok(()) => tracing::info!("Debug view: {view:?}"),
+                    Err(error) => tracing::error!("Failed to switch debug view: {error:#}"),
+                }
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::Tab),
+                        ..
+                    },
+                ..
+            } => {
+                self.set_walking(!self.is_walking());
+                tracing::info!(
+                    "{}",
+                    if self.is_walking() {
+                        "Walking"
+                    } else {
+                        "Orbiting"
+                    }
+                );
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F),
+                        ..
+                    },
+                ..
+            } => {
+                if !self.focus_selection() {
+                    tracing::info!("Nothing is outlined to focus on");
+                }
+                true
+            }
+            #[cfg(feature = "physics")]
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::C),
+                        ..
+                    },
+                ..
+            } if self.physics.is_some() => {
+                // Each one a little further round and tipped a little more, so that they tumble into a pile
+                let count = self.instances.len() as f32;
+                let translation = Vector3::new((count * 2.4).cos(), 8.0, (count * 2.4).sin());
+                let rotation = Quaternion::from_angle_y(Deg(count * 53.0))
+                    * Quaternion::from_angle_x(Deg(count * 37.0));
+                self.spawn_cube(Transform::from_translation(translation).with_rotation(rotation));
+                true
+            }
+            _ => match &mut self.walk {
+                Some(walk) => walk.process_events(event),
+                None => self.camera_controller.process_events(event),
+            },
+        }
+    }
+
+    /// Start a new frame, returning how far it is from the last. `update` moves everything along by it
+    pub fn tick(&mut self) -> FrameTime {
+        self.time = self.clock.tick();
+        self.time
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub fn update(&mut self) {
+        puffin::profile_function!();
+        #[cfg(feature = "renderdoc")]
+        if let Some(gpu_capture) = &mut self.gpu_capture {
+            gpu_capture.poll();
+        }
+        let elapsed = self.time.elapsed;
+        self.film_grain().set_time(elapsed);
+        let delta = self.time.delta;
+        self.tonemapping().set_delta_time(delta);
+        #[cfg(feature = "physics")]
+        if let Some(physics) = &mut self.physics {
+            if physics.step(self.time.delta) > 0 {
+                physics.sync(&mut self.instances);
+                self.instances_changed = true;
+            }
+        }
+        if std::mem::take(&mut self.instances_changed) || self.instances_moving {
+            self.write_instances();
+            // where they are now is where they were, come the next frame
+            self.instances_moving = false;
+            for (previous, instance) in self.previous_models.iter_mut().zip(&self.instances) {
+                let model = instance.transform.to_matrix();
+                self.instances_moving |= *previous != model;
+                *previous = model;
+            }
+        }
+        let eye = self.camera.eye;
+        let obstacles = self.obstacles();
+        let terrain = self.terrain.as_ref();
+        let ground = |x, z| terrain.and_then(|terrain: &Terrain| terrain.height_at(x, z));
+        if let Some(walk) = &mut self.walk {
+            walk.update_camera(&mut self.camera, self.time.delta, &obstacles, ground);
+        } else {
+            self.camera_controller
+                .update_camera(&mut self.camera, self.time.delta);
+            self.camera.eye =
+                self.camera_collision
+                    .resolve(eye, self.camera.eye, &obstacles, ground);
+        }
+        self.camera_uniform.get_mut().update_view_proj(&self.camera);
+        self.camera_uniform.write(&self.queue);
+        self.light_uniform.get_mut().update(&self.light);
+        self.light_uniform.write(&self.queue);
+        if let Some(sky) = &mut self.sky {
+            if let Some(time_of_day) = &mut self.time_of_day {
+                time_of_day.advance(self.time.delta);
+                time_of_day.place_sun(sky.sun_mut());
+            }
+            sky.update(&self.queue);
+            self.fog.color = sky.horizon_color();
+            self.directional_light_uniform
+                .get_mut()
+                .update(&sky.light());
+            self.directional_light_uniform.write(&self.queue);
+        }
+        self.fog_uniform.get_mut().update(&self.fog);
+        self.fog_uniform.write(&self.queue);
+        self.parallax_uniform
+            .get_mut()
+            .update(&self.parallax.unwrap_or(Parallax::OFF));
+        self.parallax_uniform.write(&self.queue);
+        self.displacement_uniform
+            .get_mut()
+            .update(&self.displacement.unwrap_or_default(), elapsed);
+        self.displacement_uniform.write(&self.queue);
+        self.shadow_map.update(&self.queue, &self.light);
+        if let Some(water) = &mut self.water {
+            water.update(&self.queue, &self.camera, elapsed, &self.clip_planes);
+        }
+        for mirror in &mut self.mirrors {
+            mirror.update(&self.queue, &self.camera, &self.clip_planes);
+        }
+        if let Some(point_cloud) = &mut self.point_cloud {
+            point_cloud.update(&self.queue);
+        }
+        if let Some(stereo) = &mut self.stereo {
+            stereo.update(&self.queue, &self.camera, &self.clip_planes);
+        }
+        self.outline.update(&self.queue);
+        self.normal_view.update(&self.queue);
+        if let Some(glass) = &mut self.glass {
+            // the eyes are side by side in the scene, whether or not they're combined afterwards
+            let view_scale = if self.stereo.is_some() {
+                [0.5, 1.0]
+            } else {
+                [1.0, 1.0]
+            };
+            glass.update(&self.queue, view_scale);
+        }
+        let anaglyph = self
+            .stereo
+            .as_ref()
+            .is_some_and(|stereo| stereo.settings().output == StereoOutput::Anaglyph);
+        self.anaglyph().enabled = anaglyph;
+        if let Some(vegetation) = &mut self.vegetation {
+            vegetation.update(&self.queue, elapsed);
+        }
+        for skin in self
+            .models
+            .iter_mut()
+            .filter_map(|model| model.skin.as_mut())
+        {
+            skin.write(&self.queue);
+        }
+        for morph in self
+            .models
+            .iter_mut()
+            .filter_map(|model| model.morph.as_mut())
+        {
+            morph.animate(elapsed);
+            morph.write(&self.queue);
+        }
+
+        self.debug_draw.clear();
+        if self.show_gizmos {
+            self.light.draw_gizmo(&mut self.debug_draw);
+        }
+        if let Some((start, end)) = self.marquee.dragging() {
+            self.draw_marquee(start, end);
+        }
+        self.debug_draw.prepare(&self.device, &self.queue);
+
+        #[cfg(feature = "ui")]
+        {
+            self.text.clear();
+            self.log_console
+                .draw(&mut self.text, self.config.width, self.config.height);
+            let targets = self.post_process.scene();
+            self.profiler.set_fragments(
+                &self.fragment_counts,
+                targets.width as u64 * targets.height as u64,
+            );
+            self.profiler
+                .draw(&mut self.text, self.config.width, self.config.height);
+            self.recorder.draw(&mut self.text, self.config.width);
+            self.text.prepare(
+                &self.device,
+                &self.queue,
+                self.config.width,
+                self.config.height,
+            );
+        }
+    }
+
+    /// Whether a debug view is showing, which leaves out whatever isn't drawn with the scene's shader:
+    /// the sky, terrain, vegetation, point cloud, water and mirrors
+    fn debugging(&self) -> bool {
+        self.debug_view() != DebugView::Off
+    }
+
+    /// What the scene's passes draw, borrowed so that they can be recorded on other threads
+    fn scene(&self) -> Scene<'_> {
+        Scene {
+            models: &self.models,
+            sky: self.sky.as_ref(),
+            terrain: self.terrain.as_ref(),
+            water: self.water.as_ref(),
+            mirrors: &self.mirrors,
+            point_cloud: self.point_cloud.as_ref(),
+            vegetation: self.vegetation.as_ref(),
+            stereo: self.stereo.as_ref(),
+            camera: &self.camera,
+            camera_bind_group: &self.camera_bind_group,
+            fragment_stats: self.fragment_stats.as_ref(),
+            diffuse_bind_groups: &self.diffuse_bind_groups,
+            material_textures: &self.material_textures,
+            instances: &self.instances,
+            light_bind_group: &self.light_bind_group,
+            vertex_pool: &self.vertex_pool,
+            index_pool: &self.index_pool,
+            instance_buffer: &self.instance_buffer,
+            pipeline_cache: &self.pipeline_cache,
+            skinned_pipeline_cache: &self.skinned_pipeline_cache,
+            morphed_pipeline_cache: self.morphed_pipeline_cache.as_ref(),
+            shader_defs: &self.shader_defs,
+            outline: &self.outline,
+            normal_view: &self.normal_view,
+            debug_draw: &self.debug_draw,
+            targets: self.post_process.scene(),
+            target_view: self.post_process.scene_view(),
+            target_texture: self.post_process.scene_texture(),
+            background: self.background,
+            debugging: self.debugging(),
+        }
+    }
+
+    /// How many threads the frame's passes are recorded on, see `set_encoding_threads`
+    pub fn encoding_threads(&self) -> usize {
+        self.jobs.threads()
+    }
+
+    /// Record the shadow map, reflections and scene on up to `threads` threads from the next frame,
+    /// counting the one rendering, where 1 records them all on it.
+    /// Spreading them out only pays off once there's a lot to draw, as the threads are started every frame
+    pub fn set_encoding_threads(&mut self, threads: usize) {
+        self.jobs = JobSystem::new(threads);
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub fn render(&mut self) -> Result<(), SurfaceError> {
+        self.render_with(|_| ())
+    }
+
+    /// `render`, letting `custom` draw over the top of the post-processed scene
+    pub fn render_with(
+        &mut self,
+        custom: impl FnOnce(&mut FrameContext),
+    ) -> Result<(), SurfaceError> {
+        puffin::profile_function!();
+        let Some(surface) = &self.surface else {
+            // suspended, there's nowhere to render to
+            return Ok(());
+        };
+        let output = {
+            // this is where we wait for the display when vsync is on
+            puffin::profile_scope!("acquire");
+            surface.get_current_texture()?
+        };
+        self.surface_lost = false;
+        let view = output
+            .texture
+            .create_view(&TextureViewDescriptor::default());
+        self.draw_frame(&view, custom);
+        {
+            puffin::profile_scope!("present");
+            output.present();
+        }
+
+        Ok(())
+    }
+
+    /// Render a frame into `view` rather than the surface, see `with_device`.
+    /// The frame has been submitted by the time this returns, so the caller can sample `view`
+    /// in its own commands straight away
+    pub fn render_to(&mut self, view: &TextureView) {
+        puffin::profile_function!();
+        self.draw_frame(view, |_| ());
+    }
+
+    /// Record and submit everything which goes into a frame, finishing with `view`.
+    /// The shadow map, each reflection and the scene don't depend on each other while they're recorded,
+    /// so each goes into a command buffer of its own through `jobs`, submitted in order between
+    /// what's recorded here before and after them. `async_compute`'s workloads go in a submission of their own first
+    fn draw_frame(&mut self, view: &TextureView, custom: impl FnOnce(&mut FrameContext)) {
+        self.async_compute.submit(&self.device, &self.queue);
+        let mut encoder = command_encoder(&self.device, "Render Encoder");
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            gpu_timer.begin(&mut encoder, self.time.frame);
+        }
+        if let Some(fragment_stats) = &mut self.fragment_stats {
+            fragment_stats.begin(self.time.frame);
+        }
+        // The environment map has to be rendered before the scene which samples it, though only when the sky has changed
+        self.environment_map
+            .update(&mut encoder, self.sky.as_ref(), self.background);
+        let before = encoder.finish();
+
+        // Glass is only drawn once the opaque scene is, in a pass of its own as it has to see what's behind it.
+        // Without any, everything goes in the one pass
+        let glass = self.glass.as_ref().filter(|_| {
+            self.instances
+                .iter()
+                .any(|instance| instance.transmission > 0.0)
+        });
+        let scene = self.scene();
+        let device = &self.device;
+        let shadow_map = &self.shadow_map;
+        let mut jobs = Vec::<Job<CommandBuffer>>::new();
+        // As does the shadow map
+        jobs.push(Box::new(move || {
+            let mut encoder = command_encoder(device, "Shadow Encoder");
+            shadow_map.render(
+                &mut encoder,
+                scene.models,
+                scene.terrain,
+                scene.vertex_pool.slice(scene.instance_buffer),
+                scene.vertex_pool,
+                scene.index_pool,
+            );
+            encoder.finish()
+        }));
+        // And the water's reflection, which is the scene again as seen from under the water
+        if let Some(water) = scene.water.filter(|_| !scene.debugging) {
+            jobs.push(Box::new(move || {
+                puffin::profile_scope!("water reflection pass");
+                let mut encoder = command_encoder(device, "Water Reflection Encoder");
+                let planar_reflection = water.planar_reflection();
+                let mut render_pass = planar_reflection.begin_pass(&mut encoder, scene.background);
+                scene.draw_scene(
+                    &mut render_pass,
+                    planar_reflection.camera_bind_group(),
+                    planar_reflection.view_proj(),
+                    &mut FragmentCounter::disabled(),
+                );
+                drop(render_pass);
+                encoder.finish()
+            }));
+        }
+        // And the mirrors', the same way
+        for planar_reflection in scene
+            .mirrors
+            .iter()
+            .filter(|_| !scene.debugging)
+            .filter_map(|mirror| mirror.planar_reflection(scene.camera))
+        {
+            jobs.push(Box::new(move || {
+                puffin::profile_scope!("mirror reflection pass");
+                let mut encoder = command_encoder(device, "Mirror Reflection Encoder");
+                let mut render_pass = planar_reflection.begin_pass(&mut encoder, scene.background);
+                scene.draw_scene(
+                    &mut render_pass,
+                    planar_reflection.camera_bind_group(),
+                    planar_reflection.view_proj(),
+                    &mut FragmentCounter::disabled(),
+                );
+                drop(render_pass);
+                encoder.finish()
+            }));
+        }
+        jobs.push(Box::new(move || {
+            let mut encoder = command_encoder(device, "Scene Encoder");
+            // Each eye would count the same objects again, so only the one view is counted
+            let mut counter = scene
+                .fragment_stats
+                .filter(|_| scene.stereo.is_none())
+                .map_or_else(FragmentCounter::disabled, FragmentStats::counter);
+            // `encoder.begin_render_pass()` takes a mutable reference to `encoder`
+            // which we want to drop once we're done with, hence the block expression
+            {
+                puffin::profile_scope!("scene pass");
+                let mut render_pass = scene.begin_pass(&mut encoder, "Render Pass", true);
+                scene.for_each_view(
+                    &mut render_pass,
+                    |render_pass, camera_bind_group, view_proj| {
+                        scene.draw_opaque(render_pass, camera_bind_group, view_proj, &mut counter);
+                        if glass.is_none() {
+                            scene.draw_overlaid(render_pass, camera_bind_group, &mut counter);
+                        }
+                    },
+                );
+            }
+            if let Some(glass) = glass {
+                puffin::profile_scope!("glass pass");
+                glass.copy_opaque_scene(&mut encoder, scene.target_texture);
+                let mut render_pass = scene.begin_pass(&mut encoder, "Glass Pass", false);
+                scene.for_each_view(&mut render_pass, |render_pass, camera_bind_group, _| {
+                    scene.draw_glass(render_pass, glass, camera_bind_group, &mut counter);
+                    scene.draw_overlaid(render_pass, camera_bind_group, &mut counter);
+                });
+            }
+            counter.finish(&mut encoder);
+            encoder.finish()
+        }));
+        let passes = self.jobs.run(jobs);
+
+        let mut encoder = command_encoder(&self.device, "Post-process Encoder");
+        self.post_process.render(&self.queue, &mut encoder, view);
+        {
+            puffin::profile_scope!("custom");
+            custom(&mut FrameContext {
+                device: &self.device,
+                queue: &self.queue,
+                encoder: &mut encoder,
+                view,
+                format: self.config.format,
+                size: PhysicalSize::new(self.config.width, self.config.height),
+                view_proj: self.camera.build_view_projection_matrix(),
+            });
+        }
+        self.recorder.capture(
+            &self.device,
+            &mut encoder,
+            &self.post_process,
+            self.config.format,
+            self.config.width,
+            self.config.height,
+        );
+        self.frame_stream.capture(
+            &self.device,
+            &mut encoder,
+            &self.post_process,
+            self.config.format,
+            self.config.width,
+            self.config.height,
+        );
+        // Drawn after post-processing, so that the text isn't blurred or graded along with the scene
+        #[cfg(feature = "ui")]
+        self.text.render(&mut encoder, view);
+
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            gpu_timer.end(&mut encoder);
+        }
+
+        // Submit the finished command buffers for execution, in the order they were recorded in
+        {
+            puffin::profile_scope!("submit");
+            self.queue.submit(
+                std::iter::once(before)
+                    .chain(passes)
+                    .chain(std::iter::once(encoder.finish())),
+            );
+        }
+        self.recorder.finish_frame(&self.device);
+        self.frame_stream.finish_frame(&self.device);
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            let times = gpu_timer.read(&self.device);
+            let scale = self
+                .dynamic_resolution
+                .as_mut()
+                .and_then(|dynamic_resolution| {
+                    for &(_, gpu_ms) in &times {
+                        dynamic_resolution.record(gpu_ms);
+                    }
+                    dynamic_resolution.adjust(self.render_scale)
+                });
+            if let Some(scale) = scale {
+                self.set_render_scale(scale);
+            }
+            self.gpu_times.extend(times);
+            // nobody's taking them, e.g. when rendering offscreen
+            let excess = self.gpu_times.len().saturating_sub(Self::MAX_GPU_TIMES);
+            self.gpu_times.drain(..excess);
+        }
+        if let Some(fragment_stats) = &mut self.fragment_stats {
+            if let Some((_, counts)) = fragment_stats.read(&self.device) {
+                self.fragment_counts = counts;
+            }
+        }
+    }
+}
+
+/// Everything the scene's passes draw, borrowed from `State`, which can't be shared between threads
+/// itself, so that they can be recorded on any of them
+#[derive(Clone, Copy)]
+struct Scene<'a> {
+    models: &'a [Model],
+    sky: Option<&'a Sky>,
+    terrain: Option<&'a Terrain>,
+    water: Option<&'a Water>,
+    mirrors: &'a [Mirror],
+    point_cloud: Option<&'a PointCloud>,
+    vegetation: Option<&'a Vegetation>,
+    stereo: Option<&'a Stereo>,
+    camera: &'a Camera,
+    camera_bind_group: &'a BindGroup,
+    /// Only set while the frame's fragments are being counted
+    fragment_stats: Option<&'a FragmentStats>,
+    /// See `State::diffuse_bind_groups`
+    diffuse_bind_groups: &'a [BindGroup],
+    material_textures: &'a MaterialTextures,
+    /// Which texture each instance is painted with
+    instances: &'a [Instance],
+    light_bind_group: &'a BindGroup,
+    vertex_pool: &'a BufferPool,
+    index_pool: &'a BufferPool,
+    instance_buffer: &'a Allocation,
+    pipeline_cache: &'a PipelineCache,
+    skinned_pipeline_cache: &'a PipelineCache,
+    morphed_pipeline_cache: Option<&'a PipelineCache>,
+    shader_defs: &'a ShaderDefs,
+    outline: &'a Outline,
+    normal_view: &'a NormalView,
+    debug_draw: &'a DebugDraw,
+    /// What the scene's drawn into, before it's post-processed
+    targets: &'a SceneTargets,
+    target_view: &'a TextureView,
+    target_texture: &'a Texture,
+    background: Color,
+    /// Whether a debug view is shown, which only what's drawn with the scene's shader has
+    debugging: bool,
+}
+
+impl Scene<'_> {
+    /// Draw everything but the water, the glass, the overlays and the gizmos,
+    /// seen through the camera in `camera_bind_group` whose view-projection matrix is `view_proj`
+    fn draw_scene<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        camera_bind_group: &'a BindGroup,
+        view_proj: &Matrix4<f32>,
+        counter: &mut FragmentCounter,
+    ) {
+        if let Some(sky) = self.sky.filter(|_| !self.debugging) {
+            render_pass.set_bind_group(1, camera_bind_group, &[]);
+            counter.count(render_pass, DrawnObject::Sky, |render_pass| {
+                sky.draw(render_pass)
+            });
+        }
+        render_pass.set_bind_group(0, &self.diffuse_bind_groups[0], &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.set_bind_group(2, self.light_bind_group, &[]);
+        render_pass.set_vertex_buffer(1, self.vertex_pool.slice(self.instance_buffer));
+        for (index, model) in self
+            .models
+            .iter()
+            .enumerate()
+            .filter(|(_, model)| !model.mesh.is_skinned() && model.morph.is_none())
+        {
+            render_pass.set_pipeline(
+                self.pipeline_cache
+                    .get(&topology_defs(self.shader_defs, model.mesh.topology()))
+                    .expect("the current shader permutation is compiled by `set_shader_defs`"),
+            );
+            self.draw_model(render_pass, model, counter, DrawnObject::Model(index));
+        }
+        if let Some(terrain) = self.terrain.filter(|_| !self.debugging) {
+            counter.count(render_pass, DrawnObject::Terrain, |render_pass| {
+                terrain.draw(render_pass, view_proj, self.vertex_pool, self.index_pool);
+            });
+            // the rest go back to the scene's material
+            render_pass.set_bind_group(0, &self.diffuse_bind_groups[0], &[]);
+        }
+        if let Some(vegetation) = self.vegetation.filter(|_| !self.debugging) {
+            counter.count(render_pass, DrawnObject::Vegetation, |render_pass| {
+                vegetation.draw(render_pass, view_proj, self.vertex_pool, self.index_pool);
+            });
+            // and back to the scene's material and instances
+            render_pass.set_bind_group(0, &self.diffuse_bind_groups[0], &[]);
+            render_pass.set_vertex_buffer(1, self.vertex_pool.slice(self.instance_buffer));
+        }
+        if let Some(point_cloud) = self.point_cloud.filter(|_| !self.debugging) {
+            counter.count(render_pass, DrawnObject::PointCloud, |render_pass| {
+                point_cloud.draw(render_pass, camera_bind_group)
+            });
+            // and back to the scene's material and vertices
+            render_pass.set_bind_group(0, &self.diffuse_bind_groups[0], &[]);
+        }
+        // Skinned meshes get the same bind groups and so the same materials,
+        // only their vertices and the pipeline differ
+        let mut skinned = self
+            .models
+            .iter()
+            .enumerate()
+            .filter_map(|(index, model)| Some((index, model, model.skin.as_ref()?)))
+            .peekable();
+        if skinned.peek().is_some() {
+            render_pass.set_pipeline(
+                self.skinned_pipeline_cache
+                    .get(&skinned_defs(self.shader_defs, SKIN_GROUP))
+                    .expect("skinned permutations are compiled along with their skinned meshes"),
+            );
+            for (index, model, skin) in skinned {
+                render_pass.set_bind_group(SKIN_GROUP, skin.bind_group(), &[]);
+                self.draw_model(render_pass, model, counter, DrawnObject::Model(index));
+            }
+        }
+        let mut morphed = self
+            .models
+            .iter()
+            .enumerate()
+            .filter(|(_, model)| model.skin.is_none())
+            .filter_map(|(index, model)| Some((index, model, model.morph.as_ref()?)))
+            .peekable();
+        if let (Some(cache), true) = (self.morphed_pipeline_cache, morphed.peek().is_some()) {
+            render_pass.set_pipeline(
+                cache
+                    .get(&morphed_defs(self.shader_defs, MORPH_GROUP))
+                    .expect("morphed permutations are compiled along with their morphed meshes"),
+            );
+            for (index, model, morph) in morphed {
+                render_pass.set_bind_group(MORPH_GROUP, morph.bind_group(), &[]);
+                self.draw_model(render_pass, model, counter, DrawnObject::Model(index));
+            }
+        }
+    }
+
+    /// Draw `model` with the material's bind group for each of its instances' textures,
+    /// which is only bound once when they're bindless, counting its fragments as `object`'s
+    fn draw_model<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        model: &Model,
+        counter: &mut FragmentCounter,
+        object: DrawnObject,
+    ) {
+        counter.count(render_pass, object, |render_pass| {
+            if self.material_textures.is_bindless() {
+                model.draw(render_pass, self.vertex_pool, self.index_pool);
+                return;
+            }
+            for (texture, instances) in self
+                .material_textures
+                .runs(self.instances, model.instances.clone())
+            {
+                render_pass.set_bind_group(0, &self.diffuse_bind_groups[texture], &[]);
+                model
+                    .mesh
+                    .draw(render_pass, self.vertex_pool, self.index_pool, instances);
+            }
+            render_pass.set_bind_group(0, &self.diffuse_bind_groups[0], &[]);
+        });
+    }
+
+    /// Everything opaque in the scene, seen through the camera in `camera_bind_group`
+    fn draw_opaque<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        camera_bind_group: &'a BindGroup,
+        view_proj: &Matrix4<f32>,
+        counter: &mut FragmentCounter,
+    ) {
+        self.draw_scene(render_pass, camera_bind_group, view_proj, counter);
+        for (index, mirror) in self.mirrors.iter().enumerate().filter(|_| !self.debugging) {
+            counter.count(render_pass, DrawnObject::Mirror(index), |render_pass| {
+                mirror.draw(render_pass, self.vertex_pool, self.index_pool)
+            });
+        }
+    }
+
+    /// The instances which are glass, drawn the same way as in `draw_scene` but with `glass`' pipeline
+    fn draw_glass<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        glass: &'a Glass,
+        camera_bind_group: &'a BindGroup,
+        counter: &mut FragmentCounter,
+    ) {
+        glass.bind(render_pass, self.shader_defs);
+        render_pass.set_bind_group(0, &self.diffuse_bind_groups[0], &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.set_bind_group(2, self.light_bind_group, &[]);
+        render_pass.set_vertex_buffer(1, self.vertex_pool.slice(self.instance_buffer));
+        for (index, model) in self.models.iter().enumerate().filter(|(_, model)| {
+            !model.mesh.is_skinned() && model.morph.is_none() && model.mesh.is_triangle_list()
+        }) {
+            self.draw_model(render_pass, model, counter, DrawnObject::Glass(index));
+        }
+    }
+
+    /// What's drawn over the rest of the scene, seen through the camera in `camera_bind_group`
+    fn draw_overlaid<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        camera_bind_group: &'a BindGroup,
+        counter: &mut FragmentCounter,
+    ) {
+        // Drawn last, as it's blended over what's under it
+        if let Some(water) = self.water.filter(|_| !self.debugging) {
+            counter.count(render_pass, DrawnObject::Water, |render_pass| {
+                water.draw(render_pass, self.vertex_pool, self.index_pool)
+            });
+        }
+        self.outline.draw(
+            render_pass,
+            self.models,
+            self.vertex_pool.slice(self.instance_buffer),
+            self.vertex_pool,
+            self.index_pool,
+        );
+        self.normal_view.draw(
+            render_pass,
+            self.models,
+            self.vertex_pool.slice(self.instance_buffer),
+            self.vertex_pool,
+        );
+        self.debug_draw.draw(render_pass, camera_bind_group);
+    }
+
+    /// Begin a pass drawing into the scene's targets, clearing them first if `clear` is set
+    fn begin_pass<'a>(
+        &'a self,
+        encoder: &'a mut CommandEncoder,
+        label: &str,
+        clear: bool,
+    ) -> RenderPass<'a> {
+        fn load<V>(clear: bool, value: V) -> LoadOp<V> {
+            if clear {
+                LoadOp::Clear(value)
+            } else {
+                LoadOp::Load
+            }
+        }
+        // drawn into the multisampled targets if there are any, which are resolved into the rest at the end
+        let targets = self.targets;
+        let (color, velocity, depth, resolve_targets) = match &targets.multisampled {
+            Some(multisampled) => (
+                &multisampled.color.view,
+                &multisampled.velocity.view,
+                &multisampled.depth.view,
+                [Some(self.target_view), Some(&targets.velocity.view)],
+            ),
+            None => (
+                self.target_view,
+                &targets.velocity.view,
+                &targets.depth.view,
+                [None, None],
+            ),
+        };
+        encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[
+                Some(RenderPassColorAttachment {
+                    view: color,
+                    resolve_target: resolve_targets[0],
+                    ops: Operations {
+                        // black behind a debug view, where nothing's drawn
+                        load: load(
+                            clear,
+                            if self.debugging {
+                                Color::BLACK
+                            } else {
+                                self.background
+                            },
+                        ),
+                        store: true,
+                    },
+                }),
+                // the background isn't moving
+                Some(RenderPassColorAttachment {
+                    view: velocity,
+                    resolve_target: resolve_targets[1],
+                    ops: Operations {
+                        load: load(clear, Color::TRANSPARENT),
+                        store: true,
+                    },
+                }),
+            ],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: depth,
+                depth_ops: Some(Operations {
+                    load: load(clear, 1.0),
+                    store: true,
+                }),
+                stencil_ops: Some(Operations {
+                    load: load(clear, 0),
+                    store: true,
+                }),
+            }),
+        })
+    }
+
+    /// Call `draw` with the camera and view-projection matrix of each view of the scene,
+    /// which is once for each eye with drawing limited to its half of the target in stereo,
+    /// and otherwise just once through the camera
+    fn for_each_view<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        mut draw: impl FnMut(&mut RenderPass<'a>, &'a BindGroup, &Matrix4<f32>),
+    ) {
+        if let Some(stereo) = self.stereo {
+            let scene = self.targets;
+            for eye in Eye::BOTH {
+                Stereo::set_viewport(render_pass, eye, scene.width, scene.height);
+                draw(
+                    render_pass,
+                    stereo.camera_bind_group(eye),
+                    stereo.view_proj(eye),
+                );
+            }
+        } else {
+            let view_proj = self.camera.build_view_projection_matrix();
+            draw(render_pass, self.camera_bind_group, &view_proj);
+        }
+    }
+}
+
+fn command_encoder(device: &Device, label: &str) -> CommandEncoder {
+    device.create_command_encoder(&CommandEncoderDescriptor { label: Some(label) })
+}
+
+/// What goes in the scene's material bind group, in binding order
+#[allow(clippy::too_many_arguments)]
+fn material_entries<'a>(
+    diffuse: BindingResource<'a>,
+    diffuse_sampler: &'a Sampler,
+    emissive_texture: &'a OurTexture,
+    height_texture: &'a OurTexture,
+    parallax_uniform: &'a UniformBuffer<ParallaxUniform>,
+    displacement_texture: &'a OurTexture,
+    displacement_uniform: &'a UniformBuffer<DisplacementUniform>,
+    displacement_sampler: &'a Sampler,
+) -> [BindGroupEntry<'a>; 8] {
+    [
+        BindGroupEntry {
+            binding: 0,
+            resource: diffuse,
+        },
+        BindGroupEntry {
+            binding: 1,
+            resource: BindingResource::Sampler(diffuse_sampler),
+        },
+        BindGroupEntry {
+            binding: 2,
+            resource: BindingResource::TextureView(&emissive_texture.view),
+        },
+        BindGroupEntry {
+            binding: 3,
+            resource: BindingResource::TextureView(&height_texture.view),
+        },
+        parallax_uniform.bind_group_entry(4),
+        BindGroupEntry {
+            binding: 5,
+            resource: BindingResource::TextureView(&displacement_texture.view),
+        },
+        displacement_uniform.bind_group_entry(6),
+        BindGroupEntry {
+            binding: 7,
+            resource: BindingResource::Sampler(displacement_sampler),
+        },
+    ]
+}
+
+/// Our shaders output linear colours, so we want the surface to do the conversion to sRGB for us,
+/// otherwise the output would look too dark. The first supported format might be either
+fn surface_format(surface: &Surface, adapter: &Adapter) -> TextureFormat {
+    let formats = surface.get_supported_formats(adapter);
+    formats
+        .iter()
+        .copied()
+        .find(|format| format.describe().srgb)
+        .unwrap_or_else(|| {
+            tracing::warn!("No sRGB surface format available, colours will be too dark");
+            formats[0]
+        })
+}
+
+/// An alpha mode which blends the surface with whatever is behind the window, if there is one
+fn transparent_alpha_mode(surface: &Surface, adapter: &Adapter) -> CompositeAlphaMode {
+    let alpha_modes = surface.get_supported_alpha_modes(adapter);
+    [
+        CompositeAlphaMode::PreMultiplied,
+        CompositeAlphaMode::PostMultiplied,
+        CompositeAlphaMode::Inherit,
+    ]
+    .into_iter()
+    .find(|mode| alpha_modes.contains(mode))
+    .unwrap_or_else(|| {
+        tracing::warn!("The surface can't be transparent, it only supports {alpha_modes:?}");
+        CompositeAlphaMode::Auto
+    })
 }
@@ -0,0 +1,179 @@
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupLayout, Device, Queue, RenderPass};
+
+use crate::{
+    camera::{Camera, CameraUniform},
+    geometry::Plane,
+    math::{InnerSpace, Matrix4, Point3, SquareMatrix},
+    uniform::UniformBuffer,
+};
+
+/// How far apart the eyes are and where they look, which can be changed at any time through `Stereo::settings_mut`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StereoSettings {
+    /// The distance between the eyes in world units, where a metre to a unit makes 0.064 about a person's
+    pub eye_separation: f32,
+    /// How far in front of the camera the eyes' views meet, which is where the scene sits at the depth of the screen.
+    /// Anything nearer looks like it comes out of the screen
+    pub convergence: f32,
+    /// How the eyes are shown
+    pub output: StereoOutput,
+}
+
+/// How the eyes' views are shown
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StereoOutput {
+    /// Side by side, each squeezed into half of the screen, for a 3D TV or a viewer to spread back out
+    #[default]
+    SideBySide,
+    /// On top of each other in red and cyan by the `Anaglyph` effect, for glasses with a red lens on the left
+    Anaglyph,
+}
+
+impl Default for StereoSettings {
+    fn default() -> Self {
+        Self {
+            eye_separation: 0.064,
+            convergence: 5.0,
+            output: StereoOutput::default(),
+        }
+    }
+}
+
+/// Which of the two views
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl Eye {
+    pub const BOTH: [Self; 2] = [Self::Left, Self::Right];
+
+    /// Which way the eye is moved from the camera along its right vector
+    fn side(self) -> f32 {
+        match self {
+            Self::Left => -1.0,
+            Self::Right => 1.0,
+        }
+    }
+}
+
+/// Renders the scene twice, once for each eye, side by side in the scene's target with the left eye on the left,
+/// which is either shown like that or combined by the `Anaglyph` effect, see `StereoOutput`.
+/// Each eye is the camera moved half of `StereoSettings::eye_separation` to the side, looking the same way
+/// but with its frustum skewed so that the views meet at `StereoSettings::convergence`,
+/// which keeps the vertical parallax that turning the eyes inwards would cause out of the picture.
+///
+/// It's only ever shown in the window. There's no OpenXR session to hand the eyes to a headset,
+/// as wgpu 0.14 has no way of rendering into an XR runtime's swapchain images, and nothing is drawn
+/// with multiview, so each eye gets a pass over the scene of its own. Both are left for later,
+/// as VR support in its own right. The water's reflection is still rendered once, through the camera,
+/// so it only roughly lines up in each eye
+pub struct Stereo {
+    settings: StereoSettings,
+    eyes: [EyeCamera; 2],
+}
+
+struct EyeCamera {
+    uniform: UniformBuffer<CameraUniform>,
+    bind_group: BindGroup,
+    view_proj: Matrix4<f32>,
+}
+
+impl Stereo {
+    pub fn new(device: &Device, camera_layout: &BindGroupLayout, settings: StereoSettings) -> Self {
+        let eyes = Eye::BOTH.map(|eye| {
+            let label = match eye {
+                Eye::Left => "Left Eye Camera Buffer",
+                Eye::Right => "Right Eye Camera Buffer",
+            };
+            let uniform = UniformBuffer::new(device, CameraUniform::default(), Some(label));
+            let bind_group = device.create_bind_group(&BindGroupDescriptor {
+                label: Some("eye_camera_bind_group"),
+                layout: camera_layout,
+                entries: &[uniform.bind_group_entry(0)],
+            });
+            EyeCamera {
+                uniform,
+                bind_group,
+                view_proj: Matrix4::identity(),
+            }
+        });
+        Self { settings, eyes }
+    }
+
+    pub fn settings(&self) -> &StereoSettings {
+        &self.settings
+    }
+
+    pub fn settings_mut(&mut self) -> &mut StereoSettings {
+        &mut self.settings
+    }
+
+    /// Move the eyes to either side of `camera`, whose aspect ratio is the whole scene's, and write their matrices
+    /// along with the scene's `clip_planes`
+    pub fn update(&mut self, queue: &Queue, camera: &Camera, clip_planes: &[Plane]) {
+        for eye in Eye::BOTH {
+            let view_proj = self.view_projection_matrix(camera, eye);
+            let position = self.eye_position(camera, eye);
+            let eye_camera = &mut self.eyes[eye as usize];
+            eye_camera.view_proj = view_proj;
+            let uniform = eye_camera.uniform.get_mut();
+            uniform.set_view_proj(position, view_proj);
+            uniform.set_clip_planes(clip_planes);
+            eye_camera.uniform.write(queue);
+        }
+    }
+
+    /// The view-projection matrix of `eye` looking through `camera`, in wgpu's 0 to 1 depth range
+    pub fn view_projection_matrix(&self, camera: &Camera, eye: Eye) -> Matrix4<f32> {
+        let offset = self.eye_position(camera, eye) - camera.eye;
+        let eye_camera = Camera {
+            eye: camera.eye + offset,
+            target: camera.target + offset,
+            aspect: match self.settings.output {
+                // each eye gets half of the width
+                StereoOutput::SideBySide => camera.aspect / 2.0,
+                // which is stretched back out over the whole width
+                StereoOutput::Anaglyph => camera.aspect,
+            },
+            ..*camera
+        };
+        let view_proj = eye_camera.build_view_projection_matrix();
+        // Skew the frustum towards the other eye, so that what's `convergence` straight ahead of the camera
+        // ends up in the middle of both views, rather than half the separation off to the side
+        let x_scale = 1.0 / (eye_camera.aspect * (camera.fovy.to_radians() / 2.0).tan());
+        let shift = eye.side() * self.settings.eye_separation / 2.0 * x_scale
+            / self.settings.convergence.max(camera.znear);
+        let mut skew = Matrix4::identity();
+        // x += shift * w, where w is the distance in front of the eye
+        skew.w.x = shift;
+        skew * view_proj
+    }
+
+    fn eye_position(&self, camera: &Camera, eye: Eye) -> Point3<f32> {
+        let forward = (camera.target - camera.eye).normalize();
+        let right = forward.cross(camera.up).normalize();
+        camera.eye + right * (eye.side() * self.settings.eye_separation / 2.0)
+    }
+
+    /// The camera to bind at group 1 in place of the scene's while drawing `eye`
+    pub fn camera_bind_group(&self, eye: Eye) -> &BindGroup {
+        &self.eyes[eye as usize].bind_group
+    }
+
+    /// The matrix `update` last wrote for `eye`, for culling what's drawn for it
+    pub fn view_proj(&self, eye: Eye) -> &Matrix4<f32> {
+        &self.eyes[eye as usize].view_proj
+    }
+
+    /// Limit drawing to `eye`'s half of a target `width` by `height` pixels
+    pub fn set_viewport(render_pass: &mut RenderPass, eye: Eye, width: u32, height: u32) {
+        let half = width as f32 / 2.0;
+        let x = match eye {
+            Eye::Left => 0.0,
+            Eye::Right => half,
+        };
+        render_pass.set_viewport(x, 0.0, half, height as f32, 0.0, 1.0);
+    }
+}
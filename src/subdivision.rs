@@ -0,0 +1,304 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
+
+use anyhow::*;
+
+use crate::{
+    math::{InnerSpace, Vector3, Zero},
+    vertex::Vertex,
+};
+
+/// How `subdivide` places the vertices it adds and moves the ones already there
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Subdivision {
+    /// Each new vertex goes halfway along its edge and nothing else moves, which keeps the mesh's
+    /// shape and hard edges, e.g. to give a displacement map more vertices to move
+    Midpoint,
+    /// Loop subdivision, which rounds the mesh off a little more each time, towards a smooth surface.
+    /// Vertices in the same place count as one, so hard edges are smoothed over, and the normals
+    /// are worked out again from the smoothed surface
+    Loop,
+}
+
+impl FromStr for Subdivision {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "midpoint" => Self::Midpoint,
+            "loop" => Self::Loop,
+            _ => bail!("unknown subdivision `{s}`, expected `midpoint` or `loop`"),
+        })
+    }
+}
+
+/// Split every triangle of a mesh into 4, `levels` times over, before it's uploaded, so that
+/// low-poly meshes like the cube can be smoothed or have vertices to displace and animate.
+/// Each level has 4 times as many triangles as the last, so this fails if the vertices
+/// would no longer fit in `u16` indices
+pub fn subdivide(
+    vertices: &[Vertex],
+    indices: &[u16],
+    subdivision: Subdivision,
+    levels: u32,
+) -> Result<(Vec<Vertex>, Vec<u16>)> {
+    ensure!(
+        indices.len().is_multiple_of(3),
+        "only meshes of triangles can be subdivided"
+    );
+    ensure!(
+        indices
+            .iter()
+            .all(|&index| (index as usize) < vertices.len()),
+        "the mesh's indices go past the end of its vertices"
+    );
+    let mut mesh = (vertices.to_vec(), indices.to_vec());
+    for level in 1..=levels {
+        mesh = match subdivision {
+            Subdivision::Midpoint => midpoint(&mesh.0, &mesh.1),
+            Subdivision::Loop => loop_subdivision(&mesh.0, &mesh.1),
+        }
+        .with_context(|| format!("failed to subdivide the mesh {level} times"))?;
+    }
+    Ok(mesh)
+}
+
+fn midpoint(vertices: &[Vertex], indices: &[u16]) -> Result<(Vec<Vertex>, Vec<u16>)> {
+    split(vertices, indices, |start, end| {
+        let (start, end) = (&vertices[start as usize], &vertices[end as usize]);
+        let normal = Vector3::from(start.normal()) + Vector3::from(end.normal());
+        let normal = if normal.is_zero() {
+            start.normal()
+        } else {
+            normal.normalize().into()
+        };
+        Vertex::new(
+            halfway(start.position(), end.position()),
+            halfway(start.tex_coords(), end.tex_coords()),
+            normal,
+        )
+    })
+}
+
+fn loop_subdivision(vertices: &[Vertex], indices: &[u16]) -> Result<(Vec<Vertex>, Vec<u16>)> {
+    // Each vertex's place, as an index into `places`, so that copies of a vertex for each face count as one
+    let mut found = HashMap::new();
+    let mut places = Vec::<Vector3<f32>>::new();
+    let place = vertices
+        .iter()
+        .map(|vertex| {
+            *found
+                .entry(vertex.position().map(f32::to_bits))
+                .or_insert_with(|| {
+                    places.push(vertex.position().into());
+                    places.len() - 1
+                })
+        })
+        .collect::<Vec<_>>();
+
+    // The place opposite each edge in every triangle it's an edge of, which is 2 for an edge
+    // through the middle of a surface and 1 where the surface ends, kept in order so that the
+    // neighbours are always added up the same way
+    let mut opposites = BTreeMap::<(usize, usize), Vec<usize>>::new();
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|index| place[index as usize]);
+        for (start, end, opposite) in [(a, b, c), (b, c, a), (c, a, b)] {
+            opposites
+                .entry(edge(start, end))
+                .or_default()
+                .push(opposite);
+        }
+    }
+    let mut neighbours = vec![Vec::new(); places.len()];
+    let mut borders = vec![Vec::new(); places.len()];
+    for (&(start, end), opposite) in &opposites {
+        neighbours[start].push(end);
+        neighbours[end].push(start);
+        if opposite.len() != 2 {
+            borders[start].push(end);
+            borders[end].push(start);
+        }
+    }
+
+    // Where the places already there move to, weighted towards their neighbours
+    let moved = places
+        .iter()
+        .enumerate()
+        .map(|(index, &place)| match borders[index].as_slice() {
+            [] => {
+                let count = neighbours[index].len() as f32;
+                let weight = if neighbours[index].len() == 3 {
+                    3.0 / 16.0
+                } else {
+                    3.0 / (8.0 * count)
+                };
+                let sum = neighbours[index]
+                    .iter()
+                    .fold(Vector3::zero(), |sum, &neighbour| sum + places[neighbour]);
+                place * (1.0 - count * weight) + sum * weight
+            }
+            &[start, end] => place * 0.75 + (places[start] + places[end]) * 0.125,
+            // corners where more than one border meets stay where they are
+            _ => place,
+        })
+        .collect::<Vec<_>>();
+
+    let (mut vertices, indices) = split(vertices, indices, |start, end| {
+        let (start_place, end_place) = (place[start as usize], place[end as usize]);
+        let position = match opposites[&edge(start_place, end_place)].as_slice() {
+            &[left, right] => {
+                (places[start_place] + places[end_place]) * 0.375
+                    + (places[left] + places[right]) * 0.125
+            }
+            _ => (places[start_place] + places[end_place]) * 0.5,
+        };
+        let (start, end) = (&vertices[start as usize], &vertices[end as usize]);
+        Vertex::new(
+            position.into(),
+            halfway(start.tex_coords(), end.tex_coords()),
+            start.normal(),
+        )
+    })?;
+    for (vertex, &place) in vertices.iter_mut().zip(&place) {
+        *vertex = Vertex::new(moved[place].into(), vertex.tex_coords(), vertex.normal());
+    }
+    Ok((smooth_normals(&vertices, &indices), indices))
+}
+
+/// Split each triangle into 4 through a new vertex on each of its edges, made by `edge_vertex`
+/// from the indices of the edge's ends. Triangles sharing an edge share its new vertex,
+/// and the new triangles wind the same way as the one they were split from
+fn split(
+    vertices: &[Vertex],
+    indices: &[u16],
+    mut edge_vertex: impl FnMut(u16, u16) -> Vertex,
+) -> Result<(Vec<Vertex>, Vec<u16>)> {
+    let mut new_vertices = vertices.to_vec();
+    let mut new_indices = Vec::with_capacity(indices.len() * 4);
+    let mut found = HashMap::new();
+    let mut middle = |start: u16, end: u16| -> Result<u16> {
+        let key = if start < end {
+            (start, end)
+        } else {
+            (end, start)
+        };
+        if let Some(&index) = found.get(&key) {
+            return Ok(index);
+        }
+        let index = u16::try_from(new_vertices.len())
+            .ok()
+            .context("too many vertices for u16 indices")?;
+        new_vertices.push(edge_vertex(key.0, key.1));
+        found.insert(key, index);
+        Ok(index)
+    };
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]];
+        let [ab, bc, ca] = [middle(a, b)?, middle(b, c)?, middle(c, a)?];
+        new_indices.extend_from_slice(&[a, ab, ca, ab, b, bc, ca, bc, c, ab, bc, ca]);
+    }
+    Ok((new_vertices, new_indices))
+}
+
+/// Each vertex's normal as the average of the triangles around its place, weighted by their area
+fn smooth_normals(vertices: &[Vertex], indices: &[u16]) -> Vec<Vertex> {
+    let mut normals = HashMap::<[u32; 3], Vector3<f32>>::new();
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]]
+            .map(|index| Vector3::from(vertices[index as usize].position()));
+        // as long as twice the triangle's area
+        let normal = (b - a).cross(c - a);
+        for index in triangle {
+            *normals
+                .entry(vertices[*index as usize].position().map(f32::to_bits))
+                .or_insert_with(Vector3::zero) += normal;
+        }
+    }
+    vertices
+        .iter()
+        .map(
+            |vertex| match normals.get(&vertex.position().map(f32::to_bits)) {
+                Some(normal) if !normal.is_zero() => Vertex::new(
+                    vertex.position(),
+                    vertex.tex_coords(),
+                    normal.normalize().into(),
+                ),
+                _ => *vertex,
+            },
+        )
+        .collect()
+}
+
+fn edge(start: usize, end: usize) -> (usize, usize) {
+    (start.min(end), start.max(end))
+}
+
+fn halfway<const N: usize>(start: [f32; N], end: [f32; N]) -> [f32; N] {
+    std::array::from_fn(|axis| (start[axis] + end[axis]) * 0.5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vertex::{INDICES, VERTICES};
+
+    /// Each triangle's normal by its winding, against the way out from the cube's centre, the origin
+    fn assert_outward(vertices: &[Vertex], indices: &[u16]) {
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]]
+                .map(|index| Vector3::from(vertices[index as usize].position()));
+            let normal = (b - a).cross(c - a);
+            assert!(normal.dot(a + b + c) > 0.0, "{triangle:?} faces inwards");
+        }
+    }
+
+    #[test]
+    fn midpoint_cube() {
+        let (vertices, indices) = subdivide(VERTICES, INDICES, Subdivision::Midpoint, 1).unwrap();
+        // the cube's faces don't share vertices, so each face's 5 edges get a vertex of their own
+        assert_eq!(vertices.len(), 24 + 6 * 5);
+        assert_eq!(indices.len(), INDICES.len() * 4);
+        assert_outward(&vertices, &indices);
+        // nothing moves off the cube's surface
+        for vertex in &vertices {
+            let position = vertex.position();
+            assert!(position.iter().any(|axis| axis.abs() == 1.0));
+        }
+    }
+
+    #[test]
+    fn loop_cube() {
+        let (vertices, indices) = subdivide(VERTICES, INDICES, Subdivision::Loop, 1).unwrap();
+        assert_eq!(vertices.len(), 24 + 6 * 5);
+        assert_eq!(indices.len(), INDICES.len() * 4);
+        assert_outward(&vertices, &indices);
+    }
+
+    #[test]
+    fn loop_moves_towards_centroid() {
+        let (vertices, _) = subdivide(VERTICES, INDICES, Subdivision::Loop, 1).unwrap();
+        // the cube's centroid is the origin, and its corners are the furthest from it
+        let corner = Vector3::from(VERTICES[0].position()).magnitude();
+        for (before, after) in VERTICES.iter().zip(&vertices) {
+            let before = Vector3::from(before.position()).magnitude();
+            let after = Vector3::from(after.position()).magnitude();
+            assert!(after < before, "{after} isn't nearer than {before}");
+        }
+        for vertex in &vertices {
+            assert!(Vector3::from(vertex.position()).magnitude() < corner);
+        }
+    }
+
+    #[test]
+    fn too_many_vertices() {
+        // 6 faces of 129 x 129 vertices after 7 levels
+        let error = subdivide(VERTICES, INDICES, Subdivision::Midpoint, 7).unwrap_err();
+        assert!(
+            format!("{error:#}").contains("too many vertices for u16 indices"),
+            "{error:#}"
+        );
+        assert!(subdivide(VERTICES, INDICES, Subdivision::Midpoint, 6).is_ok());
+    }
+}
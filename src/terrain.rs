@@ -0,0 +1,516 @@
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use image::DynamicImage;
+use wgpu::{
+    AddressMode, BindGroup, BindGroupEntry, BindGroupLayout, BindingResource, Device, FilterMode,
+    PipelineLayoutDescriptor, Queue, RenderPass, Sampler, SamplerDescriptor, TextureFormat,
+};
+
+use crate::{
+    assets,
+    buffer_pool::BufferPool,
+    decode::DecodedImages,
+    geometry::{Aabb, Frustum},
+    math::{InnerSpace, Matrix4, Point3, Vector3},
+    mesh::Mesh,
+    pipeline::PipelineCache,
+    reflection::ShaderReflection,
+    shader::{ShaderCode, ShaderDefs, ShaderLibrary},
+    texture::{OurTexture, TextureKind},
+    uniform::UniformBuffer,
+    vertex::Vertex,
+};
+
+/// Where the terrain's heights, size and textures come from
+#[derive(Clone, Debug)]
+pub struct TerrainConfig {
+    /// A greyscale image, where black is the lowest point and white the highest,
+    /// with one vertex per pixel
+    pub heightmap: PathBuf,
+    /// How far the terrain stretches along its longer side, in world units
+    pub size: f32,
+    /// How far the highest point is above the lowest
+    pub height: f32,
+    /// The texture tiled across the terrain, otherwise the planks
+    pub texture: Option<PathBuf>,
+    /// How far apart the texture repeats, in world units
+    pub tile_size: f32,
+    /// An image whose red, green, blue and alpha channels say how much of each of `layers`
+    /// to use at each point, stretched over the whole terrain
+    pub splat_map: Option<PathBuf>,
+    /// Up to 4 textures blended by `splat_map`, tiled like `texture`.
+    /// `texture` is the first layer unless all 4 are given
+    pub layers: Vec<PathBuf>,
+    /// Project the textures along each axis instead of stretching them over the terrain,
+    /// which keeps them from smearing down steep slopes
+    pub triplanar: bool,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        Self {
+            heightmap: PathBuf::new(),
+            size: 40.0,
+            height: 4.0,
+            texture: None,
+            tile_size: 2.0,
+            splat_map: None,
+            layers: Vec::new(),
+            triplanar: false,
+        }
+    }
+}
+
+/// The height of the ground at each point on a grid, from 0 to 1
+#[derive(Clone, Debug)]
+pub struct Heightmap {
+    width: u32,
+    depth: u32,
+    /// Row by row, from -z to +z
+    heights: Vec<f32>,
+}
+
+impl Heightmap {
+    /// Read the heights out of `image`'s brightness, keeping up to 16 bits of precision
+    pub fn from_image(image: &DynamicImage) -> Result<Self> {
+        let image = image.to_luma16();
+        let (width, depth) = image.dimensions();
+        ensure!(
+            width >= 2 && depth >= 2,
+            "a heightmap needs at least 2x2 pixels, not {width}x{depth}"
+        );
+        Ok(Self {
+            width,
+            depth,
+            heights: image
+                .into_raw()
+                .into_iter()
+                .map(|height| height as f32 / u16::MAX as f32)
+                .collect(),
+        })
+    }
+
+    /// The number of samples along x and z
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.depth)
+    }
+
+    /// The height at sample `(x, z)`, clamped to the edges
+    pub fn get(&self, x: i64, z: i64) -> f32 {
+        let x = x.clamp(0, self.width as i64 - 1) as usize;
+        let z = z.clamp(0, self.depth as i64 - 1) as usize;
+        self.heights[z * self.width as usize + x]
+    }
+
+    /// The height between samples, bilinearly interpolated from the 4 around `(x, z)`
+    pub fn sample(&self, x: f32, z: f32) -> f32 {
+        let (x0, z0) = (x.floor(), z.floor());
+        let (tx, tz) = (x - x0, z - z0);
+        let (x0, z0) = (x0 as i64, z0 as i64);
+        let top = self.get(x0, z0) * (1.0 - tx) + self.get(x0 + 1, z0) * tx;
+        let bottom = self.get(x0, z0 + 1) * (1.0 - tx) + self.get(x0 + 1, z0 + 1) * tx;
+        top * (1.0 - tz) + bottom * tz
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+struct TerrainUniform {
+    repeat: [f32; 2],
+    tile_size: f32,
+    _padding: f32,
+}
+
+/// A square of the terrain, drawn or skipped as a whole
+struct TerrainChunk {
+    mesh: Mesh,
+    bounds: Aabb,
+}
+
+/// A grid mesh built from a `Heightmap`, split into chunks so that only the ones the camera
+/// can see are drawn, and so that each one's indices fit in 16 bits
+pub struct Terrain {
+    heightmap: Heightmap,
+    /// The corner at the lowest x and z, at the height of the heightmap's 0
+    origin: Point3<f32>,
+    /// The distance between neighbouring samples along x and z
+    spacing: f32,
+    height: f32,
+    chunks: Vec<TerrainChunk>,
+    pipeline_cache: PipelineCache,
+    defs: ShaderDefs,
+    _uniform: UniformBuffer<TerrainUniform>,
+    _textures: Vec<OurTexture>,
+    _samplers: [Sampler; 2],
+    bind_group: BindGroup,
+    /// An identity instance in the scene's instance buffer, as the shadow pass places every mesh by its instance
+    shadow_instances: Range<u32>,
+}
+
+impl Terrain {
+    /// The number of quads along each side of a chunk, so that a chunk's 65x65 vertices fit in `u16` indices
+    const CHUNK_SIZE: u32 = 64;
+
+    /// Build the terrain described by `config`, centered on the origin with its highest possible point at `top`.
+    /// `camera_layout` and `light_layout` are the scene's, and `formats` and `depth_format` must match
+    /// the scene pass. `shadow_instances` must be an identity instance in the instance buffer
+    #[allow(clippy::too_many_arguments)]
+    #[tracing::instrument(skip_all, fields(heightmap = %config.heightmap.display()))]
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        library: &ShaderLibrary,
+        config: &TerrainConfig,
+        top: f32,
+        camera_layout: &BindGroupLayout,
+        light_layout: &BindGroupLayout,
+        formats: Vec<TextureFormat>,
+        depth_format: TextureFormat,
+        vertex_pool: &mut BufferPool,
+        index_pool: &mut BufferPool,
+        shadow_instances: Range<u32>,
+        images: &mut DecodedImages,
+    ) -> Result<Self> {
+        let image = images.take(&config.heightmap).with_context(|| {
+            format!(
+                "failed to load the heightmap {}",
+                config.heightmap.display()
+            )
+        })?;
+        let heightmap = Heightmap::from_image(&image)?;
+        let (width, depth) = heightmap.dimensions();
+        let spacing = config.size / (width.max(depth) - 1) as f32;
+        let extent_x = spacing * (width - 1) as f32;
+        let extent_z = spacing * (depth - 1) as f32;
+        let origin = Point3::new(-extent_x / 2.0, top - config.height, -extent_z / 2.0);
+
+        let mut chunks = Vec::new();
+        for chunk_z in (0..depth - 1).step_by(Self::CHUNK_SIZE as usize) {
+            for chunk_x in (0..width - 1).step_by(Self::CHUNK_SIZE as usize) {
+                let xs = chunk_x..(chunk_x + Self::CHUNK_SIZE).min(width - 1) + 1;
+                let zs = chunk_z..(chunk_z + Self::CHUNK_SIZE).min(depth - 1) + 1;
+                chunks.push(build_chunk(
+                    device,
+                    queue,
+                    vertex_pool,
+                    index_pool,
+                    &heightmap,
+                    origin,
+                    spacing,
+                    config.height,
+                    xs,
+                    zs,
+                ));
+            }
+        }
+        tracing::info!(
+            "Built a {width}x{depth} terrain out of {} chunks",
+            chunks.len()
+        );
+
+        let splat = config.splat_map.is_some();
+        let mut defs = ShaderDefs::new();
+        defs.set("SPLAT", splat);
+        defs.set("TRIPLANAR", config.triplanar);
+        let code = ShaderCode::from(library.resolve("terrain.wgsl")?);
+        let reflection =
+            ShaderReflection::from_code(&code, &defs).context("failed to reflect terrain.wgsl")?;
+        let layout =
+            reflection.create_bind_group_layout(device, 0, Some("terrain_bind_group_layout"));
+
+        let mut textures = vec![match &config.texture {
+            Some(path) => load_texture(device, queue, images, path)?,
+            None => OurTexture::from_bytes(
+                device,
+                queue,
+                &assets::load("plank_texture.png")?,
+                "plank_texture.png",
+                TextureKind::Albedo,
+            )?,
+        }];
+        if splat {
+            ensure!(
+                config.layers.len() <= 4,
+                "a splat map blends at most 4 layers, not {}",
+                config.layers.len()
+            );
+            // the base texture is the first layer unless they're all given
+            if config.layers.len() == 4 {
+                textures.clear();
+            }
+            for path in &config.layers {
+                textures.push(load_texture(device, queue, images, path)?);
+            }
+        }
+        let layer_count = textures.len();
+        if let Some(path) = &config.splat_map {
+            let mut splat_map = images
+                .take(path)
+                .with_context(|| format!("failed to load the splat map {}", path.display()))?
+                .into_rgba8();
+            // a channel without a layer would otherwise blend in whichever layer is standing in for it,
+            // e.g. an RGB image's alpha is all 1s
+            for pixel in splat_map.pixels_mut() {
+                pixel.0[layer_count..].fill(0);
+            }
+            textures.push(OurTexture::from_image(
+                device,
+                queue,
+                &splat_map.into(),
+                Some("Splat Map"),
+                TextureKind::Data,
+            )?);
+        }
+
+        let uniform = UniformBuffer::new(
+            device,
+            TerrainUniform {
+                repeat: [extent_x / config.tile_size, extent_z / config.tile_size],
+                tile_size: config.tile_size,
+                ..Default::default()
+            },
+            Some("Terrain Buffer"),
+        );
+        let layer_sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Terrain Layer Sampler"),
+            address_mode_u: AddressMode::Repeat,
+            address_mode_v: AddressMode::Repeat,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+        let splat_sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Splat Map Sampler"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+        let view =
+            |layer: usize| BindingResource::TextureView(&textures[layer.min(layer_count - 1)].view);
+        let mut entries = vec![
+            uniform.bind_group_entry(0),
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&layer_sampler),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: view(0),
+            },
+        ];
+        if splat {
+            // missing layers are zeroed out of the splat map, so any texture will do for them
+            for layer in 1..4 {
+                entries.push(BindGroupEntry {
+                    binding: 2 + layer as u32,
+                    resource: view(layer),
+                });
+            }
+            entries.push(BindGroupEntry {
+                binding: 6,
+                resource: BindingResource::TextureView(&textures[layer_count].view),
+            });
+            entries.push(BindGroupEntry {
+                binding: 7,
+                resource: BindingResource::Sampler(&splat_sampler),
+            });
+        }
+        let bind_group = reflection.create_bind_group(
+            device,
+            0,
+            &layout,
+            &entries,
+            Some("terrain_bind_group"),
+        )?;
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Terrain Pipeline Layout"),
+            bind_group_layouts: &[&layout, camera_layout, light_layout],
+            push_constant_ranges: &[],
+        });
+        let mut pipeline_cache = PipelineCache::new(
+            "Terrain Pipeline",
+            code,
+            pipeline_layout,
+            vec![Vertex::desc()],
+            formats,
+            Some(depth_format),
+        );
+        pipeline_cache.prepare(device, &defs)?;
+
+        Ok(Self {
+            heightmap,
+            origin,
+            spacing,
+            height: config.height,
+            chunks,
+            pipeline_cache,
+            defs,
+            _uniform: uniform,
+            _textures: textures,
+            _samplers: [layer_sampler, splat_sampler],
+            bind_group,
+            shadow_instances,
+        })
+    }
+
+    /// The height of the ground at world position `(x, z)`, if the terrain reaches that far
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let (width, depth) = self.heightmap.dimensions();
+        let x = (x - self.origin.x) / self.spacing;
+        let z = (z - self.origin.z) / self.spacing;
+        if !(0.0..=(width - 1) as f32).contains(&x) || !(0.0..=(depth - 1) as f32).contains(&z) {
+            return None;
+        }
+        Some(self.origin.y + self.heightmap.sample(x, z) * self.height)
+    }
+
+    /// Draw into a scene pass whose targets have `sample_count` samples per pixel from now on,
+    /// compiling the pipeline again if that's a change
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) -> Result<()> {
+        self.pipeline_cache.set_sample_count(sample_count);
+        self.pipeline_cache.prepare(device, &self.defs)
+    }
+
+    /// Draw the chunks which are in view of `view_proj`, returning how many were drawn.
+    /// The scene's camera and light bind groups must already be bound at groups 1 and 2
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        view_proj: &Matrix4<f32>,
+        vertex_pool: &'a BufferPool,
+        index_pool: &'a BufferPool,
+    ) -> usize {
+        let frustum = Frustum::from_view_proj(view_proj);
+        render_pass.set_pipeline(
+            self.pipeline_cache
+                .get(&self.defs)
+                .expect("the terrain's pipeline is compiled in `new`"),
+        );
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        let mut drawn = 0;
+        for chunk in self
+            .chunks
+            .iter()
+            .filter(|chunk| frustum.intersects(&chunk.bounds))
+        {
+            chunk.mesh.draw(render_pass, vertex_pool, index_pool, 0..1);
+            drawn += 1;
+        }
+        drawn
+    }
+
+    /// Draw the chunks within `radius` of `center` into a shadow map,
+    /// with whatever shadow pipeline and instance buffer `render_pass` currently has set
+    pub fn draw_shadow_casters<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        center: Point3<f32>,
+        radius: f32,
+        vertex_pool: &'a BufferPool,
+        index_pool: &'a BufferPool,
+    ) {
+        for chunk in self
+            .chunks
+            .iter()
+            .filter(|chunk| chunk.bounds.distance_to(center) < radius)
+        {
+            chunk.mesh.draw(
+                render_pass,
+                vertex_pool,
+                index_pool,
+                self.shadow_instances.clone(),
+            );
+        }
+    }
+}
+
+/// The vertices and indices covering samples `xs` by `zs` of `heightmap`, along with their bounds
+#[allow(clippy::too_many_arguments)]
+fn build_chunk(
+    device: &Device,
+    queue: &Queue,
+    vertex_pool: &mut BufferPool,
+    index_pool: &mut BufferPool,
+    heightmap: &Heightmap,
+    origin: Point3<f32>,
+    spacing: f32,
+    height: f32,
+    xs: Range<u32>,
+    zs: Range<u32>,
+) -> TerrainChunk {
+    let (width, depth) = heightmap.dimensions();
+    let mut vertices = Vec::with_capacity(xs.len() * zs.len());
+    let (mut min_y, mut max_y) = (f32::INFINITY, f32::NEG_INFINITY);
+    for z in zs.clone() {
+        for x in xs.clone() {
+            let (x, z) = (x as i64, z as i64);
+            let y = origin.y + heightmap.get(x, z) * height;
+            min_y = min_y.min(y);
+            max_y = max_y.max(y);
+            // central differences, which the heightmap clamps to one-sided ones at its edges
+            let slope_x = (heightmap.get(x + 1, z) - heightmap.get(x - 1, z)) * height;
+            let slope_z = (heightmap.get(x, z + 1) - heightmap.get(x, z - 1)) * height;
+            let normal = Vector3::new(-slope_x, 2.0 * spacing, -slope_z).normalize();
+            vertices.push(Vertex::new(
+                [
+                    origin.x + x as f32 * spacing,
+                    y,
+                    origin.z + z as f32 * spacing,
+                ],
+                [x as f32 / (width - 1) as f32, z as f32 / (depth - 1) as f32],
+                normal.into(),
+            ));
+        }
+    }
+    let row = xs.len() as u16;
+    let mut indices = Vec::with_capacity((xs.len() - 1) * (zs.len() - 1) * 6);
+    for z in 0..zs.len() as u16 - 1 {
+        for x in 0..row - 1 {
+            let top_left = z * row + x;
+            let bottom_left = top_left + row;
+            // counter-clockwise when seen from above, like the floor
+            indices.extend_from_slice(&[
+                top_left,
+                bottom_left,
+                top_left + 1,
+                top_left + 1,
+                bottom_left,
+                bottom_left + 1,
+            ]);
+        }
+    }
+    TerrainChunk {
+        mesh: Mesh::new(device, queue, vertex_pool, index_pool, &vertices, &indices),
+        bounds: Aabb {
+            min: Point3::new(
+                origin.x + xs.start as f32 * spacing,
+                min_y,
+                origin.z + zs.start as f32 * spacing,
+            ),
+            max: Point3::new(
+                origin.x + (xs.end - 1) as f32 * spacing,
+                max_y,
+                origin.z + (zs.end - 1) as f32 * spacing,
+            ),
+        },
+    }
+}
+
+/// Load a colour texture from `path`
+fn load_texture(
+    device: &Device,
+    queue: &Queue,
+    images: &mut DecodedImages,
+    path: &Path,
+) -> Result<OurTexture> {
+    let image = images
+        .take(path)
+        .with_context(|| format!("failed to load {}", path.display()))?;
+    OurTexture::from_image(device, queue, &image, path.to_str(), TextureKind::Albedo)
+}
@@ -0,0 +1,108 @@
+// Heightmap terrain, whose vertices are already in world space so it needs no instance
+
+#include "camera.wgsl"
+#include "fog.wgsl"
+#include "light.wgsl"
+#include "triplanar.wgsl"
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    // across the whole terrain, from 0 to 1
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) current_position: vec4<f32>,
+    @location(2) previous_position: vec4<f32>,
+    @location(3) world_position: vec3<f32>,
+    @location(4) normal: vec3<f32>,
+}
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.world_position = model.position;
+    out.normal = model.normal;
+    out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
+    out.current_position = out.clip_position;
+    out.previous_position = camera.prev_view_proj * vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+struct TerrainUniform {
+    // how many times the layers repeat across the terrain
+    repeat: vec2<f32>,
+    // how far apart they repeat in world units, for triplanar mapping
+    tile_size: f32,
+}
+@group(0) @binding(0)
+var<uniform> terrain: TerrainUniform;
+// Wraps around, for tiling the layers
+@group(0) @binding(1)
+var s_layer: sampler;
+@group(0) @binding(2)
+var t_layer0: texture_2d<f32>;
+#ifdef SPLAT
+@group(0) @binding(3)
+var t_layer1: texture_2d<f32>;
+@group(0) @binding(4)
+var t_layer2: texture_2d<f32>;
+@group(0) @binding(5)
+var t_layer3: texture_2d<f32>;
+// How much of each layer to use, one per channel, stretched over the whole terrain
+@group(0) @binding(6)
+var t_splat: texture_2d<f32>;
+@group(0) @binding(7)
+var s_splat: sampler;
+#endif
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec2<f32>,
+}
+
+// One of the layers where this fragment is, tiled across the terrain
+fn sample_layer(t: texture_2d<f32>, in: VertexOutput, normal: vec3<f32>) -> vec3<f32> {
+#ifdef TRIPLANAR
+    return triplanar_sample(t, s_layer, in.world_position, dpdx(in.world_position), dpdy(in.world_position), normal, terrain.tile_size).rgb;
+#else
+    return textureSample(t, s_layer, in.tex_coords * terrain.repeat).rgb;
+#endif
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    let normal = normalize(in.normal);
+#ifdef SPLAT
+    let splat = textureSample(t_splat, s_splat, in.tex_coords);
+    // normalised, so that painting more than one layer at a time doesn't brighten the ground
+    let weights = splat / max(splat.r + splat.g + splat.b + splat.a, 0.0001);
+    let albedo = sample_layer(t_layer0, in, normal) * weights.r
+        + sample_layer(t_layer1, in, normal) * weights.g
+        + sample_layer(t_layer2, in, normal) * weights.b
+        + sample_layer(t_layer3, in, normal) * weights.a;
+#else
+    let albedo = sample_layer(t_layer0, in, normal);
+#endif
+    // only after sampling, as texture lookups need every fragment around them to still be running
+    if is_clipped(in.world_position) {
+        discard;
+    }
+    var material: Material;
+    material.albedo = albedo;
+    material.roughness = 1.0;
+    material.metallic = 0.0;
+    let view_direction = normalize(camera.view_position.xyz - in.world_position);
+
+    var out: FragmentOutput;
+    let color = lighting(in.world_position, normal, view_direction, material);
+    out.color = vec4<f32>(apply_fog(color, in.current_position.w), 1.0);
+    let current = in.current_position.xy / in.current_position.w;
+    let previous = in.previous_position.xy / in.previous_position.w;
+    out.velocity = (current - previous) * vec2<f32>(0.5, -0.5);
+    return out;
+}
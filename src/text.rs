@@ -0,0 +1,520 @@
+use std::num::NonZeroU32;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use fontdue::{Font, FontSettings};
+use wgpu::{
+    AddressMode, BindGroup, BindGroupEntry, BindGroupLayout, BindingResource, BlendState, Buffer,
+    BufferAddress, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, CommandEncoder,
+    Device, Extent3d, FilterMode, FragmentState, ImageCopyTexture, ImageDataLayout, LoadOp,
+    MultisampleState, Operations, Origin3d, PipelineLayout, PipelineLayoutDescriptor,
+    PrimitiveState, Queue, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline,
+    RenderPipelineDescriptor, Sampler, SamplerDescriptor, ShaderModule, ShaderModuleDescriptor,
+    ShaderSource, Texture, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat,
+    TextureUsages, TextureView, TextureViewDescriptor, VertexAttribute, VertexBufferLayout,
+    VertexFormat, VertexState, VertexStepMode,
+};
+
+use crate::{
+    assets,
+    reflection::ShaderReflection,
+    shader::{preprocess, ShaderDefs, ShaderLibrary},
+    uniform::UniformBuffer,
+};
+
+/// Hack, a monospaced font, so that columns of text line up
+const FONT: &str = "fonts/Hack-Regular.ttf";
+
+/// The printable ASCII characters, which are all that the atlas contains
+const FIRST_CHAR: char = ' ';
+const LAST_CHAR: char = '~';
+/// The number of glyphs in each row of the atlas
+const ATLAS_COLUMNS: u32 = 16;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct ScreenUniform {
+    size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct TextVertex {
+    position: [f32; 2],
+    tex_coords: [f32; 2],
+    color: [f32; 4],
+}
+
+impl TextVertex {
+    const ATTRIBUTES: [VertexAttribute; 3] = [
+        VertexAttribute {
+            offset: 0,
+            shader_location: 0,
+            format: VertexFormat::Float32x2,
+        },
+        VertexAttribute {
+            offset: std::mem::size_of::<[f32; 2]>() as BufferAddress,
+            shader_location: 1,
+            format: VertexFormat::Float32x2,
+        },
+        VertexAttribute {
+            offset: std::mem::size_of::<[f32; 4]>() as BufferAddress,
+            shader_location: 2,
+            format: VertexFormat::Float32x4,
+        },
+    ];
+
+    fn desc<'a>() -> VertexBufferLayout<'a> {
+        VertexBufferLayout {
+            array_stride: std::mem::size_of::<TextVertex>() as BufferAddress,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// Where a glyph is in the atlas, and how to place it relative to the pen position
+#[derive(Copy, Clone, Debug)]
+struct Glyph {
+    uv_min: [f32; 2],
+    uv_max: [f32; 2],
+    size: [f32; 2],
+    /// From the pen position on the baseline to the top left of the glyph
+    offset: [f32; 2],
+}
+
+/// Immediate-mode text and rectangles drawn in screen space over the final image, e.g. for the log console.
+/// Positions are in physical pixels from the top left of the screen, and the text is rasterised
+/// up front at its size in logical pixels times the scale factor, then again whenever that changes
+pub struct TextRenderer {
+    font: Font,
+    /// The font size in logical pixels
+    size: f32,
+    /// Physical pixels per logical pixel, i.e. the window's scale factor
+    scale: f32,
+    atlas: GlyphAtlas,
+    vertices: Vec<TextVertex>,
+    /// Grows as needed to fit `vertices`
+    buffer: Buffer,
+    screen: UniformBuffer<ScreenUniform>,
+    sampler: Sampler,
+    /// Kept for rebuilding `bind_group` when the atlas is rasterised again
+    reflection: ShaderReflection,
+    bind_group_layout: BindGroupLayout,
+    bind_group: BindGroup,
+    /// Kept for rebuilding `pipeline` when the output's format changes
+    shader: ShaderModule,
+    layout: PipelineLayout,
+    pipeline: RenderPipeline,
+    /// The number of vertices uploaded by the last `prepare`
+    uploaded: u32,
+}
+
+/// Every glyph rasterised at one size, along with the metrics for laying them out
+struct GlyphAtlas {
+    glyphs: Vec<Glyph>,
+    /// The horizontal distance between characters, which is the same for all of them
+    advance: f32,
+    line_height: f32,
+    /// From the top of a line to its baseline
+    ascent: f32,
+    /// The middle of a fully covered part of the atlas, for drawing solid rectangles
+    solid_uv: [f32; 2],
+    _texture: Texture,
+    view: TextureView,
+}
+
+impl GlyphAtlas {
+    /// Rasterise every glyph of `font` at `size` pixels
+    fn new(device: &Device, queue: &Queue, font: &Font, size: f32) -> Result<Self> {
+        let line_metrics = font
+            .horizontal_line_metrics(size)
+            .context("the font has no horizontal line metrics")?;
+        let rasterized = (FIRST_CHAR..=LAST_CHAR)
+            .map(|c| font.rasterize(c, size))
+            .collect::<Vec<_>>();
+
+        // Every glyph gets a cell of the same size, with a pixel of padding so that they don't bleed into each other,
+        // plus one extra cell which is filled in for drawing rectangles
+        let cell_width = rasterized
+            .iter()
+            .map(|(metrics, _)| metrics.width)
+            .max()
+            .unwrap_or(0) as u32
+            + 1;
+        let cell_height = rasterized
+            .iter()
+            .map(|(metrics, _)| metrics.height)
+            .max()
+            .unwrap_or(0) as u32
+            + 1;
+        let cells = rasterized.len() as u32 + 1;
+        let width = ATLAS_COLUMNS * cell_width;
+        let height = cells.div_ceil(ATLAS_COLUMNS) * cell_height;
+        let cell_origin = |index: u32| {
+            (
+                index % ATLAS_COLUMNS * cell_width,
+                index / ATLAS_COLUMNS * cell_height,
+            )
+        };
+
+        let mut data = vec![0; (width * height) as usize];
+        let mut glyphs = Vec::with_capacity(rasterized.len());
+        for (index, (metrics, bitmap)) in rasterized.iter().enumerate() {
+            let (x, y) = cell_origin(index as u32);
+            for row in 0..metrics.height {
+                let start = (y as usize + row) * width as usize + x as usize;
+                data[start..start + metrics.width]
+                    .copy_from_slice(&bitmap[row * metrics.width..(row + 1) * metrics.width]);
+            }
+            glyphs.push(Glyph {
+                uv_min: [x as f32 / width as f32, y as f32 / height as f32],
+                uv_max: [
+                    (x as usize + metrics.width) as f32 / width as f32,
+                    (y as usize + metrics.height) as f32 / height as f32,
+                ],
+                size: [metrics.width as f32, metrics.height as f32],
+                // fontdue's y axis points up, from the baseline to the bottom of the glyph
+                offset: [
+                    metrics.xmin as f32,
+                    -(metrics.ymin as f32 + metrics.height as f32),
+                ],
+            });
+        }
+        let (x, y) = cell_origin(cells - 1);
+        for row in y..y + cell_height - 1 {
+            let start = (row * width + x) as usize;
+            data[start..start + cell_width as usize - 1].fill(u8::MAX);
+        }
+        let solid_uv = [
+            (x as f32 + 0.5 * (cell_width - 1) as f32) / width as f32,
+            (y as f32 + 0.5 * (cell_height - 1) as f32) / height as f32,
+        ];
+        let advance = font.metrics('M', size).advance_width;
+
+        let extent = Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let atlas = device.create_texture(&TextureDescriptor {
+            label: Some("Glyph Atlas"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        });
+        queue.write_texture(
+            ImageCopyTexture {
+                aspect: TextureAspect::All,
+                texture: &atlas,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+            },
+            &data,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(width),
+                rows_per_image: NonZeroU32::new(height),
+            },
+            extent,
+        );
+        Ok(Self {
+            glyphs,
+            advance,
+            line_height: line_metrics.new_line_size.ceil(),
+            ascent: line_metrics.ascent.ceil(),
+            solid_uv,
+            view: atlas.create_view(&TextureViewDescriptor::default()),
+            _texture: atlas,
+        })
+    }
+}
+
+impl TextRenderer {
+    /// `size` is the font size in logical pixels, `scale` is the window's scale factor,
+    /// and `format` must match the view passed to `render`
+    #[tracing::instrument(skip(device, queue, library))]
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        library: &ShaderLibrary,
+        format: TextureFormat,
+        size: f32,
+        scale: f32,
+    ) -> Result<Self> {
+        let font = Font::from_bytes(assets::load(FONT)?, FontSettings::default())
+            .map_err(|error| anyhow!(error))?;
+        let atlas = GlyphAtlas::new(device, queue, &font, size * scale)?;
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Glyph Atlas Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            // glyphs are drawn at the size they were rasterised at, so there's nothing to interpolate
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+        let screen = UniformBuffer::new(
+            device,
+            ScreenUniform {
+                size: [1.0; 2],
+                _padding: [0.0; 2],
+            },
+            Some("Text Screen Size"),
+        );
+
+        let name = "text.wgsl";
+        let source = preprocess(&library.resolve(name)?, &ShaderDefs::new())?;
+        let reflection = ShaderReflection::from_code(&source.clone().into(), &ShaderDefs::new())
+            .with_context(|| format!("failed to reflect {name}"))?;
+        let bind_group_layout = reflection.create_bind_group_layout(device, 0, Some(name));
+        let bind_group = create_bind_group(
+            device,
+            &reflection,
+            &bind_group_layout,
+            &screen,
+            &atlas.view,
+            &sampler,
+        )?;
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(name),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some(name),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_pipeline(device, &shader, &layout, format);
+
+        Ok(Self {
+            font,
+            size,
+            scale,
+            atlas,
+            vertices: Vec::new(),
+            buffer: create_buffer(device, 4096),
+            screen,
+            sampler,
+            reflection,
+            bind_group_layout,
+            bind_group,
+            shader,
+            layout,
+            pipeline,
+            uploaded: 0,
+        })
+    }
+
+    /// Rasterise the text again for a new scale factor, e.g. when the window moves to a monitor
+    /// with a different DPI, so that it stays sharp and the same size to the eye
+    pub fn set_scale(&mut self, device: &Device, queue: &Queue, scale: f32) -> Result<()> {
+        if scale == self.scale {
+            return Ok(());
+        }
+        let atlas = GlyphAtlas::new(device, queue, &self.font, self.size * scale)?;
+        self.bind_group = create_bind_group(
+            device,
+            &self.reflection,
+            &self.bind_group_layout,
+            &self.screen,
+            &atlas.view,
+            &self.sampler,
+        )?;
+        self.atlas = atlas;
+        self.scale = scale;
+        Ok(())
+    }
+
+    /// Physical pixels per logical pixel
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// `logical` pixels in physical pixels, rounded to a whole pixel, for sizing things on screen
+    /// consistently across displays
+    pub fn pixels(&self, logical: f32) -> f32 {
+        (logical * self.scale).round()
+    }
+
+    /// Draw to views of another format from now on, e.g. when the surface's format changes
+    pub fn set_format(&mut self, device: &Device, format: TextureFormat) {
+        self.pipeline = create_pipeline(device, &self.shader, &self.layout, format);
+    }
+
+    /// The width of a single character
+    pub fn advance(&self) -> f32 {
+        self.atlas.advance
+    }
+
+    /// The distance between the tops of consecutive lines
+    pub fn line_height(&self) -> f32 {
+        self.atlas.line_height
+    }
+
+    /// Forget everything added since the last `clear`
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    /// A filled rectangle from `min` (top left) to `max` (bottom right)
+    pub fn rect(&mut self, min: [f32; 2], max: [f32; 2], color: [f32; 4]) {
+        self.quad(min, max, self.atlas.solid_uv, self.atlas.solid_uv, color);
+    }
+
+    /// A single line of text with its top left corner at `position`, returning where the line ends.
+    /// Anything which isn't printable ASCII is drawn as `?`
+    pub fn text(&mut self, position: [f32; 2], text: &str, color: [f32; 4]) -> f32 {
+        let [mut x, y] = position;
+        let baseline = (y + self.atlas.ascent).round();
+        for c in text.chars() {
+            let c = match c {
+                '\t' => ' ',
+                FIRST_CHAR..=LAST_CHAR => c,
+                _ => '?',
+            };
+            let glyph = self.atlas.glyphs[c as usize - FIRST_CHAR as usize];
+            if glyph.size[0] > 0.0 && glyph.size[1] > 0.0 {
+                let min = [(x + glyph.offset[0]).round(), baseline + glyph.offset[1]];
+                let max = [min[0] + glyph.size[0], min[1] + glyph.size[1]];
+                self.quad(min, max, glyph.uv_min, glyph.uv_max, color);
+            }
+            x += self.atlas.advance;
+        }
+        x
+    }
+
+    fn quad(
+        &mut self,
+        min: [f32; 2],
+        max: [f32; 2],
+        uv_min: [f32; 2],
+        uv_max: [f32; 2],
+        color: [f32; 4],
+    ) {
+        let vertex = |x: usize, y: usize| TextVertex {
+            position: [[min[0], max[0]][x], [min[1], max[1]][y]],
+            tex_coords: [[uv_min[0], uv_max[0]][x], [uv_min[1], uv_max[1]][y]],
+            color,
+        };
+        self.vertices.extend([
+            vertex(0, 0),
+            vertex(0, 1),
+            vertex(1, 0),
+            vertex(1, 0),
+            vertex(0, 1),
+            vertex(1, 1),
+        ]);
+    }
+
+    /// Upload everything added since the last `clear`, for a screen of the given size
+    pub fn prepare(&mut self, device: &Device, queue: &Queue, width: u32, height: u32) {
+        self.screen.get_mut().size = [width as f32, height as f32];
+        self.screen.write(queue);
+
+        let size = std::mem::size_of_val(self.vertices.as_slice()) as BufferAddress;
+        if size > self.buffer.size() {
+            self.buffer = create_buffer(device, size.next_power_of_two());
+        }
+        if size > 0 {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.vertices));
+        }
+        self.uploaded = self.vertices.len() as u32;
+    }
+
+    /// Draw everything uploaded by the last `prepare` over the top of `output`
+    pub fn render(&self, encoder: &mut CommandEncoder, output: &TextureView) {
+        puffin::profile_function!();
+        if self.uploaded == 0 {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Text Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+        render_pass.draw(0..self.uploaded, 0..1);
+    }
+}
+
+fn create_pipeline(
+    device: &Device,
+    shader: &ShaderModule,
+    layout: &PipelineLayout,
+    format: TextureFormat,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("text.wgsl"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[TextVertex::desc()],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format,
+                blend: Some(BlendState::ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+fn create_bind_group(
+    device: &Device,
+    reflection: &ShaderReflection,
+    layout: &BindGroupLayout,
+    screen: &UniformBuffer<ScreenUniform>,
+    atlas: &TextureView,
+    sampler: &Sampler,
+) -> Result<BindGroup> {
+    reflection.create_bind_group(
+        device,
+        0,
+        layout,
+        &[
+            screen.bind_group_entry(0),
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(atlas),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::Sampler(sampler),
+            },
+        ],
+        Some("text_bind_group"),
+    )
+}
+
+fn create_buffer(device: &Device, size: BufferAddress) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("Text Vertices"),
+        size,
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
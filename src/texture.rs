@@ -1,6 +1,7 @@
 use std::num::NonZeroU32;
 
 use anyhow::*;
+use half::f16;
 use image::GenericImageView;
 use wgpu::{
     AddressMode, Device, Extent3d, FilterMode, ImageCopyTexture, ImageDataLayout, Origin3d, Queue,
@@ -8,28 +9,171 @@ use wgpu::{
     TextureFormat, TextureUsages, TextureView, TextureViewDescriptor,
 };
 
+/// What an image loaded into a texture holds, which decides the texture's format
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureKind {
+    /// Colours authored in sRGB, like albedo/diffuse or emissive, which the GPU converts to linear when sampled
+    Albedo,
+    /// A tangent-space normal map, which is linear
+    Normal,
+    /// Anything else which isn't a colour, like roughness, heights or a splat map, which is also linear
+    Data,
+    /// Linear colours which can be brighter than 1, e.g. from a `.hdr` image, kept as half floats
+    Hdr,
+}
+
+impl TextureKind {
+    pub fn format(self) -> TextureFormat {
+        match self {
+            Self::Albedo => TextureFormat::Rgba8UnormSrgb,
+            Self::Normal | Self::Data => TextureFormat::Rgba8Unorm,
+            Self::Hdr => TextureFormat::Rgba16Float,
+        }
+    }
+
+    /// Whether the texels are stored in sRGB, and so converted to linear when sampled
+    pub fn is_srgb(self) -> bool {
+        self == Self::Albedo
+    }
+}
+
+/// A texture along with the view and sampler needed to bind it.
+///
+/// Images are loaded as a `TextureKind`, which keeps colours and data apart: colours are authored in sRGB
+/// and stored that way, but loading a normal or roughness map as sRGB would "gamma correct" it and it'd come out wrong
 pub struct OurTexture {
     pub texture: Texture,
     pub view: TextureView,
     pub sampler: Sampler,
+    /// What `texture` was created with, which wgpu doesn't keep track of
+    pub size: Extent3d,
+    pub format: TextureFormat,
 }
 
 impl OurTexture {
-    pub fn from_bytes(device: &Device, queue: &Queue, bytes: &[u8], label: &str) -> Result<Self> {
+    /// Depth with a stencil buffer alongside it, which passes can mark pixels in to mask later draws with
+    pub const DEPTH_FORMAT: TextureFormat = TextureFormat::Depth24PlusStencil8;
+
+    /// A depth buffer of the given size, which later passes can also read from through `create_depth_view`
+    pub fn create_depth_texture(device: &Device, width: u32, height: u32, label: &str) -> Self {
+        Self::create_render_target(device, width, height, Self::DEPTH_FORMAT, label)
+    }
+
+    /// A view of only the depth in a texture from `create_depth_texture`, for binding it to a shader,
+    /// as `view` covers the stencil as well and so can only be attached to a pass
+    pub fn create_depth_view(&self) -> TextureView {
+        self.texture.create_view(&TextureViewDescriptor {
+            label: Some("Depth View"),
+            aspect: TextureAspect::DepthOnly,
+            ..Default::default()
+        })
+    }
+
+    /// A texture of the given size and format which can be both rendered to and read from later on
+    pub fn create_render_target(
+        device: &Device,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        label: &str,
+    ) -> Self {
+        Self::create_multisampled_target(
+            device,
+            width,
+            height,
+            format,
+            1,
+            TextureUsages::TEXTURE_BINDING,
+            label,
+        )
+    }
+
+    /// A render target with `sample_count` samples per pixel, e.g. for drawing into and resolving
+    /// into a single-sampled one, which can also be used for whatever `usage` asks for
+    pub fn create_multisampled_target(
+        device: &Device,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        sample_count: u32,
+        usage: TextureUsages,
+        label: &str,
+    ) -> Self {
+        let size = Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::RENDER_ATTACHMENT | usage,
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Nearest,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+            size,
+            format,
+        }
+    }
+
+    #[tracing::instrument(skip(device, queue, bytes))]
+    pub fn from_bytes(
+        device: &Device,
+        queue: &Queue,
+        bytes: &[u8],
+        label: &str,
+        kind: TextureKind,
+    ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        Self::from_image(device, queue, &img, Some(label), kind)
     }
 
+    #[tracing::instrument(skip(device, queue, img))]
     pub fn from_image(
         device: &Device,
         queue: &Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
+        kind: TextureKind,
     ) -> Result<Self> {
         // Note: we're using `.to_rgba8()` rather than `.as_rgba8()` as the latter requires an alpha channel
         // This means if it is called on a JPEG, a panic will occur
-        let rgba = img.to_rgba8();
+        let (texels, bytes_per_texel) = match kind {
+            TextureKind::Hdr => {
+                let texels = img
+                    .to_rgba32f()
+                    .into_raw()
+                    .into_iter()
+                    .map(f16::from_f32)
+                    .collect::<Vec<_>>();
+                (bytemuck::cast_slice(&texels).to_vec(), 8)
+            }
+            _ => (img.to_rgba8().into_raw(), 4),
+        };
         let dimensions = img.dimensions();
+        ensure!(
+            dimensions.0 > 0 && dimensions.1 > 0,
+            "can't make a texture from a {}x{} image",
+            dimensions.0,
+            dimensions.1
+        );
 
         let size = Extent3d {
             width: dimensions.0,
@@ -42,7 +186,7 @@ impl OurTexture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: TextureDimension::D2,
-            format: TextureFormat::Rgba8UnormSrgb,
+            format: kind.format(),
             usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
         });
 
@@ -53,10 +197,10 @@ impl OurTexture {
                 mip_level: 0,
                 origin: Origin3d::ZERO,
             },
-            &rgba,
+            &texels,
             ImageDataLayout {
                 offset: 0,
-                bytes_per_row: NonZeroU32::new(4 * dimensions.0),
+                bytes_per_row: NonZeroU32::new(bytes_per_texel * dimensions.0),
                 rows_per_image: NonZeroU32::new(dimensions.1),
             },
             size,
@@ -77,6 +221,8 @@ impl OurTexture {
             texture,
             view,
             sampler,
+            size,
+            format: kind.format(),
         })
     }
 }
@@ -0,0 +1,98 @@
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+use crate::{
+    math::{InnerSpace, Vector3},
+    sky::Sun,
+};
+
+/// A clock running through the day, which moves the sun across the sky and so changes the sky's colours,
+/// the sun's light and the ambient light along with it.
+/// `T` pauses it, and `[` and `]` scrub it backwards and forwards
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeOfDay {
+    /// Hours since midnight, from 0 up to 24
+    hour: f32,
+    /// How many seconds a whole day takes
+    pub cycle_length: f32,
+    pub paused: bool,
+    /// How high the sun gets at midday, in degrees above the horizon
+    pub max_elevation: f32,
+}
+
+impl Default for TimeOfDay {
+    /// Mid-morning, with a day lasting two minutes
+    fn default() -> Self {
+        Self {
+            hour: 9.0,
+            cycle_length: 120.0,
+            paused: false,
+            max_elevation: 60.0,
+        }
+    }
+}
+
+impl TimeOfDay {
+    /// How far each press of `[` or `]` moves the clock
+    const SCRUB_HOURS: f32 = 0.5;
+
+    pub fn hour(&self) -> f32 {
+        self.hour
+    }
+
+    /// Jump to `hour` hours since midnight, wrapping around into the next or previous day
+    pub fn set_hour(&mut self, hour: f32) {
+        self.hour = hour.rem_euclid(24.0);
+    }
+
+    /// Move the clock on by `hours`, or back if it's negative, even while paused
+    pub fn scrub(&mut self, hours: f32) {
+        self.set_hour(self.hour + hours);
+    }
+
+    /// Move the clock on by `delta` real seconds, unless it's paused
+    pub fn advance(&mut self, delta: f32) {
+        if !self.paused && self.cycle_length > 0.0 {
+            self.scrub(delta / self.cycle_length * 24.0);
+        }
+    }
+
+    /// Put `sun` where it is at this time of day. It rises in the east (+x) at 6:00,
+    /// is highest in the south (+z) at 12:00 and sets in the west at 18:00
+    pub fn place_sun(&self, sun: &mut Sun) {
+        let angle = (self.hour - 6.0) / 12.0 * std::f32::consts::PI;
+        let tilt = (90.0 - self.max_elevation).to_radians();
+        let overhead = Vector3::unit_y() * tilt.cos() + Vector3::unit_z() * tilt.sin();
+        let direction = (Vector3::unit_x() * angle.cos() + overhead * angle.sin()).normalize();
+        sun.elevation = direction.y.asin().to_degrees();
+        sun.azimuth = direction.x.atan2(-direction.z).to_degrees();
+    }
+
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(keycode),
+                        ..
+                    },
+                ..
+            } => match keycode {
+                VirtualKeyCode::T => {
+                    self.paused = !self.paused;
+                    true
+                }
+                VirtualKeyCode::LBracket => {
+                    self.scrub(-Self::SCRUB_HOURS);
+                    true
+                }
+                VirtualKeyCode::RBracket => {
+                    self.scrub(Self::SCRUB_HOURS);
+                    true
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
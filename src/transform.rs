@@ -0,0 +1,158 @@
+use std::ops::Mul;
+
+use crate::math::{
+    ElementWise, EuclideanSpace, InnerSpace, Matrix3, Matrix4, Point3, Quaternion, Rotation,
+    SquareMatrix, Vector3, VectorSpace,
+};
+
+/// A position, orientation and size, stored separately rather than as a matrix
+/// so that each part can be changed (or interpolated) on its own
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Transform {
+    /// Leaves everything where it is
+    pub const IDENTITY: Self = Self {
+        translation: Vector3::new(0.0, 0.0, 0.0),
+        rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        scale: Vector3::new(1.0, 1.0, 1.0),
+    };
+
+    pub fn from_translation(translation: Vector3<f32>) -> Self {
+        Self {
+            translation,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn from_rotation(rotation: Quaternion<f32>) -> Self {
+        Self {
+            rotation,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn from_scale(scale: Vector3<f32>) -> Self {
+        Self {
+            scale,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn with_translation(self, translation: Vector3<f32>) -> Self {
+        Self {
+            translation,
+            ..self
+        }
+    }
+
+    pub fn with_rotation(self, rotation: Quaternion<f32>) -> Self {
+        Self { rotation, ..self }
+    }
+
+    pub fn with_scale(self, scale: Vector3<f32>) -> Self {
+        Self { scale, ..self }
+    }
+
+    /// Scales, then rotates, then translates
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    /// The matrix for transforming normals, which have to be scaled by the inverse of `scale`
+    /// to stay perpendicular to their surface (the inverse transpose of the upper 3x3 of `to_matrix`)
+    pub fn normal_matrix(&self) -> Matrix3<f32> {
+        Matrix3::from(self.rotation)
+            * Matrix3::from_diagonal(Vector3::new(1.0, 1.0, 1.0).div_element_wise(self.scale))
+    }
+
+    pub fn transform_point(&self, point: Point3<f32>) -> Point3<f32> {
+        Point3::from_vec(self.transform_vector(point.to_vec()) + self.translation)
+    }
+
+    /// Scale and rotate `vector`, without translating it
+    pub fn transform_vector(&self, vector: Vector3<f32>) -> Vector3<f32> {
+        self.rotation
+            .rotate_vector(vector.mul_element_wise(self.scale))
+    }
+
+    /// The direction the transform's local -z axis points in, i.e. what it's looking at
+    pub fn forward(&self) -> Vector3<f32> {
+        self.rotation.rotate_vector(-Vector3::unit_z())
+    }
+
+    /// Rotate so that `forward` points towards `target`, with the local y axis as close to `up` as possible
+    pub fn look_at(&mut self, target: Point3<f32>, up: Vector3<f32>) {
+        self.look_to(
+            (target - Point3::from_vec(self.translation)).normalize(),
+            up,
+        );
+    }
+
+    /// Rotate so that `forward` points along `direction`, with the local y axis as close to `up` as possible
+    pub fn look_to(&mut self, direction: Vector3<f32>, up: Vector3<f32>) {
+        let back = -direction.normalize();
+        let right = up.cross(back).normalize();
+        let up = back.cross(right);
+        self.rotation = Quaternion::from(Matrix3::from_cols(right, up, back));
+    }
+
+    pub fn looking_at(mut self, target: Point3<f32>, up: Vector3<f32>) -> Self {
+        self.look_at(target, up);
+        self
+    }
+
+    /// Interpolate between `self` (at `amount` 0.0) and `other` (at 1.0),
+    /// using the cheaper normalised lerp for the rotation, which is fine when the rotations are close
+    pub fn lerp(&self, other: &Self, amount: f32) -> Self {
+        Self {
+            translation: self.translation.lerp(other.translation, amount),
+            rotation: self.rotation.nlerp(other.rotation, amount),
+            scale: self.scale.lerp(other.scale, amount),
+        }
+    }
+
+    /// Interpolate between `self` and `other` like `lerp`,
+    /// but rotating at a constant speed, which matters when the rotations are far apart
+    pub fn slerp(&self, other: &Self, amount: f32) -> Self {
+        Self {
+            rotation: self.rotation.slerp(other.rotation, amount),
+            ..self.lerp(other, amount)
+        }
+    }
+}
+
+/// `parent * child` gives the child's transform relative to whatever the parent is relative to.
+/// Like with matrices, this isn't exact when the parent has a non-uniform scale and the child is rotated,
+/// as that would shear the child, which a `Transform` can't represent
+impl Mul for Transform {
+    type Output = Self;
+
+    fn mul(self, child: Self) -> Self {
+        Self {
+            translation: self
+                .transform_point(Point3::from_vec(child.translation))
+                .to_vec(),
+            rotation: self.rotation * child.rotation,
+            scale: self.scale.mul_element_wise(child.scale),
+        }
+    }
+}
+
+impl From<Transform> for Matrix4<f32> {
+    fn from(transform: Transform) -> Self {
+        transform.to_matrix()
+    }
+}
@@ -0,0 +1,11 @@
+use crate::shader::ShaderDefs;
+
+/// The defines which select the triplanar permutation of `shader.wgsl`, which textures meshes by where
+/// they are in the world rather than by their texture co-ordinates, repeating every `tile_size` world units.
+/// For meshes whose texture co-ordinates are missing or stretched, like generated shapes
+pub fn triplanar_defs(defs: &ShaderDefs, tile_size: f32) -> ShaderDefs {
+    // `{:?}` always writes a decimal point, which WGSL needs to read it as a float
+    defs.clone()
+        .flag("TRIPLANAR")
+        .value("TRIPLANAR_TILE_SIZE", format!("{tile_size:?}"))
+}
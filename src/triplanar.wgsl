@@ -0,0 +1,38 @@
+// Triplanar mapping, which textures a surface by its position rather than its texture co-ordinates,
+// projecting the texture along each of the world's axes and blending between them by which way the surface faces
+
+// How much each axis' projection counts towards a surface facing `normal`, adding up to 1.
+// Sharpened so that only surfaces at an angle blend between projections
+fn triplanar_weights(normal: vec3<f32>) -> vec3<f32> {
+    let weights = pow(abs(normal), vec3<f32>(4.0));
+    return weights / (weights.x + weights.y + weights.z);
+}
+
+// One projection, which wraps around itself so that it tiles whatever `s` does at the edges.
+// The gradients are from before wrapping, otherwise the jump back to 0 would pick the smallest mip level
+fn triplanar_plane(t: texture_2d<f32>, s: sampler, uv: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> {
+    return textureSampleGrad(t, s, fract(uv), ddx, ddy);
+}
+
+// Sample `t` at `world_position`, repeating every `tile_size` world units.
+// `position_dx` and `position_dy` are `dpdx` and `dpdy` of `world_position`, which only fragment shaders
+// can work out, and this is compiled into vertex shaders too
+fn triplanar_sample(
+    t: texture_2d<f32>,
+    s: sampler,
+    world_position: vec3<f32>,
+    position_dx: vec3<f32>,
+    position_dy: vec3<f32>,
+    normal: vec3<f32>,
+    tile_size: f32,
+) -> vec4<f32> {
+    let position = world_position / tile_size;
+    let dx = position_dx / tile_size;
+    let dy = position_dy / tile_size;
+    let weights = triplanar_weights(normal);
+    // texture co-ordinates go downwards, so flip the vertical ones to keep the sides the right way up
+    let flip = vec2<f32>(1.0, -1.0);
+    return triplanar_plane(t, s, position.zy * flip, dx.zy * flip, dy.zy * flip) * weights.x
+        + triplanar_plane(t, s, position.xz, dx.xz, dy.xz) * weights.y
+        + triplanar_plane(t, s, position.xy * flip, dx.xy * flip, dy.xy * flip) * weights.z;
+}
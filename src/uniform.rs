@@ -0,0 +1,89 @@
+use std::mem::size_of;
+
+use bytemuck::Pod;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, Buffer, BufferUsages, Device,
+    Queue,
+};
+
+/// A uniform buffer holding a single `T`, along with a CPU-side copy which is uploaded on `write`
+pub struct UniformBuffer<T: Pod> {
+    value: T,
+    buffer: Buffer,
+    /// Whether `value` has changed since it was last written to `buffer`
+    dirty: bool,
+}
+
+impl<T: Pod> UniformBuffer<T> {
+    /// Uniform buffers follow std140-like layout rules, where structs are padded out to 16 bytes,
+    /// so a `T` whose size isn't a multiple of 16 is missing the padding the shader expects
+    const LAYOUT_CHECK: () = assert!(
+        size_of::<T>().is_multiple_of(16),
+        "uniform types must be padded to a multiple of 16 bytes"
+    );
+
+    pub fn new(device: &Device, value: T, label: Option<&str>) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::LAYOUT_CHECK;
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label,
+            contents: bytemuck::bytes_of(&value),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+        Self {
+            value,
+            buffer,
+            dirty: false,
+        }
+    }
+
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    /// Mutable access to the CPU-side value, which will be uploaded on the next `write`
+    pub fn get_mut(&mut self) -> &mut T {
+        self.dirty = true;
+        &mut self.value
+    }
+
+    pub fn set(&mut self, value: &T) {
+        self.value = *value;
+        self.dirty = true;
+    }
+
+    /// Upload the value to the GPU if it has changed since the last write
+    pub fn write(&mut self, queue: &Queue) {
+        if self.dirty {
+            queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&self.value));
+            self.dirty = false;
+        }
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn bind_group_entry(&self, binding: u32) -> BindGroupEntry<'_> {
+        BindGroupEntry {
+            binding,
+            resource: self.buffer.as_entire_binding(),
+        }
+    }
+
+    /// Create a bind group containing just this buffer at binding 0
+    pub fn create_bind_group(
+        &self,
+        device: &Device,
+        layout: &BindGroupLayout,
+        label: Option<&str>,
+    ) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            layout,
+            entries: &[self.bind_group_entry(0)],
+            label,
+        })
+    }
+}
@@ -0,0 +1,39 @@
+//! Events posted into the event loop from other threads, e.g. an asset loader that's finished
+//! or a script driving the camera, which `run` handles alongside the window's own events.
+//! Anything holding an `EventProxy` can post them, and it can be cloned and sent to as many threads as needed
+
+use std::path::PathBuf;
+
+use winit::event_loop::EventLoopProxy;
+
+use crate::{fog::FogMode, math::Point3, transform::Transform, vertex::Vertex};
+
+/// Posts `UserEvent`s into the event loop, from any thread.
+/// `send_event` fails once the event loop has exited
+pub type EventProxy = EventLoopProxy<UserEvent>;
+
+#[derive(Clone, Debug)]
+pub enum UserEvent {
+    /// Replace the shader `name` in the shader library with `source`, see `State::reload_shader`
+    ReloadShader { name: String, source: String },
+    /// Move the camera to `eye`, looking at `target`
+    SetCamera {
+        eye: Point3<f32>,
+        target: Point3<f32>,
+    },
+    /// Save the next frame as a PNG, see `Recorder::screenshot`
+    Screenshot(PathBuf),
+    /// Add a mesh to the scene, see `State::add_mesh`
+    AddMesh {
+        vertices: Vec<Vertex>,
+        indices: Vec<u16>,
+        transform: Transform,
+        tint: [f32; 3],
+    },
+    /// Paint the scene with the image at this path, see `State::set_texture`
+    LoadTexture(PathBuf),
+    /// Change how the fog thickens with distance
+    SetFog(FogMode),
+    /// Show or hide the gizmos, see `State::show_gizmos`
+    ShowGizmos(bool),
+}
@@ -0,0 +1,410 @@
+use std::ops::Range;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    BindGroup, BindGroupLayout, Device, PipelineLayoutDescriptor, Queue, RenderPass, TextureFormat,
+};
+
+use crate::{
+    buffer_pool::{Allocation, BufferPool},
+    geometry::{Aabb, Frustum},
+    instance::{Instance, InstanceRaw},
+    math::{InnerSpace, Matrix4, Point3, Quaternion, Rad, Rotation3, Vector3},
+    mesh::Mesh,
+    pipeline::PipelineCache,
+    reflection::ShaderReflection,
+    shader::{ShaderCode, ShaderDefs, ShaderLibrary},
+    transform::Transform,
+    uniform::UniformBuffer,
+    vertex::Vertex,
+};
+
+/// How the wind bends the vegetation over
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Wind {
+    /// Which way it blows along x and z, which doesn't need to be normalised
+    pub direction: [f32; 2],
+    /// How far the tips of the blades bend, as a fraction of how tall they are
+    pub strength: f32,
+    /// How many times a second the blades sway back and forth
+    pub frequency: f32,
+}
+
+impl Default for Wind {
+    fn default() -> Self {
+        Self {
+            direction: [1.0, 0.4],
+            strength: 0.3,
+            frequency: 0.5,
+        }
+    }
+}
+
+/// How much vegetation to scatter over the ground, and where
+#[derive(Clone, Debug)]
+pub struct VegetationConfig {
+    /// How many tufts of grass to scatter
+    pub count: u32,
+    /// Half the width of the square they're scattered over, centred on the origin.
+    /// Anywhere in it without any ground under it is left bare
+    pub extent: f32,
+    /// The shortest and tallest a tuft can be, in world units
+    pub heights: [f32; 2],
+    /// The same seed always scatters the tufts in the same places
+    pub seed: u64,
+    pub wind: Wind,
+}
+
+impl Default for VegetationConfig {
+    fn default() -> Self {
+        Self {
+            count: 20_000,
+            // the same as the floor
+            extent: 10.0,
+            heights: [0.2, 0.45],
+            seed: 0,
+            wind: Wind::default(),
+        }
+    }
+}
+
+/// The layout `vegetation.wgsl` expects
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+struct WindUniform {
+    direction: [f32; 2],
+    strength: f32,
+    frequency: f32,
+    time: f32,
+    previous_time: f32,
+    _padding: [f32; 2],
+}
+
+impl WindUniform {
+    fn update(&mut self, wind: &Wind, time: f32) {
+        let [x, z] = wind.direction;
+        let length = (x * x + z * z).sqrt();
+        self.direction = if length > 0.0 {
+            [x / length, z / length]
+        } else {
+            [0.0; 2]
+        };
+        self.strength = wind.strength;
+        self.frequency = wind.frequency;
+        self.previous_time = self.time;
+        self.time = time;
+    }
+}
+
+/// A square of the scattered tufts, drawn or skipped as a whole
+struct VegetationCell {
+    /// Which of the vegetation's instances are in the cell
+    instances: Range<u32>,
+    bounds: Aabb,
+}
+
+/// Thousands of tufts of grass scattered over the ground, swaying in the wind.
+/// They're all instances of the same mesh, grouped into cells so that only the ones the camera
+/// can see are drawn, which neighbouring cells share a draw call for
+pub struct Vegetation {
+    mesh: Mesh,
+    /// Sorted by cell, so that each cell's instances are next to each other
+    instance_buffer: Allocation,
+    cells: Vec<VegetationCell>,
+    wind: Wind,
+    uniform: UniformBuffer<WindUniform>,
+    bind_group: BindGroup,
+    pipeline_cache: PipelineCache,
+    defs: ShaderDefs,
+}
+
+impl Vegetation {
+    /// How far each cell is across, in world units
+    const CELL_SIZE: f32 = 4.0;
+
+    /// Scatter the tufts described by `config` over the ground, which `ground_height` gives the height of
+    /// at each `(x, z)`, or `None` where there isn't any. `camera_layout` and `light_layout` are the scene's,
+    /// and `formats` and `depth_format` must match the scene pass
+    #[allow(clippy::too_many_arguments)]
+    #[tracing::instrument(skip_all, fields(count = config.count))]
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        library: &ShaderLibrary,
+        config: &VegetationConfig,
+        ground_height: impl Fn(f32, f32) -> Option<f32>,
+        camera_layout: &BindGroupLayout,
+        light_layout: &BindGroupLayout,
+        formats: Vec<TextureFormat>,
+        depth_format: TextureFormat,
+        vertex_pool: &mut BufferPool,
+        index_pool: &mut BufferPool,
+    ) -> Result<Self> {
+        ensure!(
+            config.extent > 0.0,
+            "vegetation needs somewhere to grow, not an extent of {}",
+            config.extent
+        );
+        let [shortest, tallest] = config.heights;
+        ensure!(
+            0.0 < shortest && shortest <= tallest,
+            "vegetation can't be between {shortest} and {tallest} units tall"
+        );
+
+        let cells_across = (2.0 * config.extent / Self::CELL_SIZE).ceil().max(1.0) as usize;
+        let cell_of = |coordinate: f32| {
+            (((coordinate + config.extent) / Self::CELL_SIZE) as usize).min(cells_across - 1)
+        };
+        let mut rng = Rng::new(config.seed);
+        let mut scattered = vec![Vec::new(); cells_across * cells_across];
+        for _ in 0..config.count {
+            let x = rng.range(-config.extent, config.extent);
+            let z = rng.range(-config.extent, config.extent);
+            let yaw = rng.range(0.0, std::f32::consts::TAU);
+            let height = rng.range(shortest, tallest);
+            // from yellowish to a deeper green
+            let tint = rng.range(0.0, 1.0);
+            let Some(y) = ground_height(x, z) else {
+                continue;
+            };
+            let instance = Instance {
+                transform: Transform::from_translation(Vector3::new(x, y, z))
+                    .with_rotation(Quaternion::from_angle_y(Rad(yaw)))
+                    // a little wider as they get taller, but not in proportion
+                    .with_scale(Vector3::new(height.sqrt(), height, height.sqrt())),
+                tint: [0.3 - 0.15 * tint, 0.55 - 0.15 * tint, 0.1],
+                roughness: 0.8,
+                metallic: 0.0,
+                emissive: [0.0; 3],
+                displacement: 0.0,
+                transmission: 0.0,
+                texture: 0,
+            };
+            scattered[cell_of(z) * cells_across + cell_of(x)].push(instance);
+        }
+
+        let mut instances = Vec::with_capacity(config.count as usize);
+        let mut cells = Vec::new();
+        // the furthest a tip can bend, so that swaying tufts near a cell's edge aren't culled
+        let reach = tallest * config.wind.strength.abs();
+        for cell in scattered.iter().filter(|cell| !cell.is_empty()) {
+            let start = instances.len() as u32;
+            let (mut min, mut max) = (
+                Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+                Point3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+            );
+            for instance in cell {
+                let Transform {
+                    translation, scale, ..
+                } = instance.transform;
+                let radius = Tuft::RADIUS * scale.x + reach;
+                min.x = min.x.min(translation.x - radius);
+                min.y = min.y.min(translation.y);
+                min.z = min.z.min(translation.z - radius);
+                max.x = max.x.max(translation.x + radius);
+                max.y = max.y.max(translation.y + scale.y);
+                max.z = max.z.max(translation.z + radius);
+                instances.push(instance.to_raw());
+            }
+            cells.push(VegetationCell {
+                instances: start..instances.len() as u32,
+                bounds: Aabb { min, max },
+            });
+        }
+        tracing::info!(
+            "Scattered {} tufts of grass into {} cells",
+            instances.len(),
+            cells.len()
+        );
+        let instance_buffer =
+            vertex_pool.allocate_init(device, queue, bytemuck::cast_slice(&instances));
+        let (vertices, indices) = Tuft::mesh();
+        let mesh = Mesh::new(device, queue, vertex_pool, index_pool, &vertices, &indices);
+
+        let defs = ShaderDefs::new();
+        let code = ShaderCode::from(library.resolve("vegetation.wgsl")?);
+        let reflection = ShaderReflection::from_code(&code, &defs)
+            .context("failed to reflect vegetation.wgsl")?;
+        let layout =
+            reflection.create_bind_group_layout(device, 0, Some("vegetation_bind_group_layout"));
+        let mut uniform = WindUniform::default();
+        uniform.update(&config.wind, 0.0);
+        let uniform = UniformBuffer::new(device, uniform, Some("Wind Buffer"));
+        let bind_group = reflection.create_bind_group(
+            device,
+            0,
+            &layout,
+            &[uniform.bind_group_entry(0)],
+            Some("vegetation_bind_group"),
+        )?;
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Vegetation Pipeline Layout"),
+            bind_group_layouts: &[&layout, camera_layout, light_layout],
+            push_constant_ranges: &[],
+        });
+        let mut pipeline_cache = PipelineCache::new(
+            "Vegetation Pipeline",
+            code,
+            pipeline_layout,
+            vec![Vertex::desc(), InstanceRaw::desc()],
+            formats,
+            Some(depth_format),
+        );
+        pipeline_cache.prepare(device, &defs)?;
+
+        Ok(Self {
+            mesh,
+            instance_buffer,
+            cells,
+            wind: config.wind,
+            uniform,
+            bind_group,
+            pipeline_cache,
+            defs,
+        })
+    }
+
+    /// The wind the vegetation sways in, changes take effect from the next `update`.
+    /// The cells are only made big enough for the strength it was scattered with,
+    /// so much stronger wind can bend tufts at the edge of the view out of a culled cell
+    pub fn wind(&mut self) -> &mut Wind {
+        &mut self.wind
+    }
+
+    /// Upload the wind and how far through it the vegetation is, at `time` seconds
+    pub fn update(&mut self, queue: &Queue, time: f32) {
+        self.uniform.get_mut().update(&self.wind, time);
+        self.uniform.write(queue);
+    }
+
+    /// Draw into a scene pass whose targets have `sample_count` samples per pixel from now on,
+    /// compiling the pipeline again if that's a change
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) -> Result<()> {
+        self.pipeline_cache.set_sample_count(sample_count);
+        self.pipeline_cache.prepare(device, &self.defs)
+    }
+
+    /// Draw the cells which are in view of `view_proj`, returning how many were drawn.
+    /// The scene's camera and light bind groups must already be bound at groups 1 and 2,
+    /// and this replaces the instance buffer
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        view_proj: &Matrix4<f32>,
+        vertex_pool: &'a BufferPool,
+        index_pool: &'a BufferPool,
+    ) -> usize {
+        let frustum = Frustum::from_view_proj(view_proj);
+        render_pass.set_pipeline(
+            self.pipeline_cache
+                .get(&self.defs)
+                .expect("the vegetation's pipeline is compiled in `new`"),
+        );
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(1, vertex_pool.slice(&self.instance_buffer));
+        let mut drawn = 0;
+        // Neighbouring cells' instances are next to each other, so a run of visible cells is one draw
+        let mut run: Option<Range<u32>> = None;
+        for cell in self
+            .cells
+            .iter()
+            .filter(|cell| frustum.intersects(&cell.bounds))
+        {
+            drawn += 1;
+            match &mut run {
+                Some(run) if run.end == cell.instances.start => run.end = cell.instances.end,
+                _ => {
+                    if let Some(run) = run.replace(cell.instances.clone()) {
+                        self.mesh.draw(render_pass, vertex_pool, index_pool, run);
+                    }
+                }
+            }
+        }
+        if let Some(run) = run {
+            self.mesh.draw(render_pass, vertex_pool, index_pool, run);
+        }
+        drawn
+    }
+}
+
+/// A few blades of grass fanned out around the same root, one unit tall
+struct Tuft;
+
+impl Tuft {
+    const BLADES: u16 = 3;
+    /// How far out the blades reach from the root, at most
+    const RADIUS: f32 = 0.12;
+
+    /// Each blade is a tapering strip, twice over so that it can be seen from both sides
+    fn mesh() -> (Vec<Vertex>, Vec<u16>) {
+        // across and up, from the root to the tip
+        const OUTLINE: [[f32; 2]; 5] = [
+            [-0.04, 0.0],
+            [0.04, 0.0],
+            [-0.03, 0.5],
+            [0.03, 0.5],
+            [0.0, 1.0],
+        ];
+        const TRIANGLES: [u16; 9] = [0, 1, 2, 2, 1, 3, 2, 3, 4];
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for blade in 0..Self::BLADES {
+            let angle = blade as f32 * std::f32::consts::PI / Self::BLADES as f32;
+            let across = Vector3::new(angle.cos(), 0.0, -angle.sin());
+            let facing = Vector3::new(angle.sin(), 0.0, angle.cos());
+            // leaning out from the root, so that the tuft spreads at the top
+            let lean = facing * (Self::RADIUS - 0.04);
+            for side in [1.0, -1.0] {
+                let start = vertices.len() as u16;
+                // tilted upwards, which lights the blades more like the ground they're growing out of
+                let normal = (facing * side + Vector3::unit_y() * 0.5).normalize();
+                let offset = facing * side * 0.04;
+                for [x, y] in OUTLINE {
+                    let position = across * x + Vector3::unit_y() * y + (lean + offset) * y;
+                    vertices.push(Vertex::new(
+                        position.into(),
+                        [x + 0.5, 1.0 - y],
+                        normal.into(),
+                    ));
+                }
+                // wound the other way round from the back
+                if side > 0.0 {
+                    indices.extend(TRIANGLES.iter().map(|index| start + index));
+                } else {
+                    indices.extend(
+                        TRIANGLES
+                            .chunks(3)
+                            .flat_map(|triangle| [triangle[0], triangle[2], triangle[1]])
+                            .map(|index| start + index),
+                    );
+                }
+            }
+        }
+        (vertices, indices)
+    }
+}
+
+/// A small seeded random number generator (SplitMix64), which is all scattering needs
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly between `min` and `max`
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        // the top 24 bits, which is as many as an `f32` can hold exactly
+        let unit = (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32;
+        min + (max - min) * unit
+    }
+}
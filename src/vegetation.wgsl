@@ -0,0 +1,97 @@
+// Tufts of grass scattered over the ground, bent over by the wind
+
+#include "camera.wgsl"
+#include "fog.wgsl"
+#include "instance.wgsl"
+#include "light.wgsl"
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) current_position: vec4<f32>,
+    @location(1) previous_position: vec4<f32>,
+    @location(2) world_position: vec3<f32>,
+    @location(3) normal: vec3<f32>,
+    @location(4) tint: vec3<f32>,
+    // from 0 at the root of a blade to 1 at its tip
+    @location(5) height: f32,
+    @location(6) roughness: f32,
+}
+
+struct Wind {
+    // along x and z, normalised
+    direction: vec2<f32>,
+    // how far the tips bend, as a fraction of the tuft's height
+    strength: f32,
+    // sways per second
+    frequency: f32,
+    time: f32,
+    // the last frame's, so that the motion vectors follow the swaying
+    previous_time: f32,
+}
+@group(0) @binding(0)
+var<uniform> wind: Wind;
+
+// How far the wind pushes a point `height` up a tuft rooted at `root` which is `size` tall, at `time`
+fn sway(root: vec3<f32>, height: f32, size: f32, time: f32) -> vec3<f32> {
+    // Further downwind tufts lag behind, so that gusts roll across the field,
+    // and a second faster wave keeps neighbouring tufts from moving in lockstep
+    let phase = time * wind.frequency * 6.2831853 - dot(root.xz, wind.direction) * 0.5;
+    let gust = 0.6 + 0.4 * sin(phase) + 0.15 * sin(phase * 2.3 + root.x * 1.7 + root.z * 1.3);
+    // the roots stay put, and the bend grows towards the tips
+    let bend = wind.strength * size * height * height * gust;
+    return vec3<f32>(wind.direction.x * bend, 0.0, wind.direction.y * bend);
+}
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = instance_model_matrix(instance);
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    let root = instance.model_matrix_3.xyz;
+    let size = length(instance.model_matrix_1.xyz);
+
+    var out: VertexOutput;
+    let current = world_position.xyz + sway(root, model.position.y, size, wind.time);
+    let previous = world_position.xyz + sway(root, model.position.y, size, wind.previous_time);
+    out.world_position = current;
+    out.normal = instance_normal_matrix(instance) * model.normal;
+    out.tint = instance.tint;
+    out.height = model.position.y;
+    out.roughness = instance.material.x;
+    out.clip_position = camera.view_proj * vec4<f32>(current, 1.0);
+    out.current_position = out.clip_position;
+    out.previous_position = camera.prev_view_proj * vec4<f32>(previous, 1.0);
+    return out;
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec2<f32>,
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    if is_clipped(in.world_position) {
+        discard;
+    }
+    var material: Material;
+    // darker down in the tuft, where the blades shade each other
+    material.albedo = in.tint * mix(0.35, 1.0, in.height);
+    material.roughness = in.roughness;
+    material.metallic = 0.0;
+    let normal = normalize(in.normal);
+    let view_direction = normalize(camera.view_position.xyz - in.world_position);
+
+    var out: FragmentOutput;
+    let color = lighting(in.world_position, normal, view_direction, material);
+    out.color = vec4<f32>(apply_fog(color, in.current_position.w), 1.0);
+    let current = in.current_position.xy / in.current_position.w;
+    let previous = in.previous_position.xy / in.previous_position.w;
+    out.velocity = (current - previous) * vec2<f32>(0.5, -0.5);
+    return out;
+}
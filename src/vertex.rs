@@ -1,65 +1,214 @@
+use std::collections::HashMap;
+
 use bytemuck::{Pod, Zeroable};
 use wgpu::{BufferAddress, VertexAttribute, VertexBufferLayout, VertexFormat, VertexStepMode};
 
+use crate::math::{InnerSpace, Vector3};
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct Vertex {
     position: [f32; 3],
     tex_coords: [f32; 2],
+    /// The direction the surface faces, used for lighting
+    normal: [f32; 3],
 }
 
+// Each face of the cube needs its own 4 vertices, as the corners they share have a different normal on each face.
+// Within a face, the vertices are the top left, top right, bottom left and bottom right corners as seen from outside
+#[rustfmt::skip]
 pub const VERTICES: &[Vertex] = &[
-    Vertex {
-        position: [-1.0, 1.0, -1.0],
-        tex_coords: [0.0, 0.0],
-    }, // B
-    Vertex {
-        position: [1.0, 1.0, -1.0],
-        tex_coords: [1.0, 0.0],
-    }, // A
-    Vertex {
-        position: [-1.0, -1.0, -1.0],
-        tex_coords: [0.0, 1.0],
-    }, // C
-    Vertex {
-        position: [1.0, -1.0, -1.0],
-        tex_coords: [1.0, 1.0],
-    }, // D
-    Vertex {
-        position: [-1.0, 1.0, 1.0],
-        tex_coords: [1.0, 0.0],
-    }, // H
-    Vertex {
-        position: [1.0, 1.0, 1.0],
-        tex_coords: [0.0, 0.0],
-    }, // E
-    Vertex {
-        position: [-1.0, -1.0, 1.0],
-        tex_coords: [1.0, 1.0],
-    }, // G
-    Vertex {
-        position: [1.0, -1.0, 1.0],
-        tex_coords: [0.0, 1.0],
-    }, // F
+    // +x
+    Vertex::new([1.0, 1.0, 1.0], [0.0, 0.0], [1.0, 0.0, 0.0]),
+    Vertex::new([1.0, 1.0, -1.0], [1.0, 0.0], [1.0, 0.0, 0.0]),
+    Vertex::new([1.0, -1.0, 1.0], [0.0, 1.0], [1.0, 0.0, 0.0]),
+    Vertex::new([1.0, -1.0, -1.0], [1.0, 1.0], [1.0, 0.0, 0.0]),
+    // -x
+    Vertex::new([-1.0, 1.0, -1.0], [0.0, 0.0], [-1.0, 0.0, 0.0]),
+    Vertex::new([-1.0, 1.0, 1.0], [1.0, 0.0], [-1.0, 0.0, 0.0]),
+    Vertex::new([-1.0, -1.0, -1.0], [0.0, 1.0], [-1.0, 0.0, 0.0]),
+    Vertex::new([-1.0, -1.0, 1.0], [1.0, 1.0], [-1.0, 0.0, 0.0]),
+    // +y
+    Vertex::new([-1.0, 1.0, -1.0], [0.0, 0.0], [0.0, 1.0, 0.0]),
+    Vertex::new([1.0, 1.0, -1.0], [1.0, 0.0], [0.0, 1.0, 0.0]),
+    Vertex::new([-1.0, 1.0, 1.0], [0.0, 1.0], [0.0, 1.0, 0.0]),
+    Vertex::new([1.0, 1.0, 1.0], [1.0, 1.0], [0.0, 1.0, 0.0]),
+    // -y
+    Vertex::new([-1.0, -1.0, 1.0], [0.0, 0.0], [0.0, -1.0, 0.0]),
+    Vertex::new([1.0, -1.0, 1.0], [1.0, 0.0], [0.0, -1.0, 0.0]),
+    Vertex::new([-1.0, -1.0, -1.0], [0.0, 1.0], [0.0, -1.0, 0.0]),
+    Vertex::new([1.0, -1.0, -1.0], [1.0, 1.0], [0.0, -1.0, 0.0]),
+    // +z
+    Vertex::new([-1.0, 1.0, 1.0], [0.0, 0.0], [0.0, 0.0, 1.0]),
+    Vertex::new([1.0, 1.0, 1.0], [1.0, 0.0], [0.0, 0.0, 1.0]),
+    Vertex::new([-1.0, -1.0, 1.0], [0.0, 1.0], [0.0, 0.0, 1.0]),
+    Vertex::new([1.0, -1.0, 1.0], [1.0, 1.0], [0.0, 0.0, 1.0]),
+    // -z
+    Vertex::new([1.0, 1.0, -1.0], [0.0, 0.0], [0.0, 0.0, -1.0]),
+    Vertex::new([-1.0, 1.0, -1.0], [1.0, 0.0], [0.0, 0.0, -1.0]),
+    Vertex::new([1.0, -1.0, -1.0], [0.0, 1.0], [0.0, 0.0, -1.0]),
+    Vertex::new([-1.0, -1.0, -1.0], [1.0, 1.0], [0.0, 0.0, -1.0]),
 ];
 
 #[rustfmt::skip]
 pub const INDICES: &[u16] = &[
-    0, 1, 2, // Side 0
-    2, 1, 3,
-    4, 0, 6, // Side 1
-    6, 0, 2,
-    7, 5, 6, // Side 2
-    6, 5, 4,
-    3, 1, 7, // Side 3 
-    7, 1, 5,
-    4, 5, 0, // Side 4 
-    0, 5, 1,
-    3, 7, 2, // Side 5 
-    2, 7, 6
+    0, 2, 1, // +x
+    1, 2, 3,
+    4, 6, 5, // -x
+    5, 6, 7,
+    8, 10, 9, // +y
+    9, 10, 11,
+    12, 14, 13, // -y
+    13, 14, 15,
+    16, 18, 17, // +z
+    17, 18, 19,
+    20, 22, 21, // -z
+    21, 22, 23,
+];
+
+/// How high the floor is, level with the bottom of the cube
+pub const FLOOR_HEIGHT: f32 = -1.0;
+/// How far the floor reaches from the origin along x and z
+pub const FLOOR_EXTENT: f32 = 10.0;
+
+/// A large square for the cube to sit on, so that there's something for its shadow to fall onto
+#[rustfmt::skip]
+pub const FLOOR_VERTICES: &[Vertex] = &[
+    Vertex::new([-FLOOR_EXTENT, FLOOR_HEIGHT, -FLOOR_EXTENT], [0.0, 0.0], [0.0, 1.0, 0.0]),
+    Vertex::new([FLOOR_EXTENT, FLOOR_HEIGHT, -FLOOR_EXTENT], [1.0, 0.0], [0.0, 1.0, 0.0]),
+    Vertex::new([-FLOOR_EXTENT, FLOOR_HEIGHT, FLOOR_EXTENT], [0.0, 1.0], [0.0, 1.0, 0.0]),
+    Vertex::new([FLOOR_EXTENT, FLOOR_HEIGHT, FLOOR_EXTENT], [1.0, 1.0], [0.0, 1.0, 0.0]),
 ];
 
+#[rustfmt::skip]
+pub const FLOOR_INDICES: &[u16] = &[
+    0, 2, 1,
+    1, 2, 3,
+];
+
+/// The same square as `FLOOR_VERTICES`, cut into `divisions` x `divisions` smaller squares,
+/// for when its vertices are displaced and need to be close enough together to show any detail
+pub fn floor_grid(divisions: u16) -> (Vec<Vertex>, Vec<u16>) {
+    let [min, max] = [FLOOR_VERTICES[0].position, FLOOR_VERTICES[3].position];
+    let row = divisions + 1;
+    let vertices = (0..row)
+        .flat_map(|z| (0..row).map(move |x| (x, z)))
+        .map(|(x, z)| {
+            let uv = [x as f32 / divisions as f32, z as f32 / divisions as f32];
+            Vertex::new(
+                [
+                    min[0] + (max[0] - min[0]) * uv[0],
+                    min[1],
+                    min[2] + (max[2] - min[2]) * uv[1],
+                ],
+                uv,
+                [0.0, 1.0, 0.0],
+            )
+        })
+        .collect();
+    // each square is wound the same way as `FLOOR_INDICES`
+    let indices = (0..divisions)
+        .flat_map(|z| (0..divisions).map(move |x| z * row + x))
+        .flat_map(|corner| {
+            [
+                corner,
+                corner + row,
+                corner + 1,
+                corner + 1,
+                corner + row,
+                corner + row + 1,
+            ]
+        })
+        .collect();
+    (vertices, indices)
+}
+
+/// The edges of a mesh of triangles where its surface creases or ends, as pairs of indices into `vertices`
+/// to draw as a `LineList`, e.g. the 12 edges of the cube. Vertices in the same place count as one,
+/// as meshes with hard edges have a copy for each face, and edges between triangles facing the same way
+/// are left out, like the diagonal across each of the cube's faces
+pub fn crease_edges(vertices: &[Vertex], indices: &[u16]) -> Vec<u16> {
+    // how far apart two triangles have to face for the edge between them to be kept, about a degree
+    const MIN_CREASE: f32 = 0.9998;
+
+    let place = |index: u16| vertices[index as usize].position.map(f32::to_bits);
+    // in the order they're first found, with the face normal of every triangle they're an edge of
+    let mut edges = Vec::<([u16; 2], Vec<Vector3<f32>>)>::new();
+    let mut found = HashMap::new();
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]]
+            .map(|index| Vector3::from(vertices[index as usize].position));
+        let normal = (b - a).cross(c - a).normalize();
+        for (start, end) in [
+            (triangle[0], triangle[1]),
+            (triangle[1], triangle[2]),
+            (triangle[2], triangle[0]),
+        ] {
+            let (start_place, end_place) = (place(start), place(end));
+            let key = if start_place < end_place {
+                (start_place, end_place)
+            } else {
+                (end_place, start_place)
+            };
+            let edge = *found.entry(key).or_insert_with(|| {
+                edges.push(([start, end], Vec::new()));
+                edges.len() - 1
+            });
+            edges[edge].1.push(normal);
+        }
+    }
+    edges
+        .into_iter()
+        .filter(|(_, normals)| {
+            normals.len() == 1
+                || normals
+                    .iter()
+                    .any(|normal| normal.dot(normals[0]) < MIN_CREASE)
+        })
+        .flat_map(|(edge, _)| edge)
+        .collect()
+}
+
 impl Vertex {
+    pub const fn new(position: [f32; 3], tex_coords: [f32; 2], normal: [f32; 3]) -> Self {
+        Self {
+            position,
+            tex_coords,
+            normal,
+        }
+    }
+
+    pub fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    pub fn tex_coords(&self) -> [f32; 2] {
+        self.tex_coords
+    }
+
+    pub fn normal(&self) -> [f32; 3] {
+        self.normal
+    }
+
+    /// The vertex moved `distance` along its normal, e.g. to keep lines along a mesh's edges from
+    /// disappearing into its faces
+    pub fn pushed_out(&self, distance: f32) -> Self {
+        let position = Vector3::from(self.position) + Vector3::from(self.normal) * distance;
+        Self {
+            position: position.into(),
+            ..*self
+        }
+    }
+
+    /// Both ends of a line along the vertex's normal, told apart by their first texture co-ordinate,
+    /// which is 0.0 at the start and 1.0 at the end, for `normals.wgsl` to stretch out
+    pub fn normal_line(&self) -> [Self; 2] {
+        [0.0, 1.0].map(|along| Self {
+            tex_coords: [along, 0.0],
+            ..*self
+        })
+    }
+
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         // https://sotrh.github.io/learn-wgpu/assets/img/vb_desc.63afb652.png
         VertexBufferLayout {
@@ -76,7 +225,84 @@ impl Vertex {
                     shader_location: 1,
                     format: VertexFormat::Float32x2,
                 },
+                VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 5]>() as BufferAddress,
+                    shader_location: 2,
+                    format: VertexFormat::Float32x3,
+                },
             ],
         }
     }
 }
+
+/// A vertex of a skinned mesh, which is moved by up to 4 of its skin's joints
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct SkinnedVertex {
+    position: [f32; 3],
+    tex_coords: [f32; 2],
+    normal: [f32; 3],
+    /// Indices into the skin's joints
+    joints: [u16; 4],
+    /// How much each of `joints` moves the vertex, which should add up to 1
+    weights: [f32; 4],
+}
+
+impl SkinnedVertex {
+    pub const fn new(
+        position: [f32; 3],
+        tex_coords: [f32; 2],
+        normal: [f32; 3],
+        joints: [u16; 4],
+        weights: [f32; 4],
+    ) -> Self {
+        Self {
+            position,
+            tex_coords,
+            normal,
+            joints,
+            weights,
+        }
+    }
+
+    pub fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    pub fn desc<'a>() -> VertexBufferLayout<'a> {
+        // the same as `Vertex` up until the joints, so that the shaders can share their inputs
+        const ATTRIBUTES: [VertexAttribute; 5] = [
+            VertexAttribute {
+                offset: 0,
+                shader_location: 0,
+                format: VertexFormat::Float32x3,
+            },
+            VertexAttribute {
+                offset: std::mem::size_of::<[f32; 3]>() as BufferAddress,
+                shader_location: 1,
+                format: VertexFormat::Float32x2,
+            },
+            VertexAttribute {
+                offset: std::mem::size_of::<[f32; 5]>() as BufferAddress,
+                shader_location: 2,
+                format: VertexFormat::Float32x3,
+            },
+            VertexAttribute {
+                offset: std::mem::size_of::<[f32; 8]>() as BufferAddress,
+                shader_location: 3,
+                format: VertexFormat::Uint16x4,
+            },
+            VertexAttribute {
+                offset: (std::mem::size_of::<[f32; 8]>() + std::mem::size_of::<[u16; 4]>())
+                    as BufferAddress,
+                shader_location: 4,
+                format: VertexFormat::Float32x4,
+            },
+        ];
+        VertexBufferLayout {
+            array_stride: std::mem::size_of::<SkinnedVertex>() as BufferAddress,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use std::str::FromStr;
+
+use anyhow::*;
+use wgpu::{Color, RenderPass};
+
+/// A rectangle of the window the picture is drawn into, in physical pixels from its top left.
+/// The picture is stretched to fill it, so the scene is rendered at its aspect ratio
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    /// The whole of a `width` by `height` output
+    pub fn full(width: u32, height: u32) -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            width: width as f32,
+            height: height as f32,
+        }
+    }
+
+    /// Width over height
+    pub fn aspect(&self) -> f32 {
+        self.width / self.height
+    }
+
+    /// The part of this which is within a `width` by `height` output, which is `None` if none of it is,
+    /// as wgpu doesn't allow viewports outside of the target
+    pub fn clamp(&self, width: u32, height: u32) -> Option<Self> {
+        let (left, top) = (self.x.max(0.0), self.y.max(0.0));
+        let right = (self.x + self.width).min(width as f32);
+        let bottom = (self.y + self.height).min(height as f32);
+        (right > left && bottom > top).then_some(Self {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        })
+    }
+}
+
+/// A rectangle of the window outside of which nothing is drawn, in physical pixels from its top left
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScissorRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ScissorRect {
+    /// The part of this which is within a `width` by `height` output, which is empty if none of it is
+    pub fn clamp(&self, width: u32, height: u32) -> Self {
+        let (x, y) = (self.x.min(width), self.y.min(height));
+        Self {
+            x,
+            y,
+            width: self.x.saturating_add(self.width).min(width) - x,
+            height: self.y.saturating_add(self.height).min(height) - y,
+        }
+    }
+}
+
+/// Keep the picture at a fixed aspect ratio whatever shape the window is, filling the rest with bars,
+/// so that e.g. captures frame the scene the same way on every machine
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Letterbox {
+    /// Width over height
+    pub aspect: f32,
+    /// The colour of the bars
+    pub color: Color,
+}
+
+impl Letterbox {
+    pub fn new(aspect: f32) -> Self {
+        Self {
+            aspect,
+            color: Color::BLACK,
+        }
+    }
+
+    /// The largest viewport of `aspect` in the middle of a `width` by `height` output,
+    /// with bars above and below if the output is narrower than it and at the sides if it's wider
+    pub fn fit(&self, width: u32, height: u32) -> Viewport {
+        let (width, height) = (width as f32, height as f32);
+        // a whole number of pixels, so that the picture's edges are sharp
+        let (fitted_width, fitted_height) = if width / height > self.aspect {
+            ((height * self.aspect).round().max(1.0), height)
+        } else {
+            (width, (width / self.aspect).round().max(1.0))
+        };
+        Viewport {
+            x: ((width - fitted_width) / 2.0).floor(),
+            y: ((height - fitted_height) / 2.0).floor(),
+            width: fitted_width,
+            height: fitted_height,
+        }
+    }
+}
+
+/// Parses an aspect ratio, either as a width and height like `16:9` or as a number like `2.39`
+impl FromStr for Letterbox {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let aspect = match s.split_once(':') {
+            Some((width, height)) => {
+                let length = |s: &str| {
+                    s.trim()
+                        .parse::<f32>()
+                        .with_context(|| format!("invalid aspect ratio `{s}`"))
+                };
+                length(width)? / length(height)?
+            }
+            None => s
+                .parse()
+                .with_context(|| format!("invalid aspect ratio `{s}`"))?,
+        };
+        ensure!(
+            aspect.is_finite() && aspect > 0.0,
+            "the aspect ratio has to be positive"
+        );
+        Ok(Self::new(aspect))
+    }
+}
+
+/// Where in the output the final picture is drawn, and what the rest is cleared to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputRegion {
+    /// The whole output if `None`
+    pub viewport: Option<Viewport>,
+    /// Nothing is cut off if `None`
+    pub scissor: Option<ScissorRect>,
+    /// What's left outside of the viewport or scissor rectangle
+    pub clear_color: Color,
+}
+
+impl Default for OutputRegion {
+    fn default() -> Self {
+        Self {
+            viewport: None,
+            scissor: None,
+            clear_color: Color::BLACK,
+        }
+    }
+}
+
+impl OutputRegion {
+    /// Limit `render_pass` to the region
+    pub fn apply(&self, render_pass: &mut RenderPass) {
+        if let Some(Viewport {
+            x,
+            y,
+            width,
+            height,
+        }) = self.viewport
+        {
+            render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+        }
+        if let Some(ScissorRect {
+            x,
+            y,
+            width,
+            height,
+        }) = self.scissor
+        {
+            render_pass.set_scissor_rect(x, y, width, height);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! Walking round the scene at eye level, for scenes built to a human scale. The walker is an upright capsule
+//! standing on the ground, or on top of whatever's under it, which gravity pulls down and which can jump.
+//! W and S walk forwards and backwards, A and D step to the side, the arrow keys look around,
+//! Space jumps and holding left shift runs. It bumps into the same boxes as `CameraCollision`
+
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+use crate::{
+    camera::Camera,
+    camera_collision,
+    geometry::Aabb,
+    math::{InnerSpace, Point3, Vector3},
+};
+
+pub struct WalkController {
+    /// Where the walker's feet are
+    feet: Point3<f32>,
+    /// Which way the walker's facing, in radians from +x towards +z
+    yaw: f32,
+    /// How far the walker's looking up or down, in radians
+    pitch: f32,
+    /// How fast the walker's rising or falling
+    vertical_speed: f32,
+    /// Whether the walker's standing on something, so it can jump
+    grounded: bool,
+    /// Where the walker started, which it's put back to if it falls out of the world
+    start: Point3<f32>,
+    is_forward_pressed: bool,
+    is_backward_pressed: bool,
+    is_left_pressed: bool,
+    is_right_pressed: bool,
+    is_turn_left_pressed: bool,
+    is_turn_right_pressed: bool,
+    is_look_up_pressed: bool,
+    is_look_down_pressed: bool,
+    is_jump_pressed: bool,
+    is_run_pressed: bool,
+}
+
+impl WalkController {
+    /// How far the eyes are above the feet
+    pub const EYE_HEIGHT: f32 = 1.7;
+    /// How far the top of the capsule is above the eyes
+    const HEAD_ROOM: f32 = 0.15;
+    /// The capsule's radius, which is how close the walker can get to anything
+    const RADIUS: f32 = 0.3;
+    /// In units per second
+    const WALK_SPEED: f32 = 3.0;
+    const RUN_SPEED: f32 = 6.0;
+    /// In radians per second
+    const TURN_SPEED: f32 = 2.0;
+    /// How far up or down the walker can look, just short of straight up or down so `look_at` still works
+    const MAX_PITCH: f32 = 1.5;
+    /// In units per second per second
+    const GRAVITY: f32 = 9.81;
+    /// How fast the walker leaves the ground when it jumps, which is enough for about half a unit
+    const JUMP_SPEED: f32 = 3.2;
+    /// How far below where it started the walker can fall before it's put back
+    const FALL_LIMIT: f32 = 100.0;
+
+    /// Start walking from where `camera` is, looking the same way, with the walker's eyes at the camera
+    pub fn new(camera: &Camera) -> Self {
+        let forward = camera.target - camera.eye;
+        // looking along -z, as the camera does by default, if the target's right on the eye
+        let forward = if forward.magnitude2() > 0.0 {
+            forward.normalize()
+        } else {
+            -Vector3::unit_z()
+        };
+        let feet = camera.eye - Vector3::unit_y() * Self::EYE_HEIGHT;
+        Self {
+            feet,
+            yaw: forward.z.atan2(forward.x),
+            pitch: forward.y.asin().clamp(-Self::MAX_PITCH, Self::MAX_PITCH),
+            vertical_speed: 0.0,
+            grounded: false,
+            start: feet,
+            is_forward_pressed: false,
+            is_backward_pressed: false,
+            is_left_pressed: false,
+            is_right_pressed: false,
+            is_turn_left_pressed: false,
+            is_turn_right_pressed: false,
+            is_look_up_pressed: false,
+            is_look_down_pressed: false,
+            is_jump_pressed: false,
+            is_run_pressed: false,
+        }
+    }
+
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        let WindowEvent::KeyboardInput {
+            input:
+                KeyboardInput {
+                    state,
+                    virtual_keycode: Some(keycode),
+                    ..
+                },
+            ..
+        } = event
+        else {
+            return false;
+        };
+        let is_pressed = *state == ElementState::Pressed;
+        let pressed = match keycode {
+            VirtualKeyCode::W => &mut self.is_forward_pressed,
+            VirtualKeyCode::S => &mut self.is_backward_pressed,
+            VirtualKeyCode::A => &mut self.is_left_pressed,
+            VirtualKeyCode::D => &mut self.is_right_pressed,
+            VirtualKeyCode::Left => &mut self.is_turn_left_pressed,
+            VirtualKeyCode::Right => &mut self.is_turn_right_pressed,
+            VirtualKeyCode::Up => &mut self.is_look_up_pressed,
+            VirtualKeyCode::Down => &mut self.is_look_down_pressed,
+            VirtualKeyCode::Space => &mut self.is_jump_pressed,
+            VirtualKeyCode::LShift => &mut self.is_run_pressed,
+            _ => return false,
+        };
+        *pressed = is_pressed;
+        true
+    }
+
+    /// Move the walker `delta` seconds on among `obstacles`, standing on `ground`'s height at each point
+    /// if there's ground there, and put `camera` at its eyes
+    pub fn update_camera(
+        &mut self,
+        camera: &mut Camera,
+        delta: f32,
+        obstacles: &[Aabb],
+        ground: impl Fn(f32, f32) -> Option<f32>,
+    ) {
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        self.yaw +=
+            axis(self.is_turn_right_pressed, self.is_turn_left_pressed) * Self::TURN_SPEED * delta;
+        self.pitch = (self.pitch
+            + axis(self.is_look_up_pressed, self.is_look_down_pressed) * Self::TURN_SPEED * delta)
+            .clamp(-Self::MAX_PITCH, Self::MAX_PITCH);
+
+        let forward = Vector3::new(self.yaw.cos(), 0.0, self.yaw.sin());
+        let right = forward.cross(Vector3::unit_y());
+        let mut walk = forward * axis(self.is_forward_pressed, self.is_backward_pressed)
+            + right * axis(self.is_right_pressed, self.is_left_pressed);
+        if walk.magnitude2() > 0.0 {
+            let speed = if self.is_run_pressed {
+                Self::RUN_SPEED
+            } else {
+                Self::WALK_SPEED
+            };
+            walk = walk.normalize() * speed;
+        }
+        if self.is_jump_pressed && self.grounded {
+            self.vertical_speed = Self::JUMP_SPEED;
+        }
+        self.vertical_speed -= Self::GRAVITY * delta;
+
+        // the capsule's feet hit a box wherever the whole capsule would, once the box has grown by its radius
+        // sideways, by its height below and by nothing above, for standing on
+        let obstacles = obstacles
+            .iter()
+            .map(|obstacle| Aabb {
+                min: obstacle.min
+                    - Vector3::new(
+                        Self::RADIUS,
+                        Self::EYE_HEIGHT + Self::HEAD_ROOM,
+                        Self::RADIUS,
+                    ),
+                max: obstacle.max + Vector3::new(Self::RADIUS, 0.0, Self::RADIUS),
+            })
+            .collect::<Vec<_>>();
+        let to = self.feet + (walk + Vector3::unit_y() * self.vertical_speed) * delta;
+        let slide = camera_collision::slide(self.feet, to, &obstacles);
+        self.feet = slide.position;
+        self.grounded = slide.landed && self.vertical_speed <= 0.0;
+        if slide.bumped {
+            self.vertical_speed = self.vertical_speed.min(0.0);
+        }
+        if let Some(height) = ground(self.feet.x, self.feet.z) {
+            if self.feet.y <= height {
+                self.feet.y = height;
+                self.grounded = true;
+            }
+        }
+        if self.grounded {
+            self.vertical_speed = 0.0;
+        }
+        if self.feet.y < self.start.y - Self::FALL_LIMIT {
+            tracing::info!("Fell out of the world, starting again");
+            self.feet = self.start;
+            self.vertical_speed = 0.0;
+        }
+
+        camera.eye = self.feet + Vector3::unit_y() * Self::EYE_HEIGHT;
+        camera.target = camera.eye
+            + Vector3::new(
+                self.yaw.cos() * self.pitch.cos(),
+                self.pitch.sin(),
+                self.yaw.sin() * self.pitch.cos(),
+            );
+    }
+}
@@ -0,0 +1,428 @@
+use std::f32::consts::TAU;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    AddressMode, BindGroup, BindGroupEntry, BindGroupLayout, BindingResource, BlendState,
+    ColorTargetState, ColorWrites, CompareFunction, DepthBiasState, DepthStencilState, Device,
+    FilterMode, FragmentState, PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPass,
+    RenderPipelineDescriptor, Sampler, SamplerDescriptor, ShaderModuleDescriptor, ShaderSource,
+    StencilState, TextureFormat, VertexState,
+};
+
+use crate::{
+    buffer_pool::BufferPool,
+    camera::Camera,
+    geometry::Plane,
+    math::{Point3, Vector3},
+    mesh::Mesh,
+    pipeline::ScenePipeline,
+    planar_reflection::PlanarReflection,
+    reflection::ShaderReflection,
+    shader::{preprocess, ShaderDefs, ShaderLibrary},
+    texture::{OurTexture, TextureKind},
+    uniform::UniformBuffer,
+    vertex::{Vertex, FLOOR_INDICES},
+};
+
+/// How the water looks, which can be changed at any time through `Water::settings_mut`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WaterSettings {
+    /// The height of the surface
+    pub level: f32,
+    /// How far the surface stretches along x and z, centered on the origin
+    pub size: f32,
+    /// Linear RGB
+    pub color: [f32; 3],
+    /// How much of what's under the water shows through when looking straight down, from 0 to 1
+    pub clarity: f32,
+    /// How much is reflected when looking straight down, rising to everything at grazing angles.
+    /// Real water reflects about 0.02, which makes the reflection hard to see from above
+    pub reflectivity: f32,
+    /// The distance the waves' normal map covers before it repeats, in world units
+    pub wave_scale: f32,
+    /// How fast the waves move, in world units per second
+    pub wave_speed: f32,
+    /// How far the waves bend the reflection, as a fraction of the screen
+    pub distortion: f32,
+}
+
+impl Default for WaterSettings {
+    fn default() -> Self {
+        Self {
+            level: -1.5,
+            size: 40.0,
+            color: [0.02, 0.1, 0.15],
+            clarity: 0.6,
+            reflectivity: 0.1,
+            wave_scale: 8.0,
+            wave_speed: 0.3,
+            distortion: 0.02,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+struct WaterUniform {
+    color: [f32; 3],
+    level: f32,
+    clarity: f32,
+    size: f32,
+    wave_scale: f32,
+    wave_speed: f32,
+    distortion: f32,
+    reflectivity: f32,
+    time: f32,
+    _padding: f32,
+}
+
+impl WaterUniform {
+    fn new(settings: &WaterSettings, time: f32) -> Self {
+        Self {
+            color: settings.color,
+            level: settings.level,
+            clarity: settings.clarity,
+            size: settings.size,
+            wave_scale: settings.wave_scale,
+            wave_speed: settings.wave_speed,
+            distortion: settings.distortion,
+            reflectivity: settings.reflectivity,
+            time,
+            _padding: 0.0,
+        }
+    }
+}
+
+/// A flat sheet of water with scrolling waves, which reflects the scene above it, see `PlanarReflection`
+pub struct Water {
+    settings: WaterSettings,
+    uniform: UniformBuffer<WaterUniform>,
+    /// A unit square, scaled and moved into place by the shader
+    mesh: Mesh,
+    normal_map: OurTexture,
+    normal_sampler: Sampler,
+    reflection_sampler: Sampler,
+    planar_reflection: PlanarReflection,
+    reflection: ShaderReflection,
+    layout: BindGroupLayout,
+    bind_group: BindGroup,
+    pipeline: ScenePipeline,
+}
+
+impl Water {
+    /// The reflection is rendered at a fraction of the window's size, as the waves blur it anyway
+    const REFLECTION_SCALE: u32 = 2;
+    /// The size of the generated normal map in texels
+    const NORMAL_MAP_SIZE: u32 = 256;
+
+    /// `camera_layout` and `light_layout` are the scene's, `formats` and `depth_format` must match
+    /// the scene pass and `width` and `height` are the size of the window
+    #[allow(clippy::too_many_arguments)]
+    #[tracing::instrument(skip_all)]
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        library: &ShaderLibrary,
+        settings: WaterSettings,
+        camera_layout: &BindGroupLayout,
+        light_layout: &BindGroupLayout,
+        formats: &[TextureFormat],
+        depth_format: TextureFormat,
+        vertex_pool: &mut BufferPool,
+        index_pool: &mut BufferPool,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        let name = "water.wgsl";
+        let source = preprocess(&library.resolve(name)?, &ShaderDefs::new())?;
+        let reflection = ShaderReflection::from_code(&source.clone().into(), &ShaderDefs::new())
+            .with_context(|| format!("failed to reflect {name}"))?;
+        let layout =
+            reflection.create_bind_group_layout(device, 0, Some("water_bind_group_layout"));
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(name),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Water Pipeline Layout"),
+            bind_group_layouts: &[&layout, camera_layout, light_layout],
+            push_constant_ranges: &[],
+        });
+        // The colour is blended over what's under the water, while the motion vectors replace it
+        let targets = formats
+            .iter()
+            .enumerate()
+            .map(|(index, &format)| {
+                Some(ColorTargetState {
+                    format,
+                    blend: Some(if index == 0 {
+                        BlendState::ALPHA_BLENDING
+                    } else {
+                        BlendState::REPLACE
+                    }),
+                    write_mask: ColorWrites::ALL,
+                })
+            })
+            .collect::<Vec<_>>();
+        let pipeline = ScenePipeline::new(device, move |device, multisample| {
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(name),
+                layout: Some(&pipeline_layout),
+                vertex: VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[Vertex::desc()],
+                },
+                fragment: Some(FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &targets,
+                }),
+                // seen from underneath, the surface is still there
+                primitive: PrimitiveState {
+                    cull_mode: None,
+                    ..Default::default()
+                },
+                depth_stencil: Some(DepthStencilState {
+                    format: depth_format,
+                    depth_write_enabled: true,
+                    depth_compare: CompareFunction::Less,
+                    stencil: StencilState::default(),
+                    bias: DepthBiasState::default(),
+                }),
+                multisample,
+                multiview: None,
+            })
+        });
+
+        #[rustfmt::skip]
+        let vertices = [
+            Vertex::new([-0.5, 0.0, -0.5], [0.0, 0.0], [0.0, 1.0, 0.0]),
+            Vertex::new([0.5, 0.0, -0.5], [1.0, 0.0], [0.0, 1.0, 0.0]),
+            Vertex::new([-0.5, 0.0, 0.5], [0.0, 1.0], [0.0, 1.0, 0.0]),
+            Vertex::new([0.5, 0.0, 0.5], [1.0, 1.0], [0.0, 1.0, 0.0]),
+        ];
+        // the same square as the floor
+        let mesh = Mesh::new(
+            device,
+            queue,
+            vertex_pool,
+            index_pool,
+            &vertices,
+            FLOOR_INDICES,
+        );
+
+        let normal_map = OurTexture::from_image(
+            device,
+            queue,
+            &wave_normal_map(Self::NORMAL_MAP_SIZE).into(),
+            Some("Water Normal Map"),
+            TextureKind::Normal,
+        )?;
+        let normal_sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Water Normal Sampler"),
+            address_mode_u: AddressMode::Repeat,
+            address_mode_v: AddressMode::Repeat,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+        let reflection_sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Water Reflection Sampler"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+        let uniform = UniformBuffer::new(
+            device,
+            WaterUniform::new(&settings, 0.0),
+            Some("Water Buffer"),
+        );
+        let planar_reflection = PlanarReflection::new(
+            device,
+            camera_layout,
+            "Water Reflection",
+            (width / Self::REFLECTION_SCALE).max(1),
+            (height / Self::REFLECTION_SCALE).max(1),
+        );
+        let bind_group = create_bind_group(
+            device,
+            &reflection,
+            &layout,
+            &uniform,
+            &normal_map,
+            &normal_sampler,
+            &planar_reflection,
+            &reflection_sampler,
+        )?;
+
+        Ok(Self {
+            settings,
+            uniform,
+            mesh,
+            normal_map,
+            normal_sampler,
+            reflection_sampler,
+            planar_reflection,
+            reflection,
+            layout,
+            bind_group,
+            pipeline,
+        })
+    }
+
+    pub fn settings(&self) -> &WaterSettings {
+        &self.settings
+    }
+
+    /// Change how the water looks, which takes effect from the next `update`
+    pub fn settings_mut(&mut self) -> &mut WaterSettings {
+        &mut self.settings
+    }
+
+    /// Resize the reflection to match the window
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        self.planar_reflection.resize(
+            device,
+            (width / Self::REFLECTION_SCALE).max(1),
+            (height / Self::REFLECTION_SCALE).max(1),
+        );
+        self.bind_group = create_bind_group(
+            device,
+            &self.reflection,
+            &self.layout,
+            &self.uniform,
+            &self.normal_map,
+            &self.normal_sampler,
+            &self.planar_reflection,
+            &self.reflection_sampler,
+        )
+        .expect("the water's bindings don't change size");
+    }
+
+    /// Draw into a scene pass whose targets have `sample_count` samples per pixel from now on,
+    /// and render the reflection with as many, as it's drawn with the scene's pipelines
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        self.pipeline.set_sample_count(device, sample_count);
+        self.planar_reflection
+            .set_sample_count(device, sample_count);
+    }
+
+    /// Move the waves on to `time` seconds and mirror `camera` in the surface for the reflection pass,
+    /// cutting away what's behind any of the scene's `clip_planes` in the reflection too
+    pub fn update(&mut self, queue: &Queue, camera: &Camera, time: f32, clip_planes: &[Plane]) {
+        *self.uniform.get_mut() = WaterUniform::new(&self.settings, time);
+        self.uniform.write(queue);
+
+        let mirrored = self.mirrored(camera);
+        let surface = Plane {
+            normal: Vector3::unit_y(),
+            distance: self.settings.level,
+        };
+        self.planar_reflection
+            .update(queue, &mirrored, surface, clip_planes);
+    }
+
+    /// `camera` reflected in the surface, which sees the scene upside down from where the reflection is.
+    /// It has to keep `camera`'s up direction rather than flipping it, so that the winding order
+    /// of every triangle is unchanged and back faces are still culled
+    pub fn mirrored(&self, camera: &Camera) -> Camera {
+        let mirror =
+            |point: Point3<f32>| Point3::new(point.x, 2.0 * self.settings.level - point.y, point.z);
+        Camera {
+            eye: mirror(camera.eye),
+            target: mirror(camera.target),
+            up: camera.up,
+            aspect: camera.aspect,
+            fovy: camera.fovy,
+            znear: camera.znear,
+            zfar: camera.zfar,
+        }
+    }
+
+    /// The scene above the water as seen in its surface, which has to be rendered before the water is drawn
+    pub fn planar_reflection(&self) -> &PlanarReflection {
+        &self.planar_reflection
+    }
+
+    /// Draw the surface over whatever is already in the scene pass.
+    /// The scene's camera and light bind groups must already be bound at groups 1 and 2
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        vertex_pool: &'a BufferPool,
+        index_pool: &'a BufferPool,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        self.mesh.draw(render_pass, vertex_pool, index_pool, 0..1);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_bind_group(
+    device: &Device,
+    reflection: &ShaderReflection,
+    layout: &BindGroupLayout,
+    uniform: &UniformBuffer<WaterUniform>,
+    normal_map: &OurTexture,
+    normal_sampler: &Sampler,
+    planar_reflection: &PlanarReflection,
+    reflection_sampler: &Sampler,
+) -> Result<BindGroup> {
+    reflection.create_bind_group(
+        device,
+        0,
+        layout,
+        &[
+            uniform.bind_group_entry(0),
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(&normal_map.view),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::Sampler(normal_sampler),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: BindingResource::TextureView(planar_reflection.view()),
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: BindingResource::Sampler(reflection_sampler),
+            },
+        ],
+        Some("water_bind_group"),
+    )
+}
+
+/// A tiling normal map of gentle waves, from a sum of sine waves which each fit a whole number
+/// of times across the texture
+fn wave_normal_map(size: u32) -> image::RgbaImage {
+    // (waves across x, waves across y, amplitude, phase)
+    const WAVES: [(f32, f32, f32, f32); 6] = [
+        (1.0, 2.0, 0.03, 0.0),
+        (3.0, -1.0, 0.015, 1.3),
+        (-2.0, 5.0, 0.01, 2.1),
+        (7.0, 4.0, 0.004, 0.7),
+        (-9.0, 6.0, 0.003, 4.2),
+        (11.0, -13.0, 0.002, 3.3),
+    ];
+    image::RgbaImage::from_fn(size, size, |x, y| {
+        let (u, v) = (x as f32 / size as f32, y as f32 / size as f32);
+        // the slope of the summed waves, whose heights are in units of the texture's width
+        let (mut dx, mut dy) = (0.0, 0.0);
+        for (kx, ky, amplitude, phase) in WAVES {
+            let slope = amplitude * TAU * (TAU * (kx * u + ky * v) + phase).cos();
+            dx += slope * kx;
+            dy += slope * ky;
+        }
+        let normal = Vector3::new(-dx, -dy, 1.0);
+        let normal = normal / (normal.x * normal.x + normal.y * normal.y + 1.0).sqrt();
+        let encode = |value: f32| ((value * 0.5 + 0.5) * 255.0).round() as u8;
+        image::Rgba([encode(normal.x), encode(normal.y), encode(normal.z), 255])
+    })
+}
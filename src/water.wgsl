@@ -0,0 +1,110 @@
+// A flat, animated water surface which reflects the scene above it
+
+#include "camera.wgsl"
+#include "fog.wgsl"
+#include "light.wgsl"
+
+struct WaterUniform {
+    color: vec3<f32>,
+    // the height of the surface
+    level: f32,
+    // how much of what's under the water shows through when looking straight down, from 0 to 1
+    clarity: f32,
+    // how far the surface stretches along x and z
+    size: f32,
+    // the distance the normal map covers before it repeats, in world units
+    wave_scale: f32,
+    // how fast the normal maps scroll, in world units per second
+    wave_speed: f32,
+    // how far the waves bend the reflection, as a fraction of the screen
+    distortion: f32,
+    // how much is reflected when looking straight down, rising to everything at grazing angles
+    reflectivity: f32,
+    time: f32,
+}
+@group(0) @binding(0)
+var<uniform> water: WaterUniform;
+// Tangent space normals, with x and y in the red and green channels and up in the blue channel
+@group(0) @binding(1)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(2)
+var s_normal: sampler;
+// The scene above the water as seen by the mirrored camera, which is upside down from our point of view
+@group(0) @binding(3)
+var t_reflection: texture_2d<f32>;
+@group(0) @binding(4)
+var s_reflection: sampler;
+
+struct VertexInput {
+    // a unit square around the origin, which is moved to `level` and scaled by `size`
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) current_position: vec4<f32>,
+    @location(1) previous_position: vec4<f32>,
+    @location(2) world_position: vec3<f32>,
+}
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    let world_position = vec4<f32>(model.position.x * water.size, water.level, model.position.z * water.size, 1.0);
+    var out: VertexOutput;
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
+    out.current_position = out.clip_position;
+    out.previous_position = camera.prev_view_proj * world_position;
+    return out;
+}
+
+fn sample_normal(uv: vec2<f32>) -> vec3<f32> {
+    return textureSample(t_normal, s_normal, uv).xyz * 2.0 - 1.0;
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec2<f32>,
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    if is_clipped(in.world_position) {
+        discard;
+    }
+    // Two copies of the normal map scrolling in different directions, so that the waves don't visibly slide
+    let uv = in.world_position.xz / water.wave_scale;
+    let scroll = water.time * water.wave_speed / water.wave_scale;
+    let a = sample_normal(uv + vec2<f32>(1.0, 0.3) * scroll);
+    let b = sample_normal(uv * 1.7 + vec2<f32>(-0.2, 1.0) * scroll);
+    // "whiteout" blending, which keeps the detail of both
+    let tangent_normal = normalize(vec3<f32>(a.xy + b.xy, a.z * b.z));
+    // the surface is flat, so tangent space is world space with y and z swapped
+    let normal = vec3<f32>(tangent_normal.x, tangent_normal.z, tangent_normal.y);
+    let view_direction = normalize(camera.view_position.xyz - in.world_position);
+
+    // Schlick's approximation
+    let cos_theta = max(dot(normal, view_direction), 0.0);
+    let fresnel = water.reflectivity + (1.0 - water.reflectivity) * pow(1.0 - cos_theta, 5.0);
+
+    let ndc = in.current_position.xy / in.current_position.w;
+    // the mirrored camera sees everything flipped vertically, which cancels out texture co-ordinates going downwards
+    let reflection_uv = clamp(ndc * 0.5 + 0.5 + normal.xz * water.distortion, vec2<f32>(0.0), vec2<f32>(1.0));
+    let reflection = textureSample(t_reflection, s_reflection, reflection_uv).rgb;
+
+    var material: Material;
+    material.albedo = water.color;
+    material.roughness = 0.1;
+    material.metallic = 0.0;
+    let surface = lighting(in.world_position, normal, view_direction, material);
+
+    var out: FragmentOutput;
+    let color = apply_fog(mix(surface, reflection, fresnel), in.current_position.w);
+    out.color = vec4<f32>(color, mix(1.0 - water.clarity, 1.0, fresnel));
+    let current = in.current_position.xy / in.current_position.w;
+    let previous = in.previous_position.xy / in.previous_position.w;
+    out.velocity = (current - previous) * vec2<f32>(0.5, -0.5);
+    return out;
+}
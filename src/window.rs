@@ -0,0 +1,210 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::*;
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event_loop::EventLoopWindowTarget,
+    monitor::MonitorHandle,
+    window::{Icon, Window, WindowBuilder},
+};
+
+/// How the window looks and behaves, which `run` builds it from
+#[derive(Clone, Debug)]
+pub struct WindowConfig {
+    pub title: String,
+    /// A PNG, JPEG or GIF to use as the window's icon, rather than the platform's default
+    pub icon: Option<PathBuf>,
+    pub min_size: Option<PhysicalSize<u32>>,
+    pub max_size: Option<PhysicalSize<u32>>,
+    pub resizable: bool,
+    /// Let the desktop show through wherever nothing is drawn.
+    /// This needs a surface which supports blending with it, which not every platform has
+    pub transparent: bool,
+    /// Whether the window has a title bar and borders
+    pub decorations: bool,
+    pub always_on_top: bool,
+    /// The monitor to open on, otherwise the primary one
+    pub monitor: Option<MonitorSelector>,
+    /// Where to open on the monitor, which is centered if only `monitor` is set,
+    /// otherwise left to the platform
+    pub position: Option<WindowPosition>,
+}
+
+impl WindowConfig {
+    /// A builder for a window with these settings
+    pub fn builder(&self) -> Result<WindowBuilder> {
+        let mut builder = WindowBuilder::new()
+            .with_title(&self.title)
+            .with_resizable(self.resizable)
+            .with_transparent(self.transparent)
+            .with_decorations(self.decorations)
+            .with_always_on_top(self.always_on_top);
+        if let Some(size) = self.min_size {
+            builder = builder.with_min_inner_size(size);
+        }
+        if let Some(size) = self.max_size {
+            builder = builder.with_max_inner_size(size);
+        }
+        if let Some(path) = &self.icon {
+            let icon = load_icon(path)
+                .with_context(|| format!("failed to load the icon {}", path.display()))?;
+            builder = builder.with_window_icon(Some(icon));
+        }
+        Ok(builder)
+    }
+
+    /// Build the window and move it to its monitor and position
+    pub fn build<T>(&self, event_loop: &EventLoopWindowTarget<T>) -> Result<Window> {
+        // hidden until it's been placed, so that it doesn't visibly jump across
+        let window = self.builder()?.with_visible(false).build(event_loop)?;
+        let position = self
+            .position
+            .or_else(|| self.monitor.as_ref().map(|_| WindowPosition::Center));
+        if let Some(position) = position {
+            let monitor = self
+                .monitor
+                .as_ref()
+                .and_then(|selector| {
+                    let monitor = selector.find(event_loop.available_monitors());
+                    if monitor.is_none() {
+                        tracing::warn!("No monitor matches {selector:?}, using the primary one");
+                    }
+                    monitor
+                })
+                .or_else(|| event_loop.primary_monitor());
+            match (monitor, position) {
+                (Some(monitor), WindowPosition::Center) => center_on(&window, &monitor),
+                (Some(monitor), WindowPosition::At(offset)) => {
+                    let origin = monitor.position();
+                    window.set_outer_position(PhysicalPosition::new(
+                        origin.x + offset.x,
+                        origin.y + offset.y,
+                    ));
+                }
+                (None, _) => tracing::warn!("Can't place the window without a monitor"),
+            }
+        }
+        window.set_visible(true);
+        Ok(window)
+    }
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            title: "WGPU Cube".into(),
+            icon: None,
+            min_size: None,
+            max_size: None,
+            resizable: true,
+            transparent: false,
+            decorations: true,
+            always_on_top: false,
+            monitor: None,
+            position: None,
+        }
+    }
+}
+
+/// Which monitor to open the window on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MonitorSelector {
+    /// The monitor's index in `available_monitors`
+    Index(usize),
+    /// The first monitor whose name contains this, ignoring case
+    Name(String),
+}
+
+impl MonitorSelector {
+    /// The monitor from `monitors` this selects, if there is one
+    pub fn find(&self, monitors: impl IntoIterator<Item = MonitorHandle>) -> Option<MonitorHandle> {
+        let mut monitors = monitors.into_iter();
+        match self {
+            Self::Index(index) => monitors.nth(*index),
+            Self::Name(name) => {
+                let name = name.to_lowercase();
+                monitors.find(|monitor| {
+                    monitor
+                        .name()
+                        .is_some_and(|monitor| monitor.to_lowercase().contains(&name))
+                })
+            }
+        }
+    }
+}
+
+/// Parses an index if it's a number, otherwise a name
+impl FromStr for MonitorSelector {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        ensure!(!s.is_empty(), "expected a monitor's index or name");
+        Ok(s.parse()
+            .map(Self::Index)
+            .unwrap_or_else(|_| Self::Name(s.into())))
+    }
+}
+
+/// Where to put the window on its monitor
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowPosition {
+    Center,
+    /// The window's top left corner, relative to the monitor's
+    At(PhysicalPosition<i32>),
+}
+
+/// Parses `center`, or a position like `100,50`
+impl FromStr for WindowPosition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "center" {
+            return Ok(Self::Center);
+        }
+        let (x, y) = s
+            .split_once(',')
+            .with_context(|| format!("expected a position like `100,50`, found `{s}`"))?;
+        Ok(Self::At(PhysicalPosition::new(
+            x.trim().parse().context("invalid x")?,
+            y.trim().parse().context("invalid y")?,
+        )))
+    }
+}
+
+/// Move `window` to the middle of `monitor`
+pub fn center_on(window: &Window, monitor: &MonitorHandle) {
+    window.set_outer_position(centered_position(monitor, window.outer_size()));
+}
+
+/// Where a window `size` big has to go for it to be in the middle of `monitor`
+pub fn centered_position(
+    monitor: &MonitorHandle,
+    size: PhysicalSize<u32>,
+) -> PhysicalPosition<i32> {
+    let origin = monitor.position();
+    let monitor = monitor.size();
+    PhysicalPosition::new(
+        origin.x + (monitor.width as i32 - size.width as i32) / 2,
+        origin.y + (monitor.height as i32 - size.height as i32) / 2,
+    )
+}
+
+fn load_icon(path: &Path) -> Result<Icon> {
+    let image = image::open(path)?.into_rgba8();
+    let (width, height) = image.dimensions();
+    Ok(Icon::from_rgba(image.into_raw(), width, height)?)
+}
+
+/// Parses a size like `1920x1080`
+pub fn parse_size(s: &str) -> Result<PhysicalSize<u32>> {
+    let (width, height) = s
+        .split_once('x')
+        .with_context(|| format!("expected a size like `1920x1080`, found `{s}`"))?;
+    Ok(PhysicalSize::new(
+        width.parse().context("invalid width")?,
+        height.parse().context("invalid height")?,
+    ))
+}
@@ -0,0 +1,118 @@
+//! Golden-image tests: known scenes rendered with `Offscreen` and compared against the reference images
+//! in `tests/golden/`, within a tolerance for the small differences between GPUs and drivers.
+//! Set `UPDATE_GOLDEN=1` to write the references from what's rendered instead, after checking by eye
+//! that it's right. Machines without an adapter to render with skip these
+
+use std::{
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+};
+
+use image::RgbaImage;
+use wgpu_cube::{config::Config, offscreen::Offscreen};
+
+const WIDTH: u32 = 160;
+const HEIGHT: u32 = 120;
+/// How far each channel of a pixel can be from the reference's before the pixel counts as different
+const CHANNEL_TOLERANCE: u8 = 8;
+/// The fraction of pixels which can be different, for edges rasterised slightly differently
+const MAX_DIFFERENT_PIXELS: f64 = 0.005;
+
+/// Rendering from several threads at once can lose the adapter on some drivers
+static RENDER_LOCK: Mutex<()> = Mutex::new(());
+
+/// Render the scene described by `args` for `frames` frames and compare the last against `tests/golden/{name}.png`
+fn check(name: &str, args: &[&str], frames: usize) {
+    let _lock = RENDER_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let config = Config::parse(
+        ["--deterministic"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.to_string()),
+    )
+    .unwrap();
+    let mut offscreen = match pollster::block_on(Offscreen::new(WIDTH, HEIGHT, &config)) {
+        Ok(offscreen) => offscreen,
+        Err(error) => {
+            eprintln!("skipping golden image `{name}`: {error:#}");
+            return;
+        }
+    };
+    for _ in 1..frames {
+        offscreen.render();
+    }
+    let rendered = offscreen.render();
+
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name)
+        .with_extension("png");
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        rendered.save(&path).unwrap();
+        return;
+    }
+    let reference = image::open(&path)
+        .unwrap_or_else(|error| {
+            panic!(
+                "no reference image at {}, render it with UPDATE_GOLDEN=1: {error}",
+                path.display()
+            )
+        })
+        .into_rgba8();
+    if let Err(message) = compare(&rendered, &reference) {
+        let actual = std::env::temp_dir().join(format!("golden-{name}.png"));
+        rendered.save(&actual).unwrap();
+        panic!(
+            "`{name}` doesn't match {}: {message}, it was saved to {}",
+            path.display(),
+            actual.display()
+        );
+    }
+}
+
+fn compare(rendered: &RgbaImage, reference: &RgbaImage) -> Result<(), String> {
+    if rendered.dimensions() != reference.dimensions() {
+        return Err(format!(
+            "it's {:?} rather than {:?}",
+            rendered.dimensions(),
+            reference.dimensions()
+        ));
+    }
+    let different = rendered
+        .pixels()
+        .zip(reference.pixels())
+        .filter(|(a, b)| {
+            a.0.iter()
+                .zip(b.0)
+                .any(|(a, b)| a.abs_diff(b) > CHANNEL_TOLERANCE)
+        })
+        .count();
+    let fraction = different as f64 / rendered.pixels().len() as f64;
+    if fraction > MAX_DIFFERENT_PIXELS {
+        return Err(format!(
+            "{different} pixels are more than {CHANNEL_TOLERANCE} off in a channel"
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn default_scene() {
+    check("default", &[], 1);
+}
+
+#[test]
+fn normals() {
+    check("normals", &["--debug-view", "normals"], 1);
+}
+
+#[test]
+fn uvs() {
+    check("uv", &["--debug-view", "uv"], 1);
+}
+
+/// Half a second in, for the exposure to have adapted partway, measured ahead of each frame
+#[test]
+fn auto_exposure() {
+    check("auto_exposure", &["--exposure", "auto"], 30);
+}